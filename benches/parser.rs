@@ -0,0 +1,104 @@
+//! Parser benchmarks
+//!
+//! Run with `cargo bench --bench parser`. There's no benchmarking framework
+//! among the dependencies, so each case is simply timed over enough
+//! iterations to take a fraction of a second, after a warm-up run.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ast::{
+    Dialect, Parser, ParserConfig, evaluate, parse_and_eval, parse_borrowed, parse_expression,
+    parse_expression_with,
+};
+
+/// How long to spend timing each case
+const TARGET: Duration = Duration::from_millis(500);
+
+/// A long formula with the usual mix of operators, calls and spacing
+fn formula(terms: usize) -> String {
+    (0..terms)
+        .map(|i| match i % 4 {
+            0 => format!("rate_{i} * 12.5"),
+            1 => format!("sqrt(x + {i}) / 2"),
+            2 => format!("(a - b) * -c_{i}"),
+            _ => format!("max(p, q) >= {i} && flag || !done"),
+        })
+        .collect::<Vec<_>>()
+        .join(" +\n    ")
+}
+
+/// Time a parse of `input`, printing the mean time and throughput
+fn bench(name: &str, input: &str, mut parse: impl FnMut(&str) -> usize) {
+    assert!(parse(input) > 0, "{name}: nothing parsed");
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < TARGET {
+        black_box(parse(black_box(input)));
+        iterations += 1;
+    }
+    let each = start.elapsed() / iterations;
+    let throughput = input.len() as f64 / each.as_secs_f64() / 1e6;
+    println!("{name:<24} {each:>12.2?} {throughput:>8.1} MB/s");
+}
+
+fn main() {
+    let short = "2 * (x + 1) - sqrt(y) / 4";
+    let long = formula(10_000);
+    let chain = vec!["1"; 100_000].join(" + ");
+    let spaced = format!("  {}  ", vec!["x"; 10_000].join("   *   "));
+    let excel = ParserConfig {
+        dialect: Dialect::Excel,
+        cell_references: true,
+        ..ParserConfig::default()
+    };
+    let excel_input = vec!["(A1 ^ 2 <> B2) * C3"; 5_000].join(" - ");
+
+    let consumed = |input: &str, remaining: &str| input.len() - remaining.len();
+    bench("short", short, |s| {
+        consumed(s, parse_expression(s).unwrap().0)
+    });
+    let arithmetic = "2 * (3.5 + 4) - sqrt(16) / max(1, 2) + abs(-3)";
+    bench("short, then evaluated", arithmetic, |s| {
+        let (remaining, ast) = parse_expression(s).unwrap();
+        black_box(evaluate(&ast).unwrap());
+        consumed(s, remaining)
+    });
+    bench("short, parse_and_eval", arithmetic, |s| {
+        black_box(parse_and_eval(s).unwrap());
+        s.len()
+    });
+    bench("long formula", &long, |s| {
+        consumed(s, parse_expression(s).unwrap().0)
+    });
+    bench("long formula, borrowed", &long, |s| {
+        consumed(s, parse_borrowed(s, &ParserConfig::default()).unwrap().0)
+    });
+    let formulas: Vec<String> = (0..1_000).map(|i| formula(i % 4 + 1)).collect();
+    let batch = formulas.join(";");
+    bench("1000 formulas", &batch, |s| {
+        s.split(';')
+            .map(|line| consumed(line, parse_expression(line).unwrap().0))
+            .sum()
+    });
+    let mut parser = Parser::default();
+    bench("1000 formulas, Parser", &batch, |s| {
+        s.split(';')
+            .map(|line| {
+                let (remaining, ast) = parser.parse(line).unwrap();
+                let parsed = consumed(line, remaining);
+                parser.recycle(ast);
+                parsed
+            })
+            .sum()
+    });
+    bench("100k chain", &chain, |s| {
+        consumed(s, parse_expression(s).unwrap().0)
+    });
+    bench("whitespace", &spaced, |s| {
+        consumed(s, parse_expression(s).unwrap().0)
+    });
+    bench("excel", &excel_input, |s| {
+        consumed(s, parse_expression_with(s, &excel).unwrap().0)
+    });
+}
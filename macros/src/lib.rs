@@ -0,0 +1,231 @@
+//! Expressions parsed at compile time
+//!
+//! [`expr!`] parses a formula written in Rust source while the program is
+//! built, so a typo in a hard-coded formula is a build error rather than a
+//! failure at runtime, and the program starts with the tree already built.
+//! [`const_expr!`] goes further, turning a formula into a
+//! [`ast::ConstExpr`] that can be evaluated in a `const`.
+
+use ast::{Expr, parse_with_recovery};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Parse an expression at compile time, giving the [`ast::Expr`] it describes
+///
+/// The argument is a string literal, raw or not, in the default syntax of
+/// [`ast::parse_expression`]. A syntax error stops the build, pointing at
+/// the literal. The expansion refers to the crate as `::ast`, so `ast` has
+/// to be a dependency under that name.
+///
+/// # Example
+/// ```
+/// use ast::{Environment, Evaluator, Expr, parse_expression};
+/// use ast_macros::expr;
+///
+/// let area: Expr = expr!("pi * r * r");
+/// assert_eq!(area, parse_expression("pi * r * r").unwrap().1);
+///
+/// let env: Environment = [("pi", 3.0), ("r", 2.0)].into_iter().collect();
+/// assert_eq!(Evaluator::new(&env).evaluate(&area).unwrap(), 12.0);
+/// ```
+///
+/// ```compile_fail
+/// let broken = ast_macros::expr!("1 + * 2");
+/// ```
+#[proc_macro]
+pub fn expr(input: TokenStream) -> TokenStream {
+    let expanded = parse(flatten(input)).and_then(|(ast, _, rest)| match rest.first() {
+        Some(token) => Err(("expected only a string literal".to_string(), token.span())),
+        None => Ok(ast.to_rust_constructor()),
+    });
+    finish(expanded)
+}
+
+/// Parse an expression at compile time, giving an [`ast::ConstExpr`] that computes it
+///
+/// The string literal is followed by the names of the expression's
+/// variables, in the order their values are given to
+/// [`ast::ConstExpr::evaluate`]. Expressions that can't be evaluated in a
+/// `const fn`, such as ones calling `sqrt`, stop the build, as do syntax
+/// errors and variables that aren't listed.
+///
+/// # Example
+/// ```
+/// use ast::ConstExpr;
+/// use ast_macros::const_expr;
+///
+/// const AREA: ConstExpr = const_expr!("piecewise((w < 0 || h < 0, 0), w * h)", w, h);
+/// const TILE: f64 = AREA.value(&[0.5, 0.25]);
+/// assert_eq!(TILE, 0.125);
+///
+/// // Computed at compile time, with nothing to pass in
+/// const SECONDS_PER_WEEK: f64 = const_expr!("7 * 24 * 60 * 60").value(&[]);
+/// assert_eq!(SECONDS_PER_WEEK, 604800.0);
+/// ```
+///
+/// ```compile_fail
+/// const ROOT: ast::ConstExpr = ast_macros::const_expr!("sqrt(x)", x);
+/// ```
+#[proc_macro]
+pub fn const_expr(input: TokenStream) -> TokenStream {
+    let expanded = parse(flatten(input)).and_then(|(ast, span, rest)| {
+        let mut vars = Vec::new();
+        let mut rest = rest.into_iter();
+        while let Some(token) = rest.next() {
+            match (token, rest.next()) {
+                (TokenTree::Punct(comma), Some(TokenTree::Ident(name)))
+                    if comma.as_char() == ',' =>
+                {
+                    vars.push(name.to_string());
+                }
+                (TokenTree::Punct(comma), None) if comma.as_char() == ',' => break,
+                (token, _) => {
+                    return Err((
+                        "expected a comma and a variable name".to_string(),
+                        token.span(),
+                    ));
+                }
+            }
+        }
+        let vars: Vec<&str> = vars.iter().map(String::as_str).collect();
+        ast.to_rust_const(&vars)
+            .map_err(|error| (format!("can't be evaluated in a const: {}", error), span))
+    });
+    finish(expanded)
+}
+
+/// Parse the string literal that starts a macro's input
+///
+/// Gives the tree, the literal's span and the tokens after the literal.
+fn parse(tokens: Vec<TokenTree>) -> Result<(Expr, Span, Vec<TokenTree>), (String, Span)> {
+    let mut tokens = tokens.into_iter();
+    let literal = match tokens.next() {
+        Some(TokenTree::Literal(literal)) => literal,
+        Some(token) => {
+            return Err(("expected a string literal".to_string(), token.span()));
+        }
+        None => return Err(("expected a string literal".to_string(), Span::call_site())),
+    };
+    let source = unquote(&literal.to_string())
+        .ok_or_else(|| ("expected a string literal".to_string(), literal.span()))?;
+    let (ast, diagnostics) = parse_with_recovery(&source);
+    if let Some(diagnostic) = diagnostics.first() {
+        return Err((
+            format!("invalid expression: {}", diagnostic),
+            literal.span(),
+        ));
+    }
+    Ok((ast, literal.span(), tokens.collect()))
+}
+
+/// The code a macro expands to, or a compile error if it failed
+fn finish(expanded: Result<String, (String, Span)>) -> TokenStream {
+    match expanded {
+        Ok(code) => code.parse().expect("generated code is always valid Rust"),
+        Err((message, span)) => compile_error(&message, span),
+    }
+}
+
+/// The tokens, with any invisible groups from `macro_rules!` expansions opened up
+fn flatten(input: TokenStream) -> Vec<TokenTree> {
+    input
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::None => {
+                flatten(group.stream())
+            }
+            token => vec![token],
+        })
+        .collect()
+}
+
+/// The text of a string literal as written in source, or `None` if it isn't one
+fn unquote(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let raw = &raw[hashes..];
+        return raw
+            .strip_prefix('"')?
+            .strip_suffix(&"#".repeat(hashes))?
+            .strip_suffix('"')
+            .map(str::to_string);
+    }
+
+    let quoted = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => text.push('\n'),
+            'r' => text.push('\r'),
+            't' => text.push('\t'),
+            '0' => text.push('\0'),
+            '\\' => text.push('\\'),
+            '\'' => text.push('\''),
+            '"' => text.push('"'),
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                text.push(char::from(u8::from_str_radix(&digits, 16).ok()?));
+            }
+            'u' => {
+                let digits: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                text.push(char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?);
+            }
+            // A backslash at the end of a line skips the line break and the
+            // indentation after it
+            '\n' => {
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(text)
+}
+
+/// A `compile_error!` invocation with `message`, reported at `span`
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut arguments = Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(message)),
+    );
+    arguments.set_span(span);
+    [
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(arguments),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test reading string literals as they're written in source
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote(r#""x + 1""#).as_deref(), Some("x + 1"));
+        assert_eq!(
+            unquote(r#""a\t\"b\" \x41\u{e9}\\""#).as_deref(),
+            Some("a\t\"b\" A\u{e9}\\")
+        );
+        assert_eq!(unquote("\"1 +\\\n     2\"").as_deref(), Some("1 +2"));
+        assert_eq!(
+            unquote(r###"r#"say "hi""#"###).as_deref(),
+            Some(r#"say "hi""#)
+        );
+        assert_eq!(unquote(r#"r"x""#).as_deref(), Some("x"));
+        assert_eq!(unquote("42"), None);
+        assert_eq!(unquote(r#"b"x""#), None);
+        assert_eq!(unquote(r#""\q""#), None);
+    }
+}
@@ -0,0 +1,239 @@
+//! Records of what an evaluation used
+//!
+//! Some figures have to be explained long after they were computed: which
+//! rate was in effect, what the lookup returned. [`Evaluator::evaluate_audited`]
+//! evaluates as usual while keeping an [`AuditLog`] of every variable and
+//! cell it read and every function it called, in the order it happened,
+//! which can be stored as JSON next to the result.
+
+use std::cell::RefCell;
+
+use serde::{Serialize, Serializer};
+
+use crate::{EvaluationError, Evaluator, Expr, Value, explain::grouped};
+
+/// One thing an evaluation read or called
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A variable was looked up in the environment
+    ///
+    /// Variables bound by `let` are part of the expression, so they aren't recorded.
+    Variable {
+        /// The variable's name
+        name: String,
+        /// Its value, or `None` if it wasn't defined
+        #[serde(serialize_with = "optional")]
+        value: Option<f64>,
+    },
+    /// A spreadsheet cell was looked up
+    Cell {
+        /// The cell, such as `B2`
+        cell: String,
+        /// Its value, or `None` if it had none
+        #[serde(serialize_with = "optional")]
+        value: Option<f64>,
+    },
+    /// A range of cells was looked up, as a function argument
+    Range {
+        /// The first cell
+        from: String,
+        /// The last cell
+        to: String,
+        /// The values of the cells that have them
+        #[serde(serialize_with = "numbers")]
+        values: Vec<f64>,
+    },
+    /// A function was called, after everything its arguments read and called
+    Call {
+        /// The function's name, as written in the call
+        function: String,
+        /// The arguments' values, or `None` for functions like `if` that
+        /// evaluate their own arguments (their reads are recorded before this)
+        arguments: Option<Vec<String>>,
+        /// What the function gave, if it succeeded
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<String>,
+        /// Why the function failed, if it did
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// An evaluation and everything it read and called
+///
+/// Numbers are written to JSON exactly, so a figure can be recomputed from
+/// its log; infinities and NaN, which JSON has no numbers for, are written
+/// as the strings `"inf"`, `"-inf"` and `"NaN"`.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct AuditLog {
+    /// The expression that was evaluated, fully parenthesized
+    pub expression: String,
+    /// The result, if the evaluation succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    /// Why the evaluation failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// What the evaluation read and called, in order
+    pub events: Vec<AuditEvent>,
+}
+
+impl AuditLog {
+    /// The log as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("audit logs are always valid JSON")
+    }
+}
+
+impl Evaluator<'_> {
+    /// Evaluate an expression, keeping a log of every variable, cell and function it used
+    ///
+    /// The result is the same as from [`Evaluator::evaluate_value`]; the
+    /// log is returned whether or not the evaluation succeeds.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, AuditEvent, Environment, Evaluator};
+    ///
+    /// let (_, ast) = parse_expression("round(price * (1 + vat), 2)").unwrap();
+    /// let env: Environment = [("price", 19.99), ("vat", 0.2)].into_iter().collect();
+    /// let (result, log) = Evaluator::new(&env).evaluate_audited(&ast);
+    ///
+    /// assert_eq!(result.unwrap().to_string(), "23.99");
+    /// assert_eq!(log.events[0], AuditEvent::Variable { name: "price".into(), value: Some(19.99) });
+    /// assert!(log.to_json().contains("\"function\": \"round\""));
+    /// ```
+    pub fn evaluate_audited(&self, expr: &Expr) -> (Result<Value, EvaluationError>, AuditLog) {
+        let events = RefCell::new(Vec::new());
+        let result = self.with_audit(&events).evaluate_value(expr);
+        let log = AuditLog {
+            expression: grouped(expr),
+            result: result.as_ref().ok().map(text),
+            error: result.as_ref().err().map(ToString::to_string),
+            events: events.into_inner(),
+        };
+        (result, log)
+    }
+}
+
+/// A value written out exactly, for the log
+pub(crate) fn text(value: &Value) -> String {
+    match value {
+        Value::Number(number) => number.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// A number for JSON, as a string if JSON can't hold it
+struct Number(f64);
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_finite() {
+            serializer.serialize_f64(self.0)
+        } else {
+            serializer.serialize_str(&self.0.to_string())
+        }
+    }
+}
+
+/// Write a number that may be missing
+fn optional<S: Serializer>(value: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    value.map(Number).serialize(serializer)
+}
+
+/// Write a list of numbers
+fn numbers<S: Serializer>(values: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|&value| Number(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CellRef, CellResolver, Environment, ParserConfig, parse_expression, parse_expression_with,
+    };
+
+    /// Cells holding their row number, in column A only
+    struct Rows;
+
+    impl CellResolver for Rows {
+        fn cell(&self, cell: CellRef) -> Option<f64> {
+            (cell.column == 1).then_some(cell.row as f64)
+        }
+    }
+
+    /// Test what's recorded, and in what order
+    #[test]
+    fn test_events() {
+        let input = "let k = 2 in max(x * k, A2, mean(A1:A3)) + if(y > 0, 1, B1)";
+        let (_, ast) = parse_expression_with(input, &ParserConfig::spreadsheet()).unwrap();
+        let env: Environment = [("x", 1.5)].into_iter().collect();
+        let evaluator = Evaluator::new(&env).with_cells(&Rows);
+        let (result, log) = evaluator.evaluate_audited(&ast);
+        assert!(result.is_err());
+        let call =
+            |function: &str, arguments: Option<&[&str]>, result: Option<&str>| AuditEvent::Call {
+                function: function.to_string(),
+                arguments: arguments
+                    .map(|arguments| arguments.iter().map(|a| a.to_string()).collect()),
+                result: result.map(str::to_string),
+                error: None,
+            };
+        let mut expected = vec![
+            AuditEvent::Variable {
+                name: "x".to_string(),
+                value: Some(1.5),
+            },
+            AuditEvent::Cell {
+                cell: "A2".to_string(),
+                value: Some(2.0),
+            },
+            AuditEvent::Range {
+                from: "A1".to_string(),
+                to: "A3".to_string(),
+                values: vec![1.0, 2.0, 3.0],
+            },
+            call("mean", Some(&["1", "2", "3"]), Some("2")),
+            call("max", Some(&["3", "2", "2"]), Some("3")),
+            AuditEvent::Variable {
+                name: "y".to_string(),
+                value: None,
+            },
+        ];
+        expected.push(AuditEvent::Call {
+            function: "if".to_string(),
+            arguments: None,
+            result: None,
+            error: Some("Undefined variable 'y'".to_string()),
+        });
+        assert_eq!(log.events, expected);
+        assert_eq!(log.error.as_deref(), Some("Undefined variable 'y'"));
+    }
+
+    /// Test the JSON form, including numbers JSON can't hold
+    #[test]
+    fn test_json() {
+        let (_, ast) = parse_expression("a / b + c").unwrap();
+        let env: Environment = [("a", 1.0), ("b", 0.0), ("c", f64::NAN)]
+            .into_iter()
+            .collect();
+        let (_, log) = Evaluator::new(&env).evaluate_audited(&ast);
+        let json: serde_json::Value = serde_json::from_str(&log.to_json()).unwrap();
+        assert_eq!(json["expression"], "(a / b) + c");
+        assert_eq!(json["error"], "Division by zero");
+        assert_eq!(
+            json["events"][0],
+            serde_json::json!({"event": "variable", "name": "b", "value": 0.0})
+        );
+        assert_eq!(json["events"].as_array().unwrap().len(), 2);
+
+        let (_, ast) = parse_expression("c * 0.1").unwrap();
+        let (result, log) = Evaluator::new(&env).evaluate_audited(&ast);
+        assert!(result.unwrap().into_number().unwrap().is_nan());
+        let json: serde_json::Value = serde_json::from_str(&log.to_json()).unwrap();
+        assert_eq!(json["events"][0]["value"], "NaN");
+        assert_eq!(json["result"], "NaN");
+    }
+}
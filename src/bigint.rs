@@ -0,0 +1,177 @@
+//! Exact integers of any size, with the `bigint` feature
+//!
+//! `factorial(100)` and `pow(2, 512)` are far beyond an `i64`, and beyond
+//! what an `f64` holds exactly; `factorial(171)` is infinite as a float.
+//! [`evaluate_big`] computes them exactly, the way [`evaluate_checked`](crate::evaluate_checked)
+//! computes with 64-bit integers, with [`BigInt`] from the `num-bigint`
+//! crate as the integer type.
+//!
+//! Numbers convert between integers and floats the same way in both:
+//!
+//! - a literal or input that's a whole float up to 2^53 in magnitude is an
+//!   integer, since every such float is exactly that integer; larger floats
+//!   stay floats, and big integers should be given as [`Number::Integer`];
+//! - an integer becomes a float, the nearest one, when it's combined with a
+//!   float, divided with a remainder, or given to a function without an
+//!   exact version, such as `sqrt`; one beyond `f64::MAX` becomes infinite;
+//! - a float never becomes an integer again, except through `floor`, `ceil`,
+//!   `round` and `trunc` when the result is at most 2^53.
+//!
+//! Integers are limited to [`MAX_BIGINT_BITS`] bits, so that a formula such as
+//! `pow(10, 1e9)` can't take all of memory; a result beyond that is an
+//! [`EvaluationError::Overflow`].
+
+pub use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::{EvaluationError, Expr, Number, integer::Whole};
+
+/// How many bits a big integer may have, about 79,000 decimal digits
+pub const MAX_BIGINT_BITS: u64 = 1 << 18;
+
+impl Whole for BigInt {
+    const LIMIT: f64 = f64::MAX;
+
+    fn to_f64(&self) -> f64 {
+        ToPrimitive::to_f64(self).unwrap_or(f64::NAN)
+    }
+
+    fn to_u32(&self) -> Option<u32> {
+        ToPrimitive::to_u32(self)
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_sub(&self, other: &Self) -> Option<Self> {
+        Some(self - other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        (self.bits() + other.bits() <= MAX_BIGINT_BITS).then(|| self * other)
+    }
+
+    fn checked_div(&self, other: &Self) -> Option<Self> {
+        Some(self / other)
+    }
+
+    fn divisible_by(&self, other: &Self) -> bool {
+        (self % other).is_zero()
+    }
+
+    fn checked_neg(&self) -> Option<Self> {
+        Some(-self)
+    }
+
+    fn checked_pow(&self, exponent: u32) -> Option<Self> {
+        // Powers of 0, 1 and -1 stay small, whatever the exponent
+        let small = self.abs() <= BigInt::from(1);
+        (small || self.bits().saturating_mul(u64::from(exponent)) <= MAX_BIGINT_BITS)
+            .then(|| self.pow(exponent))
+    }
+}
+
+impl Number<BigInt> {
+    /// The number as the nearest float, which is infinite beyond `f64::MAX`
+    pub fn to_f64(&self) -> f64 {
+        crate::integer::float(self)
+    }
+
+    /// The number as an integer, if it's one
+    pub fn as_integer(&self) -> Option<&BigInt> {
+        match self {
+            Number::Integer(value) => Some(value),
+            Number::Float(_) => None,
+        }
+    }
+}
+
+impl From<BigInt> for Number<BigInt> {
+    fn from(value: BigInt) -> Self {
+        Number::Integer(value)
+    }
+}
+
+/// Evaluate `expr` with whole numbers kept as integers of any size
+///
+/// This is [`evaluate_checked`](crate::evaluate_checked) with [`BigInt`]s instead of `i64`s, so
+/// results only overflow beyond [`MAX_BIGINT_BITS`] bits.
+///
+/// # Example
+/// ```
+/// use ast::{evaluate_big, parse_expression, BigInt, Number};
+///
+/// let (_, ast) = parse_expression("factorial(25) / factorial(23)").unwrap();
+/// assert_eq!(evaluate_big(&ast, &[]).unwrap(), Number::Integer(BigInt::from(600)));
+///
+/// let (_, ast) = parse_expression("pow(2, 512) + 1").unwrap();
+/// let result = evaluate_big(&ast, &[]).unwrap();
+/// assert_eq!(result.to_string().len(), 155);
+/// assert!(result.to_string().ends_with("084097"));
+/// ```
+pub fn evaluate_big(
+    expr: &Expr,
+    vars: &[(&str, Number<BigInt>)],
+) -> Result<Number<BigInt>, EvaluationError> {
+    crate::integer::evaluate_whole(expr, vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// The value of an expression, which must parse
+    fn eval(input: &str) -> Result<Number<BigInt>, EvaluationError> {
+        let (rest, ast) = parse_expression(input).unwrap();
+        assert_eq!(rest, "");
+        evaluate_big(&ast, &[])
+    }
+
+    /// Test exact results far beyond 64 bits, and the conversions to floats
+    #[test]
+    fn test_big() {
+        let hundred = eval("factorial(100)").unwrap();
+        let digits = hundred.to_string();
+        assert_eq!(digits.len(), 158);
+        assert!(digits.starts_with("933262154439441526816992388562667"));
+        assert!(digits.ends_with(&"0".repeat(24)));
+        assert_eq!(
+            eval("factorial(100) / factorial(99)").unwrap(),
+            Number::Integer(BigInt::from(100))
+        );
+        assert_eq!(
+            eval("comb(100, 50)").unwrap().to_string(),
+            "100891344545564193334812497256"
+        );
+        assert_eq!(
+            eval("pow(2, 512) > pow(2, 511) * 2 - 1").unwrap(),
+            Number::Integer(BigInt::from(1))
+        );
+        assert_eq!(
+            eval("pow(-1, 1e9)").unwrap(),
+            Number::Integer(BigInt::from(1))
+        );
+
+        // Back to floats
+        assert_eq!(
+            eval("factorial(171) * 0.5").unwrap(),
+            Number::Float(f64::INFINITY)
+        );
+        assert_eq!(
+            eval("pow(2, 60) / 3").unwrap().to_f64(),
+            2f64.powi(60) / 3.0
+        );
+        assert_eq!(eval("sqrt(pow(10, 40))").unwrap(), Number::Float(1e20));
+
+        assert!(matches!(
+            eval("pow(10, 1e6)"),
+            Err(EvaluationError::Overflow { .. })
+        ));
+        assert!(matches!(
+            eval("factorial(-1)"),
+            Err(EvaluationError::OutOfDomain { .. })
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+//! HMAC-SHA256, which signs Jupyter messages
+//!
+//! Every message between a notebook and its kernel carries the HMAC of its
+//! parts, keyed with the `key` from the connection file, as lowercase hex.
+
+/// The SHA-256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The bytes SHA-256 works on at a time
+const BLOCK: usize = 64;
+
+/// A SHA-256 hash being computed
+#[derive(Clone)]
+pub struct Sha256 {
+    /// The hash of the blocks so far
+    state: [u32; 8],
+    /// Bytes not yet making up a whole block
+    pending: Vec<u8>,
+    /// How many bytes have been hashed, pending ones included
+    length: u64,
+}
+
+impl Sha256 {
+    /// A hash of nothing yet
+    pub fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            pending: Vec::with_capacity(BLOCK),
+            length: 0,
+        }
+    }
+
+    /// Hash `data` after what came before
+    pub fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        let whole = self.pending.len() - self.pending.len() % BLOCK;
+        for block in self.pending[..whole].chunks_exact(BLOCK) {
+            compress(&mut self.state, block);
+        }
+        self.pending.drain(..whole);
+    }
+
+    /// The hash of everything given to [`Sha256::update`]
+    pub fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+        self.pending.push(0x80);
+        while self.pending.len() % BLOCK != BLOCK - 8 {
+            self.pending.push(0);
+        }
+        self.pending.extend(bits.to_be_bytes());
+        for block in self.pending.chunks_exact(BLOCK) {
+            compress(&mut self.state, block);
+        }
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+}
+
+/// Mix one block into the state
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes(bytes.try_into().expect("4 bytes"));
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(choice)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(majority);
+        (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// The HMAC-SHA256 of `parts`, one after another, as lowercase hex
+pub fn sign(key: &[u8], parts: &[&[u8]]) -> String {
+    let mut padded = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        let mut hash = Sha256::new();
+        hash.update(key);
+        padded[..32].copy_from_slice(&hash.finish());
+    } else {
+        padded[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(&padded.map(|byte| byte ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(&padded.map(|byte| byte ^ 0x5c));
+    outer.update(&inner.finish());
+    outer
+        .finish()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether `signature` is what [`sign`] gives for `parts`
+///
+/// Every byte is compared, so how long it takes doesn't tell a forger how
+/// much of a guess was right.
+pub fn verify(key: &[u8], parts: &[&[u8]], signature: &[u8]) -> bool {
+    let expected = sign(key, parts);
+    expected.len() == signature.len()
+        && expected
+            .bytes()
+            .zip(signature)
+            .fold(0, |differences, (a, b)| differences | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-256 of `data`, as hex
+    fn sha256(data: &[u8]) -> String {
+        let mut hash = Sha256::new();
+        hash.update(data);
+        hash.finish()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Test against the FIPS 180-2 examples, fed in pieces of every size
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let expected = "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1";
+        assert_eq!(sha256(long), expected);
+        for size in 1..long.len() {
+            let mut hash = Sha256::new();
+            long.chunks(size).for_each(|chunk| hash.update(chunk));
+            let hex: String = hash.finish().iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(hex, expected, "{}", size);
+        }
+        assert_eq!(
+            sha256(&[b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    /// A key, a message in parts and the start of its signature
+    type Case<'a> = (&'a [u8], &'a [&'a [u8]], &'a str);
+
+    /// Test against the RFC 4231 examples
+    #[test]
+    fn test_hmac() {
+        let counting: Vec<u8> = (1..=25).collect();
+        let cases: [Case; 7] = [
+            (
+                &[0x0b; 20],
+                &[b"Hi ", b"There"],
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                &[b"what do ya want for nothing?"],
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[&[0xdd; 50]],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &counting,
+                &[&[0xcd; 50]],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // The RFC only gives the first 128 bits of this one
+            (
+                &[0x0c; 20],
+                &[b"Test With Truncation"],
+                "a3b6167473100ee06e0c796c2955552b",
+            ),
+            // A key longer than a block is hashed first
+            (
+                &[0xaa; 131],
+                &[b"Test Using Larger Than Block-Size Key - Hash Key First"],
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                &[
+                    b"This is a test using a larger than block-size key and a larger ",
+                    b"than block-size data. The key needs to be hashed before being ",
+                    b"used by the HMAC algorithm.",
+                ],
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (number, (key, parts, expected)) in cases.into_iter().enumerate() {
+            assert!(
+                sign(key, parts).starts_with(expected),
+                "test case {}",
+                number + 1
+            );
+        }
+    }
+
+    /// Test that only the exact signature is accepted
+    #[test]
+    fn test_verify() {
+        let signature = sign(b"Jefe", &[b"what do ya want for nothing?"]);
+        assert!(verify(
+            b"Jefe",
+            &[b"what do ya ", b"want for nothing?"],
+            signature.as_bytes()
+        ));
+        let mut forged = signature.clone().into_bytes();
+        forged[63] ^= 1;
+        for wrong in [&forged, &signature.as_bytes()[..63], b"".as_slice()] {
+            assert!(!verify(b"Jefe", &[b"what do ya want for nothing?"], wrong));
+        }
+        assert!(!verify(
+            b"Jeff",
+            &[b"what do ya want for nothing?"],
+            signature.as_bytes()
+        ));
+    }
+}
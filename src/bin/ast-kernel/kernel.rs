@@ -0,0 +1,506 @@
+//! The Jupyter messaging protocol: what the kernel says to each request
+//!
+//! A cell's lines are evaluated in one [`Session`], so variables and
+//! functions carry over from cell to cell. The value of the last line is the
+//! cell's result, shown as text, as LaTeX (the expression typeset, and its
+//! value) and as HTML (the expression's tree).
+
+use std::fmt::Write;
+
+use ast::{DateTime, Expr, FunctionRegistry, Session, SessionError, Value, parse_expression};
+use serde_json::{Value as Json, json};
+
+use crate::hmac;
+
+/// The version of the messaging protocol spoken
+const PROTOCOL_VERSION: &str = "5.3";
+
+/// The frame between a message's routing identities and its signature
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// A message of the Jupyter protocol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// Routing identities from the peer, or the topic of a published message
+    pub identities: Vec<Vec<u8>>,
+    pub header: Json,
+    pub parent_header: Json,
+    pub metadata: Json,
+    pub content: Json,
+}
+
+impl Message {
+    /// Decode a message's frames, checking its signature
+    pub fn decode(mut frames: Vec<Vec<u8>>, key: &[u8]) -> Result<Message, String> {
+        let delimiter = frames
+            .iter()
+            .position(|frame| frame == DELIMITER)
+            .ok_or("no <IDS|MSG> delimiter")?;
+        let mut parts = frames.split_off(delimiter);
+        if parts.len() < 6 {
+            return Err(format!(
+                "{} frames after the delimiter, not 5",
+                parts.len() - 1
+            ));
+        }
+        parts.truncate(6);
+        let signed: Vec<&[u8]> = parts[2..].iter().map(Vec::as_slice).collect();
+        if !key.is_empty() && !hmac::verify(key, &signed, &parts[1]) {
+            return Err("invalid signature".to_string());
+        }
+        let json =
+            |index: usize| serde_json::from_slice(&parts[index]).map_err(|error| error.to_string());
+        Ok(Message {
+            identities: frames,
+            header: json(2)?,
+            parent_header: json(3)?,
+            metadata: json(4)?,
+            content: json(5)?,
+        })
+    }
+
+    /// The message's frames, signed with `key`
+    pub fn encode(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        let parts = [
+            &self.header,
+            &self.parent_header,
+            &self.metadata,
+            &self.content,
+        ]
+        .map(|part| part.to_string().into_bytes());
+        let signature = if key.is_empty() {
+            String::new()
+        } else {
+            hmac::sign(key, &parts.each_ref().map(Vec::as_slice))
+        };
+        let mut frames = self.identities.clone();
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature.into_bytes());
+        frames.extend(parts);
+        frames
+    }
+
+    /// What kind of message it is, such as `execute_request`
+    pub fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+}
+
+/// Where a message from the kernel goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// Back to the peer the request came from, on the same socket
+    Reply,
+    /// To every notebook, on the IOPub socket
+    Publish,
+}
+
+/// The kernel's state between requests
+pub struct Kernel {
+    /// Variables and functions, kept between cells
+    session: Session,
+    /// This run of the kernel's id, which starts every message id
+    id: String,
+    /// How many messages the kernel has sent
+    sent: u64,
+    /// How many cells have been executed
+    executions: u64,
+}
+
+impl Kernel {
+    /// A kernel whose message ids start with `id`
+    pub fn new(id: String) -> Self {
+        Kernel {
+            session: Session::new(),
+            id,
+            sent: 0,
+            executions: 0,
+        }
+    }
+
+    /// The messages to send in response to `request`, and whether to shut down after
+    pub fn handle(&mut self, request: &Message) -> (Vec<(Route, Message)>, bool) {
+        let mut out = vec![self.status(request, "busy")];
+        let mut shutdown = false;
+        let reply = match request.msg_type() {
+            "kernel_info_request" => Some(("kernel_info_reply", kernel_info())),
+            "execute_request" => Some(("execute_reply", self.execute(request, &mut out))),
+            "complete_request" => Some(("complete_reply", self.complete(&request.content))),
+            "is_complete_request" => Some(("is_complete_reply", is_complete(&request.content))),
+            "inspect_request" => Some((
+                "inspect_reply",
+                json!({"status": "ok", "found": false, "data": {}, "metadata": {}}),
+            )),
+            "history_request" => Some(("history_reply", json!({"status": "ok", "history": []}))),
+            "comm_info_request" => Some(("comm_info_reply", json!({"status": "ok", "comms": {}}))),
+            "shutdown_request" => {
+                shutdown = true;
+                let restart = request.content["restart"].as_bool().unwrap_or(false);
+                Some((
+                    "shutdown_reply",
+                    json!({"status": "ok", "restart": restart}),
+                ))
+            }
+            _ => None,
+        };
+        if let Some((msg_type, content)) = reply {
+            let reply = self.message(request, request.identities.clone(), msg_type, content);
+            out.push((Route::Reply, reply));
+        }
+        out.push(self.status(request, "idle"));
+        (out, shutdown)
+    }
+
+    /// Run a cell, publishing its input and its result or error
+    fn execute(&mut self, request: &Message, out: &mut Vec<(Route, Message)>) -> Json {
+        let code = request.content["code"].as_str().unwrap_or_default();
+        let silent = request.content["silent"].as_bool().unwrap_or(false);
+        if !silent {
+            self.executions += 1;
+        }
+        let count = self.executions;
+        let input = json!({"code": code, "execution_count": count});
+        out.push(self.publish(request, "execute_input", input));
+
+        let mut last = None;
+        for line in code.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match self.session.evaluate(line) {
+                Ok(value) => last = Some((line, value)),
+                Err(error) => {
+                    let ename = match error {
+                        SessionError::Syntax { .. } => "SyntaxError",
+                        _ => "EvaluationError",
+                    };
+                    let evalue = format!("{}: {}", line, error);
+                    let content = json!({"ename": ename, "evalue": evalue, "traceback": [evalue]});
+                    if !silent {
+                        out.push(self.publish(request, "error", content.clone()));
+                    }
+                    let mut reply = content;
+                    reply["status"] = json!("error");
+                    reply["execution_count"] = json!(count);
+                    return reply;
+                }
+            }
+        }
+        if let Some((line, value)) = last
+            && !silent
+            && value != Value::Null
+        {
+            let data = self.display(line, &value);
+            let result = json!({"execution_count": count, "data": data, "metadata": {}});
+            out.push(self.publish(request, "execute_result", result));
+        }
+        json!({"status": "ok", "execution_count": count, "user_expressions": {}})
+    }
+
+    /// A line's result as text, LaTeX and HTML
+    fn display(&self, line: &str, value: &Value) -> Json {
+        let text = self.session.format(value);
+        let (assigned, expression) = match self.session.split_assignment(line) {
+            Some((assignee, expression)) => (Some(assignee), expression),
+            None => (None, line),
+        };
+        let mut data = json!({ "text/plain": text });
+        let Ok((rest, expr)) = parse_expression(expression) else {
+            return data;
+        };
+        if !rest.trim().is_empty() {
+            return data;
+        }
+        let value = if text.parse::<f64>().is_ok() {
+            text.clone()
+        } else {
+            format!(r"\text{{{}}}", text.replace(['{', '}', '\\'], ""))
+        };
+        let latex = match assigned {
+            Some(ast::Assignee::Variable(name)) => {
+                format!(
+                    "{} = {}",
+                    Expr::Var(name.to_string()).to_latex(),
+                    expr.to_latex()
+                )
+            }
+            _ => expr.to_latex(),
+        };
+        data["text/latex"] = json!(match expr {
+            Expr::Float(_) => format!("$${}$$", value),
+            _ => format!("$${} = {}$$", latex, value),
+        });
+        data["text/html"] = json!(html_tree(&expr));
+        data
+    }
+
+    /// Names starting with the word before the cursor
+    fn complete(&self, content: &Json) -> Json {
+        let code = content["code"].as_str().unwrap_or_default();
+        let cursor = content["cursor_pos"].as_u64().unwrap_or(0) as usize;
+        // The cursor counts characters, not bytes
+        let end = code
+            .char_indices()
+            .nth(cursor)
+            .map_or(code.len(), |(index, _)| index);
+        let before = &code[..end];
+        let start = before
+            .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .map_or(0, |index| index + 1);
+        let word = &before[start..];
+        let functions = FunctionRegistry::standard();
+        let mut matches: Vec<&str> = functions
+            .names()
+            .into_iter()
+            .chain(self.session.defined_functions())
+            .chain(self.session.environment().iter().map(|(name, _)| name))
+            .filter(|name| name.starts_with(word))
+            .collect();
+        matches.sort_unstable();
+        matches.dedup();
+        json!({
+            "status": "ok",
+            "matches": matches,
+            "cursor_start": before[..start].chars().count(),
+            "cursor_end": cursor,
+            "metadata": {},
+        })
+    }
+
+    /// The kernel's status, busy or idle, published about `parent`
+    fn status(&mut self, parent: &Message, state: &str) -> (Route, Message) {
+        self.publish(parent, "status", json!({ "execution_state": state }))
+    }
+
+    /// A message for every notebook, about `parent`
+    fn publish(&mut self, parent: &Message, msg_type: &str, content: Json) -> (Route, Message) {
+        let topic = format!("kernel.{}.{}", self.id, msg_type).into_bytes();
+        (
+            Route::Publish,
+            self.message(parent, vec![topic], msg_type, content),
+        )
+    }
+
+    /// A new message in response to `parent`
+    fn message(
+        &mut self,
+        parent: &Message,
+        identities: Vec<Vec<u8>>,
+        msg_type: &str,
+        content: Json,
+    ) -> Message {
+        self.sent += 1;
+        let header = json!({
+            "msg_id": format!("{}_{}", self.id, self.sent),
+            "session": self.id,
+            "username": "kernel",
+            "date": now(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        Message {
+            identities,
+            header,
+            parent_header: parent.header.clone(),
+            metadata: json!({}),
+            content,
+        }
+    }
+}
+
+/// What the kernel is and speaks
+fn kernel_info() -> Json {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "ast",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "ast",
+            "version": env!("CARGO_PKG_VERSION"),
+            "mimetype": "text/x-ast",
+            "file_extension": ".ast",
+        },
+        "banner": "AST calculator: each cell's last line is its result. Set variables with \
+                   'x = 2' and define functions with 'f(x) = x * x'.",
+        "help_links": [],
+    })
+}
+
+/// Whether a cell is ready to run: it is unless its brackets are still open
+fn is_complete(content: &Json) -> Json {
+    let code = content["code"].as_str().unwrap_or_default();
+    let open = code.chars().fold(0i64, |open, c| match c {
+        '(' | '[' => open + 1,
+        ')' | ']' => open - 1,
+        _ => open,
+    });
+    if open > 0 {
+        json!({"status": "incomplete", "indent": ""})
+    } else {
+        json!({"status": "complete"})
+    }
+}
+
+/// A tree as nested HTML lists, each node its kind and label
+fn html_tree(expr: &Expr) -> String {
+    let graph = expr.to_graph();
+    let mut built: Vec<String> = Vec::with_capacity(graph.nodes.len());
+    // Children come before their parents
+    for node in &graph.nodes {
+        let mut html = format!("<li><code>{}</code> {}", node.kind, escape(&node.label));
+        if !node.children.is_empty() {
+            html.push_str("<ul>");
+            for &child in &node.children {
+                html.push_str(&built[child]);
+            }
+            html.push_str("</ul>");
+        }
+        html.push_str("</li>");
+        built.push(html);
+    }
+    format!(
+        "<details><summary>tree</summary><ul>{}</ul></details>",
+        built.swap_remove(graph.root)
+    )
+}
+
+/// Text with the characters HTML treats specially escaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The time now in ISO 8601, in UTC
+fn now() -> String {
+    let now = DateTime::now();
+    let (year, month, day) = now.ymd();
+    let seconds = now.time_of_day();
+    let mut date = format!("{:04}-{:02}-{:02}T", year, month, day);
+    let (hours, minutes) = ((seconds / 3600.0) as u32, (seconds % 3600.0 / 60.0) as u32);
+    write!(date, "{:02}:{:02}:{:06.3}Z", hours, minutes, seconds % 60.0)
+        .expect("writing to a string can't fail");
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A request of some type, as a notebook would send it
+    fn request(msg_type: &str, content: Json) -> Message {
+        Message {
+            identities: vec![b"notebook".to_vec()],
+            header: json!({"msg_id": "1", "session": "s", "msg_type": msg_type}),
+            parent_header: json!({}),
+            metadata: json!({}),
+            content,
+        }
+    }
+
+    /// The published messages' types and the replies' contents
+    fn run(kernel: &mut Kernel, msg_type: &str, content: Json) -> (Vec<String>, Vec<Message>) {
+        let (out, _) = kernel.handle(&request(msg_type, content));
+        let published = out
+            .iter()
+            .filter(|(route, _)| *route == Route::Publish)
+            .map(|(_, message)| message.msg_type().to_string())
+            .collect();
+        let replies = out
+            .into_iter()
+            .filter(|(route, _)| *route == Route::Reply)
+            .map(|(_, message)| message)
+            .collect();
+        (published, replies)
+    }
+
+    /// Test that messages survive encoding, and that forged ones are turned away
+    #[test]
+    fn test_encoding() {
+        let message = request("kernel_info_request", json!({}));
+        let frames = message.encode(b"secret");
+        assert_eq!(frames[0], b"notebook");
+        assert_eq!(frames[1], DELIMITER);
+        assert_eq!(frames[2].len(), 64);
+        assert_eq!(
+            Message::decode(frames.clone(), b"secret"),
+            Ok(message.clone())
+        );
+        assert!(Message::decode(frames.clone(), b"other").is_err());
+        let mut forged = frames;
+        forged[5] = br#"{"code": "1"}"#.to_vec();
+        assert!(Message::decode(forged, b"secret").is_err());
+        assert_eq!(Message::decode(message.encode(b""), b""), Ok(message));
+    }
+
+    /// Test running cells, with state carried between them and errors reported
+    #[test]
+    fn test_execute() {
+        let mut kernel = Kernel::new("k".to_string());
+        let (published, replies) = run(
+            &mut kernel,
+            "execute_request",
+            json!({"code": "r = 2\nf(x) = x * x"}),
+        );
+        assert_eq!(published, ["status", "execute_input", "status"]);
+        assert_eq!(replies[0].content["status"], "ok");
+        assert_eq!(replies[0].identities, [b"notebook"]);
+        assert_eq!(replies[0].parent_header["msg_id"], "1");
+
+        let (out, shutdown) =
+            kernel.handle(&request("execute_request", json!({"code": "f(r) / 8"})));
+        assert!(!shutdown);
+        let result = &out[2].1;
+        assert_eq!(result.msg_type(), "execute_result");
+        assert_eq!(result.identities, [b"kernel.k.execute_result"]);
+        assert_eq!(result.content["execution_count"], 2);
+        let data = &result.content["data"];
+        assert_eq!(data["text/plain"], "0.5");
+        assert_eq!(
+            data["text/latex"],
+            r"$$\frac{\operatorname{f}\left(r\right)}{8} = 0.5$$"
+        );
+        assert!(
+            data["text/html"]
+                .as_str()
+                .unwrap()
+                .contains("<code>Div</code> /")
+        );
+
+        let (published, replies) = run(&mut kernel, "execute_request", json!({"code": "1 / 0"}));
+        assert_eq!(published, ["status", "execute_input", "error", "status"]);
+        assert_eq!(replies[0].content["status"], "error");
+        assert_eq!(replies[0].content["ename"], "EvaluationError");
+    }
+
+    /// Test the requests besides running cells
+    #[test]
+    fn test_requests() {
+        let mut kernel = Kernel::new("k".to_string());
+        let (_, replies) = run(&mut kernel, "kernel_info_request", json!({}));
+        assert_eq!(replies[0].msg_type(), "kernel_info_reply");
+        assert_eq!(replies[0].content["language_info"]["name"], "ast");
+
+        run(
+            &mut kernel,
+            "execute_request",
+            json!({"code": "sqrt_two = sqrt(2)"}),
+        );
+        let (_, replies) = run(
+            &mut kernel,
+            "complete_request",
+            json!({"code": "1 + sq", "cursor_pos": 6}),
+        );
+        assert_eq!(replies[0].content["matches"], json!(["sqrt", "sqrt_two"]));
+        assert_eq!(replies[0].content["cursor_start"], 4);
+
+        let (_, replies) = run(
+            &mut kernel,
+            "is_complete_request",
+            json!({"code": "max(1,"}),
+        );
+        assert_eq!(replies[0].content["status"], "incomplete");
+        let (_, replies) = run(&mut kernel, "unknown_request", json!({}));
+        assert!(replies.is_empty());
+        let (_, shutdown) = kernel.handle(&request("shutdown_request", json!({"restart": false})));
+        assert!(shutdown);
+    }
+}
@@ -0,0 +1,205 @@
+//! A Jupyter kernel for the calculator
+//!
+//! `ast-kernel install` registers the kernel with Jupyter, after which
+//! notebooks can pick "AST" as their kernel. Jupyter then starts
+//! `ast-kernel <connection-file>`, which listens on the ports the file names
+//! and evaluates each cell, showing results with the expression typeset and
+//! its tree.
+
+mod hmac;
+mod kernel;
+mod zmtp;
+
+use kernel::{Kernel, Message, Route};
+use serde_json::{Value as Json, json};
+use std::{
+    env, fs,
+    io::{BufReader, BufWriter},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zmtp::SocketType;
+
+/// How to run the kernel
+const USAGE: &str = "usage: ast-kernel <connection-file>
+       ast-kernel install";
+
+/// Connections to the IOPub socket, which get every published message
+type Subscribers = Arc<Mutex<Vec<TcpStream>>>;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["install"] => install(),
+        ["-h" | "--help"] => {
+            println!("{}", USAGE);
+            return;
+        }
+        [connection] => run(connection),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(message) = result {
+        eprintln!("❌ ast-kernel: {}", message);
+        process::exit(1);
+    }
+}
+
+/// Write the kernel spec where Jupyter looks for kernels
+fn install() -> Result<(), String> {
+    let data = match env::var_os("JUPYTER_DATA_DIR") {
+        Some(data) => PathBuf::from(data),
+        None => {
+            let home = env::var_os("HOME").ok_or("neither JUPYTER_DATA_DIR nor HOME is set")?;
+            PathBuf::from(home).join(".local/share/jupyter")
+        }
+    };
+    let directory = data.join("kernels").join("ast");
+    let program = env::current_exe().map_err(|error| error.to_string())?;
+    let spec = json!({
+        "argv": [program, "{connection_file}"],
+        "display_name": "AST",
+        "language": "ast",
+    });
+    fs::create_dir_all(&directory).map_err(|error| error.to_string())?;
+    let path = directory.join("kernel.json");
+    fs::write(&path, format!("{:#}\n", spec)).map_err(|error| error.to_string())?;
+    println!("Installed the kernel in {}", path.display());
+    Ok(())
+}
+
+/// Listen on the connection file's ports, serving notebooks until told to shut down
+fn run(connection: &str) -> Result<(), String> {
+    let text =
+        fs::read_to_string(connection).map_err(|error| format!("{}: {}", connection, error))?;
+    let config: Json =
+        serde_json::from_str(&text).map_err(|error| format!("{}: {}", connection, error))?;
+    if config["transport"].as_str().unwrap_or("tcp") != "tcp" {
+        return Err("only the tcp transport is supported".to_string());
+    }
+    let scheme = config["signature_scheme"].as_str().unwrap_or("hmac-sha256");
+    if scheme != "hmac-sha256" && !scheme.is_empty() {
+        return Err(format!("unsupported signature scheme {}", scheme));
+    }
+    let ip = config["ip"].as_str().unwrap_or("127.0.0.1");
+    let bind = |name: &str| {
+        let port = config[format!("{}_port", name)]
+            .as_u64()
+            .ok_or(format!("the connection file has no {}_port", name))?;
+        TcpListener::bind((ip, port as u16))
+            .map_err(|error| format!("{} port {}: {}", name, port, error))
+    };
+    let (shell, control, stdin, iopub, hb) = (
+        bind("shell")?,
+        bind("control")?,
+        bind("stdin")?,
+        bind("iopub")?,
+        bind("hb")?,
+    );
+
+    let key: Arc<[u8]> = config["key"].as_str().unwrap_or_default().as_bytes().into();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    let kernel = Arc::new(Mutex::new(Kernel::new(format!(
+        "{:x}-{:x}",
+        process::id(),
+        nanos
+    ))));
+    let subscribers = Subscribers::default();
+
+    let publish_to = Arc::clone(&subscribers);
+    accept(iopub, SocketType::Pub, move |stream| {
+        if let Ok(clone) = stream.try_clone() {
+            lock(&publish_to).push(clone);
+        }
+        // Subscriptions arrive as messages; every subscriber gets everything
+        let mut reader = BufReader::new(stream);
+        while zmtp::read_message(&mut reader).is_ok() {}
+    });
+    accept(hb, SocketType::Rep, |stream| {
+        let mut reader = BufReader::new(&stream);
+        let mut writer = &stream;
+        while let Ok(message) = zmtp::read_message(&mut reader) {
+            if zmtp::write_message(&mut writer, &message).is_err() {
+                break;
+            }
+        }
+    });
+    // The kernel never asks for input, so anything on stdin is read and dropped
+    accept(stdin, SocketType::Router, |stream| {
+        let mut reader = BufReader::new(stream);
+        while zmtp::read_message(&mut reader).is_ok() {}
+    });
+    for listener in [shell, control] {
+        let (kernel, key, subscribers) = (
+            Arc::clone(&kernel),
+            Arc::clone(&key),
+            Arc::clone(&subscribers),
+        );
+        accept(listener, SocketType::Router, move |stream| {
+            requests(&stream, &kernel, &key, &subscribers);
+        });
+    }
+    loop {
+        thread::park();
+    }
+}
+
+/// Serve every connection to `listener` on its own thread, after the handshake
+fn accept(
+    listener: TcpListener,
+    socket: SocketType,
+    serve: impl Fn(TcpStream) + Clone + Send + 'static,
+) {
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let serve = serve.clone();
+            thread::spawn(move || {
+                if zmtp::handshake(&mut stream, socket).is_ok() {
+                    serve(stream);
+                }
+            });
+        }
+    });
+}
+
+/// Answer the requests on a shell or control connection until it closes
+fn requests(stream: &TcpStream, kernel: &Mutex<Kernel>, key: &[u8], subscribers: &Subscribers) {
+    let mut reader = BufReader::new(stream);
+    while let Ok(frames) = zmtp::read_message(&mut reader) {
+        let request = match Message::decode(frames, key) {
+            Ok(request) => request,
+            Err(message) => {
+                eprintln!("⚠️ ast-kernel: dropped a message: {}", message);
+                continue;
+            }
+        };
+        let (out, shutdown) = lock(kernel).handle(&request);
+        for (route, message) in out {
+            let frames = message.encode(key);
+            match route {
+                Route::Reply => {
+                    if zmtp::write_message(&mut BufWriter::new(stream), &frames).is_err() {
+                        return;
+                    }
+                }
+                Route::Publish => lock(subscribers)
+                    .retain(|mut subscriber| zmtp::write_message(&mut subscriber, &frames).is_ok()),
+            }
+        }
+        if shutdown {
+            process::exit(0);
+        }
+    }
+}
+
+/// Lock a mutex, even if a thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,226 @@
+//! Just enough of ZMTP 3.0, ZeroMQ's wire protocol, to talk to Jupyter
+//!
+//! Notebooks connect to a kernel's sockets with ZeroMQ. Each connection
+//! starts with a 64 byte greeting and a `READY` command from each side, using
+//! the NULL security mechanism, then carries messages made of frames. This
+//! implements the kernel's end of that exchange, one peer per connection;
+//! routing between peers, and subscription filtering for `PUB` sockets, is
+//! left to the caller.
+
+use std::io::{self, Read, Write};
+
+/// The largest frame accepted, in bytes
+const MAX_FRAME: u64 = 64 * 1024 * 1024;
+
+/// Frame flags
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+/// The kinds of ZeroMQ socket the kernel has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    /// Takes requests from any number of peers, and replies to each one
+    Router,
+    /// Sends every message to every peer
+    Pub,
+    /// Answers each request
+    Rep,
+}
+
+impl SocketType {
+    /// The name ZMTP uses for it
+    fn name(self) -> &'static str {
+        match self {
+            SocketType::Router => "ROUTER",
+            SocketType::Pub => "PUB",
+            SocketType::Rep => "REP",
+        }
+    }
+}
+
+/// A message, or a command such as `READY`
+enum Frames {
+    Message(Vec<Vec<u8>>),
+    Command(Vec<u8>),
+}
+
+/// Exchange greetings and `READY` commands with a peer that just connected
+pub fn handshake(stream: &mut (impl Read + Write), socket: SocketType) -> io::Result<()> {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+
+    let mut peer = [0u8; 64];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xff || peer[9] & 1 != 1 {
+        return Err(invalid("the peer isn't speaking ZMTP"));
+    }
+    if peer[10] < 3 {
+        return Err(invalid("the peer only speaks ZMTP 2 or older"));
+    }
+    if &peer[12..32] != b"NULL\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0" {
+        return Err(invalid(
+            "the peer wants a security mechanism other than NULL",
+        ));
+    }
+
+    let mut ready = b"\x05READY".to_vec();
+    property(&mut ready, "Socket-Type", socket.name().as_bytes());
+    write_frames(stream, &[ready], COMMAND)?;
+    match read_frames(stream)? {
+        Frames::Command(command) if command.starts_with(b"\x05READY") => Ok(()),
+        _ => Err(invalid("the peer didn't send READY")),
+    }
+}
+
+/// Append a `READY` command's property
+fn property(command: &mut Vec<u8>, name: &str, value: &[u8]) {
+    command.push(name.len() as u8);
+    command.extend(name.as_bytes());
+    command.extend((value.len() as u32).to_be_bytes());
+    command.extend(value);
+}
+
+/// Read the next message, skipping any commands before it
+pub fn read_message(reader: &mut impl Read) -> io::Result<Vec<Vec<u8>>> {
+    loop {
+        if let Frames::Message(frames) = read_frames(reader)? {
+            return Ok(frames);
+        }
+    }
+}
+
+/// Write a message of one or more frames
+pub fn write_message(writer: &mut impl Write, frames: &[impl AsRef<[u8]>]) -> io::Result<()> {
+    write_frames(writer, frames, 0)
+}
+
+/// Read the frames of a message, or a command
+fn read_frames(reader: &mut impl Read) -> io::Result<Frames> {
+    let mut frames = Vec::new();
+    loop {
+        let mut flags = [0u8];
+        reader.read_exact(&mut flags)?;
+        let flags = flags[0];
+        let size = if flags & LONG == 0 {
+            let mut size = [0u8];
+            reader.read_exact(&mut size)?;
+            u64::from(size[0])
+        } else {
+            let mut size = [0u8; 8];
+            reader.read_exact(&mut size)?;
+            u64::from_be_bytes(size)
+        };
+        if size > MAX_FRAME {
+            return Err(invalid("a frame is too large"));
+        }
+        let mut frame = vec![0; size as usize];
+        reader.read_exact(&mut frame)?;
+        if flags & COMMAND != 0 {
+            return Ok(Frames::Command(frame));
+        }
+        frames.push(frame);
+        if flags & MORE == 0 {
+            return Ok(Frames::Message(frames));
+        }
+    }
+}
+
+/// Write frames with `flags`, and `MORE` on all but the last
+fn write_frames(writer: &mut impl Write, frames: &[impl AsRef<[u8]>], flags: u8) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    for (index, frame) in frames.iter().enumerate() {
+        let frame = frame.as_ref();
+        let mut flags = flags;
+        if index + 1 < frames.len() {
+            flags |= MORE;
+        }
+        match u8::try_from(frame.len()) {
+            Ok(size) => bytes.extend([flags, size]),
+            Err(_) => {
+                bytes.push(flags | LONG);
+                bytes.extend((frame.len() as u64).to_be_bytes());
+            }
+        }
+        bytes.extend(frame);
+    }
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+/// An error for a peer that broke the protocol
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Test short and long frames, and that commands between messages are skipped
+    #[test]
+    fn test_frames() {
+        let long = vec![7u8; 300];
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &[&b"<IDS|MSG>"[..], b"", &long]).unwrap();
+        assert_eq!(bytes[..11], *b"\x01\x09<IDS|MSG>");
+        assert_eq!(bytes[11..13], [0x01, 0]);
+        assert_eq!(bytes[13..22], [0x02, 0, 0, 0, 0, 0, 0, 0x01, 0x2c]);
+
+        let mut stream = b"\x04\x04PING".to_vec();
+        stream.extend(&bytes);
+        let message = read_message(&mut &stream[..]).unwrap();
+        assert_eq!(message, [b"<IDS|MSG>".to_vec(), vec![], long]);
+        assert!(read_message(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    /// Test the handshake, with both ends played by this module
+    #[test]
+    fn test_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let kernel = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            handshake(&mut stream, SocketType::Rep).unwrap();
+            let message = read_message(&mut stream).unwrap();
+            write_message(&mut stream, &message).unwrap();
+        });
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        handshake(&mut client, SocketType::Router).unwrap();
+        write_message(&mut client, &[&b""[..], b"ping"]).unwrap();
+        assert_eq!(
+            read_message(&mut client).unwrap(),
+            [vec![], b"ping".to_vec()]
+        );
+        kernel.join().unwrap();
+
+        let mut http = &b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(2)[..];
+        let mut both = ReadWrite(&mut http, Vec::new());
+        assert!(handshake(&mut both, SocketType::Pub).is_err());
+    }
+
+    /// Something to read from and write to, for one side of a handshake
+    struct ReadWrite<'a>(&'a mut &'a [u8], Vec<u8>);
+
+    impl Read for ReadWrite<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for ReadWrite<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
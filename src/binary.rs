@@ -0,0 +1,544 @@
+//! A compact binary form of trees, for caching parsed formulas
+//!
+//! [`Expr::to_bytes`] writes, after a four byte header:
+//!
+//! - the number literals used, each once, as eight little-endian bytes;
+//! - the names used, each once, as a length and UTF-8 text;
+//! - the distinct subtrees, children before parents and the root last, each
+//!   as an opcode followed by pool indices, counts and the indices of its
+//!   children among the subtrees before it.
+//!
+//! All the numbers except literals are LEB128 varints. A subtree that appears
+//! several times, like `a * b` in `(a * b + 1) / (a * b)`, is written once and
+//! referred to by index, and [`Expr::from_bytes`] expands it again.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{CellRef, Comparison, Expr, Span};
+
+/// The first bytes of every encoded tree, the last being the format version
+const HEADER: [u8; 4] = *b"AST\x01";
+
+/// The most nodes [`Expr::from_bytes`] expands shared subtrees into
+///
+/// A few bytes can describe a subtree that appears in itself a billion times
+/// over, so untrusted input is stopped here rather than exhausting memory.
+pub const MAX_DECODED_NODES: u64 = 10_000_000;
+
+/// Opcodes, one for each kind of node
+const FLOAT: u64 = 0;
+const VAR: u64 = 1;
+const CELL_REF: u64 = 2;
+const CELL_RANGE: u64 = 3;
+const ADD: u64 = 4;
+const SUB: u64 = 5;
+const MUL: u64 = 6;
+const DIV: u64 = 7;
+const NEG: u64 = 8;
+const COMPARE: u64 = 9;
+const AND: u64 = 10;
+const OR: u64 = 11;
+const NOT: u64 = 12;
+const CALL: u64 = 13;
+const LET: u64 = 14;
+const PIECEWISE: u64 = 15;
+const LIST: u64 = 16;
+const INDEX: u64 = 17;
+const ERROR: u64 = 18;
+const NAMED: u64 = 19;
+
+/// Errors that can occur while decoding a tree
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum BinaryError {
+    #[error("Not an encoded expression")]
+    NotEncoded,
+
+    #[error("Unsupported encoding version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Encoded expression ends early")]
+    Truncated,
+
+    #[error("Invalid encoded expression at byte {0}")]
+    Invalid(usize),
+
+    #[error("Encoded expression expands to more than {MAX_DECODED_NODES} nodes")]
+    TooLarge,
+}
+
+impl Expr {
+    /// This tree in a compact binary form, for [`Expr::from_bytes`] to read back
+    ///
+    /// Number literals and names are each written once, as is each distinct
+    /// subtree, so repeated parts of a formula cost a few bytes each time
+    /// after the first.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, ast) = parse_expression("sqrt(x * x + y * y) / (x * x + y * y)").unwrap();
+    /// let bytes = ast.to_bytes();
+    /// assert!(bytes.len() < 48);
+    /// assert_eq!(Expr::from_bytes(&bytes), Ok(ast));
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        // Indices of the subtrees finished but not yet part of their parent
+        let mut done = Vec::new();
+        let mut stack = vec![(self, false)];
+        while let Some((expr, children_done)) = stack.pop() {
+            let children = expr.children();
+            if !children_done && !children.is_empty() {
+                stack.push((expr, true));
+                stack.extend(children.into_iter().rev().map(|child| (child, false)));
+                continue;
+            }
+            let children = done.split_off(done.len() - children.len());
+            let record = encoder.record(expr, children);
+            done.push(encoder.intern(record));
+        }
+
+        let mut bytes = HEADER.to_vec();
+        write_varint(&mut bytes, encoder.floats.len() as u64);
+        for float in &encoder.floats {
+            bytes.extend(float.to_le_bytes());
+        }
+        write_varint(&mut bytes, encoder.names.len() as u64);
+        for name in &encoder.names {
+            write_varint(&mut bytes, name.len() as u64);
+            bytes.extend(name.as_bytes());
+        }
+        write_varint(&mut bytes, encoder.records.len() as u64);
+        for record in &encoder.records {
+            record.iter().for_each(|&n| write_varint(&mut bytes, n));
+        }
+        bytes
+    }
+
+    /// Decode a tree written by [`Expr::to_bytes`]
+    ///
+    /// Anything that isn't such a tree gives an error rather than a panic,
+    /// including input that would expand to more than
+    /// [`MAX_DECODED_NODES`] nodes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Expr, BinaryError> {
+        let mut reader = Reader { bytes, position: 0 };
+        match bytes.get(..HEADER.len()) {
+            Some(header) if header[..3] == HEADER[..3] && header[3] != HEADER[3] => {
+                return Err(BinaryError::UnsupportedVersion(header[3]));
+            }
+            Some(header) if header == HEADER => reader.position = HEADER.len(),
+            _ => return Err(BinaryError::NotEncoded),
+        }
+
+        let count = reader.count(8)?;
+        let floats = (0..count)
+            .map(|_| Ok(f64::from_le_bytes(reader.take(8)?.try_into().unwrap())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = reader.count(1)?;
+        let names = (0..count)
+            .map(|_| {
+                let start = reader.position;
+                let length = reader.count(1)?;
+                let text = reader.take(length)?;
+                String::from_utf8(text.to_vec()).map_err(|_| BinaryError::Invalid(start))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let count = reader.count(2)?;
+        let mut records = Vec::with_capacity(count);
+        // How many nodes each subtree expands to
+        let mut sizes: Vec<u64> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let record = reader.record(records.len(), floats.len(), names.len())?;
+            let size = children(&record).iter().fold(1u64, |size, &child| {
+                size.saturating_add(sizes[child as usize])
+            });
+            if size > MAX_DECODED_NODES {
+                return Err(BinaryError::TooLarge);
+            }
+            records.push(record);
+            sizes.push(size);
+        }
+        if records.is_empty() || reader.position < bytes.len() {
+            return Err(BinaryError::Invalid(reader.position));
+        }
+
+        // Expand the subtrees from the root down, without recursing
+        let mut done = Vec::new();
+        let mut stack = vec![(records.len() - 1, false)];
+        while let Some((index, children_done)) = stack.pop() {
+            let record = &records[index];
+            let ids = children(record);
+            if !children_done && !ids.is_empty() {
+                stack.push((index, true));
+                stack.extend(ids.iter().rev().map(|&child| (child as usize, false)));
+                continue;
+            }
+            let children = done.split_off(done.len() - ids.len());
+            done.push(build(record, children, &floats, &names));
+        }
+        Ok(done.pop().unwrap())
+    }
+}
+
+/// The pools and distinct subtrees of a tree being encoded
+#[derive(Default)]
+struct Encoder<'a> {
+    /// Number literals, in the order first seen
+    floats: Vec<f64>,
+    /// Where each literal is in `floats`, by its bits
+    float_indices: HashMap<u64, u64>,
+    /// Names, in the order first seen
+    names: Vec<&'a str>,
+    /// Where each name is in `names`
+    name_indices: HashMap<&'a str, u64>,
+    /// Each distinct subtree, children before parents
+    records: Vec<Vec<u64>>,
+    /// Where each subtree is in `records`
+    record_indices: HashMap<Vec<u64>, u64>,
+}
+
+impl<'a> Encoder<'a> {
+    /// The record for a node whose children are the subtrees at `children`
+    fn record(&mut self, expr: &'a Expr, children: Vec<u64>) -> Vec<u64> {
+        let mut record = match expr {
+            Expr::Float(value) => vec![FLOAT, self.float(*value)],
+            Expr::Var(name) => vec![VAR, self.name(name)],
+            Expr::CellRef(cell) => vec![CELL_REF, cell.column.into(), cell.row.into()],
+            Expr::CellRange(from, to) => vec![
+                CELL_RANGE,
+                from.column.into(),
+                from.row.into(),
+                to.column.into(),
+                to.row.into(),
+            ],
+            Expr::Error(span) => vec![ERROR, span.start as u64, span.end as u64],
+            Expr::Add(..) => vec![ADD],
+            Expr::Sub(..) => vec![SUB],
+            Expr::Mul(..) => vec![MUL],
+            Expr::Div(..) => vec![DIV],
+            Expr::Neg(_) => vec![NEG],
+            Expr::Compare(comparison, ..) => vec![COMPARE, *comparison as u64],
+            Expr::And(..) => vec![AND],
+            Expr::Or(..) => vec![OR],
+            Expr::Not(_) => vec![NOT],
+            Expr::Call(name, args) => vec![CALL, self.name(name), args.len() as u64],
+            Expr::Let(name, ..) => vec![LET, self.name(name)],
+            Expr::Piecewise(cases, default) => {
+                vec![PIECEWISE, cases.len() as u64, default.is_some().into()]
+            }
+            Expr::List(elements) => vec![LIST, elements.len() as u64],
+            Expr::Index(_, indices) => vec![INDEX, indices.len() as u64],
+            Expr::Named(name, _) => vec![NAMED, self.name(name)],
+        };
+        record.extend(children);
+        record
+    }
+
+    /// The index of a subtree, adding it if it's new
+    fn intern(&mut self, record: Vec<u64>) -> u64 {
+        let next = self.records.len() as u64;
+        *self
+            .record_indices
+            .entry(record)
+            .or_insert_with_key(|record| {
+                self.records.push(record.clone());
+                next
+            })
+    }
+
+    /// The pool index of a literal, adding it if it's new
+    fn float(&mut self, value: f64) -> u64 {
+        let next = self.floats.len() as u64;
+        *self
+            .float_indices
+            .entry(value.to_bits())
+            .or_insert_with(|| {
+                self.floats.push(value);
+                next
+            })
+    }
+
+    /// The pool index of a name, adding it if it's new
+    fn name(&mut self, name: &'a str) -> u64 {
+        let next = self.names.len() as u64;
+        *self.name_indices.entry(name).or_insert_with(|| {
+            self.names.push(name);
+            next
+        })
+    }
+}
+
+/// Append `n` as a LEB128 varint
+fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+/// How many of a record's numbers come before its children's indices
+fn fields(opcode: u64) -> usize {
+    match opcode {
+        ADD | SUB | MUL | DIV | NEG | AND | OR | NOT => 0,
+        FLOAT | VAR | COMPARE | LET | LIST | INDEX | NAMED => 1,
+        CELL_REF | ERROR | CALL | PIECEWISE => 2,
+        _ => 4,
+    }
+}
+
+/// The indices of a record's children
+fn children(record: &[u64]) -> &[u64] {
+    &record[1 + fields(record[0])..]
+}
+
+/// The node a checked record stands for, given its children
+fn build(record: &[u64], children: Vec<Expr>, floats: &[f64], names: &[String]) -> Expr {
+    let name = || names[record[1] as usize].clone();
+    let cell = |at: usize| CellRef {
+        column: record[at] as u32,
+        row: record[at + 1] as u32,
+    };
+    let mut children = children.into_iter();
+    let mut child = || Box::new(children.next().unwrap());
+    match record[0] {
+        FLOAT => Expr::Float(floats[record[1] as usize]),
+        VAR => Expr::Var(name()),
+        CELL_REF => Expr::CellRef(cell(1)),
+        CELL_RANGE => Expr::CellRange(cell(1), cell(3)),
+        ERROR => Expr::Error(Span {
+            start: record[1] as usize,
+            end: record[2] as usize,
+        }),
+        ADD => Expr::Add(child(), child()),
+        SUB => Expr::Sub(child(), child()),
+        MUL => Expr::Mul(child(), child()),
+        DIV => Expr::Div(child(), child()),
+        NEG => Expr::Neg(child()),
+        COMPARE => Expr::Compare(COMPARISONS[record[1] as usize], child(), child()),
+        AND => Expr::And(child(), child()),
+        OR => Expr::Or(child(), child()),
+        NOT => Expr::Not(child()),
+        LET => Expr::Let(name(), child(), child()),
+        INDEX => Expr::Index(child(), children.collect()),
+        CALL => Expr::Call(name(), children.collect()),
+        NAMED => Expr::Named(name(), child()),
+        LIST => Expr::List(children.collect()),
+        _ => {
+            let mut cases = Vec::with_capacity(record[1] as usize);
+            for _ in 0..record[1] {
+                cases.push((*child(), *child()));
+            }
+            Expr::Piecewise(cases, children.next().map(Box::new))
+        }
+    }
+}
+
+/// Every comparison, at the index its discriminant gives
+const COMPARISONS: [Comparison; 6] = [
+    Comparison::Equal,
+    Comparison::NotEqual,
+    Comparison::Less,
+    Comparison::LessOrEqual,
+    Comparison::Greater,
+    Comparison::GreaterOrEqual,
+];
+
+/// A position in bytes being decoded
+struct Reader<'a> {
+    /// Everything being decoded
+    bytes: &'a [u8],
+    /// Where the next byte is read from
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// The next `n` bytes
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BinaryError> {
+        let taken = self
+            .bytes
+            .get(self.position..self.position.saturating_add(n))
+            .ok_or(BinaryError::Truncated)?;
+        self.position += n;
+        Ok(taken)
+    }
+
+    /// The next varint
+    fn varint(&mut self) -> Result<u64, BinaryError> {
+        let start = self.position;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return if shift == 63 && byte > 1 {
+                    Err(BinaryError::Invalid(start))
+                } else {
+                    Ok(n)
+                };
+            }
+        }
+        Err(BinaryError::Invalid(start))
+    }
+
+    /// The next varint, as a count of things at least `each` bytes long
+    ///
+    /// Counts too large for the bytes left can't be right, and are caught
+    /// here before anything is allocated for them.
+    fn count(&mut self, each: usize) -> Result<usize, BinaryError> {
+        let start = self.position;
+        let left = (self.bytes.len() - self.position) as u64;
+        match self.varint()? {
+            count if count.saturating_mul(each as u64) <= left => Ok(count as usize),
+            _ => Err(BinaryError::Invalid(start)),
+        }
+    }
+
+    /// The next record, checked to only refer to what comes before it
+    fn record(
+        &mut self,
+        before: usize,
+        floats: usize,
+        names: usize,
+    ) -> Result<Vec<u64>, BinaryError> {
+        let start = self.position;
+        let invalid = BinaryError::Invalid(start);
+        let opcode = self.varint()?;
+        if opcode > NAMED {
+            return Err(invalid);
+        }
+        let mut record = vec![opcode];
+        for _ in 0..fields(opcode) {
+            record.push(self.varint()?);
+        }
+        let field = |at: usize| record[at];
+        let cell =
+            |at: usize| u32::try_from(field(at)).is_ok() && u32::try_from(field(at + 1)).is_ok();
+        let (valid, children) = match opcode {
+            FLOAT => (field(1) < floats as u64, 0),
+            VAR => (field(1) < names as u64, 0),
+            CELL_REF => (cell(1), 0),
+            CELL_RANGE => (cell(1) && cell(3), 0),
+            ERROR => (
+                usize::try_from(field(1)).is_ok() && usize::try_from(field(2)).is_ok(),
+                0,
+            ),
+            NEG | NOT => (true, 1),
+            COMPARE => (field(1) < COMPARISONS.len() as u64, 2),
+            LET => (field(1) < names as u64, 2),
+            NAMED => (field(1) < names as u64, 1),
+            CALL => (field(1) < names as u64, field(2)),
+            PIECEWISE => (
+                field(2) <= 1,
+                field(1).saturating_mul(2).saturating_add(field(2)),
+            ),
+            LIST => (true, field(1)),
+            INDEX => (true, field(1).saturating_add(1)),
+            _ => (true, 2),
+        };
+        if !valid || children > (self.bytes.len() - self.position) as u64 {
+            return Err(invalid);
+        }
+        for _ in 0..children {
+            match self.varint()? {
+                child if child < before as u64 => record.push(child),
+                _ => return Err(invalid),
+            }
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Parse an expression that must be valid
+    fn parse(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test that trees of every kind come back as they were, sharing or not
+    #[test]
+    fn test_round_trip() {
+        let mut trees: Vec<Expr> = [
+            "1",
+            "x + y - 2 * z / -w",
+            "(a * b + 1) / (a * b) + (a * b + 1)",
+            "x < 1 && !(y >= 2) || z != 3",
+            "let t = max(a, b, 0.5) in t == t",
+            "piecewise((x < 0, -x), (x > 9, 9), x) + piecewise((x, 1))",
+            "[[1, 2], [3, 4]][2, 1] + [][1] + f()",
+            "0.1 + -0.0 + 0.0 + 1e300",
+            "pmt(0.05, nper=360, pv=x * 2)",
+        ]
+        .iter()
+        .map(|input| parse(input))
+        .collect();
+        trees.push(Expr::CellRange(
+            CellRef::new(1, 2),
+            CellRef::new(u32::MAX, 3),
+        ));
+        trees.push(Expr::Add(
+            Box::new(Expr::CellRef(CellRef::new(27, 5))),
+            Box::new(Expr::Error(Span { start: 3, end: 9 })),
+        ));
+        for tree in trees {
+            assert_eq!(
+                Expr::from_bytes(&tree.to_bytes()),
+                Ok(tree.clone()),
+                "{:?}",
+                tree
+            );
+        }
+
+        let repeated = parse(&vec!["(x * y + 1)"; 1000].join(" * "));
+        assert!(repeated.to_bytes().len() < 6_000);
+        assert_eq!(Expr::from_bytes(&repeated.to_bytes()), Ok(repeated));
+
+        let chain = parse(&vec!["x"; 100_000].join(" - "));
+        let decoded = Expr::from_bytes(&chain.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), chain.to_bytes());
+    }
+
+    /// Test that broken and hostile input is rejected
+    #[test]
+    fn test_invalid() {
+        let bytes = parse("f(x, 2) + [x][1]").to_bytes();
+        for end in 0..bytes.len() {
+            assert!(Expr::from_bytes(&bytes[..end]).is_err(), "{}", end);
+        }
+        assert_eq!(
+            Expr::from_bytes(b"{\"x\": 1}"),
+            Err(BinaryError::NotEncoded)
+        );
+        assert_eq!(
+            Expr::from_bytes(b"AST\x07"),
+            Err(BinaryError::UnsupportedVersion(7))
+        );
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(
+            Expr::from_bytes(&extra),
+            Err(BinaryError::Invalid(bytes.len()))
+        );
+
+        // A child referring to itself
+        let cycle = b"AST\x01\x00\x00\x01\x08\x00";
+        assert_eq!(Expr::from_bytes(cycle), Err(BinaryError::Invalid(7)));
+
+        // Each subtree the sum of the one before it with itself
+        let mut bomb = b"AST\x01\x01".to_vec();
+        bomb.extend(1.0f64.to_le_bytes());
+        bomb.extend([0, 64, FLOAT as u8, 0]);
+        for child in 0..63 {
+            bomb.extend([ADD as u8, child, child]);
+        }
+        assert_eq!(Expr::from_bytes(&bomb), Err(BinaryError::TooLarge));
+    }
+}
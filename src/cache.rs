@@ -0,0 +1,296 @@
+//! Compiling each distinct formula once
+//!
+//! A server evaluating formulas typed by its users sees the same ones over
+//! and over, often differing only in spacing or parentheses. A
+//! [`FormulaCache`] keeps the [lowered](crate::lower) code for each, keyed
+//! by a hash of the parsed tree's [binary form](crate::Expr::to_bytes), so those
+//! variants share one entry. The formula text is remembered too, so exact
+//! repeats aren't even parsed again.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use crate::{
+    Environment, Instruction, ParseEvalError, Parser, ParserConfig, execute, lower,
+    streaming::whole,
+};
+
+/// The most formula texts remembered for one entry
+///
+/// Keeps a flood of variants of one formula (`x+1`, `x +1`, `x  + 1`, …)
+/// from growing the cache without limit; the rest are parsed every time.
+const TEXTS_PER_ENTRY: usize = 8;
+
+/// Counts of what a [`FormulaCache`] has done, for monitoring
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Formulas whose code was already in the cache
+    pub hits: u64,
+    /// Formulas that had to be compiled
+    pub misses: u64,
+    /// Formula texts that had to be parsed, including ones that didn't parse
+    pub parses: u64,
+    /// Entries dropped to make room for new ones
+    pub evictions: u64,
+}
+
+/// Compiled code for the most recently used formulas
+///
+/// Holds up to a fixed number of entries, dropping the least recently used
+/// when a new formula needs room. Formulas that don't parse aren't cached.
+///
+/// # Example
+/// ```
+/// use ast::{Environment, FormulaCache};
+///
+/// let mut cache = FormulaCache::new(1000);
+/// let env: Environment = [("price", 20.0)].into_iter().collect();
+/// assert_eq!(cache.evaluate("price * 1.5", &env).unwrap(), 30.0);
+/// assert_eq!(cache.evaluate("(price*1.5)", &env).unwrap(), 30.0);
+/// assert_eq!(cache.len(), 1);
+/// assert_eq!(cache.stats().hits, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FormulaCache {
+    /// The most entries kept
+    capacity: usize,
+    /// Parses formulas, reusing the names and lists of earlier ones
+    parser: Parser,
+    /// The entries, by the hash of their tree
+    entries: HashMap<u64, Entry>,
+    /// The entry for each remembered formula text
+    texts: HashMap<String, u64>,
+    /// The entries by when they were last used, oldest first
+    recency: BTreeMap<u64, u64>,
+    /// Counts up with every use, to order the entries
+    clock: u64,
+    /// What the cache has done
+    stats: CacheStats,
+}
+
+/// A cached formula
+#[derive(Debug, Clone)]
+struct Entry {
+    /// The tree's binary form, to tell formulas whose hashes collide apart
+    tree: Vec<u8>,
+    /// The lowered code
+    code: Arc<[Instruction]>,
+    /// When the entry was last used, its key in the recency order
+    last_used: u64,
+    /// The formula texts remembered for this entry
+    texts: Vec<String>,
+}
+
+impl FormulaCache {
+    /// A cache for up to `capacity` distinct formulas, using the default syntax
+    pub fn new(capacity: usize) -> Self {
+        FormulaCache::with_config(capacity, ParserConfig::default())
+    }
+
+    /// A cache for up to `capacity` distinct formulas in the syntax `config` describes
+    pub fn with_config(capacity: usize, config: ParserConfig) -> Self {
+        FormulaCache {
+            capacity,
+            parser: Parser::new(config),
+            entries: HashMap::new(),
+            texts: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// The code for a formula, compiling it only if it isn't cached
+    ///
+    /// The code is shared with the cache, so it's cheap to keep and can be
+    /// run on another thread with [`execute`].
+    pub fn compile(&mut self, formula: &str) -> Result<Arc<[Instruction]>, ParseEvalError> {
+        if let Some(&key) = self.texts.get(formula) {
+            self.stats.hits += 1;
+            return Ok(self.touch(key));
+        }
+
+        self.stats.parses += 1;
+        let parsed = self.parser.parse(formula);
+        let ast = whole(formula, parsed)?;
+        let tree = ast.to_bytes();
+        let key = hash(&tree);
+        let code = match self.entries.get(&key) {
+            Some(entry) if entry.tree == tree => {
+                self.stats.hits += 1;
+                self.touch(key)
+            }
+            _ => {
+                self.stats.misses += 1;
+                let code = Arc::from(lower(&ast));
+                self.insert(key, tree, Arc::clone(&code));
+                code
+            }
+        };
+        self.parser.recycle(ast);
+
+        if let Some(entry) = self.entries.get_mut(&key)
+            && entry.texts.len() < TEXTS_PER_ENTRY
+        {
+            entry.texts.push(formula.to_string());
+            self.texts.insert(formula.to_string(), key);
+        }
+        Ok(code)
+    }
+
+    /// Compile a formula, if it isn't cached, and run it with `env`
+    pub fn evaluate(&mut self, formula: &str, env: &Environment) -> Result<f64, ParseEvalError> {
+        Ok(execute(&self.compile(formula)?, env)?)
+    }
+
+    /// What the cache has done so far
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// How many distinct formulas are cached
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no formulas are cached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The most distinct formulas the cache holds
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Drop every cached formula, keeping the stats
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.texts.clear();
+        self.recency.clear();
+    }
+
+    /// Mark an entry as just used, giving its code
+    fn touch(&mut self, key: u64) -> Arc<[Instruction]> {
+        let entry = self.entries.get_mut(&key).unwrap();
+        self.recency.remove(&entry.last_used);
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.recency.insert(self.clock, key);
+        Arc::clone(&entry.code)
+    }
+
+    /// Add an entry, making room for it first
+    fn insert(&mut self, key: u64, tree: Vec<u8>, code: Arc<[Instruction]>) {
+        if self.capacity == 0 {
+            return;
+        }
+        // A different formula with the same hash is replaced
+        self.remove(key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.remove(oldest);
+            self.stats.evictions += 1;
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, key);
+        let entry = Entry {
+            tree,
+            code,
+            last_used: self.clock,
+            texts: Vec::new(),
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Drop an entry and the texts remembered for it, if it's there
+    fn remove(&mut self, key: u64) {
+        if let Some(entry) = self.entries.remove(&key) {
+            self.recency.remove(&entry.last_used);
+            for text in &entry.texts {
+                self.texts.remove(text);
+            }
+        }
+    }
+}
+
+/// The key for a tree's binary form
+fn hash(tree: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tree.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Default for FormulaCache {
+    /// A cache for up to 1024 formulas
+    fn default() -> Self {
+        FormulaCache::new(1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EvaluationError;
+
+    /// Test that variants of one formula share an entry, and what's counted
+    #[test]
+    fn test_hits() {
+        let mut cache = FormulaCache::new(10);
+        let env: Environment = [("x", 3.0)].into_iter().collect();
+        for formula in ["x * x + 1", "x * x + 1", "(x*x) + 1", " x*x+1 "] {
+            assert_eq!(cache.evaluate(formula, &env).unwrap(), 10.0);
+        }
+        let expected = CacheStats {
+            hits: 3,
+            misses: 1,
+            parses: 3,
+            evictions: 0,
+        };
+        assert_eq!(cache.stats(), expected);
+        assert_eq!(cache.len(), 1);
+        assert!(Arc::ptr_eq(
+            &cache.compile("x * x + 1").unwrap(),
+            &cache.compile("(x*x) + 1").unwrap()
+        ));
+
+        assert!(matches!(
+            cache.evaluate("x +", &env),
+            Err(ParseEvalError::Syntax { position: 3 })
+        ));
+        assert!(matches!(
+            cache.evaluate("x / 0", &env),
+            Err(ParseEvalError::Evaluation(EvaluationError::DivisionByZero))
+        ));
+        assert_eq!(cache.len(), 2);
+    }
+
+    /// Test that the least recently used formula is dropped, with its texts
+    #[test]
+    fn test_eviction() {
+        let mut cache = FormulaCache::new(2);
+        cache.compile("1 + 1").unwrap();
+        cache.compile("2 + 2").unwrap();
+        cache.compile("1+1").unwrap();
+        cache.compile("3 + 3").unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(cache.texts.contains_key("1+1"));
+        assert!(!cache.texts.contains_key("2 + 2"));
+
+        let misses = cache.stats().misses;
+        cache.compile("2 + 2").unwrap();
+        assert_eq!(cache.stats().misses, misses + 1);
+        assert!(!cache.texts.contains_key("1 + 1"));
+        assert_eq!(cache.recency.len(), 2);
+
+        let mut none = FormulaCache::new(0);
+        assert_eq!(none.evaluate("2 * 3", &Environment::new()).unwrap(), 6.0);
+        assert!(none.is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+//! Stopping evaluations from another thread
+//!
+//! An interactive program evaluating formulas in the background wants to
+//! stop as soon as the user edits them. It hands a [`CancellationToken`] to
+//! the [evaluator](crate::Evaluator::with_cancellation) or the
+//! [virtual machine](crate::execute_cancellable), keeps a clone, and trips
+//! it when the results are no longer wanted. Evaluation checks the token as
+//! it goes and fails with [`EvaluationError::Cancelled`](crate::EvaluationError::Cancelled).
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// A flag, shared between its clones, that asks evaluations to stop
+///
+/// Once cancelled, a token stays cancelled; use a new token for the next
+/// piece of work.
+///
+/// # Example
+/// ```
+/// use std::thread;
+/// use ast::{parse_expression, CancellationToken, Environment, EvaluationError, Evaluator};
+///
+/// let token = CancellationToken::new();
+/// let env = Environment::new();
+/// let evaluator = Evaluator::new(&env).with_cancellation(&token);
+/// let (_, ast) = parse_expression("1 + 2").unwrap();
+/// assert_eq!(evaluator.evaluate(&ast).unwrap(), 3.0);
+///
+/// let remote = token.clone();
+/// thread::spawn(move || remote.cancel()).join().unwrap();
+/// assert!(token.is_cancelled());
+/// assert!(matches!(evaluator.evaluate(&ast), Err(EvaluationError::Cancelled)));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// A token that hasn't been cancelled
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Ask every evaluation using this token, or a clone of it, to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`CancellationToken::cancel`] has been called on this token or a clone of it
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, EvaluationError, Evaluator, FunctionRegistry, parse_expression};
+
+    /// Test that tripping a token mid-evaluation stops what's left of it
+    #[test]
+    fn test_cancel_midway() {
+        let token = CancellationToken::new();
+        let mut functions = FunctionRegistry::standard();
+        let remote = token.clone();
+        functions.register("stop", 0, move |_| {
+            remote.cancel();
+            Ok(1.0)
+        });
+        let env = Environment::new();
+        let evaluator = Evaluator::new(&env)
+            .with_functions(&functions)
+            .with_cancellation(&token);
+
+        let (_, ast) = parse_expression("stop() + 1").unwrap();
+        assert!(matches!(
+            evaluator.evaluate(&ast),
+            Err(EvaluationError::Cancelled)
+        ));
+        assert_eq!(EvaluationError::Cancelled.code(), "E0020");
+
+        // Evaluators without the token aren't affected
+        let evaluator = Evaluator::new(&env).with_functions(&functions);
+        assert_eq!(evaluator.evaluate(&ast).unwrap(), 2.0);
+    }
+}
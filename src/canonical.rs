@@ -0,0 +1,292 @@
+//! Writing formulas in one canonical style
+//!
+//! Formula files read by several people stay consistent when a tool decides
+//! the layout. [`format`] writes any tree the same way, whatever spacing and
+//! parentheses it was typed with:
+//!
+//! - binary operators have one space on each side: `a + b * c`;
+//! - `-` and `!` are written straight before their operand: `-x`, `!done`,
+//!   with a second minus in parentheses: `-(-x)`;
+//! - parentheses are only written where the grouping needs them, as in
+//!   `(a + b) * c` and `a - (b - c)`, and around a `let` that's an operand;
+//! - arguments, elements and indices are separated by a comma and a space:
+//!   `max(a, b)`, `[1, 2]`, `m[2, 1]`, and named arguments have no spaces
+//!   around their `=`: `round(x, digits=2)`;
+//! - a chain of three or more calls, each the first argument of the next, is
+//!   written as a pipeline where it stands alone, such as at the top or as
+//!   an argument: `x |> abs |> round(2) |> sqrt`;
+//! - numbers are written the shortest way that reads back exactly, as
+//!   [`NumberFormat::default`] writes them: `0.5`, `1e300`, `NaN`.
+//!
+//! The text parses back to the same tree, so formatting twice changes nothing.
+//! [`SyntaxTree`](crate::SyntaxTree) does the opposite, keeping a formula as it
+//! was written.
+
+use crate::{
+    Expr, NumberFormat,
+    explain::{binary, precedence},
+};
+
+/// An expression written in the canonical style
+///
+/// Placeholders for syntax errors, [`Expr::Error`], are written as `?`.
+///
+/// # Example
+/// ```
+/// use ast::{format, parse_expression};
+///
+/// let (_, ast) = parse_expression("((a+b))*-(c)/2-(x-(y-1))").unwrap();
+/// assert_eq!(format(&ast), "(a + b) * -c / 2 - (x - (y - 1))");
+///
+/// let (_, ast) = parse_expression("let  t=[1,2]   in max( t[1] ,0.50 )").unwrap();
+/// assert_eq!(format(&ast), "let t = [1, 2] in max(t[1], 0.5)");
+/// ```
+pub fn format(expr: &Expr) -> String {
+    let mut out = String::new();
+    operand(expr, Position::Delimited, &mut out);
+    out
+}
+
+/// How many calls a chain needs to be written as a pipeline
+const PIPELINE: usize = 3;
+
+/// One call in a pipeline: the function's name and its arguments after the first
+type Stage<'e> = (&'e str, &'e [Expr]);
+
+/// The value a chain of calls starts from, and the name and other arguments
+/// of each call from the innermost out, if it's long enough for a pipeline
+fn stages(expr: &Expr) -> Option<(&Expr, Vec<Stage<'_>>)> {
+    let mut stages = Vec::new();
+    let mut value = expr;
+    while let Expr::Call(name, args) = value
+        && let Some((first, rest)) = args.split_first()
+        && !matches!(first, Expr::Named(..))
+    {
+        stages.push((name.as_str(), rest));
+        value = first;
+    }
+    stages.reverse();
+    (stages.len() >= PIPELINE).then_some((value, stages))
+}
+
+/// Where an operand is, which decides whether it needs parentheses
+#[derive(Clone, Copy)]
+enum Position {
+    /// Inside delimiters already: at the top, or an argument, element, index or `let` body
+    Delimited,
+    /// The left operand of an operator of this precedence
+    Left(u8),
+    /// The right operand of an operator of this precedence
+    Right(u8),
+    /// The operand of `-` or `!`
+    Unary,
+    /// What an index applies to
+    Indexed,
+}
+
+/// Write `expr` at `position`, in parentheses if it needs them there
+fn operand(expr: &Expr, position: Position, out: &mut String) {
+    if let (Position::Delimited, Some((value, stages))) = (position, stages(expr)) {
+        // A pipeline binds more loosely than any operator, but not `let`
+        operand(value, Position::Left(0), out);
+        for (name, args) in stages {
+            out.push_str(" |> ");
+            out.push_str(name);
+            if !args.is_empty() {
+                out.push('(');
+                list(args, out);
+                out.push(')');
+            }
+        }
+        return;
+    }
+    let needs = match (expr, position) {
+        (_, Position::Delimited) => false,
+        (Expr::Let(..), _) => true,
+        (_, Position::Unary) => binary(expr).is_some(),
+        (Expr::Float(value), Position::Indexed) => value.is_sign_negative() && !value.is_nan(),
+        (_, Position::Indexed) => {
+            binary(expr).is_some() || matches!(expr, Expr::Neg(_) | Expr::Not(_))
+        }
+        _ => match (binary(expr), position) {
+            (Some((op, ..)), Position::Left(outer)) => precedence(op) < outer,
+            (Some((op, ..)), Position::Right(outer)) => precedence(op) <= outer,
+            _ => false,
+        },
+    };
+    if needs {
+        out.push('(');
+        write(expr, out);
+        out.push(')');
+    } else {
+        write(expr, out);
+    }
+}
+
+/// Write `items` separated by commas, each within delimiters
+fn list<'a>(items: impl IntoIterator<Item = &'a Expr>, out: &mut String) {
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        operand(item, Position::Delimited, out);
+    }
+}
+
+/// Write `expr` without parentheses around it
+fn write(expr: &Expr, out: &mut String) {
+    match expr {
+        Expr::Float(value) => out.push_str(&NumberFormat::default().format(*value)),
+        Expr::Var(name) => out.push_str(name),
+        Expr::CellRef(cell) => out.push_str(&cell.to_string()),
+        Expr::CellRange(from, to) => out.push_str(&format!("{}:{}", from, to)),
+        Expr::Error(_) => out.push('?'),
+        Expr::Neg(inner) => {
+            out.push('-');
+            // `--x` would read as a decrement in other languages
+            match **inner {
+                Expr::Neg(_) => operand(inner, Position::Indexed, out),
+                Expr::Float(value) if value < 0.0 => operand(inner, Position::Indexed, out),
+                _ => operand(inner, Position::Unary, out),
+            }
+        }
+        Expr::Not(inner) => {
+            out.push('!');
+            operand(inner, Position::Unary, out);
+        }
+        Expr::Call(name, args) => {
+            out.push_str(name);
+            out.push('(');
+            list(args, out);
+            out.push(')');
+        }
+        Expr::Named(name, value) => {
+            out.push_str(name);
+            out.push('=');
+            operand(value, Position::Delimited, out);
+        }
+        Expr::List(elements) => {
+            out.push('[');
+            list(elements, out);
+            out.push(']');
+        }
+        Expr::Index(target, indices) => {
+            operand(target, Position::Indexed, out);
+            out.push('[');
+            list(indices, out);
+            out.push(']');
+        }
+        Expr::Let(name, value, body) => {
+            out.push_str("let ");
+            out.push_str(name);
+            out.push_str(" = ");
+            operand(value, Position::Left(0), out);
+            out.push_str(" in ");
+            operand(body, Position::Delimited, out);
+        }
+        Expr::Piecewise(cases, default) => {
+            out.push_str("piecewise(");
+            for (index, (condition, value)) in cases.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                out.push('(');
+                list([condition, value], out);
+                out.push(')');
+            }
+            if let Some(default) = default {
+                out.push_str(", ");
+                operand(default, Position::Delimited, out);
+            }
+            out.push(')');
+        }
+        _ => {
+            // Left operands that need no parentheses are followed in a loop,
+            // so that a long chain like `1 + 1 + … + 1` doesn't recurse
+            let mut rights = Vec::new();
+            let mut left = expr;
+            while let Some((op, inner, right)) = binary(left) {
+                let level = precedence(op);
+                rights.push((op, level, right));
+                left = inner;
+                if !matches!(binary(inner), Some((inner, ..)) if precedence(inner) >= level) {
+                    break;
+                }
+            }
+            let (_, level, _) = rights.last().expect("every other node is binary");
+            operand(left, Position::Left(*level), out);
+            for (op, level, right) in rights.into_iter().rev() {
+                out.push(' ');
+                out.push_str(op);
+                out.push(' ');
+                operand(right, Position::Right(level), out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Corpus, parse_expression};
+
+    /// Test the style rules on hand-written formulas
+    #[test]
+    fn test_style() {
+        for (input, formatted) in [
+            ("1+2*3", "1 + 2 * 3"),
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("a-(b+c)", "a - (b + c)"),
+            ("(a-b)+c", "a - b + c"),
+            ("a/(b*c)", "a / (b * c)"),
+            ("a<b==(c<d)", "a < b == (c < d)"),
+            ("!(a&&b)||c", "!(a && b) || c"),
+            ("-(-x)", "-(-x)"),
+            ("-(x*2)", "-(x * 2)"),
+            ("(m)[1][2,3]", "m[1][2, 3]"),
+            ("(-m)[1]", "(-m)[1]"),
+            ("1+(let t=2 in t)", "1 + (let t = 2 in t)"),
+            (
+                "piecewise((x<0,-x),(x<1,x*x),1)",
+                "piecewise((x < 0, -x), (x < 1, x * x), 1)",
+            ),
+            ("f( )", "f()"),
+            ("sqrt(abs(x))", "sqrt(abs(x))"),
+            ("round( x , digits = 1+1 )", "round(x, digits=1 + 1)"),
+            (
+                "x+1 |> abs |> round(2)|>max(y |> f)",
+                "x + 1 |> abs |> round(2) |> max(f(y))",
+            ),
+            ("1 + exp(sqrt(abs(x)))", "1 + exp(sqrt(abs(x)))"),
+            (
+                "[(let t = 2 in t) |> a |> b |> c]",
+                "[(let t = 2 in t) |> a |> b |> c]",
+            ),
+            ("1.50e3 + .5", "1500 + 0.5"),
+            ("INF + nan", "inf + NaN"),
+        ] {
+            let (_, ast) = parse_expression(input).unwrap();
+            assert_eq!(format(&ast), formatted, "'{}'", input);
+        }
+
+        let negative = Expr::Index(Box::new(Expr::Float(-2.0)), vec![Expr::Float(1.0)]);
+        assert_eq!(format(&negative), "(-2)[1]");
+
+        let chain = vec!["1"; 100_000].join(" - ");
+        let (_, ast) = parse_expression(&chain).unwrap();
+        assert_eq!(format(&ast), chain);
+    }
+
+    /// Test that formatted text parses back to the same tree
+    #[test]
+    fn test_round_trip() {
+        for text in Corpus::new(7, 6).take(500) {
+            let (_, ast) = parse_expression(&text).unwrap();
+            let formatted = format(&ast);
+            let (rest, again) = parse_expression(&formatted).unwrap();
+            assert_eq!(rest, "", "'{}'", formatted);
+            assert_eq!(again, ast, "'{}' from '{}'", formatted, text);
+            assert_eq!(format(&again), formatted);
+        }
+    }
+}
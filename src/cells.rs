@@ -0,0 +1,216 @@
+//! Spreadsheet cell references
+//!
+//! With [`ParserConfig::cell_references`](crate::ParserConfig::cell_references)
+//! switched on, names like `A1` parse into [`Expr::CellRef`](crate::Expr::CellRef)
+//! and ranges like `B2:B10` into [`Expr::CellRange`](crate::Expr::CellRange).
+//! The calculator doesn't know what's in the cells; during evaluation it asks a
+//! [`CellResolver`] provided by the embedding spreadsheet.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use nom::{
+    IResult, Parser,
+    character::complete::{alpha1, char, digit1, satisfy},
+    combinator::{not, opt},
+    sequence::preceded,
+};
+
+use crate::Expr;
+
+/// The most letters a column name can have (`XFD` is the last Excel column)
+pub(crate) const MAX_COLUMN_LETTERS: usize = 3;
+
+/// A reference to a single cell, such as `B3`
+///
+/// Columns and rows are both numbered from 1, so `A1` is column 1, row 1.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct CellRef {
+    /// Column number, where `A` is 1, `Z` is 26 and `AA` is 27
+    pub column: u32,
+    /// Row number, starting at 1
+    pub row: u32,
+}
+
+impl CellRef {
+    /// Create a reference to the cell at the given column and row
+    pub fn new(column: u32, row: u32) -> Self {
+        CellRef { column, row }
+    }
+
+    /// Every cell in the rectangle between two corners, row by row
+    pub fn range(from: CellRef, to: CellRef) -> impl Iterator<Item = CellRef> {
+        let columns = from.column.min(to.column)..=from.column.max(to.column);
+        (from.row.min(to.row)..=from.row.max(to.row))
+            .flat_map(move |row| columns.clone().map(move |column| CellRef { column, row }))
+    }
+}
+
+impl fmt::Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Columns use "bijective base 26": A..Z, then AA..AZ, BA.. and so on
+        let mut letters = Vec::new();
+        let mut column = self.column;
+        while column > 0 {
+            column -= 1;
+            letters.push(char::from(b'A' + (column % 26) as u8));
+            column /= 26;
+        }
+        letters.reverse();
+        write!(f, "{}{}", letters.into_iter().collect::<String>(), self.row)
+    }
+}
+
+impl FromStr for CellRef {
+    type Err = String;
+
+    /// Parse a cell name such as `B3` (letters are case-insensitive)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_cell(s) {
+            Ok(("", cell)) => Ok(cell),
+            _ => Err(format!("'{}' is not a cell reference", s)),
+        }
+    }
+}
+
+/// Supplies the values of cells during evaluation
+///
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+/// use ast::{parse_expression_with, CellRef, Environment, Evaluator, ParserConfig};
+///
+/// let mut sheet = HashMap::new();
+/// sheet.insert(CellRef::new(1, 1), 10.0); // A1
+/// sheet.insert(CellRef::new(2, 1), 4.0); // B1
+///
+/// let (_, ast) = parse_expression_with("A1 - B1", &ParserConfig::spreadsheet()).unwrap();
+/// let env = Environment::new();
+/// let value = Evaluator::new(&env).with_cells(&sheet).evaluate(&ast).unwrap();
+/// assert_eq!(value, 6.0);
+/// ```
+pub trait CellResolver {
+    /// The value of a cell, or `None` if it's empty or doesn't hold a number
+    fn cell(&self, cell: CellRef) -> Option<f64>;
+
+    /// The values of all the non-empty cells in a range, row by row
+    fn range(&self, from: CellRef, to: CellRef) -> Vec<f64> {
+        CellRef::range(from, to)
+            .filter_map(|cell| self.cell(cell))
+            .collect()
+    }
+}
+
+impl CellResolver for HashMap<CellRef, f64> {
+    fn cell(&self, cell: CellRef) -> Option<f64> {
+        self.get(&cell).copied()
+    }
+}
+
+/// Parse a single cell name, such as `A1` or `xfd1048576`
+fn parse_cell(input: &str) -> IResult<&str, CellRef> {
+    let (rest, (letters, digits)) = (alpha1, digit1).parse(input)?;
+    // Don't split a longer name such as `A1B`; that's a variable. A name
+    // followed by "(" is a function call, such as `LOG10(x)`
+    let (rest, _) = not(satisfy(|c| {
+        c.is_ascii_alphanumeric() || c == '_' || c == '('
+    }))
+    .parse(rest)?;
+
+    let row = digits.parse::<u32>().unwrap_or(0);
+    if letters.len() > MAX_COLUMN_LETTERS || !letters.is_ascii() || row == 0 {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    Ok((rest, CellRef::new(column_number(letters), row)))
+}
+
+/// The number of the column named by ASCII `letters`, where `A` is 1 and `AA` is 27
+pub(crate) fn column_number(letters: &str) -> u32 {
+    letters.bytes().fold(0, |column, letter| {
+        column * 26 + u32::from(letter.to_ascii_uppercase() - b'A' + 1)
+    })
+}
+
+/// Parse a cell reference or a range of cells into an Expr
+///
+/// Only used when cell references are enabled in the parser configuration.
+pub(crate) fn parse_cell_reference<S>(input: &str) -> IResult<&str, Expr<S>> {
+    let (rest, from) = parse_cell(input)?;
+    let (rest, to) = opt(preceded(char(':'), parse_cell)).parse(rest)?;
+
+    match to {
+        Some(to) => Ok((rest, Expr::CellRange(from, to))),
+        None => Ok((rest, Expr::CellRef(from))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test converting between cell names and column/row numbers
+    #[test]
+    fn test_cell_names() {
+        let cases = [
+            ("A1", CellRef::new(1, 1)),
+            ("z9", CellRef::new(26, 9)),
+            ("AA10", CellRef::new(27, 10)),
+            ("XFD1048576", CellRef::new(16384, 1048576)),
+        ];
+        for (name, cell) in cases {
+            assert_eq!(name.parse::<CellRef>(), Ok(cell));
+            assert_eq!(cell.to_string(), name.to_ascii_uppercase());
+        }
+
+        for invalid in ["A0", "ABCD1", "A", "1A", "A1B", "A1_"] {
+            assert!(invalid.parse::<CellRef>().is_err(), "'{}'", invalid);
+        }
+    }
+
+    /// Test that cell values come from the resolver
+    #[test]
+    fn test_evaluate_cells() {
+        use crate::{Environment, EvaluationError, Evaluator, ParserConfig, parse_expression_with};
+
+        let sheet: HashMap<CellRef, f64> = [(CellRef::new(1, 1), 2.0), (CellRef::new(1, 2), 3.0)]
+            .into_iter()
+            .collect();
+        let env: Environment = [("rate", 10.0)].into_iter().collect();
+        let evaluator = Evaluator::new(&env).with_cells(&sheet);
+        let config = ParserConfig::spreadsheet();
+
+        let (_, ast) = parse_expression_with("(a1 + A2) * rate", &config).unwrap();
+        assert_eq!(evaluator.evaluate(&ast).unwrap(), 50.0);
+
+        let (_, ast) = parse_expression_with("A3", &config).unwrap();
+        assert!(matches!(
+            evaluator.evaluate(&ast),
+            Err(EvaluationError::UnresolvedCell(cell)) if cell == CellRef::new(1, 3)
+        ));
+
+        let (_, ast) = parse_expression_with("A1:A2 + 1", &config).unwrap();
+        assert!(matches!(
+            evaluator.evaluate(&ast),
+            Err(EvaluationError::RangeNotAllowed(..))
+        ));
+    }
+
+    /// Test parsing ranges and iterating over their cells
+    #[test]
+    fn test_ranges() {
+        let (rest, expr) = parse_cell_reference::<String>("B2:A3 + 1").unwrap();
+        assert_eq!(rest, " + 1");
+        assert_eq!(
+            expr,
+            Expr::CellRange(CellRef::new(2, 2), CellRef::new(1, 3))
+        );
+
+        let cells: Vec<String> = CellRef::range(CellRef::new(2, 2), CellRef::new(1, 3))
+            .map(|cell| cell.to_string())
+            .collect();
+        assert_eq!(cells, vec!["A2", "B2", "A3", "B3"]);
+    }
+}
@@ -0,0 +1,791 @@
+//! Generating source code from expressions
+//!
+//! A formula that's finished changing doesn't need to be parsed at runtime.
+//! [`Expr::to_rust_fn`] turns it into a standalone Rust function, which a
+//! `build.rs` script can write out and the crate can `include!`.
+//!
+//! [`Expr::to_glsl`] and [`Expr::to_wgsl`] do the same for shaders, and
+//! [`Expr::to_sql`] for database queries.
+//!
+//! Generated code follows plain `f64` arithmetic rather than the evaluator's
+//! error checks: dividing by zero gives an infinity, and a function outside
+//! its domain (such as `sqrt(-1)`) gives NaN.
+
+use thiserror::Error;
+
+use crate::{Arity, CellRef, Comparison, ConstOp, Expr, functions::STANDARD};
+
+/// Errors that can occur while generating code from an expression
+#[derive(Error, Debug, PartialEq)]
+pub enum CodegenError {
+    /// A variable isn't one of the generated function's parameters
+    #[error("Variable '{0}' isn't a parameter")]
+    UnknownVariable(String),
+
+    /// A function has no translation into the target language
+    #[error("Function '{name}' can't be translated to {target}")]
+    UnsupportedFunction {
+        /// The function's name, as written in the call
+        name: String,
+        /// The language being generated
+        target: &'static str,
+    },
+
+    /// A function was called with the wrong number of arguments
+    #[error("Function '{signature}' takes {expected} arguments, got {found}")]
+    WrongArgumentCount {
+        /// How the function is called, as in `round(x, digits=0)`, see
+        /// [`Function::signature`](crate::Function::signature)
+        signature: String,
+        /// How many arguments the function accepts
+        expected: Arity,
+        /// How many arguments the call has
+        found: usize,
+    },
+
+    /// A call's named arguments don't fit the function's parameters
+    #[error("{0}")]
+    Arguments(String),
+
+    /// A number is too large for the target language's number type
+    #[error("{value} can't be written in {target}")]
+    UnrepresentableNumber {
+        /// The number
+        value: f64,
+        /// The language being generated
+        target: &'static str,
+    },
+
+    /// A name can't be used as an identifier in the target language
+    #[error("'{0}' can't be used as a name")]
+    InvalidName(String),
+
+    /// Cell references only have values inside a spreadsheet
+    #[error("Cell references can't be translated")]
+    CellReference,
+
+    /// The tree contains [`Expr::Error`] placeholders from recovery parsing
+    #[error("Expression contains syntax errors")]
+    ContainsErrors,
+
+    /// A piecewise definition has no default, so some inputs have no value
+    #[error("A piecewise definition needs a default to be translated")]
+    PiecewiseWithoutDefault,
+
+    /// Targets only work with numbers, so lists and matrices can't be written
+    #[error("Lists and matrices can't be translated")]
+    Matrix,
+}
+
+/// Rust keywords, which need to be written as raw identifiers (`r#type`)
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Functions that are the `f64` method of the same name
+const RUST_METHODS: &[&str] = &[
+    "abs", "sqrt", "cbrt", "exp", "ln", "log10", "log2", "sin", "cos", "tan", "asin", "acos",
+    "atan", "atan2", "sinh", "cosh", "tanh", "floor", "ceil", "trunc", "hypot",
+];
+
+impl Expr {
+    /// Generate a standalone Rust function that computes this expression
+    ///
+    /// The function is `pub`, takes one `f64` parameter for each of `vars`
+    /// (in that order) and returns an `f64`. Every variable in the expression
+    /// has to be one of `vars`. Cell references and functions other than the
+    /// [standard math functions](crate::FunctionRegistry::standard) can't be
+    /// translated.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("sqrt(x * x + y * y) > 1").unwrap();
+    /// assert_eq!(
+    ///     ast.to_rust_fn("outside", &["x", "y"]).unwrap(),
+    ///     "pub fn outside(x: f64, y: f64) -> f64 {\n    \
+    ///          f64::from(u8::from(f64::sqrt(x * x + y * y) > 1.0))\n}\n"
+    /// );
+    /// ```
+    pub fn to_rust_fn(&self, name: &str, vars: &[&str]) -> Result<String, CodegenError> {
+        let parameters = vars
+            .iter()
+            .map(|var| Ok(format!("{}: f64", Rust.name(var)?)))
+            .collect::<Result<Vec<_>, CodegenError>>()?;
+        let body = write(&Rust, self, Some(vars))?;
+        Ok(format!(
+            "pub fn {}({}) -> f64 {{\n    {}\n}}\n",
+            Rust.name(name)?,
+            parameters.join(", "),
+            body
+        ))
+    }
+
+    /// Generate a Rust expression that builds this tree
+    ///
+    /// The code names the crate as `::ast`, so it compiles wherever this
+    /// crate is a dependency under its own name. It's what the `expr!` macro
+    /// in the `ast-macros` crate expands to, and a `build.rs` script can use
+    /// it the same way to embed trees it parsed.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("-x").unwrap();
+    /// assert_eq!(
+    ///     ast.to_rust_constructor(),
+    ///     "::ast::Expr::Neg(::std::boxed::Box::new(\
+    ///          ::ast::Expr::Var(::std::string::String::from(\"x\"))))"
+    /// );
+    /// ```
+    pub fn to_rust_constructor(&self) -> String {
+        let mut code = String::new();
+        constructor(self, &mut code);
+        code
+    }
+
+    /// Generate a Rust expression for a [`ConstExpr`](crate::ConstExpr)
+    /// that computes this expression, taking `vars` in that order
+    ///
+    /// This is what the `const_expr!` macro in the `ast-macros` crate expands
+    /// to. The code names the crate as `::ast`.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("-x").unwrap();
+    /// assert_eq!(
+    ///     ast.to_rust_const(&["x"]).unwrap(),
+    ///     "::ast::ConstExpr::new(&[::ast::ConstOp::Variable(0), ::ast::ConstOp::Neg])"
+    /// );
+    /// ```
+    pub fn to_rust_const(&self, vars: &[&str]) -> Result<String, CodegenError> {
+        let ops = self
+            .to_const_ops(vars)?
+            .into_iter()
+            .map(|op| match op {
+                ConstOp::Number(value) => format!("::ast::ConstOp::Number({})", float(value)),
+                ConstOp::Compare(comparison) => format!(
+                    "::ast::ConstOp::Compare(::ast::Comparison::{:?})",
+                    comparison
+                ),
+                // Every other step is written the same in Rust as in its Debug form
+                op => format!("::ast::ConstOp::{:?}", op),
+            })
+            .collect::<Vec<_>>();
+        Ok(format!("::ast::ConstExpr::new(&[{}])", ops.join(", ")))
+    }
+}
+
+/// A Rust expression for an `f64`, exactly
+fn float(value: f64) -> String {
+    if value.is_nan() {
+        "::std::f64::NAN".to_string()
+    } else if value == f64::INFINITY {
+        "::std::f64::INFINITY".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "::std::f64::NEG_INFINITY".to_string()
+    } else {
+        format!("{:?}f64", value)
+    }
+}
+
+/// Write Rust code that builds `expr` to `code`
+fn constructor(expr: &Expr, code: &mut String) {
+    let boxed = |expr: &Expr, code: &mut String| {
+        code.push_str("::std::boxed::Box::new(");
+        constructor(expr, code);
+        code.push(')');
+    };
+    let list = |exprs: &[Expr], code: &mut String| {
+        code.push_str("::std::vec![");
+        for (index, expr) in exprs.iter().enumerate() {
+            if index > 0 {
+                code.push_str(", ");
+            }
+            constructor(expr, code);
+        }
+        code.push(']');
+    };
+    let string = |name: &str| format!("::std::string::String::from({:?})", name);
+    let cell = |cell: &CellRef| format!("::ast::CellRef::new({}, {})", cell.column, cell.row);
+
+    code.push_str("::ast::Expr::");
+    match expr {
+        Expr::Float(value) => code.push_str(&format!("Float({})", float(*value))),
+        Expr::Var(name) => code.push_str(&format!("Var({})", string(name))),
+        Expr::CellRef(reference) => code.push_str(&format!("CellRef({})", cell(reference))),
+        Expr::CellRange(from, to) => {
+            code.push_str(&format!("CellRange({}, {})", cell(from), cell(to)))
+        }
+        Expr::Error(span) => code.push_str(&format!(
+            "Error(::ast::Span::new({}, {}))",
+            span.start, span.end
+        )),
+        Expr::Add(left, right)
+        | Expr::Sub(left, right)
+        | Expr::Mul(left, right)
+        | Expr::Div(left, right)
+        | Expr::And(left, right)
+        | Expr::Or(left, right) => {
+            let variant = match expr {
+                Expr::Add(..) => "Add",
+                Expr::Sub(..) => "Sub",
+                Expr::Mul(..) => "Mul",
+                Expr::Div(..) => "Div",
+                Expr::And(..) => "And",
+                _ => "Or",
+            };
+            code.push_str(variant);
+            code.push('(');
+            boxed(left, code);
+            code.push_str(", ");
+            boxed(right, code);
+            code.push(')');
+        }
+        Expr::Compare(comparison, left, right) => {
+            code.push_str(&format!("Compare(::ast::Comparison::{:?}, ", comparison));
+            boxed(left, code);
+            code.push_str(", ");
+            boxed(right, code);
+            code.push(')');
+        }
+        Expr::Neg(inner) | Expr::Not(inner) => {
+            code.push_str(if matches!(expr, Expr::Neg(_)) {
+                "Neg("
+            } else {
+                "Not("
+            });
+            boxed(inner, code);
+            code.push(')');
+        }
+        Expr::Call(name, args) => {
+            code.push_str(&format!("Call({}, ", string(name)));
+            list(args, code);
+            code.push(')');
+        }
+        Expr::Named(name, value) => {
+            code.push_str(&format!("Named({}, ", string(name)));
+            boxed(value, code);
+            code.push(')');
+        }
+        Expr::Let(name, value, body) => {
+            code.push_str(&format!("Let({}, ", string(name)));
+            boxed(value, code);
+            code.push_str(", ");
+            boxed(body, code);
+            code.push(')');
+        }
+        Expr::Piecewise(cases, default) => {
+            code.push_str("Piecewise(::std::vec![");
+            for (index, (condition, value)) in cases.iter().enumerate() {
+                if index > 0 {
+                    code.push_str(", ");
+                }
+                code.push('(');
+                constructor(condition, code);
+                code.push_str(", ");
+                constructor(value, code);
+                code.push(')');
+            }
+            code.push_str("], ");
+            match default {
+                Some(default) => {
+                    code.push_str("::std::option::Option::Some(");
+                    boxed(default, code);
+                    code.push(')');
+                }
+                None => code.push_str("::std::option::Option::None"),
+            }
+            code.push(')');
+        }
+        Expr::List(elements) => {
+            code.push_str("List(");
+            list(elements, code);
+            code.push(')');
+        }
+        Expr::Index(target, indices) => {
+            code.push_str("Index(");
+            boxed(target, code);
+            code.push_str(", ");
+            list(indices, code);
+            code.push(')');
+        }
+    }
+}
+
+/// The precedence of expressions that need parentheses around them in any operation
+pub(crate) const LOOSEST: u8 = 0;
+
+/// The precedence of `+` and `-`
+pub(crate) const SUM: u8 = 4;
+
+/// The precedence of `*` and `/`
+pub(crate) const PRODUCT: u8 = 5;
+
+/// The precedence of unary minus
+pub(crate) const UNARY: u8 = 6;
+
+/// The precedence of literals, names and calls, which never need parentheses
+pub(crate) const ATOM: u8 = 7;
+
+/// A language that expressions can be written in
+///
+/// Arithmetic, comparisons and the logical operators are written the same way
+/// in every language; this describes everything else.
+pub(crate) trait Target {
+    /// Whether `&&` needs parentheses when it's an operand of `||`
+    const PARENTHESIZE_AND_IN_OR: bool = false;
+
+    /// The language's name, for error messages
+    fn language(&self) -> &'static str;
+
+    /// Spell a comparison or logical operator, given as written in expressions
+    ///
+    /// `!` is written directly before its operand.
+    fn operator(&self, op: &'static str) -> &'static str {
+        op
+    }
+
+    /// Write a variable's name as an identifier
+    fn name(&self, name: &str) -> Result<String, CodegenError>;
+
+    /// Write a number literal, and its precedence
+    fn literal(&self, value: f64) -> Result<(String, u8), CodegenError>;
+
+    /// Turn a boolean into a number: 1 for true and 0 for false
+    fn number(&self, boolean: &str) -> String;
+
+    /// Write a call to one of the standard functions, and its precedence
+    ///
+    /// The arguments are already written, and the number of them is allowed.
+    /// Returns `None` if the function has no translation.
+    fn call(&self, name: &str, args: Vec<String>) -> Option<(String, u8)>;
+
+    /// Write a choice between values, and its precedence
+    ///
+    /// Each case is a written condition and the value it picks, and the
+    /// default is picked when no condition holds.
+    fn piecewise(&self, cases: Vec<(String, String)>, default: String) -> (String, u8);
+}
+
+/// Write an expression in a target language
+///
+/// If `vars` is given, every variable in the expression has to be one of them.
+pub(crate) fn write<T: Target>(
+    target: &T,
+    expr: &Expr,
+    vars: Option<&[&str]>,
+) -> Result<String, CodegenError> {
+    Writer { target, vars }.number(expr, LOOSEST)
+}
+
+/// Check that a name is made of ASCII letters, digits and underscores, not starting with a digit
+pub(crate) fn identifier(name: &str) -> Result<&str, CodegenError> {
+    let mut chars = name.chars();
+    if chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Ok(name)
+    } else {
+        Err(CodegenError::InvalidName(name.to_string()))
+    }
+}
+
+/// Writes expressions in a target language
+struct Writer<'a, T> {
+    target: &'a T,
+    /// The variables that may be used, if they're restricted
+    vars: Option<&'a [&'a str]>,
+}
+
+impl<T: Target> Writer<'_, T> {
+    /// Write an expression that produces a number
+    ///
+    /// `context` is the precedence of the surrounding operator: the result is
+    /// parenthesized if it binds less tightly. Right operands are given one
+    /// more than their operator, to keep the parentheses in `a - (b - c)`.
+    fn number(&self, expr: &Expr, context: u8) -> Result<String, CodegenError> {
+        let (code, precedence) = match expr {
+            Expr::Float(value) => self.target.literal(*value)?,
+            Expr::Var(name) if self.vars.is_none_or(|vars| vars.contains(&name.as_str())) => {
+                (self.target.name(name)?, ATOM)
+            }
+            Expr::Var(name) => return Err(CodegenError::UnknownVariable(name.clone())),
+            Expr::CellRef(_) | Expr::CellRange(_, _) => return Err(CodegenError::CellReference),
+            Expr::List(_) | Expr::Index(..) => return Err(CodegenError::Matrix),
+            Expr::Error(_) => return Err(CodegenError::ContainsErrors),
+            Expr::Add(left, right) => self.binary("+", SUM, left, right)?,
+            Expr::Sub(left, right) => self.binary("-", SUM, left, right)?,
+            Expr::Mul(left, right) => self.binary("*", PRODUCT, left, right)?,
+            Expr::Div(left, right) => self.binary("/", PRODUCT, left, right)?,
+            Expr::Neg(inner) => (format!("-{}", self.number(inner, ATOM)?), UNARY),
+            Expr::Compare(..) | Expr::And(..) | Expr::Or(..) | Expr::Not(..) => {
+                (self.target.number(&self.boolean(expr, 0)?), ATOM)
+            }
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Named(_, value) => return self.number(value, context),
+            // Targets have no local variables, so the value is written where it's used
+            Expr::Let(..) => return self.number(&expr.inline_lets(), context),
+            Expr::Piecewise(cases, default) => {
+                let default = default
+                    .as_deref()
+                    .ok_or(CodegenError::PiecewiseWithoutDefault)?;
+                let cases = cases
+                    .iter()
+                    .map(|(condition, value)| {
+                        Ok((
+                            self.boolean(condition, LOOSEST)?,
+                            self.number(value, LOOSEST)?,
+                        ))
+                    })
+                    .collect::<Result<_, CodegenError>>()?;
+                self.target.piecewise(cases, self.number(default, LOOSEST)?)
+            }
+        };
+        Ok(parenthesize(code, precedence, context))
+    }
+
+    /// Write an operator applied to two numbers
+    fn binary(
+        &self,
+        op: &str,
+        precedence: u8,
+        left: &Expr,
+        right: &Expr,
+    ) -> Result<(String, u8), CodegenError> {
+        let left = self.number(left, precedence)?;
+        let right = self.number(right, precedence + 1)?;
+        Ok((format!("{} {} {}", left, op, right), precedence))
+    }
+
+    /// Write an expression that produces a boolean, treating numbers other than 0 as true
+    ///
+    /// `context` is the precedence of the surrounding operator, as for
+    /// [`Writer::number`]: 1 for `||`, 2 for `&&`, 3 for comparisons and 4 for `!`.
+    fn boolean(&self, expr: &Expr, context: u8) -> Result<String, CodegenError> {
+        let (code, precedence) = match expr {
+            Expr::Compare(comparison, left, right) => {
+                let left = self.number(left, SUM)?;
+                let right = self.number(right, SUM)?;
+                let op = self.target.operator(comparison.symbol());
+                (format!("{} {} {}", left, op, right), 3)
+            }
+            Expr::And(left, right) => {
+                let left = self.boolean(left, 2)?;
+                let right = self.boolean(right, 2)?;
+                (
+                    format!("{} {} {}", left, self.target.operator("&&"), right),
+                    2,
+                )
+            }
+            Expr::Or(left, right) => {
+                let operand = |expr: &Expr| {
+                    let and = T::PARENTHESIZE_AND_IN_OR && matches!(expr, Expr::And(..));
+                    self.boolean(expr, if and { 3 } else { 1 })
+                };
+                let op = self.target.operator("||");
+                (format!("{} {} {}", operand(left)?, op, operand(right)?), 1)
+            }
+            Expr::Not(inner) if is_boolean(inner) => {
+                let op = self.target.operator("!");
+                (format!("{}{}", op, self.boolean(inner, 4)?), 4)
+            }
+            Expr::Not(inner) => return self.boolean(&zero_test(Comparison::Equal, inner), context),
+            _ => return self.boolean(&zero_test(Comparison::NotEqual, expr), context),
+        };
+        Ok(parenthesize(code, precedence, context))
+    }
+
+    /// Write a call to one of the standard functions, and its precedence
+    fn call(&self, name: &str, args: &[Expr]) -> Result<(String, u8), CodegenError> {
+        let unsupported = || CodegenError::UnsupportedFunction {
+            name: name.to_string(),
+            target: self.target.language(),
+        };
+        let function = STANDARD.get(name).ok_or_else(unsupported)?;
+        let args = function
+            .arrange(name, args)
+            .map_err(|error| CodegenError::Arguments(error.to_string()))?;
+        let arity = function.arity();
+        if !arity.accepts(args.len()) {
+            return Err(CodegenError::WrongArgumentCount {
+                signature: function.signature(name),
+                expected: arity,
+                found: args.len(),
+            });
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| self.number(arg, LOOSEST))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.target.call(name, args).ok_or_else(unsupported)
+    }
+}
+
+/// Wrap code in parentheses if it binds less tightly than its context
+fn parenthesize(code: String, precedence: u8, context: u8) -> String {
+    if precedence < context {
+        format!("({})", code)
+    } else {
+        code
+    }
+}
+
+/// Whether an expression is a comparison or logical operation, rather than a number
+fn is_boolean(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Compare(..) | Expr::And(..) | Expr::Or(..) | Expr::Not(..)
+    )
+}
+
+/// Compare a number with 0, e.g. to find its truth value
+fn zero_test(comparison: Comparison, expr: &Expr) -> Expr {
+    Expr::Compare(
+        comparison,
+        Box::new(expr.clone()),
+        Box::new(Expr::Float(0.0)),
+    )
+}
+
+/// Combine the arguments of `min` or `max` pairwise, e.g. `max(max(a, b), c)`
+///
+/// `function` writes a call with two arguments. A single argument is
+/// returned as it is.
+pub(crate) fn pairwise(
+    args: Vec<String>,
+    function: impl Fn(String, String) -> String,
+) -> (String, u8) {
+    if args.len() == 1 {
+        // The argument was written without knowing where it would end up
+        let arg = args.into_iter().next().expect("one argument");
+        return (arg, LOOSEST);
+    }
+    let code = args
+        .into_iter()
+        .reduce(function)
+        .expect("min and max take at least one argument");
+    (code, ATOM)
+}
+
+/// Rust, with `f64` for numbers
+struct Rust;
+
+impl Target for Rust {
+    fn language(&self) -> &'static str {
+        "Rust"
+    }
+
+    /// Rust identifiers, using raw identifiers for keywords
+    fn name(&self, name: &str) -> Result<String, CodegenError> {
+        let name = identifier(name)?;
+        if ["_", "self", "Self", "super", "crate"].contains(&name) {
+            Err(CodegenError::InvalidName(name.to_string()))
+        } else if RUST_KEYWORDS.contains(&name) {
+            Ok(format!("r#{}", name))
+        } else {
+            Ok(name.to_string())
+        }
+    }
+
+    fn literal(&self, value: f64) -> Result<(String, u8), CodegenError> {
+        Ok(if value.is_nan() {
+            ("f64::NAN".to_string(), ATOM)
+        } else if value == f64::INFINITY {
+            ("f64::INFINITY".to_string(), ATOM)
+        } else if value == f64::NEG_INFINITY {
+            ("f64::NEG_INFINITY".to_string(), ATOM)
+        } else if value < 0.0 {
+            (format!("{:?}", value), UNARY)
+        } else {
+            (format!("{:?}", value), ATOM)
+        })
+    }
+
+    fn number(&self, boolean: &str) -> String {
+        format!("f64::from(u8::from({}))", boolean)
+    }
+
+    fn call(&self, name: &str, args: Vec<String>) -> Option<(String, u8)> {
+        Some(match name {
+            _ if RUST_METHODS.contains(&name) => {
+                (format!("f64::{}({})", name, args.join(", ")), ATOM)
+            }
+            "pow" => (format!("f64::powf({}, {})", args[0], args[1]), ATOM),
+            "round" if args.len() == 1 => (format!("f64::round({})", args[0]), ATOM),
+            // `signum` gives 1 for 0. An `if` swallows any operator after it, so it binds loosest
+            "sign" => (
+                format!(
+                    "if {0} == 0.0 {{ 0.0 }} else {{ f64::signum({0}) }}",
+                    args[0]
+                ),
+                LOOSEST,
+            ),
+            "min" | "max" => pairwise(args, |so_far, arg| {
+                format!("f64::{}({}, {})", name, so_far, arg)
+            }),
+            _ => return None,
+        })
+    }
+
+    /// An `if` chain, which like `sign` binds loosest
+    fn piecewise(&self, cases: Vec<(String, String)>, default: String) -> (String, u8) {
+        let mut code = String::new();
+        for (condition, value) in cases {
+            code.push_str(&format!("if {} {{ {} }} else ", condition, value));
+        }
+        (format!("{}{{ {} }}", code, default), LOOSEST)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Generate the body of a function of x and y
+    fn body(expression: &str) -> Result<String, CodegenError> {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        let code = ast.to_rust_fn("f", &["x", "y"])?;
+        let body = code
+            .strip_prefix("pub fn f(x: f64, y: f64) -> f64 {\n    ")
+            .and_then(|code| code.strip_suffix("\n}\n"))
+            .expect("function header and footer");
+        Ok(body.to_string())
+    }
+
+    /// Test the generated Rust for a variety of expressions
+    #[test]
+    fn test_rust() {
+        let cases = [
+            ("1 + 2 * x", "1.0 + 2.0 * x"),
+            ("(1 + 2) * x", "(1.0 + 2.0) * x"),
+            ("x - (y - 1)", "x - (y - 1.0)"),
+            ("x / y / 2", "x / y / 2.0"),
+            ("-(x + 1)", "-(x + 1.0)"),
+            ("--x", "-(-x)"),
+            (
+                "pow(x, 0.5) + max(x, y, 1e300)",
+                "f64::powf(x, 0.5) + f64::max(f64::max(x, y), 1e300)",
+            ),
+            (
+                "sign(x - 1)",
+                "if x - 1.0 == 0.0 { 0.0 } else { f64::signum(x - 1.0) }",
+            ),
+            (
+                "sign(x) * 2",
+                "(if x == 0.0 { 0.0 } else { f64::signum(x) }) * 2.0",
+            ),
+            ("x < y", "f64::from(u8::from(x < y))"),
+            (
+                "(x < y) < 1",
+                "f64::from(u8::from(f64::from(u8::from(x < y)) < 1.0))",
+            ),
+            (
+                "x && !(y > 1) || !x",
+                "f64::from(u8::from(x != 0.0 && !(y > 1.0) || x == 0.0))",
+            ),
+            (
+                "x && (y || 1)",
+                "f64::from(u8::from(x != 0.0 && (y != 0.0 || 1.0 != 0.0)))",
+            ),
+            ("!!x", "f64::from(u8::from(!(x == 0.0)))"),
+            ("x * (y == 2)", "x * f64::from(u8::from(y == 2.0))"),
+            ("let t = x + 1 in t * t", "(x + 1.0) * (x + 1.0)"),
+            ("let x = y in let y = 2 in x + y", "y + 2.0"),
+            (
+                "piecewise((x < 0, -x), (y, 1), 0) + 1",
+                "(if x < 0.0 { -x } else if y != 0.0 { 1.0 } else { 0.0 }) + 1.0",
+            ),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(body(expression).unwrap(), expected, "'{}'", expression);
+        }
+
+        let (_, ast) = parse_expression("type + 1").unwrap();
+        assert_eq!(
+            ast.to_rust_fn("r", &["type"]).unwrap(),
+            "pub fn r(r#type: f64) -> f64 {\n    r#type + 1.0\n}\n"
+        );
+    }
+
+    /// Test the Rust that builds trees, for nodes the parser doesn't make too
+    #[test]
+    fn test_constructor() {
+        let (_, ast) = parse_expression("f(x < 1, [])").unwrap();
+        assert_eq!(
+            ast.to_rust_constructor(),
+            "::ast::Expr::Call(::std::string::String::from(\"f\"), ::std::vec![\
+                 ::ast::Expr::Compare(::ast::Comparison::Less, \
+                 ::std::boxed::Box::new(::ast::Expr::Var(::std::string::String::from(\"x\"))), \
+                 ::std::boxed::Box::new(::ast::Expr::Float(1.0f64))), \
+                 ::ast::Expr::List(::std::vec![])])"
+        );
+        let range = Expr::CellRange(CellRef::new(1, 2), CellRef::new(3, 4));
+        assert_eq!(
+            range.to_rust_constructor(),
+            "::ast::Expr::CellRange(::ast::CellRef::new(1, 2), ::ast::CellRef::new(3, 4))"
+        );
+        assert_eq!(
+            Expr::Float(f64::NEG_INFINITY).to_rust_constructor(),
+            "::ast::Expr::Float(::std::f64::NEG_INFINITY)"
+        );
+        assert_eq!(
+            Expr::Float(1e-7).to_rust_constructor(),
+            "::ast::Expr::Float(1e-7f64)"
+        );
+    }
+
+    /// Test that expressions without a Rust translation are rejected
+    #[test]
+    fn test_rust_errors() {
+        assert_eq!(
+            body("x + z"),
+            Err(CodegenError::UnknownVariable("z".to_string()))
+        );
+        assert_eq!(
+            body("round(x, 2)"),
+            Err(CodegenError::UnsupportedFunction {
+                name: "round".to_string(),
+                target: "Rust"
+            })
+        );
+        assert!(matches!(
+            body("sqrt(x, y)"),
+            Err(CodegenError::WrongArgumentCount { found: 2, .. })
+        ));
+        assert_eq!(
+            body("sqrt(x=y)"),
+            Err(CodegenError::Arguments(
+                "Function 'sqrt' has no parameter named 'x'".to_string()
+            ))
+        );
+        assert!(matches!(
+            body("days(x)"),
+            Err(CodegenError::UnsupportedFunction { .. })
+        ));
+        assert_eq!(
+            body("piecewise((x < 0, -x))"),
+            Err(CodegenError::PiecewiseWithoutDefault)
+        );
+
+        let (_, ast) = parse_expression("x").unwrap();
+        assert_eq!(
+            ast.to_rust_fn("self", &["x"]),
+            Err(CodegenError::InvalidName("self".to_string()))
+        );
+    }
+}
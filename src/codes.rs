@@ -0,0 +1,347 @@
+//! Stable codes for diagnostics and errors
+//!
+//! Messages may be reworded or translated, but codes don't change: `E0001`
+//! always means division by zero. Applications can key their own messages,
+//! help links or translations on the code of a [`Diagnostic`](crate::Diagnostic)
+//! or an [`EvaluationError`], and [`explain_code`] gives a longer explanation.
+//!
+//! Codes starting with `P` are syntax errors, `W` are lint warnings and `E`
+//! are evaluation errors.
+
+use crate::EvaluationError;
+
+pub(crate) const MISSING_OPERAND: &str = "P0001";
+pub(crate) const UNEXPECTED_INPUT: &str = "P0002";
+pub(crate) const UNBALANCED_PARENTHESIS: &str = "P0003";
+pub(crate) const STRAY_COMMA: &str = "P0004";
+pub(crate) const MISSING_OPERATOR: &str = "P0005";
+pub(crate) const HALF_OPERATOR: &str = "P0006";
+pub(crate) const INCOMPLETE_LET: &str = "P0007";
+pub(crate) const MALFORMED_PIECEWISE: &str = "P0008";
+pub(crate) const ARGUMENT_ORDER: &str = "P0009";
+pub(crate) const TOO_DEEP: &str = "P0010";
+
+pub(crate) const REDUNDANT_PARENTHESES: &str = "W0001";
+pub(crate) const DOUBLE_NEGATION: &str = "W0002";
+pub(crate) const ZERO_FACTOR: &str = "W0003";
+pub(crate) const FRACTION_EQUALITY: &str = "W0004";
+pub(crate) const UNUSED_ASSIGNMENT: &str = "W0005";
+
+/// Every code, with a short title and an explanation
+const CODES: &[(&str, &str, &str)] = &[
+    (
+        MISSING_OPERAND,
+        "missing operand",
+        "An operator or an opening parenthesis isn't followed by the value it needs, as in `2 * ` or `()`.",
+    ),
+    (
+        UNEXPECTED_INPUT,
+        "unexpected input",
+        "Something that is neither a number, a name, an operator nor a parenthesis appears where a value should be, as in `2 + #`.",
+    ),
+    (
+        UNBALANCED_PARENTHESIS,
+        "unbalanced parenthesis",
+        "A '(' is never closed, or a ')' has no '(' to close. Every opening parenthesis needs a closing one.",
+    ),
+    (
+        STRAY_COMMA,
+        "comma outside a function call",
+        "Commas only separate the arguments of a function call, as in `max(1, 2)`. Lists such as `(1, 2)` aren't values.",
+    ),
+    (
+        MISSING_OPERATOR,
+        "missing operator",
+        "Two values follow each other without an operator between them, as in `2 x`. Multiplication must be written as `2 * x`.",
+    ),
+    (
+        HALF_OPERATOR,
+        "incomplete operator",
+        "A single '=', '&' or '|' was written where '==', '&&' or '||' was probably meant.",
+    ),
+    (
+        INCOMPLETE_LET,
+        "incomplete let",
+        "A local variable is written `let name = value in body`, and part of that is missing, as in `let t = 2 t * t`.",
+    ),
+    (
+        MALFORMED_PIECEWISE,
+        "malformed piecewise",
+        "A piecewise definition is written `piecewise((condition, value), ..., default)`, with at least one case and the optional default last.",
+    ),
+    (
+        ARGUMENT_ORDER,
+        "positional argument after a named one",
+        "A call's arguments given by position come first, then any given by name, as in `round(x, digits=2)`. Once one is named, the ones after it need names too.",
+    ),
+    (
+        TOO_DEEP,
+        "nested too deeply",
+        "Parentheses, negations, function calls or `let`s are nested more levels deep than the parser allows, which is 64 by default. Split the formula up, or raise `ParserConfig::max_depth`.",
+    ),
+    (
+        REDUNDANT_PARENTHESES,
+        "redundant parentheses",
+        "The parentheses don't change how the expression is grouped, so they can be removed.",
+    ),
+    (
+        DOUBLE_NEGATION,
+        "subtracting a negation",
+        "Subtracting a negative value, as in `5 - -3`, is easier to read as an addition: `5 + 3`.",
+    ),
+    (
+        ZERO_FACTOR,
+        "factor is always zero",
+        "One factor of a product always evaluates to 0, so the whole product is always 0. This is usually a mistake.",
+    ),
+    (
+        FRACTION_EQUALITY,
+        "fractions compared exactly",
+        "Fractions such as `0.1` can't be stored exactly, so calculations with them are slightly off and `0.1 + 0.2 == 0.3` is false. Compare the difference against a tolerance instead, as in `abs(x - 0.3) < 1e-9`.",
+    ),
+    (
+        UNUSED_ASSIGNMENT,
+        "unused assignment",
+        "A value is given a name that is never used, as in `let t = 2 in 5`, or a variable is assigned again before its value is used, so the first value is thrown away.",
+    ),
+    (
+        "E0001",
+        "division by zero",
+        "The divisor of a division evaluated to 0. Check the divisor, or guard the division with a condition.",
+    ),
+    (
+        "E0002",
+        "undefined variable",
+        "The expression uses a variable that has no value. Define it, or check its spelling.",
+    ),
+    (
+        "E0003",
+        "cell has no value",
+        "A cell reference such as `A1` refers to an empty cell, or there's nothing to look cells up in.",
+    ),
+    (
+        "E0004",
+        "cell range used as a number",
+        "A range such as `A1:B2` can only be passed to a function such as `SUM`, not used as a number itself.",
+    ),
+    (
+        "E0005",
+        "unknown function",
+        "The expression calls a function that doesn't exist. Check its spelling, or register it.",
+    ),
+    (
+        "E0006",
+        "wrong number of arguments",
+        "A function was called with more or fewer arguments than it takes.",
+    ),
+    (
+        "E0007",
+        "argument out of range",
+        "A function's argument is outside the values the function is defined for, such as `sqrt(-1)` or `ln(0)`.",
+    ),
+    (
+        "E0008",
+        "type mismatch",
+        "A value of the wrong kind was used, such as a date where a number is needed.",
+    ),
+    (
+        "E0009",
+        "unsupported operands",
+        "An operator was used with kinds of values it doesn't work on, such as adding two dates.",
+    ),
+    (
+        "E0010",
+        "overflow",
+        "A result is larger than the configured maximum magnitude.",
+    ),
+    (
+        "E0011",
+        "subnormal result",
+        "A result is so close to zero that it can't be represented accurately, and the configuration treats that as an error.",
+    ),
+    (
+        "E0012",
+        "syntax errors",
+        "The expression still contains parts that couldn't be parsed. Fix the syntax errors first.",
+    ),
+    (
+        "E0013",
+        "recursion too deep",
+        "A function kept calling itself, or other functions, past the configured depth. Check that its recursion stops, for example with `if`.",
+    ),
+    (
+        "E0014",
+        "matrix sizes don't match",
+        "Matrices are added and subtracted element by element, so they need the same size, and `a * b` needs as many columns in `a` as rows in `b`.",
+    ),
+    (
+        "E0015",
+        "index out of range",
+        "Indices into a vector or matrix count from 1, so `[5, 6, 7][3]` is the last element. An index must be a whole number no larger than the length.",
+    ),
+    (
+        "E0016",
+        "rows of different lengths",
+        "Every row of a matrix needs the same number of elements, as in `[[1, 2], [3, 4]]`.",
+    ),
+    (
+        "E0017",
+        "function arguments of different sizes",
+        "A function applied to matrices works element by element, so its matrix arguments need the same size. `dot(a, b)` needs vectors of the same length and `cross(a, b)` two vectors of 3 elements.",
+    ),
+    (
+        "E0018",
+        "function not allowed",
+        "The function reads the clock, uses random numbers or reaches outside the program, and whoever is evaluating the expression has turned such functions off so that results can be reproduced.",
+    ),
+    (
+        "E0019",
+        "deadline passed",
+        "Evaluating the expression took longer than whoever was evaluating it allowed. Simplify the expression, or allow more time.",
+    ),
+    (
+        "E0020",
+        "evaluation cancelled",
+        "Whoever was evaluating the expression no longer needed the result and stopped the evaluation, usually because the expression changed.",
+    ),
+    (
+        "E0021",
+        "no modular inverse",
+        "In arithmetic modulo m, dividing by b multiplies by the inverse of b, which only exists when b and m have no common factor: modulo 12, 1 / 5 is 5, but 1 / 4 has no value. Use a prime modulus, or avoid dividing by such numbers.",
+    ),
+    (
+        "E0022",
+        "unknown parameter name",
+        "An argument was given by a name, as in `round(x, digits=2)`, that isn't one of the function's parameters. Check the spelling, or give the argument by position.",
+    ),
+    (
+        "E0023",
+        "argument given twice",
+        "Each parameter takes one argument. In `round(2.5, 1, digits=0)` the second argument is already `digits`, so naming it again is an error.",
+    ),
+    (
+        "E0024",
+        "missing argument",
+        "Arguments given by name can skip ahead, but every parameter before the last one given needs an argument: `date(2024, 1, minute=30)` leaves out `day` and `hour`.",
+    ),
+];
+
+/// The explanation of a code, or `None` if there's no such code
+///
+/// # Example
+/// ```
+/// use ast::{explain_code, parse_with_recovery, EvaluationError};
+///
+/// let (_, diagnostics) = parse_with_recovery("(1 + 2");
+/// assert_eq!(diagnostics[0].code, Some("P0003"));
+/// assert!(explain_code("P0003").unwrap().starts_with("unbalanced parenthesis: "));
+///
+/// assert_eq!(EvaluationError::DivisionByZero.code(), "E0001");
+/// assert_eq!(explain_code("X9999"), None);
+/// ```
+pub fn explain_code(code: &str) -> Option<String> {
+    CODES
+        .iter()
+        .find(|(known, ..)| known.eq_ignore_ascii_case(code))
+        .map(|(_, title, explanation)| format!("{}: {}", title, explanation))
+}
+
+impl EvaluationError {
+    /// The stable code for this kind of error, such as `E0001` for division by zero
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvaluationError::DivisionByZero => "E0001",
+            EvaluationError::UndefinedVariable(_) => "E0002",
+            EvaluationError::UnresolvedCell(_) => "E0003",
+            EvaluationError::RangeNotAllowed(..) => "E0004",
+            EvaluationError::UnknownFunction(_) => "E0005",
+            EvaluationError::WrongArgumentCount { .. } => "E0006",
+            EvaluationError::DomainError(_) | EvaluationError::OutOfDomain { .. } => "E0007",
+            EvaluationError::TypeMismatch { .. } => "E0008",
+            EvaluationError::UnsupportedOperands { .. } => "E0009",
+            EvaluationError::Overflow { .. } => "E0010",
+            EvaluationError::Subnormal(_) => "E0011",
+            EvaluationError::ContainsErrors => "E0012",
+            EvaluationError::RecursionLimit(_) => "E0013",
+            EvaluationError::DimensionMismatch { .. } => "E0014",
+            EvaluationError::IndexOutOfRange { .. } => "E0015",
+            EvaluationError::RaggedRows { .. } => "E0016",
+            EvaluationError::ArgumentSizes { .. } => "E0017",
+            EvaluationError::NotPermitted(_) => "E0018",
+            EvaluationError::Timeout => "E0019",
+            EvaluationError::Cancelled => "E0020",
+            EvaluationError::NoInverse { .. } => "E0021",
+            EvaluationError::UnknownParameter { .. } => "E0022",
+            EvaluationError::DuplicateArgument { .. } => "E0023",
+            EvaluationError::MissingArgument { .. } => "E0024",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lint, parse_with_recovery};
+
+    /// Test that codes are unique and every one has an explanation
+    #[test]
+    fn test_codes_are_unique() {
+        for (index, (code, title, explanation)) in CODES.iter().enumerate() {
+            assert_eq!(code.len(), 5, "{}", code);
+            assert!(!title.is_empty() && explanation.ends_with('.'), "{}", code);
+            assert!(
+                CODES[index + 1..].iter().all(|(other, ..)| other != code),
+                "{} is used twice",
+                code
+            );
+        }
+        assert_eq!(
+            explain_code("e0002"),
+            Some(
+                "undefined variable: The expression uses a variable that has no value. \
+                 Define it, or check its spelling."
+                    .to_string()
+            )
+        );
+    }
+
+    /// Test that every diagnostic gets a code that can be explained
+    #[test]
+    fn test_diagnostic_codes() {
+        let cases = [
+            ("1 +", MISSING_OPERAND),
+            ("1 + #", UNEXPECTED_INPUT),
+            ("(1 + 2", UNBALANCED_PARENTHESIS),
+            ("1 + 2)", UNBALANCED_PARENTHESIS),
+            ("(1, 2)", STRAY_COMMA),
+            ("1 2", MISSING_OPERATOR),
+            ("1 = 2", HALF_OPERATOR),
+            ("let t 2 in t", INCOMPLETE_LET),
+            ("let t = 2", INCOMPLETE_LET),
+            ("piecewise(1)", MALFORMED_PIECEWISE),
+            ("piecewise((x, 1), 2, 3)", MALFORMED_PIECEWISE),
+            ("round(digits=2, x)", ARGUMENT_ORDER),
+            (&"(".repeat(100), TOO_DEEP),
+        ];
+        for (input, code) in cases {
+            let (_, diagnostics) = parse_with_recovery(input);
+            assert_eq!(diagnostics[0].code, Some(code), "'{}'", input);
+        }
+
+        let warnings = lint("(2 * 3) - -x * (1 - 1) + (let t = 2 in x / 3 == 0.5)");
+        let codes: Vec<_> = warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(
+            codes,
+            [
+                Some(REDUNDANT_PARENTHESES),
+                Some(DOUBLE_NEGATION),
+                Some(ZERO_FACTOR),
+                Some(UNUSED_ASSIGNMENT),
+                Some(FRACTION_EQUALITY)
+            ]
+        );
+        for code in codes {
+            assert!(explain_code(code.unwrap()).is_some());
+        }
+    }
+}
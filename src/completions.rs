@@ -0,0 +1,209 @@
+//! Shell completion scripts
+//!
+//! `ast completions bash` (or `zsh` or `fish`) prints a script that teaches
+//! the shell the calculator's options, built from [`FLAGS`]: it completes
+//! option names, the words an option accepts (`--angle degrees`) and file
+//! names where an option takes a file. Installing it is the usual
+//! `ast completions bash > /etc/bash_completion.d/ast`, or the equivalent
+//! directory for zsh (`_ast` on `$fpath`) or fish
+//! (`~/.config/fish/completions/ast.fish`).
+
+use std::fmt::Write;
+
+use crate::settings::{FLAGS, Flag, FlagValue};
+
+/// The shells completion scripts can be written for
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// The completion script for `shell`, or `None` if it isn't one of [`SHELLS`]
+pub fn script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        _ => None,
+    }
+}
+
+/// The ways to write an option, such as `-q` and `--quiet`
+fn names(flag: &Flag) -> Vec<String> {
+    let long = format!("--{}", flag.long);
+    match flag.short {
+        Some(short) => vec![format!("-{}", short), long],
+        None => vec![long],
+    }
+}
+
+/// A bash completion function, registered with `complete -F`
+fn bash() -> String {
+    let mut script = String::from(
+        "# bash completion for ast\n\
+         _ast() {\n    \
+             local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n    \
+             if [[ $COMP_CWORD -eq 1 && $cur != -* ]]; then\n        \
+                 COMPREPLY=($(compgen -W \"completions\" -- \"$cur\"))\n        \
+                 return\n    \
+             fi\n    \
+             case \"$prev\" in\n",
+    );
+    writeln!(
+        script,
+        "        completions) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;",
+        SHELLS.join(" ")
+    )
+    .expect("writing to a string can't fail");
+    for flag in FLAGS {
+        let reply = match flag.value {
+            FlagValue::None => continue,
+            FlagValue::Choice(words) => {
+                format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    words.join(" ")
+                )
+            }
+            FlagValue::File => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+            FlagValue::Text => "COMPREPLY=()".to_string(),
+        };
+        writeln!(
+            script,
+            "        {}) {}; return ;;",
+            names(flag).join("|"),
+            reply
+        )
+        .expect("writing to a string can't fail");
+    }
+    let all: Vec<String> = FLAGS.iter().flat_map(names).collect();
+    writeln!(
+        script,
+        "    esac\n    COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n}}\ncomplete -F _ast ast",
+        all.join(" ")
+    )
+    .expect("writing to a string can't fail");
+    script
+}
+
+/// A zsh completion function, for a file named `_ast` on `$fpath`
+fn zsh() -> String {
+    let mut script = format!(
+        "#compdef ast\n\n\
+         _ast() {{\n  \
+             if [[ $words[2] == completions ]]; then\n    \
+                 (( CURRENT == 3 )) && _values 'shell' {}\n    \
+                 return\n  \
+             fi\n  \
+             _arguments -s \\\n",
+        SHELLS.join(" ")
+    );
+    for flag in FLAGS {
+        let help = flag
+            .help
+            .replace('\'', "'\\''")
+            .replace('[', "\\[")
+            .replace(']', "\\]");
+        let value = match flag.value {
+            FlagValue::None => String::new(),
+            FlagValue::Choice(words) => format!(":{}:({})", flag.long, words.join(" ")),
+            FlagValue::File => ":file:_files".to_string(),
+            FlagValue::Text => format!(":{}: ", flag.long),
+        };
+        let spec = match flag.short {
+            Some(short) => format!(
+                "'(-{0} --{1})'{{-{0},--{1}}}'[{2}]{3}'",
+                short, flag.long, help, value
+            ),
+            None => format!("'--{}[{}]{}'", flag.long, help, value),
+        };
+        writeln!(script, "    {} \\", spec).expect("writing to a string can't fail");
+    }
+    script.push_str("    '1:command:(completions)'\n}\n\n_ast \"$@\"\n");
+    script
+}
+
+/// fish `complete` commands, for `~/.config/fish/completions/ast.fish`
+fn fish() -> String {
+    let mut script = String::from("# fish completion for ast\ncomplete -c ast -f\n");
+    writeln!(
+        script,
+        "complete -c ast -n __fish_use_subcommand -a completions -d 'Print a shell completion script'\n\
+         complete -c ast -n '__fish_seen_subcommand_from completions' -a '{}'",
+        SHELLS.join(" ")
+    )
+    .expect("writing to a string can't fail");
+    for flag in FLAGS {
+        let mut line = String::from("complete -c ast");
+        if let Some(short) = flag.short {
+            write!(line, " -s {}", short).expect("writing to a string can't fail");
+        }
+        write!(line, " -l {}", flag.long).expect("writing to a string can't fail");
+        match flag.value {
+            FlagValue::None => {}
+            FlagValue::Choice(words) => write!(line, " -x -a '{}'", words.join(" "))
+                .expect("writing to a string can't fail"),
+            FlagValue::File => line.push_str(" -r -F"),
+            FlagValue::Text => line.push_str(" -x"),
+        }
+        writeln!(script, "{} -d '{}'", line, flag.help.replace('\'', "\\'"))
+            .expect("writing to a string can't fail");
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that every script offers every option, and the words options take
+    #[test]
+    fn test_scripts() {
+        for shell in SHELLS {
+            let script = script(shell).unwrap();
+            for flag in FLAGS {
+                assert!(
+                    script.contains(flag.long),
+                    "{} lacks --{}",
+                    shell,
+                    flag.long
+                );
+            }
+            assert!(script.contains("radians degrees"), "{}", shell);
+            assert!(script.contains("completions"), "{}", shell);
+        }
+        assert!(script("powershell").is_none());
+
+        let bash = bash();
+        assert!(bash.contains("        --angle) COMPREPLY=($(compgen -W \"radians degrees\""));
+        assert!(bash.contains("        --config) COMPREPLY=($(compgen -f"));
+        assert!(bash.contains("        -e|--eval) COMPREPLY=(); return ;;"));
+        assert!(bash.ends_with("complete -F _ast ast\n"));
+
+        let zsh = zsh();
+        assert!(zsh.starts_with("#compdef ast\n"));
+        assert!(zsh.contains("'(-q --quiet)'{-q,--quiet}'[Print only results]' \\\n"));
+        assert!(zsh.contains("'--history[Append every input line to a file]:file:_files' \\\n"));
+        assert!(zsh.contains("[Print an expression'\\''s value and exit]"));
+
+        let fish = fish();
+        assert!(
+            fish.contains("complete -c ast -l theme -x -a 'dark light' -d 'The colors to use'\n")
+        );
+        assert!(fish.contains("complete -c ast -s D -l define -x -d 'Set a variable for -e'\n"));
+    }
+
+    /// Test that the scripts are valid for the shells that are installed
+    #[test]
+    fn test_syntax() {
+        for (shell, check) in [("bash", "-n"), ("zsh", "-n"), ("fish", "--no-execute")] {
+            let path = std::env::temp_dir().join(format!("ast-{}-{}", shell, std::process::id()));
+            std::fs::write(&path, script(shell).unwrap()).unwrap();
+            let status = std::process::Command::new(shell)
+                .arg(check)
+                .arg(&path)
+                .status();
+            std::fs::remove_file(&path).unwrap();
+            // A shell that isn't installed can't check anything
+            if let Ok(status) = status {
+                assert!(status.success(), "{} rejects its script", shell);
+            }
+        }
+    }
+}
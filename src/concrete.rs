@@ -0,0 +1,315 @@
+//! Parsing that keeps the formula's text, for tools that rewrite it
+//!
+//! An [`Expr`] drops everything that doesn't change the value: spacing, line
+//! breaks, redundant parentheses and comments. A formatter that saves a file
+//! of formulas, or a refactoring that renames a variable, wants to write back
+//! what the user wrote, changed only where it has to be. A [`SyntaxTree`]
+//! keeps the source alongside the tree, with the span of every node, and
+//! [`SyntaxTree::tokens`] splits it into tokens and the trivia between them.
+//!
+//! Comments start with `#` and run to the end of the line, as in startup
+//! files; the plain parser doesn't accept them.
+
+use std::fmt;
+
+use crate::{ChildIndex, Diagnostic, Expr, Span, identifier, parse_number, parse_with_spans};
+
+/// A parsed formula that writes back exactly as it was read
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, SyntaxTree};
+///
+/// let source = "(rate)  *  12   # monthly\n  + fee";
+/// let tree = SyntaxTree::parse(source).unwrap();
+/// assert_eq!(tree.to_string(), source);
+/// assert_eq!(tree.expr(), &parse_expression("rate * 12 + fee").unwrap().1);
+/// assert_eq!(tree.comments().collect::<Vec<_>>(), ["# monthly"]);
+///
+/// // Replacing a subtree leaves the rest of the text alone
+/// let tree = tree.replace(&[0, 0], "annual_rate / 12").unwrap();
+/// assert_eq!(tree.to_string(), "(annual_rate / 12)  *  12   # monthly\n  + fee");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    /// The text as it was given
+    source: String,
+    /// The abstract tree, as [`parse_expression`](crate::parse_expression) gives it
+    expr: Expr,
+    /// Where each node is in the source, in the order of [`Expr::subexpressions`]
+    spans: Vec<Span>,
+}
+
+/// What a [`Token`] is
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum TokenKind {
+    /// Spaces, tabs and line breaks
+    Whitespace,
+    /// A comment, from `#` up to the end of the line
+    Comment,
+    /// A number literal, including `nan` and `inf`
+    Number,
+    /// A variable, function or keyword (`let`, `in`, `piecewise`)
+    Name,
+    /// An operator, such as `+`, `<=` or `!`
+    Operator,
+    /// A parenthesis, bracket or comma
+    Punctuation,
+}
+
+impl TokenKind {
+    /// Whether the token is whitespace or a comment, which the tree leaves out
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment)
+    }
+}
+
+/// A piece of the source of a [`SyntaxTree`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Token {
+    /// What the piece is
+    pub kind: TokenKind,
+    /// Where it is in the source
+    pub span: Span,
+}
+
+/// Operators of two characters, which are tried before the single ones
+const PAIRS: &[&str] = &["==", "!=", "<=", ">=", "&&", "||", "|>"];
+
+impl SyntaxTree {
+    /// Parse a formula that may have comments, keeping its text
+    ///
+    /// The formula uses the default syntax of
+    /// [`parse_expression`](crate::parse_expression) and must be complete,
+    /// nested no deeper than [`ParserConfig::max_depth`](crate::ParserConfig::max_depth).
+    /// Problems are reported the way
+    /// [`parse_with_recovery`](crate::parse_with_recovery) reports them.
+    pub fn parse(source: &str) -> Result<SyntaxTree, Vec<Diagnostic>> {
+        // Blank out comments, keeping every other byte where it is
+        let mut masked = String::with_capacity(source.len());
+        for token in tokens(source) {
+            let text = &source[token.span.start..token.span.end];
+            match token.kind {
+                TokenKind::Comment => masked.extend(std::iter::repeat_n(' ', text.len())),
+                _ => masked.push_str(text),
+            }
+        }
+        let (expr, diagnostics, post_order) = parse_with_spans(&masked);
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        let mut spans = Vec::with_capacity(post_order.len());
+        pre_order(&expr, &mut post_order.into_iter(), &mut spans);
+        Ok(SyntaxTree {
+            source: source.to_string(),
+            expr,
+            spans,
+        })
+    }
+
+    /// The abstract tree
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// The text the tree was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Where the node at `path` is in the source, or `None` if there's no node there
+    ///
+    /// A node's span leaves out parentheses around it, so `(a + b) * c` has
+    /// `a + b` at `[0]`, from 1 to 6.
+    pub fn span(&self, path: &[ChildIndex]) -> Option<Span> {
+        let mut expr = &self.expr;
+        let mut index = 0;
+        for &step in path {
+            let children = expr.children();
+            let child = *children.get(step)?;
+            index += 1 + children[..step].iter().map(|c| c.size()).sum::<usize>();
+            expr = child;
+        }
+        self.spans.get(index).copied()
+    }
+
+    /// The source split into tokens, trivia included, which together are the whole of it
+    pub fn tokens(&self) -> Vec<Token> {
+        tokens(&self.source)
+    }
+
+    /// The text of each comment, `#` included
+    pub fn comments(&self) -> impl Iterator<Item = &str> {
+        self.tokens()
+            .into_iter()
+            .filter(|token| token.kind == TokenKind::Comment)
+            .map(|token| &self.source[token.span.start..token.span.end])
+    }
+
+    /// The tree with the node at `path` written as `text`, and the rest of the source unchanged
+    ///
+    /// If `text` would be grouped differently where it goes, as `x + 1`
+    /// would inside `a * b`, it's put in parentheses. Problems with `text`
+    /// are reported at their place in the new source.
+    ///
+    /// # Panics
+    ///
+    /// If there's no node at `path`.
+    pub fn replace(&self, path: &[ChildIndex], text: &str) -> Result<SyntaxTree, Vec<Diagnostic>> {
+        let span = self.span(path).expect("there's a node at the path");
+        let splice = |text: &str| {
+            let mut source = String::with_capacity(self.source.len() + text.len());
+            source.push_str(&self.source[..span.start]);
+            source.push_str(text);
+            source.push_str(&self.source[span.end..]);
+            SyntaxTree::parse(&source)
+        };
+        let replaced = splice(text)?;
+        let Ok(new) = SyntaxTree::parse(text) else {
+            return Ok(replaced);
+        };
+        let mut expected = self.expr.clone();
+        expected.replace_at(path, new.expr);
+        if replaced.expr == expected {
+            return Ok(replaced);
+        }
+        splice(&format!("({})", text))
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Put the spans of `expr`'s nodes, which `post_order` gives children first, in `spans` parents first
+fn pre_order(expr: &Expr, post_order: &mut impl Iterator<Item = Span>, spans: &mut Vec<Span>) {
+    // A node comes back with its index once its children are done, to take its span
+    let mut pending = vec![(expr, None)];
+    while let Some((expr, index)) = pending.pop() {
+        match index {
+            Some(index) => spans[index] = post_order.next().unwrap_or_default(),
+            None => {
+                pending.push((expr, Some(spans.len())));
+                spans.push(Span::default());
+                pending.extend(expr.children().into_iter().rev().map(|child| (child, None)));
+            }
+        }
+    }
+}
+
+/// Split `source` into tokens, with anything unknown as a one-character operator
+fn tokens(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        let (kind, length) = if c.is_whitespace() {
+            let end = rest.find(|c: char| !c.is_whitespace());
+            (TokenKind::Whitespace, end.unwrap_or(rest.len()))
+        } else if c == '#' {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if let Ok((after, _)) = identifier(rest) {
+            (TokenKind::Name, rest.len() - after.len())
+        } else if !matches!(c, '+' | '-')
+            && let Ok((after, _)) = parse_number(rest)
+        {
+            (TokenKind::Number, rest.len() - after.len())
+        } else if matches!(c, '(' | ')' | '[' | ']' | ',') {
+            (TokenKind::Punctuation, 1)
+        } else {
+            let pair = PAIRS.iter().find(|pair| rest.starts_with(**pair));
+            (
+                TokenKind::Operator,
+                pair.map_or(c.len_utf8(), |pair| pair.len()),
+            )
+        };
+        let start = source.len() - rest.len();
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, start + length),
+        });
+        rest = &rest[length..];
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+    use TokenKind::*;
+
+    /// Test that formulas write back exactly, and tokens cover the whole source
+    #[test]
+    fn test_round_trip() {
+        let source = "# total\nlet  t = ((a+b)) in\n\tpiecewise(( t>=1 ,t ), -t)  # clamp\n";
+        let tree = SyntaxTree::parse(source).unwrap();
+        assert_eq!(tree.to_string(), source);
+        let (_, plain) = parse_expression("let t = a + b in piecewise((t >= 1, t), -t)").unwrap();
+        assert_eq!(tree.expr(), &plain);
+
+        let tokens = tree.tokens();
+        let text: String = tokens
+            .iter()
+            .map(|token| &source[token.span.start..token.span.end])
+            .collect();
+        assert_eq!(text, source);
+        let kinds: Vec<_> = tokens
+            .iter()
+            .filter(|token| !token.kind.is_trivia())
+            .map(|token| token.kind)
+            .take(4)
+            .collect();
+        assert_eq!(kinds, [Name, Name, Operator, Punctuation]);
+        assert_eq!(tree.comments().count(), 2);
+
+        // The parentheses around `a+b` aren't part of its span
+        assert_eq!(tree.span(&[0]), Some(Span::new(19, 22)));
+        assert_eq!(tree.span(&[1, 2]), Some(Span::new(52, 54)));
+        assert_eq!(tree.span(&[2]), None);
+
+        assert!(SyntaxTree::parse("1 + # oops").is_err());
+        assert!(SyntaxTree::parse("1e3 >= .5 || !nan").is_ok());
+    }
+
+    /// Test that long chains parse, and nesting past the limit is a syntax error
+    #[test]
+    fn test_limits() {
+        let chain = format!("{}  # long", vec!["x"; 100_000].join(" +\n"));
+        let tree = SyntaxTree::parse(&chain).unwrap();
+        assert_eq!(tree.to_string(), chain);
+        assert_eq!(
+            tree.span(&[1]),
+            Some(Span::new(chain.len() - 9, chain.len() - 8))
+        );
+
+        for source in [
+            "(".repeat(100_000),
+            format!("{}1{}", "(".repeat(100), ")".repeat(100)),
+        ] {
+            let errors = SyntaxTree::parse(&source).unwrap_err();
+            assert_eq!(errors[0].code, Some(crate::codes::TOO_DEEP));
+        }
+    }
+
+    /// Test replacing subtrees, with parentheses only where the grouping needs them
+    #[test]
+    fn test_replace() {
+        let tree = SyntaxTree::parse("a  *  b  # area").unwrap();
+        let summed = tree.replace(&[1], "x + 1").unwrap();
+        assert_eq!(summed.to_string(), "a  *  (x + 1)  # area");
+        let scaled = tree.replace(&[0], "2*x").unwrap();
+        assert_eq!(scaled.to_string(), "2*x  *  b  # area");
+        let scaled = tree.replace(&[1], "2*x").unwrap();
+        assert_eq!(scaled.to_string(), "a  *  (2*x)  # area");
+        assert_eq!(
+            tree.replace(&[], "c - d").unwrap().to_string(),
+            "c - d  # area"
+        );
+
+        // In `f(  *  b  # area`, the first problem is the missing operand before `*`
+        let errors = tree.replace(&[0], "f(").unwrap_err();
+        assert_eq!(errors[0].span.start, 4);
+    }
+}
@@ -0,0 +1,358 @@
+//! Parser and evaluator configuration
+//!
+//! The default configuration parses the plain calculator grammar. Optional
+//! dialects and extensions are switched on through [`ParserConfig`] and passed
+//! to [`parse_expression_with`](crate::parse_expression_with). Likewise,
+//! [`EvalConfig`] changes how an [`Evaluator`](crate::Evaluator) evaluates.
+
+use crate::{Dialect, NumberFormat};
+
+/// Options that change what the parser accepts
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression_with, Expr, ParserConfig};
+///
+/// let config = ParserConfig { cell_references: true, ..ParserConfig::default() };
+/// let (_, ast) = parse_expression_with("A1 * 2", &config).unwrap();
+/// assert!(matches!(&ast, Expr::Mul(left, _) if matches!(**left, Expr::CellRef(_))));
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParserConfig {
+    /// Parse spreadsheet cell references such as `A1` and ranges such as
+    /// `B2:B10` into [`Expr::CellRef`](crate::Expr::CellRef) and
+    /// [`Expr::CellRange`](crate::Expr::CellRange) instead of variables
+    pub cell_references: bool,
+
+    /// Operators and names from another engine's syntax, see [`Dialect`]
+    pub dialect: Dialect,
+
+    /// Accept number literals that end in an SI prefix, such as `4.7k` for
+    /// 4700 or `100n` for 100 × 10^-9
+    ///
+    /// The prefixes are `y z a f p n u µ m k M G T P Z Y`; exa is left out,
+    /// because `1E3` is an exponent.
+    pub si_suffixes: bool,
+
+    /// Accept the number literals `nan`, `inf` and `infinity` (in any case),
+    /// which is the default
+    ///
+    /// Without them, formulas can only get a NaN or an infinity by
+    /// calculating one, such as `1e308 * 10`. The words are never names,
+    /// either way, so switching this off makes them syntax errors rather
+    /// than variables. NaN is unequal to everything, itself included, so
+    /// `nan == nan` is 0 and `nan != nan` is 1; results print as `NaN`,
+    /// `inf` and `-inf`, which parse back to the same values.
+    pub nonfinite_literals: bool,
+
+    /// Accept measurements with an uncertainty, such as `5.0 ± 0.1` or
+    /// `5.0 +/- 0.1`, between two number literals
+    ///
+    /// A measurement parses to a call to `uncertain(value, error)`, which
+    /// [`evaluate_uncertain`](crate::evaluate_uncertain) propagates through
+    /// the formula; other evaluators don't know the function.
+    pub uncertainties: bool,
+
+    /// Which version of the grammar to accept, the latest by default
+    ///
+    /// Formulas stored under an older version's rules keep parsing the same
+    /// way when it's pinned, since anything newer is a syntax error.
+    pub grammar: GrammarVersion,
+
+    /// How deeply parentheses, negations, calls, lists and `let`s may nest,
+    /// 64 by default
+    ///
+    /// Nesting is parsed by recursion, so a formula nested past the limit is
+    /// a syntax error at the factor that goes too deep, rather than a stack
+    /// overflow. The default leaves room on a 2 MiB thread in a debug build;
+    /// raise it on a bigger stack.
+    pub max_depth: usize,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            cell_references: false,
+            dialect: Dialect::default(),
+            si_suffixes: false,
+            nonfinite_literals: true,
+            uncertainties: false,
+            grammar: GrammarVersion::default(),
+            max_depth: 64,
+        }
+    }
+}
+
+impl ParserConfig {
+    /// The spreadsheet formula dialect, with cell references enabled
+    pub fn spreadsheet() -> Self {
+        ParserConfig {
+            cell_references: true,
+            ..ParserConfig::default()
+        }
+    }
+}
+
+/// A version of the grammar, each one accepting everything the one before does
+///
+/// Versions are ordered, so `grammar >= GrammarVersion::V2` asks whether
+/// function calls are allowed. Optional extensions, such as
+/// [`ParserConfig::cell_references`], are switched on separately and work
+/// with any version.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression_with, GrammarVersion, ParserConfig};
+///
+/// let v1 = ParserConfig { grammar: GrammarVersion::V1, ..ParserConfig::default() };
+/// assert!(parse_expression_with("(a + b) * -2", &v1).is_ok());
+/// assert!(parse_expression_with("sqrt(a)", &v1).is_err());
+///
+/// // Operators from a later version are left unparsed
+/// let (remaining, _) = parse_expression_with("a < b", &v1).unwrap();
+/// assert_eq!(remaining, " < b");
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub enum GrammarVersion {
+    /// The four arithmetic operators, negation, parentheses, numbers and variables
+    V1,
+    /// Adds function calls, with powers written `pow(x, y)` (or in a
+    /// dialect's power operator, such as Excel's `^`)
+    V2,
+    /// Adds comparisons, `&&`, `||` and `!`, `let`, `piecewise`, and lists
+    /// with indexing
+    #[default]
+    V3,
+}
+
+impl GrammarVersion {
+    /// The newest version, which is the default
+    pub const LATEST: GrammarVersion = GrammarVersion::V3;
+}
+
+/// Options that change how expressions are evaluated
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, Environment, EvalConfig, Evaluator};
+///
+/// let env: Environment = [("x", 0.0)].into_iter().collect();
+/// let (_, ast) = parse_expression("x != 0 && 1 / x > 2").unwrap();
+///
+/// // By default the right side is skipped, because the left side is false
+/// assert_eq!(Evaluator::new(&env).evaluate(&ast).unwrap(), 0.0);
+///
+/// // In strict mode both sides are evaluated, so the division fails
+/// let strict = EvalConfig { strict_evaluation: true, ..EvalConfig::default() };
+/// assert!(Evaluator::new(&env).with_config(strict).evaluate(&ast).is_err());
+/// ```
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct EvalConfig {
+    /// Evaluate both operands of `&&` and `||` even when the left one already
+    /// decides the result, so that errors on the right are always reported
+    pub strict_evaluation: bool,
+
+    /// What happens to missing inputs, such as undefined variables and empty cells
+    pub nulls: NullPolicy,
+
+    /// Add up chains of `+` and `-` with compensated (Kahan-Babuska) summation
+    ///
+    /// The rounding error of a plain sum grows with its length; with
+    /// compensation it stays about as small as a single addition's, so
+    /// `0.1 + 0.1 + ... + 0.1` (ten times) is exactly 1. Only the final sum
+    /// of a chain is checked against [`EvalConfig::max_magnitude`].
+    pub compensated_sums: bool,
+
+    /// The largest magnitude a number may reach, or `None` for no limit
+    ///
+    /// Every intermediate result is checked, so an overflow is caught where
+    /// it happens rather than turning into infinity further along.
+    pub max_magnitude: Option<f64>,
+
+    /// What happens to a number beyond [`EvalConfig::max_magnitude`]
+    pub overflow: OverflowPolicy,
+
+    /// What happens to subnormal numbers, which are too close to zero to keep full precision
+    pub subnormals: SubnormalPolicy,
+
+    /// What dividing a number by zero gives
+    pub division_by_zero: DivisionPolicy,
+
+    /// What a math function gives for an argument outside its domain, such as `sqrt(-1)`
+    pub domain_errors: DomainPolicy,
+
+    /// How [`Evaluator::format`](crate::Evaluator::format) writes results
+    pub format: NumberFormat,
+
+    /// How deeply calls to functions defined in a [`Session`](crate::Session) may nest
+    ///
+    /// A recursive function that never stops would otherwise overflow the
+    /// stack; going deeper is an [`EvaluationError::RecursionLimit`](crate::EvaluationError::RecursionLimit).
+    pub max_call_depth: usize,
+
+    /// Remember what calls to functions defined in a [`Session`](crate::Session) give,
+    /// for as long as one evaluation lasts
+    ///
+    /// A call with the same arguments as an earlier one then gives the same
+    /// result without evaluating the body again, so a recursive definition
+    /// such as `fib(n) = if(n < 2, n, fib(n - 1) + fib(n - 2))` takes a
+    /// number of calls linear in `n` rather than exponential. Functions that
+    /// use the clock or random numbers, even through other functions, are
+    /// never remembered.
+    pub memoize_calls: bool,
+}
+
+impl Default for EvalConfig {
+    fn default() -> Self {
+        EvalConfig {
+            strict_evaluation: false,
+            nulls: NullPolicy::default(),
+            compensated_sums: false,
+            max_magnitude: None,
+            overflow: OverflowPolicy::default(),
+            subnormals: SubnormalPolicy::default(),
+            division_by_zero: DivisionPolicy::default(),
+            domain_errors: DomainPolicy::default(),
+            format: NumberFormat::default(),
+            max_call_depth: 100,
+            memoize_calls: true,
+        }
+    }
+}
+
+/// How missing inputs and [`Value::Null`](crate::Value::Null) are handled
+///
+/// Whatever the policy, `coalesce(a, b, ...)` gives its first argument that
+/// isn't missing or null.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, Environment, EvalConfig, Evaluator, NullPolicy, Value};
+///
+/// let env: Environment = [("price", 10.0)].into_iter().collect();
+/// let (_, ast) = parse_expression("price * quantity").unwrap();
+/// let evaluate = |nulls| {
+///     let config = EvalConfig { nulls, ..EvalConfig::default() };
+///     Evaluator::new(&env).with_config(config).evaluate_value(&ast)
+/// };
+///
+/// assert!(evaluate(NullPolicy::Error).is_err());
+/// assert_eq!(evaluate(NullPolicy::Poison).unwrap(), Value::Null);
+/// assert_eq!(evaluate(NullPolicy::Coalesce).unwrap(), Value::Number(0.0));
+///
+/// let (_, ast) = parse_expression("price * coalesce(quantity, 1)").unwrap();
+/// assert_eq!(Evaluator::new(&env).evaluate(&ast).unwrap(), 10.0);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum NullPolicy {
+    /// Missing inputs are errors
+    #[default]
+    Error,
+    /// Missing inputs are null, and any operation on a null gives null, as in SQL
+    Poison,
+    /// Missing inputs are null, and nulls count as 0, like blank spreadsheet cells
+    Coalesce,
+}
+
+/// How numbers beyond [`EvalConfig::max_magnitude`] are handled
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, Environment, EvalConfig, Evaluator, OverflowPolicy};
+///
+/// let env = Environment::new();
+/// let (_, ast) = parse_expression("1e200 * 1e200 - 1").unwrap();
+/// let evaluate = |overflow| {
+///     let config = EvalConfig { max_magnitude: Some(1e300), overflow, ..EvalConfig::default() };
+///     Evaluator::new(&env).with_config(config).evaluate(&ast)
+/// };
+///
+/// assert!(evaluate(OverflowPolicy::Error).is_err());
+/// assert_eq!(evaluate(OverflowPolicy::Clamp).unwrap(), 1e300 - 1.0);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OverflowPolicy {
+    /// Numbers beyond the limit are errors
+    #[default]
+    Error,
+    /// Numbers beyond the limit are replaced by the limit, keeping their sign
+    Clamp,
+}
+
+/// What dividing a number by zero gives
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, DivisionPolicy, Environment, EvalConfig, Evaluator};
+///
+/// let env = Environment::new();
+/// let evaluate = |input, division_by_zero| {
+///     let (_, ast) = parse_expression(input).unwrap();
+///     let config = EvalConfig { division_by_zero, ..EvalConfig::default() };
+///     Evaluator::new(&env).with_config(config).evaluate(&ast)
+/// };
+///
+/// assert!(evaluate("1 / 0", DivisionPolicy::Error).is_err());
+/// assert_eq!(evaluate("1 / 0", DivisionPolicy::Ieee).unwrap(), f64::INFINITY);
+/// assert_eq!(evaluate("-1 / 0", DivisionPolicy::Ieee).unwrap(), f64::NEG_INFINITY);
+/// assert!(evaluate("0 / 0", DivisionPolicy::Ieee).unwrap().is_nan());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DivisionPolicy {
+    /// Division by zero is an [`EvaluationError::DivisionByZero`](crate::EvaluationError::DivisionByZero)
+    #[default]
+    Error,
+    /// Division by zero follows IEEE 754: `x / 0` is an infinity with the
+    /// sign of `x` times the sign of the zero, and `0 / 0` is NaN
+    ///
+    /// This applies to the `/` operator on numbers and on a matrix divided
+    /// by a number. An infinite result is still checked against
+    /// [`EvalConfig::max_magnitude`].
+    Ieee,
+}
+
+/// What a math function gives for an argument outside its domain
+///
+/// This covers the functions that fail with
+/// [`EvaluationError::OutOfDomain`](crate::EvaluationError::OutOfDomain),
+/// such as `sqrt`, `ln` and `asin`. Other domain errors, like the mean of an
+/// empty list, are always errors.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, DomainPolicy, Environment, EvalConfig, Evaluator};
+///
+/// let env = Environment::new();
+/// let (_, ast) = parse_expression("1 + sqrt(-4)").unwrap();
+/// let evaluate = |domain_errors| {
+///     let config = EvalConfig { domain_errors, ..EvalConfig::default() };
+///     Evaluator::new(&env).with_config(config).evaluate(&ast)
+/// };
+///
+/// let error = evaluate(DomainPolicy::Error).unwrap_err();
+/// assert_eq!(error.to_string(), "Argument -4 out of range for 'sqrt'");
+/// assert!(evaluate(DomainPolicy::Nan).unwrap().is_nan());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DomainPolicy {
+    /// An argument outside the domain is an error that says what the argument was
+    #[default]
+    Error,
+    /// An argument outside the domain gives NaN, as the IEEE 754 functions do
+    Nan,
+}
+
+/// How subnormal numbers are handled
+///
+/// Subnormal numbers (smaller in magnitude than `f64::MIN_POSITIVE`, but not
+/// zero) lose precision, and are slow to compute with on some hardware.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SubnormalPolicy {
+    /// Subnormal numbers are used as they are
+    #[default]
+    Keep,
+    /// Subnormal numbers are replaced by zero, keeping their sign
+    FlushToZero,
+    /// Subnormal numbers are errors
+    Error,
+}
@@ -0,0 +1,457 @@
+//! Evaluating fixed formulas in `const` contexts
+//!
+//! The evaluator works on boxed trees and allocates as it goes, which a
+//! `const fn` can't. A [`ConstExpr`] is a formula flattened into a slice of
+//! [`ConstOp`]s for a small stack machine that can run at compile time, so
+//! a fixed formula can define a `const`. [`Expr::to_const_ops`] flattens a
+//! tree, and the `const_expr!` macro in the `ast-macros` crate does it while
+//! the program is built.
+//!
+//! Only what can be computed in a `const fn` is supported: arithmetic,
+//! comparisons, the logical operators, `let`, `piecewise`, and the `abs`,
+//! `min` and `max` functions.
+
+use thiserror::Error;
+
+use crate::{CodegenError, Comparison, Expr, functions::STANDARD};
+
+/// The most values a [`ConstExpr`] can have on its stack at once
+///
+/// Each operand waiting for its operator takes one, so this limits how deeply
+/// right operands can nest; chains like `1 + 2 + … + 100` need only two.
+pub const CONST_STACK_SIZE: usize = 64;
+
+/// One step of a [`ConstExpr`], taking its operands off the stack and pushing its result
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConstOp {
+    /// Push a number
+    Number(f64),
+    /// Push the variable with this index in the values given to [`ConstExpr::evaluate`]
+    Variable(usize),
+    /// Add the top two values
+    Add,
+    /// Subtract the top value from the one below it
+    Sub,
+    /// Multiply the top two values
+    Mul,
+    /// Divide the value below the top by the top one, failing if it's zero
+    Div,
+    /// Negate the top value
+    Neg,
+    /// Compare the value below the top with the top one, giving 1 or 0
+    Compare(Comparison),
+    /// Replace the top value with 1 if it's 0, and with 0 otherwise
+    Not,
+    /// Replace the top value with 0 if it's 0, and with 1 otherwise
+    Truth,
+    /// Replace the top value with its absolute value
+    Abs,
+    /// Replace the top two values with the smaller, ignoring NaN
+    Min,
+    /// Replace the top two values with the larger, ignoring NaN
+    Max,
+    /// Go on at this step
+    Jump(usize),
+    /// Take the top value off, going on at this step if it's 0
+    JumpIfZero(usize),
+    /// Take the top value off, going on at this step if it isn't 0
+    JumpIfNonzero(usize),
+    /// Fail, because no case of a piecewise definition holds
+    NoCase,
+}
+
+/// Errors from evaluating a [`ConstExpr`]
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConstError {
+    #[error("Division by zero")]
+    DivisionByZero,
+
+    /// Fewer values were given than the expression has variables
+    #[error("No value given for variable {0}")]
+    MissingVariable(usize),
+
+    /// No case of a piecewise definition holds, and it has no default
+    #[error("Argument out of range for 'piecewise'")]
+    NoCase,
+
+    /// The expression needs more than [`CONST_STACK_SIZE`] values on the stack
+    #[error("Expression is nested too deeply")]
+    StackOverflow,
+
+    /// The steps don't make a complete expression, such as an operator
+    /// without operands or a jump to nowhere
+    #[error("Malformed expression")]
+    Malformed,
+}
+
+/// A formula that can be evaluated in a `const fn`
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, ConstExpr, ConstOp};
+///
+/// // (x + 1) * 2, with x as the first variable
+/// const DOUBLED: ConstExpr = ConstExpr::new(&[
+///     ConstOp::Variable(0),
+///     ConstOp::Number(1.0),
+///     ConstOp::Add,
+///     ConstOp::Number(2.0),
+///     ConstOp::Mul,
+/// ]);
+/// const EIGHT: f64 = DOUBLED.value(&[3.0]);
+/// assert_eq!(EIGHT, 8.0);
+///
+/// let (_, ast) = parse_expression("(x + 1) * 2").unwrap();
+/// assert_eq!(ast.to_const_ops(&["x"]).unwrap(), DOUBLED.ops());
+/// ```
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ConstExpr<'a> {
+    /// The steps, in order
+    ops: &'a [ConstOp],
+}
+
+impl<'a> ConstExpr<'a> {
+    /// An expression made of these steps
+    pub const fn new(ops: &'a [ConstOp]) -> Self {
+        ConstExpr { ops }
+    }
+
+    /// The steps
+    pub const fn ops(&self) -> &'a [ConstOp] {
+        self.ops
+    }
+
+    /// Evaluate the expression, with `variables` as the values of its variables by index
+    ///
+    /// Results are the same as from the [`Evaluator`](crate::Evaluator) with
+    /// the default [`EvalConfig`](crate::EvalConfig).
+    pub const fn evaluate(&self, variables: &[f64]) -> Result<f64, ConstError> {
+        let mut stack = [0.0; CONST_STACK_SIZE];
+        let mut len = 0;
+        let mut step = 0;
+        while step < self.ops.len() {
+            let op = self.ops[step];
+            step += 1;
+            // How many operands the step takes
+            let operands = match op {
+                ConstOp::Number(_) | ConstOp::Variable(_) | ConstOp::Jump(_) | ConstOp::NoCase => 0,
+                ConstOp::Neg
+                | ConstOp::Not
+                | ConstOp::Truth
+                | ConstOp::Abs
+                | ConstOp::JumpIfZero(_)
+                | ConstOp::JumpIfNonzero(_) => 1,
+                _ => 2,
+            };
+            if len < operands {
+                return Err(ConstError::Malformed);
+            }
+            let (left, right) = match operands {
+                2 => (stack[len - 2], stack[len - 1]),
+                1 => (stack[len - 1], 0.0),
+                _ => (0.0, 0.0),
+            };
+            len -= operands;
+            let result = match op {
+                ConstOp::Number(value) => value,
+                ConstOp::Variable(index) => {
+                    if index >= variables.len() {
+                        return Err(ConstError::MissingVariable(index));
+                    }
+                    variables[index]
+                }
+                ConstOp::Add => left + right,
+                ConstOp::Sub => left - right,
+                ConstOp::Mul => left * right,
+                ConstOp::Div => {
+                    if right == 0.0 {
+                        return Err(ConstError::DivisionByZero);
+                    }
+                    left / right
+                }
+                ConstOp::Neg => -left,
+                ConstOp::Compare(comparison) => {
+                    let holds = match comparison {
+                        Comparison::Equal => left == right,
+                        Comparison::NotEqual => left != right,
+                        Comparison::Less => left < right,
+                        Comparison::LessOrEqual => left <= right,
+                        Comparison::Greater => left > right,
+                        Comparison::GreaterOrEqual => left >= right,
+                    };
+                    if holds { 1.0 } else { 0.0 }
+                }
+                ConstOp::Not => {
+                    if left == 0.0 {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                ConstOp::Truth => {
+                    if left == 0.0 {
+                        0.0
+                    } else {
+                        1.0
+                    }
+                }
+                ConstOp::Abs => left.abs(),
+                ConstOp::Min => left.min(right),
+                ConstOp::Max => left.max(right),
+                ConstOp::Jump(target) => {
+                    step = target;
+                    continue;
+                }
+                ConstOp::JumpIfZero(target) => {
+                    if left == 0.0 {
+                        step = target;
+                    }
+                    continue;
+                }
+                ConstOp::JumpIfNonzero(target) => {
+                    if left != 0.0 {
+                        step = target;
+                    }
+                    continue;
+                }
+                ConstOp::NoCase => return Err(ConstError::NoCase),
+            };
+            if len == CONST_STACK_SIZE {
+                return Err(ConstError::StackOverflow);
+            }
+            stack[len] = result;
+            len += 1;
+        }
+        if len == 1 && step == self.ops.len() {
+            Ok(stack[0])
+        } else {
+            Err(ConstError::Malformed)
+        }
+    }
+
+    /// Evaluate the expression, panicking if it fails
+    ///
+    /// In a `const`, the panic is a compile error.
+    pub const fn value(&self, variables: &[f64]) -> f64 {
+        match self.evaluate(variables) {
+            Ok(value) => value,
+            Err(ConstError::DivisionByZero) => panic!("division by zero"),
+            Err(ConstError::MissingVariable(_)) => panic!("no value given for a variable"),
+            Err(ConstError::NoCase) => panic!("no case of a piecewise definition holds"),
+            Err(ConstError::StackOverflow) => panic!("expression is nested too deeply"),
+            Err(ConstError::Malformed) => panic!("malformed expression"),
+        }
+    }
+}
+
+impl Expr {
+    /// Flatten this expression into steps for a [`ConstExpr`]
+    ///
+    /// Each variable becomes its index in `vars`, the order its value is
+    /// given in. `let`s are written out, so a value used several times is
+    /// computed each time.
+    ///
+    /// Like the other code generators, this recurses down the tree, so it
+    /// suits formulas written by hand rather than generated chains thousands
+    /// of operators long.
+    pub fn to_const_ops(&self, vars: &[&str]) -> Result<Vec<ConstOp>, CodegenError> {
+        let mut ops = Vec::new();
+        flatten(&self.inline_lets(), vars, &mut ops)?;
+        Ok(ops)
+    }
+}
+
+/// Add the steps for `expr` to `ops`
+fn flatten(expr: &Expr, vars: &[&str], ops: &mut Vec<ConstOp>) -> Result<(), CodegenError> {
+    let binary = |left: &Expr, right: &Expr, op, ops: &mut Vec<ConstOp>| {
+        flatten(left, vars, ops)?;
+        flatten(right, vars, ops)?;
+        ops.push(op);
+        Ok(())
+    };
+    match expr {
+        Expr::Float(value) => ops.push(ConstOp::Number(*value)),
+        Expr::Var(name) => {
+            let index = vars
+                .iter()
+                .position(|var| var == name)
+                .ok_or_else(|| CodegenError::UnknownVariable(name.clone()))?;
+            ops.push(ConstOp::Variable(index));
+        }
+        Expr::CellRef(_) | Expr::CellRange(..) => return Err(CodegenError::CellReference),
+        Expr::List(_) | Expr::Index(..) => return Err(CodegenError::Matrix),
+        Expr::Error(_) => return Err(CodegenError::ContainsErrors),
+        Expr::Add(left, right) => binary(left, right, ConstOp::Add, ops)?,
+        Expr::Sub(left, right) => binary(left, right, ConstOp::Sub, ops)?,
+        Expr::Mul(left, right) => binary(left, right, ConstOp::Mul, ops)?,
+        Expr::Div(left, right) => binary(left, right, ConstOp::Div, ops)?,
+        Expr::Compare(comparison, left, right) => {
+            binary(left, right, ConstOp::Compare(*comparison), ops)?
+        }
+        Expr::Neg(inner) | Expr::Not(inner) => {
+            flatten(inner, vars, ops)?;
+            ops.push(match expr {
+                Expr::Neg(_) => ConstOp::Neg,
+                _ => ConstOp::Not,
+            });
+        }
+        // left, then if it decides the result, that; otherwise the truth of right
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            let and = matches!(expr, Expr::And(..));
+            flatten(left, vars, ops)?;
+            let decided = ops.len();
+            ops.push(ConstOp::Jump(0));
+            flatten(right, vars, ops)?;
+            ops.push(ConstOp::Truth);
+            let end = ops.len();
+            ops.push(ConstOp::Jump(0));
+            ops[decided] = if and {
+                ConstOp::JumpIfZero(ops.len())
+            } else {
+                ConstOp::JumpIfNonzero(ops.len())
+            };
+            ops.push(ConstOp::Number(if and { 0.0 } else { 1.0 }));
+            ops[end] = ConstOp::Jump(ops.len());
+        }
+        Expr::Piecewise(cases, default) => {
+            let mut ends = Vec::new();
+            for (condition, value) in cases {
+                flatten(condition, vars, ops)?;
+                let next = ops.len();
+                ops.push(ConstOp::Jump(0));
+                flatten(value, vars, ops)?;
+                ends.push(ops.len());
+                ops.push(ConstOp::Jump(0));
+                ops[next] = ConstOp::JumpIfZero(ops.len());
+            }
+            match default {
+                Some(default) => flatten(default, vars, ops)?,
+                None => ops.push(ConstOp::NoCase),
+            }
+            for end in ends {
+                ops[end] = ConstOp::Jump(ops.len());
+            }
+        }
+        Expr::Call(name, args) => {
+            let (op, start) = match name.as_str() {
+                "abs" => (ConstOp::Abs, None),
+                "min" => (ConstOp::Min, Some(f64::INFINITY)),
+                "max" => (ConstOp::Max, Some(f64::NEG_INFINITY)),
+                _ => {
+                    return Err(CodegenError::UnsupportedFunction {
+                        name: name.clone(),
+                        target: "const",
+                    });
+                }
+            };
+            let function = STANDARD.get(name).expect("abs, min and max are standard");
+            let args = function
+                .arrange(name, args)
+                .map_err(|error| CodegenError::Arguments(error.to_string()))?;
+            let arity = function.arity();
+            if !arity.accepts(args.len()) {
+                return Err(CodegenError::WrongArgumentCount {
+                    signature: function.signature(name),
+                    expected: arity,
+                    found: args.len(),
+                });
+            }
+            // min and max fold their arguments from an infinity, as the standard ones do
+            if let Some(start) = start {
+                ops.push(ConstOp::Number(start));
+            }
+            for arg in args {
+                flatten(&arg, vars, ops)?;
+                if start.is_some() {
+                    ops.push(op);
+                }
+            }
+            if start.is_none() {
+                ops.push(op);
+            }
+        }
+        Expr::Named(_, value) => flatten(value, vars, ops)?,
+        Expr::Let(..) => unreachable!("lets are inlined first"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Evaluator, parse_expression};
+
+    /// Test that flattened expressions give what the evaluator does
+    #[test]
+    fn test_matches_evaluator() {
+        let inputs = [
+            "1 + 2 * x - y / 4",
+            "-(x - 10) * -y",
+            "x < y && y < 10 || !x",
+            "x && 0",
+            "x || y == 3",
+            "piecewise((x > 5, 1), (y > 5, 2), 3) * 10",
+            "piecewise((x < 0, -x), x)",
+            "let t = x + y in t * t",
+            "abs(x - y) + min(x, y, 0) + max(y)",
+            "max(0 / 1 - 1, x)",
+        ];
+        let env: Environment = [("x", 2.0), ("y", 8.0)].into_iter().collect();
+        for input in inputs {
+            let (_, ast) = parse_expression(input).unwrap();
+            let ops = ast.to_const_ops(&["x", "y"]).unwrap();
+            let expected = Evaluator::new(&env).evaluate(&ast).unwrap();
+            assert_eq!(
+                ConstExpr::new(&ops).evaluate(&[2.0, 8.0]),
+                Ok(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    /// Test evaluation errors and what can't be flattened
+    #[test]
+    fn test_errors() {
+        let run = |input: &str, values: &[f64]| {
+            let (_, ast) = parse_expression(input).unwrap();
+            ConstExpr::new(&ast.to_const_ops(&["x"]).unwrap()).evaluate(values)
+        };
+        assert_eq!(run("1 / (x - 1)", &[1.0]), Err(ConstError::DivisionByZero));
+        assert_eq!(run("x + 1", &[]), Err(ConstError::MissingVariable(0)));
+        assert_eq!(run("piecewise((x, 1))", &[0.0]), Err(ConstError::NoCase));
+        assert_eq!(run(&vec!["1"; 200].join(" + "), &[]), Ok(200.0));
+        let depth = CONST_STACK_SIZE;
+        let nested = format!("{}1{}", "1 + (".repeat(depth), ")".repeat(depth));
+        assert_eq!(run(&nested, &[]), Err(ConstError::StackOverflow));
+
+        assert_eq!(
+            ConstExpr::new(&[ConstOp::Add]).evaluate(&[]),
+            Err(ConstError::Malformed)
+        );
+        let unfinished = [ConstOp::Number(1.0), ConstOp::Number(2.0)];
+        assert_eq!(
+            ConstExpr::new(&unfinished).evaluate(&[]),
+            Err(ConstError::Malformed)
+        );
+        let beyond = [ConstOp::Number(1.0), ConstOp::Jump(5)];
+        assert_eq!(
+            ConstExpr::new(&beyond).evaluate(&[]),
+            Err(ConstError::Malformed)
+        );
+
+        let (_, ast) = parse_expression("sqrt(x) + z").unwrap();
+        assert!(matches!(
+            ast.to_const_ops(&["x"]),
+            Err(CodegenError::UnsupportedFunction {
+                target: "const",
+                ..
+            })
+        ));
+        let (_, ast) = parse_expression("x + z").unwrap();
+        assert_eq!(
+            ast.to_const_ops(&["x"]),
+            Err(CodegenError::UnknownVariable("z".to_string()))
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Random expressions for fuzzing and test corpora
+//!
+//! A [`Corpus`] writes out an endless stream of random, valid expressions
+//! in the default grammar, from the same generator that
+//! [`compare_backends`](crate::compare_backends) tests with. The stream only
+//! depends on the seed, so a corpus can be rebuilt exactly. `ast gen` prints
+//! one from the command line.
+
+use crate::{Environment, Expr, differential::Random, differential::VARIABLES, explain::grouped};
+
+/// Random expressions, written out as input for the parser
+///
+/// Expressions use the operators, the numbers `0`, `0.25`, `0.5`, `1`, `2`,
+/// `3`, `7` and `10`, a handful of standard functions, and the variables
+/// `x`, `y` and `z`, which [`Corpus::environment`] gives values to. Each one
+/// parses back to the tree it was written from.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, evaluate_with, Corpus};
+///
+/// let env = Corpus::environment();
+/// for text in Corpus::new(42, 4).take(100) {
+///     let (rest, ast) = parse_expression(&text).unwrap();
+///     assert!(rest.is_empty());
+///     let _ = evaluate_with(&ast, &env); // a value, or an error like division by zero
+/// }
+/// assert_eq!(Corpus::new(7, 8).nth(3), Corpus::new(7, 8).nth(3));
+/// ```
+pub struct Corpus {
+    /// Where the expressions come from
+    random: Random,
+    /// How deeply operations may be nested
+    max_depth: usize,
+}
+
+impl Corpus {
+    /// The expressions for `seed`, with operations nested at most `max_depth` deep
+    pub fn new(seed: u64, max_depth: usize) -> Self {
+        Corpus {
+            random: Random(seed),
+            max_depth,
+        }
+    }
+
+    /// The variables the expressions use: x = 1.5, y = -2 and z = 0
+    pub fn environment() -> Environment {
+        VARIABLES.into_iter().collect()
+    }
+
+    /// The next expression as a tree
+    pub(crate) fn next_expr(&mut self) -> Expr {
+        self.random.expr(self.max_depth)
+    }
+}
+
+impl Iterator for Corpus {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        Some(grouped(&self.next_expr()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Test that every expression parses back to the tree it was written from
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..5 {
+            let mut corpus = Corpus::new(seed, 8);
+            for _ in 0..200 {
+                let expr = corpus.next_expr();
+                let text = grouped(&expr);
+                let (rest, parsed) = parse_expression(&text).unwrap();
+                assert!(rest.is_empty(), "'{}' not fully parsed", text);
+                assert_eq!(parsed, expr, "'{}'", text);
+            }
+        }
+        assert_eq!(Corpus::new(1, 0).next(), Corpus::new(1, 0).next());
+    }
+}
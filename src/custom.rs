@@ -0,0 +1,196 @@
+//! Values of kinds the application defines
+//!
+//! Besides numbers, dates, durations and matrices, an expression can work
+//! with values of any type that implements [`CustomValue`], such as a
+//! temperature or a sum of money in a currency. They're made by functions
+//! the application registers with [`FunctionRegistry::register_value`](crate::FunctionRegistry::register_value),
+//! and the trait says which operators they support.
+
+use std::{any::Any, cmp::Ordering, fmt};
+
+use crate::{EvaluationError, Value};
+
+/// A kind of value defined by the application, held in [`Value::Custom`]
+///
+/// Only [`CustomValue::type_name`] has to be written; operators a type
+/// doesn't support are [`EvaluationError::UnsupportedOperands`] errors.
+/// When only one operand of `+`, `-`, `*` or `/` is custom, its
+/// [`CustomValue::binary`] is asked, with `swapped` saying whether it's
+/// on the right. When both are, the left one is asked first, then the
+/// right one. [`Value::downcast_ref`] gets the concrete type back.
+///
+/// # Example
+/// ```
+/// use std::fmt;
+/// use ast::{
+///     parse_expression, CustomValue, Environment, EvaluationError, Evaluator, FunctionRegistry,
+///     Value,
+/// };
+///
+/// #[derive(Debug)]
+/// struct Celsius(f64);
+///
+/// impl fmt::Display for Celsius {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         write!(f, "{}°C", self.0)
+///     }
+/// }
+///
+/// impl CustomValue for Celsius {
+///     fn type_name(&self) -> &'static str {
+///         "a temperature"
+///     }
+///
+///     fn binary(
+///         &self,
+///         op: &'static str,
+///         other: &Value,
+///         _swapped: bool,
+///     ) -> Option<Result<Value, EvaluationError>> {
+///         // The difference between two temperatures is a number of degrees
+///         let other = other.downcast_ref::<Celsius>()?;
+///         match op {
+///             "-" => Some(Ok(Value::Number(self.0 - other.0))),
+///             _ => None,
+///         }
+///     }
+///
+///     fn compare(&self, other: &Value) -> Option<std::cmp::Ordering> {
+///         self.0.partial_cmp(&other.downcast_ref::<Celsius>()?.0)
+///     }
+/// }
+///
+/// let mut functions = FunctionRegistry::standard();
+/// functions.register_value("celsius", 1, |args| {
+///     Ok(Value::custom(Celsius(args[0].clone().into_number()?)))
+/// });
+/// let env = Environment::new();
+/// let evaluator = Evaluator::new(&env).with_functions(&functions);
+///
+/// let (_, ast) = parse_expression("celsius(25) - celsius(18.5)").unwrap();
+/// assert_eq!(evaluator.evaluate(&ast).unwrap(), 6.5);
+/// let (_, ast) = parse_expression("celsius(25) > celsius(30)").unwrap();
+/// assert_eq!(evaluator.evaluate(&ast).unwrap(), 0.0);
+/// let (_, ast) = parse_expression("celsius(25)").unwrap();
+/// assert_eq!(evaluator.evaluate_value(&ast).unwrap().to_string(), "25°C");
+///
+/// let (_, ast) = parse_expression("celsius(25) + 1").unwrap();
+/// assert_eq!(
+///     evaluator.evaluate(&ast).unwrap_err().to_string(),
+///     "Can't use '+' with a temperature and a number"
+/// );
+/// ```
+pub trait CustomValue: Any + fmt::Debug + fmt::Display + Send + Sync {
+    /// A description of the kind of value, for error messages, such as "a temperature"
+    fn type_name(&self) -> &'static str;
+
+    /// Apply `op` (`+`, `-`, `*` or `/`) to this value and `other`, which is
+    /// on the left of `self` if `swapped`, or `None` if that isn't supported
+    fn binary(
+        &self,
+        op: &'static str,
+        other: &Value,
+        swapped: bool,
+    ) -> Option<Result<Value, EvaluationError>> {
+        let _ = (op, other, swapped);
+        None
+    }
+
+    /// The value negated, or `None` if that isn't supported
+    fn negate(&self) -> Option<Result<Value, EvaluationError>> {
+        None
+    }
+
+    /// How this value compares with `other`, or `None` if they can't be compared
+    ///
+    /// This is used for the comparison operators, and for `==` between [`Value`]s.
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        let _ = other;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Evaluator, FunctionRegistry, parse_expression};
+
+    /// An amount of money, in cents
+    #[derive(Debug, PartialEq)]
+    struct Money(i64);
+
+    impl fmt::Display for Money {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "${}.{:02}", self.0 / 100, self.0 % 100)
+        }
+    }
+
+    impl CustomValue for Money {
+        fn type_name(&self) -> &'static str {
+            "money"
+        }
+
+        fn binary(
+            &self,
+            op: &'static str,
+            other: &Value,
+            swapped: bool,
+        ) -> Option<Result<Value, EvaluationError>> {
+            let cents = match (op, other) {
+                ("+", Value::Custom(_)) => self.0 + other.downcast_ref::<Money>()?.0,
+                ("*", Value::Number(factor)) => (self.0 as f64 * factor).round() as i64,
+                ("/", Value::Number(divisor)) if !swapped => {
+                    (self.0 as f64 / divisor).round() as i64
+                }
+                _ => return None,
+            };
+            Some(Ok(Value::custom(Money(cents))))
+        }
+
+        fn negate(&self) -> Option<Result<Value, EvaluationError>> {
+            Some(Ok(Value::custom(Money(-self.0))))
+        }
+
+        fn compare(&self, other: &Value) -> Option<Ordering> {
+            Some(self.0.cmp(&other.downcast_ref::<Money>()?.0))
+        }
+    }
+
+    /// Test operators on both sides, negation, comparisons and equality
+    #[test]
+    fn test_custom_values() {
+        let mut functions = FunctionRegistry::standard();
+        functions.register_value("cents", 1, |args| {
+            Ok(Value::custom(Money(args[0].clone().into_number()? as i64)))
+        });
+        let env = Environment::new();
+        let evaluator = Evaluator::new(&env).with_functions(&functions);
+        let eval = |input: &str| evaluator.evaluate_value(&parse_expression(input).unwrap().1);
+
+        let total = eval("cents(250) * 3 + 2 * cents(99)").unwrap();
+        assert_eq!(total.downcast_ref::<Money>(), Some(&Money(948)));
+        assert_eq!(total.to_string(), "$9.48");
+        assert_eq!(total, Value::custom(Money(948)));
+        assert_ne!(total, Value::Number(948.0));
+        assert_eq!(Value::Number(948.0).downcast_ref::<Money>(), None);
+
+        assert_eq!(eval("-cents(5)").unwrap(), Value::custom(Money(-5)));
+        assert_eq!(eval("cents(5) < cents(7)").unwrap(), Value::Number(1.0));
+        assert_eq!(
+            eval("cents(100) / 3 == cents(33)").unwrap(),
+            Value::Number(1.0)
+        );
+        assert!(matches!(
+            eval("3 / cents(100)"),
+            Err(EvaluationError::UnsupportedOperands {
+                op: "/",
+                left: "a number",
+                right: "money"
+            })
+        ));
+        assert!(matches!(
+            eval("cents(1) < 2"),
+            Err(EvaluationError::UnsupportedOperands { .. })
+        ));
+    }
+}
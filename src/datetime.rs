@@ -0,0 +1,392 @@
+//! Dates, times and durations
+//!
+//! Dates are made with functions such as `date(2024, 1, 31)` or `now()`, and
+//! durations with `days(3)`, `hours(1.5)` and friends. They combine with the
+//! usual operators: subtracting two dates gives a duration, adding a duration
+//! to a date gives a later date, and dividing two durations gives a plain
+//! number, so `(date(2024, 3, 1) - date(2024, 2, 1)) / days(1)` is 29.
+//!
+//! All dates are in UTC, on the proleptic Gregorian calendar.
+
+use std::{
+    fmt,
+    ops::{Add, Mul, Neg, Sub},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Capabilities, EvaluationError, FunctionRegistry, Parameter, Value, functions::domain};
+
+const SECONDS_PER_MINUTE: f64 = 60.0;
+const SECONDS_PER_HOUR: f64 = 60.0 * SECONDS_PER_MINUTE;
+const SECONDS_PER_DAY: f64 = 24.0 * SECONDS_PER_HOUR;
+
+/// A point in time, stored as seconds since 1970-01-01T00:00:00 UTC
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct DateTime {
+    seconds: f64,
+}
+
+/// A length of time, stored in seconds (negative for "earlier")
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct Duration {
+    seconds: f64,
+}
+
+impl DateTime {
+    /// The date and time for a calendar day and time of day
+    ///
+    /// Returns `None` unless the month is 1-12, the day exists in that month,
+    /// and the time of day is within 00:00:00 to 23:59:59.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::DateTime;
+    ///
+    /// let leap_day = DateTime::new(2024, 2, 29, 12, 0, 0.0).unwrap();
+    /// assert_eq!(leap_day.to_string(), "2024-02-29T12:00:00");
+    /// assert!(DateTime::new(2023, 2, 29, 0, 0, 0.0).is_none());
+    /// ```
+    pub fn new(
+        year: i64,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: f64,
+    ) -> Option<Self> {
+        let valid = (1..=12).contains(&month)
+            && day >= 1
+            && day <= days_in_month(year, month)
+            && hour < 24
+            && minute < 60
+            && (0.0..60.0).contains(&second);
+        valid.then(|| DateTime {
+            seconds: days_from_civil(year, month, day) as f64 * SECONDS_PER_DAY
+                + f64::from(hour) * SECONDS_PER_HOUR
+                + f64::from(minute) * SECONDS_PER_MINUTE
+                + second,
+        })
+    }
+
+    /// The current date and time
+    pub fn now() -> Self {
+        let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs_f64(),
+            Err(before) => -before.duration().as_secs_f64(),
+        };
+        DateTime { seconds }
+    }
+
+    /// Seconds since 1970-01-01T00:00:00 UTC
+    pub fn unix_seconds(&self) -> f64 {
+        self.seconds
+    }
+
+    /// The calendar day: (year, month, day)
+    pub fn ymd(&self) -> (i64, u32, u32) {
+        civil_from_days((self.seconds / SECONDS_PER_DAY).floor() as i64)
+    }
+
+    /// Seconds since midnight
+    pub fn time_of_day(&self) -> f64 {
+        self.seconds.rem_euclid(SECONDS_PER_DAY)
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// ISO 8601, leaving out the time at midnight: `2024-01-31` or `2024-01-31T08:30:00`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)?;
+        let time = self.time_of_day().floor();
+        if time > 0.0 {
+            write!(f, "T{}", clock(time))?;
+        }
+        Ok(())
+    }
+}
+
+impl Duration {
+    /// A duration of the given number of seconds
+    pub fn from_seconds(seconds: f64) -> Self {
+        Duration { seconds }
+    }
+
+    /// The length in seconds
+    pub fn seconds(&self) -> f64 {
+        self.seconds
+    }
+}
+
+impl fmt::Display for Duration {
+    /// Whole days, then the rest as a clock time: `3 days`, `1 day 02:30:00`, `-00:15:00`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.seconds.abs().floor();
+        // No sign on a zero duration, even -0 or less than a second before
+        if self.seconds < 0.0 && total > 0.0 {
+            write!(f, "-")?;
+        }
+        let days = (total / SECONDS_PER_DAY).floor();
+        let time = total - days * SECONDS_PER_DAY;
+        match (days, time) {
+            (1.0, 0.0) => write!(f, "1 day"),
+            (days, 0.0) if days > 0.0 => write!(f, "{} days", days),
+            (0.0, time) => write!(f, "{}", clock(time)),
+            (1.0, time) => write!(f, "1 day {}", clock(time)),
+            (days, time) => write!(f, "{} days {}", days, clock(time)),
+        }
+    }
+}
+
+impl Sub for DateTime {
+    type Output = Duration;
+
+    fn sub(self, earlier: DateTime) -> Duration {
+        Duration::from_seconds(self.seconds - earlier.seconds)
+    }
+}
+
+impl Add<Duration> for DateTime {
+    type Output = DateTime;
+
+    fn add(self, duration: Duration) -> DateTime {
+        DateTime {
+            seconds: self.seconds + duration.seconds,
+        }
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration::from_seconds(self.seconds + other.seconds)
+    }
+}
+
+impl Mul<f64> for Duration {
+    type Output = Duration;
+
+    fn mul(self, factor: f64) -> Duration {
+        Duration::from_seconds(self.seconds * factor)
+    }
+}
+
+impl Neg for Duration {
+    type Output = Duration;
+
+    fn neg(self) -> Duration {
+        Duration::from_seconds(-self.seconds)
+    }
+}
+
+/// Seconds since midnight as `HH:MM:SS`
+fn clock(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a calendar date
+///
+/// Howard Hinnant's algorithm: shift the year to start in March, so the leap
+/// day is the last day of the year, then count whole 400-year eras.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The calendar date for a number of days since 1970-01-01 (the inverse of [`days_from_civil`])
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Add the date and duration functions to a registry
+///
+/// | Function | Description |
+/// | --- | --- |
+/// | `date(y, m, d, [h, min, s])` | The date (and time) in UTC |
+/// | `now()` | The current date and time |
+/// | `year(d)`, `month(d)`, `day(d)` | Parts of a date's calendar day |
+/// | `days(n)`, `hours(n)`, `minutes(n)`, `seconds(n)` | A duration |
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register_value("date", 3..=6, |args| {
+        let mut parts = [0.0; 6];
+        for (part, arg) in parts.iter_mut().zip(args) {
+            *part = arg.clone().into_number()?;
+        }
+        let [year, month, day, hour, minute, second] = parts;
+        let whole = parts[..5].iter().all(|part| part.fract() == 0.0);
+        let date = DateTime::new(
+            year as i64,
+            month as u32,
+            day as u32,
+            hour as u32,
+            minute as u32,
+            second,
+        )
+        .filter(|_| whole && parts[1..5].iter().all(|part| *part >= 0.0));
+        date.map(Value::Date)
+            .ok_or_else(|| EvaluationError::DomainError("date".to_string()))
+    });
+    let midnight = |name| Parameter::new(name).with_default(0.0);
+    registry.set_parameters(
+        "date",
+        &[
+            "year".into(),
+            "month".into(),
+            "day".into(),
+            midnight("hour"),
+            midnight("minute"),
+            midnight("second"),
+        ],
+    );
+    registry.register_value("now", 0, |_| Ok(Value::Date(DateTime::now())));
+    registry.set_capabilities("now", Capabilities::CLOCK);
+
+    registry.register_value("year", 1, |args| {
+        Ok(Value::Number(args[0].clone().into_date()?.ymd().0 as f64))
+    });
+    registry.register_value("month", 1, |args| {
+        Ok(Value::Number(f64::from(
+            args[0].clone().into_date()?.ymd().1,
+        )))
+    });
+    registry.register_value("day", 1, |args| {
+        Ok(Value::Number(f64::from(
+            args[0].clone().into_date()?.ymd().2,
+        )))
+    });
+
+    for (name, unit) in [
+        ("days", SECONDS_PER_DAY),
+        ("hours", SECONDS_PER_HOUR),
+        ("minutes", SECONDS_PER_MINUTE),
+        ("seconds", 1.0),
+    ] {
+        registry.register_value(name, 1, move |args| {
+            let count = args[0].clone().into_number()?;
+            domain(name, count.is_finite(), count)?;
+            Ok(Value::Duration(Duration::from_seconds(count * unit)))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Evaluator, parse_expression};
+
+    /// Evaluate an expression to a value
+    fn eval(expression: &str) -> Result<Value, EvaluationError> {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        Evaluator::new(&Environment::new()).evaluate_value(&ast)
+    }
+
+    /// Test converting between calendar dates and day numbers
+    #[test]
+    fn test_calendar() {
+        let cases = [
+            ((1970, 1, 1), 0),
+            ((2000, 3, 1), 11_017),
+            ((2024, 2, 29), 19_782),
+            ((1969, 12, 31), -1),
+            ((1600, 1, 1), -135_140),
+        ];
+        for ((year, month, day), days) in cases {
+            assert_eq!(days_from_civil(year, month, day), days);
+            assert_eq!(civil_from_days(days), (year, month, day));
+        }
+    }
+
+    /// Test the arithmetic rules between dates, durations and numbers
+    #[test]
+    fn test_date_arithmetic() {
+        let cases = [
+            ("date(2024, 3, 1) - date(2024, 2, 1)", "29 days"),
+            ("date(2024, 1, 31) + days(1)", "2024-02-01"),
+            ("date(2024, 1, 1) - hours(1.5)", "2023-12-31T22:30:00"),
+            ("days(1) + hours(2) * 2 - minutes(30)", "1 day 03:30:00"),
+            ("-(days(3) / 4)", "-18:00:00"),
+            ("(date(2024, 3, 1) - date(2024, 2, 1)) / days(1)", "29"),
+            ("year(date(2024, 12, 31) + days(1))", "2025"),
+            ("date(2024, 5, 6, 7, 8, 9)", "2024-05-06T07:08:09"),
+            ("date(2024, 5, 6, minute=30)", "2024-05-06T00:30:00"),
+            ("now() - now()", "00:00:00"),
+            ("-days(0)", "00:00:00"),
+            ("-days(1) / 100000", "00:00:00"),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                eval(expression).unwrap().to_string(),
+                expected,
+                "'{}'",
+                expression
+            );
+        }
+    }
+
+    /// Test the errors for mixing values that don't go together
+    #[test]
+    fn test_type_errors() {
+        assert_eq!(
+            eval("date(2024, 1, 1) + date(2024, 1, 2)")
+                .unwrap_err()
+                .to_string(),
+            "Can't use '+' with a date and a date"
+        );
+        assert_eq!(
+            eval("date(2024, 1, 1) + 1").unwrap_err().to_string(),
+            "Can't use '+' with a date and a number"
+        );
+        assert_eq!(
+            eval("sqrt(days(4))").unwrap_err().to_string(),
+            "Expected a number, found a duration"
+        );
+        assert!(matches!(
+            eval("date(2023, 2, 29)"),
+            Err(EvaluationError::DomainError(name)) if name == "date"
+        ));
+        assert!(matches!(
+            eval("days(2) / (days(1) - days(1))"),
+            Err(EvaluationError::DivisionByZero)
+        ));
+    }
+}
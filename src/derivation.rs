@@ -0,0 +1,206 @@
+//! Watching the parser work, for learning how recursive descent parses
+//!
+//! The parser has one function per rule of the grammar, from `expression`
+//! down through `pipeline`, `or`, `and`, `comparison`, `sum`, `term` and `factor` to
+//! `number` and `name`, and each calls the rules below it. [`parse_traced`]
+//! parses as usual while recording every rule entered and left, with where
+//! in the input it started and what it matched. Printing the [`ParseTrace`]
+//! shows the derivation as an indented outline:
+//!
+//! ```text
+//! expression at 0
+//!   pipeline at 0
+//!     or at 0
+//!       ...
+//!             factor at 0
+//!               number at 0
+//!               number matched "2"
+//!             factor matched "2"
+//! ```
+
+use std::fmt;
+
+use nom::IResult;
+
+use crate::{Context, Expr, ParserConfig, Spare, parse_expression_as};
+
+/// One rule entered or left while parsing, see [`parse_traced`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseStep {
+    /// The parser started trying a rule
+    Enter {
+        /// The rule, such as `sum` or `factor`
+        rule: &'static str,
+        /// The byte offset in the input it started at
+        position: usize,
+    },
+    /// The parser finished trying a rule
+    Exit {
+        /// The rule, such as `sum` or `factor`
+        rule: &'static str,
+        /// The byte offset in the input it started at
+        position: usize,
+        /// The byte offset just past what it matched, or `None` if it failed
+        end: Option<usize>,
+    },
+}
+
+/// Every rule the parser entered and left, in order, see [`parse_traced`]
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseTrace {
+    /// The text that was parsed
+    source: String,
+    /// The rules entered and left
+    steps: Vec<ParseStep>,
+}
+
+/// Parse an expression like [`parse_expression_with`](crate::parse_expression_with), recording each rule tried
+///
+/// Rules that fail aren't always mistakes: the parser tries a function call
+/// before falling back to a number, for example, so the trace also shows
+/// the alternatives it considered.
+///
+/// # Example
+/// ```
+/// use ast::{parse_traced, ParserConfig, ParseStep};
+///
+/// let (result, trace) = parse_traced("1 + 2", &ParserConfig::default());
+/// assert!(result.is_ok());
+/// assert_eq!(trace.steps()[0], ParseStep::Enter { rule: "expression", position: 0 });
+/// let sums = trace.steps().iter().filter(|step| matches!(step, ParseStep::Exit { rule: "sum", .. }));
+/// assert_eq!(sums.count(), 1);
+///
+/// let text = trace.to_string();
+/// assert!(text.contains("number matched \"1\""));
+/// assert!(text.ends_with("expression matched \"1 + 2\"\n"));
+/// ```
+pub fn parse_traced<'a>(
+    input: &'a str,
+    config: &ParserConfig,
+) -> (IResult<&'a str, Expr>, ParseTrace) {
+    let mut trace = ParseTrace {
+        source: input.to_string(),
+        steps: Vec::new(),
+    };
+    let mut spare = Spare::default();
+    let result = parse_expression_as(
+        input,
+        &mut Context::new(config, &mut spare).with_trace(&mut trace),
+    );
+    (result, trace)
+}
+
+impl ParseTrace {
+    /// The rules entered and left, in order
+    pub fn steps(&self) -> &[ParseStep] {
+        &self.steps
+    }
+
+    /// The text that was parsed
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Record that the parser started trying `rule` on `input`, the end of the source
+    pub(crate) fn enter(&mut self, rule: &'static str, input: &str) {
+        let position = self.source.len() - input.len();
+        self.steps.push(ParseStep::Enter { rule, position });
+    }
+
+    /// Record that `rule`, started on `input`, left `rest` unparsed, or failed if it's `None`
+    pub(crate) fn exit(&mut self, rule: &'static str, input: &str, rest: Option<&str>) {
+        let position = self.source.len() - input.len();
+        let end = rest.map(|rest| self.source.len() - rest.len());
+        self.steps.push(ParseStep::Exit {
+            rule,
+            position,
+            end,
+        });
+    }
+}
+
+impl fmt::Display for ParseTrace {
+    /// One line per step, indented by how deeply rules are nested
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut depth = 0;
+        for step in &self.steps {
+            match *step {
+                ParseStep::Enter { rule, position } => {
+                    writeln!(
+                        f,
+                        "{:indent$}{} at {}",
+                        "",
+                        rule,
+                        position,
+                        indent = depth * 2
+                    )?;
+                    depth += 1;
+                }
+                ParseStep::Exit {
+                    rule,
+                    position,
+                    end,
+                } => {
+                    depth = depth.saturating_sub(1);
+                    write!(f, "{:indent$}{} ", "", rule, indent = depth * 2)?;
+                    match end {
+                        Some(end) => {
+                            writeln!(f, "matched {:?}", self.source[position..end].trim())?
+                        }
+                        None => writeln!(f, "failed")?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that every rule entered is left, and that tracing doesn't change the tree
+    #[test]
+    fn test_balanced() {
+        for input in [
+            "-(a + 2) * max(b, 3) > 1 || !c",
+            "let t = 2 in [t, t][1]",
+            "1 +",
+        ] {
+            let (traced, trace) = parse_traced(input, &ParserConfig::default());
+            assert_eq!(
+                traced.map(|(rest, ast)| (rest.len(), ast)).ok(),
+                crate::parse_expression(input)
+                    .map(|(rest, ast)| (rest.len(), ast))
+                    .ok()
+            );
+
+            let mut open = Vec::new();
+            for step in trace.steps() {
+                match *step {
+                    ParseStep::Enter { rule, position } => open.push((rule, position)),
+                    ParseStep::Exit { rule, position, .. } => {
+                        assert_eq!(open.pop(), Some((rule, position)), "'{}'", input);
+                    }
+                }
+            }
+            assert!(open.is_empty());
+        }
+    }
+
+    /// Test the outline a trace prints as, including rules that failed
+    #[test]
+    fn test_display() {
+        let (_, trace) = parse_traced("f(x)", &ParserConfig::default());
+        let text = trace.to_string();
+        assert!(text.starts_with("expression at 0\n  pipeline at 0\n    or at 0\n"));
+        assert!(text.contains("            arguments at 1\n"));
+        assert!(text.contains("name matched \"f(x)\""));
+
+        let (result, trace) = parse_traced("2 * ", &ParserConfig::default());
+        assert!(result.is_err());
+        assert!(trace.to_string().contains("number at 4\n"));
+        assert!(trace.to_string().ends_with("expression failed\n"));
+    }
+}
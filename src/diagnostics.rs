@@ -0,0 +1,113 @@
+//! Source locations and diagnostics
+//!
+//! Diagnostics describe problems found in the input text. Each one carries the
+//! byte range (a [`Span`]) it refers to, so tools can underline the exact part
+//! of the input that caused it.
+
+use std::fmt;
+
+/// A byte range `start..end` within the original input string
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub struct Span {
+    /// Byte offset of the first character covered by the span
+    pub start: usize,
+    /// Byte offset one past the last character covered by the span
+    pub end: usize,
+}
+
+impl Span {
+    /// Create a span covering `start..end`
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The number of bytes covered by the span
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the span is zero-width (it points *between* two characters)
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// How serious a diagnostic is
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Severity {
+    /// The input is invalid and can't be evaluated as written
+    Error,
+    /// The input is valid but probably not what the author meant
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in the input, pointing at the span it concerns
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    /// Whether this is an error or just a warning
+    pub severity: Severity,
+    /// Where in the input the problem is
+    pub span: Span,
+    /// A short, lowercase description of the problem
+    pub message: String,
+    /// The problem's stable code, such as `P0003`, see [`explain_code`](crate::explain_code)
+    pub code: Option<&'static str>,
+}
+
+impl Diagnostic {
+    /// Create an error diagnostic for the given span
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            span,
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    /// Create a warning diagnostic for the given span
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            span,
+            message: message.into(),
+            code: None,
+        }
+    }
+}
+
+impl Diagnostic {
+    /// The same diagnostic, with a code
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(
+                f,
+                "{}: {}[{}]: {}",
+                self.span, self.severity, code, self.message
+            ),
+            None => write!(f, "{}: {}: {}", self.span, self.severity, self.message),
+        }
+    }
+}
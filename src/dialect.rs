@@ -0,0 +1,186 @@
+//! Formula syntax from other engines
+//!
+//! Formulas written for Python or a spreadsheet use slightly different
+//! operators and names. [`parse_dialect`] accepts them and normalizes them
+//! into the same [`Expr`] trees as the calculator's own syntax, so migrated
+//! formulas can be evaluated, compared and rewritten like any other.
+
+use nom::IResult;
+
+use crate::{Comparison, Expr, Name, ParserConfig, parse_expression_with};
+
+/// A formula syntax that the parser can read
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Dialect {
+    /// The calculator's own syntax
+    #[default]
+    Standard,
+
+    /// Python arithmetic: `x ** 2` for powers, `a // b` for floor division,
+    /// and `math.sqrt(x)`, `math.log(x)` and `math.pi` for the `math` module
+    Python,
+
+    /// Spreadsheet formulas: an optional leading `=`, `x ^ 2` for powers,
+    /// `=` and `<>` for comparisons, and cell references
+    Excel,
+}
+
+/// Parse an expression written in another engine's syntax
+///
+/// Python's `x ** y` and Excel's `x ^ y` become `pow(x, y)`, Python's
+/// `a // b` becomes `floor(a / b)`, and the functions and constants of
+/// Python's `math` module become their standard equivalents, e.g.
+/// `math.log` becomes `ln`. Python's `**` binds tighter than a minus sign
+/// before it and groups right to left; Excel's `^` binds looser than a minus
+/// sign and groups left to right, so `-2 ^ 2` is 4.
+///
+/// # Example
+/// ```
+/// use ast::{evaluate, parse_dialect, parse_expression, Dialect};
+///
+/// let (_, python) = parse_dialect("-2 ** 2 + 7 // 2 + math.sqrt(16)", Dialect::Python).unwrap();
+/// let (_, native) = parse_expression("-pow(2, 2) + floor(7 / 2) + sqrt(16)").unwrap();
+/// assert_eq!(python, native);
+/// assert_eq!(evaluate(&python).unwrap(), 3.0);
+///
+/// let (_, excel) = parse_dialect("=-2 ^ 2 <> 4", Dialect::Excel).unwrap();
+/// assert_eq!(evaluate(&excel).unwrap(), 0.0);
+/// ```
+pub fn parse_dialect(input: &str, dialect: Dialect) -> IResult<&str, Expr> {
+    let config = ParserConfig {
+        cell_references: dialect == Dialect::Excel,
+        dialect,
+        ..ParserConfig::default()
+    };
+    let input = match dialect {
+        Dialect::Excel => {
+            let trimmed = input.trim_start();
+            trimmed.strip_prefix('=').unwrap_or(trimmed)
+        }
+        _ => input,
+    };
+    parse_expression_with(input, &config)
+}
+
+impl Dialect {
+    /// The comparison operators, longest first so that `<=` isn't read as `<`
+    pub(crate) fn comparison_symbols(self) -> &'static [&'static str] {
+        match self {
+            Dialect::Excel => &["<>", "<=", ">=", "=", "<", ">"],
+            _ => Comparison::SYMBOLS,
+        }
+    }
+
+    /// The comparison a symbol from [`Dialect::comparison_symbols`] stands for
+    pub(crate) fn comparison(self, symbol: &str) -> Option<Comparison> {
+        match (self, symbol) {
+            (Dialect::Excel, "=") => Some(Comparison::Equal),
+            (Dialect::Excel, "<>") => Some(Comparison::NotEqual),
+            _ => Comparison::from_symbol(symbol),
+        }
+    }
+}
+
+/// `pow(base, exponent)`, which `**` and `^` stand for
+pub(crate) fn power<'a, S: Name<'a>>(base: Expr<S>, exponent: Expr<S>) -> Expr<S> {
+    Expr::Call("pow".into(), vec![base, exponent])
+}
+
+/// `floor(left / right)`, which Python's `//` stands for
+pub(crate) fn floor_division<'a, S: Name<'a>>(left: Expr<S>, right: Expr<S>) -> Expr<S> {
+    let quotient = Expr::Div(Box::new(left), Box::new(right));
+    Expr::Call("floor".into(), vec![quotient])
+}
+
+/// A name from Python's `math` module, e.g. `pi` from `math.pi`
+///
+/// Constants become numbers. Functions become variables, to be turned into
+/// calls (through [`python_call`]) when arguments follow. Returns `None` for
+/// names this crate has no equivalent of.
+pub(crate) fn python_math<'a, S: Name<'a>>(name: &'a str) -> Option<Expr<S>> {
+    Some(match name {
+        "pi" => Expr::Float(std::f64::consts::PI),
+        "e" => Expr::Float(std::f64::consts::E),
+        "tau" => Expr::Float(std::f64::consts::TAU),
+        "inf" => Expr::Float(f64::INFINITY),
+        "nan" => Expr::Float(f64::NAN),
+        "sqrt" | "cbrt" | "exp" | "log" | "log10" | "log2" | "sin" | "cos" | "tan" | "asin"
+        | "acos" | "atan" | "atan2" | "sinh" | "cosh" | "tanh" | "floor" | "ceil" | "trunc"
+        | "pow" | "fabs" | "hypot" | "factorial" | "comb" => Expr::Var(name.into()),
+        _ => return None,
+    })
+}
+
+/// A call to a Python function, as a call to the standard equivalent
+///
+/// Python's `log` is the natural logarithm, with an optional base.
+pub(crate) fn python_call<'a, S: Name<'a>>(name: &'a str, mut args: Vec<Expr<S>>) -> Expr<S> {
+    let ln = |arg| Expr::Call("ln".into(), vec![arg]);
+    if args.iter().any(|arg| matches!(arg, Expr::Named(..))) {
+        return Expr::Call(name.into(), args);
+    }
+    match (name, args.len()) {
+        ("fabs", _) => Expr::Call("abs".into(), args),
+        ("log", 1) => Expr::Call("ln".into(), args),
+        ("log", 2) => {
+            let base = args.pop().expect("two arguments");
+            let value = args.pop().expect("two arguments");
+            Expr::Div(Box::new(ln(value)), Box::new(ln(base)))
+        }
+        _ => Expr::Call(name.into(), args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate, parse_expression};
+
+    /// Test that dialect formulas parse to the same trees as their standard equivalents
+    #[test]
+    fn test_normalization() {
+        let cases = [
+            (Dialect::Python, "2 ** 3 ** 2", "pow(2, pow(3, 2))"),
+            (Dialect::Python, "-x ** -2", "-pow(x, -2)"),
+            (Dialect::Python, "a // b * c / d", "floor(a / b) * c / d"),
+            (
+                Dialect::Python,
+                "math.log(x) + math.log(x, 2)",
+                "ln(x) + ln(x) / ln(2)",
+            ),
+            (Dialect::Python, "math.fabs(x) * abs(y)", "abs(x) * abs(y)"),
+            (Dialect::Excel, "=2 ^ 3 ^ 2", "pow(pow(2, 3), 2)"),
+            (Dialect::Excel, "-x^2 * 3", "pow(-x, 2) * 3"),
+            (Dialect::Excel, "a = 1 <> (b <= 2)", "a == 1 != (b <= 2)"),
+        ];
+
+        for (dialect, input, expected) in cases {
+            let (_, ast) = parse_dialect(input, dialect).unwrap();
+            let (_, expected) = parse_expression(expected).unwrap();
+            assert_eq!(ast, expected, "{:?} '{}'", dialect, input);
+        }
+
+        let (_, ast) = parse_dialect("math.pi * r ** 2", Dialect::Python).unwrap();
+        let area = Expr::Mul(
+            Box::new(Expr::Float(std::f64::consts::PI)),
+            Box::new(power(Expr::Var("r".to_string()), Expr::Float(2.0))),
+        );
+        assert_eq!(ast, area);
+    }
+
+    /// Test that each dialect's operators are only accepted in that dialect
+    #[test]
+    fn test_dialect_limits() {
+        assert!(parse_dialect("x ** 2", Dialect::Standard).is_err());
+        assert!(parse_dialect("7 // 2", Dialect::Excel).is_err());
+        let (remaining, _) = parse_dialect("x ^ 2", Dialect::Python).unwrap();
+        assert_eq!(remaining, " ^ 2");
+        assert!(parse_dialect("math.gamma(2)", Dialect::Python).is_err());
+        assert!(parse_dialect("os.getcwd()", Dialect::Python).is_err());
+
+        let (_, ast) = parse_dialect("-7 // 2", Dialect::Python).unwrap();
+        assert_eq!(evaluate(&ast).unwrap(), -4.0);
+        let (_, ast) = parse_dialect("=SUM(A1:A3)", Dialect::Excel).unwrap();
+        assert!(matches!(&ast, Expr::Call(_, args) if matches!(args[0], Expr::CellRange(..))));
+    }
+}
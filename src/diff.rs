@@ -0,0 +1,306 @@
+//! Structural differences between two expression trees
+//!
+//! Comparing formulas as text mixes real changes with spacing and redundant
+//! parentheses. [`diff`] compares the trees instead and reports what actually
+//! changed: literal values, added or removed operands, and replaced subtrees.
+
+use std::fmt;
+
+use crate::Expr;
+
+/// A single difference between two trees
+///
+/// Paths list the child indices to follow from the root: `0` is the left (or
+/// only) operand and `1` the right operand. An empty path is the root itself,
+/// and [`Expr::get`] and [`Expr::replace_at`] follow paths.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Change {
+    /// A numeric literal changed value
+    Literal {
+        /// Where the literal is (the same in both trees)
+        path: Vec<usize>,
+        /// The value in the old tree
+        old: f64,
+        /// The value in the new tree
+        new: f64,
+    },
+
+    /// An operand was added around an existing subtree, e.g. `a` became `a + b`
+    Added {
+        /// Where the added operand is, in the new tree
+        path: Vec<usize>,
+        /// The added operand
+        expr: Expr,
+    },
+
+    /// An operand was removed, leaving the other one in place, e.g. `a + b` became `a`
+    Removed {
+        /// Where the removed operand was, in the old tree
+        path: Vec<usize>,
+        /// The removed operand
+        expr: Expr,
+    },
+
+    /// A subtree was replaced by a different one
+    Replaced {
+        /// Where the subtree is (the same in both trees)
+        path: Vec<usize>,
+        /// The subtree in the old tree
+        old: Expr,
+        /// The subtree in the new tree
+        new: Expr,
+    },
+}
+
+/// All the differences between two trees, in left-to-right order
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ExprDiff {
+    /// The individual changes
+    pub changes: Vec<Change>,
+}
+
+impl ExprDiff {
+    /// Whether the two trees were identical
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Compute the structural differences between an old and a new tree
+///
+/// # Example
+/// ```
+/// use ast::{diff, parse_expression, Change};
+///
+/// let (_, old) = parse_expression("2 * (3 + 4)").unwrap();
+/// let (_, new) = parse_expression("2 * (3 + 5)").unwrap();
+/// assert_eq!(
+///     diff(&old, &new).changes,
+///     vec![Change::Literal { path: vec![1, 1], old: 4.0, new: 5.0 }]
+/// );
+///
+/// // Formatting differences aren't changes
+/// let (_, new) = parse_expression("2*(3+4)").unwrap();
+/// assert!(diff(&old, &new).is_empty());
+/// ```
+pub fn diff(old: &Expr, new: &Expr) -> ExprDiff {
+    let mut changes = Vec::new();
+    let mut path = Vec::new();
+    compare(old, new, &mut path, &mut changes);
+    ExprDiff { changes }
+}
+
+/// Compare two subtrees found at `path`, recording changes
+fn compare(old: &Expr, new: &Expr, path: &mut Vec<usize>, changes: &mut Vec<Change>) {
+    if old == new {
+        return;
+    }
+
+    if let (Expr::Float(old), Expr::Float(new)) = (old, new) {
+        changes.push(Change::Literal {
+            path: path.clone(),
+            old: *old,
+            new: *new,
+        });
+        return;
+    }
+
+    let (old_children, new_children) = (old.children(), new.children());
+
+    // Same operation: the difference is somewhere further down
+    if same_operation(old, new) && !old_children.is_empty() {
+        for (index, (old, new)) in old_children.into_iter().zip(new_children).enumerate() {
+            path.push(index);
+            compare(old, new, path, changes);
+            path.pop();
+        }
+        return;
+    }
+
+    // The old subtree is now one operand of a new binary operation
+    if binary(new)
+        && let Some(kept) = new_children.iter().position(|child| *child == old)
+    {
+        let added = 1 - kept;
+        path.push(added);
+        changes.push(Change::Added {
+            path: path.clone(),
+            expr: new_children[added].clone(),
+        });
+        path.pop();
+        return;
+    }
+
+    // A binary operation was dropped, leaving one of its operands
+    if binary(old)
+        && let Some(kept) = old_children.iter().position(|child| *child == new)
+    {
+        let removed = 1 - kept;
+        path.push(removed);
+        changes.push(Change::Removed {
+            path: path.clone(),
+            expr: old_children[removed].clone(),
+        });
+        path.pop();
+        return;
+    }
+
+    changes.push(Change::Replaced {
+        path: path.clone(),
+        old: old.clone(),
+        new: new.clone(),
+    });
+}
+
+/// Whether two nodes are the same operation, differing at most in their children
+fn same_operation(old: &Expr, new: &Expr) -> bool {
+    match (old, new) {
+        (Expr::Call(old_name, old_args), Expr::Call(new_name, new_args)) => {
+            old_name == new_name && old_args.len() == new_args.len()
+        }
+        (Expr::Compare(old_op, ..), Expr::Compare(new_op, ..)) => old_op == new_op,
+        (Expr::List(old_elements), Expr::List(new_elements)) => {
+            old_elements.len() == new_elements.len()
+        }
+        (Expr::Index(_, old_indices), Expr::Index(_, new_indices)) => {
+            old_indices.len() == new_indices.len()
+        }
+        (Expr::Let(old_name, ..), Expr::Let(new_name, ..))
+        | (Expr::Named(old_name, _), Expr::Named(new_name, _)) => old_name == new_name,
+        (Expr::Piecewise(old_cases, old_default), Expr::Piecewise(new_cases, new_default)) => {
+            old_cases.len() == new_cases.len() && old_default.is_some() == new_default.is_some()
+        }
+        _ => std::mem::discriminant(old) == std::mem::discriminant(new),
+    }
+}
+
+/// Whether a node is a binary operation
+fn binary(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Add(..)
+            | Expr::Sub(..)
+            | Expr::Mul(..)
+            | Expr::Div(..)
+            | Expr::Compare(..)
+            | Expr::And(..)
+            | Expr::Or(..)
+    )
+}
+
+/// Write a path as `/0/1`, or `/` for the root
+fn fmt_path(path: &[usize], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if path.is_empty() {
+        return write!(f, "/");
+    }
+    for index in path {
+        write!(f, "/{}", index)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Literal { path, old, new } => {
+                write!(f, "literal changed at ")?;
+                fmt_path(path, f)?;
+                write!(f, ": {} -> {}", old, new)
+            }
+            Change::Added { path, expr } => {
+                write!(f, "added at ")?;
+                fmt_path(path, f)?;
+                write!(f, ": {:?}", expr)
+            }
+            Change::Removed { path, expr } => {
+                write!(f, "removed at ")?;
+                fmt_path(path, f)?;
+                write!(f, ": {:?}", expr)
+            }
+            Change::Replaced { path, old, new } => {
+                write!(f, "replaced at ")?;
+                fmt_path(path, f)?;
+                write!(f, ": {:?} -> {:?}", old, new)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExprDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, change) in self.changes.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Parse both expressions and diff them
+    fn diff_str(old: &str, new: &str) -> ExprDiff {
+        let (_, old) = parse_expression(old).unwrap();
+        let (_, new) = parse_expression(new).unwrap();
+        diff(&old, &new)
+    }
+
+    /// Test added and removed operands
+    #[test]
+    fn test_added_and_removed() {
+        assert_eq!(
+            diff_str("1 + 2", "(1 + 2) * 3").changes,
+            vec![Change::Added {
+                path: vec![1],
+                expr: Expr::Float(3.0)
+            }]
+        );
+        assert_eq!(
+            diff_str("4 - (1 + 2)", "4 - 2").changes,
+            vec![Change::Removed {
+                path: vec![1, 0],
+                expr: Expr::Float(1.0)
+            }]
+        );
+    }
+
+    /// Test that unrelated changes are reported as replacements, in order
+    #[test]
+    fn test_replaced_and_multiple_changes() {
+        let changes = diff_str("1 + 2 * 3", "5 + 2 / 3");
+        assert_eq!(changes.changes.len(), 2);
+        assert!(matches!(changes.changes[0], Change::Literal { .. }));
+        assert!(matches!(&changes.changes[1], Change::Replaced { path, .. } if path == &vec![1]));
+        assert_eq!(
+            changes.to_string(),
+            "literal changed at /0: 1 -> 5\n\
+             replaced at /1: Mul(Float(2.0), Float(3.0)) -> Div(Float(2.0), Float(3.0))"
+        );
+    }
+
+    /// Test that calls are only compared argument by argument when they call the same function
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            diff_str("max(a, 1)", "max(a, 2)").changes,
+            vec![Change::Literal {
+                path: vec![1],
+                old: 1.0,
+                new: 2.0
+            }]
+        );
+        assert!(matches!(
+            &diff_str("max(a, 1)", "min(a, 1)").changes[..],
+            [Change::Replaced { path, .. }] if path.is_empty()
+        ));
+        assert!(matches!(
+            &diff_str("a", "max(a, 1)").changes[..],
+            [Change::Replaced { .. }]
+        ));
+    }
+}
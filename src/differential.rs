@@ -0,0 +1,379 @@
+//! Differential testing of the evaluation backends
+//!
+//! The tree-walking [`Evaluator`](crate::Evaluator) isn't the only way to get
+//! an expression's value: [`lower`](crate::lower) and [`execute`](crate::execute)
+//! compute it on a virtual machine, and arithmetic can be done exactly with
+//! rationals. [`compare_backends`] evaluates many random expressions every
+//! way and reports where the results disagree, so that a change to the
+//! grammar or to one backend can't quietly make them drift apart.
+
+use std::fmt;
+
+use crate::{Comparison, Environment, Expr, evaluate_with, execute, lower};
+
+/// A way of evaluating expressions that is checked against the tree walker
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Backend {
+    /// Lowering to three-address code and running it with [`execute`](crate::execute)
+    Ir,
+    /// Exact rational arithmetic, for expressions with only `+`, `-`, `*` and `/`
+    Rational,
+}
+
+/// Options for [`compare_backends`]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DifferentialConfig {
+    /// Where the random expressions start; the same seed gives the same expressions
+    pub seed: u64,
+    /// How many expressions to try
+    pub cases: usize,
+    /// How deeply operations may be nested
+    pub max_depth: usize,
+    /// How far apart results may be, relative to their size (or absolutely, below 1)
+    pub tolerance: f64,
+    /// Whether to also compare arithmetic with [`Backend::Rational`]
+    pub rationals: bool,
+}
+
+impl Default for DifferentialConfig {
+    fn default() -> Self {
+        DifferentialConfig {
+            seed: 1,
+            cases: 1000,
+            max_depth: 5,
+            tolerance: 1e-9,
+            rationals: true,
+        }
+    }
+}
+
+/// An expression that a backend evaluated differently from the tree walker
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    /// The expression
+    pub expr: Expr,
+    /// The backend that disagreed
+    pub backend: Backend,
+    /// What the tree walker gave, or its error message
+    pub expected: Result<f64, String>,
+    /// What the backend gave, or its error message
+    pub found: Result<f64, String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |result: &Result<f64, String>| match result {
+            Ok(value) => value.to_string(),
+            Err(message) => format!("error '{}'", message),
+        };
+        write!(
+            f,
+            "{:?} gives {} for {:?}, but the tree walker gives {}",
+            self.backend,
+            show(&self.found),
+            self.expr,
+            show(&self.expected)
+        )
+    }
+}
+
+/// The variables that random expressions can use
+pub(crate) const VARIABLES: [(&str, f64); 3] = [("x", 1.5), ("y", -2.0), ("z", 0.0)];
+
+/// Number literals for random expressions; they're all exact in binary
+const LITERALS: [f64; 8] = [0.0, 1.0, 2.0, 3.0, 7.0, 0.5, 0.25, 10.0];
+
+/// Functions for random expressions, with how many arguments to give them
+///
+/// `if` and `coalesce` evaluate their own arguments, which backends must
+/// respect, e.g. by not dividing by zero in the branch `if` doesn't take.
+const FUNCTIONS: [(&str, usize); 9] = [
+    ("abs", 1),
+    ("sqrt", 1),
+    ("floor", 1),
+    ("round", 1),
+    ("min", 2),
+    ("max", 3),
+    ("hypot", 2),
+    ("if", 3),
+    ("coalesce", 2),
+];
+
+/// Evaluate random expressions with every backend, returning where they disagree with the tree walker
+///
+/// Two errors count as agreeing even if they're different errors: the
+/// backends may evaluate operands in a different order, and so run into a
+/// different problem first.
+///
+/// # Example
+/// ```
+/// use ast::{compare_backends, DifferentialConfig};
+///
+/// let config = DifferentialConfig { cases: 200, ..DifferentialConfig::default() };
+/// let divergences = compare_backends(&config);
+/// assert!(divergences.is_empty(), "{}", divergences[0]);
+/// ```
+pub fn compare_backends(config: &DifferentialConfig) -> Vec<Divergence> {
+    let env: Environment = VARIABLES.into_iter().collect();
+    let mut random = Random(config.seed);
+    (0..config.cases)
+        .flat_map(|_| compare_expr(&random.expr(config.max_depth), &env, config))
+        .collect()
+}
+
+/// Evaluate one expression with every backend, returning where they disagree with the tree walker
+pub fn compare_expr(
+    expr: &Expr,
+    env: &Environment,
+    config: &DifferentialConfig,
+) -> Vec<Divergence> {
+    let expected = evaluate_with(expr, env).map_err(|error| error.to_string());
+    let mut results = vec![(
+        Backend::Ir,
+        execute(&lower(expr), env).map_err(|error| error.to_string()),
+    )];
+    if config.rationals
+        && let Some(exact) = Ratio::evaluate(expr, env)
+    {
+        results.push((Backend::Rational, exact.map(Ratio::to_f64)));
+    }
+
+    results
+        .into_iter()
+        .filter(|(_, found)| !agree(&expected, found, config.tolerance))
+        .map(|(backend, found)| Divergence {
+            expr: expr.clone(),
+            backend,
+            expected: expected.clone(),
+            found,
+        })
+        .collect()
+}
+
+/// Whether two results are the same, within the tolerance
+pub(crate) fn agree(
+    left: &Result<f64, String>,
+    right: &Result<f64, String>,
+    tolerance: f64,
+) -> bool {
+    match (left, right) {
+        (Ok(left), Ok(right)) => {
+            left == right
+                || (left.is_nan() && right.is_nan())
+                || (left - right).abs() <= tolerance * left.abs().max(right.abs()).max(1.0)
+        }
+        (Err(_), Err(_)) => true,
+        _ => false,
+    }
+}
+
+/// A small, seedable pseudo-random number generator (SplitMix64)
+pub(crate) struct Random(pub(crate) u64);
+
+impl Random {
+    /// The next 64 random bits
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number from 0 up to, but not including, 1
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A random number below `n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A random expression with operations nested at most `depth` deep
+    pub(crate) fn expr(&mut self, depth: usize) -> Expr {
+        if depth == 0 || self.below(4) == 0 {
+            return match self.below(10) {
+                0..=2 => Expr::Var(VARIABLES[self.below(VARIABLES.len())].0.to_string()),
+                _ => Expr::Float(LITERALS[self.below(LITERALS.len())]),
+            };
+        }
+
+        match self.below(12) {
+            0 | 1 => Expr::Add(self.operand(depth), self.operand(depth)),
+            2 | 3 => Expr::Sub(self.operand(depth), self.operand(depth)),
+            4 | 5 => Expr::Mul(self.operand(depth), self.operand(depth)),
+            6 | 7 => Expr::Div(self.operand(depth), self.operand(depth)),
+            8 => Expr::Neg(self.operand(depth)),
+            9 => {
+                let symbol = Comparison::SYMBOLS[self.below(Comparison::SYMBOLS.len())];
+                let comparison = Comparison::from_symbol(symbol).expect("a comparison symbol");
+                Expr::Compare(comparison, self.operand(depth), self.operand(depth))
+            }
+            10 => match self.below(3) {
+                0 => Expr::And(self.operand(depth), self.operand(depth)),
+                1 => Expr::Or(self.operand(depth), self.operand(depth)),
+                _ => Expr::Not(self.operand(depth)),
+            },
+            _ => {
+                let (name, count) = FUNCTIONS[self.below(FUNCTIONS.len())];
+                let args = (0..count).map(|_| self.expr(depth - 1)).collect();
+                Expr::Call(name.to_string(), args)
+            }
+        }
+    }
+
+    /// An operand for an operation at `depth`
+    fn operand(&mut self, depth: usize) -> Box<Expr> {
+        Box::new(self.expr(depth - 1))
+    }
+}
+
+/// An exact fraction, kept in lowest terms with a positive denominator
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Ratio {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl Ratio {
+    /// The fraction `numerator / denominator`, or `None` if it doesn't fit
+    fn new(numerator: i128, denominator: i128) -> Option<Ratio> {
+        let divisor = gcd(numerator, denominator);
+        let sign = denominator.signum();
+        Some(Ratio {
+            numerator: numerator.checked_div(divisor)?.checked_mul(sign)?,
+            denominator: denominator.checked_div(divisor)?.checked_mul(sign)?,
+        })
+    }
+
+    /// The exact value of a number, if it's a fraction with a denominator up to 2^32
+    fn from_f64(value: f64) -> Option<Ratio> {
+        let mut denominator: i128 = 1;
+        while (value * denominator as f64).fract() != 0.0 {
+            denominator = denominator.checked_mul(2).filter(|&d| d <= 1 << 32)?;
+        }
+        let numerator = value * denominator as f64;
+        (numerator.abs() < 1e30).then(|| Ratio::new(numerator as i128, denominator))?
+    }
+
+    /// The nearest number
+    fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// Evaluate an expression exactly, or `None` if it has anything but
+    /// arithmetic in it or the fractions grow too big
+    ///
+    /// Division by zero is the only error.
+    fn evaluate(expr: &Expr, env: &Environment) -> Option<Result<Ratio, String>> {
+        let binary = |left: &Expr, right: &Expr| -> Option<Result<(Ratio, Ratio), String>> {
+            let left = Ratio::evaluate(left, env)?;
+            let right = Ratio::evaluate(right, env)?;
+            Some(left.and_then(|left| right.map(|right| (left, right))))
+        };
+        let (a, b) = match expr {
+            Expr::Float(value) => return Ratio::from_f64(*value).map(Ok),
+            Expr::Var(name) => return Ratio::from_f64(env.get(name)?).map(Ok),
+            Expr::Neg(inner) => {
+                return Some(match Ratio::evaluate(inner, env)? {
+                    Ok(value) => Ok(Ratio::new(
+                        value.numerator.checked_neg()?,
+                        value.denominator,
+                    )?),
+                    Err(error) => Err(error),
+                });
+            }
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right) => match binary(left, right)? {
+                Ok(operands) => operands,
+                Err(error) => return Some(Err(error)),
+            },
+            _ => return None,
+        };
+
+        let cross = |x: i128, y: i128| x.checked_mul(y);
+        Some(Ok(match expr {
+            Expr::Add(..) => Ratio::new(
+                cross(a.numerator, b.denominator)?
+                    .checked_add(cross(b.numerator, a.denominator)?)?,
+                cross(a.denominator, b.denominator)?,
+            )?,
+            Expr::Sub(..) => Ratio::new(
+                cross(a.numerator, b.denominator)?
+                    .checked_sub(cross(b.numerator, a.denominator)?)?,
+                cross(a.denominator, b.denominator)?,
+            )?,
+            Expr::Mul(..) => Ratio::new(
+                cross(a.numerator, b.numerator)?,
+                cross(a.denominator, b.denominator)?,
+            )?,
+            _ if b.numerator == 0 => return Some(Err("Division by zero".to_string())),
+            _ => Ratio::new(
+                cross(a.numerator, b.denominator)?,
+                cross(a.denominator, b.numerator)?,
+            )?,
+        }))
+    }
+}
+
+/// The greatest common divisor, which is 1 for 0 and 0 so that dividing by it is safe
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    match a.checked_abs() {
+        Some(0) | None => 1,
+        Some(a) => a,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Test that the backends agree on many random expressions
+    #[test]
+    fn test_backends_agree() {
+        for seed in 0..5 {
+            let config = DifferentialConfig {
+                seed,
+                ..DifferentialConfig::default()
+            };
+            let divergences = compare_backends(&config);
+            assert!(divergences.is_empty(), "seed {}: {}", seed, divergences[0]);
+        }
+    }
+
+    /// Test exact arithmetic, and that a disagreement is reported
+    #[test]
+    fn test_rationals() {
+        let env: Environment = VARIABLES.into_iter().collect();
+        let exact = |input: &str| {
+            let (_, ast) = parse_expression(input).unwrap();
+            Ratio::evaluate(&ast, &env)
+        };
+        assert_eq!(exact("1 / 3 + x"), Some(Ok(Ratio::new(11, 6).unwrap())));
+        assert_eq!(exact("-(y * 0.25)"), Some(Ok(Ratio::new(1, 2).unwrap())));
+        assert_eq!(
+            exact("1 / (x - 1.5)"),
+            Some(Err("Division by zero".to_string()))
+        );
+        assert_eq!(exact("sqrt(4) + 1"), None);
+        assert_eq!(exact("0.1"), None);
+
+        // 0.1 + 0.2 isn't 0.3 in floating point, but it is with fractions
+        let (_, ast) = parse_expression("1 / 10 + 2 / 10 - 3 / 10").unwrap();
+        let config = DifferentialConfig {
+            tolerance: 0.0,
+            ..DifferentialConfig::default()
+        };
+        let divergences = compare_expr(&ast, &env, &config);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].backend, Backend::Rational);
+        assert_eq!(divergences[0].found, Ok(0.0));
+    }
+}
@@ -0,0 +1,786 @@
+//! Everything needed to evaluate formulas, in one place
+//!
+//! Evaluating a formula from text involves a [`ParserConfig`], a
+//! [`FunctionRegistry`], an [`Environment`] of constants, an [`EvalConfig`]
+//! and, for formulas that come up again and again, a cache of parsed trees.
+//! An [`Engine`] holds them all, so embedding the crate takes one type and
+//! one call.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    Arity, EngineEvent, EngineObserver, Environment, EvalConfig, EvaluationError, Evaluator, Expr,
+    FunctionRegistry, MetricsSink, ParseEvalError, ParserConfig, Prelude, Stage, metrics,
+    observer::{self, StageSpan},
+    parse_expression_with,
+    streaming::whole,
+};
+
+/// A parsed formula, ready for [`Engine::run`]
+///
+/// Cheap to clone, and can be kept and run any number of times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    /// The text it was parsed from
+    text: Arc<str>,
+    /// The tree, shared with the engine's cache
+    expr: Arc<Expr>,
+    /// How many nodes the tree has
+    nodes: usize,
+}
+
+impl Formula {
+    /// The text the formula was parsed from
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The formula's tree
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+}
+
+/// Parses and evaluates formulas with one configuration
+///
+/// By default, formulas use the default syntax, the
+/// [standard functions](FunctionRegistry::standard), no constants and the
+/// default [`EvalConfig`]. The trees of the 1024 most recently used formula
+/// texts are kept, so repeated formulas are only parsed once. An engine can
+/// be shared between threads.
+///
+/// # Example
+/// ```
+/// use ast::{Engine, Environment, Prelude};
+///
+/// let engine = Engine::new().with_prelude(Prelude::scientific());
+/// let vars: Environment = [("r", 2.0)].into_iter().collect();
+/// assert_eq!(engine.eval("round(pi * r * r, 2)", &vars).unwrap(), 12.57);
+///
+/// let area = engine.compile("pi * r * r").unwrap();
+/// for r in [1.0, 2.0, 3.0] {
+///     let vars: Environment = [("r", r)].into_iter().collect();
+///     assert_eq!(engine.run(&area, &vars).unwrap(), std::f64::consts::PI * r * r);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Engine {
+    /// The syntax formulas are written in
+    parser: ParserConfig,
+    /// The functions formulas can call
+    functions: FunctionRegistry,
+    /// Values every formula can use, behind its own variables
+    constants: Arc<Environment>,
+    /// How formulas are evaluated
+    config: EvalConfig,
+    /// The most formula texts whose trees are kept
+    capacity: usize,
+    /// The deepest a formula's tree may be, if there's a limit
+    max_depth: Option<usize>,
+    /// The longest a number literal may be, if there's a limit
+    max_literal_length: Option<usize>,
+    /// The longest a variable or function name may be, if there's a limit
+    max_name_length: Option<usize>,
+    /// Told about everything the engine does, if set
+    observer: Option<Observer>,
+    /// Counts and times what the engine does, if set
+    metrics: Option<Metrics>,
+    /// Recently parsed formulas
+    cache: Mutex<Trees>,
+}
+
+/// An [`EngineObserver`], shared between an engine and its clones
+#[derive(Clone)]
+struct Observer(Arc<dyn EngineObserver>);
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+/// A [`MetricsSink`], shared between an engine and its clones
+#[derive(Clone)]
+struct Metrics(Arc<dyn MetricsSink>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Metrics")
+    }
+}
+
+/// Configures an [`Engine`], starting from what [`Engine::new`] gives
+///
+/// Created by [`Engine::builder`].
+///
+/// # Example
+/// ```
+/// use ast::{Engine, ParseEvalError};
+///
+/// let engine = Engine::builder()
+///     .with_function("lerp", 3, |args| Ok(args[0] + (args[1] - args[0]) * args[2]))
+///     .with_constant("g", 9.81)
+///     .max_depth(64)
+///     .build();
+///
+/// let vars = engine.environment();
+/// assert_eq!(engine.eval("lerp(0, g, 0.5) * 2", &vars).unwrap(), 9.81);
+/// assert!(matches!(
+///     engine.eval(&vec!["1"; 100].join(" + "), &vars),
+///     Err(ParseEvalError::TooDeep { depth: 100, limit: 64 })
+/// ));
+/// ```
+#[derive(Debug, Clone)]
+pub struct EngineBuilder {
+    /// The syntax formulas are written in
+    parser: ParserConfig,
+    /// The functions formulas can call
+    functions: FunctionRegistry,
+    /// Values every formula can use
+    constants: Environment,
+    /// How formulas are evaluated
+    config: EvalConfig,
+    /// The most formula texts whose trees are kept
+    capacity: usize,
+    /// The deepest a formula's tree may be, if there's a limit
+    max_depth: Option<usize>,
+    /// The longest a number literal may be, if there's a limit
+    max_literal_length: Option<usize>,
+    /// The longest a variable or function name may be, if there's a limit
+    max_name_length: Option<usize>,
+    /// Told about everything the engine does, if set
+    observer: Option<Observer>,
+    /// Counts and times what the engine does, if set
+    metrics: Option<Metrics>,
+}
+
+/// The trees of recently parsed formulas, by their text
+#[derive(Debug, Default)]
+struct Trees {
+    /// Each formula and when it was last used
+    trees: HashMap<String, (Formula, u64)>,
+    /// The formulas by when they were last used, oldest first
+    recency: BTreeMap<u64, String>,
+    /// Counts up with every use, to order the formulas
+    clock: u64,
+}
+
+impl Engine {
+    /// An engine with the default syntax, the standard functions, no
+    /// constants and the default evaluation settings
+    pub fn new() -> Self {
+        Engine::builder().build()
+    }
+
+    /// Configure an engine one setting at a time
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder {
+            parser: ParserConfig::default(),
+            functions: FunctionRegistry::standard(),
+            constants: Environment::new(),
+            config: EvalConfig::default(),
+            capacity: 1024,
+            max_depth: None,
+            max_literal_length: None,
+            max_name_length: None,
+            observer: None,
+            metrics: None,
+        }
+    }
+
+    /// Use a different syntax
+    pub fn with_parser_config(mut self, parser: ParserConfig) -> Self {
+        self.parser = parser;
+        self.clear_cache();
+        self
+    }
+
+    /// Use a different set of functions
+    pub fn with_functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Use a prelude's functions and constants
+    pub fn with_prelude(mut self, prelude: Prelude) -> Self {
+        self.functions = prelude.functions().clone();
+        self.constants = Arc::clone(prelude.constants());
+        self
+    }
+
+    /// Use different evaluation settings
+    pub fn with_config(mut self, config: EvalConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Keep the trees of up to `capacity` formulas, or none if it's 0
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self.clear_cache();
+        self
+    }
+
+    /// The syntax formulas are written in
+    pub fn parser_config(&self) -> &ParserConfig {
+        &self.parser
+    }
+
+    /// The functions formulas can call
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    /// The constants every formula can use
+    pub fn constants(&self) -> &Arc<Environment> {
+        &self.constants
+    }
+
+    /// How formulas are evaluated
+    pub fn config(&self) -> &EvalConfig {
+        &self.config
+    }
+
+    /// Parse a formula, or take its tree from the cache
+    pub fn compile(&self, formula: &str) -> Result<Formula, ParseEvalError> {
+        let _span = StageSpan::enter(Stage::Compile, formula);
+        let start = self.start();
+        let cached = self.cache().get(formula);
+        let hit = cached.is_some();
+        let compiled = match cached {
+            Some(compiled) => Ok(compiled),
+            None => self.parse(formula),
+        };
+        self.report(Stage::Compile, formula, start, hit, &compiled);
+        compiled
+    }
+
+    /// Evaluate a compiled formula with `vars` as its variables
+    ///
+    /// The variables hide constants of the same name. Passing an environment
+    /// from [`Engine::environment`] saves copying the variables in.
+    pub fn run(&self, formula: &Formula, vars: &Environment) -> Result<f64, ParseEvalError> {
+        let _span = StageSpan::enter(Stage::Evaluate, &formula.text);
+        let start = self.start();
+        let result = self
+            .with_constants(vars, |env| self.evaluator(env).evaluate(&formula.expr))
+            .map_err(ParseEvalError::from);
+        if let Some(start) = start {
+            self.notify(EngineEvent {
+                stage: Stage::Evaluate,
+                formula: &formula.text,
+                nodes: formula.nodes,
+                cached: false,
+                duration: start.elapsed(),
+                error: result.as_ref().err(),
+            });
+        }
+        result
+    }
+
+    /// Parse a formula, unless it's cached, and evaluate it with `vars` as its variables
+    pub fn eval(&self, formula: &str, vars: &Environment) -> Result<f64, ParseEvalError> {
+        self.run(&self.compile(formula)?, vars)
+    }
+
+    /// An empty scope over the constants, for one evaluation's variables
+    pub fn environment(&self) -> Environment {
+        self.constants.child()
+    }
+
+    /// An evaluator with this engine's functions and settings, looking variables up in `env`
+    ///
+    /// For what [`Engine::run`] doesn't offer, such as results that aren't
+    /// numbers. `env` should usually come from [`Engine::environment`], to
+    /// see the constants.
+    pub fn evaluator<'a>(&'a self, env: &'a Environment) -> Evaluator<'a> {
+        Evaluator::new(env)
+            .with_functions(&self.functions)
+            .with_config(self.config)
+    }
+
+    /// Forget every cached tree
+    pub fn clear_cache(&self) {
+        *self.cache() = Trees::default();
+    }
+
+    /// How many formulas have their trees cached
+    pub fn cached(&self) -> usize {
+        self.cache().trees.len()
+    }
+
+    /// A formula for a tree that wasn't parsed here, checked against the
+    /// engine's limits
+    ///
+    /// A tree nested more deeply than [`ParserConfig::max_depth`] lets text
+    /// nest is refused too, since evaluating it could overflow the stack. The
+    /// formula's text is the tree written out by [`format`](crate::format).
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub(crate) fn adopt(&self, expr: Expr) -> Result<Formula, ParseEvalError> {
+        let (nesting, limit) = (expr.nesting(), self.parser.max_depth);
+        if nesting > limit {
+            return Err(ParseEvalError::TooDeep {
+                depth: nesting,
+                limit,
+            });
+        }
+        self.check_depth(&expr)?;
+        Ok(Formula {
+            text: Arc::from(crate::format(&expr)),
+            nodes: expr.size(),
+            expr: Arc::new(expr),
+        })
+    }
+
+    /// Check a tree's depth against [`EngineBuilder::max_depth`], if it's set
+    fn check_depth(&self, expr: &Expr) -> Result<(), ParseEvalError> {
+        if let Some(limit) = self.max_depth {
+            let depth = expr.depth();
+            if depth > limit {
+                return Err(ParseEvalError::TooDeep { depth, limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a formula that isn't cached, checking its depth and caching it
+    fn parse(&self, formula: &str) -> Result<Formula, ParseEvalError> {
+        let _span = StageSpan::enter(Stage::Parse, formula);
+        let start = self.start();
+        let parsed = self
+            .check_lengths(formula)
+            .and_then(|()| whole(formula, parse_expression_with(formula, &self.parser)))
+            .and_then(|expr| {
+                self.check_depth(&expr)?;
+                Ok(Formula {
+                    text: Arc::from(formula),
+                    nodes: expr.size(),
+                    expr: Arc::new(expr),
+                })
+            });
+        self.report(Stage::Parse, formula, start, false, &parsed);
+        if let Ok(parsed) = &parsed
+            && self.capacity > 0
+        {
+            self.cache().insert(formula, parsed.clone(), self.capacity);
+        }
+        parsed
+    }
+
+    /// Check a formula's number literals and names against the length limits, before parsing it
+    ///
+    /// Literals and names are found as runs of ASCII letters, digits, `_`
+    /// and `.`, which covers everything the parser reads as one: a run
+    /// starting with a digit or `.` is a literal, any other a name. The
+    /// digits of an exponent after its sign, as in `1e+999`, count as a
+    /// literal of their own.
+    fn check_lengths(&self, formula: &str) -> Result<(), ParseEvalError> {
+        if self.max_literal_length.is_none() && self.max_name_length.is_none() {
+            return Ok(());
+        }
+        let bytes = formula.as_bytes();
+        let in_word = |b: &u8| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.');
+        let mut position = 0;
+        while position < bytes.len() {
+            let length = bytes[position..].iter().take_while(|b| in_word(b)).count();
+            if length == 0 {
+                position += 1;
+                continue;
+            }
+            let literal = bytes[position].is_ascii_digit() || bytes[position] == b'.';
+            match (literal, self.max_literal_length, self.max_name_length) {
+                (true, Some(limit), _) if length > limit => {
+                    return Err(ParseEvalError::LiteralTooLong {
+                        position,
+                        length,
+                        limit,
+                    });
+                }
+                (false, _, Some(limit)) if length > limit => {
+                    return Err(ParseEvalError::NameTooLong {
+                        position,
+                        length,
+                        limit,
+                    });
+                }
+                _ => position += length,
+            }
+        }
+        Ok(())
+    }
+
+    /// When a stage started, if there's an observer, metrics sink or `tracing` subscriber to tell about it
+    fn start(&self) -> Option<Instant> {
+        (self.observer.is_some() || self.metrics.is_some() || observer::traced()).then(Instant::now)
+    }
+
+    /// Tell the observer, metrics sink and `tracing` subscriber that a stage that started at `start` gave `result`
+    fn report(
+        &self,
+        stage: Stage,
+        formula: &str,
+        start: Option<Instant>,
+        cached: bool,
+        result: &Result<Formula, ParseEvalError>,
+    ) {
+        if let Some(start) = start {
+            self.notify(EngineEvent {
+                stage,
+                formula,
+                nodes: result.as_ref().map_or(0, |compiled| compiled.nodes),
+                cached,
+                duration: start.elapsed(),
+                error: result.as_ref().err(),
+            });
+        }
+    }
+
+    /// Pass an event to the observer, metrics sink and `tracing` subscriber
+    fn notify(&self, event: EngineEvent<'_>) {
+        #[cfg(feature = "tracing")]
+        observer::trace(&event);
+        if let Some(Observer(observer)) = &self.observer {
+            observer.observe(&event);
+        }
+        if let Some(Metrics(sink)) = &self.metrics {
+            metrics::update(sink.as_ref(), &event);
+        }
+    }
+
+    /// Call `f` with `vars` in front of the constants
+    fn with_constants<T>(&self, vars: &Environment, f: impl FnOnce(&Environment) -> T) -> T {
+        let over_constants = vars
+            .parent()
+            .is_some_and(|parent| Arc::ptr_eq(parent, &self.constants));
+        if over_constants || self.constants.iter().next().is_none() {
+            return f(vars);
+        }
+        let mut env = self.environment();
+        for (name, value) in vars.iter() {
+            env.set(name, value);
+        }
+        f(&env)
+    }
+
+    /// The cache, locked
+    fn cache(&self) -> std::sync::MutexGuard<'_, Trees> {
+        // The cache is always consistent between calls, so a panic while
+        // it was locked can't have broken it
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Trees {
+    /// A formula, marking it as just used
+    fn get(&mut self, formula: &str) -> Option<Formula> {
+        let (compiled, last_used) = self.trees.get_mut(formula)?;
+        let key = self.recency.remove(last_used)?;
+        self.clock += 1;
+        *last_used = self.clock;
+        self.recency.insert(self.clock, key);
+        Some(compiled.clone())
+    }
+
+    /// Add a formula, dropping the least recently used to keep at most `capacity`
+    fn insert(&mut self, formula: &str, compiled: Formula, capacity: usize) {
+        if let Some((_, last_used)) = self.trees.remove(formula) {
+            self.recency.remove(&last_used);
+        }
+        while self.trees.len() >= capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.trees.remove(&oldest);
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, formula.to_string());
+        self.trees
+            .insert(formula.to_string(), (compiled, self.clock));
+    }
+}
+
+impl EngineBuilder {
+    /// Use a different syntax
+    pub fn parser_config(mut self, parser: ParserConfig) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Use a different set of functions, in place of the standard ones
+    pub fn functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Use a prelude's functions, and add its constants
+    pub fn prelude(mut self, prelude: Prelude) -> Self {
+        self.functions = prelude.functions().clone();
+        for (name, value) in prelude.constants().iter() {
+            self.constants.set(name, value);
+        }
+        self
+    }
+
+    /// Add a function, replacing any of the same name, as
+    /// [`FunctionRegistry::register`] does
+    pub fn with_function(
+        mut self,
+        name: impl Into<String>,
+        arity: impl Into<Arity>,
+        function: impl Fn(&[f64]) -> Result<f64, EvaluationError> + Send + Sync + 'static,
+    ) -> Self {
+        self.functions.register(name, arity, function);
+        self
+    }
+
+    /// Add a constant, replacing any of the same name
+    pub fn with_constant(mut self, name: &str, value: f64) -> Self {
+        self.constants.set(name, value);
+        self
+    }
+
+    /// Use different evaluation settings
+    pub fn config(mut self, config: EvalConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reject formulas whose trees are more than `limit` deep, see [`Expr::depth`]
+    ///
+    /// Parts of the crate that walk trees recursively, such as code
+    /// generation, run out of stack on very deep trees; a limit keeps
+    /// formulas from untrusted sources within what they handle.
+    pub fn max_depth(mut self, limit: usize) -> Self {
+        self.max_depth = Some(limit);
+        self
+    }
+
+    /// Reject formulas with a number literal longer than `limit` characters
+    ///
+    /// Literals like `1e99999…` or thousands of digits take memory and time
+    /// to parse, all to end up as an infinity or a rounded float; a limit
+    /// rejects them from untrusted sources before the parser sees them.
+    pub fn max_literal_length(mut self, limit: usize) -> Self {
+        self.max_literal_length = Some(limit);
+        self
+    }
+
+    /// Reject formulas with a variable or function name longer than `limit` characters
+    ///
+    /// Names are copied into the tree and looked up when evaluating, so very
+    /// long ones cost memory and time for no use.
+    pub fn max_name_length(mut self, limit: usize) -> Self {
+        self.max_name_length = Some(limit);
+        self
+    }
+
+    /// Tell `observer` about every parse, compile and evaluation, see [`EngineObserver`]
+    pub fn observer(mut self, observer: impl EngineObserver + 'static) -> Self {
+        self.observer = Some(Observer(Arc::new(observer)));
+        self
+    }
+
+    /// Count parses, cache hits, evaluations and failures, and time parses
+    /// and evaluations, see [`MetricsSink`]
+    pub fn metrics(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics = Some(Metrics(Arc::new(sink)));
+        self
+    }
+
+    /// Keep the trees of up to `capacity` formulas, or none if it's 0
+    pub fn cache_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// The engine
+    pub fn build(self) -> Engine {
+        Engine {
+            parser: self.parser,
+            functions: self.functions,
+            constants: Arc::new(self.constants),
+            config: self.config,
+            capacity: self.capacity,
+            max_depth: self.max_depth,
+            max_literal_length: self.max_literal_length,
+            max_name_length: self.max_name_length,
+            observer: self.observer,
+            metrics: self.metrics,
+            cache: Mutex::new(Trees::default()),
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Clone for Engine {
+    /// The same configuration, with an empty cache
+    fn clone(&self) -> Self {
+        Engine {
+            parser: self.parser.clone(),
+            functions: self.functions.clone(),
+            constants: Arc::clone(&self.constants),
+            config: self.config,
+            capacity: self.capacity,
+            max_depth: self.max_depth,
+            max_literal_length: self.max_literal_length,
+            max_name_length: self.max_name_length,
+            observer: self.observer.clone(),
+            metrics: self.metrics.clone(),
+            cache: Mutex::new(Trees::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NullPolicy, Value};
+
+    /// Test evaluating with constants, variables hiding them, and other settings
+    #[test]
+    fn test_eval() {
+        let engine = Engine::new().with_prelude(Prelude::none().with_constant("rate", 0.5));
+        let vars: Environment = [("x", 10.0)].into_iter().collect();
+        assert_eq!(engine.eval("x * rate", &vars).unwrap(), 5.0);
+
+        let mut scoped = engine.environment();
+        scoped.set("rate", 0.25);
+        assert_eq!(
+            engine.eval("x * rate", &scoped).unwrap_err().to_string(),
+            "Undefined variable 'x'"
+        );
+        scoped.set("x", 10.0);
+        assert_eq!(engine.eval("x * rate", &scoped).unwrap(), 2.5);
+
+        assert!(matches!(
+            engine.eval("sqrt(x)", &vars),
+            Err(ParseEvalError::Evaluation(
+                EvaluationError::UnknownFunction(_)
+            ))
+        ));
+        assert!(matches!(
+            engine.eval("x +", &vars),
+            Err(ParseEvalError::Syntax { position: 3 })
+        ));
+
+        let config = EvalConfig {
+            nulls: NullPolicy::Coalesce,
+            ..EvalConfig::default()
+        };
+        let lenient = Engine::new()
+            .with_config(config)
+            .with_parser_config(ParserConfig::spreadsheet());
+        assert_eq!(lenient.eval("y + 1", &vars).unwrap(), 1.0);
+        let env = Environment::new();
+        let (_, ast) = crate::parse_expression("[1, 2]").unwrap();
+        assert!(matches!(
+            lenient.evaluator(&env).evaluate_value(&ast),
+            Ok(Value::Matrix(_))
+        ));
+    }
+
+    /// Test building an engine a setting at a time, and the depth limit
+    #[test]
+    fn test_builder() {
+        let engine = Engine::builder()
+            .with_constant("pi", 3.0)
+            .prelude(Prelude::scientific())
+            .with_constant("e", 2.0)
+            .with_function("twice", 1, |args| Ok(args[0] * 2.0))
+            .max_depth(3)
+            .cache_capacity(0)
+            .build();
+        let vars = Environment::new();
+        assert_eq!(engine.eval("twice(e)", &vars).unwrap(), 4.0);
+        assert_eq!(engine.eval("round(pi)", &vars).unwrap(), 3.0);
+        assert!(matches!(
+            engine.eval("-(-(-1))", &vars),
+            Err(ParseEvalError::TooDeep { depth: 4, limit: 3 })
+        ));
+
+        let bare = Engine::builder().functions(FunctionRegistry::new()).build();
+        assert!(bare.eval("twice(1)", &vars).is_err());
+        assert!(bare.eval("abs(1)", &vars).is_err());
+    }
+
+    /// Test rejecting long literals and names before parsing
+    #[test]
+    fn test_length_limits() {
+        let engine = Engine::builder()
+            .max_literal_length(8)
+            .max_name_length(4)
+            .build();
+        let vars: Environment = [("rate", 0.5)].into_iter().collect();
+        assert_eq!(engine.eval("12345.25 * rate", &vars).unwrap(), 6172.625);
+
+        let huge = format!("1 + 1e{}", "9".repeat(10_000));
+        assert!(matches!(
+            engine.eval(&huge, &vars),
+            Err(ParseEvalError::LiteralTooLong {
+                position: 4,
+                length: 10_002,
+                limit: 8
+            })
+        ));
+        assert!(matches!(
+            engine.eval("2e+123456789", &vars),
+            Err(ParseEvalError::LiteralTooLong { position: 3, .. })
+        ));
+        assert!(matches!(
+            engine.eval("rates * 2", &vars),
+            Err(ParseEvalError::NameTooLong {
+                position: 0,
+                length: 5,
+                limit: 4
+            })
+        ));
+        assert_eq!(
+            engine
+                .eval("x + 0.000000001", &vars)
+                .unwrap_err()
+                .to_string(),
+            "Number at character 4 is 11 characters long, more than the limit of 8"
+        );
+
+        // Either limit works on its own
+        let names = Engine::builder().max_name_length(4).build();
+        assert!(names.eval(&"1".repeat(20), &vars).is_ok());
+    }
+
+    /// Test that parsed trees are reused, and the least recently used dropped
+    #[test]
+    fn test_cache() {
+        let engine = Engine::new().with_cache_capacity(2);
+        let first = engine.compile("1 + 1").unwrap();
+        assert!(Arc::ptr_eq(
+            &first.expr,
+            &engine.compile("1 + 1").unwrap().expr
+        ));
+        engine.compile("2 + 2").unwrap();
+        engine.compile("1 + 1").unwrap();
+        engine.compile("3 + 3").unwrap();
+        assert_eq!(engine.cached(), 2);
+        assert!(!engine.cache().trees.contains_key("2 + 2"));
+        assert!(Arc::ptr_eq(
+            &first.expr,
+            &engine.compile("1 + 1").unwrap().expr
+        ));
+
+        let uncached = Engine::new().with_cache_capacity(0);
+        assert_eq!(uncached.eval("6 * 7", &Environment::new()).unwrap(), 42.0);
+        assert_eq!(uncached.cached(), 0);
+        assert_eq!(engine.clone().cached(), 0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Variable environments
+//!
+//! An [`Environment`] holds the values of the variables an expression can
+//! refer to. It is passed to [`evaluate_with`](crate::evaluate_with), which
+//! looks up each [`Expr::Var`](crate::Expr::Var) in it.
+//!
+//! Environments can be layered: [`Environment::child`] makes an empty scope
+//! that sees everything in its parent, so per-request variables can sit
+//! over a shared set of constants without copying them.
+
+use std::{collections::HashMap, iter, sync::Arc};
+
+/// A set of named variables and their values
+///
+/// # Example
+/// ```
+/// use ast::{evaluate_with, parse_expression, Environment};
+///
+/// let mut env = Environment::new();
+/// env.set("width", 3.0);
+/// env.set("height", 4.0);
+///
+/// let (_, ast) = parse_expression("width * height").unwrap();
+/// assert_eq!(evaluate_with(&ast, &env).unwrap(), 12.0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// The variables set in this scope, with `None` for ones removed from it
+    /// that a parent still has
+    variables: HashMap<String, Option<f64>>,
+    /// The scope that variables not found here are looked up in
+    parent: Option<Arc<Environment>>,
+}
+
+impl Environment {
+    /// Create an empty environment
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    /// An empty scope inside this one
+    ///
+    /// Variables are looked up in the child first and then here. Setting or
+    /// removing a variable in the child leaves this scope as it is, which
+    /// can be shared by any number of children.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use ast::{evaluate_with, parse_expression, Environment};
+    ///
+    /// let constants: Arc<Environment> = Arc::new([("g", 9.81)].into_iter().collect());
+    /// let mut request = constants.child();
+    /// request.set("t", 2.0);
+    ///
+    /// let (_, ast) = parse_expression("g * t * t / 2").unwrap();
+    /// assert_eq!(evaluate_with(&ast, &request).unwrap(), 19.62);
+    /// assert!(!constants.contains("t"));
+    /// ```
+    pub fn child(self: &Arc<Self>) -> Environment {
+        Environment {
+            variables: HashMap::new(),
+            parent: Some(Arc::clone(self)),
+        }
+    }
+
+    /// The scope this one is inside, if it was made by [`Environment::child`]
+    pub fn parent(&self) -> Option<&Arc<Environment>> {
+        self.parent.as_ref()
+    }
+
+    /// Set a variable in this scope, replacing any previous value
+    pub fn set(&mut self, name: impl Into<String>, value: f64) {
+        self.variables.insert(name.into(), Some(value));
+    }
+
+    /// Look up the value of a variable, in this scope or the nearest parent that has it
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.scopes()
+            .find_map(|scope| scope.variables.get(name))
+            .copied()
+            .flatten()
+    }
+
+    /// Whether a variable is defined
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Remove a variable, returning its value if it was defined
+    ///
+    /// A value from a parent scope is hidden rather than removed from the parent.
+    pub fn remove(&mut self, name: &str) -> Option<f64> {
+        let value = self.get(name);
+        let inherited = self
+            .parent
+            .as_deref()
+            .is_some_and(|parent| parent.contains(name));
+        if inherited {
+            self.variables.insert(name.to_string(), None);
+        } else {
+            self.variables.remove(name);
+        }
+        value
+    }
+
+    /// Iterate over all variables and their values, in no particular order
+    ///
+    /// A variable set in several scopes appears once, with the value [`Environment::get`] gives.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.scopes().enumerate().flat_map(move |(depth, scope)| {
+            scope.variables.iter().filter_map(move |(name, value)| {
+                let hidden = self
+                    .scopes()
+                    .take(depth)
+                    .any(|inner| inner.variables.contains_key(name));
+                match value {
+                    Some(value) if !hidden => Some((name.as_str(), *value)),
+                    _ => None,
+                }
+            })
+        })
+    }
+
+    /// This scope and the ones around it, innermost first
+    fn scopes(&self) -> impl Iterator<Item = &Environment> {
+        iter::successors(Some(self), |scope| scope.parent.as_deref())
+    }
+}
+
+/// Environments are equal when they give the same variables the same
+/// values, however they're split into scopes
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().count() == other.iter().count()
+            && self
+                .iter()
+                .all(|(name, value)| other.get(name) == Some(value))
+    }
+}
+
+impl<K: Into<String>> FromIterator<(K, f64)> for Environment {
+    fn from_iter<I: IntoIterator<Item = (K, f64)>>(iter: I) -> Self {
+        Environment {
+            variables: iter
+                .into_iter()
+                .map(|(name, value)| (name.into(), Some(value)))
+                .collect(),
+            parent: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test lookups, shadowing and hiding across scopes
+    #[test]
+    fn test_scopes() {
+        let globals: Arc<Environment> = Arc::new([("a", 1.0), ("b", 2.0)].into_iter().collect());
+        let mut middle = globals.child();
+        middle.set("b", 20.0);
+        middle.set("c", 30.0);
+        let middle = Arc::new(middle);
+        let mut inner = middle.child();
+        assert_eq!(inner.remove("a"), Some(1.0));
+        inner.set("d", 4.0);
+
+        assert_eq!(inner.get("a"), None);
+        assert_eq!(inner.get("b"), Some(20.0));
+        assert_eq!(globals.get("b"), Some(2.0));
+        let mut visible: Vec<_> = inner.iter().collect();
+        visible.sort_by(|x, y| x.0.cmp(y.0));
+        assert_eq!(visible, [("b", 20.0), ("c", 30.0), ("d", 4.0)]);
+
+        inner.set("a", 5.0);
+        assert_eq!(inner.get("a"), Some(5.0));
+        assert_eq!(inner.remove("d"), Some(4.0));
+        assert_eq!(inner.remove("d"), None);
+        assert!(Arc::ptr_eq(inner.parent().unwrap(), &middle));
+
+        let flat: Environment = [("a", 5.0), ("b", 20.0), ("c", 30.0)].into_iter().collect();
+        assert_eq!(inner, flat);
+        assert_ne!(*middle, flat);
+    }
+}
@@ -0,0 +1,175 @@
+//! Checking that two formulas agree, by trying them on random inputs
+//!
+//! Rewriting a long formula, to simplify it or to split it up, shouldn't
+//! change what it computes. Proving that in general is hard, but trying
+//! both versions on many inputs catches most mistakes: [`probably_equivalent`]
+//! evaluates them side by side and gives the first input they disagree on.
+
+use std::{fmt, ops::RangeInclusive};
+
+use crate::{
+    Environment, Expr,
+    differential::{Random, agree},
+    evaluate_with,
+};
+
+/// The seed the inputs are drawn from, so a check always tries the same ones
+const SEED: u64 = 0xe90a1;
+
+/// How far apart two results may be, relative to their size (or absolutely, below 1)
+const TOLERANCE: f64 = 1e-9;
+
+/// Inputs two formulas give different results for
+#[derive(Debug, PartialEq, Clone)]
+pub struct Counterexample {
+    /// Each variable's value
+    pub inputs: Vec<(String, f64)>,
+    /// What the first formula gives, or its error message
+    pub left: Result<f64, String>,
+    /// What the second formula gives, or its error message
+    pub right: Result<f64, String>,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |result: &Result<f64, String>| match result {
+            Ok(value) => value.to_string(),
+            Err(message) => format!("error '{}'", message),
+        };
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        write!(
+            f,
+            "with {}, the first gives {} but the second gives {}",
+            inputs.join(", "),
+            show(&self.left),
+            show(&self.right)
+        )
+    }
+}
+
+/// Whether two formulas give the same results for `samples` random inputs, or a counterexample
+///
+/// Each variable is drawn from its range in `var_ranges`; half of the
+/// samples are rounded to whole numbers, since formulas with comparisons
+/// often only differ at the boundary, as `x < 1` and `x <= 1` do. The first
+/// two samples put every variable at the start of its range and at its
+/// end. Results agree to within a relative 10⁻⁹, and two errors agree
+/// whatever they are. A variable without a range is undefined.
+///
+/// The inputs come from a fixed seed, so the same call always tries the same ones.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, probably_equivalent};
+///
+/// let (_, original) = parse_expression("price * qty - price * qty * discount").unwrap();
+/// let (_, refactored) = parse_expression("price * qty * (1 - discount)").unwrap();
+/// let ranges = [("price", 0.0..=100.0), ("qty", 0.0..=50.0), ("discount", 0.0..=0.5)];
+/// assert!(probably_equivalent(&original, &refactored, &ranges, 1000).is_ok());
+///
+/// let (_, wrong) = parse_expression("price * qty * (1 - discount) + piecewise((qty == 7, 1), 0)").unwrap();
+/// let counterexample = probably_equivalent(&original, &wrong, &ranges, 1000).unwrap_err();
+/// assert_eq!(counterexample.inputs[1], ("qty".to_string(), 7.0));
+/// ```
+pub fn probably_equivalent(
+    a: &Expr,
+    b: &Expr,
+    var_ranges: &[(&str, RangeInclusive<f64>)],
+    samples: usize,
+) -> Result<(), Counterexample> {
+    let mut random = Random(SEED);
+    let mut env = Environment::new();
+    for sample in 0..samples {
+        let inputs: Vec<(String, f64)> = var_ranges
+            .iter()
+            .map(|(name, range)| {
+                let (low, high) = (*range.start(), *range.end());
+                let value = match sample {
+                    0 => low,
+                    1 => high,
+                    _ => {
+                        let value = low + (high - low) * random.unit();
+                        // Only round when that stays in the range
+                        if sample % 2 == 0 && (low..=high).contains(&value.round()) {
+                            value.round()
+                        } else {
+                            value
+                        }
+                    }
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        for (name, value) in &inputs {
+            env.set(name, *value);
+        }
+        let left = evaluate_with(a, &env).map_err(|error| error.to_string());
+        let right = evaluate_with(b, &env).map_err(|error| error.to_string());
+        if !agree(&left, &right, TOLERANCE) {
+            return Err(Counterexample {
+                inputs,
+                left,
+                right,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// An expression that must parse
+    fn expr(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test formulas that agree, including on errors and within rounding
+    #[test]
+    fn test_equivalent() {
+        let ranges = [("x", -10.0..=10.0), ("y", 0.0..=1.0)];
+        for (a, b) in [
+            ("(x + y) * (x - y)", "x * x - y * y"),
+            ("0.1 * x + 0.2 * x", "0.3 * x"),
+            ("1 / (x - x)", "2 / (y - y)"),
+            ("piecewise((x < 0, -x), x)", "abs(x)"),
+            ("!(x > 0 && y > 0)", "x <= 0 || y <= 0"),
+        ] {
+            assert_eq!(
+                probably_equivalent(&expr(a), &expr(b), &ranges, 500),
+                Ok(()),
+                "'{}' and '{}'",
+                a,
+                b
+            );
+        }
+    }
+
+    /// Test that differences, even at one boundary, are found and reported
+    #[test]
+    fn test_counterexample() {
+        let ranges = [("x", -10.0..=10.0)];
+        let boundary = probably_equivalent(&expr("x < 1"), &expr("x <= 1"), &ranges, 500);
+        let counterexample = boundary.unwrap_err();
+        assert_eq!(counterexample.inputs, [("x".to_string(), 1.0)]);
+        assert_eq!(
+            counterexample.to_string(),
+            "with x = 1, the first gives 0 but the second gives 1"
+        );
+
+        // The first sample is at the start of the ranges
+        let nonzero = probably_equivalent(&expr("x / x"), &expr("1"), &ranges, 1);
+        assert!(nonzero.is_ok());
+        let ranges = [("x", 0.0..=1.0)];
+        let zero = probably_equivalent(&expr("x / x"), &expr("1"), &ranges, 1).unwrap_err();
+        assert_eq!(zero.left, Err("Division by zero".to_string()));
+
+        assert!(probably_equivalent(&expr("x"), &expr("x + 1"), &ranges, 0).is_ok());
+    }
+}
@@ -0,0 +1,1065 @@
+//! Tree-walking evaluation
+//!
+//! An [`Evaluator`] holds everything an expression can refer to while being
+//! evaluated: the variables in an [`Environment`], and optionally a
+//! [`CellResolver`] for spreadsheet cell references, and the
+//! [`FunctionRegistry`] that function calls are looked up in.
+//!
+//! [`Evaluator::evaluate`] produces numbers; [`Evaluator::evaluate_value`] also
+//! allows other kinds of [`Value`], such as dates. [`evaluate`](crate::evaluate)
+//! and [`evaluate_with`](crate::evaluate_with) are shortcuts for the common cases.
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    iter,
+    time::Instant,
+};
+
+use crate::{
+    AuditEvent, CancellationToken, CellRef, CellResolver, DivisionPolicy, DomainPolicy,
+    Environment, EvalConfig, EvaluationError, Expr, FunctionRegistry, Matrix, NullPolicy,
+    OverflowPolicy, SubnormalPolicy, Value, functions::STANDARD, profile::Counters,
+};
+
+/// Evaluates expressions against variables and other sources of values
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, Environment, Evaluator};
+///
+/// let env: Environment = [("x", 4.0)].into_iter().collect();
+/// let evaluator = Evaluator::new(&env);
+///
+/// let (_, ast) = parse_expression("x * x - 1").unwrap();
+/// assert_eq!(evaluator.evaluate(&ast).unwrap(), 15.0);
+/// ```
+#[derive(Clone, Copy)]
+pub struct Evaluator<'a> {
+    /// Variables available to the expression
+    env: &'a Environment,
+    /// Where cell references get their values, if anywhere
+    cells: Option<&'a dyn CellResolver>,
+    /// Functions that calls are looked up in
+    functions: &'a FunctionRegistry,
+    /// Options for how to evaluate
+    config: EvalConfig,
+    /// The innermost variable bound by `let`, which hides `env`
+    bindings: Option<&'a Binding<'a>>,
+    /// Where to record what the evaluation reads and calls, if anywhere
+    audit: Option<&'a RefCell<Vec<AuditEvent>>>,
+    /// When the evaluation has to give up, if there's a deadline
+    deadline: Option<&'a Deadline>,
+    /// Asks the evaluation to stop, if anything can
+    cancellation: Option<&'a CancellationToken>,
+    /// Where to count the time spent in each node, if anywhere
+    profile: Option<&'a RefCell<Counters>>,
+}
+
+/// How many nodes are evaluated between looks at the clock
+const DEADLINE_INTERVAL: u32 = 256;
+
+/// A point in time an evaluation has to finish by
+struct Deadline {
+    /// When the evaluation has to give up
+    at: Instant,
+    /// How many nodes have been evaluated so far
+    ticks: Cell<u32>,
+}
+
+impl Deadline {
+    /// Count a node, failing if it's one of those the clock is checked at and time is up
+    fn tick(&self) -> Result<(), EvaluationError> {
+        let ticks = self.ticks.get();
+        self.ticks.set(ticks.wrapping_add(1));
+        if ticks.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= self.at {
+            return Err(EvaluationError::Timeout);
+        }
+        Ok(())
+    }
+}
+
+/// A variable bound by `let`, kept on the stack while its body is evaluated
+struct Binding<'a> {
+    /// The variable's name
+    name: &'a str,
+    /// Its value, or `None` when the value was null and the name is left missing
+    value: Option<Value>,
+    /// The variable bound further out, if any
+    outer: Option<&'a Binding<'a>>,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Create an evaluator that looks variables up in `env`
+    ///
+    /// Function calls use [`FunctionRegistry::standard`] until
+    /// [`Evaluator::with_functions`] says otherwise.
+    pub fn new(env: &'a Environment) -> Self {
+        Evaluator {
+            env,
+            cells: None,
+            functions: &STANDARD,
+            config: EvalConfig::default(),
+            bindings: None,
+            audit: None,
+            deadline: None,
+            cancellation: None,
+            profile: None,
+        }
+    }
+
+    /// Resolve spreadsheet cell references with `cells`
+    pub fn with_cells(mut self, cells: &'a dyn CellResolver) -> Self {
+        self.cells = Some(cells);
+        self
+    }
+
+    /// Look function calls up in `functions`
+    pub fn with_functions(mut self, functions: &'a FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Evaluate according to `config`
+    pub fn with_config(mut self, config: EvalConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Stop evaluating with [`EvaluationError::Cancelled`] once `token` is cancelled
+    ///
+    /// The token is checked before every node, so an evaluation stops soon
+    /// after the token is tripped, and so does every later evaluation by
+    /// this evaluator: a loop over many formulas can share one evaluator
+    /// and be abandoned as a whole. Functions defined in a
+    /// [`Session`](crate::Session) evaluate their bodies separately, so a
+    /// call already running finishes first.
+    pub fn with_cancellation(mut self, token: &'a CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Write a result in the [`NumberFormat`](crate::NumberFormat) of this evaluator's [`EvalConfig`]
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Environment, EvalConfig, Evaluator, Notation, NumberFormat};
+    ///
+    /// let format = NumberFormat { notation: Notation::Engineering, ..NumberFormat::significant_figures(3) };
+    /// let config = EvalConfig { format, ..EvalConfig::default() };
+    /// let env = Environment::new();
+    /// let evaluator = Evaluator::new(&env).with_config(config);
+    ///
+    /// let (_, ast) = parse_expression("1 / 4700").unwrap();
+    /// let result = evaluator.evaluate_value(&ast).unwrap();
+    /// assert_eq!(evaluator.format(&result), "213e-6");
+    /// ```
+    pub fn format(&self, value: &Value) -> String {
+        value.format(&self.config.format)
+    }
+
+    /// Record what the evaluation reads and calls in `audit`
+    pub(crate) fn with_audit(mut self, audit: &'a RefCell<Vec<AuditEvent>>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Count the time spent in each node in `counters`
+    pub(crate) fn with_profile(mut self, counters: &'a RefCell<Counters>) -> Self {
+        self.profile = Some(counters);
+        self
+    }
+
+    /// Whether what the evaluation reads and calls is being recorded
+    pub(crate) fn is_audited(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Add an event to the audit trail, if there is one
+    pub(crate) fn record(&self, event: impl FnOnce() -> AuditEvent) {
+        if let Some(audit) = self.audit {
+            audit.borrow_mut().push(event());
+        }
+    }
+
+    /// Evaluate an expression to a numeric result
+    ///
+    /// Expressions that produce another kind of value, such as a date, fail
+    /// with [`EvaluationError::TypeMismatch`]; use [`Evaluator::evaluate_value`]
+    /// for those.
+    pub fn evaluate(&self, expr: &Expr) -> Result<f64, EvaluationError> {
+        self.evaluate_value(expr)?.into_number()
+    }
+
+    /// Evaluate an expression to a value of any type
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Environment, Evaluator, Value};
+    ///
+    /// let env = Environment::new();
+    /// let (_, ast) = parse_expression("date(2024, 2, 28) + days(2)").unwrap();
+    /// let value = Evaluator::new(&env).evaluate_value(&ast).unwrap();
+    /// assert!(matches!(value, Value::Date(_)));
+    /// assert_eq!(value.to_string(), "2024-03-01");
+    /// ```
+    pub fn evaluate_value(&self, expr: &Expr) -> Result<Value, EvaluationError> {
+        if let Some(deadline) = self.deadline {
+            deadline.tick()?;
+        }
+        if let Some(token) = self.cancellation
+            && token.is_cancelled()
+        {
+            return Err(EvaluationError::Cancelled);
+        }
+        let Some(profile) = self.profile else {
+            return self.checked(self.evaluate_node(expr)?);
+        };
+        profile.borrow_mut().enter();
+        let start = Instant::now();
+        let result = self
+            .evaluate_node(expr)
+            .and_then(|value| self.checked(value));
+        profile.borrow_mut().leave(expr, start.elapsed());
+        result
+    }
+
+    /// Evaluate an expression to a numeric result, giving up once `deadline` has passed
+    ///
+    /// The clock is looked at every few hundred nodes, so an evaluation
+    /// can run slightly past the deadline before failing with
+    /// [`EvaluationError::Timeout`]. Functions defined in a
+    /// [`Session`](crate::Session) evaluate their bodies separately, so time
+    /// spent inside one call is only noticed once it returns.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use ast::{parse_expression, Environment, EvaluationError, Evaluator};
+    ///
+    /// let env = Environment::new();
+    /// let (_, ast) = parse_expression("2 * 512").unwrap();
+    /// let evaluator = Evaluator::new(&env);
+    ///
+    /// let soon = Instant::now() + Duration::from_secs(1);
+    /// assert_eq!(evaluator.evaluate_with_deadline(&ast, soon).unwrap(), 1024.0);
+    /// assert!(matches!(
+    ///     evaluator.evaluate_with_deadline(&ast, Instant::now()),
+    ///     Err(EvaluationError::Timeout)
+    /// ));
+    /// ```
+    pub fn evaluate_with_deadline(
+        &self,
+        expr: &Expr,
+        deadline: Instant,
+    ) -> Result<f64, EvaluationError> {
+        let deadline = Deadline {
+            at: deadline,
+            ticks: Cell::new(0),
+        };
+        Evaluator {
+            deadline: Some(&deadline),
+            ..*self
+        }
+        .evaluate(expr)
+    }
+
+    /// A value, with numbers checked against the configured range
+    fn checked(&self, value: Value) -> Result<Value, EvaluationError> {
+        match value {
+            Value::Number(value) => self.check_range(value).map(Value::Number),
+            value => Ok(value),
+        }
+    }
+
+    /// Evaluate one node, whose operands are evaluated by [`Evaluator::evaluate_value`]
+    fn evaluate_node(&self, expr: &Expr) -> Result<Value, EvaluationError> {
+        match expr {
+            Expr::Float(value) => Ok(Value::Number(*value)),
+            Expr::Var(name) => self
+                .variable(name)
+                .or_else(|| self.missing())
+                .ok_or_else(|| EvaluationError::UndefinedVariable(name.clone())),
+            Expr::CellRef(cell) => self
+                .cell(*cell)
+                .map(Value::Number)
+                .or_else(|| self.missing())
+                .ok_or(EvaluationError::UnresolvedCell(*cell)),
+            Expr::CellRange(from, to) => Err(EvaluationError::RangeNotAllowed(*from, *to)),
+            Expr::Add(..) | Expr::Sub(..) if self.config.compensated_sums => self.sum(expr),
+            Expr::Add(..)
+            | Expr::Sub(..)
+            | Expr::Mul(..)
+            | Expr::Div(..)
+            | Expr::Compare(..)
+            | Expr::And(..)
+            | Expr::Or(..) => self.chain(expr),
+            Expr::Neg(inner) => {
+                let mut operand = [self.evaluate_value(inner)?];
+                if !self.replace_nulls(&mut operand) {
+                    return Ok(Value::Null);
+                }
+                let [operand] = operand;
+                operand.negate()
+            }
+            Expr::Not(inner) => {
+                let mut operand = [self.evaluate_value(inner)?];
+                if !self.replace_nulls(&mut operand) {
+                    return Ok(Value::Null);
+                }
+                Ok(Value::from(!operand[0].truth()?))
+            }
+            Expr::Call(name, args) => self.call(name, args),
+            Expr::Named(_, value) => self.evaluate_value(value),
+            Expr::Let(name, value, body) => self.bind(name, value, body),
+            Expr::Piecewise(cases, default) => self.piecewise(cases, default.as_deref()),
+            Expr::List(elements) => self.list(elements),
+            Expr::Index(target, indices) => self.index(target, indices),
+            Expr::Error(_) => Err(EvaluationError::ContainsErrors),
+        }
+    }
+
+    /// Call a function by name
+    fn call(&self, name: &str, args: &[Expr]) -> Result<Value, EvaluationError> {
+        let function = self
+            .functions
+            .get(name)
+            .ok_or_else(|| EvaluationError::UnknownFunction(name.to_string()))?;
+        if !self.functions.permits(function) {
+            return Err(EvaluationError::NotPermitted(name.to_string()));
+        }
+        // Functions take their arguments in order, so named ones are moved
+        // into place and left out ones get their defaults
+        let arranged: Vec<Expr>;
+        let args = if function.needs_arranging(args) {
+            arranged = function
+                .arrange(name, args)?
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect();
+            &arranged
+        } else {
+            args
+        };
+        if !function.arity().accepts(args.len()) {
+            return Err(function.wrong_count(name, args.len()));
+        }
+        function.call(name, self, args)
+    }
+
+    /// Evaluate the body of a `let` with the name set to the value
+    ///
+    /// The binding lives on the stack for as long as the body is evaluated,
+    /// hiding any variable of the same name, rather than in a copy of the
+    /// environment.
+    fn bind(&self, name: &str, value: &Expr, body: &Expr) -> Result<Value, EvaluationError> {
+        // A null value leaves the name missing
+        let value = match self.evaluate_value(value)? {
+            Value::Null => None,
+            value => Some(value),
+        };
+        let binding = Binding {
+            name,
+            value,
+            outer: self.bindings,
+        };
+        Evaluator {
+            bindings: Some(&binding),
+            ..*self
+        }
+        .evaluate_value(body)
+    }
+
+    /// The value of a variable, from the innermost `let` that binds it or else the environment
+    fn variable(&self, name: &str) -> Option<Value> {
+        match iter::successors(self.bindings, |binding| binding.outer)
+            .find(|binding| binding.name == name)
+        {
+            Some(binding) => binding.value.clone(),
+            None => {
+                let value = self.env.get(name);
+                self.record(|| AuditEvent::Variable {
+                    name: name.to_string(),
+                    value,
+                });
+                value.map(Value::Number)
+            }
+        }
+    }
+
+    /// The value of a spreadsheet cell, if it has one
+    fn cell(&self, cell: CellRef) -> Option<f64> {
+        let value = self.cells.and_then(|cells| cells.cell(cell));
+        self.record(|| AuditEvent::Cell {
+            cell: cell.to_string(),
+            value,
+        });
+        value
+    }
+
+    /// Evaluate the value of the first case whose condition holds, or the default
+    fn piecewise(
+        &self,
+        cases: &[(Expr, Expr)],
+        default: Option<&Expr>,
+    ) -> Result<Value, EvaluationError> {
+        for (condition, value) in cases {
+            let mut condition = [self.evaluate_value(condition)?];
+            if !self.replace_nulls(&mut condition) {
+                return Ok(Value::Null);
+            }
+            if condition[0].truth()? {
+                return self.evaluate_value(value);
+            }
+        }
+        match default {
+            Some(default) => self.evaluate_value(default),
+            None => Err(EvaluationError::DomainError("piecewise".to_string())),
+        }
+    }
+
+    /// Evaluate a list: a vector of numbers, or a matrix with a vector for each row
+    fn list(&self, elements: &[Expr]) -> Result<Value, EvaluationError> {
+        let mut values = elements
+            .iter()
+            .map(|element| self.evaluate_value(element))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !self.replace_nulls(&mut values) {
+            return Ok(Value::Null);
+        }
+        if let Some(Value::Matrix(_)) = values.first() {
+            let rows = values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Matrix(row) if row.is_vector() => Ok(row),
+                    other => Err(EvaluationError::TypeMismatch {
+                        expected: "a vector",
+                        found: other.type_name(),
+                    }),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return Matrix::from_rows(&rows).map(Value::Matrix);
+        }
+        let numbers = values
+            .into_iter()
+            .map(Value::into_number)
+            .collect::<Result<_, _>>()?;
+        Ok(Value::Matrix(Matrix::vector(numbers)))
+    }
+
+    /// Evaluate element access, with indices counting from 1
+    ///
+    /// One index into a vector gives an element, and into any other matrix a row.
+    fn index(&self, target: &Expr, indices: &[Expr]) -> Result<Value, EvaluationError> {
+        let mut values = vec![self.evaluate_value(target)?];
+        for index in indices {
+            values.push(self.evaluate_value(index)?);
+        }
+        if !self.replace_nulls(&mut values) {
+            return Ok(Value::Null);
+        }
+        let mut values = values.into_iter();
+        let matrix = match values.next() {
+            Some(Value::Matrix(matrix)) => matrix,
+            other => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a matrix",
+                    found: other.map_or("null", |other| other.type_name()),
+                });
+            }
+        };
+        let position = |value: Value, length: usize| {
+            let index = value.into_number()?;
+            if index.fract() == 0.0 && index >= 1.0 && index <= length as f64 {
+                Ok(index as usize - 1)
+            } else {
+                Err(EvaluationError::IndexOutOfRange { index, length })
+            }
+        };
+        match (values.next(), values.next()) {
+            (Some(index), None) if matrix.is_vector() => {
+                let row = position(index, matrix.rows())?;
+                Ok(Value::Number(matrix.elements()[row]))
+            }
+            (Some(index), None) => {
+                let row = position(index, matrix.rows())?;
+                Ok(Value::Matrix(matrix.row(row)))
+            }
+            (Some(row), Some(column)) => {
+                let row = position(row, matrix.rows())?;
+                let column = position(column, matrix.columns())?;
+                Ok(Value::Number(
+                    matrix.elements()[row * matrix.columns() + column],
+                ))
+            }
+            _ => Err(EvaluationError::TypeMismatch {
+                expected: "one or two indices",
+                found: "a different number",
+            }),
+        }
+    }
+
+    /// Finish `&&` (when `decided_by` is false) or `||` (when it's true), given the left operand
+    ///
+    /// Once the left operand is `decided_by`, that's the result and the right
+    /// operand is skipped, unless [`EvalConfig::strict_evaluation`] is set.
+    /// A null left operand can't decide the result (unless nulls count as 0).
+    fn logical(
+        &self,
+        left: Value,
+        right: &Expr,
+        decided_by: bool,
+    ) -> Result<Value, EvaluationError> {
+        let mut operands = [left, Value::Null];
+        let decided = self.replace_nulls(&mut operands[..1]) && operands[0].truth()? == decided_by;
+        if decided && !self.config.strict_evaluation {
+            return Ok(Value::from(decided_by));
+        }
+
+        operands[1] = self.evaluate_value(right)?;
+        if !self.replace_nulls(&mut operands) {
+            return Ok(Value::Null);
+        }
+        let (left, right) = (operands[0].truth()?, operands[1].truth()?);
+        Ok(Value::from(if left == decided_by { left } else { right }))
+    }
+
+    /// Apply the magnitude limit and the subnormal policy to a result
+    fn check_range(&self, value: f64) -> Result<f64, EvaluationError> {
+        if let Some(limit) = self.config.max_magnitude
+            && value.abs() > limit
+        {
+            return match self.config.overflow {
+                OverflowPolicy::Error => Err(EvaluationError::Overflow { value, limit }),
+                OverflowPolicy::Clamp => Ok(limit.copysign(value)),
+            };
+        }
+        if value.is_subnormal() {
+            return match self.config.subnormals {
+                SubnormalPolicy::Keep => Ok(value),
+                SubnormalPolicy::FlushToZero => Ok(0.0_f64.copysign(value)),
+                SubnormalPolicy::Error => Err(EvaluationError::Subnormal(value)),
+            };
+        }
+        Ok(value)
+    }
+
+    /// Apply the domain policy to the result of a function on numbers
+    pub(crate) fn apply_domain_policy(
+        &self,
+        result: Result<f64, EvaluationError>,
+    ) -> Result<f64, EvaluationError> {
+        match result {
+            Err(EvaluationError::OutOfDomain { .. })
+                if self.config.domain_errors == DomainPolicy::Nan =>
+            {
+                Ok(f64::NAN)
+            }
+            result => result,
+        }
+    }
+
+    /// The value of a missing variable or cell: null, unless missing inputs are errors
+    fn missing(&self) -> Option<Value> {
+        match self.config.nulls {
+            NullPolicy::Error => None,
+            NullPolicy::Poison | NullPolicy::Coalesce => Some(Value::Null),
+        }
+    }
+
+    /// Apply the null policy to the operands of an operation
+    ///
+    /// With [`NullPolicy::Coalesce`], nulls are replaced by 0. Otherwise a
+    /// null operand makes the whole result null, which is signalled by
+    /// returning false.
+    pub(crate) fn replace_nulls(&self, operands: &mut [Value]) -> bool {
+        if !operands.iter().any(Value::is_null) {
+            return true;
+        }
+        match self.config.nulls {
+            NullPolicy::Coalesce => {
+                for operand in operands.iter_mut().filter(|operand| operand.is_null()) {
+                    *operand = Value::Number(0.0);
+                }
+                true
+            }
+            NullPolicy::Error | NullPolicy::Poison => false,
+        }
+    }
+
+    /// Evaluate a chain of binary operations without recursing down it
+    ///
+    /// The chain is the left spine of the tree: `a * b - c / d < e` is
+    /// `((a * b) - (c / d)) < e`, so it's made of a, `* b`, `- c / d` and
+    /// `< e`. However long the chain, as in generated formulas like
+    /// `1 + 1 + … + 1`, it takes no more stack than one operation. Operands
+    /// are evaluated in the same order as one operation at a time: left
+    /// before right, except that divisors go first, so dividing by zero is
+    /// reported even when the dividend has problems of its own. With
+    /// compensated sums, runs of `+` and `-` are left to [`Evaluator::sum`].
+    fn chain(&self, expr: &Expr) -> Result<Value, EvaluationError> {
+        let mut spine = Vec::new();
+        let mut first = expr;
+        loop {
+            first = match first {
+                Expr::Add(..) | Expr::Sub(..) if self.config.compensated_sums => {
+                    break;
+                }
+                Expr::Add(left, _)
+                | Expr::Sub(left, _)
+                | Expr::Mul(left, _)
+                | Expr::Div(left, _)
+                | Expr::Compare(_, left, _)
+                | Expr::And(left, _)
+                | Expr::Or(left, _) => {
+                    spine.push(first);
+                    left
+                }
+                _ => break,
+            };
+        }
+
+        // Divisors are evaluated on the way down, outermost first
+        let mut divisors = Vec::new();
+        for node in &spine {
+            if let Expr::Div(_, divisor) = node {
+                divisors.push(self.evaluate_value(divisor)?);
+            }
+        }
+        let mut total = self.evaluate_value(first)?;
+        for node in spine.into_iter().rev() {
+            let result = match node {
+                Expr::Add(_, right) => self.binary("+", total, self.evaluate_value(right)?),
+                Expr::Sub(_, right) => self.binary("-", total, self.evaluate_value(right)?),
+                Expr::Mul(_, right) => self.binary("*", total, self.evaluate_value(right)?),
+                Expr::Div(..) => {
+                    let divisor = divisors.pop().expect("a divisor for every division");
+                    self.binary("/", total, divisor)
+                }
+                Expr::Compare(comparison, _, right) => {
+                    let mut operands = [total, self.evaluate_value(right)?];
+                    if self.replace_nulls(&mut operands) {
+                        let [left, right] = operands;
+                        Value::compare(*comparison, left, right)
+                    } else {
+                        Ok(Value::Null)
+                    }
+                }
+                Expr::And(_, right) => self.logical(total, right, false),
+                Expr::Or(_, right) => self.logical(total, right, true),
+                _ => unreachable!("only binary operations are on the spine"),
+            };
+            total = self.checked(result?)?;
+        }
+        Ok(total)
+    }
+
+    /// Apply an arithmetic operator to evaluated operands
+    fn binary(
+        &self,
+        op: &'static str,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, EvaluationError> {
+        let mut operands = [left, right];
+        if !self.replace_nulls(&mut operands) {
+            return Ok(Value::Null);
+        }
+        let [left, right] = operands;
+        if op == "/" && self.config.division_by_zero == DivisionPolicy::Ieee {
+            match (left, right) {
+                (Value::Number(l), Value::Number(r)) => return Ok(Value::Number(l / r)),
+                (Value::Matrix(m), Value::Number(r)) => return Ok(Value::Matrix(m.map(|x| x / r))),
+                (left, right) => return Value::binary(op, left, right),
+            }
+        }
+        Value::binary(op, left, right)
+    }
+
+    /// Evaluate a chain of `+` and `-` with compensated summation
+    ///
+    /// The chain is the left spine of the tree: `a - b + c` is `(a - b) + c`,
+    /// so it's made of a, b and c. Terms are evaluated and added left to right,
+    /// as they would be one operation at a time, carrying the rounding error
+    /// of each addition along in `compensation` (Neumaier's variant of Kahan
+    /// summation). Values other than numbers are added normally.
+    fn sum(&self, expr: &Expr) -> Result<Value, EvaluationError> {
+        let mut terms = Vec::new();
+        let mut first = expr;
+        loop {
+            first = match first {
+                Expr::Add(left, right) => {
+                    terms.push(("+", right.as_ref()));
+                    left
+                }
+                Expr::Sub(left, right) => {
+                    terms.push(("-", right.as_ref()));
+                    left
+                }
+                _ => break,
+            };
+        }
+
+        let mut total = self.evaluate_value(first)?;
+        let mut compensation = 0.0;
+        for (op, term) in terms.into_iter().rev() {
+            let mut operands = [total, self.evaluate_value(term)?];
+            if !self.replace_nulls(&mut operands) {
+                total = Value::Null;
+                continue;
+            }
+            total = match operands {
+                [Value::Number(sum), Value::Number(term)] => {
+                    let term = if op == "-" { -term } else { term };
+                    let next = sum + term;
+                    compensation += if sum.abs() >= term.abs() {
+                        (sum - next) + term
+                    } else {
+                        (term - next) + sum
+                    };
+                    Value::Number(next)
+                }
+                [Value::Number(sum), other] => {
+                    let sum = Value::Number(sum + compensation);
+                    compensation = 0.0;
+                    Value::binary(op, sum, other)?
+                }
+                [left, right] => Value::binary(op, left, right)?,
+            };
+        }
+
+        Ok(match total {
+            // Once the sum is infinite or NaN, so is the compensation
+            Value::Number(sum) if sum.is_finite() => Value::Number(sum + compensation),
+            total => total,
+        })
+    }
+
+    /// Evaluate the arguments of a function call
+    ///
+    /// A cell range argument, as in `SUM(A1:A10)`, contributes the values of
+    /// all its non-empty cells.
+    pub(crate) fn evaluate_arguments(&self, args: &[Expr]) -> Result<Vec<Value>, EvaluationError> {
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg {
+                Expr::CellRange(from, to) => match self.cells {
+                    Some(cells) => {
+                        let range = cells.range(*from, *to);
+                        self.record(|| AuditEvent::Range {
+                            from: from.to_string(),
+                            to: to.to_string(),
+                            values: range.clone(),
+                        });
+                        values.extend(range.into_iter().map(Value::Number))
+                    }
+                    None => return Err(EvaluationError::UnresolvedCell(*from)),
+                },
+                arg => values.push(self.evaluate_value(arg)?),
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Evaluate an expression with x = 0 and y = 4
+    fn eval(expression: &str, config: EvalConfig) -> Result<f64, EvaluationError> {
+        let env: Environment = [("x", 0.0), ("y", 4.0)].into_iter().collect();
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        Evaluator::new(&env).with_config(config).evaluate(&ast)
+    }
+
+    /// Test comparisons and logical operators, and their precedence
+    #[test]
+    fn test_comparisons_and_logic() {
+        let cases = [
+            ("y > 3", 1.0),
+            ("y <= 3", 0.0),
+            ("y == 2 * 2 && x != 1", 1.0),
+            ("x || y - 4", 0.0),
+            ("!x + 1", 2.0),
+            ("1 < 2 == 1", 1.0),
+            ("0 && 1 || 1", 1.0),
+            ("y && 5", 1.0),
+            ("date(2024, 1, 1) < date(2024, 1, 2)", 1.0),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                eval(expression, EvalConfig::default()).unwrap(),
+                expected,
+                "'{}'",
+                expression
+            );
+        }
+        assert!(matches!(
+            eval("days(1) < 2", EvalConfig::default()),
+            Err(EvaluationError::UnsupportedOperands { op: "<", .. })
+        ));
+    }
+
+    /// Test that the right operand is skipped once the left one decides, unless strict
+    #[test]
+    fn test_short_circuit() {
+        let strict = EvalConfig {
+            strict_evaluation: true,
+            ..EvalConfig::default()
+        };
+        for expression in [
+            "x != 0 && 1 / x > 2",
+            "x == 0 || 1 / x > 2",
+            "x && undefined",
+            "y || nope()",
+        ] {
+            assert!(
+                eval(expression, EvalConfig::default()).is_ok(),
+                "'{}' should short-circuit",
+                expression
+            );
+            assert!(
+                eval(expression, strict).is_err(),
+                "'{}' should evaluate both sides when strict",
+                expression
+            );
+        }
+
+        // When the left side doesn't decide, both modes agree
+        assert_eq!(eval("y > 0 && y < 5", strict).unwrap(), 1.0);
+        assert_eq!(eval("x == 0 || 1 / y", strict).unwrap(), 1.0);
+    }
+
+    /// Test how each null policy treats missing inputs (y is defined, q isn't)
+    #[test]
+    fn test_null_policies() {
+        let with = |nulls| EvalConfig {
+            nulls,
+            ..EvalConfig::default()
+        };
+        let env: Environment = [("y", 4.0)].into_iter().collect();
+        let eval = |expression: &str, nulls| {
+            let (_, ast) = parse_expression(expression).unwrap();
+            Evaluator::new(&env)
+                .with_config(with(nulls))
+                .evaluate_value(&ast)
+        };
+
+        let cases = [
+            ("q * 2 + y", Value::Null, Value::Number(4.0)),
+            ("-q", Value::Null, Value::Number(0.0)),
+            ("sqrt(q) + 1", Value::Null, Value::Number(1.0)),
+            ("q > 1 || y", Value::Null, Value::Number(1.0)),
+            ("y > 1 || q", Value::Number(1.0), Value::Number(1.0)),
+            ("coalesce(q, y + 1)", Value::Number(5.0), Value::Number(5.0)),
+            ("coalesce(q)", Value::Null, Value::Null),
+        ];
+        for (expression, poisoned, coalesced) in cases {
+            assert_eq!(
+                eval(expression, NullPolicy::Poison).unwrap(),
+                poisoned,
+                "'{}'",
+                expression
+            );
+            assert_eq!(
+                eval(expression, NullPolicy::Coalesce).unwrap(),
+                coalesced,
+                "'{}'",
+                expression
+            );
+        }
+
+        assert!(matches!(
+            eval("q * 2", NullPolicy::Error),
+            Err(EvaluationError::UndefinedVariable(name)) if name == "q"
+        ));
+        assert_eq!(
+            eval("coalesce(q, y)", NullPolicy::Error).unwrap(),
+            Value::Number(4.0)
+        );
+    }
+
+    /// Test the magnitude limit and the subnormal policies
+    #[test]
+    fn test_range_checks() {
+        let limited = |overflow| EvalConfig {
+            max_magnitude: Some(100.0),
+            overflow,
+            ..EvalConfig::default()
+        };
+        assert_eq!(
+            eval("y * 25", limited(OverflowPolicy::Error)).unwrap(),
+            100.0
+        );
+        assert!(matches!(
+            eval("y * 30 - 50", limited(OverflowPolicy::Error)),
+            Err(EvaluationError::Overflow {
+                value: 120.0,
+                limit: 100.0
+            })
+        ));
+        // Clamping happens where the overflow does, not just at the end
+        assert_eq!(
+            eval("y * 30 - 50", limited(OverflowPolicy::Clamp)).unwrap(),
+            50.0
+        );
+        assert_eq!(
+            eval("-1 / 0.001", limited(OverflowPolicy::Clamp)).unwrap(),
+            -100.0
+        );
+        assert_eq!(
+            eval("1e308 * 10", limited(OverflowPolicy::Clamp)).unwrap(),
+            100.0
+        );
+
+        let subnormals = |subnormals| EvalConfig {
+            subnormals,
+            ..EvalConfig::default()
+        };
+        let tiny = "1e-300 / 1e10";
+        assert!(eval(tiny, subnormals(SubnormalPolicy::Keep)).unwrap() > 0.0);
+        assert_eq!(
+            eval(tiny, subnormals(SubnormalPolicy::FlushToZero)).unwrap(),
+            0.0
+        );
+        assert!(matches!(
+            eval(tiny, subnormals(SubnormalPolicy::Error)),
+            Err(EvaluationError::Subnormal(_))
+        ));
+        assert_eq!(
+            eval("1e-300 * y", subnormals(SubnormalPolicy::Error)).unwrap(),
+            4e-300
+        );
+    }
+
+    /// Test IEEE division by zero, with signed zeros, matrices and the magnitude limit
+    #[test]
+    fn test_ieee_division() {
+        let ieee = EvalConfig {
+            division_by_zero: DivisionPolicy::Ieee,
+            ..EvalConfig::default()
+        };
+        assert_eq!(eval("y / 0", ieee).unwrap(), f64::INFINITY);
+        assert_eq!(eval("y / -0", ieee).unwrap(), f64::NEG_INFINITY);
+        assert!(eval("0 / 0", ieee).unwrap().is_nan());
+        assert_eq!(eval("1 / (y / 0)", ieee).unwrap(), 0.0);
+        assert!(matches!(
+            eval("y / 0", EvalConfig::default()),
+            Err(EvaluationError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval(
+                "y / 0",
+                EvalConfig {
+                    max_magnitude: Some(1e300),
+                    ..ieee
+                }
+            ),
+            Err(EvaluationError::Overflow { .. })
+        ));
+
+        let env = Environment::new();
+        let (_, ast) = crate::parse_expression("[1, -1, 0] / 0").unwrap();
+        let value = Evaluator::new(&env).with_config(ieee).evaluate_value(&ast);
+        let Ok(Value::Matrix(m)) = value else {
+            panic!("{:?}", value);
+        };
+        assert_eq!(m.elements()[..2], [f64::INFINITY, f64::NEG_INFINITY]);
+        assert!(m.elements()[2].is_nan());
+    }
+
+    /// Test that domain errors say what the argument was, or give NaN when asked to
+    #[test]
+    fn test_domain_errors() {
+        let nan = EvalConfig {
+            domain_errors: DomainPolicy::Nan,
+            ..EvalConfig::default()
+        };
+        assert!(matches!(
+            eval("2 * ln(x - y)", EvalConfig::default()),
+            Err(EvaluationError::OutOfDomain { value: -4.0, .. })
+        ));
+        assert!(eval("2 * ln(x - y)", nan).unwrap().is_nan());
+        assert_eq!(eval("sqrt(-0.0) + acos(1)", nan).unwrap(), 0.0);
+        // Other domain errors aren't affected
+        assert!(matches!(
+            eval("mean([])", nan),
+            Err(EvaluationError::DomainError(_))
+        ));
+
+        let env = Environment::new();
+        let (_, ast) = crate::parse_expression("sqrt([4, -1])").unwrap();
+        let value = Evaluator::new(&env).with_config(nan).evaluate_value(&ast);
+        let Ok(Value::Matrix(m)) = value else {
+            panic!("{:?}", value);
+        };
+        assert_eq!(m.elements()[0], 2.0);
+        assert!(m.elements()[1].is_nan());
+    }
+
+    /// Test that compensated summation fixes rounding errors without changing other results
+    #[test]
+    fn test_compensated_sums() {
+        let compensated = EvalConfig {
+            compensated_sums: true,
+            ..EvalConfig::default()
+        };
+        let tenths = ["0.1"; 10].join(" + ");
+        assert_ne!(eval(&tenths, EvalConfig::default()).unwrap(), 1.0);
+        assert_eq!(eval(&tenths, compensated).unwrap(), 1.0);
+        assert_eq!(
+            eval("1e100 + y - 1e100", EvalConfig::default()).unwrap(),
+            0.0
+        );
+        assert_eq!(eval("1e100 + y - 1e100", compensated).unwrap(), 4.0);
+
+        for expression in ["y - 1 - (2 - x) + 3", "1 - 1e308 - 1e308", "1 / y + 1"] {
+            assert_eq!(
+                eval(expression, compensated).unwrap(),
+                eval(expression, EvalConfig::default()).unwrap(),
+                "'{}'",
+                expression
+            );
+        }
+        assert_eq!(
+            eval(
+                "date(2024, 1, 3) - date(2024, 1, 1) + days(1) - days(3) == days(0)",
+                compensated
+            )
+            .unwrap(),
+            1.0
+        );
+        assert!(matches!(
+            eval("y + date(2024, 1, 1)", compensated),
+            Err(EvaluationError::UnsupportedOperands { op: "+", .. })
+        ));
+    }
+
+    /// Test that a deadline stops an evaluation once it has passed, and only then
+    #[test]
+    fn test_deadline() {
+        // A balanced sum of 4096 ones, many more nodes than are evaluated between checks
+        let mut ast = Expr::Float(1.0);
+        for _ in 0..12 {
+            ast = Expr::Add(Box::new(ast.clone()), Box::new(ast));
+        }
+        let env = Environment::new();
+        let evaluator = Evaluator::new(&env);
+        let later = Instant::now() + std::time::Duration::from_secs(3600);
+        assert_eq!(
+            evaluator.evaluate_with_deadline(&ast, later).unwrap(),
+            4096.0
+        );
+        assert!(matches!(
+            evaluator.evaluate_with_deadline(&ast, Instant::now()),
+            Err(EvaluationError::Timeout)
+        ));
+        assert_eq!(EvaluationError::Timeout.code(), "E0019");
+
+        // The deadline only applies to the evaluation it was given for
+        assert_eq!(evaluator.evaluate(&ast).unwrap(), 4096.0);
+    }
+}
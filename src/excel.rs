@@ -0,0 +1,180 @@
+//! Excel-compatible functions
+//!
+//! [`FunctionRegistry::excel`] adds the common spreadsheet functions, with
+//! Excel's names and semantics, so formulas copied from a spreadsheet mostly
+//! just work. Function names are case-insensitive, as in Excel. There are no
+//! booleans: comparisons and logical functions use 1 for TRUE and 0 for FALSE.
+//!
+//! Only available with the `excel` cargo feature.
+
+use crate::{
+    EvaluationError, Evaluator, Expr, FunctionRegistry, Value,
+    functions::{domain, round},
+};
+
+impl FunctionRegistry {
+    /// The standard functions plus Excel's, with case-insensitive names
+    ///
+    /// | Function | Excel semantics |
+    /// | --- | --- |
+    /// | `IF(cond, then, [else])` | Only evaluates the branch it picks; `else` defaults to 0 (FALSE) |
+    /// | `AND(...)`, `OR(...)`, `NOT(x)` | Nonzero is TRUE; the result is 1 or 0 |
+    /// | `SUM`, `PRODUCT`, `AVERAGE`, `MIN`, `MAX`, `COUNT` | Ranges such as `A1:A10` contribute their non-empty cells |
+    /// | `ROUND(x, digits)`, `ROUNDUP`, `ROUNDDOWN` | Half away from zero, in decimal; negative digits round left of the point |
+    /// | `INT(x)` | Rounds down, so `INT(-1.5)` is -2 |
+    /// | `MOD(n, d)` | The result has the sign of the divisor, so `MOD(-3, 2)` is 1 |
+    /// | `POWER(x, y)`, `SQRT`, `ABS`, `EXP`, `LN`, `LOG10`, `PI()` | As in Excel |
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use ast::{parse_expression_with, CellRef, Environment, Evaluator, FunctionRegistry, ParserConfig};
+    ///
+    /// let sheet: HashMap<CellRef, f64> =
+    ///     [(CellRef::new(1, 1), 1.5), (CellRef::new(1, 2), 2.25)].into_iter().collect();
+    /// let functions = FunctionRegistry::excel();
+    /// let env = Environment::new();
+    /// let evaluator = Evaluator::new(&env).with_cells(&sheet).with_functions(&functions);
+    ///
+    /// let (_, ast) = parse_expression_with("ROUND(SUM(A1:A3), 1)", &ParserConfig::spreadsheet()).unwrap();
+    /// assert_eq!(evaluator.evaluate(&ast).unwrap(), 3.8);
+    /// ```
+    pub fn excel() -> Self {
+        let mut registry = FunctionRegistry::standard();
+        registry.set_case_insensitive();
+
+        registry.register_lazy("if", 2..=3, excel_if);
+        registry.register("and", 1.., |args| Ok(truth(args.iter().all(|&x| x != 0.0))));
+        registry.register("or", 1.., |args| Ok(truth(args.iter().any(|&x| x != 0.0))));
+        registry.register("not", 1, |args| Ok(truth(args[0] == 0.0)));
+
+        registry.register("sum", 1.., |args| Ok(args.iter().sum()));
+        registry.register("product", 1.., |args| Ok(args.iter().product()));
+        registry.register("average", 1.., |args| {
+            if args.is_empty() {
+                // Every cell in the range was empty
+                Err(EvaluationError::DivisionByZero)
+            } else {
+                Ok(args.iter().sum::<f64>() / args.len() as f64)
+            }
+        });
+        registry.register("count", 1.., |args| Ok(args.len() as f64));
+        // Excel's MIN and MAX of nothing (all-empty ranges) is 0
+        registry.register("min", 1.., |args| {
+            Ok(args.iter().copied().reduce(f64::min).unwrap_or(0.0))
+        });
+        registry.register("max", 1.., |args| {
+            Ok(args.iter().copied().reduce(f64::max).unwrap_or(0.0))
+        });
+
+        registry.register("round", 2, |args| Ok(round(args[0], args[1], f64::round)));
+        registry.register("roundup", 2, |args| {
+            Ok(round(args[0], args[1], |x| x.abs().ceil().copysign(x)))
+        });
+        registry.register("rounddown", 2, |args| {
+            Ok(round(args[0], args[1], f64::trunc))
+        });
+        registry.register("int", 1, |args| Ok(args[0].floor()));
+        registry.register("mod", 2, |args| {
+            let (number, divisor) = (args[0], args[1]);
+            if divisor == 0.0 {
+                return Err(EvaluationError::DivisionByZero);
+            }
+            Ok(number - divisor * (number / divisor).floor())
+        });
+
+        registry.register("power", 2, |args| {
+            let (base, exponent) = (args[0], args[1]);
+            domain("power", base != 0.0 || exponent > 0.0, base.powf(exponent))
+        });
+        registry.register("pi", 0, |_| Ok(std::f64::consts::PI));
+        registry
+    }
+}
+
+/// Excel's TRUE and FALSE as numbers
+fn truth(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// `IF(cond, then, [else])`, which only evaluates the branch it picks
+fn excel_if(evaluator: &Evaluator<'_>, args: &[Expr]) -> Result<Value, EvaluationError> {
+    if evaluator.evaluate(&args[0])? != 0.0 {
+        evaluator.evaluate_value(&args[1])
+    } else {
+        args.get(2).map_or(Ok(Value::Number(0.0)), |otherwise| {
+            evaluator.evaluate_value(otherwise)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{CellRef, Environment, ParserConfig, parse_expression_with};
+
+    /// Evaluate a spreadsheet formula with A1..A3 = 1, 2, 3
+    fn eval(formula: &str) -> Result<f64, EvaluationError> {
+        let sheet: HashMap<CellRef, f64> = (1..=3)
+            .map(|row| (CellRef::new(1, row), f64::from(row)))
+            .collect();
+        let functions = FunctionRegistry::excel();
+        let env = Environment::new();
+        let (remaining, ast) =
+            parse_expression_with(formula, &ParserConfig::spreadsheet()).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        Evaluator::new(&env)
+            .with_cells(&sheet)
+            .with_functions(&functions)
+            .evaluate(&ast)
+    }
+
+    /// Test Excel's semantics where they differ from the standard functions
+    #[test]
+    fn test_excel_semantics() {
+        let cases = [
+            ("MOD(-3, 2)", 1.0),
+            ("MOD(3, -2)", -1.0),
+            ("mod(5.5, 2)", 1.5),
+            ("SUM(A1:A3, 10)", 16.0),
+            ("AVERAGE(A1:A3)", 2.0),
+            ("COUNT(A1:A10)", 3.0),
+            ("MAX(B1:B3)", 0.0),
+            ("ROUND(2.675, 2)", 2.68),
+            ("ROUND(-2.5, 0)", -3.0),
+            ("ROUNDUP(-1.21, 1)", -1.3),
+            ("ROUNDDOWN(1.29, 1)", 1.2),
+            ("INT(-1.5)", -2.0),
+            ("POWER(2, 0.5) * POWER(2, 0.5)", 2.0),
+            ("LOG10(A1 * 100)", 2.0),
+            ("AND(1, A2) + OR(0, 0) + NOT(0)", 2.0),
+        ];
+        for (formula, expected) in cases {
+            let result = eval(formula).unwrap();
+            assert!(
+                (result - expected).abs() < 1e-10,
+                "'{}': expected {}, got {}",
+                formula,
+                expected,
+                result
+            );
+        }
+    }
+
+    /// Test that IF only evaluates the branch it picks
+    #[test]
+    fn test_if_is_lazy() {
+        assert_eq!(eval("IF(A1 - 1, 1 / 0, 5)").unwrap(), 5.0);
+        assert_eq!(eval("IF(0, 1)").unwrap(), 0.0);
+        assert!(matches!(
+            eval("IF(A1, 1 / 0, 5)"),
+            Err(EvaluationError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval("MOD(1, 0)"),
+            Err(EvaluationError::DivisionByZero)
+        ));
+    }
+}
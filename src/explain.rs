@@ -0,0 +1,219 @@
+//! Explaining how an expression was grouped
+//!
+//! Long formulas are easy to get wrong when the author and the parser disagree
+//! on precedence. [`explain`] writes the expression back out with explicit
+//! parentheses around every operation, plus a note for each precedence or
+//! associativity rule that decided the grouping.
+
+use crate::Expr;
+
+/// Show how an expression was grouped, with notes on the rules involved
+///
+/// The first line is the expression with every operation except the
+/// outermost one wrapped in parentheses. Each following line is a note,
+/// starting with `note: `, explaining why something was grouped the way it
+/// was. Notes are only listed once, in the order they were found.
+///
+/// # Example
+/// ```
+/// use ast::{explain, parse_expression};
+///
+/// let (_, ast) = parse_expression("2 + 3 * 4").unwrap();
+/// assert_eq!(
+///     explain(&ast),
+///     "2 + (3 * 4)\nnote: '*' binds tighter than '+', so 3 * 4 is grouped first"
+/// );
+/// ```
+pub fn explain(expr: &Expr) -> String {
+    let mut notes = Vec::new();
+    let mut explanation = group(expr, true, &mut notes);
+    for note in notes {
+        explanation.push_str("\nnote: ");
+        explanation.push_str(&note);
+    }
+    explanation
+}
+
+/// An expression written out with every operation except the outermost one in parentheses
+pub(crate) fn grouped(expr: &Expr) -> String {
+    group(expr, true, &mut Vec::new())
+}
+
+/// The operator and operands of a binary operation
+pub(crate) fn binary(expr: &Expr) -> Option<(&'static str, &Expr, &Expr)> {
+    match expr {
+        Expr::Add(left, right) => Some(("+", left, right)),
+        Expr::Sub(left, right) => Some(("-", left, right)),
+        Expr::Mul(left, right) => Some(("*", left, right)),
+        Expr::Div(left, right) => Some(("/", left, right)),
+        Expr::Compare(comparison, left, right) => Some((comparison.symbol(), left, right)),
+        Expr::And(left, right) => Some(("&&", left, right)),
+        Expr::Or(left, right) => Some(("||", left, right)),
+        _ => None,
+    }
+}
+
+/// How tightly an operator binds; higher binds tighter
+pub(crate) fn precedence(op: &str) -> u8 {
+    match op {
+        "*" | "/" => 5,
+        "+" | "-" => 4,
+        "&&" => 2,
+        "||" => 1,
+        _ => 3, // comparisons
+    }
+}
+
+/// Write out an expression, parenthesizing every operation unless it's at the top
+fn group(expr: &Expr, top: bool, notes: &mut Vec<String>) -> String {
+    match expr {
+        Expr::Float(value) if *value < 0.0 && !top => format!("({})", value),
+        Expr::Float(value) => format!("{}", value),
+        Expr::Var(name) => name.clone(),
+        Expr::CellRef(cell) => cell.to_string(),
+        Expr::CellRange(from, to) => format!("{}:{}", from, to),
+        Expr::Error(_) => "?".to_string(),
+        Expr::Neg(inner) => format!("-{}", group(inner, false, notes)),
+        Expr::Not(inner) => format!("!{}", group(inner, false, notes)),
+        Expr::Named(name, value) => format!("{}={}", name, group(value, true, notes)),
+        Expr::Let(name, value, body) => {
+            let value = group(value, true, notes);
+            let body = group(body, true, notes);
+            let binding = format!("let {} = {} in {}", name, value, body);
+            if top {
+                binding
+            } else {
+                format!("({})", binding)
+            }
+        }
+        Expr::Piecewise(cases, default) => {
+            let mut items: Vec<String> = cases
+                .iter()
+                .map(|(condition, value)| {
+                    let condition = group(condition, true, notes);
+                    format!("({}, {})", condition, group(value, true, notes))
+                })
+                .collect();
+            items.extend(default.iter().map(|default| group(default, true, notes)));
+            format!("piecewise({})", items.join(", "))
+        }
+        Expr::List(elements) => {
+            let elements: Vec<String> = elements.iter().map(|e| group(e, true, notes)).collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Expr::Index(target, indices) => {
+            let indices: Vec<String> = indices.iter().map(|i| group(i, true, notes)).collect();
+            format!("{}[{}]", group(target, false, notes), indices.join(", "))
+        }
+        Expr::Call(name, args) => {
+            // Each argument is already delimited by the call's parentheses and commas
+            let args: Vec<String> = args.iter().map(|arg| group(arg, true, notes)).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+        _ => {
+            let (op, left, right) = binary(expr).expect("every other node is binary");
+
+            // Explain the inner groupings first, so notes read inside-out
+            let left_grouped = group(left, false, notes);
+            let right_grouped = group(right, false, notes);
+
+            for child in [left, right] {
+                if let Some((child_op, ..)) = binary(child)
+                    && precedence(child_op) > precedence(op)
+                {
+                    note(
+                        notes,
+                        format!(
+                            "'{}' binds tighter than '{}', so {} is grouped first",
+                            child_op,
+                            op,
+                            group(child, true, &mut Vec::new())
+                        ),
+                    );
+                }
+            }
+
+            if let Some((left_op, ..)) = binary(left)
+                && precedence(left_op) == precedence(op)
+                && matches!(op, "-" | "/")
+            {
+                note(
+                    notes,
+                    format!(
+                        "'{}' and '{}' group left to right, so {} is worked out first",
+                        left_op,
+                        op,
+                        group(left, true, &mut Vec::new())
+                    ),
+                );
+            }
+
+            let grouped = format!("{} {} {}", left_grouped, op, right_grouped);
+            if top {
+                grouped
+            } else {
+                format!("({})", grouped)
+            }
+        }
+    }
+}
+
+/// Add a note unless the same one was already made
+fn note(notes: &mut Vec<String>, note: String) {
+    if !notes.contains(&note) {
+        notes.push(note);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Test the grouping line for a variety of expressions
+    #[test]
+    fn test_grouping() {
+        let cases = [
+            ("42", "42"),
+            ("1 + 2 * 3 - 4", "(1 + (2 * 3)) - 4"),
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("8 / 4 / 2", "(8 / 4) / 2"),
+            ("-(3 + 4) * 2", "-(3 + 4) * 2"),
+            ("2.5 * -3", "2.5 * -3"),
+            ("max(1 + 2 * 3, -4) / 2", "max(1 + (2 * 3), -4) / 2"),
+            ("a || b && c < 1 + 2", "a || (b && (c < (1 + 2)))"),
+            ("!x == 0", "!x == 0"),
+        ];
+
+        for (expression, expected) in cases {
+            let (_, ast) = parse_expression(expression).unwrap();
+            let explanation = explain(&ast);
+            assert_eq!(
+                explanation.lines().next(),
+                Some(expected),
+                "'{}'",
+                expression
+            );
+        }
+    }
+
+    /// Test that the notes explain precedence and associativity once each
+    #[test]
+    fn test_notes() {
+        let (_, ast) = parse_expression("10 - 3 - 2 * 4 - 6 / 2").unwrap();
+        let explanation = explain(&ast);
+        let notes: Vec<&str> = explanation.lines().skip(1).collect();
+        assert_eq!(
+            notes,
+            vec![
+                "note: '*' binds tighter than '-', so 2 * 4 is grouped first",
+                "note: '-' and '-' group left to right, so 10 - 3 is worked out first",
+                "note: '/' binds tighter than '-', so 6 / 2 is grouped first",
+                "note: '-' and '-' group left to right, so (10 - 3) - (2 * 4) is worked out first",
+            ]
+        );
+
+        let (_, ast) = parse_expression("1 + 2 + 3").unwrap();
+        assert_eq!(explain(&ast), "(1 + 2) + 3");
+    }
+}
@@ -0,0 +1,358 @@
+//! Interactive tree explorer
+//!
+//! `:explore <expression>` shows the parsed tree as an outline that can be
+//! folded and walked with the arrow keys (or `h`, `j`, `k`, `l`). The value and
+//! source span of the selected subtree are shown below it. With the `tui`
+//! feature, in a terminal, the explorer takes over the screen and keys act as
+//! soon as they're pressed (see the `tui` module). Otherwise input is read a
+//! line at a time, so keys take effect after Enter, and several can be typed
+//! at once.
+
+use ast::{Expr, Span};
+
+use crate::render::{Renderer, Style};
+
+/// Keys that the explorer understands
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Key {
+    /// Select the node above
+    Up,
+    /// Select the node below
+    Down,
+    /// Fold the selected node, or select its parent if it's folded
+    Left,
+    /// Unfold the selected node, or select its first child if it's unfolded
+    Right,
+    /// Fold or unfold the selected node
+    Toggle,
+    /// Leave the explorer
+    Quit,
+}
+
+impl Key {
+    /// The keys on a line of input; an empty line is [`Key::Toggle`]
+    ///
+    /// Arrow keys arrive as the escape sequences the terminal sends for them.
+    pub fn parse_line(line: &str) -> Vec<Key> {
+        if line.is_empty() {
+            return vec![Key::Toggle];
+        }
+        let mut keys = Vec::new();
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let (key, length) = match (c, rest.get(..3)) {
+                (_, Some("\x1b[A")) => (Some(Key::Up), 3),
+                (_, Some("\x1b[B")) => (Some(Key::Down), 3),
+                (_, Some("\x1b[C")) => (Some(Key::Right), 3),
+                (_, Some("\x1b[D")) => (Some(Key::Left), 3),
+                ('k', _) => (Some(Key::Up), 1),
+                ('j', _) => (Some(Key::Down), 1),
+                ('l', _) => (Some(Key::Right), 1),
+                ('h', _) => (Some(Key::Left), 1),
+                (' ', _) => (Some(Key::Toggle), 1),
+                ('q', _) => (Some(Key::Quit), 1),
+                (c, _) => (None, c.len_utf8()),
+            };
+            keys.extend(key);
+            rest = &rest[length..];
+        }
+        keys
+    }
+}
+
+/// A node of the tree, with where it is in the source and in the outline
+#[derive(Debug)]
+struct Node<'a> {
+    /// The subtree rooted at this node
+    expr: &'a Expr,
+    /// Where the node is in the source
+    span: Span,
+    /// How deeply the node is nested
+    depth: usize,
+    /// The node's parent, as an index into the outline
+    parent: Option<usize>,
+    /// The node's children, as indices into the outline
+    children: Vec<usize>,
+    /// Whether the node's children are hidden
+    folded: bool,
+}
+
+/// The state of the explorer: the outline, what's folded and what's selected
+#[derive(Debug)]
+pub struct Explorer<'a> {
+    /// The input the tree was parsed from
+    source: &'a str,
+    /// Every node, in pre-order
+    nodes: Vec<Node<'a>>,
+    /// Index of the selected node
+    selected: usize,
+}
+
+impl<'a> Explorer<'a> {
+    /// An explorer for a tree, given its node spans in post-order, with the root selected
+    ///
+    /// The spans are what [`ast::parse_with_spans`] returns for `source`.
+    pub fn new(source: &'a str, ast: &'a Expr, spans: &[Span]) -> Self {
+        let mut explorer = Explorer {
+            source,
+            nodes: Vec::new(),
+            selected: 0,
+        };
+        let mut spans = spans.iter().copied();
+        explorer.add(ast, 0, None, &mut spans);
+        explorer
+    }
+
+    /// Add a node and its children to the outline, returning the node's index
+    fn add(
+        &mut self,
+        expr: &'a Expr,
+        depth: usize,
+        parent: Option<usize>,
+        spans: &mut impl Iterator<Item = Span>,
+    ) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            expr,
+            span: Span::new(0, self.source.len()),
+            depth,
+            parent,
+            children: Vec::new(),
+            folded: false,
+        });
+        for child in expr.children() {
+            let child = self.add(child, depth + 1, Some(index), spans);
+            self.nodes[index].children.push(child);
+        }
+        // Children come before their parent in post-order
+        if let Some(span) = spans.next() {
+            self.nodes[index].span = span;
+        }
+        index
+    }
+
+    /// The selected subtree
+    pub fn selected(&self) -> &'a Expr {
+        self.nodes[self.selected].expr
+    }
+
+    /// Where the selected subtree is in the source
+    pub fn selected_span(&self) -> Span {
+        self.nodes[self.selected].span
+    }
+
+    /// The input the tree was parsed from
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// The indices of the nodes that aren't inside a folded node, in pre-order
+    fn visible(&self) -> Vec<usize> {
+        let mut visible = Vec::new();
+        let mut index = 0;
+        while index < self.nodes.len() {
+            visible.push(index);
+            if self.nodes[index].folded {
+                // Skip the node's descendants, which come right after it
+                let depth = self.nodes[index].depth;
+                index += 1;
+                while index < self.nodes.len() && self.nodes[index].depth > depth {
+                    index += 1;
+                }
+            } else {
+                index += 1;
+            }
+        }
+        visible
+    }
+
+    /// Handle a key, returning `false` once the explorer should close
+    pub fn press(&mut self, key: Key) -> bool {
+        let node = &self.nodes[self.selected];
+        let has_children = !node.children.is_empty();
+        match key {
+            Key::Up | Key::Down => {
+                let visible = self.visible();
+                let position = visible
+                    .iter()
+                    .position(|&index| index == self.selected)
+                    .expect("the selected node is visible");
+                let position = match key {
+                    Key::Up => position.saturating_sub(1),
+                    _ => (position + 1).min(visible.len() - 1),
+                };
+                self.selected = visible[position];
+            }
+            Key::Left if has_children && !node.folded => self.nodes[self.selected].folded = true,
+            Key::Left => self.selected = node.parent.unwrap_or(self.selected),
+            Key::Right if node.folded => self.nodes[self.selected].folded = false,
+            Key::Right => self.selected = node.children.first().copied().unwrap_or(self.selected),
+            Key::Toggle if has_children => self.nodes[self.selected].folded ^= true,
+            Key::Toggle => {}
+            Key::Quit => return false,
+        }
+        true
+    }
+
+    /// A line for each node of the outline that isn't folded away, and which of them is selected
+    pub fn outline(&self, render: &Renderer) -> (Vec<String>, usize) {
+        let visible = self.visible();
+        let selected = visible
+            .iter()
+            .position(|&index| index == self.selected)
+            .expect("the selected node is visible");
+        let lines = visible
+            .into_iter()
+            .map(|index| {
+                let node = &self.nodes[index];
+                let marker = match (node.children.is_empty(), node.folded) {
+                    (true, _) => "•",
+                    (false, true) => "▸",
+                    (false, false) => "▾",
+                };
+                let mut line = format!(
+                    "{}{} {}",
+                    "  ".repeat(node.depth),
+                    marker,
+                    label(render, node.expr)
+                );
+                if node.folded {
+                    let text = &self.source[node.span.start..node.span.end];
+                    line.push_str(&render.paint(Style::Hint, format!("  {}", text)));
+                }
+                line
+            })
+            .collect();
+        (lines, selected)
+    }
+
+    /// The outline, followed by the selected subtree's value and its span underlined in the source
+    ///
+    /// `value` describes the value of a subtree, or why it has none.
+    pub fn render(&self, render: &Renderer, value: impl Fn(&Expr) -> String) -> String {
+        let mut out = String::new();
+        let (lines, selected) = self.outline(render);
+        for (row, line) in lines.into_iter().enumerate() {
+            let cursor = if row == selected { "> " } else { "  " };
+            out.push_str(cursor);
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        let span = self.selected_span();
+        out.push_str(&format!("\nvalue: {}\n", value(self.selected())));
+        out.push_str(&format!("span:  {}\n", span));
+        out.push_str(&format!("  {}\n", self.source));
+        let column = self.source[..span.start].chars().count();
+        let width = self.source[span.start..span.end].chars().count().max(1);
+        out.push_str(&format!(
+            "  {}{}\n",
+            " ".repeat(column),
+            render.paint(Style::Hint, "^".repeat(width))
+        ));
+        out
+    }
+}
+
+/// What a node is, without its children
+fn label(render: &Renderer, expr: &Expr) -> String {
+    let (kind, detail) = match expr {
+        Expr::Float(value) => ("Float", render.paint(Style::Number, value.to_string())),
+        Expr::Var(name) => ("Var", render.paint(Style::Variable, name)),
+        Expr::Call(name, _) => ("Call", render.paint(Style::Function, name)),
+        Expr::Named(name, _) => ("Named", render.paint(Style::Variable, name)),
+        Expr::Compare(comparison, _, _) => ("Compare", comparison.symbol().to_string()),
+        Expr::Add(..) => ("Add", "+".to_string()),
+        Expr::Sub(..) => ("Sub", "-".to_string()),
+        Expr::Mul(..) => ("Mul", "*".to_string()),
+        Expr::Div(..) => ("Div", "/".to_string()),
+        Expr::And(..) => ("And", "&&".to_string()),
+        Expr::Or(..) => ("Or", "||".to_string()),
+        Expr::Neg(_) => ("Neg", "-".to_string()),
+        Expr::Not(_) => ("Not", "!".to_string()),
+        Expr::Let(name, _, _) => ("Let", render.paint(Style::Variable, name)),
+        Expr::List(elements) => ("List", format!("{} elements", elements.len())),
+        Expr::Index(..) => ("Index", "[]".to_string()),
+        Expr::Piecewise(cases, default) => (
+            "Piecewise",
+            format!(
+                "{} cases{}",
+                cases.len(),
+                if default.is_some() { ", default" } else { "" }
+            ),
+        ),
+        Expr::CellRef(_) | Expr::CellRange(_, _) | Expr::Error(_) => {
+            let debug = format!("{:?}", expr);
+            let name = debug.split('(').next().unwrap_or_default().to_string();
+            return render.paint(Style::Node, &name) + &debug[name.len()..];
+        }
+    };
+    format!("{} {}", render.paint(Style::Node, kind), detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::parse_with_spans;
+
+    /// Test reading keys, including the escape sequences of arrow keys
+    #[test]
+    fn test_keys() {
+        assert_eq!(Key::parse_line(""), [Key::Toggle]);
+        assert_eq!(
+            Key::parse_line("jj\x1b[A\x1b[Cxh q"),
+            [
+                Key::Down,
+                Key::Down,
+                Key::Up,
+                Key::Right,
+                Key::Left,
+                Key::Toggle,
+                Key::Quit
+            ]
+        );
+    }
+
+    /// Test walking and folding the outline, and what's shown for the selection
+    #[test]
+    fn test_navigation() {
+        let source = "1 + (x * 2)";
+        let (ast, _, spans) = parse_with_spans(source);
+        let mut explorer = Explorer::new(source, &ast, &spans);
+        let plain = Renderer::new(None);
+        let value = |expr: &Expr| format!("{:?}", ast::evaluate(expr).ok());
+
+        assert_eq!(explorer.selected(), &ast);
+        assert_eq!(explorer.selected_span(), Span::new(0, 11));
+
+        // Down to the product, then into it
+        for key in [Key::Down, Key::Down, Key::Right] {
+            assert!(explorer.press(key));
+        }
+        assert_eq!(explorer.selected(), &Expr::Var("x".to_string()));
+        assert_eq!(explorer.selected_span(), Span::new(5, 6));
+
+        // Back out to the product and fold it
+        explorer.press(Key::Left);
+        explorer.press(Key::Left);
+        assert_eq!(explorer.selected_span(), Span::new(5, 10));
+        assert_eq!(
+            explorer.render(&plain, value),
+            "  ▾ Add +\n    • Float 1\n>   ▸ Mul *  x * 2\n\n\
+             value: None\nspan:  5..10\n  1 + (x * 2)\n       ^^^^^\n"
+        );
+
+        // The last visible node is the folded product
+        explorer.press(Key::Down);
+        assert_eq!(explorer.selected_span(), Span::new(5, 10));
+        explorer.press(Key::Up);
+        explorer.press(Key::Up);
+        explorer.press(Key::Toggle);
+        assert_eq!(
+            explorer.render(&plain, value).lines().next(),
+            Some("> ▸ Add +  1 + (x * 2)")
+        );
+        assert!(!explorer.press(Key::Quit));
+    }
+}
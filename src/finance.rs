@@ -0,0 +1,217 @@
+//! Financial functions
+//!
+//! Loan and investment functions with spreadsheet semantics: money paid out
+//! is negative and money received positive, `rate` is the interest rate per
+//! period, and the optional `type` is 1 for payments at the start of each
+//! period rather than the end. Arguments can be given by those names, as in
+//! `pmt(rate=0.05 / 12, nper=360, pv=100000)`, and the optional ones default
+//! to 0 (`irr`'s `guess` to 0.1), so `pmt(rate, nper, pv, type=1)` can skip
+//! `fv`. They're part of
+//! [`FunctionRegistry::standard`](crate::FunctionRegistry::standard), and so
+//! of the Excel functions too.
+//!
+//! Only available with the `finance` cargo feature.
+
+use crate::{
+    EvaluationError, FunctionRegistry, Parameter, Value,
+    functions::{domain, round},
+    stats::numbers,
+};
+
+/// How many Newton steps `irr` takes before giving up
+const IRR_ITERATIONS: usize = 100;
+
+/// Add the financial functions to a registry
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register("pmt", 3..=5, |args| {
+        let (rate, periods, pv) = (args[0], args[1], args[2]);
+        let (fv, due) = optional(&args[3..]);
+        if rate == 0.0 {
+            return Ok(-(pv + fv) / periods);
+        }
+        let growth = (1.0 + rate).powf(periods);
+        Ok(-rate * (fv + pv * growth) / ((1.0 + rate * due) * (growth - 1.0)))
+    });
+    registry.register("fv", 3..=5, |args| {
+        let (rate, periods, payment) = (args[0], args[1], args[2]);
+        let (pv, due) = optional(&args[3..]);
+        if rate == 0.0 {
+            return Ok(-(pv + payment * periods));
+        }
+        let growth = (1.0 + rate).powf(periods);
+        Ok(-(pv * growth + payment * (1.0 + rate * due) * (growth - 1.0) / rate))
+    });
+    registry.register("pv", 3..=5, |args| {
+        let (rate, periods, payment) = (args[0], args[1], args[2]);
+        let (fv, due) = optional(&args[3..]);
+        if rate == 0.0 {
+            return Ok(-(fv + payment * periods));
+        }
+        let growth = (1.0 + rate).powf(periods);
+        Ok(-(fv + payment * (1.0 + rate * due) * (growth - 1.0) / rate) / growth)
+    });
+    registry.register_value("npv", 2.., |args| {
+        let rate = args[0].clone().into_number()?;
+        let npv = present_value(rate, &numbers(&args[1..])?, 1);
+        domain("npv", rate != -1.0, npv).map(Value::Number)
+    });
+    registry.register_value("irr", 1..=2, |args| {
+        let flows = match &args[0] {
+            Value::Matrix(m) => m.elements(),
+            other => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a matrix",
+                    found: other.type_name(),
+                });
+            }
+        };
+        let guess = args.get(1).cloned().map_or(Ok(0.1), Value::into_number)?;
+        irr(flows, guess)
+            .map(Value::Number)
+            .ok_or_else(|| EvaluationError::DomainError("irr".to_string()))
+    });
+    registry.register("round_bankers", 1..=2, |args| {
+        Ok(round(
+            args[0],
+            args.get(1).copied().unwrap_or(0.0),
+            f64::round_ties_even,
+        ))
+    });
+    // The optional arguments default to 0, as in spreadsheets
+    let optional = |name| Parameter::new(name).with_default(0.0);
+    let (fv, pv, due) = (optional("fv"), optional("pv"), optional("type"));
+    registry.set_parameters(
+        "pmt",
+        &[
+            "rate".into(),
+            "nper".into(),
+            "pv".into(),
+            fv.clone(),
+            due.clone(),
+        ],
+    );
+    registry.set_parameters(
+        "fv",
+        &["rate".into(), "nper".into(), "pmt".into(), pv, due.clone()],
+    );
+    registry.set_parameters("pv", &["rate".into(), "nper".into(), "pmt".into(), fv, due]);
+    registry.set_parameters(
+        "irr",
+        &["values".into(), Parameter::new("guess").with_default(0.1)],
+    );
+    registry.set_parameters("round_bankers", &["x".into(), optional("digits")]);
+}
+
+/// The optional future or present value (default 0) and payment type (default 0, any nonzero is 1)
+fn optional(args: &[f64]) -> (f64, f64) {
+    let value = args.first().copied().unwrap_or(0.0);
+    let due = args.get(1).map_or(0.0, |&due| f64::from(due != 0.0));
+    (value, due)
+}
+
+/// The value of cash flows discounted at `rate`, the first one `first` periods away
+fn present_value(rate: f64, flows: &[f64], first: i32) -> f64 {
+    (first..)
+        .zip(flows)
+        .map(|(period, flow)| flow / (1.0 + rate).powi(period))
+        .sum()
+}
+
+/// The rate at which the cash flows, starting now, have no net present value
+///
+/// Found with Newton's method from `guess`, as spreadsheets do, so it's
+/// `None` if the flows don't change sign or the method doesn't settle.
+fn irr(flows: &[f64], guess: f64) -> Option<f64> {
+    let signs_change = flows.iter().any(|&x| x > 0.0) && flows.iter().any(|&x| x < 0.0);
+    if !signs_change {
+        return None;
+    }
+    let mut rate = guess;
+    for _ in 0..IRR_ITERATIONS {
+        let value = present_value(rate, flows, 0);
+        let slope: f64 = (0..)
+            .zip(flows)
+            .map(|(period, flow)| -f64::from(period) * flow / (1.0 + rate).powi(period + 1))
+            .sum();
+        let next = rate - value / slope;
+        if !next.is_finite() || next <= -1.0 {
+            return None;
+        }
+        if (next - rate).abs() < 1e-12 {
+            return Some(next);
+        }
+        rate = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Evaluator, parse_expression};
+
+    /// Evaluate an expression with the standard functions
+    fn eval(expression: &str) -> Result<f64, EvaluationError> {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        Evaluator::new(&Environment::new()).evaluate(&ast)
+    }
+
+    /// Test against the examples in spreadsheet documentation
+    #[test]
+    fn test_finance() {
+        let cases = [
+            ("pmt(0.08 / 12, 10, 10000)", -1037.0320893591),
+            ("pmt(0.08 / 12, 10, 10000, 0, 1)", -1030.1643271779),
+            ("pmt(0, 4, 1000)", -250.0),
+            ("pmt(nper=10, pv=10000, rate=0.08 / 12)", -1037.0320893591),
+            ("pmt(0.08 / 12, 10, 10000, fv=0, type=1)", -1030.1643271779),
+            ("pmt(0.08 / 12, 10, 10000, type=1)", -1030.1643271779),
+            ("fv(0.06 / 12, 10, -200, -500, 1)", 2581.4033740601),
+            ("fv(0, 10, -100)", 1000.0),
+            ("pv(0.08 / 12, 12 * 20, 500)", -59777.1458511878),
+            ("npv(0.1, -10000, 3000, 4200, 6800)", 1188.4434123352),
+            ("npv(0.1, [-10000, 3000], [4200, 6800])", 1188.4434123352),
+            (
+                "irr([-70000, 12000, 15000, 18000, 21000, 26000])",
+                0.0866309480,
+            ),
+            (
+                "irr([-70000, 12000, 15000, 18000, 21000], -0.1)",
+                -0.0212448483,
+            ),
+            ("round_bankers(2.5) + round_bankers(3.5)", 6.0),
+            ("round_bankers(0.125, 2)", 0.12),
+        ];
+        for (expression, expected) in cases {
+            let result = eval(expression).unwrap();
+            assert!(
+                (result - expected).abs() < 1e-9,
+                "'{}': expected {}, got {}",
+                expression,
+                expected,
+                result
+            );
+        }
+    }
+
+    /// Test that irr fails without a change of sign, and npv at a rate of -100%
+    #[test]
+    fn test_finance_errors() {
+        assert!(matches!(
+            eval("irr([100, 200])"),
+            Err(EvaluationError::DomainError(name)) if name == "irr"
+        ));
+        assert!(matches!(
+            eval("npv(-1, 100)"),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(matches!(
+            eval("irr(5)"),
+            Err(EvaluationError::TypeMismatch {
+                expected: "a matrix",
+                ..
+            })
+        ));
+    }
+}
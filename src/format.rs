@@ -0,0 +1,238 @@
+//! Formatting numbers for display
+//!
+//! Results are shown with the fewest digits that still read back as exactly
+//! the same number, switching to exponent notation for very large and very
+//! small magnitudes. A [`NumberFormat`] with a precision rounds them to fewer
+//! significant digits first, which hides floating-point noise such as the
+//! last digit of `0.1 + 0.2 = 0.30000000000000004`. Engineering and SI
+//! [`Notation`] write exponents in steps of 3, the way component values and
+//! lab measurements are usually given.
+
+use crate::si;
+
+/// How numbers are written out
+///
+/// # Example
+/// ```
+/// use ast::{NumberFormat, Notation};
+///
+/// let shortest = NumberFormat::default();
+/// assert_eq!(shortest.format(0.1 + 0.2), "0.30000000000000004");
+/// assert_eq!(shortest.format(1e300), "1e300");
+/// assert_eq!(shortest.format(-0.000125), "-0.000125");
+///
+/// let rounded = NumberFormat { precision: Some(10), ..NumberFormat::default() };
+/// assert_eq!(rounded.format(0.1 + 0.2), "0.3");
+/// assert_eq!(rounded.format(2.0 / 3.0), "0.6666666667");
+///
+/// let measured = NumberFormat { notation: Notation::Si, ..NumberFormat::significant_figures(3) };
+/// assert_eq!(measured.format(4700.0), "4.70k");
+/// assert_eq!(measured.format(0.0000221), "22.1µ");
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct NumberFormat {
+    /// The most significant digits to show, or `None` for as many as it
+    /// takes to identify the number exactly
+    pub precision: Option<usize>,
+
+    /// Keep the trailing zeros of a rounded number, so that with a precision
+    /// of 4, 1.5 is "1.500" rather than "1.5"
+    pub trailing_zeros: bool,
+
+    /// Where the exponent goes, see [`Notation`]
+    pub notation: Notation,
+}
+
+/// How the magnitude of a number is written
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Notation {
+    /// Plain digits, with an exponent (`1.5e-9`) only for very large and very
+    /// small numbers
+    #[default]
+    Auto,
+
+    /// An exponent that is a multiple of 3, e.g. `12.5e3` and `470e-9`, left
+    /// out when it is 0
+    Engineering,
+
+    /// Engineering notation with SI prefixes for the exponent, e.g. `12.5k`
+    /// and `470n`, from `y` (10^-24) to `Y` (10^24)
+    ///
+    /// Numbers beyond that range use an exponent.
+    Si,
+}
+
+/// Numbers from 10^-7 up to (but not including) 10^21 are written without an
+/// exponent, the same range as JavaScript uses
+const POSITIONAL: std::ops::Range<i32> = -7..21;
+
+impl NumberFormat {
+    /// Exactly `digits` significant figures, including trailing zeros
+    pub fn significant_figures(digits: usize) -> Self {
+        NumberFormat {
+            precision: Some(digits),
+            trailing_zeros: true,
+            ..NumberFormat::default()
+        }
+    }
+
+    /// Write a number in this format
+    pub fn format(&self, value: f64) -> String {
+        if value.is_nan() {
+            return "NaN".to_string();
+        } else if value.is_infinite() {
+            return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+        } else if value == 0.0 {
+            // Including -0, which would only be confusing
+            return "0".to_string();
+        }
+
+        // Scientific notation gives the digits and exponent, correctly rounded
+        let scientific = match self.precision {
+            Some(precision) => format!("{:.*e}", precision.max(1) - 1, value),
+            None => format!("{:e}", value),
+        };
+        let (mantissa, exponent) = scientific
+            .split_once('e')
+            .expect("scientific notation has an exponent");
+        let exponent: i32 = exponent.parse().expect("the exponent is an integer");
+        let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+        let digits = if self.trailing_zeros && self.precision.is_some() {
+            &digits
+        } else {
+            digits.trim_end_matches('0')
+        };
+
+        let magnitude = match self.notation {
+            Notation::Auto if POSITIONAL.contains(&exponent) => positional(digits, exponent),
+            Notation::Auto => exponential(digits, exponent),
+            Notation::Engineering | Notation::Si => {
+                let power = exponent.div_euclid(3) * 3;
+                let mantissa = positional(digits, exponent - power);
+                let prefix = usize::try_from(power / 3 + 8)
+                    .ok()
+                    .and_then(|index| si::PREFIXES.get(index));
+                match prefix {
+                    Some(prefix) if self.notation == Notation::Si => mantissa + prefix,
+                    _ if power == 0 => mantissa,
+                    _ => format!("{}e{}", mantissa, power),
+                }
+            }
+        };
+        let sign = if value < 0.0 { "-" } else { "" };
+        format!("{}{}", sign, magnitude)
+    }
+}
+
+/// Write `d.ddd × 10^exponent` without an exponent, e.g. "1234.5" or "0.00012"
+fn positional(digits: &str, exponent: i32) -> String {
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        return format!("0.{}{}", zeros, digits);
+    }
+
+    let whole = exponent as usize + 1;
+    if digits.len() <= whole {
+        format!("{}{}", digits, "0".repeat(whole - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..whole], &digits[whole..])
+    }
+}
+
+/// Write `d.ddd × 10^exponent` with an exponent, e.g. "1.5e-9"
+fn exponential(digits: &str, exponent: i32) -> String {
+    let (first, rest) = digits.split_at(1);
+    if rest.is_empty() {
+        format!("{}e{}", first, exponent)
+    } else {
+        format!("{}.{}e{}", first, rest, exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that the shortest format reads back exactly, with or without an exponent
+    #[test]
+    fn test_shortest() {
+        let cases = [
+            (42.0, "42"),
+            (-3.5, "-3.5"),
+            (-0.0, "0"),
+            (1e20, "100000000000000000000"),
+            (1e21, "1e21"),
+            (1.25e-7, "0.000000125"),
+            (1.25e-8, "1.25e-8"),
+            (123456.789, "123456.789"),
+            (f64::MAX, "1.7976931348623157e308"),
+            (5e-324, "5e-324"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (value, expected) in cases {
+            let formatted = NumberFormat::default().format(value);
+            assert_eq!(formatted, expected);
+            assert_eq!(formatted.parse::<f64>().unwrap(), value);
+        }
+    }
+
+    /// Test rounding to a number of significant digits
+    #[test]
+    fn test_precision() {
+        let format = |precision, value| {
+            NumberFormat {
+                precision: Some(precision),
+                ..NumberFormat::default()
+            }
+            .format(value)
+        };
+        assert_eq!(format(3, 123456.0), "123000");
+        assert_eq!(format(3, 0.0012345), "0.00123");
+        assert_eq!(format(3, 9.999), "10");
+        assert_eq!(format(3, 9.999e20), "1e21");
+        assert_eq!(format(1, -0.26), "-0.3");
+        assert_eq!(format(0, 7.5), "8");
+        assert_eq!(format(15, 0.1 + 0.2), "0.3");
+        assert_eq!(format(17, 0.1 + 0.2), "0.30000000000000004");
+
+        let fixed = |digits, value| NumberFormat::significant_figures(digits).format(value);
+        assert_eq!(fixed(4, 1.5), "1.500");
+        assert_eq!(fixed(2, 0.000123), "0.00012");
+        assert_eq!(fixed(3, 2e25), "2.00e25");
+        assert_eq!(fixed(2, 999.0), "1000");
+    }
+
+    /// Test engineering notation and SI prefixes
+    #[test]
+    fn test_engineering() {
+        let format = |notation, value| {
+            NumberFormat {
+                notation,
+                ..NumberFormat::default()
+            }
+            .format(value)
+        };
+        let cases = [
+            (12.0, "12", "12"),
+            (12345.0, "12.345e3", "12.345k"),
+            (-0.0047, "-4.7e-3", "-4.7m"),
+            (1e-6, "1e-6", "1µ"),
+            (2.2e6, "2.2e6", "2.2M"),
+            (0.1, "100e-3", "100m"),
+            (1e27, "1e27", "1e27"),
+            (3.3e-25, "330e-27", "330e-27"),
+        ];
+        for (value, engineering, si) in cases {
+            assert_eq!(format(Notation::Engineering, value), engineering);
+            assert_eq!(format(Notation::Si, value), si);
+        }
+
+        let fixed = NumberFormat {
+            notation: Notation::Si,
+            ..NumberFormat::significant_figures(3)
+        };
+        assert_eq!(fixed.format(1000.0), "1.00k");
+        assert_eq!(fixed.format(999.9), "1.00k");
+        assert_eq!(fixed.format(470e-9), "470n");
+    }
+}
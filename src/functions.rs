@@ -0,0 +1,1231 @@
+//! Built-in and registered functions
+//!
+//! Function calls like `sqrt(2)` or `max(a, b, c)` are looked up by name in a
+//! [`FunctionRegistry`] when they are evaluated. [`FunctionRegistry::standard`]
+//! holds the usual math functions and the date functions, and embedders can
+//! register their own.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    ops::{BitOr, RangeFrom, RangeInclusive},
+    sync::{Arc, LazyLock},
+};
+
+use crate::{
+    AuditEvent, EvaluationError, Evaluator, Expr, Matrix, Value, audit::text, datetime, format,
+    matrix, stats,
+};
+
+/// The signature of a function that works on already-evaluated arguments
+type NativeFn = dyn Fn(&[f64]) -> Result<f64, EvaluationError> + Send + Sync;
+
+/// The signature of a function that works on values other than numbers, such as dates
+type ValueFn = dyn Fn(&[Value]) -> Result<Value, EvaluationError> + Send + Sync;
+
+/// The signature of a function that decides itself which arguments to evaluate
+type LazyFn = fn(&Evaluator<'_>, &[Expr]) -> Result<Value, EvaluationError>;
+
+/// The standard registry, shared by every evaluator that isn't given another one
+pub(crate) static STANDARD: LazyLock<FunctionRegistry> = LazyLock::new(FunctionRegistry::standard);
+
+/// The unit that trigonometric functions take and give angles in
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AngleUnit {
+    /// A full turn is 2π
+    #[default]
+    Radians,
+    /// A full turn is 360
+    Degrees,
+}
+
+/// How many arguments a function accepts
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Arity {
+    /// The fewest arguments allowed
+    pub min: usize,
+    /// The most arguments allowed, or `None` if there's no limit
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    /// Whether a call with `count` arguments is allowed
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl From<usize> for Arity {
+    /// Exactly this many arguments
+    fn from(count: usize) -> Self {
+        Arity {
+            min: count,
+            max: Some(count),
+        }
+    }
+}
+
+impl From<RangeInclusive<usize>> for Arity {
+    /// Between the two counts, inclusive
+    fn from(range: RangeInclusive<usize>) -> Self {
+        Arity {
+            min: *range.start(),
+            max: Some(*range.end()),
+        }
+    }
+}
+
+impl From<RangeFrom<usize>> for Arity {
+    /// At least this many arguments
+    fn from(range: RangeFrom<usize>) -> Self {
+        Arity {
+            min: range.start,
+            max: None,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// A named parameter of a function, see [`FunctionRegistry::set_parameters`]
+///
+/// # Example
+/// ```
+/// use ast::Parameter;
+///
+/// let digits = Parameter::new("digits").with_default(0.0);
+/// assert_eq!(digits.to_string(), "digits=0");
+/// assert_eq!(Parameter::from("x").default(), None);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    name: String,
+    /// What a call that leaves the argument out gets instead
+    default: Option<Expr>,
+}
+
+impl Parameter {
+    /// A parameter that every call has to give
+    pub fn new(name: &str) -> Self {
+        Parameter {
+            name: name.to_string(),
+            default: None,
+        }
+    }
+
+    /// Let calls leave the argument out, using `value` instead
+    pub fn with_default(mut self, value: f64) -> Self {
+        self.default = Some(Expr::Float(value));
+        self
+    }
+
+    /// The parameter's name, for named arguments
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What a call that leaves the argument out gets instead, if it may
+    pub fn default(&self) -> Option<&Expr> {
+        self.default.as_ref()
+    }
+}
+
+impl From<&str> for Parameter {
+    fn from(name: &str) -> Self {
+        Parameter::new(name)
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.default {
+            Some(default) => write!(f, "{}={}", self.name, format(default)),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// What a function depends on besides its arguments
+///
+/// A function's result normally follows from its arguments alone. Functions
+/// that also read the clock, draw random numbers or reach outside the
+/// program say so, and a [`FunctionRegistry`] can [deny](FunctionRegistry::deny)
+/// them, so that untrusted formulas give the same result every time they're
+/// evaluated. Combine capabilities with `|`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    /// Depends on nothing but the arguments
+    pub const NONE: Capabilities = Capabilities(0);
+    /// Reads the current date or time, like `now()`
+    pub const CLOCK: Capabilities = Capabilities(1);
+    /// Uses random numbers
+    pub const RANDOM: Capabilities = Capabilities(2);
+    /// Reads or changes anything outside the evaluation, such as files or the network
+    pub const EXTERNAL: Capabilities = Capabilities(4);
+    /// Anything that can give a different result for the same arguments
+    pub const NONDETERMINISTIC: Capabilities = Capabilities(1 | 2 | 4);
+
+    /// Whether every capability in `other` is in this set too
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether this set and `other` have any capability in common
+    pub fn intersects(self, other: Capabilities) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+/// How a function computes its result
+#[derive(Clone)]
+enum Body {
+    /// Called with the values of all arguments, which must be numbers
+    Native(Arc<NativeFn>),
+    /// Called with the values of all arguments, of any type
+    Value(Arc<ValueFn>),
+    /// Called with the unevaluated arguments, e.g. for `IF`
+    Lazy(LazyFn),
+}
+
+/// A function that can be called from expressions
+#[derive(Clone)]
+pub struct Function {
+    arity: Arity,
+    body: Body,
+    /// What the function depends on besides its arguments
+    capabilities: Capabilities,
+    /// Its parameters, in order, for calls with named or left out arguments
+    parameters: Vec<Parameter>,
+}
+
+impl Function {
+    /// How many arguments the function accepts
+    ///
+    /// Parameters with defaults at the end of the list can be left out,
+    /// so they lower the fewest arguments a call needs.
+    pub fn arity(&self) -> Arity {
+        let required = self
+            .parameters
+            .iter()
+            .rposition(|parameter| parameter.default.is_none())
+            .map_or(0, |position| position + 1);
+        let min = if self.parameters.len() >= self.arity.min {
+            required.min(self.arity.min)
+        } else {
+            self.arity.min
+        };
+        Arity { min, ..self.arity }
+    }
+
+    /// What the function depends on besides its arguments
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Its parameters, in order, or none if it only takes positional arguments
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// How a call to the function, named `name`, is written, as in `round(x, digits=0)`
+    ///
+    /// Just the name if its parameters aren't named.
+    pub fn signature(&self, name: &str) -> String {
+        if self.parameters.is_empty() {
+            return name.to_string();
+        }
+        let parameters: Vec<String> = self.parameters.iter().map(ToString::to_string).collect();
+        format!("{}({})", name, parameters.join(", "))
+    }
+
+    /// The error for a call to the function, named `name`, with `found` arguments
+    pub(crate) fn wrong_count(&self, name: &str, found: usize) -> EvaluationError {
+        EvaluationError::WrongArgumentCount {
+            signature: self.signature(name).into(),
+            expected: self.arity(),
+            found,
+        }
+    }
+
+    /// Whether a call with `args` has to be [arranged](Function::arrange)
+    /// before the function can take them
+    pub(crate) fn needs_arranging(&self, args: &[Expr]) -> bool {
+        args.len() < self.arity.min || args.iter().any(|arg| matches!(arg, Expr::Named(..)))
+    }
+
+    /// The arguments of a call to the function, named `name`, in parameter order
+    ///
+    /// Positional arguments come first, and each named one goes where its
+    /// parameter is, leaving off the [`Expr::Named`] wrapper. A parameter
+    /// left out before a later one is given gets its default, as do the
+    /// last ones if there are too few arguments without them. A name that
+    /// isn't a parameter, a parameter given twice, or one left out without
+    /// a default are errors.
+    pub(crate) fn arrange<'e>(
+        &self,
+        name: &str,
+        args: &'e [Expr],
+    ) -> Result<Vec<Cow<'e, Expr>>, EvaluationError> {
+        let mut slots: Vec<Option<&Expr>> = Vec::with_capacity(args.len());
+        for arg in args {
+            let Expr::Named(parameter, value) = arg else {
+                slots.push(Some(arg));
+                continue;
+            };
+            let position = self
+                .parameters
+                .iter()
+                .position(|candidate| candidate.name == *parameter)
+                .ok_or_else(|| EvaluationError::UnknownParameter {
+                    name: name.to_string(),
+                    parameter: parameter.clone(),
+                })?;
+            if position >= slots.len() {
+                slots.resize(position + 1, None);
+            }
+            if slots[position].is_some() {
+                return Err(EvaluationError::DuplicateArgument {
+                    name: name.to_string(),
+                    parameter: parameter.clone(),
+                });
+            }
+            slots[position] = Some(value);
+        }
+        let mut arranged = Vec::with_capacity(slots.len().max(self.arity.min));
+        for (position, slot) in slots.into_iter().enumerate() {
+            let arg = match slot {
+                Some(arg) => Cow::Borrowed(arg),
+                // Only named arguments leave gaps, so a gap always has a parameter
+                None => {
+                    let parameter = &self.parameters[position];
+                    let default = parameter.default.clone().ok_or_else(|| {
+                        EvaluationError::MissingArgument {
+                            name: name.to_string(),
+                            parameter: parameter.name.clone(),
+                        }
+                    })?;
+                    Cow::Owned(default)
+                }
+            };
+            arranged.push(arg);
+        }
+        let defaults = self.parameters[arranged.len().min(self.parameters.len())..]
+            .iter()
+            .map_while(|parameter| parameter.default.clone());
+        let missing = self.arity.min.saturating_sub(arranged.len());
+        arranged.extend(defaults.take(missing).map(Cow::Owned));
+        Ok(arranged)
+    }
+
+    /// Call the function, named `name`, with the (unevaluated) arguments from a call expression
+    ///
+    /// A function on numbers given matrices is applied element by element.
+    pub(crate) fn call(
+        &self,
+        name: &str,
+        evaluator: &Evaluator<'_>,
+        args: &[Expr],
+    ) -> Result<Value, EvaluationError> {
+        let mut arguments = None;
+        let result = self.run(name, evaluator, args, &mut arguments);
+        evaluator.record(|| AuditEvent::Call {
+            function: name.to_string(),
+            arguments: arguments.map(|values| values.iter().map(text).collect()),
+            result: result.as_ref().ok().map(text),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        result
+    }
+
+    /// Helper for [`Function::call`], which keeps the evaluated arguments in
+    /// `arguments` when the evaluation is audited
+    fn run(
+        &self,
+        name: &str,
+        evaluator: &Evaluator<'_>,
+        args: &[Expr],
+        arguments: &mut Option<Vec<Value>>,
+    ) -> Result<Value, EvaluationError> {
+        let mut values = || -> Result<Option<Vec<Value>>, EvaluationError> {
+            let mut values = evaluator.evaluate_arguments(args)?;
+            if evaluator.is_audited() {
+                *arguments = Some(values.clone());
+            }
+            Ok(evaluator.replace_nulls(&mut values).then_some(values))
+        };
+        match &self.body {
+            Body::Native(function) => {
+                let Some(values) = values()? else {
+                    return Ok(Value::Null);
+                };
+                let function = |numbers: &[f64]| evaluator.apply_domain_policy(function(numbers));
+                if let Some(first) = values.iter().find_map(as_matrix) {
+                    return broadcast(name, function, first, &values);
+                }
+                let numbers = values
+                    .into_iter()
+                    .map(Value::into_number)
+                    .collect::<Result<Vec<_>, _>>()?;
+                function(&numbers).map(Value::Number)
+            }
+            Body::Value(function) => match values()? {
+                Some(values) => function(&values),
+                None => Ok(Value::Null),
+            },
+            Body::Lazy(function) => function(evaluator, args),
+        }
+    }
+
+    /// Whether the function evaluates its own arguments, like `if`
+    pub(crate) fn is_lazy(&self) -> bool {
+        matches!(self.body, Body::Lazy(_))
+    }
+
+    /// Call the function with arguments that are already evaluated to numbers
+    ///
+    /// `None` if the function evaluates its own arguments, so it needs them unevaluated.
+    pub(crate) fn call_numbers(&self, args: &[f64]) -> Option<Result<Value, EvaluationError>> {
+        match &self.body {
+            Body::Native(function) => Some(function(args).map(Value::Number)),
+            Body::Value(function) => {
+                let values: Vec<_> = args.iter().copied().map(Value::Number).collect();
+                Some(function(&values))
+            }
+            Body::Lazy(_) => None,
+        }
+    }
+}
+
+/// Apply a function on numbers to each element of its matrix arguments
+///
+/// The matrices must all have the same size, and number arguments are used
+/// for every element, so `pow([1, 2], 2)` is `[1, 4]`.
+fn broadcast(
+    name: &str,
+    function: impl Fn(&[f64]) -> Result<f64, EvaluationError>,
+    first: &Matrix,
+    values: &[Value],
+) -> Result<Value, EvaluationError> {
+    let size = |matrix: &Matrix| (matrix.rows(), matrix.columns());
+    let mut matrices = values.iter().filter_map(as_matrix);
+    if let Some(other) = matrices.find(|matrix| size(matrix) != size(first)) {
+        return Err(EvaluationError::ArgumentSizes {
+            name: name.into(),
+            left: size(first),
+            right: size(other),
+        });
+    }
+    let mut elements = Vec::with_capacity(first.elements().len());
+    let mut numbers = vec![0.0; values.len()];
+    for i in 0..first.elements().len() {
+        for (number, value) in numbers.iter_mut().zip(values) {
+            *number = match value {
+                Value::Matrix(matrix) => matrix.elements()[i],
+                other => other.clone().into_number()?,
+            };
+        }
+        elements.push(function(&numbers)?);
+    }
+    let (rows, columns) = size(first);
+    Ok(Value::Matrix(
+        Matrix::new(rows, columns, elements).expect("one result per element"),
+    ))
+}
+
+/// The matrix in a value, if it is one
+fn as_matrix(value: &Value) -> Option<&Matrix> {
+    match value {
+        Value::Matrix(matrix) => Some(matrix),
+        _ => None,
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("arity", &self.arity())
+            .field("capabilities", &self.capabilities)
+            .field("parameters", &self.parameters)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A set of functions that expressions can call, by name
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, Environment, Evaluator, FunctionRegistry};
+///
+/// let mut functions = FunctionRegistry::standard();
+/// functions.register("double", 1, |args| Ok(args[0] * 2.0));
+///
+/// let env = Environment::new();
+/// let (_, ast) = parse_expression("double(sqrt(16))").unwrap();
+/// let value = Evaluator::new(&env).with_functions(&functions).evaluate(&ast).unwrap();
+/// assert_eq!(value, 8.0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Function>,
+    /// Whether `SUM` finds `sum`, as in spreadsheets
+    case_insensitive: bool,
+    /// Functions needing any of these can't be called
+    denied: Capabilities,
+}
+
+impl FunctionRegistry {
+    /// Create a registry without any functions
+    pub fn new() -> Self {
+        FunctionRegistry::default()
+    }
+
+    /// The standard math functions
+    ///
+    /// | Function | Description |
+    /// | --- | --- |
+    /// | `abs(x)`, `sign(x)` | Absolute value and sign (-1, 0 or 1) |
+    /// | `sqrt(x)`, `cbrt(x)` | Square and cube roots |
+    /// | `pow(x, y)`, `exp(x)` | Powers |
+    /// | `ln(x)`, `log10(x)`, `log2(x)` | Logarithms |
+    /// | `sin`, `cos`, `tan`, `asin`, `acos`, `atan`, `atan2(y, x)` | Trigonometry, in radians (see [`FunctionRegistry::set_angle_unit`]) |
+    /// | `sinh`, `cosh`, `tanh` | Hyperbolic functions |
+    /// | `floor(x)`, `ceil(x)`, `trunc(x)` | Rounding to an integer |
+    /// | `round(x)`, `round(x, digits)` | Rounding half away from zero |
+    /// | `min(...)`, `max(...)` | Smallest and largest of one or more values |
+    /// | `hypot(x, y)` | Length of the hypotenuse |
+    /// | `factorial(n)`, `comb(n, k)` | Factorial and the number of ways to choose `k` of `n`, for whole numbers |
+    /// | `coalesce(...)` | The first argument that isn't missing or null |
+    /// | `if(condition, then, otherwise)` | `then` if the condition is nonzero, otherwise `otherwise`; only the branch picked is evaluated |
+    ///
+    /// It also has the date and duration functions: `date(y, m, d, [h, min, s])`,
+    /// `now()`, `year(d)`, `month(d)`, `day(d)`, and `days(n)`, `hours(n)`,
+    /// `minutes(n)` and `seconds(n)` for durations, and the vector functions
+    /// `dot(a, b)`, `cross(a, b)` and `norm(a)`. The statistics functions
+    /// `mean`, `median`, `variance` and `stddev` take numbers and lists,
+    /// alongside `percentile(list, p)` and `correl(xs, ys)`.
+    ///
+    /// With the `finance` feature it also has `pmt`, `fv`, `pv`, `npv`, `irr`
+    /// and `round_bankers`, with spreadsheet semantics.
+    ///
+    /// The functions on numbers work element by element on vectors and
+    /// matrices, so `sqrt([4, 9])` is `[2, 3]`. `round`, `percentile`,
+    /// `date` (with `year`, `month`, `day`, `hour`, `minute` and `second`)
+    /// and the financial functions also take arguments by name, as in
+    /// `round(x, digits=2)`, and their optional arguments have defaults, so
+    /// `pmt(rate, nper, pv, type=1)` leaves `fv` at 0.
+    pub fn standard() -> Self {
+        let mut registry = FunctionRegistry::new();
+        registry.register("abs", 1, |args| Ok(args[0].abs()));
+        registry.register("sign", 1, |args| {
+            Ok(if args[0] == 0.0 {
+                0.0
+            } else {
+                args[0].signum()
+            })
+        });
+        registry.register("sqrt", 1, |args| {
+            argument("sqrt", args[0], args[0] >= 0.0, args[0].sqrt())
+        });
+        registry.register("cbrt", 1, |args| Ok(args[0].cbrt()));
+        registry.register("pow", 2, |args| Ok(args[0].powf(args[1])));
+        registry.register("exp", 1, |args| Ok(args[0].exp()));
+        registry.register("ln", 1, |args| {
+            argument("ln", args[0], args[0] > 0.0, args[0].ln())
+        });
+        registry.register("log10", 1, |args| {
+            argument("log10", args[0], args[0] > 0.0, args[0].log10())
+        });
+        registry.register("log2", 1, |args| {
+            argument("log2", args[0], args[0] > 0.0, args[0].log2())
+        });
+        registry.set_angle_unit(AngleUnit::Radians);
+        registry.register("sinh", 1, |args| Ok(args[0].sinh()));
+        registry.register("cosh", 1, |args| Ok(args[0].cosh()));
+        registry.register("tanh", 1, |args| Ok(args[0].tanh()));
+        registry.register("floor", 1, |args| Ok(args[0].floor()));
+        registry.register("ceil", 1, |args| Ok(args[0].ceil()));
+        registry.register("trunc", 1, |args| Ok(args[0].trunc()));
+        registry.register("round", 1..=2, |args| {
+            Ok(round(
+                args[0],
+                args.get(1).copied().unwrap_or(0.0),
+                f64::round,
+            ))
+        });
+        registry.register("min", 1.., |args| {
+            Ok(args.iter().copied().fold(f64::INFINITY, f64::min))
+        });
+        registry.register("max", 1.., |args| {
+            Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+        });
+        registry.register("hypot", 2, |args| Ok(args[0].hypot(args[1])));
+        registry.register("factorial", 1, |args| {
+            let n = argument("factorial", args[0], whole(args[0]), args[0])?;
+            Ok(factorial(n))
+        });
+        registry.register("comb", 2, |args| {
+            let n = argument("comb", args[0], whole(args[0]), args[0])?;
+            let k = argument("comb", args[1], whole(args[1]), args[1])?;
+            Ok(binomial(n, k))
+        });
+        registry.register_lazy("coalesce", 1.., coalesce);
+        registry.register_lazy("if", 3, choose);
+        registry.set_parameters(
+            "round",
+            &["x".into(), Parameter::new("digits").with_default(0.0)],
+        );
+        datetime::register(&mut registry);
+        matrix::register(&mut registry);
+        stats::register(&mut registry);
+        #[cfg(feature = "finance")]
+        crate::finance::register(&mut registry);
+        registry
+    }
+
+    /// Make the trigonometric functions take and give angles in `unit`
+    ///
+    /// This replaces `sin`, `cos`, `tan`, `asin`, `acos`, `atan` and `atan2`
+    /// with the standard versions for that unit.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, AngleUnit, Environment, Evaluator, FunctionRegistry};
+    ///
+    /// let mut functions = FunctionRegistry::standard();
+    /// functions.set_angle_unit(AngleUnit::Degrees);
+    ///
+    /// let env = Environment::new();
+    /// let (_, ast) = parse_expression("sin(30) + atan2(1, 1)").unwrap();
+    /// let value = Evaluator::new(&env).with_functions(&functions).evaluate(&ast).unwrap();
+    /// assert!((value - 45.5).abs() < 1e-12);
+    /// ```
+    pub fn set_angle_unit(&mut self, unit: AngleUnit) {
+        // Angles are converted to radians on the way in, and back on the way out
+        let (to_radians, from_radians) = match unit {
+            AngleUnit::Radians => (1.0, 1.0),
+            AngleUnit::Degrees => (std::f64::consts::PI / 180.0, 180.0 / std::f64::consts::PI),
+        };
+        self.register("sin", 1, move |args| Ok((args[0] * to_radians).sin()));
+        self.register("cos", 1, move |args| Ok((args[0] * to_radians).cos()));
+        self.register("tan", 1, move |args| Ok((args[0] * to_radians).tan()));
+        self.register("asin", 1, move |args| {
+            let in_domain = args[0].abs() <= 1.0;
+            argument("asin", args[0], in_domain, args[0].asin() * from_radians)
+        });
+        self.register("acos", 1, move |args| {
+            let in_domain = args[0].abs() <= 1.0;
+            argument("acos", args[0], in_domain, args[0].acos() * from_radians)
+        });
+        self.register("atan", 1, move |args| Ok(args[0].atan() * from_radians));
+        self.register("atan2", 2, move |args| {
+            Ok(args[0].atan2(args[1]) * from_radians)
+        });
+    }
+
+    /// Add a function, replacing any existing one with the same name
+    ///
+    /// `arity` is how many arguments it takes: an exact count like `2`, a
+    /// range like `1..=2`, or an open range like `1..` for "one or more".
+    /// The function is only called with an allowed number of arguments.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        arity: impl Into<Arity>,
+        function: impl Fn(&[f64]) -> Result<f64, EvaluationError> + Send + Sync + 'static,
+    ) {
+        self.insert(name.into(), arity.into(), Body::Native(Arc::new(function)));
+    }
+
+    /// Add a function that takes and returns any kind of [`Value`], not just numbers
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Environment, Evaluator, FunctionRegistry, Value};
+    ///
+    /// let mut functions = FunctionRegistry::standard();
+    /// functions.register_value("later", 1, |args| {
+    ///     let date = args[0].clone().into_date()?;
+    ///     Ok(Value::Date(date + ast::Duration::from_seconds(60.0)))
+    /// });
+    ///
+    /// let env = Environment::new();
+    /// let (_, ast) = parse_expression("later(date(2024, 1, 1))").unwrap();
+    /// let value = Evaluator::new(&env).with_functions(&functions).evaluate_value(&ast).unwrap();
+    /// assert_eq!(value.to_string(), "2024-01-01T00:01:00");
+    /// ```
+    pub fn register_value(
+        &mut self,
+        name: impl Into<String>,
+        arity: impl Into<Arity>,
+        function: impl Fn(&[Value]) -> Result<Value, EvaluationError> + Send + Sync + 'static,
+    ) {
+        self.insert(name.into(), arity.into(), Body::Value(Arc::new(function)));
+    }
+
+    /// Add a function that evaluates its own arguments, e.g. to skip some
+    pub(crate) fn register_lazy(&mut self, name: &str, arity: impl Into<Arity>, function: LazyFn) {
+        self.insert(name.to_string(), arity.into(), Body::Lazy(function));
+    }
+
+    /// Store a function under its (possibly case-folded) name
+    fn insert(&mut self, name: String, arity: Arity, body: Body) {
+        let name = if self.case_insensitive {
+            name.to_ascii_lowercase()
+        } else {
+            name
+        };
+        let function = Function {
+            arity,
+            body,
+            capabilities: Capabilities::NONE,
+            parameters: Vec::new(),
+        };
+        self.functions.insert(name, function);
+    }
+
+    /// Say what a function depends on besides its arguments, returning whether it exists
+    ///
+    /// Functions are registered as depending on nothing else; one that
+    /// reads the clock or uses random numbers should be marked so that
+    /// [`FunctionRegistry::deny`] can stop it being called.
+    pub fn set_capabilities(&mut self, name: &str, capabilities: Capabilities) -> bool {
+        let name = if self.case_insensitive {
+            name.to_ascii_lowercase()
+        } else {
+            name.to_string()
+        };
+        match self.functions.get_mut(&name) {
+            Some(function) => {
+                function.capabilities = capabilities;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Name a function's parameters, in order, returning whether it exists
+    ///
+    /// Calls can then give arguments by name, after any positional ones,
+    /// and in any order: `pmt(0.05 / 12, pv=100000, nper=360)`. Functions
+    /// are registered without names, so only take positional arguments.
+    /// Parameters given a default with [`Parameter::with_default`] can be
+    /// left out, and wrong argument counts are reported with the
+    /// function's [signature](Function::signature).
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{
+    ///     parse_expression, Environment, EvaluationError, Evaluator, FunctionRegistry, Parameter,
+    /// };
+    ///
+    /// let mut functions = FunctionRegistry::standard();
+    /// functions.register("clamp", 3, |args| Ok(args[0].clamp(args[1], args[2])));
+    /// functions.set_parameters(
+    ///     "clamp",
+    ///     &[
+    ///         Parameter::new("value"),
+    ///         Parameter::new("low").with_default(0.0),
+    ///         Parameter::new("high").with_default(1.0),
+    ///     ],
+    /// );
+    ///
+    /// let env = Environment::new();
+    /// let evaluator = Evaluator::new(&env).with_functions(&functions);
+    /// let eval = |input: &str| evaluator.evaluate(&parse_expression(input).unwrap().1);
+    /// assert_eq!(eval("clamp(150, high=100, low=0)").unwrap(), 100.0);
+    /// assert_eq!(eval("clamp(1.5) + clamp(-5, high=10)").unwrap(), 1.0);
+    ///
+    /// assert!(matches!(
+    ///     eval("clamp(150, 0, top=100)"),
+    ///     Err(EvaluationError::UnknownParameter { parameter, .. }) if parameter == "top"
+    /// ));
+    /// assert_eq!(
+    ///     eval("clamp()").unwrap_err().to_string(),
+    ///     "Function 'clamp(value, low=0, high=1)' takes 1 to 3 arguments, got 0"
+    /// );
+    /// ```
+    pub fn set_parameters(
+        &mut self,
+        name: &str,
+        parameters: &[impl Into<Parameter> + Clone],
+    ) -> bool {
+        let name = if self.case_insensitive {
+            name.to_ascii_lowercase()
+        } else {
+            name.to_string()
+        };
+        match self.functions.get_mut(&name) {
+            Some(function) => {
+                function.parameters = parameters.iter().cloned().map(Into::into).collect();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Refuse to call functions that need any of `capabilities`
+    ///
+    /// Calling one is an [`EvaluationError::NotPermitted`]. The functions
+    /// stay registered, so they can be allowed again with
+    /// [`FunctionRegistry::allow`].
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Capabilities, Environment, EvaluationError, Evaluator, FunctionRegistry};
+    ///
+    /// let mut functions = FunctionRegistry::standard();
+    /// functions.register("roll", 0, |_| Ok(4.0));
+    /// functions.set_capabilities("roll", Capabilities::RANDOM);
+    /// functions.deny(Capabilities::NONDETERMINISTIC);
+    ///
+    /// let env = Environment::new();
+    /// let evaluator = Evaluator::new(&env).with_functions(&functions);
+    /// for input in ["roll() + 1", "year(now())"] {
+    ///     let (_, ast) = parse_expression(input).unwrap();
+    ///     assert!(matches!(evaluator.evaluate(&ast), Err(EvaluationError::NotPermitted(_))));
+    /// }
+    /// let (_, ast) = parse_expression("sqrt(16)").unwrap();
+    /// assert_eq!(evaluator.evaluate(&ast).unwrap(), 4.0);
+    /// ```
+    pub fn deny(&mut self, capabilities: Capabilities) {
+        self.denied = self.denied | capabilities;
+    }
+
+    /// Let functions that need `capabilities` be called again, after [`FunctionRegistry::deny`]
+    pub fn allow(&mut self, capabilities: Capabilities) {
+        self.denied = Capabilities(self.denied.0 & !capabilities.0);
+    }
+
+    /// The capabilities functions can't use
+    pub fn denied(&self) -> Capabilities {
+        self.denied
+    }
+
+    /// Whether a function from this registry may be called
+    pub fn permits(&self, function: &Function) -> bool {
+        !function.capabilities.intersects(self.denied)
+    }
+
+    /// Make function names case-insensitive, as they are in spreadsheets
+    #[cfg_attr(not(feature = "excel"), allow(dead_code))]
+    pub(crate) fn set_case_insensitive(&mut self) {
+        self.case_insensitive = true;
+        self.functions = self
+            .functions
+            .drain()
+            .map(|(name, function)| (name.to_ascii_lowercase(), function))
+            .collect();
+    }
+
+    /// Look up a function by name
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        if self.case_insensitive {
+            self.functions.get(&name.to_ascii_lowercase())
+        } else {
+            self.functions.get(name)
+        }
+    }
+
+    /// Whether a function with this name exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Remove a function, returning whether it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let name = if self.case_insensitive {
+            name.to_ascii_lowercase()
+        } else {
+            name.to_string()
+        };
+        self.functions.remove(&name).is_some()
+    }
+
+    /// The names of all functions, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// `coalesce(...)`: the first argument that isn't null, an undefined variable or an empty cell
+fn coalesce(evaluator: &Evaluator<'_>, args: &[Expr]) -> Result<Value, EvaluationError> {
+    for arg in args {
+        match evaluator.evaluate_value(arg) {
+            Ok(Value::Null)
+            | Err(EvaluationError::UndefinedVariable(_) | EvaluationError::UnresolvedCell(_)) => {}
+            result => return result,
+        }
+    }
+    Ok(Value::Null)
+}
+
+/// `if(condition, then, otherwise)`, which only evaluates the branch it picks
+fn choose(evaluator: &Evaluator<'_>, args: &[Expr]) -> Result<Value, EvaluationError> {
+    if evaluator.evaluate_value(&args[0])?.truth()? {
+        evaluator.evaluate_value(&args[1])
+    } else {
+        evaluator.evaluate_value(&args[2])
+    }
+}
+
+/// Return `value` if the argument was within the function's domain
+pub(crate) fn domain(function: &str, in_domain: bool, value: f64) -> Result<f64, EvaluationError> {
+    if in_domain {
+        Ok(value)
+    } else {
+        Err(EvaluationError::DomainError(function.to_string()))
+    }
+}
+
+/// Return `value` if `arg`, the function's one argument, was within its domain
+pub(crate) fn argument(
+    function: &str,
+    arg: f64,
+    in_domain: bool,
+    value: f64,
+) -> Result<f64, EvaluationError> {
+    if in_domain {
+        Ok(value)
+    } else {
+        Err(EvaluationError::OutOfDomain {
+            name: function.into(),
+            value: arg,
+        })
+    }
+}
+
+/// Whether a number is a whole number that isn't negative
+fn whole(value: f64) -> bool {
+    value >= 0.0 && value.fract() == 0.0
+}
+
+/// `n!` for a whole number, which is infinite from 171 on
+pub(crate) fn factorial(n: f64) -> f64 {
+    (2..=n.min(171.0) as u32).map(f64::from).product()
+}
+
+/// The number of ways to choose `k` things from `n`, for whole numbers, rounded to the nearest float
+pub(crate) fn binomial(n: f64, k: f64) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    let k = k.min(n - k);
+    let mut result: f64 = 1.0;
+    let mut i = 0.0;
+    // The result only grows, so the loop ends soon after it's infinite
+    while i < k && result.is_finite() {
+        result = result * (n - i) / (i + 1.0);
+        i += 1.0;
+    }
+    result.round()
+}
+
+/// Round to a number of decimal digits (negative digits round left of the point)
+///
+/// `rounding` does the actual rounding to an integer, e.g. `f64::round`.
+/// Digits past what an f64 can hold leave the value as it is, and digits
+/// far enough left of the point round it to zero.
+pub(crate) fn round(value: f64, digits: f64, rounding: fn(f64) -> f64) -> f64 {
+    let digits = digits.trunc();
+    if digits >= f64::from(f64::MAX_10_EXP) {
+        return value;
+    }
+    if digits < -f64::from(f64::MAX_10_EXP) {
+        return 0.0f64.copysign(value);
+    }
+    let factor = 10f64.powi(digits.abs() as i32);
+    let scaled = if digits >= 0.0 {
+        value * factor
+    } else {
+        value / factor
+    };
+    if !scaled.is_finite() {
+        return value;
+    }
+    // Undo binary noise from scaling (2.675 * 100 is 267.49999999999997):
+    // within a few units in the last place of a half, it's the half
+    let half = (scaled * 2.0).round() / 2.0;
+    let scaled = if (scaled - half).abs() <= scaled.abs() * 4.0 * f64::EPSILON {
+        half
+    } else {
+        scaled
+    };
+    let rounded = rounding(scaled);
+    let result = if digits >= 0.0 {
+        rounded / factor
+    } else {
+        rounded * factor
+    };
+    if result.is_finite() { result } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, parse_expression};
+
+    /// Evaluate an expression with the standard functions
+    fn eval(expression: &str) -> Result<f64, EvaluationError> {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        Evaluator::new(&Environment::new()).evaluate(&ast)
+    }
+
+    /// Test a selection of standard functions
+    #[test]
+    fn test_standard_functions() {
+        let cases = [
+            ("sqrt(16) + abs(-2)", 6.0),
+            ("max(1, 5, 3) - min(4, 2)", 3.0),
+            ("round(2.5) + round(-2.5)", 0.0),
+            ("round(2.675, 2)", 2.68),
+            ("round(1234.5, -2)", 1200.0),
+            ("pow(2, 10)", 1024.0),
+            ("floor(-1.5) * ceil(1.2)", -4.0),
+            ("sign(-3) + sign(0)", -1.0),
+            ("factorial(5) + factorial(0)", 121.0),
+            ("comb(52, 5) + comb(3, 4)", 2_598_960.0),
+        ];
+        for (expression, expected) in cases {
+            let result = eval(expression).unwrap();
+            assert!(
+                (result - expected).abs() < 1e-10,
+                "'{}': expected {}, got {}",
+                expression,
+                expected,
+                result
+            );
+        }
+    }
+
+    /// Test rounding to digits an f64 can't scale by
+    #[test]
+    fn test_round_extremes() {
+        assert_eq!(round(1e308, 2.0, f64::round), 1e308);
+        assert_eq!(round(1.5, 400.0, f64::round), 1.5);
+        assert_eq!(round(1.5, 308.0, f64::round), 1.5);
+        assert_eq!(round(1.5, -400.0, f64::round), 0.0);
+        assert!(round(-1.5, -400.0, f64::round).is_sign_negative());
+        assert_eq!(round(1.7e308, -308.0, f64::round), 1.7e308);
+        assert_eq!(round(1.005, 2.0, f64::round), 1.01);
+        assert_eq!(round(2.5, 0.0, f64::round_ties_even), 2.0);
+        assert!(round(f64::NAN, 2.0, f64::round).is_nan());
+    }
+
+    /// Test errors for unknown functions, bad arity and domain errors
+    #[test]
+    fn test_function_errors() {
+        assert!(matches!(
+            eval("nope(1)"),
+            Err(EvaluationError::UnknownFunction(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            eval("sqrt(1, 2)"),
+            Err(EvaluationError::WrongArgumentCount { found: 2, .. })
+        ));
+        assert!(matches!(
+            eval("max()"),
+            Err(EvaluationError::WrongArgumentCount { .. })
+        ));
+        assert!(eval("factorial(171)").unwrap().is_infinite());
+        assert!(matches!(
+            eval("comb(5, 2.5)"),
+            Err(EvaluationError::OutOfDomain { value: 2.5, .. })
+        ));
+        assert!(matches!(
+            eval("ln(0)"),
+            Err(EvaluationError::OutOfDomain { name, value: 0.0 }) if &*name == "ln"
+        ));
+        assert_eq!(
+            eval("1 + asin(2 * 0.75)").unwrap_err().to_string(),
+            "Argument 1.5 out of range for 'asin'"
+        );
+        assert_eq!(
+            eval("round(1, 2, 3)").unwrap_err().to_string(),
+            "Function 'round(x, digits=0)' takes 1 to 2 arguments, got 3"
+        );
+    }
+
+    /// Test that functions on numbers apply element by element to matrices
+    #[test]
+    fn test_broadcasting() {
+        let eval = |expression: &str| {
+            let (_, ast) = parse_expression(expression).unwrap();
+            Evaluator::new(&Environment::new()).evaluate_value(&ast)
+        };
+        let cases = [
+            ("sqrt([4, 9, 16])", "[2, 3, 4]"),
+            ("pow([1, 2, 3], 2)", "[1, 4, 9]"),
+            ("pow(2, [[1, 2], [3, 4]])", "[[2, 4], [8, 16]]"),
+            ("max([1, 5], [4, 2])", "[4, 5]"),
+            ("round([1.25, 2.5], 1)", "[1.3, 2.5]"),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                eval(expression).unwrap().to_string(),
+                expected,
+                "'{}'",
+                expression
+            );
+        }
+
+        assert!(matches!(
+            eval("max([1, 2], [1, 2, 3])"),
+            Err(EvaluationError::ArgumentSizes { name, left: (2, 1), right: (3, 1) }) if &*name == "max"
+        ));
+        assert!(matches!(
+            eval("ln([1, 0])"),
+            Err(EvaluationError::OutOfDomain { .. })
+        ));
+    }
+
+    /// Test that names are case-sensitive unless the registry says otherwise
+    #[test]
+    fn test_case_sensitivity() {
+        let mut registry = FunctionRegistry::standard();
+        assert!(!registry.contains("SQRT"));
+        registry.set_case_insensitive();
+        assert!(registry.contains("SQRT"));
+        registry.register("Twice", 1, |args| Ok(args[0] * 2.0));
+        assert!(registry.contains("TWICE"));
+        assert!(registry.remove("twice"));
+    }
+
+    /// Test moving named arguments into place, and the ways they can be wrong
+    #[test]
+    fn test_named_arguments() {
+        let mut registry = FunctionRegistry::standard();
+        registry.register(
+            "lerp",
+            3,
+            |args| Ok(args[0] + (args[1] - args[0]) * args[2]),
+        );
+        assert!(registry.set_parameters("lerp", &["from", "to", "t"]));
+        assert!(!registry.set_parameters("missing", &["x"]));
+        let names: Vec<&str> = registry
+            .get("lerp")
+            .unwrap()
+            .parameters()
+            .iter()
+            .map(Parameter::name)
+            .collect();
+        assert_eq!(names, ["from", "to", "t"]);
+        assert!(registry.get("sqrt").unwrap().parameters().is_empty());
+
+        let env = Environment::new();
+        let evaluator = Evaluator::new(&env).with_functions(&registry);
+        let eval = |input: &str| evaluator.evaluate(&parse_expression(input).unwrap().1);
+        assert_eq!(eval("lerp(t=0.25, to=20, from=10)").unwrap(), 12.5);
+        assert_eq!(
+            eval("lerp(10, 20, t=0.5) + round(2.675, digits=2)").unwrap(),
+            17.68
+        );
+
+        let error = |input: &str| eval(input).unwrap_err().to_string();
+        assert_eq!(
+            error("lerp(10, 20, s=1)"),
+            "Function 'lerp' has no parameter named 's'"
+        );
+        assert_eq!(
+            error("lerp(10, 20, from=1)"),
+            "Function 'lerp' was given 'from' more than once"
+        );
+        assert_eq!(
+            error("lerp(10, t=1)"),
+            "Function 'lerp' is missing the argument 'to'"
+        );
+        assert_eq!(
+            error("sqrt(x=4)"),
+            "Function 'sqrt' has no parameter named 'x'"
+        );
+    }
+
+    /// Test leaving out parameters with defaults, and the signatures in arity errors
+    #[test]
+    fn test_defaults() {
+        let mut registry = FunctionRegistry::standard();
+        registry.register("scale", 3, |args| Ok(args[0] * args[1] + args[2]));
+        registry.set_parameters(
+            "scale",
+            &[
+                Parameter::new("x"),
+                Parameter::new("factor").with_default(2.0),
+                Parameter::new("offset").with_default(0.5),
+            ],
+        );
+        let scale = registry.get("scale").unwrap();
+        assert_eq!(scale.arity(), (1..=3).into());
+        assert_eq!(scale.signature("scale"), "scale(x, factor=2, offset=0.5)");
+        assert_eq!(registry.get("round").unwrap().arity(), (1..=2).into());
+        assert_eq!(registry.get("sqrt").unwrap().signature("sqrt"), "sqrt");
+
+        let env = Environment::new();
+        let evaluator = Evaluator::new(&env).with_functions(&registry);
+        let eval = |input: &str| evaluator.evaluate(&parse_expression(input).unwrap().1);
+        assert_eq!(eval("scale(3)").unwrap(), 6.5);
+        assert_eq!(eval("scale(3, 4)").unwrap(), 12.5);
+        assert_eq!(eval("scale(3, offset=1)").unwrap(), 7.0);
+        assert_eq!(eval("round(2.5) + round(2.25, 1)").unwrap(), 5.3);
+
+        let error = |input: &str| eval(input).unwrap_err().to_string();
+        assert_eq!(
+            error("scale()"),
+            "Function 'scale(x, factor=2, offset=0.5)' takes 1 to 3 arguments, got 0"
+        );
+        assert_eq!(
+            error("round(1, 2, 3)"),
+            "Function 'round(x, digits=0)' takes 1 to 2 arguments, got 3"
+        );
+        assert_eq!(error("sqrt()"), "Function 'sqrt' takes 1 arguments, got 0");
+    }
+
+    /// Test denying and allowing functions by what they depend on
+    #[test]
+    fn test_capabilities() {
+        let mut registry = FunctionRegistry::standard();
+        let now = registry.get("now").unwrap();
+        assert_eq!(now.capabilities(), Capabilities::CLOCK);
+        assert!(
+            Capabilities::NONDETERMINISTIC.contains(Capabilities::CLOCK | Capabilities::RANDOM)
+        );
+        assert!(!Capabilities::CLOCK.contains(Capabilities::NONDETERMINISTIC));
+
+        registry.register("fetch", 1, |args| Ok(args[0]));
+        assert!(registry.set_capabilities("fetch", Capabilities::EXTERNAL));
+        assert!(!registry.set_capabilities("missing", Capabilities::RANDOM));
+        registry.deny(Capabilities::CLOCK | Capabilities::EXTERNAL);
+        registry.allow(Capabilities::CLOCK);
+        assert_eq!(registry.denied(), Capabilities::EXTERNAL);
+
+        let env = Environment::new();
+        let evaluator = Evaluator::new(&env).with_functions(&registry);
+        let eval = |input: &str| evaluator.evaluate_value(&parse_expression(input).unwrap().1);
+        assert!(eval("now()").is_ok());
+        assert!(matches!(
+            eval("1 + fetch(2)"),
+            Err(EvaluationError::NotPermitted(name)) if name == "fetch"
+        ));
+        assert!(matches!(eval("sqrt(4)"), Ok(Value::Number(2.0))));
+    }
+}
@@ -0,0 +1,61 @@
+//! Random expressions from the command line, for seeding fuzzers
+//!
+//! `ast gen --count 1000 --max-depth 8 --seed 42` prints a [`Corpus`], one
+//! expression per line. With `--values`, each line also has the
+//! expression's value after a tab, or its error, evaluated with the
+//! corpus's variables.
+
+use std::io::{self, Write};
+
+use ast::{Corpus, NumberFormat, evaluate_with, parse_expression};
+
+/// The command line help for `ast gen`
+pub const USAGE: &str = "Usage: ast gen [--count <n>] [--max-depth <n>] [--seed <n>] [--values]
+
+Print random valid expressions, one per line: 100 of them, nested at most
+5 deep, from seed 1 by default. The same seed gives the same expressions.
+With --values, each expression is followed by a tab and its value (or
+'error: ' and the message), with x = 1.5, y = -2 and z = 0.";
+
+/// Parse the arguments after `gen` and print the expressions
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (mut count, mut max_depth, mut seed, mut values) = (100usize, 5usize, 1u64, false);
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| {
+            let text = args
+                .next()
+                .ok_or_else(|| format!("{}: expected a value", name))?;
+            text.parse::<u64>()
+                .map_err(|_| format!("{} {}: expected a whole number", name, text))
+        };
+        match arg.as_str() {
+            "--count" => count = number("--count")? as usize,
+            "--max-depth" => max_depth = number("--max-depth")? as usize,
+            "--seed" => seed = number("--seed")?,
+            "--values" => values = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("{}: unknown option", arg)),
+        }
+    }
+
+    let env = Corpus::environment();
+    let format = NumberFormat::default();
+    let mut out = io::stdout().lock();
+    for text in Corpus::new(seed, max_depth).take(count) {
+        let line = if values {
+            let (_, ast) = parse_expression(&text).expect("corpus expressions parse");
+            match evaluate_with(&ast, &env) {
+                Ok(value) => format!("{}\t{}", text, format.format(value)),
+                Err(error) => format!("{}\terror: {}", text, error),
+            }
+        } else {
+            text
+        };
+        // Stop quietly when the reader goes away, as with `ast gen | head`
+        if writeln!(out, "{}", line).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,323 @@
+//! The grammar the parser accepts, written out as EBNF
+//!
+//! Documentation for a customized dialect goes stale when it's written by
+//! hand. [`grammar_ebnf`] instead describes what the parser does with a given
+//! [`ParserConfig`], in ISO 14977 EBNF: the grammar version and the
+//! dialect's operators, whether cell references and SI suffixes are
+//! accepted, and the functions of a [`FunctionRegistry`].
+
+use std::fmt::Write;
+
+use crate::{Comparison, Dialect, FunctionRegistry, GrammarVersion, ParserConfig, si};
+
+/// The grammar of expressions parsed with `config`, in EBNF, listing the functions in `functions`
+///
+/// Rules are listed from the lowest precedence to the highest. Spaces, tabs
+/// and line breaks may separate any two symbols, which the grammar leaves
+/// out. The parser accepts a call to any name; the `function` rule lists the
+/// names `functions` can evaluate.
+///
+/// # Example
+/// ```
+/// use ast::{grammar_ebnf, Dialect, FunctionRegistry, ParserConfig};
+///
+/// let mut functions = FunctionRegistry::new();
+/// functions.register("lerp", 3, |args| Ok(args[0] + (args[1] - args[0]) * args[2]));
+/// let config = ParserConfig { dialect: Dialect::Excel, ..ParserConfig::default() };
+///
+/// let grammar = grammar_ebnf(&config, &functions);
+/// assert!(grammar.contains("term = power, { ( \"*\" | \"/\" ), power } ;"));
+/// assert!(grammar.contains("power = factor, { \"^\", factor } ;"));
+/// assert!(grammar.contains("\"<>\""));
+/// assert!(grammar.contains("function = \"lerp\" ;"));
+/// ```
+pub fn grammar_ebnf(config: &ParserConfig, functions: &FunctionRegistry) -> String {
+    let calls = config.grammar >= GrammarVersion::V2;
+    let logic = config.grammar >= GrammarVersion::V3;
+    let python = config.dialect == Dialect::Python;
+    let excel = config.dialect == Dialect::Excel && calls;
+    let mut rules: Vec<(&str, String)> = Vec::new();
+    let mut rule = |name, body: &str| rules.push((name, body.to_string()));
+
+    if logic {
+        rule(
+            "expression",
+            "\"let\", name, \"=\", expression, \"in\", expression | pipeline",
+        );
+        rule("pipeline", "or, { \"|>\", name, [ arguments ] }");
+        rule("or", "and, { \"||\", and }");
+        rule("and", "comparison, { \"&&\", comparison }");
+        rule("comparison", "sum, { comparison_operator, sum }");
+        let comparisons = match config.dialect {
+            Dialect::Excel => &["=", "<>", "<", "<=", ">", ">="][..],
+            _ => Comparison::SYMBOLS,
+        };
+        rule("comparison_operator", &alternatives(comparisons));
+    } else {
+        rule("expression", "sum");
+    }
+    rule("sum", "term, { ( \"+\" | \"-\" ), term }");
+    let operand = if excel { "power" } else { "factor" };
+    let products = if python && calls {
+        "\"*\" | \"/\" | \"//\""
+    } else {
+        "\"*\" | \"/\""
+    };
+    rule(
+        "term",
+        &format!("{0}, {{ ( {1} ), {0} }}", operand, products),
+    );
+    if excel {
+        rule("power", "factor, { \"^\", factor }");
+    }
+    let power = if python && calls {
+        ", [ \"**\", factor ]"
+    } else {
+        ""
+    };
+    if logic {
+        let postfix = if python { "index" } else { "index | method" };
+        rule(
+            "factor",
+            &format!(
+                "\"-\", factor | \"!\", factor | primary, {{ {} }}{}",
+                postfix, power
+            ),
+        );
+    } else {
+        rule("factor", &format!("\"-\", factor | primary{}", power));
+    }
+
+    let mut primaries = if config.uncertainties {
+        vec!["measurement", "number"]
+    } else {
+        vec!["number"]
+    };
+    if config.cell_references {
+        primaries.extend(["cell_range", "cell"]);
+    }
+    if logic {
+        primaries.push("piecewise");
+    }
+    if calls {
+        primaries.push("call");
+    }
+    primaries.extend(["name", "\"(\", expression, \")\""]);
+    if logic {
+        primaries.push("list");
+    }
+    rule("primary", &primaries.join(" | "));
+    if logic {
+        rule("index", "\"[\", expression, [ \",\", expression ], \"]\"");
+        if !python {
+            rule("method", "\".\", name, arguments");
+        }
+        rule(
+            "list",
+            "\"[\", [ expression, { \",\", expression } ], \"]\"",
+        );
+    }
+    if calls {
+        rule("call", "name, arguments");
+        if logic && config.dialect != Dialect::Excel {
+            rule(
+                "arguments",
+                "\"(\", [ expression, { \",\", expression }, { \",\", named } | named, { \",\", named } ], \")\"",
+            );
+            rule("named", "name, \"=\", expression");
+        } else {
+            rule(
+                "arguments",
+                "\"(\", [ expression, { \",\", expression } ], \")\"",
+            );
+        }
+    }
+    if logic {
+        rule(
+            "piecewise",
+            "\"piecewise\", \"(\", case, { \",\", case }, [ \",\", expression ], \")\"",
+        );
+        rule("case", "\"(\", expression, \",\", expression, \")\"");
+    }
+    if python {
+        rule("name", "[ \"math\", \".\" ], identifier");
+        rule("identifier", "letter, { letter | digit }");
+    } else {
+        rule("name", "letter, { letter | digit }");
+    }
+    if config.cell_references {
+        rule("cell_range", "cell, \":\", cell");
+        rule("cell", "letter, [ letter, [ letter ] ], digits");
+    }
+
+    let suffix = if config.si_suffixes {
+        ", [ si_prefix ]"
+    } else {
+        ""
+    };
+    let words = if config.nonfinite_literals {
+        " | \"nan\" | \"inf\" | \"infinity\""
+    } else {
+        ""
+    };
+    rule(
+        "number",
+        &format!(
+            "( digits, [ \".\", [ digits ] ] | \".\", digits ), [ exponent ]{}{}",
+            suffix, words
+        ),
+    );
+    if config.uncertainties {
+        rule(
+            "measurement",
+            "number, ( \"±\" | \"+/-\" ), unsigned_number",
+        );
+        rule(
+            "unsigned_number",
+            &format!(
+                "( digits, [ \".\", [ digits ] ] | \".\", digits ), [ exponent ]{}",
+                suffix
+            ),
+        );
+    }
+    rule("exponent", "( \"e\" | \"E\" ), [ \"+\" | \"-\" ], digits");
+    if config.si_suffixes {
+        let mut prefixes: Vec<&str> = si::PREFIXES
+            .iter()
+            .copied()
+            .filter(|prefix| !prefix.is_empty() && *prefix != "E")
+            .collect();
+        prefixes.push("u");
+        rule("si_prefix", &alternatives(&prefixes));
+    }
+    rule("digits", "digit, { digit }");
+    rule(
+        "digit",
+        "\"0\" | \"1\" | \"2\" | \"3\" | \"4\" | \"5\" | \"6\" | \"7\" | \"8\" | \"9\"",
+    );
+    rule("letter", "? an ASCII letter ? | \"_\"");
+
+    let names = functions.names();
+    if calls && !names.is_empty() {
+        rule("function", &alternatives(&names));
+    }
+
+    let mut grammar = format!(
+        "(* Expressions in the {:?} dialect, grammar {:?}; whitespace may separate any two symbols *)\n",
+        config.dialect, config.grammar
+    );
+    for (name, body) in rules {
+        writeln!(grammar, "{} = {} ;", name, body).expect("writing to a string can't fail");
+    }
+    grammar
+}
+
+/// Terminal symbols separated by `|`, each quoted
+fn alternatives(symbols: &[&str]) -> String {
+    let quoted: Vec<String> = symbols
+        .iter()
+        .map(|symbol| {
+            if symbol.contains('"') {
+                format!("'{}'", symbol)
+            } else {
+                format!("\"{}\"", symbol)
+            }
+        })
+        .collect();
+    quoted.join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    /// The rules a grammar defines, and the rule names its right-hand sides use
+    fn defined_and_used(grammar: &str) -> (BTreeSet<String>, BTreeSet<String>) {
+        let (mut defined, mut used) = (BTreeSet::new(), BTreeSet::new());
+        for line in grammar.lines().filter(|line| !line.starts_with("(*")) {
+            let (name, body) = line.split_once(" = ").unwrap();
+            defined.insert(name.to_string());
+            // Drop quoted terminals and special sequences, keeping rule names
+            let mut outside = String::new();
+            let mut quote = None;
+            for c in body.chars() {
+                match quote {
+                    Some(q) if c == q => quote = None,
+                    Some(_) => {}
+                    None if matches!(c, '"' | '\'' | '?') => quote = Some(c),
+                    None => outside.push(c),
+                }
+            }
+            used.extend(
+                outside
+                    .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .filter(|word| !word.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        (defined, used)
+    }
+
+    /// Test that every configuration's grammar defines each rule it uses
+    #[test]
+    fn test_complete() {
+        let functions = FunctionRegistry::standard();
+        for dialect in [Dialect::Standard, Dialect::Python, Dialect::Excel] {
+            for (cell_references, si_suffixes) in [(false, false), (true, true)] {
+                for grammar in [GrammarVersion::V1, GrammarVersion::V2, GrammarVersion::V3] {
+                    let config = ParserConfig {
+                        cell_references,
+                        dialect,
+                        si_suffixes,
+                        nonfinite_literals: !si_suffixes,
+                        uncertainties: cell_references,
+                        grammar,
+                        ..ParserConfig::default()
+                    };
+                    let text = grammar_ebnf(&config, &functions);
+                    let (defined, used) = defined_and_used(&text);
+                    assert!(used.is_subset(&defined), "{}", text);
+                    assert_eq!(text.contains("\"sqrt\""), grammar >= GrammarVersion::V2);
+                    assert_eq!(text.contains("\"&&\""), grammar >= GrammarVersion::V3);
+                    assert_eq!(text.contains("\"|>\""), grammar >= GrammarVersion::V3);
+                    assert_eq!(text.contains("cell_range"), cell_references);
+                    assert_eq!(text.contains("measurement"), cell_references);
+                    assert_eq!(text.contains("si_prefix ="), si_suffixes);
+                    assert_eq!(text.contains("\"infinity\""), !si_suffixes);
+                }
+            }
+        }
+    }
+
+    /// Test the parts that differ between dialects
+    #[test]
+    fn test_dialects() {
+        let none = FunctionRegistry::new();
+        let grammar = |dialect| {
+            let config = ParserConfig {
+                dialect,
+                ..ParserConfig::default()
+            };
+            grammar_ebnf(&config, &none)
+        };
+        let standard = grammar(Dialect::Standard);
+        assert!(
+            standard.contains(
+                "comparison_operator = \"==\" | \"!=\" | \"<=\" | \">=\" | \"<\" | \">\" ;"
+            )
+        );
+        assert!(standard.contains("term = factor, { ( \"*\" | \"/\" ), factor } ;"));
+        assert!(!standard.contains("power"));
+        assert!(!standard.contains("function ="));
+        assert!(standard.contains("primary, { index | method }"));
+        assert!(standard.contains("named = name, \"=\", expression ;"));
+
+        let python = grammar(Dialect::Python);
+        assert!(python.contains("\"//\""));
+        assert!(python.contains("primary, { index }, [ \"**\", factor ] ;"));
+        assert!(python.contains("name = [ \"math\", \".\" ], identifier ;"));
+        assert!(!grammar(Dialect::Excel).contains("named"));
+    }
+}
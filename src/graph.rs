@@ -0,0 +1,228 @@
+//! Trees as graphs of shared subtrees, for visualizing
+//!
+//! A formula often repeats itself: `a * b` appears twice in
+//! `(a * b + 1) / (a * b)`. [`Expr::to_graph`] merges identical subtrees into
+//! one node, the way common subexpression elimination would, and numbers the
+//! nodes. The resulting [`ExprGraph`] can be written as Graphviz DOT or as
+//! JSON, to see how much of a large formula is shared and to debug what
+//! optimizations make of it.
+
+use std::{collections::HashMap, fmt::Write};
+
+use serde::Serialize;
+
+use crate::Expr;
+
+/// A tree with identical subtrees merged, see [`Expr::to_graph`]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ExprGraph {
+    /// The distinct subtrees, children before parents, each at the index of its id
+    pub nodes: Vec<GraphNode>,
+    /// The id of the node for the whole tree
+    pub root: usize,
+}
+
+/// One distinct subtree in an [`ExprGraph`]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct GraphNode {
+    /// The node's id, its index in [`ExprGraph::nodes`]
+    pub id: usize,
+    /// The kind of node, such as `Add` or `Var`
+    pub kind: &'static str,
+    /// What the node holds or does, such as `+`, a name or a number
+    pub label: String,
+    /// The ids of its children, in order
+    pub children: Vec<usize>,
+    /// How many times the subtree appears in the tree; more than 1 means it's shared
+    pub uses: usize,
+}
+
+impl Expr {
+    /// This tree as a graph, with each distinct subtree appearing once
+    ///
+    /// Subtrees are merged when they're written the same way, so `x + y` and
+    /// `y + x` stay apart. Works without recursion, so very deep trees are fine.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("(a * b + 1) / (a * b)").unwrap();
+    /// let graph = ast.to_graph();
+    /// assert_eq!(graph.nodes.len(), 6); // a, b, a * b, 1, + and /
+    /// let product = graph.nodes.iter().find(|node| node.label == "*").unwrap();
+    /// assert_eq!(product.uses, 2);
+    /// assert_eq!(graph.nodes[graph.root].children, [4, product.id]);
+    /// ```
+    pub fn to_graph(&self) -> ExprGraph {
+        let mut nodes: Vec<GraphNode> = Vec::new();
+        let mut ids: HashMap<(&'static str, String, Vec<usize>), usize> = HashMap::new();
+        // Ids of the subtrees finished but not yet part of their parent
+        let mut done = Vec::new();
+        let mut stack = vec![(self, false)];
+        while let Some((expr, children_done)) = stack.pop() {
+            let children = expr.children();
+            if !children_done && !children.is_empty() {
+                stack.push((expr, true));
+                stack.extend(children.into_iter().rev().map(|child| (child, false)));
+                continue;
+            }
+            let children = done.split_off(done.len() - children.len());
+            let (kind, label) = describe(expr);
+            let next = nodes.len();
+            let id = *ids
+                .entry((kind, label.clone(), children.clone()))
+                .or_insert(next);
+            if id == next {
+                nodes.push(GraphNode {
+                    id,
+                    kind,
+                    label,
+                    children,
+                    uses: 0,
+                });
+            }
+            nodes[id].uses += 1;
+            done.push(id);
+        }
+        let root = done.pop().expect("the tree has a root");
+        ExprGraph { nodes, root }
+    }
+}
+
+impl ExprGraph {
+    /// The graph in Graphviz DOT, with shared nodes drawn in bold
+    ///
+    /// Node names are their ids, so `n3` is [`ExprGraph::nodes`]`[3]`, and
+    /// children are laid out left to right in order.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("x * x").unwrap();
+    /// let dot = ast.to_graph().to_dot();
+    /// assert!(dot.starts_with("digraph {"));
+    /// assert!(dot.contains("n0 [label=\"x\", style=bold];"));
+    /// assert!(dot.contains("n1 -> n0;"));
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n    ordering=out;\n    node [shape=box];\n");
+        for node in &self.nodes {
+            let style = if node.uses > 1 { ", style=bold" } else { "" };
+            let label = node.label.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(dot, "    n{} [label=\"{}\"{}];", node.id, label, style)
+                .expect("writing to a string can't fail");
+        }
+        for node in &self.nodes {
+            for child in &node.children {
+                writeln!(dot, "    n{} -> n{};", node.id, child)
+                    .expect("writing to a string can't fail");
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The graph as JSON, with a `nodes` array and the `root` id
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("graphs are always valid JSON")
+    }
+}
+
+/// The kind of a node and what it holds or does, without its children
+pub(crate) fn describe(expr: &Expr) -> (&'static str, String) {
+    match expr {
+        Expr::Float(value) => ("Float", value.to_string()),
+        Expr::Var(name) => ("Var", name.clone()),
+        Expr::CellRef(cell) => ("CellRef", cell.to_string()),
+        Expr::CellRange(from, to) => ("CellRange", format!("{}:{}", from, to)),
+        Expr::Add(..) => ("Add", "+".to_string()),
+        Expr::Sub(..) => ("Sub", "-".to_string()),
+        Expr::Mul(..) => ("Mul", "*".to_string()),
+        Expr::Div(..) => ("Div", "/".to_string()),
+        Expr::Neg(_) => ("Neg", "-".to_string()),
+        Expr::Compare(comparison, ..) => ("Compare", comparison.symbol().to_string()),
+        Expr::And(..) => ("And", "&&".to_string()),
+        Expr::Or(..) => ("Or", "||".to_string()),
+        Expr::Not(_) => ("Not", "!".to_string()),
+        Expr::Call(name, _) => ("Call", format!("{}()", name)),
+        Expr::Named(name, _) => ("Named", format!("{}=", name)),
+        Expr::Let(name, ..) => ("Let", format!("let {}", name)),
+        Expr::Piecewise(cases, default) => (
+            "Piecewise",
+            format!(
+                "piecewise, {} cases{}",
+                cases.len(),
+                if default.is_some() {
+                    " and a default"
+                } else {
+                    ""
+                }
+            ),
+        ),
+        Expr::List(elements) => ("List", format!("[{}]", elements.len())),
+        Expr::Index(..) => ("Index", "[]".to_string()),
+        Expr::Error(span) => ("Error", format!("error at {}..{}", span.start, span.end)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// The graph of an expression
+    fn graph(expression: &str) -> ExprGraph {
+        parse_expression(expression).unwrap().1.to_graph()
+    }
+
+    /// Test which subtrees are merged, and how often each is used
+    #[test]
+    fn test_sharing() {
+        let shared = graph("f(x + 1, x + 1) - (x + 1)");
+        let summary: Vec<_> = shared
+            .nodes
+            .iter()
+            .map(|node| (node.label.as_str(), node.children.clone(), node.uses))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("x", vec![], 3),
+                ("1", vec![], 3),
+                ("+", vec![0, 1], 3),
+                ("f()", vec![2, 2], 1),
+                ("-", vec![3, 2], 1),
+            ]
+        );
+        assert_eq!(shared.root, 4);
+
+        // Same operands in another order, or under another operator, aren't the same subtree
+        assert_eq!(graph("(x - y) * (y - x) / (x + y)").nodes.len(), 7);
+        assert_eq!(graph("7").nodes.len(), 1);
+
+        let chain = graph(&vec!["1"; 50_000].join(" + "));
+        assert_eq!(chain.nodes.len(), 50_000);
+        assert_eq!(chain.nodes[0].uses, 50_000);
+    }
+
+    /// Test the DOT and JSON forms
+    #[test]
+    fn test_export() {
+        let shared = graph("let t = a * 2 in t + t");
+        assert!(shared.to_dot().contains("[label=\"let t\"]"));
+        assert!(
+            graph("max(a, a)")
+                .to_dot()
+                .contains("n1 -> n0;\n    n1 -> n0;\n")
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&graph("a / a").to_json()).unwrap();
+        assert_eq!(json["root"], 1);
+        assert_eq!(
+            json["nodes"][0],
+            serde_json::json!({"id": 0, "kind": "Var", "label": "a", "children": [], "uses": 2})
+        );
+    }
+}
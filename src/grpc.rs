@@ -0,0 +1,377 @@
+//! A tonic server for the `Calculator` gRPC service
+//!
+//! [`CalculatorService`] answers the `Eval` and `Parse` calls of the
+//! `ast.v1.Calculator` service in [`PROTO_SCHEMA`](crate::PROTO_SCHEMA) with
+//! an [`Engine`], and can be added to a `tonic::transport::Server` like a
+//! service generated by tonic-build. The messages are written out here
+//! rather than generated, so building needs no protoc; trees travel as the
+//! bytes of their `Expr` message and are read by [`Expr::from_protobuf`].
+//!
+//! Errors in the formula come back in the response's `Error`, as from
+//! `ast serve`. Requests that can't be answered at all, with no formula or
+//! a tree that can't be decoded, fail with a [`RequestError`] as an
+//! `INVALID_ARGUMENT` status.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tonic::{
+    Status,
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{Body, BoxFuture, Service, StdError, http},
+    server::{Grpc, NamedService, UnaryService},
+};
+
+use thiserror::Error;
+
+use crate::{Engine, Expr, ParseEvalError, ProtobufError};
+
+/// The `EvalRequest` message: a formula and its variables
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EvalRequest {
+    /// The formula, as text to parse or as a tree
+    #[prost(oneof = "EvalFormula", tags = "1, 2")]
+    pub formula: Option<EvalFormula>,
+    /// Values for the formula's variables, hiding the engine's constants
+    #[prost(map = "string, double", tag = "3")]
+    pub variables: HashMap<String, f64>,
+}
+
+/// The `formula` of an [`EvalRequest`]
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum EvalFormula {
+    /// Text to parse
+    #[prost(string, tag = "1")]
+    Text(String),
+    /// An `Expr` message, as from [`Expr::to_protobuf`]
+    #[prost(bytes, tag = "2")]
+    Tree(Vec<u8>),
+}
+
+/// The `EvalResponse` message: a result, or why there isn't one
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EvalResponse {
+    #[prost(oneof = "EvalOutcome", tags = "1, 2")]
+    pub outcome: Option<EvalOutcome>,
+}
+
+/// The `outcome` of an [`EvalResponse`]
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum EvalOutcome {
+    #[prost(double, tag = "1")]
+    Result(f64),
+    #[prost(message, tag = "2")]
+    Error(CalculatorError),
+}
+
+/// The `ParseRequest` message: text to parse
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ParseRequest {
+    #[prost(string, tag = "1")]
+    pub text: String,
+}
+
+/// The `ParseResponse` message: a tree, or why there isn't one
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ParseResponse {
+    #[prost(oneof = "ParseOutcome", tags = "1, 2")]
+    pub outcome: Option<ParseOutcome>,
+}
+
+/// The `outcome` of a [`ParseResponse`]
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum ParseOutcome {
+    /// An `Expr` message, for [`Expr::from_protobuf`]
+    #[prost(bytes, tag = "1")]
+    Tree(Vec<u8>),
+    #[prost(message, tag = "2")]
+    Error(CalculatorError),
+}
+
+/// The `Error` message: why a formula couldn't be parsed or evaluated
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CalculatorError {
+    /// `syntax`, `too_deep`, `literal_too_long`, `name_too_long` or `evaluation`
+    #[prost(string, tag = "1")]
+    pub kind: String,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    /// For evaluation errors, the stable code such as `E0001`
+    #[prost(string, tag = "3")]
+    pub code: String,
+    /// Where in the text (in bytes) the problem is, for syntax errors
+    #[prost(uint64, optional, tag = "4")]
+    pub position: Option<u64>,
+}
+
+/// Why a request can't be answered at all, as opposed to its formula having an error
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum RequestError {
+    #[error("EvalRequest has no formula")]
+    NoFormula,
+
+    #[error(transparent)]
+    Tree(#[from] ProtobufError),
+}
+
+impl From<RequestError> for Status {
+    fn from(e: RequestError) -> Self {
+        Status::invalid_argument(e.to_string())
+    }
+}
+
+impl From<&ParseEvalError> for CalculatorError {
+    fn from(e: &ParseEvalError) -> Self {
+        let at = |position: &usize| Some(*position as u64);
+        let (kind, code, position) = match e {
+            ParseEvalError::Syntax { position } => ("syntax", "", at(position)),
+            ParseEvalError::TooDeep { .. } => ("too_deep", "", None),
+            ParseEvalError::LiteralTooLong { position, .. } => {
+                ("literal_too_long", "", at(position))
+            }
+            ParseEvalError::NameTooLong { position, .. } => ("name_too_long", "", at(position)),
+            ParseEvalError::Evaluation(error) => ("evaluation", error.code(), None),
+        };
+        CalculatorError {
+            kind: kind.to_string(),
+            message: e.to_string(),
+            code: code.to_string(),
+            position,
+        }
+    }
+}
+
+/// The `ast.v1.Calculator` service, answered by an engine
+///
+/// # Example
+/// ```
+/// use ast::{CalculatorService, Engine, EvalFormula, EvalOutcome, EvalRequest};
+///
+/// let service = CalculatorService::new(Engine::new());
+/// let request = EvalRequest {
+///     formula: Some(EvalFormula::Text("a * 2".to_string())),
+///     variables: [("a".to_string(), 21.0)].into_iter().collect(),
+/// };
+/// let response = service.eval(request).unwrap();
+/// assert_eq!(response.outcome, Some(EvalOutcome::Result(42.0)));
+/// ```
+///
+/// To serve it, add it to a server:
+/// ```no_run
+/// # async fn serve() -> Result<(), tonic::transport::Error> {
+/// let service = ast::CalculatorService::new(ast::Engine::new());
+/// tonic::transport::Server::builder()
+///     .add_service(service)
+///     .serve("127.0.0.1:50051".parse().unwrap())
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CalculatorService {
+    engine: Arc<Engine>,
+}
+
+impl CalculatorService {
+    /// A service that parses and evaluates with `engine`
+    pub fn new(engine: Engine) -> Self {
+        CalculatorService {
+            engine: Arc::new(engine),
+        }
+    }
+
+    /// Answer an `Eval` call
+    pub fn eval(&self, request: EvalRequest) -> Result<EvalResponse, RequestError> {
+        let formula = match request.formula {
+            Some(EvalFormula::Text(text)) => self.engine.compile(&text),
+            Some(EvalFormula::Tree(bytes)) => self.engine.adopt(Expr::from_protobuf(&bytes)?),
+            None => return Err(RequestError::NoFormula),
+        };
+        let mut vars = self.engine.environment();
+        for (name, value) in request.variables {
+            vars.set(name, value);
+        }
+        let outcome = match formula.and_then(|formula| self.engine.run(&formula, &vars)) {
+            Ok(result) => EvalOutcome::Result(result),
+            Err(error) => EvalOutcome::Error(CalculatorError::from(&error)),
+        };
+        Ok(EvalResponse {
+            outcome: Some(outcome),
+        })
+    }
+
+    /// Answer a `Parse` call
+    pub fn parse(&self, request: ParseRequest) -> ParseResponse {
+        let outcome = match self.engine.compile(&request.text) {
+            Ok(formula) => ParseOutcome::Tree(formula.expr().to_protobuf()),
+            Err(error) => ParseOutcome::Error(CalculatorError::from(&error)),
+        };
+        ParseResponse {
+            outcome: Some(outcome),
+        }
+    }
+}
+
+impl NamedService for CalculatorService {
+    const NAME: &'static str = "ast.v1.Calculator";
+}
+
+/// One of the service's calls, as tonic's [`Grpc`] runs them
+struct Call<F>(Arc<F>);
+
+impl<Request, Response, F> UnaryService<Request> for Call<F>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+    F: Fn(Request) -> Result<Response, RequestError> + Send + Sync + 'static,
+{
+    type Response = Response;
+    type Future = BoxFuture<tonic::Response<Response>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Request>) -> Self::Future {
+        let answer = Arc::clone(&self.0);
+        Box::pin(async move { Ok(tonic::Response::new(answer(request.into_inner())?)) })
+    }
+}
+
+impl<B> Service<http::Request<B>> for CalculatorService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let service = self.clone();
+        match request.uri().path() {
+            "/ast.v1.Calculator/Eval" => Box::pin(async move {
+                let call = Call(Arc::new(move |request| service.eval(request)));
+                Ok(Grpc::new(ProstCodec::default()).unary(call, request).await)
+            }),
+            "/ast.v1.Calculator/Parse" => Box::pin(async move {
+                let call = Call(Arc::new(move |request| Ok(service.parse(request))));
+                Ok(Grpc::new(ProstCodec::default()).unary(call, request).await)
+            }),
+            path => {
+                let status = Status::unimplemented(format!("no method {}", path));
+                Box::pin(async move { Ok(status.into_http()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+    use tokio::{net::TcpListener, runtime::Runtime};
+    use tonic::transport::{Channel, Server, server::TcpIncoming};
+
+    /// Make a call over a real connection to a server on a free port
+    async fn call<Request, Response>(method: &'static str, request: Request) -> Response
+    where
+        Request: prost::Message + Send + Sync + 'static,
+        Response: prost::Message + Default + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = CalculatorService::new(Engine::new());
+        let server = Server::builder().add_service(service);
+        tokio::spawn(server.serve_with_incoming(incoming));
+
+        let channel = Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = tonic::client::Grpc::new(channel);
+        client.ready().await.unwrap();
+        let path = http::uri::PathAndQuery::from_static(method);
+        let response = client
+            .unary(tonic::Request::new(request), path, ProstCodec::default())
+            .await
+            .unwrap();
+        response.into_inner()
+    }
+
+    /// Test evaluating text and trees, and the errors that come back
+    #[test]
+    fn test_eval() {
+        let service = CalculatorService::new(Engine::new());
+        let eval = |formula| {
+            let request = EvalRequest {
+                formula: Some(formula),
+                variables: [("x".to_string(), 3.0)].into_iter().collect(),
+            };
+            service.eval(request).unwrap().outcome.unwrap()
+        };
+        let tree = parse_expression("x * x + 1").unwrap().1.to_protobuf();
+        assert_eq!(eval(EvalFormula::Tree(tree)), EvalOutcome::Result(10.0));
+
+        let EvalOutcome::Error(error) = eval(EvalFormula::Text("1 +".to_string())) else {
+            panic!("evaluated a syntax error");
+        };
+        assert_eq!((error.kind.as_str(), error.position), ("syntax", Some(3)));
+        let EvalOutcome::Error(error) = eval(EvalFormula::Text("y".to_string())) else {
+            panic!("evaluated an unknown variable");
+        };
+        assert_eq!(
+            (error.kind.as_str(), error.code.as_str()),
+            ("evaluation", "E0002")
+        );
+
+        // Trees are held to the same nesting limit as text
+        let deep = (0..100).fold(Expr::Var("x".to_string()), |inner, _| {
+            Expr::Neg(Box::new(inner))
+        });
+        let EvalOutcome::Error(error) = eval(EvalFormula::Tree(deep.to_protobuf())) else {
+            panic!("evaluated a tree nested too deeply");
+        };
+        assert_eq!(error.kind, "too_deep");
+
+        let bad = service.eval(EvalRequest {
+            formula: Some(EvalFormula::Tree(vec![0x2a, 99])),
+            variables: HashMap::new(),
+        });
+        assert_eq!(bad, Err(RequestError::Tree(ProtobufError::Truncated)));
+        let status = Status::from(RequestError::NoFormula);
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    /// Test both calls through a server and a client
+    #[test]
+    fn test_server() {
+        Runtime::new().unwrap().block_on(async {
+            let request = EvalRequest {
+                formula: Some(EvalFormula::Text("a * b + c".to_string())),
+                variables: [("a", 2.0), ("b", 3.0), ("c", 4.0)]
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+            };
+            let response: EvalResponse = call("/ast.v1.Calculator/Eval", request).await;
+            assert_eq!(response.outcome, Some(EvalOutcome::Result(10.0)));
+
+            let request = ParseRequest {
+                text: "f(x) + 1".to_string(),
+            };
+            let response: ParseResponse = call("/ast.v1.Calculator/Parse", request).await;
+            let Some(ParseOutcome::Tree(bytes)) = response.outcome else {
+                panic!("no tree for f(x) + 1");
+            };
+            let (_, expected) = parse_expression("f(x) + 1").unwrap();
+            assert_eq!(Expr::from_protobuf(&bytes), Ok(expected));
+        });
+    }
+}
@@ -0,0 +1,535 @@
+//! Exact arithmetic on whole numbers, for IDs and amounts in cents
+//!
+//! Every number is an `f64` to the main evaluator, which represents whole
+//! numbers exactly only up to 2^53: `9007199254740993` reads as
+//! `9007199254740992`, and sums of large amounts in cents quietly lose
+//! their last digits. [`evaluate_checked`] keeps whole numbers as 64-bit
+//! integers instead. Adding, subtracting, multiplying and negating them is
+//! exact, and a result beyond the range of an `i64` is an
+//! [`EvaluationError::Overflow`] rather than a rounded number. A value only
+//! becomes a float when it has to: a division that leaves a remainder, a
+//! function such as `sqrt`, or a fractional input.
+//!
+//! With the `bigint` feature, `evaluate_big` does the same with integers
+//! of any size.
+
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    EvaluationError, Expr, FunctionRegistry, NumberFormat, Value,
+    functions::{binomial, factorial},
+};
+
+/// The largest whole number every smaller one of which an `f64` holds exactly, 2^53
+const EXACT_FLOAT_LIMIT: f64 = 9_007_199_254_740_992.0;
+
+/// A number from [`evaluate_checked`], kept exact while it's a whole number
+///
+/// With the `bigint` feature, `Number<BigInt>` is the number type of `evaluate_big`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Number<I = i64> {
+    /// A whole number, computed exactly
+    Integer(I),
+    /// Any other number
+    Float(f64),
+}
+
+impl Number {
+    /// The number as a float, which may round a large integer
+    pub fn to_f64(self) -> f64 {
+        float(&self)
+    }
+
+    /// The number as an integer, if it's one
+    pub fn as_integer(self) -> Option<i64> {
+        match self {
+            Number::Integer(value) => Some(value),
+            Number::Float(_) => None,
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(value: i64) -> Self {
+        Number::Integer(value)
+    }
+}
+
+impl<I> From<f64> for Number<I> {
+    fn from(value: f64) -> Self {
+        Number::Float(value)
+    }
+}
+
+impl<I: fmt::Display> fmt::Display for Number<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Integer(value) => write!(f, "{}", value),
+            Number::Float(value) => f.write_str(&NumberFormat::default().format(*value)),
+        }
+    }
+}
+
+/// Whole numbers that can be computed with exactly, until they overflow
+///
+/// The operations give `None` for a result that doesn't fit.
+pub(crate) trait Whole: Clone + Ord + From<i64> {
+    /// The largest magnitude there is, for [`EvaluationError::Overflow`]
+    const LIMIT: f64;
+
+    /// The nearest float
+    fn to_f64(&self) -> f64;
+
+    /// The number as a `u32`, if it fits
+    fn to_u32(&self) -> Option<u32>;
+
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+
+    fn checked_sub(&self, other: &Self) -> Option<Self>;
+
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+
+    /// The quotient, when `other` divides `self`
+    fn checked_div(&self, other: &Self) -> Option<Self>;
+
+    /// Whether `other` divides `self` without a remainder
+    fn divisible_by(&self, other: &Self) -> bool;
+
+    fn checked_neg(&self) -> Option<Self>;
+
+    fn checked_pow(&self, exponent: u32) -> Option<Self>;
+}
+
+impl Whole for i64 {
+    const LIMIT: f64 = i64::MAX as f64;
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+
+    fn to_u32(&self) -> Option<u32> {
+        u32::try_from(*self).ok()
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        i64::checked_add(*self, *other)
+    }
+
+    fn checked_sub(&self, other: &Self) -> Option<Self> {
+        i64::checked_sub(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        i64::checked_mul(*self, *other)
+    }
+
+    fn checked_div(&self, other: &Self) -> Option<Self> {
+        // `i64::MIN / -1` is the one quotient of integers that overflows
+        i64::checked_div(*self, *other)
+    }
+
+    fn divisible_by(&self, other: &Self) -> bool {
+        self.wrapping_rem(*other) == 0
+    }
+
+    fn checked_neg(&self) -> Option<Self> {
+        i64::checked_neg(*self)
+    }
+
+    fn checked_pow(&self, exponent: u32) -> Option<Self> {
+        i64::checked_pow(*self, exponent)
+    }
+}
+
+/// Evaluate `expr` with whole numbers kept as checked 64-bit integers
+///
+/// Number literals and inputs that are whole numbers up to 2^53 are
+/// integers; larger IDs should be passed in `vars` as [`Number::Integer`],
+/// since a literal has already been read as a float. Integers divide
+/// exactly when there's no remainder, comparisons between them are exact,
+/// and `abs`, `min`, `max`, `pow` with a whole exponent, `floor`, `ceil`,
+/// `round`, `trunc`, `factorial` and `comb` keep them exact. Other functions
+/// come from [`FunctionRegistry::standard`] and give floats; ones that
+/// evaluate their own arguments, like `if`, can't be used, but `piecewise`
+/// can. Comparisons and logic give 0 or 1. Lists aren't supported.
+///
+/// # Example
+/// ```
+/// use ast::{evaluate_checked, parse_expression, EvaluationError, Number};
+///
+/// let (_, ast) = parse_expression("id * 10 + 3").unwrap();
+/// let id = Number::Integer(922_337_203_685_477_579);
+/// let result = evaluate_checked(&ast, &[("id", id)]).unwrap();
+/// assert_eq!(result, Number::Integer(9_223_372_036_854_775_793));
+///
+/// // One more digit doesn't fit in 64 bits
+/// let (_, ast) = parse_expression("id * 100").unwrap();
+/// assert!(matches!(
+///     evaluate_checked(&ast, &[("id", id)]),
+///     Err(EvaluationError::Overflow { .. })
+/// ));
+///
+/// // Division only leaves the integers when it has to
+/// let (_, ast) = parse_expression("12 / 4 + 7 / 2").unwrap();
+/// assert_eq!(evaluate_checked(&ast, &[]).unwrap(), Number::Float(6.5));
+/// ```
+pub fn evaluate_checked(expr: &Expr, vars: &[(&str, Number)]) -> Result<Number, EvaluationError> {
+    evaluate_whole(expr, vars)
+}
+
+/// Evaluate `expr` with whole numbers kept as `I`
+pub(crate) fn evaluate_whole<I: Whole>(
+    expr: &Expr,
+    vars: &[(&str, Number<I>)],
+) -> Result<Number<I>, EvaluationError> {
+    Checked {
+        functions: FunctionRegistry::standard(),
+        bindings: Vec::new(),
+        vars,
+    }
+    .evaluate(expr)
+}
+
+/// The state of one [`evaluate_checked`]
+struct Checked<'a, I> {
+    functions: FunctionRegistry,
+    /// Names bound by `let`, innermost last
+    bindings: Vec<(&'a str, Number<I>)>,
+    vars: &'a [(&'a str, Number<I>)],
+}
+
+impl<'a, I: Whole> Checked<'a, I> {
+    /// The value of `expr`
+    fn evaluate(&mut self, expr: &'a Expr) -> Result<Number<I>, EvaluationError> {
+        Ok(match expr {
+            Expr::Float(value) => from_float(*value),
+            Expr::Var(name) => self.variable(name)?,
+            Expr::Add(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                arithmetic(left, right, I::checked_add, |a, b| a + b)?
+            }
+            Expr::Sub(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                arithmetic(left, right, I::checked_sub, |a, b| a - b)?
+            }
+            Expr::Mul(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                arithmetic(left, right, I::checked_mul, |a, b| a * b)?
+            }
+            Expr::Div(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                if float(&right) == 0.0 {
+                    return Err(EvaluationError::DivisionByZero);
+                }
+                match (&left, &right) {
+                    (Number::Integer(a), Number::Integer(b)) if a.divisible_by(b) => {
+                        checked(a.checked_div(b), float(&left) / float(&right))?
+                    }
+                    _ => Number::Float(float(&left) / float(&right)),
+                }
+            }
+            Expr::Neg(inner) => match self.evaluate(inner)? {
+                Number::Integer(value) => checked(value.checked_neg(), -value.to_f64())?,
+                Number::Float(value) => Number::Float(-value),
+            },
+            Expr::Compare(comparison, left, right) => {
+                let holds = match (self.evaluate(left)?, self.evaluate(right)?) {
+                    (Number::Integer(a), Number::Integer(b)) => comparison.holds(a, b),
+                    (left, right) => comparison.holds(float(&left), float(&right)),
+                };
+                truth(holds)
+            }
+            Expr::And(left, right) => {
+                let left = float(&self.evaluate(left)?) != 0.0;
+                truth(left && float(&self.evaluate(right)?) != 0.0)
+            }
+            Expr::Or(left, right) => {
+                let left = float(&self.evaluate(left)?) != 0.0;
+                truth(left || float(&self.evaluate(right)?) != 0.0)
+            }
+            Expr::Not(inner) => truth(float(&self.evaluate(inner)?) == 0.0),
+            Expr::Let(name, value, body) => {
+                let value = self.evaluate(value)?;
+                self.bindings.push((name, value));
+                let result = self.evaluate(body);
+                self.bindings.pop();
+                result?
+            }
+            Expr::Piecewise(cases, default) => {
+                for (condition, value) in cases {
+                    if float(&self.evaluate(condition)?) != 0.0 {
+                        return self.evaluate(value);
+                    }
+                }
+                match default {
+                    Some(default) => self.evaluate(default)?,
+                    None => return Err(EvaluationError::DomainError("piecewise".to_string())),
+                }
+            }
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Named(_, value) => self.evaluate(value)?,
+            Expr::CellRef(cell) => return Err(EvaluationError::UnresolvedCell(*cell)),
+            Expr::CellRange(from, to) => return Err(EvaluationError::RangeNotAllowed(*from, *to)),
+            Expr::List(_) | Expr::Index(..) => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a number",
+                    found: "a matrix",
+                });
+            }
+            Expr::Error(_) => return Err(EvaluationError::ContainsErrors),
+        })
+    }
+
+    /// A variable, from the innermost `let` that binds it or else the inputs
+    fn variable(&self, name: &str) -> Result<Number<I>, EvaluationError> {
+        if let Some((_, value)) = self.bindings.iter().rev().find(|(bound, _)| *bound == name) {
+            return Ok(value.clone());
+        }
+        match self.vars.iter().find(|(var, _)| *var == name) {
+            Some((_, Number::Float(value))) => Ok(from_float(*value)),
+            Some((_, value)) => Ok(value.clone()),
+            None => Err(EvaluationError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// A function call, exact for the functions that keep integers whole
+    fn call(&mut self, name: &str, args: &'a [Expr]) -> Result<Number<I>, EvaluationError> {
+        let unknown = || EvaluationError::UnknownFunction(name.to_string());
+        let args = self
+            .functions
+            .get(name)
+            .ok_or_else(unknown)?
+            .arrange(name, args)?;
+        let args = args
+            .into_iter()
+            .map(|arg| match arg {
+                Cow::Borrowed(arg) => self.evaluate(arg),
+                // A default is a constant, so it needs nothing from this evaluation
+                Cow::Owned(default) => evaluate_whole(&default, &[]),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let function = self.functions.get(name).ok_or_else(unknown)?;
+        if !function.arity().accepts(args.len()) {
+            return Err(function.wrong_count(name, args.len()));
+        }
+
+        let integers: Option<Vec<I>> = args
+            .iter()
+            .map(|arg| match arg {
+                Number::Integer(value) => Some(value.clone()),
+                Number::Float(_) => None,
+            })
+            .collect();
+        if let Some(integers) = integers
+            && let Some(result) = exact_call(name, integers)
+        {
+            return result;
+        }
+
+        let values: Vec<f64> = args.iter().map(float).collect();
+        let value = function
+            .call_numbers(&values)
+            .ok_or_else(|| EvaluationError::NotPermitted(name.to_string()))?
+            .and_then(Value::into_number)?;
+        Ok(match name {
+            "floor" | "ceil" | "round" | "trunc" => from_float(value),
+            _ => Number::Float(value),
+        })
+    }
+}
+
+/// A call to a function that keeps integers exact, or `None` to call the float version
+fn exact_call<I: Whole>(name: &str, args: Vec<I>) -> Option<Result<Number<I>, EvaluationError>> {
+    let zero = I::from(0);
+    let negative = |value: &I| {
+        Err(EvaluationError::OutOfDomain {
+            name: name.into(),
+            value: value.to_f64(),
+        })
+    };
+    Some(match (name, args.as_slice()) {
+        ("abs", [value]) if *value < zero => checked(value.checked_neg(), -value.to_f64()),
+        ("abs" | "floor" | "ceil" | "round" | "trunc", [value]) => {
+            Ok(Number::Integer(value.clone()))
+        }
+        ("min", _) => Ok(Number::Integer(args.into_iter().min()?)),
+        ("max", _) => Ok(Number::Integer(args.into_iter().max()?)),
+        ("pow", [base, exponent]) if *exponent >= zero => {
+            let approximate = base.to_f64().powf(exponent.to_f64());
+            let power = exponent
+                .to_u32()
+                .and_then(|exponent| base.checked_pow(exponent));
+            checked(power, approximate)
+        }
+        ("factorial", [n]) if *n < zero => negative(n),
+        ("factorial", [n]) => {
+            let mut product = Some(I::from(1));
+            let mut i = I::from(2);
+            while i <= *n && product.is_some() {
+                product = product.and_then(|product| product.checked_mul(&i));
+                i = i.checked_add(&I::from(1))?;
+            }
+            checked(product, factorial(n.to_f64()))
+        }
+        ("comb", [n, _]) if *n < zero => negative(n),
+        ("comb", [_, k]) if *k < zero => negative(k),
+        ("comb", [n, k]) if *k > *n => Ok(Number::Integer(zero)),
+        ("comb", [n, k]) => {
+            let approximate = binomial(n.to_f64(), k.to_f64());
+            let rest = n.checked_sub(k)?;
+            let k = if rest < *k { rest } else { k.clone() };
+            // Each step gives comb(n, i + 1), so the division is exact
+            let mut result = Some(I::from(1));
+            let mut i = zero;
+            while i < k && result.is_some() {
+                let next = i.checked_add(&I::from(1))?;
+                result = result
+                    .and_then(|result| result.checked_mul(&n.checked_sub(&i)?))
+                    .and_then(|result| result.checked_div(&next));
+                i = next;
+            }
+            checked(result, approximate)
+        }
+        _ => return None,
+    })
+}
+
+/// A number as a float, which may round a large integer
+pub(crate) fn float<I: Whole>(number: &Number<I>) -> f64 {
+    match number {
+        Number::Integer(value) => value.to_f64(),
+        Number::Float(value) => *value,
+    }
+}
+
+/// A float as an integer if it's a whole number small enough to be exact
+fn from_float<I: Whole>(value: f64) -> Number<I> {
+    if value.fract() == 0.0 && value.abs() <= EXACT_FLOAT_LIMIT {
+        Number::Integer(I::from(value as i64))
+    } else {
+        Number::Float(value)
+    }
+}
+
+/// Apply an operator to integers exactly, or to floats if either operand is one
+fn arithmetic<I: Whole>(
+    left: Number<I>,
+    right: Number<I>,
+    integers: fn(&I, &I) -> Option<I>,
+    floats: fn(f64, f64) -> f64,
+) -> Result<Number<I>, EvaluationError> {
+    let approximate = floats(float(&left), float(&right));
+    match (&left, &right) {
+        (Number::Integer(a), Number::Integer(b)) => checked(integers(a, b), approximate),
+        _ => Ok(Number::Float(approximate)),
+    }
+}
+
+/// An integer result, or an overflow that would have given about `approximate`
+fn checked<I: Whole>(result: Option<I>, approximate: f64) -> Result<Number<I>, EvaluationError> {
+    result
+        .map(Number::Integer)
+        .ok_or(EvaluationError::Overflow {
+            value: approximate,
+            limit: I::LIMIT,
+        })
+}
+
+/// 1 for true and 0 for false
+fn truth<I: Whole>(holds: bool) -> Number<I> {
+    Number::Integer(I::from(holds as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// The value of an expression, which must parse
+    fn eval(input: &str, vars: &[(&str, Number)]) -> Result<Number, EvaluationError> {
+        let (rest, ast) = parse_expression(input).unwrap();
+        assert_eq!(rest, "");
+        evaluate_checked(&ast, vars)
+    }
+
+    /// Test that integers stay exact where floats would round, and overflow is an error
+    #[test]
+    fn test_exact() {
+        let big = [("id", Number::Integer(9_007_199_254_740_993))];
+        assert_eq!(
+            eval("id + 2", &big).unwrap(),
+            Number::Integer(9_007_199_254_740_995)
+        );
+        assert_eq!(eval("id - 1 > id - 2", &big).unwrap(), Number::Integer(1));
+        assert_eq!(
+            eval("max(id, 3) * 1", &big).unwrap(),
+            Number::Integer(9_007_199_254_740_993)
+        );
+        assert_eq!(
+            eval("pow(2, 62) - 1 + pow(2, 62)", &[]).unwrap(),
+            Number::Integer(i64::MAX)
+        );
+        assert_eq!(eval("12 / 4", &[]).unwrap(), Number::Integer(3));
+        assert_eq!(eval("round(7 / 2)", &[]).unwrap(), Number::Integer(4));
+        assert_eq!(
+            eval("let c = 1999 in c * 3", &[]).unwrap(),
+            Number::Integer(5997)
+        );
+
+        let cents = [("price", Number::Float(1999.0))];
+        assert_eq!(eval("price * 3", &cents).unwrap(), Number::Integer(5997));
+
+        for overflowing in [
+            "pow(2, 63)",
+            "pow(2, 62) * 2",
+            "-pow(2, 62) * 2 - 1",
+            "abs(-pow(2, 62) * 2)",
+            "factorial(21)",
+        ] {
+            assert!(
+                matches!(
+                    eval(overflowing, &[]),
+                    Err(EvaluationError::Overflow { .. })
+                ),
+                "'{}'",
+                overflowing
+            );
+        }
+        assert_eq!(
+            eval("-pow(2, 62) * 2", &[]).unwrap(),
+            Number::Integer(i64::MIN)
+        );
+        assert_eq!(
+            eval("factorial(20)", &[]).unwrap(),
+            Number::Integer(2_432_902_008_176_640_000)
+        );
+        assert_eq!(
+            eval("comb(60, 30) + comb(3, 4)", &[]).unwrap(),
+            Number::Integer(118_264_581_564_861_424)
+        );
+    }
+
+    /// Test falling back to floats, and errors
+    #[test]
+    fn test_floats() {
+        assert_eq!(eval("7 / 2", &[]).unwrap(), Number::Float(3.5));
+        assert_eq!(eval("0.5 + 1", &[]).unwrap(), Number::Float(1.5));
+        assert_eq!(eval("sqrt(16)", &[]).unwrap(), Number::Float(4.0));
+        assert_eq!(eval("pow(2, -1)", &[]).unwrap(), Number::Float(0.5));
+        assert_eq!(eval("1e300 * 10", &[]).unwrap(), Number::Float(1e301));
+        assert!(matches!(
+            eval("1 / 0", &[]),
+            Err(EvaluationError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval("[1]", &[]),
+            Err(EvaluationError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            eval("x", &[]),
+            Err(EvaluationError::UndefinedVariable(_))
+        ));
+        assert_eq!(Number::Integer(-12).to_string(), "-12");
+        assert_eq!(Number::<i64>::Float(0.25).to_string(), "0.25");
+    }
+}
@@ -0,0 +1,891 @@
+//! Lowering to three-address code
+//!
+//! Compilers rarely generate machine code straight from the syntax tree.
+//! Instead they first *lower* the tree into a flat list of simple instructions,
+//! each doing at most one operation and storing its result in a numbered
+//! temporary:
+//!
+//! ```text
+//! 3 + 4 * 2    =>    t1 = 4 * 2
+//!                    t2 = 3 + t1
+//!                    return t2
+//! ```
+//!
+//! [`lower`] produces this "three-address code". Nesting in the tree turns
+//! into ordering in the list: an operation's operands are always computed by
+//! earlier instructions. `&&`, `||` and functions that evaluate their own
+//! arguments, such as `if`, become conditional jumps, so an operand is only
+//! computed when it's needed, just like in the evaluator.
+//!
+//! [`execute`] runs the code on a small virtual machine. It's a second,
+//! independent way of evaluating an expression, which the
+//! [differential tests](crate::compare_backends) hold against the evaluator.
+
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+use crate::{
+    CancellationToken, CellRef, Comparison, Environment, EvaluationError, Evaluator, Expr, Span,
+    functions::STANDARD,
+};
+
+/// A temporary that holds the result of one instruction, printed as `t1`, `t2`, ...
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Temp(pub usize);
+
+/// A jump target, printed as `L1`, `L2`, ...
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Label(pub usize);
+
+/// An input to an instruction
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    /// A number literal
+    Const(f64),
+    /// A variable, read from the environment
+    Var(String),
+    /// A spreadsheet cell
+    Cell(CellRef),
+    /// A range of spreadsheet cells, only used as a function argument
+    Range(CellRef, CellRef),
+    /// The result of an earlier instruction
+    Temp(Temp),
+    /// Input that could not be parsed, printed as `?`
+    Error(Span),
+}
+
+/// One step of three-address code
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instruction {
+    /// `dest = left op right`, where `op` is an arithmetic or comparison operator
+    Binary {
+        /// Where the result goes
+        dest: Temp,
+        /// The operator, as written in expressions
+        op: &'static str,
+        /// The left operand
+        left: Operand,
+        /// The right operand
+        right: Operand,
+    },
+
+    /// `dest = op operand`, where `op` is `-` or `!`
+    Unary {
+        /// Where the result goes
+        dest: Temp,
+        /// The operator, as written in expressions
+        op: &'static str,
+        /// The operand
+        operand: Operand,
+    },
+
+    /// `dest = function(args...)`
+    Call {
+        /// Where the result goes
+        dest: Temp,
+        /// The name of the function
+        function: String,
+        /// The arguments, in order
+        args: Vec<Operand>,
+    },
+
+    /// `dest = [elements...]`, which fails when run, since the virtual machine only has numbers
+    List {
+        /// Where the result goes
+        dest: Temp,
+        /// The elements, in order
+        elements: Vec<Operand>,
+    },
+
+    /// `dest = target[indices...]`
+    Index {
+        /// Where the result goes
+        dest: Temp,
+        /// The list or matrix
+        target: Operand,
+        /// One or two indices, counting from 1
+        indices: Vec<Operand>,
+    },
+
+    /// `dest = source`
+    Copy {
+        /// Where the value goes
+        dest: Temp,
+        /// The value to copy
+        source: Operand,
+    },
+
+    /// `if condition goto label`, jumping when the condition is nonzero
+    JumpIf {
+        /// The value to test
+        condition: Operand,
+        /// Where to jump to
+        label: Label,
+    },
+
+    /// `ifnot condition goto label`, jumping when the condition is zero
+    JumpUnless {
+        /// The value to test
+        condition: Operand,
+        /// Where to jump to
+        label: Label,
+    },
+
+    /// `goto label`, always jumping
+    Goto(Label),
+
+    /// `label:`, the target of jumps
+    Label(Label),
+
+    /// `unmatched`, failing because no case of a piecewise definition holds
+    Unmatched,
+
+    /// `try label`, jumping to the label if a variable or cell is missing before the matching `endtry`
+    ///
+    /// Tries nest: a missing value jumps to the label of the innermost one.
+    Try(Label),
+
+    /// `endtry`, ending the innermost try
+    EndTry,
+
+    /// `missing`, as every argument of `coalesce` is missing
+    ///
+    /// Inside a try this jumps like a missing variable, otherwise it fails.
+    Missing,
+
+    /// `return value`, the result of the whole expression
+    Return(Operand),
+}
+
+/// Lower an expression to three-address code
+///
+/// The last instruction is always a [`Instruction::Return`] of the result.
+///
+/// # Example
+/// ```
+/// use ast::{lower, parse_expression};
+///
+/// let (_, ast) = parse_expression("3 + 4 * 2").unwrap();
+/// let code: Vec<String> = lower(&ast).iter().map(|i| i.to_string()).collect();
+/// assert_eq!(code, vec!["t1 = 4 * 2", "t2 = 3 + t1", "return t2"]);
+/// ```
+pub fn lower(expr: &Expr) -> Vec<Instruction> {
+    let mut lowering = Lowering::default();
+    let result = lowering.operand(expr);
+    lowering.code.push(Instruction::Return(result));
+    lowering.code
+}
+
+/// Run three-address code, looking variables up in `env` and functions in the standard registry
+///
+/// Every value is a number, so functions on other types of value, such as
+/// dates, fail with a type error. Code with cells or parse errors in it
+/// fails the same way the evaluator does.
+///
+/// # Example
+/// ```
+/// use ast::{execute, lower, parse_expression, Environment};
+///
+/// let (_, ast) = parse_expression("x > 0 && sqrt(x) < 3").unwrap();
+/// let env: Environment = [("x", 4.0)].into_iter().collect();
+/// assert_eq!(execute(&lower(&ast), &env).unwrap(), 1.0);
+/// ```
+pub fn execute(code: &[Instruction], env: &Environment) -> Result<f64, EvaluationError> {
+    run(code, env, None)
+}
+
+/// Run three-address code like [`execute`], stopping once `token` is cancelled
+///
+/// The token is checked before every instruction, and a cancelled run fails
+/// with [`EvaluationError::Cancelled`].
+///
+/// # Example
+/// ```
+/// use ast::{execute_cancellable, lower, parse_expression, CancellationToken, Environment, EvaluationError};
+///
+/// let (_, ast) = parse_expression("2 * 3").unwrap();
+/// let (code, env, token) = (lower(&ast), Environment::new(), CancellationToken::new());
+/// assert_eq!(execute_cancellable(&code, &env, &token).unwrap(), 6.0);
+/// token.cancel();
+/// assert!(matches!(execute_cancellable(&code, &env, &token), Err(EvaluationError::Cancelled)));
+/// ```
+pub fn execute_cancellable(
+    code: &[Instruction],
+    env: &Environment,
+    token: &CancellationToken,
+) -> Result<f64, EvaluationError> {
+    run(code, env, Some(token))
+}
+
+/// Run three-address code, checking `token` before every instruction if there is one
+fn run(
+    code: &[Instruction],
+    env: &Environment,
+    token: Option<&CancellationToken>,
+) -> Result<f64, EvaluationError> {
+    let labels: HashMap<usize, usize> = code
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            Instruction::Label(label) => Some((label.0, index)),
+            _ => None,
+        })
+        .collect();
+    let mut temps: Vec<f64> = Vec::new();
+    let read = |temps: &[f64], operand: &Operand| match operand {
+        Operand::Const(value) => Ok(*value),
+        Operand::Var(name) => env
+            .get(name)
+            .ok_or_else(|| EvaluationError::UndefinedVariable(name.clone())),
+        Operand::Temp(temp) => Ok(temps[temp.0 - 1]),
+        Operand::Cell(cell) => Err(EvaluationError::UnresolvedCell(*cell)),
+        Operand::Range(from, to) => Err(EvaluationError::RangeNotAllowed(*from, *to)),
+        Operand::Error(_) => Err(EvaluationError::ContainsErrors),
+    };
+    let write = |temps: &mut Vec<f64>, dest: Temp, value: f64| {
+        if temps.len() < dest.0 {
+            temps.resize(dest.0, 0.0);
+        }
+        temps[dest.0 - 1] = value;
+    };
+
+    let mut next = 0;
+    // The labels of the tries that haven't ended, innermost last
+    let mut tries: Vec<Label> = Vec::new();
+    while let Some(instruction) = code.get(next) {
+        if let Some(token) = token
+            && token.is_cancelled()
+        {
+            return Err(EvaluationError::Cancelled);
+        }
+        next += 1;
+        // Run the instruction, giving the result if it's a return
+        let result = (|| {
+            match instruction {
+                Instruction::Binary {
+                    dest,
+                    op,
+                    left,
+                    right,
+                } => {
+                    let (left, right) = (read(&temps, left)?, read(&temps, right)?);
+                    let value = match *op {
+                        "+" => left + right,
+                        "-" => left - right,
+                        "*" => left * right,
+                        "/" if right == 0.0 => return Err(EvaluationError::DivisionByZero),
+                        "/" => left / right,
+                        comparison => {
+                            let comparison = Comparison::from_symbol(comparison)
+                                .expect("the other operators are comparisons");
+                            if comparison.holds(left, right) {
+                                1.0
+                            } else {
+                                0.0
+                            }
+                        }
+                    };
+                    write(&mut temps, *dest, value);
+                }
+                Instruction::Unary { dest, op, operand } => {
+                    let operand = read(&temps, operand)?;
+                    let value = match *op {
+                        "-" => -operand,
+                        _ if operand == 0.0 => 1.0,
+                        _ => 0.0,
+                    };
+                    write(&mut temps, *dest, value);
+                }
+                Instruction::Call {
+                    dest,
+                    function,
+                    args,
+                } => {
+                    let found = STANDARD
+                        .get(function)
+                        .ok_or_else(|| EvaluationError::UnknownFunction(function.clone()))?;
+                    if !found.arity().accepts(args.len()) {
+                        return Err(found.wrong_count(function, args.len()));
+                    }
+                    // Functions are called the way the evaluator calls them, with the
+                    // arguments already worked out
+                    let args = args
+                        .iter()
+                        .map(|arg| read(&temps, arg).map(Expr::Float))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let value = found
+                        .call(function, &Evaluator::new(env), &args)?
+                        .into_number()?;
+                    write(&mut temps, *dest, value);
+                }
+                // Every value here is a number, so lists and indexing are type errors
+                Instruction::List { elements, .. } => {
+                    for element in elements {
+                        read(&temps, element)?;
+                    }
+                    return Err(EvaluationError::TypeMismatch {
+                        expected: "a number",
+                        found: "a matrix",
+                    });
+                }
+                Instruction::Index {
+                    target, indices, ..
+                } => {
+                    read(&temps, target)?;
+                    for index in indices {
+                        read(&temps, index)?;
+                    }
+                    return Err(EvaluationError::TypeMismatch {
+                        expected: "a matrix",
+                        found: "a number",
+                    });
+                }
+                Instruction::Copy { dest, source } => {
+                    let value = read(&temps, source)?;
+                    write(&mut temps, *dest, value);
+                }
+                Instruction::JumpIf { condition, label } => {
+                    if read(&temps, condition)? != 0.0 {
+                        next = labels[&label.0];
+                    }
+                }
+                Instruction::JumpUnless { condition, label } => {
+                    if read(&temps, condition)? == 0.0 {
+                        next = labels[&label.0];
+                    }
+                }
+                Instruction::Goto(label) => next = labels[&label.0],
+                Instruction::Label(_) => {}
+                Instruction::Unmatched => {
+                    return Err(EvaluationError::DomainError("piecewise".to_string()));
+                }
+                Instruction::Try(label) => tries.push(*label),
+                Instruction::EndTry => {
+                    tries.pop();
+                }
+                // Missing inside another `coalesce` moves it on to its next argument
+                Instruction::Missing => match tries.pop() {
+                    Some(label) => next = labels[&label.0],
+                    None => {
+                        return Err(EvaluationError::TypeMismatch {
+                            expected: "a number",
+                            found: "null",
+                        });
+                    }
+                },
+                Instruction::Return(value) => return read(&temps, value).map(Some),
+            }
+            Ok(None)
+        })();
+        match result {
+            Ok(None) => {}
+            Ok(Some(value)) => return Ok(value),
+            Err(EvaluationError::UndefinedVariable(_) | EvaluationError::UnresolvedCell(_))
+                if let Some(label) = tries.pop() =>
+            {
+                next = labels[&label.0];
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    unreachable!("lowered code always ends with a return")
+}
+
+/// The code generated so far, and the temporaries and labels used
+#[derive(Default)]
+struct Lowering {
+    code: Vec<Instruction>,
+    temps: usize,
+    labels: usize,
+    /// Where the values of the enclosing `let`s are, innermost last
+    locals: Vec<(String, Operand)>,
+}
+
+impl Lowering {
+    fn temp(&mut self) -> Temp {
+        self.temps += 1;
+        Temp(self.temps)
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        Label(self.labels)
+    }
+
+    /// Generate the code computing `expr`, returning where its value ends up
+    fn operand(&mut self, expr: &Expr) -> Operand {
+        match expr {
+            Expr::Float(value) => Operand::Const(*value),
+            Expr::Var(name) => self
+                .locals
+                .iter()
+                .rev()
+                .find(|(local, _)| local == name)
+                .map_or_else(|| Operand::Var(name.clone()), |(_, value)| value.clone()),
+            Expr::CellRef(cell) => Operand::Cell(*cell),
+            Expr::CellRange(from, to) => Operand::Range(*from, *to),
+            Expr::Error(span) => Operand::Error(*span),
+            Expr::Add(left, right) => self.binary("+", left, right),
+            Expr::Sub(left, right) => self.binary("-", left, right),
+            Expr::Mul(left, right) => self.binary("*", left, right),
+            Expr::Div(left, right) => self.binary("/", left, right),
+            Expr::Compare(comparison, left, right) => self.binary(comparison.symbol(), left, right),
+            Expr::Neg(inner) => self.unary("-", inner),
+            Expr::Not(inner) => self.unary("!", inner),
+            Expr::And(left, right) => self.logical(left, right, false),
+            Expr::Or(left, right) => self.logical(left, right, true),
+            Expr::Call(function, args) => {
+                // Instructions only have positions, so named arguments are put
+                // in place now; ones that don't fit are left in written order
+                let found = STANDARD.get(function);
+                let arranged = found
+                    .and_then(|found| found.arrange(function, args).ok())
+                    .unwrap_or_else(|| args.iter().map(Cow::Borrowed).collect());
+                // Functions that evaluate their own arguments become jumps, like
+                // `&&`; with the wrong number of arguments, the call fails
+                if found.is_some_and(|found| found.is_lazy() && found.arity().accepts(args.len())) {
+                    match (function.as_str(), &arranged[..]) {
+                        ("if", [condition, then, otherwise]) => {
+                            return self.choose(condition, then, otherwise);
+                        }
+                        ("coalesce", _) => return self.coalesce(&arranged),
+                        _ => unreachable!("every lazy standard function is lowered"),
+                    }
+                }
+                let args = arranged.iter().map(|arg| self.operand(arg)).collect();
+                let dest = self.temp();
+                self.code.push(Instruction::Call {
+                    dest,
+                    function: function.clone(),
+                    args,
+                });
+                Operand::Temp(dest)
+            }
+            Expr::Named(_, value) => self.operand(value),
+            Expr::Let(name, value, body) => {
+                let value = self.operand(value);
+                self.locals.push((name.clone(), value));
+                let body = self.operand(body);
+                self.locals.pop();
+                body
+            }
+            Expr::Piecewise(cases, default) => self.piecewise(cases, default.as_deref()),
+            Expr::List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.operand(element))
+                    .collect();
+                let dest = self.temp();
+                self.code.push(Instruction::List { dest, elements });
+                Operand::Temp(dest)
+            }
+            Expr::Index(target, indices) => {
+                let target = self.operand(target);
+                let indices = indices.iter().map(|index| self.operand(index)).collect();
+                let dest = self.temp();
+                self.code.push(Instruction::Index {
+                    dest,
+                    target,
+                    indices,
+                });
+                Operand::Temp(dest)
+            }
+        }
+    }
+
+    fn binary(&mut self, op: &'static str, left: &Expr, right: &Expr) -> Operand {
+        let left = self.operand(left);
+        let right = self.operand(right);
+        let dest = self.temp();
+        self.code.push(Instruction::Binary {
+            dest,
+            op,
+            left,
+            right,
+        });
+        Operand::Temp(dest)
+    }
+
+    fn unary(&mut self, op: &'static str, inner: &Expr) -> Operand {
+        let operand = self.operand(inner);
+        let dest = self.temp();
+        self.code.push(Instruction::Unary { dest, op, operand });
+        Operand::Temp(dest)
+    }
+
+    /// `&&` (when `decided_by` is false) or `||` (when it's true), short-circuiting
+    ///
+    /// ```text
+    /// a && b    =>    t1 = 0                 a || b    =>    t1 = 1
+    ///                 ifnot a goto L1                        if a goto L1
+    ///                 t1 = b != 0                            t1 = b != 0
+    ///                 L1:                                    L1:
+    /// ```
+    fn logical(&mut self, left: &Expr, right: &Expr, decided_by: bool) -> Operand {
+        let condition = self.operand(left);
+        let dest = self.temp();
+        let done = self.label();
+        self.code.push(Instruction::Copy {
+            dest,
+            source: Operand::Const(if decided_by { 1.0 } else { 0.0 }),
+        });
+        self.code.push(if decided_by {
+            Instruction::JumpIf {
+                condition,
+                label: done,
+            }
+        } else {
+            Instruction::JumpUnless {
+                condition,
+                label: done,
+            }
+        });
+
+        let right = self.operand(right);
+        self.code.push(Instruction::Binary {
+            dest,
+            op: "!=",
+            left: right,
+            right: Operand::Const(0.0),
+        });
+        self.code.push(Instruction::Label(done));
+        Operand::Temp(dest)
+    }
+
+    /// `if(condition, then, otherwise)`, only computing the branch it picks
+    ///
+    /// ```text
+    /// if(a, b, c)    =>    ifnot a goto L2
+    ///                      t1 = b
+    ///                      goto L1
+    ///                      L2:
+    ///                      t1 = c
+    ///                      L1:
+    /// ```
+    fn choose(&mut self, condition: &Expr, then: &Expr, otherwise: &Expr) -> Operand {
+        let dest = self.temp();
+        let done = self.label();
+        let condition = self.operand(condition);
+        let next = self.label();
+        self.code.push(Instruction::JumpUnless {
+            condition,
+            label: next,
+        });
+        let source = self.operand(then);
+        self.code.push(Instruction::Copy { dest, source });
+        self.code.push(Instruction::Goto(done));
+        self.code.push(Instruction::Label(next));
+        let source = self.operand(otherwise);
+        self.code.push(Instruction::Copy { dest, source });
+        self.code.push(Instruction::Label(done));
+        Operand::Temp(dest)
+    }
+
+    /// `coalesce(args...)`, trying each argument until one isn't missing
+    ///
+    /// ```text
+    /// coalesce(a, b)    =>    try L2
+    ///                         t1 = a
+    ///                         endtry
+    ///                         goto L1
+    ///                         L2:
+    ///                         try L3
+    ///                         t1 = b
+    ///                         endtry
+    ///                         goto L1
+    ///                         L3:
+    ///                         missing
+    ///                         L1:
+    /// ```
+    fn coalesce(&mut self, args: &[Cow<Expr>]) -> Operand {
+        let dest = self.temp();
+        let done = self.label();
+        for arg in args {
+            let next = self.label();
+            self.code.push(Instruction::Try(next));
+            let source = self.operand(arg);
+            self.code.push(Instruction::Copy { dest, source });
+            self.code.push(Instruction::EndTry);
+            self.code.push(Instruction::Goto(done));
+            self.code.push(Instruction::Label(next));
+        }
+        self.code.push(Instruction::Missing);
+        self.code.push(Instruction::Label(done));
+        Operand::Temp(dest)
+    }
+
+    /// A piecewise definition, testing each condition until one holds
+    ///
+    /// ```text
+    /// piecewise((a, b), c)    =>    ifnot a goto L2
+    ///                               t1 = b
+    ///                               goto L1
+    ///                               L2:
+    ///                               t1 = c
+    ///                               L1:
+    /// ```
+    fn piecewise(&mut self, cases: &[(Expr, Expr)], default: Option<&Expr>) -> Operand {
+        let dest = self.temp();
+        let done = self.label();
+        for (condition, value) in cases {
+            let condition = self.operand(condition);
+            let next = self.label();
+            self.code.push(Instruction::JumpUnless {
+                condition,
+                label: next,
+            });
+            let source = self.operand(value);
+            self.code.push(Instruction::Copy { dest, source });
+            self.code.push(Instruction::Goto(done));
+            self.code.push(Instruction::Label(next));
+        }
+        match default {
+            Some(default) => {
+                let source = self.operand(default);
+                self.code.push(Instruction::Copy { dest, source });
+            }
+            None => self.code.push(Instruction::Unmatched),
+        }
+        self.code.push(Instruction::Label(done));
+        Operand::Temp(dest)
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{}", self.0)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Const(value) => write!(f, "{}", value),
+            Operand::Var(name) => write!(f, "{}", name),
+            Operand::Cell(cell) => write!(f, "{}", cell),
+            Operand::Range(from, to) => write!(f, "{}:{}", from, to),
+            Operand::Temp(temp) => write!(f, "{}", temp),
+            Operand::Error(_) => write!(f, "?"),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => write!(f, "{} = {} {} {}", dest, left, op, right),
+            Instruction::Unary { dest, op, operand } => write!(f, "{} = {}{}", dest, op, operand),
+            Instruction::Call {
+                dest,
+                function,
+                args,
+            } => {
+                let args: Vec<String> = args.iter().map(Operand::to_string).collect();
+                write!(f, "{} = {}({})", dest, function, args.join(", "))
+            }
+            Instruction::List { dest, elements } => {
+                let elements: Vec<String> = elements.iter().map(Operand::to_string).collect();
+                write!(f, "{} = [{}]", dest, elements.join(", "))
+            }
+            Instruction::Index {
+                dest,
+                target,
+                indices,
+            } => {
+                let indices: Vec<String> = indices.iter().map(Operand::to_string).collect();
+                write!(f, "{} = {}[{}]", dest, target, indices.join(", "))
+            }
+            Instruction::Copy { dest, source } => write!(f, "{} = {}", dest, source),
+            Instruction::JumpIf { condition, label } => {
+                write!(f, "if {} goto {}", condition, label)
+            }
+            Instruction::JumpUnless { condition, label } => {
+                write!(f, "ifnot {} goto {}", condition, label)
+            }
+            Instruction::Goto(label) => write!(f, "goto {}", label),
+            Instruction::Label(label) => write!(f, "{}:", label),
+            Instruction::Unmatched => write!(f, "unmatched"),
+            Instruction::Try(label) => write!(f, "try {}", label),
+            Instruction::EndTry => write!(f, "endtry"),
+            Instruction::Missing => write!(f, "missing"),
+            Instruction::Return(value) => write!(f, "return {}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Lower an expression and print each instruction
+    fn lower_str(expression: &str) -> Vec<String> {
+        let (_, ast) = parse_expression(expression).unwrap();
+        lower(&ast).iter().map(Instruction::to_string).collect()
+    }
+
+    /// Test that operands are computed before the operations that use them
+    #[test]
+    fn test_lowering_order() {
+        assert_eq!(
+            lower_str("-(a - 1) / max(b, 2 * c)"),
+            vec![
+                "t1 = a - 1",
+                "t2 = -t1",
+                "t3 = 2 * c",
+                "t4 = max(b, t3)",
+                "t5 = t2 / t4",
+                "return t5",
+            ]
+        );
+        assert_eq!(lower_str("42"), vec!["return 42"]);
+        assert_eq!(
+            lower_str("piecewise((x < 0, -x), (x < 1, 1))"),
+            vec![
+                "t2 = x < 0",
+                "ifnot t2 goto L2",
+                "t3 = -x",
+                "t1 = t3",
+                "goto L1",
+                "L2:",
+                "t4 = x < 1",
+                "ifnot t4 goto L3",
+                "t1 = 1",
+                "goto L1",
+                "L3:",
+                "unmatched",
+                "L1:",
+                "return t1",
+            ]
+        );
+    }
+
+    /// Test running code, including jumps and failures
+    #[test]
+    fn test_execute() {
+        let env: Environment = [("x", 0.0), ("y", 2.0)].into_iter().collect();
+        let run = |expression: &str| {
+            let (_, ast) = parse_expression(expression).unwrap();
+            execute(&lower(&ast), &env)
+        };
+        assert_eq!(run("-(y - 1) / max(y, 2 * 3)").unwrap(), -1.0 / 6.0);
+        assert_eq!(run("x != 0 && 1 / x > 2").unwrap(), 0.0);
+        assert_eq!(run("x == 0 || 1 / x > 2").unwrap(), 1.0);
+        assert_eq!(run("!x + !y").unwrap(), 1.0);
+        assert_eq!(
+            run("let y = y * 3 in let z = y + 1 in z * y").unwrap(),
+            42.0
+        );
+        assert_eq!(
+            run("piecewise((x, 1 / x), (y > 1, y * 2), 0)").unwrap(),
+            4.0
+        );
+        assert!(matches!(
+            run("piecewise((x > 0, 1))"),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(matches!(run("1 / x"), Err(EvaluationError::DivisionByZero)));
+        assert!(matches!(
+            run("[1, y][2]"),
+            Err(EvaluationError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            run("z"),
+            Err(EvaluationError::UndefinedVariable(_))
+        ));
+        assert!(matches!(
+            run("sqrt(-y)"),
+            Err(EvaluationError::OutOfDomain { .. })
+        ));
+
+        // Functions that evaluate their own arguments only compute what they use
+        assert_eq!(run("if(x, 1 / x, 3)").unwrap(), 3.0);
+        assert_eq!(run("if(y, 1 / y, 1 / x)").unwrap(), 0.5);
+        assert_eq!(run("coalesce(w, y)").unwrap(), 2.0);
+        assert_eq!(run("coalesce(coalesce(w, v), w + 1, y * 3)").unwrap(), 6.0);
+        assert!(matches!(
+            run("coalesce(1 / x, y)"),
+            Err(EvaluationError::DivisionByZero)
+        ));
+        assert!(matches!(
+            run("coalesce(w)"),
+            Err(EvaluationError::TypeMismatch { found: "null", .. })
+        ));
+        assert!(matches!(
+            run("if(x, 1)"),
+            Err(EvaluationError::WrongArgumentCount { .. })
+        ));
+    }
+
+    /// Test that && and || jump over their right operand
+    #[test]
+    fn test_short_circuit() {
+        assert_eq!(
+            lower_str("x != 0 && 1 / x > 2 || y"),
+            vec![
+                "t1 = x != 0",
+                "t2 = 0",
+                "ifnot t1 goto L1",
+                "t3 = 1 / x",
+                "t4 = t3 > 2",
+                "t2 = t4 != 0",
+                "L1:",
+                "t5 = 1",
+                "if t2 goto L2",
+                "t5 = y != 0",
+                "L2:",
+                "return t5",
+            ]
+        );
+        assert_eq!(
+            lower_str("if(x, 1 / x, 3)"),
+            vec![
+                "ifnot x goto L2",
+                "t2 = 1 / x",
+                "t1 = t2",
+                "goto L1",
+                "L2:",
+                "t1 = 3",
+                "L1:",
+                "return t1",
+            ]
+        );
+        assert_eq!(
+            lower_str("coalesce(w, 2)"),
+            vec![
+                "try L2",
+                "t1 = w",
+                "endtry",
+                "goto L1",
+                "L2:",
+                "try L3",
+                "t1 = 2",
+                "endtry",
+                "goto L1",
+                "L3:",
+                "missing",
+                "L1:",
+                "return t1",
+            ]
+        );
+
+        // Every function that evaluates its own arguments has a lowering
+        for name in STANDARD.names() {
+            let function = STANDARD.get(name).unwrap();
+            if function.is_lazy() {
+                let args = vec![Expr::Float(1.0); function.arity().min];
+                lower(&Expr::Call(name.to_string(), args));
+            }
+        }
+    }
+}
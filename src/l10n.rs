@@ -0,0 +1,677 @@
+//! Translated error messages
+//!
+//! Syntax errors, lint warnings and evaluation errors are written in English.
+//! [`Diagnostic::localized`] and [`EvaluationError::localized`] write them in
+//! another [`Locale`] instead, which can come from the environment with
+//! [`Locale::from_env`]. Messages are looked up by their English text, the
+//! way gettext does, so a message missing from the catalog stays in English.
+//!
+//! Only available with the `l10n` cargo feature.
+
+use std::env;
+
+use crate::{Diagnostic, EvaluationError};
+
+/// A language that messages can be written in
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Locale {
+    /// The messages as written
+    #[default]
+    English,
+    German,
+    Spanish,
+    French,
+}
+
+impl Locale {
+    /// The locale for a language tag such as `de`, `es-MX` or `fr_FR.UTF-8`
+    ///
+    /// Only the language matters. Returns `None` for a language without translations.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag
+            .split(['-', '_', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match language.as_str() {
+            "en" | "c" | "posix" => Some(Locale::English),
+            "de" => Some(Locale::German),
+            "es" => Some(Locale::Spanish),
+            "fr" => Some(Locale::French),
+            _ => None,
+        }
+    }
+
+    /// The locale named by `AST_LANG`, or else the usual `LC_ALL`, `LC_MESSAGES` and `LANG`
+    ///
+    /// The first variable that is set and not empty decides. Falls back to
+    /// English if none is set, or if it names a language without translations.
+    pub fn from_env() -> Locale {
+        ["AST_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::from_tag(&value))
+            .unwrap_or_default()
+    }
+
+    /// The translation of an English message in this locale's column of a catalog
+    fn pick(self, translations: &[&'static str; 3]) -> Option<&'static str> {
+        match self {
+            Locale::English => None,
+            Locale::German => Some(translations[0]),
+            Locale::Spanish => Some(translations[1]),
+            Locale::French => Some(translations[2]),
+        }
+    }
+}
+
+/// Messages, in English and then German, Spanish and French; `{}` is filled in
+const MESSAGES: &[(&str, [&str; 3])] = &[
+    (
+        "expected an expression, found end of input",
+        [
+            "Ausdruck erwartet, aber die Eingabe ist zu Ende",
+            "se esperaba una expresión, pero la entrada terminó",
+            "expression attendue, mais l'entrée est terminée",
+        ],
+    ),
+    (
+        "expected an expression before '{}'",
+        [
+            "Ausdruck vor '{}' erwartet",
+            "se esperaba una expresión antes de '{}'",
+            "expression attendue avant '{}'",
+        ],
+    ),
+    (
+        "expected an expression, found '{}'",
+        [
+            "Ausdruck erwartet, '{}' gefunden",
+            "se esperaba una expresión, se encontró '{}'",
+            "expression attendue, '{}' trouvé",
+        ],
+    ),
+    (
+        "unmatched ')'",
+        [
+            "')' ohne passende '('",
+            "')' sin su '(' correspondiente",
+            "')' sans '(' correspondante",
+        ],
+    ),
+    (
+        "unclosed '(' (expected ')')",
+        [
+            "'(' wird nicht geschlossen (')' erwartet)",
+            "'(' sin cerrar (se esperaba ')')",
+            "'(' non fermée (')' attendue)",
+        ],
+    ),
+    (
+        "unmatched ']'",
+        [
+            "']' ohne passende '['",
+            "']' sin su '[' correspondiente",
+            "']' sans '[' correspondant",
+        ],
+    ),
+    (
+        "unclosed '[' (expected ']')",
+        [
+            "'[' wird nicht geschlossen (']' erwartet)",
+            "'[' sin cerrar (se esperaba ']')",
+            "'[' non fermé (']' attendu)",
+        ],
+    ),
+    (
+        "expected one or two indices",
+        [
+            "ein oder zwei Indizes erwartet",
+            "se esperaban uno o dos índices",
+            "un ou deux indices attendus",
+        ],
+    ),
+    (
+        "unexpected ',' outside a function call",
+        [
+            "unerwartetes ',' außerhalb eines Funktionsaufrufs",
+            "',' inesperada fuera de una llamada a función",
+            "',' inattendue hors d'un appel de fonction",
+        ],
+    ),
+    (
+        "expected an operator",
+        [
+            "Operator erwartet",
+            "se esperaba un operador",
+            "opérateur attendu",
+        ],
+    ),
+    (
+        "'{}' is not an operator, did you mean '{}'?",
+        [
+            "'{}' ist kein Operator, war '{}' gemeint?",
+            "'{}' no es un operador, ¿quería decir '{}'?",
+            "'{}' n'est pas un opérateur, vouliez-vous dire '{}' ?",
+        ],
+    ),
+    (
+        "expected '=' after 'let {}'",
+        [
+            "'=' nach 'let {}' erwartet",
+            "se esperaba '=' después de 'let {}'",
+            "'=' attendu après 'let {}'",
+        ],
+    ),
+    (
+        "expected 'in' after the value of '{}'",
+        [
+            "'in' nach dem Wert von '{}' erwartet",
+            "se esperaba 'in' después del valor de '{}'",
+            "'in' attendu après la valeur de '{}'",
+        ],
+    ),
+    (
+        "expected a (condition, value) case in 'piecewise'",
+        [
+            "Fall (Bedingung, Wert) in 'piecewise' erwartet",
+            "se esperaba un caso (condición, valor) en 'piecewise'",
+            "cas (condition, valeur) attendu dans 'piecewise'",
+        ],
+    ),
+    (
+        "the default of 'piecewise' must come last",
+        [
+            "der Standardwert von 'piecewise' muss zuletzt stehen",
+            "el valor por defecto de 'piecewise' debe ir al final",
+            "la valeur par défaut de 'piecewise' doit venir en dernier",
+        ],
+    ),
+    (
+        "positional arguments must come before named ones",
+        [
+            "Argumente ohne Namen müssen vor benannten stehen",
+            "los argumentos posicionales deben ir antes de los nombrados",
+            "les arguments positionnels doivent précéder les arguments nommés",
+        ],
+    ),
+    (
+        "subtracting a negation, use '+' instead",
+        [
+            "Subtraktion einer Negation, besser '+' verwenden",
+            "se resta una negación, use '+' en su lugar",
+            "soustraction d'une négation, utilisez plutôt '+'",
+        ],
+    ),
+    (
+        "this factor is always 0, so the product is always 0",
+        [
+            "dieser Faktor ist immer 0, also ist das Produkt immer 0",
+            "este factor siempre es 0, así que el producto siempre es 0",
+            "ce facteur vaut toujours 0, donc le produit vaut toujours 0",
+        ],
+    ),
+    (
+        "fractions may not be exactly equal, compare the difference against a tolerance",
+        [
+            "Brüche sind womöglich nicht genau gleich, besser die Differenz mit einer Toleranz vergleichen",
+            "las fracciones pueden no ser exactamente iguales, compare la diferencia con una tolerancia",
+            "les fractions peuvent ne pas être exactement égales, comparez la différence à une tolérance",
+        ],
+    ),
+    (
+        "'{}' is never used",
+        [
+            "'{}' wird nie verwendet",
+            "'{}' nunca se usa",
+            "'{}' n'est jamais utilisé",
+        ],
+    ),
+    (
+        "redundant parentheses",
+        [
+            "überflüssige Klammern",
+            "paréntesis redundantes",
+            "parenthèses superflues",
+        ],
+    ),
+    (
+        "Division by zero",
+        [
+            "Division durch null",
+            "División por cero",
+            "Division par zéro",
+        ],
+    ),
+    (
+        "Undefined variable '{}'",
+        [
+            "Undefinierte Variable '{}'",
+            "Variable '{}' no definida",
+            "Variable '{}' non définie",
+        ],
+    ),
+    (
+        "Cell {} has no value",
+        [
+            "Zelle {} hat keinen Wert",
+            "La celda {} no tiene valor",
+            "La cellule {} n'a pas de valeur",
+        ],
+    ),
+    (
+        "Cell range {}:{} can't be used as a number",
+        [
+            "Der Zellbereich {}:{} kann nicht als Zahl verwendet werden",
+            "El rango de celdas {}:{} no puede usarse como número",
+            "La plage de cellules {}:{} ne peut pas servir de nombre",
+        ],
+    ),
+    (
+        "Unknown function '{}'",
+        [
+            "Unbekannte Funktion '{}'",
+            "Función '{}' desconocida",
+            "Fonction '{}' inconnue",
+        ],
+    ),
+    (
+        "Function '{}' takes {} arguments, got {}",
+        [
+            "Funktion '{}' erwartet {} Argumente, erhielt {}",
+            "La función '{}' toma {} argumentos, recibió {}",
+            "La fonction '{}' prend {} arguments, en a reçu {}",
+        ],
+    ),
+    (
+        "Function '{}' has no parameter named '{}'",
+        [
+            "Funktion '{}' hat keinen Parameter namens '{}'",
+            "La función '{}' no tiene ningún parámetro llamado '{}'",
+            "La fonction '{}' n'a pas de paramètre nommé '{}'",
+        ],
+    ),
+    (
+        "Function '{}' was given '{}' more than once",
+        [
+            "Funktion '{}' erhielt '{}' mehr als einmal",
+            "La función '{}' recibió '{}' más de una vez",
+            "La fonction '{}' a reçu '{}' plus d'une fois",
+        ],
+    ),
+    (
+        "Function '{}' is missing the argument '{}'",
+        [
+            "Bei Funktion '{}' fehlt das Argument '{}'",
+            "A la función '{}' le falta el argumento '{}'",
+            "La fonction '{}' n'a pas reçu l'argument '{}'",
+        ],
+    ),
+    (
+        "Argument out of range for '{}'",
+        [
+            "Argument außerhalb des Definitionsbereichs von '{}'",
+            "Argumento fuera de rango para '{}'",
+            "Argument hors du domaine de '{}'",
+        ],
+    ),
+    (
+        "Argument {} out of range for '{}'",
+        [
+            "Argument {} außerhalb des Definitionsbereichs von '{}'",
+            "Argumento {} fuera de rango para '{}'",
+            "Argument {} hors du domaine de '{}'",
+        ],
+    ),
+    (
+        "Expected {}, found {}",
+        [
+            "Erwartet: {}, gefunden: {}",
+            "Se esperaba {}, se encontró {}",
+            "{} attendu, {} trouvé",
+        ],
+    ),
+    (
+        "Function '{}' can't take a {}×{} and a {}×{} matrix",
+        [
+            "Die Funktion '{}' kann keine {}×{}- und {}×{}-Matrix nehmen",
+            "La función '{}' no puede tomar una matriz de {}×{} y una de {}×{}",
+            "La fonction '{}' ne peut pas prendre une matrice {}×{} et une matrice {}×{}",
+        ],
+    ),
+    // Before the general message below, which would also match
+    (
+        "Can't use '{}' with a {}×{} and a {}×{} matrix",
+        [
+            "'{}' ist mit einer {}×{}- und einer {}×{}-Matrix nicht möglich",
+            "No se puede usar '{}' con una matriz de {}×{} y una de {}×{}",
+            "Impossible d'utiliser '{}' avec une matrice {}×{} et une matrice {}×{}",
+        ],
+    ),
+    (
+        "Can't use '{}' with {} and {}",
+        [
+            "'{}' ist mit {} und {} nicht möglich",
+            "No se puede usar '{}' con {} y {}",
+            "Impossible d'utiliser '{}' avec {} et {}",
+        ],
+    ),
+    (
+        "Result {} is beyond the limit of {}",
+        [
+            "Ergebnis {} überschreitet die Grenze von {}",
+            "El resultado {} supera el límite de {}",
+            "Le résultat {} dépasse la limite de {}",
+        ],
+    ),
+    (
+        "Result {} is too close to zero to be represented accurately",
+        [
+            "Ergebnis {} liegt zu nahe an null, um genau dargestellt zu werden",
+            "El resultado {} está demasiado cerca de cero para representarse con precisión",
+            "Le résultat {} est trop proche de zéro pour être représenté précisément",
+        ],
+    ),
+    (
+        "Function '{}' isn't allowed here",
+        [
+            "Funktion '{}' ist hier nicht erlaubt",
+            "La función '{}' no está permitida aquí",
+            "La fonction '{}' n'est pas autorisée ici",
+        ],
+    ),
+    (
+        "Evaluation ran past its deadline",
+        [
+            "Die Auswertung hat ihre Frist überschritten",
+            "La evaluación superó su plazo",
+            "L'évaluation a dépassé son échéance",
+        ],
+    ),
+    (
+        "Evaluation was cancelled",
+        [
+            "Die Auswertung wurde abgebrochen",
+            "La evaluación fue cancelada",
+            "L'évaluation a été annulée",
+        ],
+    ),
+    (
+        "{} has no inverse modulo {}",
+        [
+            "{} hat kein Inverses modulo {}",
+            "{} no tiene inverso módulo {}",
+            "{} n'a pas d'inverse modulo {}",
+        ],
+    ),
+    (
+        "Function calls nested more than {} deep",
+        [
+            "Funktionsaufrufe tiefer als {} verschachtelt",
+            "Llamadas a funciones anidadas a más de {} niveles",
+            "Appels de fonction imbriqués sur plus de {} niveaux",
+        ],
+    ),
+    (
+        "Index {} isn't a whole number from 1 to {}",
+        [
+            "Index {} ist keine ganze Zahl von 1 bis {}",
+            "El índice {} no es un número entero de 1 a {}",
+            "L'indice {} n'est pas un nombre entier de 1 à {}",
+        ],
+    ),
+    (
+        "Matrix rows must all have {} elements, found {}",
+        [
+            "Alle Matrixzeilen müssen {} Elemente haben, gefunden: {}",
+            "Todas las filas de la matriz deben tener {} elementos, se encontraron {}",
+            "Toutes les lignes de la matrice doivent avoir {} éléments, {} trouvés",
+        ],
+    ),
+    (
+        "Expression contains syntax errors",
+        [
+            "Der Ausdruck enthält Syntaxfehler",
+            "La expresión contiene errores de sintaxis",
+            "L'expression contient des erreurs de syntaxe",
+        ],
+    ),
+];
+
+/// Words and phrases that are filled into messages, such as type names and arities
+const PHRASES: &[(&str, [&str; 3])] = &[
+    ("a number", ["eine Zahl", "un número", "un nombre"]),
+    ("a date", ["ein Datum", "una fecha", "une date"]),
+    ("a matrix", ["eine Matrix", "una matriz", "une matrice"]),
+    ("a vector", ["ein Vektor", "un vector", "un vecteur"]),
+    (
+        "a whole number",
+        ["eine ganze Zahl", "un número entero", "un nombre entier"],
+    ),
+    ("a fraction", ["ein Bruch", "una fracción", "une fraction"]),
+    (
+        "a number or a matrix",
+        [
+            "eine Zahl oder eine Matrix",
+            "un número o una matriz",
+            "un nombre ou une matrice",
+        ],
+    ),
+    (
+        "a number, a duration or a matrix",
+        [
+            "eine Zahl, eine Dauer oder eine Matrix",
+            "un número, una duración o una matriz",
+            "un nombre, une durée ou une matrice",
+        ],
+    ),
+    ("a duration", ["eine Dauer", "una duración", "une durée"]),
+    ("null", ["null", "nulo", "nul"]),
+    ("{} to {}", ["{} bis {}", "de {} a {}", "de {} à {}"]),
+    (
+        "at least {}",
+        ["mindestens {}", "al menos {}", "au moins {}"],
+    ),
+];
+
+/// The parts filled into a template's `{}`s to give a message, or `None` if it doesn't fit
+fn fill_ins<'m>(template: &str, message: &'m str) -> Option<Vec<&'m str>> {
+    let pieces: Vec<&str> = template.split("{}").collect();
+    let (first, pieces) = pieces.split_first()?;
+    let mut rest = message.strip_prefix(first)?;
+    let mut parts = Vec::new();
+    for (index, piece) in pieces.iter().enumerate() {
+        // The last piece ends the message, the others end at their first match
+        let end = if index + 1 == pieces.len() {
+            rest.strip_suffix(piece)?.len()
+        } else {
+            rest.find(piece)?
+        };
+        parts.push(&rest[..end]);
+        rest = &rest[end + piece.len()..];
+    }
+    rest.is_empty().then_some(parts)
+}
+
+/// Fill a template's `{}`s in order
+fn fill(template: &str, parts: &[String]) -> String {
+    let mut out = String::new();
+    let mut pieces = template.split("{}");
+    out.push_str(pieces.next().unwrap_or_default());
+    for (piece, part) in pieces.zip(parts) {
+        out.push_str(part);
+        out.push_str(piece);
+    }
+    out
+}
+
+/// A message in a catalog, translated, or `None` if the catalog doesn't have it
+fn translate(
+    catalog: &[(&str, [&'static str; 3])],
+    message: &str,
+    locale: Locale,
+) -> Option<String> {
+    catalog.iter().find_map(|(english, translations)| {
+        let parts = fill_ins(english, message)?;
+        let translated = locale.pick(translations)?;
+        let parts: Vec<String> = parts
+            .into_iter()
+            .map(|part| translate(PHRASES, part, locale).unwrap_or_else(|| part.to_string()))
+            .collect();
+        Some(fill(translated, &parts))
+    })
+}
+
+/// An English message written in a locale, or as it is if there's no translation
+///
+/// # Example
+/// ```
+/// use ast::{Locale, localize};
+///
+/// assert_eq!(
+///     localize("Undefined variable 'x'", Locale::Spanish),
+///     "Variable 'x' no definida"
+/// );
+/// assert_eq!(localize("Not in the catalog", Locale::German), "Not in the catalog");
+/// ```
+pub fn localize(message: &str, locale: Locale) -> String {
+    translate(MESSAGES, message, locale).unwrap_or_else(|| message.to_string())
+}
+
+impl Diagnostic {
+    /// The message, written in a locale
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{Locale, parse_with_recovery};
+    ///
+    /// let (_, diagnostics) = parse_with_recovery("(1 + 2");
+    /// assert_eq!(
+    ///     diagnostics[0].localized(Locale::German),
+    ///     "'(' wird nicht geschlossen (')' erwartet)"
+    /// );
+    /// ```
+    pub fn localized(&self, locale: Locale) -> String {
+        localize(&self.message, locale)
+    }
+}
+
+impl EvaluationError {
+    /// The error message, written in a locale
+    pub fn localized(&self, locale: Locale) -> String {
+        localize(&self.to_string(), locale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arity, lint, parse_with_recovery};
+
+    /// Test reading locales from language tags
+    #[test]
+    fn test_locales() {
+        assert_eq!(Locale::from_tag("de"), Some(Locale::German));
+        assert_eq!(Locale::from_tag("es-MX"), Some(Locale::Spanish));
+        assert_eq!(Locale::from_tag("fr_CA.UTF-8"), Some(Locale::French));
+        assert_eq!(Locale::from_tag("C"), Some(Locale::English));
+        assert_eq!(Locale::from_tag("ja_JP"), None);
+    }
+
+    /// Test that every message the parser, linter and evaluator write can be translated
+    #[test]
+    fn test_catalog() {
+        let (_, mut diagnostics) = parse_with_recovery("(1 = 2, ) + 3 4 + # + ) * (");
+        diagnostics.extend(parse_with_recovery("let t 1").1);
+        diagnostics.extend(parse_with_recovery("round(digits=2, x)").1);
+        diagnostics.extend(parse_with_recovery("[1]] + [2][] + [3").1);
+        diagnostics.extend(lint("(2 * 3) - -x * (1 - 1)"));
+        let mut messages: Vec<String> = diagnostics.into_iter().map(|d| d.message).collect();
+        let errors = [
+            EvaluationError::WrongArgumentCount {
+                signature: "max".into(),
+                expected: Arity { min: 1, max: None },
+                found: 0,
+            },
+            EvaluationError::TypeMismatch {
+                expected: "a number",
+                found: "a date",
+            },
+            EvaluationError::Overflow {
+                value: 1e10,
+                limit: 1e9,
+            },
+            EvaluationError::DimensionMismatch {
+                op: "+",
+                left: (2, 2),
+                right: (3, 1),
+            },
+            EvaluationError::ArgumentSizes {
+                name: "dot".into(),
+                left: (2, 1),
+                right: (3, 1),
+            },
+            EvaluationError::NotPermitted("now".to_string()),
+            EvaluationError::OutOfDomain {
+                name: "ln".into(),
+                value: -1.0,
+            },
+            EvaluationError::Timeout,
+            EvaluationError::Cancelled,
+            EvaluationError::NoInverse {
+                value: 4,
+                modulus: 12,
+            },
+            EvaluationError::UnknownParameter {
+                name: "pmt".to_string(),
+                parameter: "rates".to_string(),
+            },
+            EvaluationError::TypeMismatch {
+                expected: "a whole number",
+                found: "a fraction",
+            },
+            EvaluationError::WrongArgumentCount {
+                signature: "round(x, digits=0)".into(),
+                expected: (1..=2).into(),
+                found: 3,
+            },
+        ];
+        messages.extend(errors.iter().map(|error| error.to_string()));
+
+        for message in messages {
+            for locale in [Locale::German, Locale::Spanish, Locale::French] {
+                assert!(
+                    translate(MESSAGES, &message, locale).is_some(),
+                    "'{}' has no translation",
+                    message
+                );
+            }
+        }
+
+        assert_eq!(
+            errors[0].localized(Locale::French),
+            "La fonction 'max' prend au moins 1 arguments, en a reçu 0"
+        );
+        assert_eq!(
+            errors[1].localized(Locale::German),
+            "Erwartet: eine Zahl, gefunden: ein Datum"
+        );
+        assert_eq!(
+            errors[errors.len() - 1].localized(Locale::German),
+            "Funktion 'round(x, digits=0)' erwartet 1 bis 2 Argumente, erhielt 3"
+        );
+        assert_eq!(
+            localize(
+                "'=' is not an operator, did you mean '=='?",
+                Locale::Spanish
+            ),
+            "'=' no es un operador, ¿quería decir '=='?"
+        );
+        assert_eq!(errors[2].localized(Locale::English), errors[2].to_string());
+        assert_eq!(
+            errors[3].localized(Locale::Spanish),
+            "No se puede usar '+' con una matriz de 2×2 y una de 3×1"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Writing expressions as LaTeX
+//!
+//! [`Expr::to_latex`] typesets a formula the way it would be written by hand:
+//! divisions as fractions, `sqrt` as a radical, `pow` as a superscript and
+//! `piecewise` as cases. Notebooks and documentation can show it with MathJax
+//! or KaTeX.
+
+use crate::{Comparison, Expr};
+
+/// How tightly each kind of node binds; higher binds tighter
+const OR: u8 = 1;
+const AND: u8 = 2;
+const COMPARISON: u8 = 3;
+const SUM: u8 = 4;
+const PRODUCT: u8 = 5;
+const UNARY: u8 = 6;
+const ATOM: u8 = 7;
+
+/// Names written as Greek letters
+const GREEK: &[&str] = &[
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "phi", "chi", "psi",
+    "omega", "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Phi", "Psi", "Omega",
+];
+
+/// Functions LaTeX has an operator for, such as `\sin`
+const OPERATORS: &[&str] = &[
+    "sin", "cos", "tan", "sinh", "cosh", "tanh", "exp", "ln", "log", "min", "max", "arg", "det",
+];
+
+impl Expr {
+    /// Write this expression as LaTeX math, without the surrounding `$`
+    ///
+    /// Multi-letter names are written in italics, and names of Greek letters
+    /// as the letter; a suffix after `_` becomes a subscript. Functions
+    /// LaTeX has no notation for are written as `\operatorname{name}`.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("sqrt(x_1 * x_1 + 1) / (2 * pi) <= theta").unwrap();
+    /// assert_eq!(
+    ///     ast.to_latex(),
+    ///     r"\frac{\sqrt{x_{1} \cdot x_{1} + 1}}{2 \cdot \pi} \le \theta"
+    /// );
+    /// ```
+    pub fn to_latex(&self) -> String {
+        latex(self, 0)
+    }
+}
+
+/// An expression as LaTeX, parenthesized if it binds less tightly than `context`
+fn latex(expr: &Expr, context: u8) -> String {
+    let (text, precedence) = match expr {
+        Expr::Float(value) => number(*value),
+        Expr::Var(name) => (variable(name), ATOM),
+        Expr::CellRef(cell) => (format!(r"\mathrm{{{}}}", cell), ATOM),
+        Expr::CellRange(from, to) => (format!(r"\mathrm{{{}{{:}}{}}}", from, to), ATOM),
+        Expr::Error(_) => (r"\text{?}".to_string(), ATOM),
+        Expr::Add(left, right) => binary(left, "+", right, SUM),
+        Expr::Sub(left, right) => binary(left, "-", right, SUM),
+        Expr::Mul(left, right) => binary(left, r"\cdot", right, PRODUCT),
+        Expr::Div(left, right) => (
+            format!(r"\frac{{{}}}{{{}}}", latex(left, 0), latex(right, 0)),
+            ATOM,
+        ),
+        Expr::Neg(inner) => (format!("-{}", latex(inner, UNARY + 1)), UNARY),
+        Expr::Not(inner) => (format!(r"\lnot {}", latex(inner, UNARY)), UNARY),
+        Expr::Compare(comparison, left, right) => {
+            let symbol = match comparison {
+                Comparison::Equal => "=",
+                Comparison::NotEqual => r"\ne",
+                Comparison::Less => "<",
+                Comparison::LessOrEqual => r"\le",
+                Comparison::Greater => ">",
+                Comparison::GreaterOrEqual => r"\ge",
+            };
+            binary(left, symbol, right, COMPARISON)
+        }
+        Expr::And(left, right) => binary(left, r"\land", right, AND),
+        Expr::Or(left, right) => binary(left, r"\lor", right, OR),
+        Expr::Call(name, args) => call(name, args),
+        Expr::Named(name, value) => (
+            format!(
+                r"\mathrm{{{}}} = {}",
+                name.replace('_', r"\_"),
+                latex(value, 0)
+            ),
+            0,
+        ),
+        Expr::Let(name, value, body) => (
+            format!(
+                r"\text{{let }} {} = {} \text{{ in }} {}",
+                variable(name),
+                latex(value, 0),
+                latex(body, 0)
+            ),
+            0,
+        ),
+        Expr::Piecewise(cases, default) => {
+            let mut rows: Vec<String> = cases
+                .iter()
+                .map(|(condition, value)| {
+                    format!(
+                        r"{} & \text{{if }} {}",
+                        latex(value, 0),
+                        latex(condition, 0)
+                    )
+                })
+                .collect();
+            if let Some(default) = default {
+                rows.push(format!(r"{} & \text{{otherwise}}", latex(default, 0)));
+            }
+            (
+                format!(r"\begin{{cases}} {} \end{{cases}}", rows.join(r" \\ ")),
+                ATOM,
+            )
+        }
+        Expr::List(elements) => (list(elements), ATOM),
+        Expr::Index(value, indices) => {
+            let indices: Vec<String> = indices.iter().map(|index| latex(index, 0)).collect();
+            (
+                format!("{}_{{{}}}", latex(value, ATOM), indices.join(", ")),
+                ATOM,
+            )
+        }
+    };
+    if precedence < context {
+        format!(r"\left({}\right)", text)
+    } else {
+        text
+    }
+}
+
+/// Two operands with an operator between them, grouped to the left
+///
+/// A negative right operand of arithmetic is parenthesized, as in `a \cdot (-b)`.
+fn binary(left: &Expr, symbol: &str, right: &Expr, precedence: u8) -> (String, u8) {
+    let left = latex(left, precedence);
+    let mut right = latex(right, precedence + 1);
+    if precedence >= SUM && right.starts_with('-') {
+        right = format!(r"\left({}\right)", right);
+    }
+    (format!("{} {} {}", left, symbol, right), precedence)
+}
+
+/// A number, with exponents written as powers of ten
+fn number(value: f64) -> (String, u8) {
+    let text = match value {
+        _ if value.is_nan() => return (r"\mathrm{NaN}".to_string(), ATOM),
+        f64::INFINITY => return (r"\infty".to_string(), ATOM),
+        f64::NEG_INFINITY => return (r"-\infty".to_string(), UNARY),
+        _ => format!("{:?}", value),
+    };
+    let text = match text.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let mantissa = mantissa.trim_end_matches(".0");
+            format!(r"{} \times 10^{{{}}}", mantissa, exponent)
+        }
+        None => text.trim_end_matches(".0").to_string(),
+    };
+    let precedence = if value.is_sign_negative() {
+        UNARY
+    } else {
+        ATOM
+    };
+    (text, precedence)
+}
+
+/// A variable's name, with a subscript for what follows the first `_`
+fn variable(name: &str) -> String {
+    let (base, subscript) = match name.split_once('_') {
+        Some((base, subscript)) if !base.is_empty() && !subscript.is_empty() => {
+            (base, Some(subscript))
+        }
+        _ => (name, None),
+    };
+    let base = if GREEK.contains(&base) {
+        format!(r"\{}", base)
+    } else if base.chars().count() == 1 {
+        base.to_string()
+    } else {
+        format!(r"\mathit{{{}}}", base.replace('_', r"\_"))
+    };
+    match subscript {
+        Some(subscript) => format!("{}_{{{}}}", base, subscript.replace('_', r"\_")),
+        None => base,
+    }
+}
+
+/// A function call, in the usual notation where there is one
+fn call(name: &str, args: &[Expr]) -> (String, u8) {
+    let arg = |index: usize| latex(&args[index], 0);
+    let text = match (name, args.len()) {
+        ("sqrt", 1) => format!(r"\sqrt{{{}}}", arg(0)),
+        ("cbrt", 1) => format!(r"\sqrt[3]{{{}}}", arg(0)),
+        ("abs", 1) => format!(r"\left|{}\right|", arg(0)),
+        ("floor", 1) => format!(r"\left\lfloor {} \right\rfloor", arg(0)),
+        ("ceil", 1) => format!(r"\left\lceil {} \right\rceil", arg(0)),
+        ("pow", 2) => {
+            return (format!("{}^{{{}}}", latex(&args[0], ATOM), arg(1)), ATOM);
+        }
+        ("log10", 1) => format!(r"\log_{{10}}\left({}\right)", arg(0)),
+        ("log2", 1) => format!(r"\log_{{2}}\left({}\right)", arg(0)),
+        _ => {
+            let function = match name.strip_prefix('a') {
+                Some(inverse @ ("sin" | "cos" | "tan")) => format!(r"\arc{}", inverse),
+                _ if OPERATORS.contains(&name) => format!(r"\{}", name),
+                _ => format!(r"\operatorname{{{}}}", name.replace('_', r"\_")),
+            };
+            let args: Vec<String> = (0..args.len()).map(arg).collect();
+            format!(r"{}\left({}\right)", function, args.join(", "))
+        }
+    };
+    (text, ATOM)
+}
+
+/// A list, as a matrix when every element is a list of the same length
+fn list(elements: &[Expr]) -> String {
+    let rows: Option<Vec<&Vec<Expr>>> = elements
+        .iter()
+        .map(|element| match element {
+            Expr::List(row) => Some(row),
+            _ => None,
+        })
+        .collect();
+    match rows {
+        Some(rows)
+            if !rows.is_empty()
+                && !rows[0].is_empty()
+                && rows.iter().all(|row| row.len() == rows[0].len()) =>
+        {
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|row| {
+                    let cells: Vec<String> = row.iter().map(|cell| latex(cell, 0)).collect();
+                    cells.join(" & ")
+                })
+                .collect();
+            format!(r"\begin{{bmatrix}} {} \end{{bmatrix}}", rows.join(r" \\ "))
+        }
+        _ => {
+            let elements: Vec<String> = elements.iter().map(|element| latex(element, 0)).collect();
+            format!(r"\left[{}\right]", elements.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_expression;
+
+    /// The LaTeX for an expression that must parse
+    fn tex(input: &str) -> String {
+        parse_expression(input).unwrap().1.to_latex()
+    }
+
+    /// Test operators and where parentheses are needed
+    #[test]
+    fn test_operators() {
+        assert_eq!(tex("a - (b - c)"), r"a - \left(b - c\right)");
+        assert_eq!(tex("(a - b) - c"), "a - b - c");
+        assert_eq!(tex("-(a + b) * c"), r"-\left(a + b\right) \cdot c");
+        assert_eq!(tex("(a + b) / -c"), r"\frac{a + b}{-c}");
+        assert_eq!(tex("x * -2.5"), r"x \cdot \left(-2.5\right)");
+        assert_eq!(
+            tex("!(a < 1) && b != 2 || c >= 3"),
+            r"\lnot \left(a < 1\right) \land b \ne 2 \lor c \ge 3"
+        );
+        assert_eq!(
+            tex("1e300 + 0.5e-9 + 1"),
+            r"1 \times 10^{300} + 5 \times 10^{-10} + 1"
+        );
+        assert_eq!(tex("inf"), r"\infty");
+    }
+
+    /// Test names, functions and the other kinds of node
+    #[test]
+    fn test_notation() {
+        assert_eq!(tex("rate_max * omega"), r"\mathit{rate}_{max} \cdot \omega");
+        assert_eq!(
+            tex("pow(a + b, 2) + asin(x) + abs(y) + f(x, 1)"),
+            r"\left(a + b\right)^{2} + \arcsin\left(x\right) + \left|y\right| + \operatorname{f}\left(x, 1\right)"
+        );
+        assert_eq!(
+            tex("piecewise((x < 0, -x), x)"),
+            r"\begin{cases} -x & \text{if } x < 0 \\ x & \text{otherwise} \end{cases}"
+        );
+        assert_eq!(
+            tex("[[1, 2], [3, 4]][2, 1] + [1, 2][1]"),
+            r"\begin{bmatrix} 1 & 2 \\ 3 & 4 \end{bmatrix}_{2, 1} + \left[1, 2\right]_{1}"
+        );
+        assert_eq!(
+            tex("let t = a + 1 in t * t"),
+            r"\text{let } t = a + 1 \text{ in } t \cdot t"
+        );
+    }
+}
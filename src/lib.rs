@@ -15,18 +15,342 @@
 //! // The AST structure is: Add(Float(3.0), Mul(Float(4.0), Float(2.0)))
 //! ```
 
+use std::collections::BTreeSet;
+
 use nom::{
-    IResult,
-    character::complete::{char, multispace0},
+    IResult, Parser as _,
+    bytes::complete::take_while,
+    character::complete::{char, multispace0, satisfy},
+    combinator::recognize,
+    error::ErrorKind,
     number::complete::double,
+    sequence::pair,
 };
 use thiserror::Error;
 
+mod audit;
+#[cfg(feature = "bigint")]
+mod bigint;
+mod binary;
+mod cache;
+mod cancel;
+mod canonical;
+mod cells;
+mod codegen;
+mod codes;
+mod concrete;
+mod config;
+mod constant;
+mod corpus;
+mod custom;
+mod datetime;
+mod derivation;
+mod diagnostics;
+mod dialect;
+mod diff;
+mod differential;
+mod engine;
+mod environment;
+mod equivalence;
+mod evaluator;
+#[cfg(feature = "excel")]
+mod excel;
+mod explain;
+#[cfg(feature = "finance")]
+mod finance;
+mod format;
+mod functions;
+mod grammar;
+mod graph;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod integer;
+mod ir;
+#[cfg(feature = "l10n")]
+mod l10n;
+mod latex;
+mod lint;
+mod matrix;
+mod metrics;
+mod modular;
+mod monte_carlo;
+mod observer;
+mod parser;
+mod paths;
+#[cfg(feature = "pratt")]
+mod pratt;
+mod prelude;
+mod profile;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod provenance;
+mod recovery;
+mod rules;
+mod sensitivity;
+mod session;
+mod shader;
+mod si;
+mod snapshot;
+mod sql;
+mod stats;
+mod store;
+mod streaming;
+mod table;
+mod trace;
+mod transform;
+mod uncertainty;
+mod value;
+
+pub use audit::{AuditEvent, AuditLog};
+#[cfg(feature = "bigint")]
+pub use bigint::{BigInt, MAX_BIGINT_BITS, evaluate_big};
+pub use binary::{BinaryError, MAX_DECODED_NODES};
+pub use cache::{CacheStats, FormulaCache};
+pub use cancel::CancellationToken;
+pub use canonical::format;
+pub use cells::{CellRef, CellResolver};
+pub use codegen::CodegenError;
+pub use codes::explain_code;
+pub use concrete::{SyntaxTree, Token, TokenKind};
+pub use config::{
+    DivisionPolicy, DomainPolicy, EvalConfig, GrammarVersion, NullPolicy, OverflowPolicy,
+    ParserConfig, SubnormalPolicy,
+};
+pub use constant::{CONST_STACK_SIZE, ConstError, ConstExpr, ConstOp};
+pub use corpus::Corpus;
+pub use custom::CustomValue;
+pub use datetime::{DateTime, Duration};
+pub use derivation::{ParseStep, ParseTrace, parse_traced};
+pub use diagnostics::{Diagnostic, Severity, Span};
+pub use dialect::{Dialect, parse_dialect};
+pub use diff::{Change, ExprDiff, diff};
+pub use differential::{Backend, DifferentialConfig, Divergence, compare_backends, compare_expr};
+pub use engine::{Engine, EngineBuilder, Formula};
+pub use environment::Environment;
+pub use equivalence::{Counterexample, probably_equivalent};
+pub use evaluator::Evaluator;
+pub use explain::explain;
+pub use format::{Notation, NumberFormat};
+pub use functions::{AngleUnit, Arity, Capabilities, Function, FunctionRegistry, Parameter};
+pub use grammar::grammar_ebnf;
+pub use graph::{ExprGraph, GraphNode};
+#[cfg(feature = "grpc")]
+pub use grpc::{
+    CalculatorError, CalculatorService, EvalFormula, EvalOutcome, EvalRequest, EvalResponse,
+    ParseOutcome, ParseRequest, ParseResponse, RequestError,
+};
+pub use integer::{Number, evaluate_checked};
+pub use ir::{Instruction, Label, Operand, Temp, execute, execute_cancellable, lower};
+#[cfg(feature = "l10n")]
+pub use l10n::{Locale, localize};
+pub use lint::lint;
+pub use matrix::Matrix;
+pub use metrics::{Counter, Histogram, MetricsSink};
+pub use modular::evaluate_mod;
+pub use monte_carlo::{Distribution, MonteCarloSummary, monte_carlo};
+pub use observer::{EngineEvent, EngineObserver, Stage};
+pub use parser::Parser;
+pub use paths::{ChildIndex, Subexpressions};
+#[cfg(feature = "pratt")]
+pub use pratt::parse_pratt;
+pub use prelude::Prelude;
+pub use profile::{Profile, ProfileEntry, profile};
+#[cfg(feature = "protobuf")]
+pub use protobuf::{PROTO_SCHEMA, ProtobufError};
+pub use provenance::Provenance;
+pub use recovery::{parse_with_recovery, parse_with_spans};
+pub use rules::{Rule, RuleError, RuleOutcome, RuleReport, RuleResult, RuleSet};
+pub use sensitivity::{Sensitivity, sensitivities, sensitivity};
+pub use session::{Assignee, Session, SessionError};
+pub use snapshot::{SNAPSHOT_VERSION, UPDATE_GOLDEN_VAR, check_golden, snapshot_of};
+pub use sql::SqlDialect;
+pub use store::{FormulaProblem, FormulaStore, FormulaVersion, StoreError};
+pub use streaming::{ParseEvalError, parse_and_eval};
+pub use table::{DEFAULT_MAX_ROWS, Domains, EnumerationError, TableRow, enumerate, tabulate};
+pub use trace::TracedError;
+pub use uncertainty::{Uncertain, evaluate_uncertain};
+pub use value::Value;
+
 /// Errors that can occur during expression evaluation
 #[derive(Error, Debug)]
 pub enum EvaluationError {
     #[error("Division by zero")]
     DivisionByZero,
+
+    /// A variable isn't defined in the environment
+    #[error("Undefined variable '{0}'")]
+    UndefinedVariable(String),
+
+    /// A spreadsheet cell has no value, or there's no [`CellResolver`] to ask
+    #[error("Cell {0} has no value")]
+    UnresolvedCell(CellRef),
+
+    /// A range of cells was used where a single number is needed
+    #[error("Cell range {0}:{1} can't be used as a number")]
+    RangeNotAllowed(CellRef, CellRef),
+
+    /// A function call names a function the [`FunctionRegistry`] doesn't have
+    #[error("Unknown function '{0}'")]
+    UnknownFunction(String),
+
+    /// A function call needs a capability the [`FunctionRegistry`] denies, see [`FunctionRegistry::deny`]
+    #[error("Function '{0}' isn't allowed here")]
+    NotPermitted(String),
+
+    /// A function was called with the wrong number of arguments
+    #[error("Function '{signature}' takes {expected} arguments, got {found}")]
+    WrongArgumentCount {
+        /// How the function is called, with its name as written in the call:
+        /// just the name, or with its parameters if they're named, as in
+        /// `round(x, digits=0)`, see [`Function::signature`]. Boxed to keep
+        /// errors small.
+        signature: Box<str>,
+        /// How many arguments the function accepts
+        expected: Arity,
+        /// How many arguments the call has
+        found: usize,
+    },
+
+    /// A call named an argument that isn't one of the function's parameters
+    #[error("Function '{name}' has no parameter named '{parameter}'")]
+    UnknownParameter {
+        /// The function's name, as written in the call
+        name: String,
+        /// The name given to the argument
+        parameter: String,
+    },
+
+    /// A call gave the same parameter twice, by position and by name or twice by name
+    #[error("Function '{name}' was given '{parameter}' more than once")]
+    DuplicateArgument {
+        /// The function's name, as written in the call
+        name: String,
+        /// The parameter given twice
+        parameter: String,
+    },
+
+    /// A call named a later argument but left out this one
+    #[error("Function '{name}' is missing the argument '{parameter}'")]
+    MissingArgument {
+        /// The function's name, as written in the call
+        name: String,
+        /// The parameter left out
+        parameter: String,
+    },
+
+    /// A function's argument is outside the values it's defined for, e.g. `sqrt(-1)`
+    #[error("Argument out of range for '{0}'")]
+    DomainError(String),
+
+    /// A math function's argument is outside its domain, e.g. `sqrt(-1)`, with the argument's value
+    ///
+    /// [`Evaluator::evaluate_traced`] finds the call that failed, and
+    /// [`DomainPolicy::Nan`] gives NaN instead.
+    #[error("Argument {value} out of range for '{name}'")]
+    OutOfDomain {
+        /// The function name, boxed to keep errors small
+        name: Box<str>,
+        /// The argument that's out of range
+        value: f64,
+    },
+
+    /// A value of the wrong type was used, e.g. a date where a number is needed
+    #[error("Expected {expected}, found {found}")]
+    TypeMismatch {
+        /// What was needed
+        expected: &'static str,
+        /// What was found instead
+        found: &'static str,
+    },
+
+    /// An operator was used with values it doesn't work on, e.g. adding two dates
+    #[error("Can't use '{op}' with {left} and {right}")]
+    UnsupportedOperands {
+        /// The operator
+        op: &'static str,
+        /// The type of the left operand
+        left: &'static str,
+        /// The type of the right operand
+        right: &'static str,
+    },
+
+    /// A result is beyond [`EvalConfig::max_magnitude`], or an integer from [`evaluate_checked`] beyond 64 bits
+    #[error("Result {value} is beyond the limit of {limit}")]
+    Overflow {
+        /// The result
+        value: f64,
+        /// The largest magnitude allowed
+        limit: f64,
+    },
+
+    /// A result is subnormal, and [`SubnormalPolicy::Error`] is in effect
+    #[error("Result {0} is too close to zero to be represented accurately")]
+    Subnormal(f64),
+
+    /// The tree contains [`Expr::Error`] placeholders from recovery parsing
+    #[error("Expression contains syntax errors")]
+    ContainsErrors,
+
+    /// Calls to session functions nested deeper than [`EvalConfig::max_call_depth`]
+    #[error("Function calls nested more than {0} deep")]
+    RecursionLimit(usize),
+
+    /// The evaluation ran past its deadline, see [`evaluate_with_deadline`]
+    #[error("Evaluation ran past its deadline")]
+    Timeout,
+
+    /// The evaluation was stopped through a [`CancellationToken`]
+    #[error("Evaluation was cancelled")]
+    Cancelled,
+
+    /// Matrices of the wrong sizes were combined, e.g. adding a 2×2 and a 3×3 matrix
+    #[error("Can't use '{op}' with a {}×{} and a {}×{} matrix", .left.0, .left.1, .right.0, .right.1)]
+    DimensionMismatch {
+        /// The operator
+        op: &'static str,
+        /// The rows and columns of the left operand
+        left: (usize, usize),
+        /// The rows and columns of the right operand
+        right: (usize, usize),
+    },
+
+    /// An index isn't a whole number from 1 to the number of elements (or rows)
+    #[error("Index {index} isn't a whole number from 1 to {length}")]
+    IndexOutOfRange {
+        /// The index
+        index: f64,
+        /// How many elements (or rows) there are
+        length: usize,
+    },
+
+    /// A division in [`evaluate_mod`] by a number that shares a factor with the modulus
+    #[error("{value} has no inverse modulo {modulus}")]
+    NoInverse {
+        /// The divisor, as a residue
+        value: u64,
+        /// The modulus
+        modulus: u64,
+    },
+
+    /// The rows of a matrix literal have different lengths
+    #[error("Matrix rows must all have {expected} elements, found {found}")]
+    RaggedRows {
+        /// The length of the first row
+        expected: usize,
+        /// The length of a row that differs
+        found: usize,
+    },
+
+    /// A function got matrices whose sizes don't fit together, e.g. `dot` with vectors of different lengths
+    #[error("Function '{name}' can't take a {}×{} and a {}×{} matrix", .left.0, .left.1, .right.0, .right.1)]
+    ArgumentSizes {
+        /// The function name, boxed to keep errors (and the evaluator's stack frames) small
+        name: Box<str>,
+        /// The rows and columns of one argument
+        left: (usize, usize),
+        /// The rows and columns of another argument
+        right: (usize, usize),
+    },
 }
 
 /// Abstract Syntax Tree representation of mathematical expressions
@@ -34,42 +358,577 @@ pub enum EvaluationError {
 /// This enum represents the structure of mathematical expressions as a tree,
 /// where each node is either a value or an operation with child nodes.
 /// Operations are stored as boxed expressions to allow for nested structures.
-#[derive(Debug, PartialEq, Clone)]
-pub enum Expr {
+///
+/// Names of variables and functions are [`String`]s, unless the tree was
+/// parsed by [`parse_borrowed`], which leaves them as `&str`s borrowing from
+/// the input.
+#[derive(Debug)]
+pub enum Expr<S = String> {
     /// A floating-point numeric literal
     ///
     /// Examples: `42.0`, `-3.14`, `0.5`
     Float(f64),
 
+    /// A variable, looked up in the [`Environment`] during evaluation
+    ///
+    /// Examples: `x`, `width`, `rate_2`
+    Var(S),
+
+    /// A spreadsheet cell reference, such as `B3`
+    ///
+    /// Only produced when [`ParserConfig::cell_references`] is enabled. The
+    /// value comes from the [`CellResolver`] given to the [`Evaluator`].
+    CellRef(CellRef),
+
+    /// A rectangular range of spreadsheet cells, such as `B2:B10`
+    ///
+    /// Only produced when [`ParserConfig::cell_references`] is enabled.
+    CellRange(CellRef, CellRef),
+
     /// Addition operation: left + right
     ///
     /// Represents the sum of two expressions. Both operands are evaluated
     /// and their results are added together.
-    Add(Box<Expr>, Box<Expr>),
+    Add(Box<Expr<S>>, Box<Expr<S>>),
 
     /// Subtraction operation: left - right
     ///
     /// Represents the difference between two expressions. The right operand
     /// is subtracted from the left operand.
-    Sub(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr<S>>, Box<Expr<S>>),
 
     /// Multiplication operation: left * right
     ///
     /// Represents the product of two expressions. Both operands are evaluated
     /// and their results are multiplied together.
-    Mul(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr<S>>, Box<Expr<S>>),
 
     /// Division operation: left / right
     ///
     /// Represents the quotient of two expressions. The left operand is divided
     /// by the right operand. Division by zero will result in an evaluation error.
-    Div(Box<Expr>, Box<Expr>),
+    Div(Box<Expr<S>>, Box<Expr<S>>),
 
     /// Negation operation: -expr
     ///
     /// Represents the negation of an expression (unary minus).
     /// Example: `-x` or `-(2 / 1)`
-    Neg(Box<Expr>),
+    Neg(Box<Expr<S>>),
+
+    /// Comparison operation: left == right, left < right, ...
+    ///
+    /// Evaluates to 1 when the comparison holds and 0 otherwise.
+    Compare(Comparison, Box<Expr<S>>, Box<Expr<S>>),
+
+    /// Logical and: left && right
+    ///
+    /// Evaluates to 1 if both operands are nonzero, otherwise 0. The right
+    /// operand is only evaluated when the left one is nonzero, unless
+    /// [`EvalConfig::strict_evaluation`] is set.
+    And(Box<Expr<S>>, Box<Expr<S>>),
+
+    /// Logical or: left || right
+    ///
+    /// Evaluates to 1 if either operand is nonzero, otherwise 0. The right
+    /// operand is only evaluated when the left one is zero, unless
+    /// [`EvalConfig::strict_evaluation`] is set.
+    Or(Box<Expr<S>>, Box<Expr<S>>),
+
+    /// Logical not: !expr
+    ///
+    /// Evaluates to 1 if the operand is 0, otherwise 0.
+    Not(Box<Expr<S>>),
+
+    /// A function call: name(arguments)
+    ///
+    /// The function is looked up by name in the [`Evaluator`]'s
+    /// [`FunctionRegistry`] during evaluation.
+    /// Examples: `sqrt(2)`, `max(a, b, c)`, `SUM(A1:A10)`
+    Call(S, Vec<Expr<S>>),
+
+    /// An argument given by the name of its parameter: name=value
+    ///
+    /// Only found among the arguments of a [`Expr::Call`], after any
+    /// positional ones. The call puts it in its parameter's place, so the
+    /// function must have named parameters, see
+    /// [`FunctionRegistry::set_parameters`].
+    /// Example: the second argument of `pmt(0.05 / 12, nper=360, pv=100000)`
+    Named(S, Box<Expr<S>>),
+
+    /// A local variable: let name = value in body
+    ///
+    /// The value is evaluated once, and the body is evaluated with the name
+    /// set to it, hiding any variable of the same name. The name is only
+    /// visible in the body. Example: `let t = a + b in t * t`
+    Let(S, Box<Expr<S>>, Box<Expr<S>>),
+
+    /// A piecewise definition: piecewise((condition, value), ..., default)
+    ///
+    /// The conditions are tried in order, and the value of the first nonzero
+    /// one is the result. Only that condition's value, and the conditions
+    /// before it, are evaluated. If none holds, the result is the default, or
+    /// a domain error without one.
+    /// Example: `piecewise((x < 0, -x), (x >= 0, x))`
+    Piecewise(Vec<(Expr<S>, Expr<S>)>, Option<Box<Expr<S>>>),
+
+    /// A list: [a, b, c]
+    ///
+    /// Evaluates to a vector of numbers, or to a [`Matrix`] when the elements
+    /// are vectors of the same length, one per row.
+    /// Example: `[[1, 2], [3, 4]]`
+    List(Vec<Expr<S>>),
+
+    /// Element access: value[index] or value[row, column]
+    ///
+    /// Indices start at 1. A single index into a matrix that isn't a vector
+    /// gives a whole row. Example: `[[1, 2], [3, 4]][2, 1]` is 3.
+    Index(Box<Expr<S>>, Vec<Expr<S>>),
+
+    /// Placeholder for input that could not be parsed
+    ///
+    /// Only produced by [`parse_with_recovery`]. The span points at the
+    /// offending input (or is zero-width where something was missing).
+    Error(Span),
+}
+
+impl Expr {
+    /// The direct children of this node, left to right
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, ast) = parse_expression("1 + 2 * 3").unwrap();
+    /// let children = ast.children();
+    /// assert_eq!(children[0], &Expr::Float(1.0));
+    /// assert!(matches!(children[1], Expr::Mul(_, _)));
+    /// ```
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => vec![],
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right) => vec![left, right],
+            Expr::Neg(inner) | Expr::Not(inner) | Expr::Named(_, inner) => vec![inner],
+            Expr::Call(_, args) => args.iter().collect(),
+            Expr::Let(_, value, body) => vec![value, body],
+            Expr::Piecewise(cases, default) => cases
+                .iter()
+                .flat_map(|(condition, value)| [condition, value])
+                .chain(default.as_deref())
+                .collect(),
+            Expr::List(elements) => elements.iter().collect(),
+            Expr::Index(target, indices) => {
+                std::iter::once(target.as_ref()).chain(indices).collect()
+            }
+        }
+    }
+
+    /// This tree with every [`Expr::Let`] replaced by its body, with the value written in for the name
+    ///
+    /// Inner `let`s are replaced first, so that a value is never written
+    /// inside a `let` that would hide the variables it uses.
+    pub(crate) fn inline_lets(&self) -> Expr {
+        let inline = |expr: &Expr| Box::new(expr.inline_lets());
+        match self {
+            Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => self.clone(),
+            Expr::Add(left, right) => Expr::Add(inline(left), inline(right)),
+            Expr::Sub(left, right) => Expr::Sub(inline(left), inline(right)),
+            Expr::Mul(left, right) => Expr::Mul(inline(left), inline(right)),
+            Expr::Div(left, right) => Expr::Div(inline(left), inline(right)),
+            Expr::Neg(inner) => Expr::Neg(inline(inner)),
+            Expr::Compare(comparison, left, right) => {
+                Expr::Compare(*comparison, inline(left), inline(right))
+            }
+            Expr::And(left, right) => Expr::And(inline(left), inline(right)),
+            Expr::Or(left, right) => Expr::Or(inline(left), inline(right)),
+            Expr::Not(inner) => Expr::Not(inline(inner)),
+            Expr::Call(name, args) => {
+                Expr::Call(name.clone(), args.iter().map(Expr::inline_lets).collect())
+            }
+            Expr::Named(name, value) => Expr::Named(name.clone(), inline(value)),
+            Expr::Let(name, value, body) => {
+                let mut body = body.inline_lets();
+                body.replace_var(name, &value.inline_lets());
+                body
+            }
+            Expr::Piecewise(cases, default) => Expr::Piecewise(
+                cases
+                    .iter()
+                    .map(|(condition, value)| (condition.inline_lets(), value.inline_lets()))
+                    .collect(),
+                default.as_deref().map(inline),
+            ),
+            Expr::List(elements) => Expr::List(elements.iter().map(Expr::inline_lets).collect()),
+            Expr::Index(target, indices) => Expr::Index(
+                inline(target),
+                indices.iter().map(Expr::inline_lets).collect(),
+            ),
+        }
+    }
+
+    /// Replace every use of a variable, in a tree without [`Expr::Let`]s
+    fn replace_var(&mut self, name: &str, value: &Expr) {
+        match self {
+            Expr::Var(var) if var == name => *self = value.clone(),
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right) => {
+                left.replace_var(name, value);
+                right.replace_var(name, value);
+            }
+            Expr::Neg(inner) | Expr::Not(inner) | Expr::Named(_, inner) => {
+                inner.replace_var(name, value)
+            }
+            Expr::Call(_, args) | Expr::List(args) => {
+                args.iter_mut().for_each(|arg| arg.replace_var(name, value))
+            }
+            Expr::Index(target, indices) => {
+                target.replace_var(name, value);
+                indices
+                    .iter_mut()
+                    .for_each(|index| index.replace_var(name, value));
+            }
+            Expr::Piecewise(cases, default) => {
+                for (condition, result) in cases {
+                    condition.replace_var(name, value);
+                    result.replace_var(name, value);
+                }
+                if let Some(default) = default {
+                    default.replace_var(name, value);
+                }
+            }
+            Expr::Let(..)
+            | Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => {}
+        }
+    }
+
+    /// Whether this tree contains any [`Expr::Error`] placeholders
+    pub fn has_errors(&self) -> bool {
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            if let Expr::Error(_) = expr {
+                return true;
+            }
+            pending.extend(expr.children());
+        }
+        false
+    }
+
+    /// The names of all variables used in this tree, sorted and without duplicates
+    ///
+    /// Names bound by [`Expr::Let`] aren't included where they refer to the
+    /// local variable.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("y * x + x").unwrap();
+    /// assert_eq!(ast.variables().into_iter().collect::<Vec<_>>(), vec!["x", "y"]);
+    ///
+    /// let (_, ast) = parse_expression("let t = t + 1 in t * u").unwrap();
+    /// assert_eq!(ast.variables().into_iter().collect::<Vec<_>>(), vec!["t", "u"]);
+    /// ```
+    pub fn variables(&self) -> BTreeSet<&str> {
+        let mut names = BTreeSet::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+    /// Helper for [`Expr::variables`], only recursing into `let` bodies
+    fn collect_variables<'a>(&'a self, names: &mut BTreeSet<&'a str>) {
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            match expr {
+                Expr::Var(name) => {
+                    names.insert(name);
+                }
+                Expr::Let(name, value, body) => {
+                    pending.push(value);
+                    let mut inside = body.variables();
+                    inside.remove(name.as_str());
+                    names.extend(inside);
+                }
+                _ => pending.extend(expr.children()),
+            }
+        }
+    }
+}
+
+impl<S> Expr<S> {
+    /// Call `f` with each of the node's own children, in order
+    pub(crate) fn each_child_mut(&mut self, mut f: impl FnMut(&mut Expr<S>)) {
+        match self {
+            Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => {}
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right)
+            | Expr::Let(_, left, right) => {
+                f(left);
+                f(right);
+            }
+            Expr::Neg(inner) | Expr::Not(inner) | Expr::Named(_, inner) => f(inner),
+            Expr::Call(_, args) | Expr::List(args) => args.iter_mut().for_each(f),
+            Expr::Piecewise(cases, default) => {
+                for (condition, value) in cases {
+                    f(condition);
+                    f(value);
+                }
+                if let Some(default) = default {
+                    f(default);
+                }
+            }
+            Expr::Index(target, indices) => {
+                f(target);
+                indices.iter_mut().for_each(f);
+            }
+        }
+    }
+
+    /// Move the children that have children of their own onto `stack`, leaving leaves behind
+    ///
+    /// Dropping a tree this way, rather than recursively, keeps long chains
+    /// like `1 + 1 + … + 1` from overflowing the stack.
+    fn detach_children(&mut self, stack: &mut Vec<Expr<S>>) {
+        self.each_child_mut(|child| {
+            let leaf = matches!(
+                child,
+                Expr::Float(_)
+                    | Expr::Var(_)
+                    | Expr::CellRef(_)
+                    | Expr::CellRange(..)
+                    | Expr::Error(_)
+            );
+            if !leaf {
+                stack.push(std::mem::replace(child, Expr::Float(0.0)));
+            }
+        });
+    }
+}
+
+/// Trees are dropped one node at a time rather than recursively, so that
+/// dropping a long chain doesn't overflow the stack
+impl<S> Drop for Expr<S> {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.detach_children(&mut stack);
+        while let Some(mut node) = stack.pop() {
+            node.detach_children(&mut stack);
+        }
+    }
+}
+
+impl Expr<&str> {
+    /// A copy of this tree that owns its names, as from [`parse_expression_with`]
+    ///
+    /// Long chains of binary operators are copied without recursing down them.
+    pub fn to_owned(&self) -> Expr {
+        self.map_names(&|name: &&str| name.to_string())
+    }
+}
+
+impl<S> Expr<S> {
+    /// The binary operations down the left of this tree, outermost first, and
+    /// the operand at the bottom of them
+    ///
+    /// A chain like `1 + 1 + … + 1` is the left spine of its tree, so passes
+    /// that follow the spine in a loop, and only recurse into the other
+    /// operands, handle chains of any length.
+    pub(crate) fn left_spine(&self) -> (Vec<&Expr<S>>, &Expr<S>) {
+        let mut spine = Vec::new();
+        let mut first = self;
+        while let Expr::Add(left, _)
+        | Expr::Sub(left, _)
+        | Expr::Mul(left, _)
+        | Expr::Div(left, _)
+        | Expr::Compare(_, left, _)
+        | Expr::And(left, _)
+        | Expr::Or(left, _) = first
+        {
+            spine.push(first);
+            first = left;
+        }
+        (spine, first)
+    }
+
+    /// A copy of this tree with each name turned into another type by `name`
+    fn map_names<T>(&self, name: &impl Fn(&S) -> T) -> Expr<T> {
+        let (spine, first) = self.left_spine();
+        let map = |expr: &Expr<S>| Box::new(expr.map_names(name));
+        let all = |exprs: &[Expr<S>]| exprs.iter().map(|expr| expr.map_names(name)).collect();
+        let mut mapped = match first {
+            Expr::Float(value) => Expr::Float(*value),
+            Expr::Var(var) => Expr::Var(name(var)),
+            Expr::CellRef(cell) => Expr::CellRef(*cell),
+            Expr::CellRange(from, to) => Expr::CellRange(*from, *to),
+            Expr::Error(span) => Expr::Error(*span),
+            Expr::Neg(inner) => Expr::Neg(map(inner)),
+            Expr::Not(inner) => Expr::Not(map(inner)),
+            Expr::Call(function, args) => Expr::Call(name(function), all(args)),
+            Expr::Named(argument, value) => Expr::Named(name(argument), map(value)),
+            Expr::Let(local, value, body) => Expr::Let(name(local), map(value), map(body)),
+            Expr::Piecewise(cases, default) => Expr::Piecewise(
+                cases
+                    .iter()
+                    .map(|(condition, value)| (condition.map_names(name), value.map_names(name)))
+                    .collect(),
+                default.as_deref().map(map),
+            ),
+            Expr::List(elements) => Expr::List(all(elements)),
+            Expr::Index(target, indices) => Expr::Index(map(target), all(indices)),
+            _ => unreachable!("binary operations are on the spine"),
+        };
+        for node in spine.into_iter().rev() {
+            let left = Box::new(mapped);
+            mapped = match node {
+                Expr::Add(_, right) => Expr::Add(left, map(right)),
+                Expr::Sub(_, right) => Expr::Sub(left, map(right)),
+                Expr::Mul(_, right) => Expr::Mul(left, map(right)),
+                Expr::Div(_, right) => Expr::Div(left, map(right)),
+                Expr::Compare(comparison, _, right) => Expr::Compare(*comparison, left, map(right)),
+                Expr::And(_, right) => Expr::And(left, map(right)),
+                Expr::Or(_, right) => Expr::Or(left, map(right)),
+                _ => unreachable!("only binary operations are on the spine"),
+            };
+        }
+        mapped
+    }
+}
+
+/// Trees are copied without recursing down long chains
+impl<S: Clone> Clone for Expr<S> {
+    fn clone(&self) -> Self {
+        self.map_names(&S::clone)
+    }
+}
+
+/// Trees are compared without recursing down long chains
+impl<S: PartialEq> PartialEq for Expr<S> {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut left, mut right) = (self, other);
+        loop {
+            match (left, right) {
+                (Expr::Add(a, x), Expr::Add(b, y))
+                | (Expr::Sub(a, x), Expr::Sub(b, y))
+                | (Expr::Mul(a, x), Expr::Mul(b, y))
+                | (Expr::Div(a, x), Expr::Div(b, y))
+                | (Expr::And(a, x), Expr::And(b, y))
+                | (Expr::Or(a, x), Expr::Or(b, y)) => {
+                    if x != y {
+                        return false;
+                    }
+                    (left, right) = (a, b);
+                }
+                (Expr::Compare(c, a, x), Expr::Compare(d, b, y)) => {
+                    if c != d || x != y {
+                        return false;
+                    }
+                    (left, right) = (a, b);
+                }
+                (Expr::Float(a), Expr::Float(b)) => return a == b,
+                (Expr::Var(a), Expr::Var(b)) => return a == b,
+                (Expr::CellRef(a), Expr::CellRef(b)) => return a == b,
+                (Expr::CellRange(a, x), Expr::CellRange(b, y)) => return a == b && x == y,
+                (Expr::Error(a), Expr::Error(b)) => return a == b,
+                (Expr::Neg(a), Expr::Neg(b)) | (Expr::Not(a), Expr::Not(b)) => return a == b,
+                (Expr::Call(a, x), Expr::Call(b, y)) => return a == b && x == y,
+                (Expr::Named(a, x), Expr::Named(b, y)) => return a == b && x == y,
+                (Expr::Let(a, x, u), Expr::Let(b, y, v)) => return a == b && x == y && u == v,
+                (Expr::Piecewise(a, x), Expr::Piecewise(b, y)) => return a == b && x == y,
+                (Expr::List(a), Expr::List(b)) => return a == b,
+                (Expr::Index(a, x), Expr::Index(b, y)) => return a == b && x == y,
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// The comparison operators
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comparison {
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Every comparison operator's symbol, longest first so that prefixes match last
+    pub(crate) const SYMBOLS: &'static [&'static str] = &["==", "!=", "<=", ">=", "<", ">"];
+
+    /// The operator as written, e.g. `"<="`
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    /// The comparison written as `symbol`, if there is one
+    pub(crate) fn from_symbol(symbol: &str) -> Option<Self> {
+        Some(match symbol {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return None,
+        })
+    }
+
+    /// Whether two ordered values satisfy the comparison
+    pub fn holds<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
 }
 
 /// Parse a number into an Expr::Float (supports decimals and negative numbers)
@@ -90,55 +949,441 @@ pub enum Expr {
 /// assert_eq!(expr, ast::Expr::Float(-3.14));
 /// ```
 pub fn parse_number(input: &str) -> IResult<&str, Expr> {
+    number(input)
+}
+
+/// [`parse_number`] for trees with any kind of names
+fn number<S>(input: &str) -> IResult<&str, Expr<S>> {
+    // In `2.abs()` the dot starts a method call rather than ending the number
+    if let Some(dot) = method_dot(input) {
+        let (_, num) = double(&input[..dot])?;
+        return Ok((&input[dot..], Expr::Float(num)));
+    }
     // nom's double parser can handle negative numbers directly
     let (input, num) = double(input)?;
     Ok((input, Expr::Float(num)))
 }
 
+/// Where the dot is in a whole number followed by a method call, as in `2.abs()`
+fn method_dot(input: &str) -> Option<usize> {
+    let sign = usize::from(input.starts_with(['+', '-']));
+    let digits = input[sign..].bytes().take_while(u8::is_ascii_digit).count();
+    let dot = sign + digits;
+    if digits == 0 || !input[dot..].starts_with('.') {
+        return None;
+    }
+    let (rest, _) = identifier(&input[dot + 1..]).ok()?;
+    rest.starts_with('(').then_some(dot)
+}
+
+/// [`number`], rejecting `nan`, `inf` and `infinity` unless `config` accepts them
+fn literal<'a, S>(input: &'a str, config: &ParserConfig) -> IResult<&'a str, Expr<S>> {
+    // Those words are the only literals that start with a letter
+    if !config.nonfinite_literals && input.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Float,
+        )));
+    }
+    number(input)
+}
+
+/// Parse a variable name into an Expr::Var
+///
+/// Variable names start with a letter or underscore, followed by any number of
+/// letters, digits and underscores. The words `nan`, `inf` and `infinity` (in
+/// any case) are number literals rather than variables, so they are rejected.
+///
+/// # Example
+/// ```
+/// use ast::parse_variable;
+///
+/// let (remaining, expr) = parse_variable("rate_2 * 3").unwrap();
+/// assert_eq!(expr, ast::Expr::Var("rate_2".to_string()));
+/// assert_eq!(remaining, " * 3");
+///
+/// assert!(parse_variable("2x").is_err());
+/// assert!(parse_variable("inf").is_err());
+/// ```
+pub fn parse_variable(input: &str) -> IResult<&str, Expr> {
+    let (remaining, name) = identifier(input)?;
+    Ok((remaining, Expr::Var(name.to_string())))
+}
+
+/// A variable or function name, as in [`parse_variable`]
+pub(crate) fn identifier(input: &str) -> IResult<&str, &str> {
+    let (remaining, name) = recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+    ))
+    .parse(input)?;
+
+    if ["nan", "inf", "infinity"]
+        .iter()
+        .any(|word| name.eq_ignore_ascii_case(word))
+    {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        )));
+    }
+    Ok((remaining, name))
+}
+
+/// The name of a named argument such as `nper=360`, and the input after its `=`
+///
+/// Arguments are only named from [`GrammarVersion::V3`] on, and never in the
+/// Excel dialect, where `=` compares.
+pub(crate) fn argument_name<'a>(
+    input: &'a str,
+    config: &ParserConfig,
+) -> Option<(&'a str, &'a str)> {
+    if config.grammar < GrammarVersion::V3 || config.dialect == Dialect::Excel {
+        return None;
+    }
+    let (after, name) = identifier(skip_whitespace(input)).ok()?;
+    let value = skip_whitespace(after).strip_prefix('=')?;
+    (!value.starts_with('=')).then_some((value, name))
+}
+
 /// Parse an expression wrapped in parentheses
 ///
 /// This function handles expressions like "(3 + 4)" or "((1 + 2) * 3)".
 /// It recursively calls parse_expression to handle nested expressions.
-fn parse_parenthesized(input: &str) -> IResult<&str, Expr> {
-    let (input, _) = char('(')(input)?; // Consume opening parenthesis
-    let (input, expr) = parse_expression(input)?; // Parse the inner expression
-    let (input, _) = char(')')(input)?; // Consume closing parenthesis
-    Ok((input, expr))
+fn parse_parenthesized<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("parenthesized", input, |cx| {
+        let (input, _) = char('(')(input)?; // Consume opening parenthesis
+        let (input, expr) = parse_expression_as(input, cx)?; // Parse the inner expression
+        let (input, _) = char(')')(input)?; // Consume closing parenthesis
+        Ok((input, expr))
+    })
 }
 
-/// Parse a factor (number or parenthesized expression)
+/// Parse the argument list of a function call, after the name
+///
+/// Arguments are separated by commas, and the list may be empty: "()".
+/// Any named arguments, as in "(0.05, nper=360)", come after the positional ones.
+fn parse_arguments<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Vec<Expr<S>>> {
+    cx.rule("arguments", input, |cx| {
+        let (input, _) = char('(')(input)?;
+        let (input, _) = multispace0(input)?;
+        if let Ok((input, _)) = char::<&str, nom::error::Error<&str>>(')')(input) {
+            return Ok((input, Vec::new()));
+        }
+
+        let mut args = cx.list();
+        let mut remaining = input;
+        let mut named = false;
+        loop {
+            let (input, arg) = match argument_name(remaining, cx.config) {
+                Some((value, name)) => {
+                    named = true;
+                    let (input, value) = parse_expression_as(value, cx)?;
+                    (input, Expr::Named(cx.name(name), Box::new(value)))
+                }
+                // Positional arguments can't follow named ones
+                None if named => {
+                    return Err(nom::Err::Error(nom::error::Error::new(
+                        remaining,
+                        ErrorKind::Verify,
+                    )));
+                }
+                None => parse_expression_as(remaining, cx)?,
+            };
+            args.push(arg);
+            let (input, _) = multispace0(input)?;
+            if let Ok((input, _)) = char::<&str, nom::error::Error<&str>>(',')(input) {
+                remaining = input;
+            } else {
+                let (input, _) = char(')')(input)?;
+                return Ok((input, args));
+            }
+        }
+    })
+}
+
+/// Parse a list of expressions in square brackets: `[a, b, c]`
+fn parse_list<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("list", input, |cx| {
+        let (input, elements) = parse_bracketed(input, cx)?;
+        Ok((input, Expr::List(elements)))
+    })
+}
+
+/// Parse any element accesses and method calls straight after a factor
+///
+/// Indices are written `m[1]`, `m[2, 1]` or `m[2][1]`. A method call such as
+/// `x.round(2)` is another way of writing `round(x, 2)`, passing the value
+/// before the dot as the first argument, so calls can be chained left to
+/// right. The Python dialect leaves dots to module names like `math.pi`.
+fn parse_postfix<'a, S: Name<'a>>(
+    mut input: &'a str,
+    mut expr: Expr<S>,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    loop {
+        if input.starts_with('[') {
+            let (after, indices) = parse_bracketed(input, cx)?;
+            if !(1..=2).contains(&indices.len()) {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    ErrorKind::Verify,
+                )));
+            }
+            expr = Expr::Index(Box::new(expr), indices);
+            input = after;
+        } else if cx.config.dialect != Dialect::Python
+            && let Some(after_dot) = input.strip_prefix('.')
+            && let Ok((after_name, name)) = identifier(after_dot)
+            && after_name.starts_with('(')
+        {
+            let (after, mut args) = parse_arguments(after_name, cx)?;
+            args.insert(0, expr);
+            expr = Expr::Call(cx.name(name), args);
+            input = after;
+        } else {
+            return Ok((input, expr));
+        }
+    }
+}
+
+/// Parse comma-separated expressions in square brackets, which may be empty
+fn parse_bracketed<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Vec<Expr<S>>> {
+    let (input, _) = char('[')(input)?;
+    let (input, _) = multispace0(input)?;
+    if let Some(input) = input.strip_prefix(']') {
+        return Ok((input, Vec::new()));
+    }
+
+    let mut elements = cx.list();
+    let mut remaining = input;
+    loop {
+        let (input, element) = parse_expression_as(remaining, cx)?;
+        elements.push(element);
+        let (input, _) = multispace0(input)?;
+        match input.strip_prefix(',') {
+            Some(input) => remaining = input,
+            None => {
+                let (input, _) = char(']')(input)?;
+                return Ok((input, elements));
+            }
+        }
+    }
+}
+
+/// Parse the parenthesized cases of a piecewise definition, after `piecewise`
+///
+/// Each case is a parenthesized `(condition, value)` pair. An item that isn't
+/// one is the default, which must come last, after at least one case.
+fn parse_piecewise<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("piecewise", input, |cx| {
+        let (mut remaining, _) = char('(')(input)?;
+        let mut cases = Vec::new();
+        loop {
+            let (input, _) = multispace0(remaining)?;
+            if let Ok((input, case)) = parse_case(input, cx) {
+                cases.push(case);
+                let (input, _) = multispace0(input)?;
+                if let Some(input) = input.strip_prefix(',') {
+                    remaining = input;
+                    continue;
+                }
+                let (input, _) = char(')')(input)?;
+                return Ok((input, Expr::Piecewise(cases, None)));
+            }
+            if cases.is_empty() {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    ErrorKind::Verify,
+                )));
+            }
+            let (input, default) = parse_expression_as(input, cx)?;
+            let (input, _) = multispace0(input)?;
+            let (input, _) = char(')')(input)?;
+            return Ok((input, Expr::Piecewise(cases, Some(Box::new(default)))));
+        }
+    })
+}
+
+/// Parse one `(condition, value)` case of a piecewise definition
+fn parse_case<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, (Expr<S>, Expr<S>)> {
+    cx.rule("case", input, |cx| {
+        let (input, _) = char('(')(input)?;
+        let (input, condition) = parse_expression_as(input, cx)?;
+        let (input, _) = multispace0(input)?;
+        let (input, _) = char(',')(input)?;
+        let (input, value) = parse_expression_as(input, cx)?;
+        let (input, _) = multispace0(input)?;
+        let (input, _) = char(')')(input)?;
+        Ok((input, (condition, value)))
+    })
+}
+
+/// Parse a variable, or a function call if the name is followed by "("
+///
+/// In the Python dialect, names from the `math` module such as `math.sqrt` or
+/// `math.pi` are accepted too.
+fn parse_name<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("name", input, |cx| {
+        let (mut input, mut name) = identifier(input)?;
+        if cx.config.dialect == Dialect::Python
+            && let Some(after_dot) = input.strip_prefix('.')
+        {
+            let (after_name, attribute) = identifier(after_dot)?;
+            let expr = (name == "math")
+                .then(|| dialect::python_math(attribute))
+                .flatten()
+                .ok_or_else(|| nom::Err::Error(nom::error::Error::new(input, ErrorKind::Verify)))?;
+            if !matches!(expr, Expr::Var(_)) {
+                return Ok((after_name, expr));
+            }
+            (input, name) = (after_name, attribute);
+        }
+
+        if input.starts_with('(') && cx.config.grammar < GrammarVersion::V2 {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                ErrorKind::Verify,
+            )));
+        }
+        if name == "piecewise" && input.starts_with('(') && cx.config.grammar >= GrammarVersion::V3
+        {
+            return parse_piecewise(input, cx);
+        }
+        if input.starts_with('(') {
+            let (input, args) = parse_arguments(input, cx)?;
+            let call = match cx.config.dialect {
+                Dialect::Python => dialect::python_call(name, args),
+                _ => Expr::Call(cx.name(name), args),
+            };
+            return Ok((input, call));
+        }
+        Ok((input, Expr::Var(cx.name(name))))
+    })
+}
+
+/// Parse a factor (number, variable or parenthesized expression)
 ///
 /// A factor is the most basic unit in our grammar hierarchy:
-/// - A number (e.g., "42", "-3.14")
+/// - A number (e.g., "42", "-3.14", or "4.7k" when SI suffixes are enabled)
+/// - A variable (e.g., "x", "width")
+/// - A function call (e.g., "sqrt(2)", "max(a, b)")
+/// - A cell reference (e.g., "A1", "B2:B10"), when enabled in the config
 /// - A parenthesized expression (e.g., "(1 + 2)")
 ///
-/// This function tries parentheses first, then falls back to parsing a number.
-fn parse_factor(input: &str) -> IResult<&str, Expr> {
-    let (input, _) = multispace0(input)?; // Skip any leading whitespace
+/// The first character decides which of these to parse, and anything else falls
+/// back to parsing a number.
+fn parse_factor<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.nested("factor", input, |cx| {
+        let input = skip_whitespace(input);
 
-    // Handle unary minus (negation)
-    if let Ok((input, _)) = char::<&str, nom::error::Error<&str>>('-')(input) {
-        let (input, expr) = parse_factor(input)?;
-        return Ok((input, Expr::Neg(Box::new(expr))));
-    }
+        // Dispatch on the first character, falling back to a number so that a
+        // factor that doesn't parse is reported where it starts
+        let (input, expr) = match input.as_bytes().first() {
+            Some(b'-') => {
+                let (input, expr) = parse_factor(&input[1..], cx)?;
+                return Ok((input, Expr::Neg(Box::new(expr))));
+            }
+            Some(b'!') if cx.config.grammar >= GrammarVersion::V3 => {
+                let (input, expr) = parse_factor(&input[1..], cx)?;
+                return Ok((input, Expr::Not(Box::new(expr))));
+            }
+            Some(b'(') => parse_parenthesized(input, cx)
+                .or_else(|_| cx.rule("number", input, |cx| literal(input, cx.config)))?,
+            Some(b'[') if cx.config.grammar >= GrammarVersion::V3 => parse_list(input, cx)?,
+            Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+                if let Some(Ok((input, expr))) = cx
+                    .config
+                    .cell_references
+                    .then(|| cells::parse_cell_reference(input))
+                {
+                    (input, expr)
+                } else {
+                    parse_name(input, cx)
+                        .or_else(|_| cx.rule("number", input, |cx| literal(input, cx.config)))?
+                }
+            }
+            _ => parse_measurement(input, cx)?,
+        };
+        let (input, expr) = if cx.config.grammar >= GrammarVersion::V3 {
+            parse_postfix(input, expr, cx)?
+        } else {
+            (input, expr)
+        };
 
-    // Try parsing parenthesized expression first
-    if let Ok((input, expr)) = parse_parenthesized(input) {
+        // Python's `**` binds tighter than a minus sign before it, and groups right to left
+        if cx.config.dialect == Dialect::Python
+            && cx.config.grammar >= GrammarVersion::V2
+            && let Some(exponent) = skip_whitespace(input).strip_prefix("**")
+        {
+            let (input, exponent) = parse_factor(exponent, cx)?;
+            return Ok((input, dialect::power(expr, exponent)));
+        }
         Ok((input, expr))
-    } else {
-        // Fall back to parsing a number
-        parse_number(input)
-    }
+    })
 }
 
-/// Helper function to try parsing one of several characters
-fn try_parse_operator<'a>(input: &'a str, operators: &[char]) -> Option<(char, &'a str)> {
-    for &op in operators {
-        if let Ok((remaining, _)) = char::<&str, nom::error::Error<&str>>(op)(input) {
-            return Some((op, remaining));
+/// Parse a number literal, followed by `± error` if uncertainties are enabled
+fn parse_measurement<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    let (input, value) = parse_literal(input, cx)?;
+    match cx
+        .config
+        .uncertainties
+        .then(|| uncertainty::plus_minus(input))
+    {
+        Some(Some(error)) => {
+            // An uncertainty can't be negative
+            let error = skip_whitespace(error);
+            if !error.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    error,
+                    ErrorKind::Float,
+                )));
+            }
+            let (input, error) = parse_literal(error, cx)?;
+            Ok((input, uncertainty::measurement(value, error)))
         }
+        _ => Ok((input, value)),
+    }
+}
+
+/// Parse a number literal, with an SI prefix if enabled
+fn parse_literal<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    if let Some(Ok((input, expr))) = cx.config.si_suffixes.then(|| si::parse_si_number(input)) {
+        Ok((input, expr))
+    } else {
+        cx.rule("number", input, |cx| literal(input, cx.config))
     }
-    None
 }
 
 /// Parse multiplication and division (higher precedence)
@@ -148,37 +1393,89 @@ fn try_parse_operator<'a>(input: &'a str, operators: &[char]) -> Option<(char, &
 /// are evaluated first in expressions like "2 + 3 * 4" (which becomes "2 + (3 * 4)").
 ///
 /// The function uses left-associativity, so "8 / 4 / 2" becomes "((8 / 4) / 2) = 1".
-fn parse_term(input: &str) -> IResult<&str, Expr> {
-    let (mut remaining, mut left) = parse_factor(input)?;
-
-    // Continue parsing multiplication and division operations
-    loop {
-        let (input_after_whitespace, _) = multispace0(remaining)?;
+///
+/// The Python dialect adds floor division (`//`) at the same level.
+fn parse_term<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("term", input, |cx| {
+        let (mut remaining, mut left) = parse_power(input, cx)?;
 
-        // Try to parse multiplication or division operator
-        if let Some((op, new_input)) = try_parse_operator(input_after_whitespace, &['*', '/']) {
-            let (new_input, right) = parse_factor(new_input)?;
+        // Continue parsing multiplication and division operations
+        loop {
+            let after_whitespace = skip_whitespace(remaining);
+            let (op, new_input) = match after_whitespace.as_bytes() {
+                // Floor division is a function call, so older grammars stop before it
+                [b'/', b'/', ..] if cx.config.dialect == Dialect::Python => {
+                    if cx.config.grammar < GrammarVersion::V2 {
+                        break;
+                    }
+                    ("//", &after_whitespace[2..])
+                }
+                [b'*', ..] => ("*", &after_whitespace[1..]),
+                [b'/', ..] => ("/", &after_whitespace[1..]),
+                _ => break, // No more multiplication or division operators
+            };
+            let (new_input, right) = parse_power(new_input, cx)?;
             left = match op {
-                '*' => Expr::Mul(Box::new(left), Box::new(right)),
-                '/' => Expr::Div(Box::new(left), Box::new(right)),
-                _ => unreachable!(),
+                "*" => Expr::Mul(Box::new(left), Box::new(right)),
+                "/" => Expr::Div(Box::new(left), Box::new(right)),
+                _ => dialect::floor_division(left, right),
             };
             remaining = new_input;
-        } else {
-            break; // No more multiplication or division operators
         }
-    }
 
-    Ok((remaining, left))
+        Ok((remaining, left))
+    })
+}
+
+/// Parse Excel's exponentiation (`^`), which binds tighter than `*` and `/`
+///
+/// It groups left to right, and a minus sign binds tighter, so `-2^2` is 4.
+/// Other dialects have no operator at this level.
+fn parse_power<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("power", input, |cx| {
+        let (mut remaining, mut left) = parse_factor(input, cx)?;
+        if cx.config.dialect != Dialect::Excel || cx.config.grammar < GrammarVersion::V2 {
+            return Ok((remaining, left));
+        }
+        while let Some(new_input) = skip_whitespace(remaining).strip_prefix('^') {
+            let (new_input, right) = parse_factor(new_input, cx)?;
+            left = dialect::power(left, right);
+            remaining = new_input;
+        }
+        Ok((remaining, left))
+    })
 }
 
-/// Parse addition and subtraction (lower precedence)
+/// Parse an expression
 ///
 /// This is the main entry point for parsing mathematical expressions.
-/// It handles addition (+) and subtraction (-) operations, which have the lowest
-/// precedence in our operator hierarchy.
+/// An expression may start with `let name = value in`, which makes the rest
+/// of it (up to a closing parenthesis, comma or the end) the body of an
+/// [`Expr::Let`]. From the lowest precedence to the highest, the operators are:
+/// - logical or (`||`)
+/// - logical and (`&&`)
+/// - comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`)
+/// - addition and subtraction (`+`, `-`)
+/// - multiplication and division (`*`, `/`)
+/// - negation and logical not (`-x`, `!x`)
 ///
-/// The function implements left-associativity, so "10 - 3 - 2" becomes "((10 - 3) - 2) = 5".
+/// Binary operators are left-associative, so "10 - 3 - 2" becomes "((10 - 3) - 2) = 5".
+///
+/// # Limits
+///
+/// Chains of binary operators, like the `1 + 1 + … + 1` of generated
+/// formulas, can be any length: they're parsed in a loop, and the
+/// [`Evaluator`] evaluates them and [`Expr`] drops them without recursing down
+/// the chain. Nesting (parentheses, negation, function calls and the like)
+/// is parsed by recursion, so it's limited to [`ParserConfig::max_depth`]
+/// levels and anything deeper is an error. Other passes over a tree, such as
+/// rendering, code generation and [`Session`], recurse into every operand.
 ///
 /// # Example
 /// ```
@@ -186,7 +1483,7 @@ fn parse_term(input: &str) -> IResult<&str, Expr> {
 ///
 /// // Simple precedence: multiplication before addition
 /// let (_, ast) = parse_expression("3 + 4 * 2").unwrap();
-/// match ast {
+/// match &ast {
 ///     Expr::Add(left, right) => {
 ///         assert!(matches!(left.as_ref(), Expr::Float(3.0)));
 ///         assert!(matches!(right.as_ref(), Expr::Mul(_, _)));
@@ -196,7 +1493,7 @@ fn parse_term(input: &str) -> IResult<&str, Expr> {
 ///
 /// // Parentheses override precedence
 /// let (_, ast) = parse_expression("(1 + 2) * 3").unwrap();
-/// match ast {
+/// match &ast {
 ///     Expr::Mul(left, right) => {
 ///         assert!(matches!(left.as_ref(), Expr::Add(_, _)));
 ///         assert!(matches!(right.as_ref(), Expr::Float(3.0)));
@@ -205,27 +1502,382 @@ fn parse_term(input: &str) -> IResult<&str, Expr> {
 /// }
 /// ```
 pub fn parse_expression(input: &str) -> IResult<&str, Expr> {
-    let (mut remaining, mut left) = parse_term(input)?;
+    parse_expression_with(input, &ParserConfig::default())
+}
 
-    // Continue parsing addition and subtraction operations
-    loop {
-        let (input_after_whitespace, _) = multispace0(remaining)?;
+/// Parse an expression using the given parser configuration
+///
+/// This is [`parse_expression`] with optional dialects and extensions, such as
+/// spreadsheet cell references, switched on by the [`ParserConfig`].
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression_with, CellRef, Expr, ParserConfig};
+///
+/// let (_, ast) = parse_expression_with("B2:B10", &ParserConfig::spreadsheet()).unwrap();
+/// assert_eq!(ast, Expr::CellRange(CellRef::new(2, 2), CellRef::new(2, 10)));
+///
+/// // Without the dialect, the same names are variables
+/// let (_, ast) = parse_expression_with("B2", &ParserConfig::default()).unwrap();
+/// assert_eq!(ast, Expr::Var("B2".to_string()));
+/// ```
+pub fn parse_expression_with<'a>(input: &'a str, config: &ParserConfig) -> IResult<&'a str, Expr> {
+    parse_expression_as(input, &mut Context::new(config, &mut Spare::default()))
+}
 
-        // Try to parse addition or subtraction operator
-        if let Some((op, new_input)) = try_parse_operator(input_after_whitespace, &['+', '-']) {
-            let (new_input, right) = parse_term(new_input)?;
-            left = match op {
-                '+' => Expr::Add(Box::new(left), Box::new(right)),
-                '-' => Expr::Sub(Box::new(left), Box::new(right)),
-                _ => unreachable!(),
+/// Parse an expression whose names borrow from the input instead of being copied
+///
+/// This gives the same tree as [`parse_expression_with`], but without
+/// allocating a [`String`] for every variable and function name, which adds
+/// up for services that parse many formulas. [`Expr::to_owned`] turns the
+/// tree into an ordinary one, e.g. to evaluate it.
+///
+/// # Example
+/// ```
+/// use ast::{parse_borrowed, Expr, ParserConfig};
+///
+/// let input = String::from("rate * 12");
+/// let (_, ast) = parse_borrowed(&input, &ParserConfig::default()).unwrap();
+/// assert!(matches!(&ast, Expr::Mul(left, _) if matches!(**left, Expr::Var("rate"))));
+///
+/// let owned: Expr = ast.to_owned();
+/// assert!(owned.variables().contains("rate"));
+/// ```
+pub fn parse_borrowed<'a>(
+    input: &'a str,
+    config: &ParserConfig,
+) -> IResult<&'a str, Expr<&'a str>> {
+    parse_expression_as(input, &mut Context::new(config, &mut Spare::default()))
+}
+
+/// What the names in a parsed tree are stored as: [`String`], or `&str` borrowed from the input
+pub(crate) trait Name<'a>: From<&'a str> + AsRef<str> {
+    /// The name `text`, reusing one of the `spare` strings if it can
+    fn reuse(text: &'a str, spare: &mut Vec<String>) -> Self;
+}
+
+impl<'a> Name<'a> for String {
+    fn reuse(text: &'a str, spare: &mut Vec<String>) -> Self {
+        match spare.pop() {
+            Some(mut name) => {
+                name.clear();
+                name.push_str(text);
+                name
+            }
+            None => text.to_string(),
+        }
+    }
+}
+
+impl<'a> Name<'a> for &'a str {
+    fn reuse(text: &'a str, _: &mut Vec<String>) -> Self {
+        text
+    }
+}
+
+/// Allocations from finished trees for building new ones, kept by a [`Parser`]
+#[derive(Debug, Clone)]
+pub(crate) struct Spare<S> {
+    /// Names, to be cleared and written over
+    pub(crate) names: Vec<String>,
+    /// Empty lists with room for arguments or elements
+    pub(crate) lists: Vec<Vec<Expr<S>>>,
+}
+
+impl<S> Default for Spare<S> {
+    fn default() -> Self {
+        Spare {
+            names: Vec::new(),
+            lists: Vec::new(),
+        }
+    }
+}
+
+/// What the parser functions share while parsing an expression
+pub(crate) struct Context<'p, S> {
+    /// What to accept
+    config: &'p ParserConfig,
+    /// Where to take allocations from before making new ones
+    spare: &'p mut Spare<S>,
+    /// Where to record the rules tried, when tracing
+    trace: Option<&'p mut ParseTrace>,
+    /// How many factors and `let`s are being parsed inside one another
+    depth: usize,
+}
+
+impl<'p, S> Context<'p, S> {
+    pub(crate) fn new(config: &'p ParserConfig, spare: &'p mut Spare<S>) -> Self {
+        Context {
+            config,
+            spare,
+            trace: None,
+            depth: 0,
+        }
+    }
+
+    /// Record the rules tried in `trace`
+    pub(crate) fn with_trace(mut self, trace: &'p mut ParseTrace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Parse `input` with the rule named `rule`, recording it if tracing
+    fn rule<'a, T>(
+        &mut self,
+        rule: &'static str,
+        input: &'a str,
+        parse: impl FnOnce(&mut Self) -> IResult<&'a str, T>,
+    ) -> IResult<&'a str, T> {
+        if self.trace.is_none() {
+            return parse(self);
+        }
+        self.traced(rule, input, parse)
+    }
+
+    /// [`Context::rule`] for a rule that recurses, failing past
+    /// [`ParserConfig::max_depth`] rather than running out of stack
+    fn nested<'a, T>(
+        &mut self,
+        rule: &'static str,
+        input: &'a str,
+        parse: impl FnOnce(&mut Self) -> IResult<&'a str, T>,
+    ) -> IResult<&'a str, T> {
+        if self.depth > self.config.max_depth {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                ErrorKind::TooLarge,
+            )));
+        }
+        self.depth += 1;
+        let result = self.rule(rule, input, parse);
+        self.depth -= 1;
+        result
+    }
+
+    /// [`Context::rule`] when tracing, kept apart so that parsing without a trace needs less stack
+    #[inline(never)]
+    fn traced<'a, T>(
+        &mut self,
+        rule: &'static str,
+        input: &'a str,
+        parse: impl FnOnce(&mut Self) -> IResult<&'a str, T>,
+    ) -> IResult<&'a str, T> {
+        if let Some(trace) = self.trace.as_deref_mut() {
+            trace.enter(rule, input);
+        }
+        let result = parse(self);
+        if let Some(trace) = self.trace.as_deref_mut() {
+            trace.exit(rule, input, result.as_ref().ok().map(|(rest, _)| *rest));
+        }
+        result
+    }
+
+    /// A name for the tree
+    fn name<'a>(&mut self, text: &'a str) -> S
+    where
+        S: Name<'a>,
+    {
+        S::reuse(text, &mut self.spare.names)
+    }
+
+    /// An empty list for arguments or elements
+    fn list(&mut self) -> Vec<Expr<S>> {
+        self.spare.lists.pop().unwrap_or_default()
+    }
+}
+
+/// Parse an expression, with names stored as `S`
+pub(crate) fn parse_expression_as<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("expression", input, |cx| match strip_let(input) {
+        Some(after) if cx.config.grammar >= GrammarVersion::V3 => parse_let(after, cx),
+        _ if cx.config.grammar < GrammarVersion::V3 => parse_sum(input, cx),
+        _ => parse_pipeline(input, cx),
+    })
+}
+
+/// Parse a pipeline, which binds more loosely than any operator: `x |> abs |> sqrt`
+///
+/// Each stage is a function name, optionally with arguments in parentheses,
+/// and is called with the value so far as its first argument, so
+/// `x |> abs |> sqrt` is `sqrt(abs(x))` and `x |> round(2)` is `round(x, 2)`.
+fn parse_pipeline<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("pipeline", input, |cx| {
+        let (mut remaining, mut value) = parse_or(input, cx)?;
+        while let Some(stage) = skip_whitespace(remaining).strip_prefix("|>") {
+            let (stage, name) = identifier(skip_whitespace(stage))?;
+            let (stage, mut args) = if stage.starts_with('(') {
+                parse_arguments(stage, cx)?
+            } else {
+                (stage, cx.list())
+            };
+            args.insert(0, value);
+            value = match cx.config.dialect {
+                Dialect::Python => dialect::python_call(name, args),
+                _ => Expr::Call(cx.name(name), args),
+            };
+            remaining = stage;
+        }
+        Ok((remaining, value))
+    })
+}
+
+/// The input after the keyword `let`, if it starts with one
+///
+/// `let` is only a keyword when a name follows it, so it can still be used as a variable.
+pub(crate) fn strip_let(input: &str) -> Option<&str> {
+    let after = input.trim_start().strip_prefix("let")?;
+    let name = after.trim_start();
+    (name.len() < after.len() && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+        .then_some(after)
+}
+
+/// The input after the keyword `in`, if it starts with one
+pub(crate) fn strip_in(input: &str) -> Option<&str> {
+    let after = input.trim_start().strip_prefix("in")?;
+    (!after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')).then_some(after)
+}
+
+/// Parse the rest of a local variable after `let`: `name = value in body`
+fn parse_let<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.nested("let", input, |cx| {
+        let (input, _) = multispace0(input)?;
+        let (input, name) = identifier(input)?;
+        let (input, _) = multispace0(input)?;
+        let (input, _) = char('=')(input)?;
+        if input.starts_with('=') {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                ErrorKind::Verify,
+            )));
+        }
+        let (input, value) = parse_expression_as(input, cx)?;
+        let input = strip_in(input).ok_or(nom::Err::Error(nom::error::Error::new(
+            input,
+            ErrorKind::Tag,
+        )))?;
+        let (input, body) = parse_expression_as(input, cx)?;
+        Ok((
+            input,
+            Expr::Let(cx.name(name), Box::new(value), Box::new(body)),
+        ))
+    })
+}
+
+/// The input after any spaces, tabs and line breaks, as `multispace0` skips
+///
+/// Operands and operators are separated by whitespace at every level of
+/// precedence, so this is the cheaper equivalent for the hot loops.
+pub(crate) fn skip_whitespace(input: &str) -> &str {
+    input.trim_start_matches([' ', '\t', '\r', '\n'])
+}
+
+/// Helper function to try parsing one of several multi-character operators
+///
+/// Longer operators must come before their prefixes, e.g. "<=" before "<".
+pub(crate) fn try_parse_symbol<'a, 'o>(
+    input: &'a str,
+    operators: &[&'o str],
+) -> Option<(&'o str, &'a str)> {
+    operators
+        .iter()
+        .find_map(|op| input.strip_prefix(op).map(|remaining| (*op, remaining)))
+}
+
+/// Parse logical or, the operator with the lowest precedence: `a || b`
+fn parse_or<'a, S: Name<'a>>(input: &'a str, cx: &mut Context<'_, S>) -> IResult<&'a str, Expr<S>> {
+    cx.rule("or", input, |cx| {
+        let (mut remaining, mut left) = parse_and(input, cx)?;
+        while let Some(new_input) = skip_whitespace(remaining).strip_prefix("||") {
+            let (new_input, right) = parse_and(new_input, cx)?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+            remaining = new_input;
+        }
+        Ok((remaining, left))
+    })
+}
+
+/// Parse logical and, which binds tighter than or: `a && b`
+fn parse_and<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("and", input, |cx| {
+        let (mut remaining, mut left) = parse_comparison(input, cx)?;
+        while let Some(new_input) = skip_whitespace(remaining).strip_prefix("&&") {
+            let (new_input, right) = parse_comparison(new_input, cx)?;
+            left = Expr::And(Box::new(left), Box::new(right));
+            remaining = new_input;
+        }
+        Ok((remaining, left))
+    })
+}
+
+/// Parse comparisons, which bind tighter than the logical operators: `a < b`
+fn parse_comparison<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("comparison", input, |cx| {
+        let (mut remaining, mut left) = parse_sum(input, cx)?;
+        loop {
+            let after_whitespace = skip_whitespace(remaining);
+            // Every comparison starts with one of these, so most operands need no more checks
+            if !after_whitespace.starts_with(['<', '>', '=', '!']) {
+                break;
+            }
+            if let Some((op, new_input)) =
+                try_parse_symbol(after_whitespace, cx.config.dialect.comparison_symbols())
+            {
+                let (new_input, right) = parse_sum(new_input, cx)?;
+                let comparison = cx
+                    .config
+                    .dialect
+                    .comparison(op)
+                    .expect("only comparison symbols are tried");
+                left = Expr::Compare(comparison, Box::new(left), Box::new(right));
+                remaining = new_input;
+            } else {
+                break;
+            }
+        }
+        Ok((remaining, left))
+    })
+}
+
+/// Parse addition and subtraction, which bind tighter than comparisons
+///
+/// The function implements left-associativity, so "10 - 3 - 2" becomes "((10 - 3) - 2) = 5".
+fn parse_sum<'a, S: Name<'a>>(
+    input: &'a str,
+    cx: &mut Context<'_, S>,
+) -> IResult<&'a str, Expr<S>> {
+    cx.rule("sum", input, |cx| {
+        let (mut remaining, mut left) = parse_term(input, cx)?;
+
+        // Continue parsing addition and subtraction operations
+        loop {
+            let after_whitespace = skip_whitespace(remaining);
+            let combine = match after_whitespace.as_bytes().first() {
+                Some(b'+') => Expr::Add,
+                Some(b'-') => Expr::Sub,
+                _ => break, // No more addition or subtraction operators
             };
+            let (new_input, right) = parse_term(&after_whitespace[1..], cx)?;
+            left = combine(Box::new(left), Box::new(right));
             remaining = new_input;
-        } else {
-            break; // No more addition or subtraction operators
         }
-    }
 
-    Ok((remaining, left))
+        Ok((remaining, left))
+    })
 }
 
 /// Evaluate an AST expression to a numeric result
@@ -234,6 +1886,8 @@ pub fn parse_expression(input: &str) -> IResult<&str, Expr> {
 /// the final numeric value. It handles all mathematical operations defined in the
 /// `Expr` enum and provides proper error handling for division by zero.
 ///
+/// No variables are defined; use [`evaluate_with`] to supply them.
+///
 /// # Example
 /// ```
 /// use ast::{parse_expression, evaluate, Expr, EvaluationError};
@@ -249,21 +1903,108 @@ pub fn parse_expression(input: &str) -> IResult<&str, Expr> {
 /// assert!(matches!(result, Err(EvaluationError::DivisionByZero)));
 /// ```
 pub fn evaluate(expr: &Expr) -> Result<f64, EvaluationError> {
-    match expr {
-        Expr::Float(value) => Ok(*value),
-        Expr::Add(left, right) => Ok(evaluate(left)? + evaluate(right)?),
-        Expr::Sub(left, right) => Ok(evaluate(left)? - evaluate(right)?),
-        Expr::Mul(left, right) => Ok(evaluate(left)? * evaluate(right)?),
-        Expr::Div(left, right) => {
-            let denominator = evaluate(right)?;
-            if denominator == 0.0 {
-                Err(EvaluationError::DivisionByZero)
-            } else {
-                Ok(evaluate(left)? / denominator)
+    evaluate_with(expr, &Environment::new())
+}
+
+/// Evaluate an AST expression, looking up variables in an environment
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, evaluate_with, Environment, EvaluationError};
+///
+/// let (_, ast) = parse_expression("2 * x + y").unwrap();
+/// let env: Environment = [("x", 3.0), ("y", 1.0)].into_iter().collect();
+/// assert_eq!(evaluate_with(&ast, &env).unwrap(), 7.0);
+///
+/// // Variables missing from the environment are an error
+/// let result = evaluate_with(&ast, &Environment::new());
+/// assert!(matches!(result, Err(EvaluationError::UndefinedVariable(name)) if name == "x"));
+/// ```
+pub fn evaluate_with(expr: &Expr, env: &Environment) -> Result<f64, EvaluationError> {
+    Evaluator::new(env).evaluate(expr)
+}
+
+/// Evaluate an AST expression like [`evaluate_with`], giving up once `deadline` has passed
+///
+/// Protects services from formulas that take too long, failing with
+/// [`EvaluationError::Timeout`]; see [`Evaluator::evaluate_with_deadline`].
+///
+/// # Example
+/// ```
+/// use std::time::{Duration, Instant};
+/// use ast::{parse_expression, evaluate_with_deadline, Environment};
+///
+/// let (_, ast) = parse_expression("x * 2").unwrap();
+/// let env: Environment = [("x", 21.0)].into_iter().collect();
+/// let deadline = Instant::now() + Duration::from_millis(50);
+/// assert_eq!(evaluate_with_deadline(&ast, &env, deadline).unwrap(), 42.0);
+/// ```
+pub fn evaluate_with_deadline(
+    expr: &Expr,
+    env: &Environment,
+    deadline: std::time::Instant,
+) -> Result<f64, EvaluationError> {
+    Evaluator::new(env).evaluate_with_deadline(expr, deadline)
+}
+
+/// Evaluate the parts of a tree that are free of syntax errors
+///
+/// [`evaluate`] returns [`EvaluationError::ContainsErrors`] for a tree that
+/// still holds [`Expr::Error`] placeholders. Tooling can use this function
+/// instead to show previews for what the user has typed so far: it returns
+/// each largest subtree without errors, in left-to-right order, along with
+/// its value. A tree without errors yields a single entry for the whole tree.
+///
+/// # Example
+/// ```
+/// use ast::{evaluate_partial, parse_with_recovery, Expr};
+///
+/// let (ast, _) = parse_with_recovery("(2 * 3) + ? - (4 / 0)");
+/// let parts = evaluate_partial(&ast);
+/// assert_eq!(parts.len(), 2);
+/// assert_eq!(parts[0].1.as_ref().unwrap(), &6.0);
+/// assert!(parts[1].1.is_err()); // 4 / 0 is still a division by zero
+/// ```
+pub fn evaluate_partial(expr: &Expr) -> Vec<(&Expr, Result<f64, EvaluationError>)> {
+    // Every node with the index of its parent, parents first and children
+    // left to right
+    let mut nodes: Vec<(&Expr, Option<usize>)> = Vec::new();
+    let mut pending = vec![(expr, None)];
+    while let Some((node, parent)) = pending.pop() {
+        let index = nodes.len();
+        nodes.push((node, parent));
+        pending.extend(
+            node.children()
+                .into_iter()
+                .rev()
+                .map(|child| (child, Some(index))),
+        );
+    }
+
+    // Mark the nodes above each error, stopping at any already marked, so
+    // each node is marked once
+    let mut errors = vec![false; nodes.len()];
+    for index in 0..nodes.len() {
+        if let (Expr::Error(_), _) = nodes[index] {
+            let mut above = Some(index);
+            while let Some(index) = above
+                && !errors[index]
+            {
+                errors[index] = true;
+                above = nodes[index].1;
             }
         }
-        Expr::Neg(inner) => Ok(-evaluate(inner)?),
     }
+
+    // The largest subtrees without errors are those whose parents have them
+    nodes
+        .iter()
+        .enumerate()
+        .filter(|&(index, &(_, parent))| {
+            !errors[index] && parent.is_none_or(|parent| errors[parent])
+        })
+        .map(|(_, &(node, _))| (node, evaluate(node)))
+        .collect()
 }
 
 #[cfg(test)]
@@ -318,6 +2059,255 @@ mod tests {
         }
     }
 
+    /// Test that trees with placeholders fail to evaluate but their valid parts don't
+    #[test]
+    fn test_evaluate_with_errors() {
+        let (ast, diagnostics) = parse_with_recovery("1 + 2 * ) - (3 * 3)");
+        assert!(!diagnostics.is_empty());
+        assert!(matches!(
+            evaluate(&ast),
+            Err(EvaluationError::ContainsErrors)
+        ));
+
+        let values: Vec<f64> = evaluate_partial(&ast)
+            .into_iter()
+            .map(|(_, value)| value.unwrap())
+            .collect();
+        assert_eq!(values, vec![1.0, 2.0, 9.0]);
+
+        // An error after a long chain leaves the chain whole
+        let (ast, _) = parse_with_recovery(&format!("{} + )", vec!["1"; 100_000].join(" + ")));
+        let parts = evaluate_partial(&ast);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].1.as_ref().ok(), Some(&100_000.0));
+    }
+
+    /// Test that variables are parsed and looked up in the environment
+    #[test]
+    fn test_variables() {
+        let (remaining, ast) = parse_expression("-(x + _y2) * information").unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(
+            ast.variables().into_iter().collect::<Vec<_>>(),
+            vec!["_y2", "information", "x"]
+        );
+
+        let env: Environment = [("x", 1.0), ("_y2", 2.0), ("information", 3.0)]
+            .into_iter()
+            .collect();
+        assert_eq!(evaluate_with(&ast, &env).unwrap(), -9.0);
+
+        // The special float words are still numbers
+        let (_, ast) = parse_expression("inf").unwrap();
+        assert_eq!(ast, Expr::Float(f64::INFINITY));
+    }
+
+    /// Test the nan and infinity literals, with and without the config accepting them
+    #[test]
+    fn test_nonfinite_literals() {
+        let value = |input| evaluate(&parse_expression(input).unwrap().1).unwrap();
+        assert_eq!(value("nan == nan"), 0.0);
+        assert_eq!(value("NaN != nan"), 1.0);
+        assert_eq!(value("nan < 1 || nan >= 1"), 0.0);
+        assert_eq!(value("-inf < -1e308 && Infinity > 1e308"), 1.0);
+        assert!(value("inf - INF").is_nan());
+
+        // Results print in a form that parses back to the same value
+        let format = NumberFormat::default();
+        for (input, printed) in [("nan", "NaN"), ("inf", "inf"), ("-infinity", "-inf")] {
+            let result = value(input);
+            assert_eq!(format.format(result), printed);
+            let again = value(printed);
+            assert!(again == result || (again.is_nan() && result.is_nan()));
+        }
+
+        let strict = ParserConfig {
+            nonfinite_literals: false,
+            ..ParserConfig::default()
+        };
+        for input in ["nan", "1 + inf", "-Infinity", "(nan)"] {
+            assert!(
+                parse_expression_with(input, &strict).is_err(),
+                "'{}'",
+                input
+            );
+        }
+        // They don't become names either, and overflow still gives an infinity
+        assert!(matches!(
+            parse_expression_with("inf(2)", &strict),
+            Err(nom::Err::Error(_))
+        ));
+        let (_, ast) = parse_expression_with("1e400 > 1", &strict).unwrap();
+        assert_eq!(evaluate(&ast).unwrap(), 1.0);
+    }
+
+    /// Test that each grammar version parses its own formulas like the latest, and stops at newer syntax
+    #[test]
+    fn test_grammar_versions() {
+        let pinned = |grammar| ParserConfig {
+            grammar,
+            ..ParserConfig::default()
+        };
+        let (v1, v2) = (pinned(GrammarVersion::V1), pinned(GrammarVersion::V2));
+        assert_eq!(ParserConfig::default().grammar, GrammarVersion::LATEST);
+
+        for input in ["-(a + 2) * b / 4 - -1", "let", "x in y"] {
+            let latest = parse_expression(input).unwrap();
+            assert_eq!(parse_expression_with(input, &v1).unwrap(), latest);
+            assert_eq!(parse_expression_with(input, &v2).unwrap(), latest);
+        }
+        let input = "pow(x, 2) + max(a, -b)";
+        assert_eq!(
+            parse_expression_with(input, &v2).unwrap(),
+            parse_expression(input).unwrap()
+        );
+
+        for input in ["sqrt(2)", "1 + f()", "!a", "[1, 2]"] {
+            assert!(parse_expression_with(input, &v1).is_err(), "'{}'", input);
+        }
+        for (input, remaining) in [
+            ("a <= b", " <= b"),
+            ("a && b || c", " && b || c"),
+            ("m[1]", "[1]"),
+            ("x.abs()", ".abs()"),
+        ] {
+            assert_eq!(parse_expression_with(input, &v2).unwrap().0, remaining);
+        }
+        assert!(parse_expression_with("piecewise((x > 0, 1), 0)", &v2).is_err());
+        assert!(parse_expression_with("round(x, digits=2)", &v2).is_err());
+        let (remaining, _) = parse_expression_with("let a = 1 in a", &v2).unwrap();
+        assert_eq!(remaining, " a = 1 in a");
+
+        let excel = ParserConfig {
+            dialect: Dialect::Excel,
+            ..v1
+        };
+        assert_eq!(parse_expression_with("2 ^ 3", &excel).unwrap().0, " ^ 3");
+    }
+
+    /// Test parsing function calls, and that a name needs "(" right after it to be one
+    #[test]
+    fn test_function_calls() {
+        let (remaining, ast) = parse_expression("max(1, x * 2 , pi()) - f").unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(
+            ast,
+            Expr::Sub(
+                Box::new(Expr::Call(
+                    "max".to_string(),
+                    vec![
+                        Expr::Float(1.0),
+                        Expr::Mul(
+                            Box::new(Expr::Var("x".to_string())),
+                            Box::new(Expr::Float(2.0))
+                        ),
+                        Expr::Call("pi".to_string(), vec![]),
+                    ]
+                )),
+                Box::new(Expr::Var("f".to_string()))
+            )
+        );
+
+        let (remaining, _) = parse_expression("f (2)").unwrap();
+        assert_eq!(remaining, " (2)");
+
+        // In the spreadsheet dialect, a cell-like name followed by "(" is a call
+        let (_, ast) = parse_expression_with("LOG10(A1)", &ParserConfig::spreadsheet()).unwrap();
+        assert_eq!(
+            ast,
+            Expr::Call("LOG10".to_string(), vec![Expr::CellRef(CellRef::new(1, 1))])
+        );
+    }
+
+    /// Test that a method call is the call with the value before the dot as the first argument
+    #[test]
+    fn test_method_calls() {
+        let parse = |input| parse_expression(input).unwrap();
+        assert_eq!(
+            parse("value.round(2).max(0)"),
+            parse("max(round(value, 2), 0)")
+        );
+        assert_eq!(parse("-[1, 2][1].abs()"), parse("-abs([1, 2][1])"));
+        assert_eq!(
+            parse("(x + 1).mean() * 2.5.floor()"),
+            parse("mean(x + 1) * floor(2.5)")
+        );
+        assert_eq!(evaluate(&parse("(-2.25).abs().round(1)").1).unwrap(), 2.3);
+
+        // A dot straight after a whole number starts a method call only before a name and "("
+        assert_eq!(parse("2.abs()"), parse("abs(2)"));
+        assert_eq!(parse("-2.abs() + 3.exp()"), parse("-abs(2) + exp(3)"));
+        assert_eq!(parse("2.e3"), ("", Expr::Float(2000.0)));
+        assert_eq!(parse_expression("2.x").unwrap().0, "x");
+
+        // Without parentheses or right after the value, the dot isn't a method call
+        assert_eq!(parse_expression("x.abs + 1").unwrap().0, ".abs + 1");
+        assert_eq!(parse_expression("x .abs()").unwrap().0, " .abs()");
+        let python = ParserConfig {
+            dialect: Dialect::Python,
+            ..ParserConfig::default()
+        };
+        assert!(parse_expression_with("x.abs()", &python).is_err());
+    }
+
+    /// Test that each stage of a pipeline calls a function with the value so far
+    #[test]
+    fn test_pipelines() {
+        let parse = |input| parse_expression(input).unwrap();
+        assert_eq!(parse("x |> abs |> sqrt"), parse("sqrt(abs(x))"));
+        assert_eq!(
+            parse("a + 1 |> round(2)|>max(b, 0)"),
+            parse("max(round(a + 1, 2), b, 0)")
+        );
+        assert_eq!(parse("(x |> f) * 2 || 1"), parse("f(x) * 2 || 1"));
+        assert_eq!(
+            parse("let t = x |> f in t |> g"),
+            parse("let t = f(x) in g(t)")
+        );
+        assert_eq!(evaluate(&parse("-2.25 |> abs |> round(1)").1).unwrap(), 2.3);
+
+        assert_eq!(parse_expression("x |> f + 1").unwrap().0, " + 1");
+        assert!(parse_expression("x |> 2").is_err());
+        let v2 = ParserConfig {
+            grammar: GrammarVersion::V2,
+            ..ParserConfig::default()
+        };
+        assert_eq!(parse_expression_with("x |> f", &v2).unwrap().0, " |> f");
+    }
+
+    /// Test arguments given by name, after the positional ones
+    #[test]
+    fn test_named_arguments() {
+        let parse = |input| parse_expression(input).unwrap().1;
+        let named = |name: &str, value| Expr::Named(name.to_string(), Box::new(value));
+        assert_eq!(
+            parse("round(x, digits = 1 + 1)"),
+            Expr::Call(
+                "round".to_string(),
+                vec![Expr::Var("x".to_string()), named("digits", parse("1 + 1"))]
+            )
+        );
+        assert_eq!(
+            parse("x.round(digits=2) |> f(y=x == 1)"),
+            parse("f(round(x, digits=2), y=(x == 1))")
+        );
+        assert_eq!(
+            evaluate(&parse("date(2024, day=15, month=3) |> day")).unwrap(),
+            15.0
+        );
+
+        assert!(parse_expression("round(digits=2, x)").is_err());
+        assert!(parse_expression("[x=1]").is_err());
+        let excel = ParserConfig {
+            dialect: Dialect::Excel,
+            ..ParserConfig::default()
+        };
+        assert_eq!(
+            parse_expression_with("f(a=1)", &excel).unwrap().1,
+            Expr::Call("f".to_string(), vec![parse("a == 1")])
+        );
+    }
+
     /// Test that division by zero is properly handled
     #[test]
     fn test_division_by_zero() {
@@ -326,6 +2316,7 @@ mod tests {
                 match evaluate(&ast) {
                     Err(EvaluationError::DivisionByZero) => (), // Expected
                     Ok(result) => panic!("Expected division by zero error, got {}", result),
+                    Err(error) => panic!("Expected division by zero error, got {}", error),
                 }
             }
             Err(error) => panic!("Parse failed: {:?}", error),
@@ -346,7 +2337,7 @@ mod tests {
             "5 + (3 * )", // Invalid: empty expression in parentheses
             "",           // Invalid: empty string
             "   ",        // Invalid: only whitespace
-            "5 + abc",    // Invalid: contains letters
+            "5 + $",      // Invalid: contains symbols
             "5 ** 3",     // Invalid: double multiplication
             "(((",        // Invalid: only opening parentheses
             ")))",        // Invalid: only closing parentheses
@@ -354,18 +2345,15 @@ mod tests {
         ];
 
         for expression in &invalid_expressions {
-            match parse_expression(expression) {
-                Ok((remaining, _)) => {
-                    // Some expressions might partially parse, which is acceptable
-                    // as long as there's significant remaining input
-                    if remaining.trim().is_empty() {
-                        panic!(
-                            "Expression '{}' should not have parsed completely",
-                            expression
-                        );
-                    }
-                }
-                Err(_) => (), // Expected failure
+            // Failing to parse is expected. Some expressions might partially
+            // parse, which is acceptable as long as there's remaining input.
+            if let Ok((remaining, _)) = parse_expression(expression)
+                && remaining.trim().is_empty()
+            {
+                panic!(
+                    "Expression '{}' should not have parsed completely",
+                    expression
+                );
             }
         }
     }
@@ -377,7 +2365,7 @@ mod tests {
         match parse_expression("2 + 3 * 4") {
             Ok((_, ast)) => {
                 // Should parse as Add(2, Mul(3, 4)), not Mul(Add(2, 3), 4)
-                match ast {
+                match &ast {
                     Expr::Add(left, right) => {
                         assert!(matches!(left.as_ref(), Expr::Float(2.0)));
                         assert!(matches!(right.as_ref(), Expr::Mul(_, _)));
@@ -396,7 +2384,7 @@ mod tests {
         match parse_expression("(2 + 3) * 4") {
             Ok((_, ast)) => {
                 // Should parse as Mul(Add(2, 3), 4)
-                match ast {
+                match &ast {
                     Expr::Mul(left, right) => {
                         assert!(matches!(left.as_ref(), Expr::Add(_, _)));
                         assert!(matches!(right.as_ref(), Expr::Float(4.0)));
@@ -407,4 +2395,336 @@ mod tests {
             Err(error) => panic!("Parse failed: {:?}", error),
         }
     }
+
+    /// Test spaces, tabs and line breaks between operators, and where failures are reported
+    #[test]
+    fn test_whitespace_and_failures() {
+        let (remaining, ast) = parse_expression("\t1 +\n 2 *\r\n3 == 7 &&\t!0  ").unwrap();
+        assert_eq!(remaining, "  ");
+        assert_eq!(evaluate(&ast).unwrap(), 1.0);
+
+        // A factor that doesn't parse is reported from where it starts
+        for (expression, at) in [
+            ("1 + (2 *", "(2 *"),
+            ("1 + f(2,", "f(2,"),
+            ("2 * ", ""),
+            ("x < #", "#"),
+        ] {
+            match parse_expression(expression) {
+                Err(nom::Err::Error(error)) => assert_eq!(error.input, at, "{}", expression),
+                other => panic!("'{}': expected an error, got {:?}", expression, other),
+            }
+        }
+    }
+
+    /// Test parsing and evaluating local variables, and their scope
+    #[test]
+    fn test_let() {
+        let (remaining, ast) = parse_expression("let t = (a + b) in t * t").unwrap();
+        assert_eq!(remaining, "");
+        let t = || Box::new(Expr::Var("t".to_string()));
+        assert_eq!(
+            ast,
+            Expr::Let(
+                "t".to_string(),
+                Box::new(Expr::Add(
+                    Box::new(Expr::Var("a".to_string())),
+                    Box::new(Expr::Var("b".to_string()))
+                )),
+                Box::new(Expr::Mul(t(), t()))
+            )
+        );
+
+        let env: Environment = [("x", 10.0), ("t", 1.0)].into_iter().collect();
+        let eval = |expression: &str| {
+            let (remaining, ast) = parse_expression(expression).unwrap();
+            assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+            evaluate_with(&ast, &env)
+        };
+        let cases = [
+            ("let t = x + 2 in t * t", 144.0),
+            // Nested lets, where an inner one hides an outer one
+            ("let t = 2 in let t = t * 3 in t + 1", 7.0),
+            ("let a = 1 in let b = a + 1 in a + b", 3.0),
+            // The name is only visible in the body
+            ("(let t = 5 in t) + t", 6.0),
+            ("max(let y = 3 in y * y, x)", 10.0),
+            // `let` and `in` are only keywords where they're used as such
+            ("let letter = 1 in letter + x", 11.0),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(eval(expression).unwrap(), expected, "'{}'", expression);
+        }
+        assert!(matches!(
+            eval("let y = 1 in y + z"),
+            Err(EvaluationError::UndefinedVariable(name)) if name == "z"
+        ));
+
+        // Names can hold any value, not just numbers
+        let value = |expression: &str| {
+            let ast = parse_expression(expression).unwrap().1;
+            Evaluator::new(&env)
+                .evaluate_value(&ast)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(value("let m = [1, 2] in m[1]"), "1");
+        assert_eq!(value("let m = [1, 2] in m * x"), "[10, 20]");
+        assert_eq!(value("let d = date(2024, 1, 1) in d"), "2024-01-01");
+        assert_eq!(
+            value("let d = date(2024, 1, 1) in d + days(1)"),
+            "2024-01-02"
+        );
+
+        for invalid in [
+            "let t = 1",
+            "let t == 1 in t",
+            "let t = 1 in",
+            "let 2 = 1 in 2",
+        ] {
+            let parsed = parse_expression(invalid);
+            assert!(
+                parsed.is_err() || !parsed.unwrap().0.trim().is_empty(),
+                "'{}' should not parse",
+                invalid
+            );
+        }
+    }
+
+    /// Test parsing piecewise definitions, and that only the chosen case is evaluated
+    #[test]
+    fn test_piecewise() {
+        let (remaining, ast) = parse_expression("piecewise((x < 0, -x), (x + 1) * 2)").unwrap();
+        assert_eq!(remaining, "");
+        let x = || Box::new(Expr::Var("x".to_string()));
+        assert_eq!(
+            ast,
+            Expr::Piecewise(
+                vec![(
+                    Expr::Compare(Comparison::Less, x(), Box::new(Expr::Float(0.0))),
+                    Expr::Neg(x())
+                )],
+                Some(Box::new(Expr::Mul(
+                    Box::new(Expr::Add(x(), Box::new(Expr::Float(1.0)))),
+                    Box::new(Expr::Float(2.0))
+                )))
+            )
+        );
+
+        let env: Environment = [("x", 0.0)].into_iter().collect();
+        let eval = |expression: &str| {
+            let (remaining, ast) = parse_expression(expression).unwrap();
+            assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+            evaluate_with(&ast, &env)
+        };
+        assert_eq!(eval("piecewise((x < 0, -1), (x == 0, 0), 1)").unwrap(), 0.0);
+        assert_eq!(eval("piecewise((x != 0, 1 / x), (1, 5))").unwrap(), 5.0);
+        assert_eq!(eval("piecewise((x > 0, undefined), 7) + 1").unwrap(), 8.0);
+        assert!(matches!(
+            eval("piecewise((x > 0, 1), (x < 0, -1))"),
+            Err(EvaluationError::DomainError(name)) if name == "piecewise"
+        ));
+
+        for invalid in [
+            "piecewise()",
+            "piecewise(1)",
+            "piecewise((x, 1), 2, 3)",
+            "piecewise(1, (x, 2))",
+        ] {
+            let parsed = parse_expression(invalid);
+            assert!(
+                parsed.is_err() || !parsed.unwrap().0.trim().is_empty(),
+                "'{}' should not parse",
+                invalid
+            );
+        }
+    }
+
+    /// Test matrix literals, element access and the linear-algebra operators
+    #[test]
+    fn test_matrices() {
+        let eval = |expression: &str| {
+            let (remaining, ast) = parse_expression(expression).unwrap();
+            assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+            Evaluator::new(&Environment::new()).evaluate_value(&ast)
+        };
+        let cases = [
+            ("[1, 2] + [3, 4]", "[4, 6]"),
+            ("[[1, 2], [3, 4]] * [1, 1]", "[3, 7]"),
+            ("[[1, 2], [3, 4]] * [[0, 1], [1, 0]]", "[[2, 1], [4, 3]]"),
+            ("2 * [[1, 2]] - [[1, 1]] / 2", "[[1.5, 3.5]]"),
+            ("-[1, 2 + 3]", "[-1, -5]"),
+            ("[[1], [2]]", "[1, 2]"),
+            ("[]", "[]"),
+            ("[[1, 2], [3, 4]][2]", "[3, 4]"),
+            ("[[1, 2], [3, 4]][2, 1]", "3"),
+            ("[[1, 2], [3, 4]][1][2] + [5, 6][2]", "8"),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                eval(expression).unwrap().to_string(),
+                expected,
+                "'{}'",
+                expression
+            );
+        }
+
+        assert!(matches!(
+            eval("[1, 2] + [1, 2, 3]"),
+            Err(EvaluationError::DimensionMismatch {
+                op: "+",
+                left: (2, 1),
+                right: (3, 1)
+            })
+        ));
+        assert!(matches!(
+            eval("[[1, 2], [3]]"),
+            Err(EvaluationError::RaggedRows {
+                expected: 2,
+                found: 1
+            })
+        ));
+        assert!(matches!(
+            eval("[1, 2][3]"),
+            Err(EvaluationError::IndexOutOfRange { length: 2, .. })
+        ));
+        assert!(matches!(
+            eval("[1, 2][1.5]"),
+            Err(EvaluationError::IndexOutOfRange { .. })
+        ));
+        assert!(matches!(
+            eval("[1, [2]]"),
+            Err(EvaluationError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            eval("[1] < [2]"),
+            Err(EvaluationError::UnsupportedOperands { .. })
+        ));
+        assert!(matches!(
+            evaluate(&parse_expression("[1, 2]").unwrap().1),
+            Err(EvaluationError::TypeMismatch {
+                found: "a matrix",
+                ..
+            })
+        ));
+
+        for invalid in ["[1, 2", "[1, 2][]", "[1][1, 2, 3]", "[1,]"] {
+            let parsed = parse_expression(invalid);
+            assert!(
+                parsed.is_err() || !parsed.unwrap().0.trim().is_empty(),
+                "'{}' should not parse",
+                invalid
+            );
+        }
+    }
+
+    /// Test that chains of 100,000 operators parse, evaluate and drop without overflowing the stack
+    #[test]
+    fn test_long_chains() {
+        let env = Environment::new();
+        let compensated = EvalConfig {
+            compensated_sums: true,
+            ..EvalConfig::default()
+        };
+        let cases = [
+            (" + ", 100_000.0),
+            (" - ", -99_998.0),
+            (" * ", 1.0),
+            (" / ", 1.0),
+            (" == ", 1.0),
+            (" && ", 1.0),
+            (" || ", 1.0),
+        ];
+        for (op, expected) in cases {
+            let input = vec!["1"; 100_000].join(op);
+            let (remaining, ast) = parse_expression(&input).unwrap();
+            assert!(remaining.is_empty(), "'{}' chain not fully parsed", op);
+            assert_eq!(
+                Evaluator::new(&env).evaluate(&ast).unwrap(),
+                expected,
+                "'{}'",
+                op
+            );
+            assert_eq!(
+                Evaluator::new(&env)
+                    .with_config(compensated)
+                    .evaluate(&ast)
+                    .unwrap(),
+                expected,
+                "'{}' with compensated sums",
+                op
+            );
+        }
+
+        let input = vec!["x"; 100_000].join(" - ");
+        let (_, borrowed) = parse_borrowed(&input, &ParserConfig::default()).unwrap();
+        assert!(matches!(borrowed.to_owned(), Expr::Sub(..)));
+
+        let input = vec!["x"; 100_000].join(" + ");
+        let (ast, diagnostics) = parse_with_recovery(&input);
+        assert!(diagnostics.is_empty());
+        assert!(matches!(ast, Expr::Add(..)));
+    }
+
+    /// Test that nesting past the limit is an error rather than a stack overflow
+    #[test]
+    fn test_nesting_limit() {
+        let nest = |open: &str, close: &str, depth| {
+            format!("{}1{}", open.repeat(depth), close.repeat(depth))
+        };
+        let builder = std::thread::Builder::new().stack_size(2 << 20);
+        let handle = builder.spawn(move || {
+            for (open, close) in [("(", ")"), ("-", ""), ("abs(", ")"), ("[", "][1]")] {
+                let input = nest(open, close, 64);
+                let (remaining, ast) = parse_expression(&input).unwrap();
+                assert!(remaining.is_empty(), "'{}' not fully parsed", open);
+                assert!(evaluate(&ast).is_ok(), "'{}'", open);
+                assert!(parse_expression(&nest(open, close, 200)).is_err());
+            }
+            assert!(parse_expression(&nest("let a = 1 in ", "", 200)).is_err());
+        });
+        handle.unwrap().join().unwrap();
+
+        let shallow = ParserConfig {
+            max_depth: 8,
+            ..ParserConfig::default()
+        };
+        assert!(parse_expression_with(&nest("(", ")", 8), &shallow).is_ok());
+        assert!(parse_expression_with(&nest("(", ")", 9), &shallow).is_err());
+    }
+
+    /// Test that borrowed trees match owned ones once converted
+    #[test]
+    fn test_borrowed() {
+        let spreadsheet = ParserConfig::spreadsheet();
+        let python = ParserConfig {
+            dialect: Dialect::Python,
+            ..ParserConfig::default()
+        };
+        let cases = [
+            ("a * max(b, 2) - -c", ParserConfig::default()),
+            ("let t = x + 1 in t * t < 3 && !y", ParserConfig::default()),
+            ("piecewise((x < 0, -x), x)", ParserConfig::default()),
+            ("[[1, x], [y, 2]][1, 2]", ParserConfig::default()),
+            ("SUM(A1:B2) * C3", spreadsheet),
+            ("math.log(x, 2) + y ** 2 // math.pi", python),
+        ];
+        for (input, config) in cases {
+            let (rest, borrowed) = parse_borrowed(input, &config).unwrap();
+            assert!(rest.is_empty(), "'{}' not fully parsed", input);
+            let (_, owned) = parse_expression_with(input, &config).unwrap();
+            assert_eq!(borrowed.to_owned(), owned, "'{}'", input);
+        }
+
+        // Names point into the input rather than being copied
+        let input = "width * height";
+        let (_, borrowed) = parse_borrowed(input, &ParserConfig::default()).unwrap();
+        let Expr::Mul(left, _) = &borrowed else {
+            panic!("expected a product");
+        };
+        let Expr::Var(name) = **left else {
+            panic!("expected a variable");
+        };
+        assert_eq!(name.as_ptr(), input.as_ptr());
+    }
 }
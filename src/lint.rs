@@ -0,0 +1,338 @@
+//! Warnings for suspicious expressions
+//!
+//! Lints look at input that parses fine but is probably not what the author
+//! meant, or could be written more clearly. They produce
+//! [`Severity::Warning`](crate::Severity::Warning) diagnostics; syntax errors
+//! are reported by [`parse_with_recovery`](crate::parse_with_recovery), except
+//! for nesting too deep to lint, which is an error here too.
+
+use crate::recovery::parse_with_spans;
+use crate::{Comparison, Diagnostic, Expr, Span, codes, evaluate, parse_expression};
+
+/// Check an expression for suspicious constructs
+///
+/// The following are reported, sorted by their position in the input:
+/// - parentheses that don't change how the expression is grouped, e.g. `(2 * 3) + 1`
+/// - subtracting a negation, e.g. `5 - -3`, which reads better as `5 + 3`
+/// - a factor that always evaluates to 0, which makes the whole product 0, e.g. `7 * (2 - 2)`
+/// - comparing fractions with `==` or `!=`, e.g. `0.1 + 0.2 == 0.3`, which is false
+///   because neither side is stored exactly
+/// - a `let` name that the body never uses, e.g. `let t = 2 in 5`
+///
+/// Input with syntax errors is linted as far as it could be parsed. Nesting
+/// deeper than [`ParserConfig::max_depth`](crate::ParserConfig::max_depth),
+/// which the parser rejects, is reported as an error.
+///
+/// # Example
+/// ```
+/// use ast::{lint, Span};
+///
+/// let warnings = lint("(2 * 3) - -1");
+/// assert_eq!(warnings.len(), 2);
+/// assert_eq!(warnings[0].span, Span::new(0, 7));
+/// assert_eq!(warnings[0].message, "redundant parentheses");
+///
+/// assert!(lint("2 * (3 + 1)").is_empty());
+/// ```
+pub fn lint(input: &str) -> Vec<Diagnostic> {
+    let (ast, diagnostics, spans) = parse_with_spans(input);
+
+    let mut linter = Linter {
+        spans: &spans,
+        next: 0,
+        warnings: Vec::new(),
+    };
+    linter.walk(&ast);
+
+    let mut warnings = linter.warnings;
+    let too_deep = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.code == Some(codes::TOO_DEEP));
+    warnings.extend(too_deep.cloned());
+    if diagnostics.is_empty() {
+        warnings.extend(redundant_parentheses(input, &ast));
+    }
+    warnings.sort_by_key(|warning| warning.span.start);
+    warnings
+}
+
+/// A node's span, and its value if it's a constant
+type Walked = (Span, Option<f64>);
+
+/// Walks a tree in post-order alongside its spans, collecting warnings
+struct Linter<'a> {
+    /// Node spans in post-order, as returned by `parse_with_spans`
+    spans: &'a [Span],
+    /// Index of the span belonging to the next node visited
+    next: usize,
+    /// Warnings found so far
+    warnings: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    /// Visit a node and its children, returning the node's span and value
+    ///
+    /// Left operands are walked in a loop rather than by recursion, so that
+    /// a long chain like `1 + 1 + … + 1` doesn't overflow the stack.
+    fn walk(&mut self, expr: &Expr) -> Walked {
+        let (spine, first) = expr.left_spine();
+        let children: Vec<Walked> = first
+            .children()
+            .into_iter()
+            .map(|child| self.walk(child))
+            .collect();
+        let mut walked = self.visit(first, &children);
+        for node in spine.into_iter().rev() {
+            let right = self.walk(node.children()[1]);
+            walked = self.visit(node, &[walked, right]);
+        }
+        walked
+    }
+
+    /// Check a node whose children have been walked, given their spans and
+    /// values, returning its own
+    fn visit(&mut self, expr: &Expr, children: &[Walked]) -> Walked {
+        match (expr, children) {
+            (Expr::Sub(_, right), [_, (right_span, _)]) if starts_with_negation(right) => {
+                self.warnings.push(
+                    Diagnostic::warning(*right_span, "subtracting a negation, use '+' instead")
+                        .with_code(codes::DOUBLE_NEGATION),
+                );
+            }
+            (Expr::Mul(..), factors) => {
+                for (span, value) in factors {
+                    if *value == Some(0.0) {
+                        self.warnings.push(
+                            Diagnostic::warning(
+                                *span,
+                                "this factor is always 0, so the product is always 0",
+                            )
+                            .with_code(codes::ZERO_FACTOR),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let span = self.spans[self.next];
+        self.next += 1;
+        match expr {
+            Expr::Compare(Comparison::Equal | Comparison::NotEqual, left, right)
+                if is_fractional(left) || is_fractional(right) =>
+            {
+                self.warnings.push(
+                    Diagnostic::warning(
+                        span,
+                        "fractions may not be exactly equal, compare the difference against a tolerance",
+                    )
+                    .with_code(codes::FRACTION_EQUALITY),
+                );
+            }
+            Expr::Let(name, _, body) if !uses(body, name) => {
+                self.warnings.push(
+                    Diagnostic::warning(span, format!("'{}' is never used", name))
+                        .with_code(codes::UNUSED_ASSIGNMENT),
+                );
+            }
+            _ => {}
+        }
+        (span, constant(expr, children))
+    }
+}
+
+/// Whether the leftmost factor of a term is a negation, as in `-2 * 3`
+fn starts_with_negation(mut expr: &Expr) -> bool {
+    while let Expr::Mul(left, _) | Expr::Div(left, _) = expr {
+        expr = left;
+    }
+    matches!(expr, Expr::Neg(_))
+}
+
+/// Whether an operand of a comparison is a fraction, or is calculated with one or a division
+///
+/// Function calls aren't looked into, since functions such as `round` and
+/// `floor` are how fractions are made safe to compare.
+fn is_fractional(expr: &Expr) -> bool {
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        match expr {
+            Expr::Float(value) if value.fract() != 0.0 => return true,
+            Expr::Div(..) => return true,
+            Expr::Call(..) => {}
+            _ => pending.extend(expr.children()),
+        }
+    }
+    false
+}
+
+/// Whether a variable is used in an expression, other than where a `let` hides it
+fn uses(expr: &Expr, name: &str) -> bool {
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        match expr {
+            Expr::Var(var) if var == name => return true,
+            Expr::Let(inner, value, body) => {
+                pending.push(value);
+                if inner != name {
+                    pending.push(body);
+                }
+            }
+            _ => pending.extend(expr.children()),
+        }
+    }
+    false
+}
+
+/// The value of a node whose children have been walked, if they're all
+/// constants and it evaluates to a number
+///
+/// Binary operators are evaluated with their operands replaced by their
+/// values, so each operator in a chain is only evaluated once.
+fn constant(expr: &Expr, children: &[Walked]) -> Option<f64> {
+    let values: Vec<f64> = children
+        .iter()
+        .map(|&(_, value)| value)
+        .collect::<Option<_>>()?;
+    let float = |value: f64| Box::new(Expr::Float(value));
+    let folded = match (expr, &values[..]) {
+        (Expr::Add(..), &[left, right]) => Expr::Add(float(left), float(right)),
+        (Expr::Sub(..), &[left, right]) => Expr::Sub(float(left), float(right)),
+        (Expr::Mul(..), &[left, right]) => Expr::Mul(float(left), float(right)),
+        (Expr::Div(..), &[left, right]) => Expr::Div(float(left), float(right)),
+        (Expr::Compare(comparison, ..), &[left, right]) => {
+            Expr::Compare(*comparison, float(left), float(right))
+        }
+        (Expr::And(..), &[left, right]) => Expr::And(float(left), float(right)),
+        (Expr::Or(..), &[left, right]) => Expr::Or(float(left), float(right)),
+        _ => return evaluate(expr).ok(),
+    };
+    evaluate(&folded).ok()
+}
+
+/// Find pairs of parentheses that can be removed without changing the tree
+///
+/// Each pair is blanked out in turn (replaced by spaces, so the other offsets
+/// stay the same) and the result re-parsed. If the tree comes out the same, the
+/// parentheses weren't needed.
+fn redundant_parentheses(input: &str, ast: &Expr) -> Vec<Diagnostic> {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+    for (index, c) in input.char_indices() {
+        match c {
+            '(' => open.push(index),
+            ')' => pairs.extend(open.pop().map(|start| (start, index))),
+            _ => (),
+        }
+    }
+
+    pairs
+        .into_iter()
+        .filter(|&(start, end)| {
+            let mut blanked = input.to_string();
+            blanked.replace_range(start..=start, " ");
+            blanked.replace_range(end..=end, " ");
+            matches!(
+                parse_expression(&blanked),
+                Ok((remaining, tree)) if remaining.trim().is_empty() && tree == *ast
+            )
+        })
+        .map(|(start, end)| {
+            Diagnostic::warning(Span::new(start, end + 1), "redundant parentheses")
+                .with_code(codes::REDUNDANT_PARENTHESES)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Severity;
+
+    /// Test which parentheses are reported as redundant
+    #[test]
+    fn test_redundant_parentheses() {
+        let cases = [
+            ("(1 + 2) * 3", vec![]),
+            ("1 - (2 - 3)", vec![]),
+            ("1 + (2 * 3)", vec![Span::new(4, 11)]),
+            ("(1 + 2)", vec![Span::new(0, 7)]),
+            ("-(4)", vec![Span::new(1, 4)]),
+            ("((1 + 2)) * 3", vec![Span::new(0, 9)]),
+            ("max(a, b)", vec![]),
+            ("f((x), 2)", vec![Span::new(2, 5)]),
+        ];
+
+        for (expression, expected) in cases {
+            let spans: Vec<Span> = lint(expression).iter().map(|w| w.span).collect();
+            assert_eq!(spans, expected, "'{}'", expression);
+        }
+    }
+
+    /// Test the operator-level lints and that they are all warnings
+    #[test]
+    fn test_suspicious_operators() {
+        let warnings = lint("10 - -2 * (4 - 4)");
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert_eq!(warnings[0].span, Span::new(5, 17));
+        assert_eq!(warnings[1].span, Span::new(11, 16));
+        assert!(warnings.iter().all(|w| w.severity == Severity::Warning));
+
+        // Input with syntax errors is linted as far as it parsed
+        assert_eq!(lint("$ * 0").len(), 1);
+
+        assert!(lint("10 - 2").is_empty());
+        assert!(lint("3 * 0.5").is_empty());
+
+        // Constants are folded once, however long the product
+        assert_eq!(lint("x * round(0.2)").len(), 1);
+        let product = format!("{} * (1 - 1)", vec!["x"; 100_000].join(" * "));
+        let warnings = lint(&product);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].span.end, product.len() - 1);
+    }
+
+    /// Test comparing fractions exactly and unused `let` names
+    #[test]
+    fn test_fractions_and_unused_names() {
+        let cases = [
+            ("0.1 + 0.2 == 0.3", vec![Span::new(0, 16)]),
+            ("x / 3 != y", vec![Span::new(0, 10)]),
+            ("x == 1", vec![]),
+            ("round(x / 3, 2) == y", vec![]),
+            ("x / 3 < 0.5", vec![]),
+            ("let t = 2 in 5", vec![Span::new(0, 14)]),
+            ("let t = 2 in t * t", vec![]),
+            ("let t = 2 in let t = 3 in t", vec![Span::new(0, 27)]),
+            ("let t = 2 in let u = t in u", vec![]),
+        ];
+
+        for (expression, expected) in cases {
+            let spans: Vec<Span> = lint(expression).iter().map(|w| w.span).collect();
+            assert_eq!(spans, expected, "'{}'", expression);
+        }
+        assert_eq!(lint("let t = 2 in 5")[0].message, "'t' is never used");
+    }
+
+    /// Test that nesting the parser rejects is an error, however deep it goes
+    #[test]
+    fn test_nesting_limit() {
+        let depth = crate::ParserConfig::default().max_depth;
+        let nested = |levels: usize| format!("{}x{} - -1", "(".repeat(levels), ")".repeat(levels));
+        assert!(
+            lint(&nested(depth))
+                .iter()
+                .all(|warning| warning.severity == Severity::Warning)
+        );
+        for levels in [depth + 1, 100_000] {
+            let input = nested(levels);
+            assert!(parse_expression(&input).is_err());
+            let errors: Vec<_> = lint(&input)
+                .into_iter()
+                .filter(|diagnostic| diagnostic.severity == Severity::Error)
+                .collect();
+            assert_eq!(errors.len(), 1, "{} levels", levels);
+            assert_eq!(errors[0].code, Some(codes::TOO_DEEP));
+        }
+    }
+}
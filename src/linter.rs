@@ -0,0 +1,615 @@
+//! Checking formula files from the command line
+//!
+//! `ast lint formulas.calc` checks files of formulas, one per line like a
+//! startup file, without evaluating them. Each line is parsed, its function
+//! calls are checked against the standard functions and the ones the file
+//! defines, its variables against the ones assigned on earlier lines, its
+//! operators against the kinds of values they're given where those are
+//! known, and its size against limits; then the warnings of [`ast::lint`]
+//! are added.
+//! The findings are printed one per line, or as JSON for tools, and the
+//! command fails if there are errors, so it can gate changes in CI.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Read},
+};
+
+use ast::{
+    Arity, Assignee, EvaluationError, Expr, FunctionRegistry, Session, Severity, Span, lint,
+    parse_with_spans,
+};
+use serde_json::json;
+
+use crate::{diagnostic_message, error_message};
+
+/// The command line help for `ast lint`
+pub const USAGE: &str =
+    "Usage: ast lint [--json] [--var <name>]... [--max-depth <n>] [--max-nodes <n>]
+                [--deny-warnings] [<file>...]
+
+Check files of formulas, one per line, without evaluating them. Errors are
+syntax errors, calls to unknown functions or with the wrong number of
+arguments, variables that aren't assigned on an earlier line or named with
+--var, and operators given values they don't work on, such as a date plus a
+number. Warnings are formulas nested more than --max-depth (20) deep or
+with more than --max-nodes (200) nodes, variables assigned again before
+their value is used, and suspicious constructs such as redundant
+parentheses. Findings are printed as
+'file:line:column: severity[code]: message', or with --json as a JSON array.
+Without files, or with '-', standard input is checked. Fails if there are
+errors, or with --deny-warnings, any findings.";
+
+/// Something wrong with a line of a formula file
+#[derive(Debug, PartialEq, Clone)]
+struct Finding {
+    /// The line, starting at 1
+    line: usize,
+    /// The column, in characters starting at 1
+    column: usize,
+    /// Whether the formula can't work as written, or probably isn't what was meant
+    severity: Severity,
+    /// The problem's stable code, if it has one
+    code: Option<&'static str>,
+    /// What's wrong
+    message: String,
+}
+
+/// What the checks allow, from the command line
+#[derive(Debug, Clone)]
+struct Limits {
+    /// Variables that come from outside the file
+    variables: BTreeSet<String>,
+    /// How deep a formula may be nested
+    max_depth: usize,
+    /// How many nodes a formula may have
+    max_nodes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            variables: BTreeSet::new(),
+            max_depth: 20,
+            max_nodes: 200,
+        }
+    }
+}
+
+/// Parse the arguments after `lint` and check the files, giving whether they passed
+pub fn run(args: impl Iterator<Item = String>) -> Result<bool, String> {
+    let (mut json, mut deny_warnings) = (false, false);
+    let mut limits = Limits::default();
+    let mut files = Vec::new();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{}: expected a value", name))
+        };
+        let mut number = |name: &str| {
+            let text = value(name)?;
+            text.parse::<usize>()
+                .map_err(|_| format!("{} {}: expected a whole number", name, text))
+        };
+        match arg.as_str() {
+            "--json" => json = true,
+            "--deny-warnings" => deny_warnings = true,
+            "--max-depth" => limits.max_depth = number("--max-depth")?,
+            "--max-nodes" => limits.max_nodes = number("--max-nodes")?,
+            "--var" => {
+                limits.variables.insert(value("--var")?);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            option if option.starts_with("--") => {
+                return Err(format!("{}: unknown option", option));
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut passed = true;
+    let mut reported = Vec::new();
+    for file in files {
+        let source = if file == "-" {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).map(|_| source)
+        } else {
+            fs::read_to_string(&file)
+        };
+        let findings = match source {
+            Ok(source) => lint_file(&source, &limits),
+            Err(error) => {
+                eprintln!("❌ {}: {}", file, error);
+                passed = false;
+                continue;
+            }
+        };
+        for finding in findings {
+            passed &= finding.severity == Severity::Warning && !deny_warnings;
+            if json {
+                reported.push(json!({
+                    "file": file,
+                    "line": finding.line,
+                    "column": finding.column,
+                    "severity": finding.severity.to_string(),
+                    "code": finding.code,
+                    "message": finding.message,
+                }));
+            } else {
+                let code = finding.code.map(|code| format!("[{}]", code));
+                println!(
+                    "{}:{}:{}: {}{}: {}",
+                    file,
+                    finding.line,
+                    finding.column,
+                    finding.severity,
+                    code.unwrap_or_default(),
+                    finding.message
+                );
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::Value::Array(reported));
+    }
+    Ok(passed)
+}
+
+/// The findings for a file of formulas, in order
+fn lint_file(source: &str, limits: &Limits) -> Vec<Finding> {
+    let session = Session::new();
+    let standard = FunctionRegistry::standard();
+    let mut functions: BTreeMap<String, Known> = standard
+        .names()
+        .into_iter()
+        .filter_map(|name| {
+            let function = standard.get(name)?;
+            let known = Known {
+                arity: function.arity(),
+                signature: function.signature(name),
+                returns: returns(name),
+            };
+            Some((name.to_string(), known))
+        })
+        .collect();
+    let ans = Known {
+        arity: Arity {
+            min: 0,
+            max: Some(1),
+        },
+        signature: "ans".to_string(),
+        returns: None,
+    };
+    functions.insert("ans".to_string(), ans);
+    let mut assigned: BTreeSet<String> = limits.variables.iter().cloned().collect();
+    assigned.insert("ans".to_string());
+
+    // Variables whose value hasn't been used yet, with where they were assigned
+    let mut unread: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+
+    let mut findings = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default().trim();
+        if code.is_empty() {
+            continue;
+        }
+        let (assignee, expression) = match session.split_assignment(code) {
+            Some((assignee, expression)) => (Some(assignee), expression),
+            None => (None, code),
+        };
+        // Where the expression starts on the line, after any indentation and assignment
+        let offset = line.len() - line.trim_start().len() + code.len() - expression.len();
+        let mut report = |span: Span, severity: Severity, code, message| {
+            findings.push(Finding {
+                line: number + 1,
+                column: line[..offset + span.start].chars().count() + 1,
+                severity,
+                code,
+                message,
+            })
+        };
+
+        // Defined before its body is checked, so it can call itself
+        if let Some(Assignee::Function { name, params }) = &assignee {
+            let known = Known {
+                arity: params.len().into(),
+                signature: format!("{}({})", name, params.join(", ")),
+                returns: None,
+            };
+            functions.insert(name.to_string(), known);
+        }
+
+        let (ast, diagnostics, spans) = parse_with_spans(expression);
+        let parameters = match &assignee {
+            Some(Assignee::Function { params, .. }) => {
+                params.iter().map(|param| (*param, None)).collect()
+            }
+            _ => Vec::new(),
+        };
+        if diagnostics.is_empty() {
+            let mut checker = Checker {
+                functions: &functions,
+                assigned: &assigned,
+                bound: parameters,
+                spans: &spans,
+                next: 0,
+                kinds: Vec::new(),
+                read: BTreeSet::new(),
+                errors: Vec::new(),
+            };
+            checker.walk(&ast);
+            unread.retain(|name, _| !checker.read.contains(name.as_str()));
+            let mut seen = BTreeSet::new();
+            for (span, error) in checker.errors {
+                // Each undefined variable once per line
+                if seen.insert(error.to_string()) {
+                    report(
+                        span,
+                        Severity::Error,
+                        Some(error.code()),
+                        error_message(&error),
+                    );
+                }
+            }
+
+            let whole = Span::new(0, expression.len());
+            let depth = ast.depth();
+            if depth > limits.max_depth {
+                let message = format!(
+                    "formula is nested {} deep, more than the limit of {}",
+                    depth, limits.max_depth
+                );
+                report(whole, Severity::Warning, None, message);
+            }
+            let nodes = ast.size();
+            if nodes > limits.max_nodes {
+                let message = format!(
+                    "formula has {} nodes, more than the limit of {}",
+                    nodes, limits.max_nodes
+                );
+                report(whole, Severity::Warning, None, message);
+            }
+        } else {
+            // What a line that doesn't parse uses isn't known
+            unread.clear();
+        }
+        // The syntax errors that `lint` repeats have been reported already
+        let warnings = lint(expression);
+        let warnings = warnings
+            .iter()
+            .filter(|warning| warning.severity == Severity::Warning);
+        for diagnostic in diagnostics.iter().chain(warnings) {
+            report(
+                diagnostic.span,
+                diagnostic.severity,
+                diagnostic.code,
+                diagnostic_message(diagnostic),
+            );
+        }
+
+        if let Some(Assignee::Variable(name)) = assignee {
+            assigned.insert(name.to_string());
+            let column = line[..line.len() - line.trim_start().len()].chars().count() + 1;
+            if let Some((line, column)) = unread.insert(name.to_string(), (number + 1, column)) {
+                findings.push(Finding {
+                    line,
+                    column,
+                    severity: Severity::Warning,
+                    code: Some("W0005"),
+                    message: format!(
+                        "the value assigned to '{}' is never used before it's assigned again",
+                        name
+                    ),
+                });
+            }
+        }
+    }
+    findings.sort_by_key(|finding| (finding.line, finding.column));
+    findings
+}
+
+/// What the checks know about a function that can be called
+struct Known {
+    /// How many arguments it takes
+    arity: Arity,
+    /// How it's written in messages, e.g. `net(gross)`
+    signature: String,
+    /// The kind of value it gives, if that's always the same
+    returns: Option<&'static str>,
+}
+
+/// The kind of value a standard function always gives, named as by [`ast::Value::type_name`]
+///
+/// Only the functions giving dates and durations matter to the checks, so
+/// the rest are left unknown.
+fn returns(name: &str) -> Option<&'static str> {
+    match name {
+        "date" | "now" => Some(DATE),
+        "days" | "hours" | "minutes" | "seconds" => Some(DURATION),
+        "year" | "month" | "day" => Some(NUMBER),
+        _ => None,
+    }
+}
+
+const NUMBER: &str = "a number";
+const DATE: &str = "a date";
+const DURATION: &str = "a duration";
+const MATRIX: &str = "a matrix";
+
+/// The kind of value an arithmetic operator gives for operands of these kinds, if it takes them
+///
+/// This follows the rules the evaluator applies to mixed values.
+fn arithmetic(op: &str, left: &'static str, right: &'static str) -> Option<&'static str> {
+    Some(match (op, left, right) {
+        (_, NUMBER, NUMBER) => NUMBER,
+        ("-", DATE, DATE) => DURATION,
+        ("+", DATE, DURATION) | ("+", DURATION, DATE) | ("-", DATE, DURATION) => DATE,
+        ("+" | "-", DURATION, DURATION) => DURATION,
+        ("*", DURATION, NUMBER) | ("*", NUMBER, DURATION) | ("/", DURATION, NUMBER) => DURATION,
+        ("/", DURATION, DURATION) => NUMBER,
+        ("+" | "-" | "*", MATRIX, MATRIX) => MATRIX,
+        ("*", MATRIX, NUMBER) | ("*", NUMBER, MATRIX) | ("/", MATRIX, NUMBER) => MATRIX,
+        _ => return None,
+    })
+}
+
+/// Walks a formula in post-order alongside its spans, collecting errors from calls, variables and operators
+struct Checker<'a> {
+    /// The functions that can be called
+    functions: &'a BTreeMap<String, Known>,
+    /// The variables assigned on earlier lines, or from outside the file
+    assigned: &'a BTreeSet<String>,
+    /// Parameters and `let` names in scope, innermost last, with the kind of value they hold if known
+    bound: Vec<(&'a str, Option<&'static str>)>,
+    /// Node spans in post-order, as returned by `parse_with_spans`
+    spans: &'a [Span],
+    /// Index of the span belonging to the next node visited
+    next: usize,
+    /// The kinds of value of the nodes visited whose parent hasn't been, where known
+    kinds: Vec<Option<&'static str>>,
+    /// Variables used that aren't parameters or `let` names
+    read: BTreeSet<&'a str>,
+    /// Errors found so far, with where they are
+    errors: Vec<(Span, EvaluationError)>,
+}
+
+impl<'a> Checker<'a> {
+    /// Visit a node and its children
+    ///
+    /// Left operands are walked in a loop rather than by recursion, so that
+    /// a long chain like `1 + 1 + … + 1` doesn't overflow the stack.
+    fn walk(&mut self, expr: &'a Expr) {
+        let mut spine = Vec::new();
+        let mut first = expr;
+        while let Expr::Add(left, _)
+        | Expr::Sub(left, _)
+        | Expr::Mul(left, _)
+        | Expr::Div(left, _)
+        | Expr::Compare(_, left, _)
+        | Expr::And(left, _)
+        | Expr::Or(left, _) = first
+        {
+            spine.push(first);
+            first = left;
+        }
+
+        let children = match first {
+            Expr::Let(name, value, body) => {
+                self.walk(value);
+                let kind = self.kinds.last().copied().flatten();
+                self.bound.push((name, kind));
+                self.walk(body);
+                self.bound.pop();
+                2
+            }
+            _ => {
+                let children = first.children();
+                for child in &children {
+                    self.walk(child);
+                }
+                children.len()
+            }
+        };
+        self.visit(first, children);
+        for node in spine.into_iter().rev() {
+            self.walk(node.children()[1]);
+            self.visit(node, 2);
+        }
+    }
+
+    /// Check a node whose `children` have been visited
+    fn visit(&mut self, expr: &'a Expr, children: usize) {
+        let kinds = self.kinds.split_off(self.kinds.len() - children);
+
+        let span = self.spans[self.next];
+        self.next += 1;
+        let kind = match self.check(expr, &kinds) {
+            Ok(kind) => kind,
+            Err(error) => {
+                self.errors.push((span, error));
+                None
+            }
+        };
+        self.kinds.push(kind);
+        if let Expr::Var(name) = expr
+            && !self.bound.iter().any(|(bound, _)| bound == name)
+        {
+            self.read.insert(name);
+        }
+    }
+
+    /// The kind of value a node gives, if known, from the kinds of its children
+    fn check(
+        &self,
+        expr: &Expr,
+        kinds: &[Option<&'static str>],
+    ) -> Result<Option<&'static str>, EvaluationError> {
+        // A truth value, which only numbers have
+        let truth = |kind: Option<&'static str>| match kind {
+            Some(found) if found != NUMBER => Err(EvaluationError::TypeMismatch {
+                expected: NUMBER,
+                found,
+            }),
+            _ => Ok(Some(NUMBER)),
+        };
+        let op = match expr {
+            Expr::Add(..) => "+",
+            Expr::Sub(..) => "-",
+            Expr::Mul(..) => "*",
+            Expr::Div(..) => "/",
+            Expr::Compare(comparison, ..) => comparison.symbol(),
+            _ => "",
+        };
+        Ok(match (expr, kinds) {
+            (Expr::Float(_), _) => Some(NUMBER),
+            (Expr::List(_), _) => Some(MATRIX),
+            (Expr::Var(name), _) => {
+                match self.bound.iter().rev().find(|(bound, _)| bound == name) {
+                    Some((_, kind)) => *kind,
+                    None if self.assigned.contains(name) => None,
+                    None => return Err(EvaluationError::UndefinedVariable(name.clone())),
+                }
+            }
+            (Expr::Let(..), [_, body]) => *body,
+            (Expr::Call(name, args), _) => match self.functions.get(name) {
+                Some(known) if !known.arity.accepts(args.len()) => {
+                    return Err(EvaluationError::WrongArgumentCount {
+                        signature: known.signature.as_str().into(),
+                        expected: known.arity,
+                        found: args.len(),
+                    });
+                }
+                Some(known) => known.returns,
+                None => return Err(EvaluationError::UnknownFunction(name.clone())),
+            },
+            (
+                Expr::Add(..) | Expr::Sub(..) | Expr::Mul(..) | Expr::Div(..),
+                [Some(left), Some(right)],
+            ) => match arithmetic(op, left, right) {
+                Some(kind) => Some(kind),
+                None => return Err(EvaluationError::UnsupportedOperands { op, left, right }),
+            },
+            (Expr::Compare(..), [Some(left), Some(right)]) if left != right || *left == MATRIX => {
+                return Err(EvaluationError::UnsupportedOperands { op, left, right });
+            }
+            (Expr::Compare(..), _) => Some(NUMBER),
+            (Expr::Neg(_), [Some(DATE)]) => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a number, a duration or a matrix",
+                    found: DATE,
+                });
+            }
+            (Expr::Neg(_), [kind]) => *kind,
+            (Expr::Not(_), [kind]) => truth(*kind)?,
+            (Expr::And(..) | Expr::Or(..), [left, right]) => {
+                truth(*left)?;
+                truth(*right)?
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test each kind of finding, and where it's reported
+    #[test]
+    fn test_lint_file() {
+        let source = "# Prices
+rate = 0.2
+net(gross) = gross / (1 + rate)  # after tax
+total = net(price) * qty
+sqrt(1, 2) + fee(3) + (rate)
+x = let t = 2 in t * y
+bad = 1 +* 2
+";
+        let limits = Limits {
+            variables: ["price".to_string()].into(),
+            ..Limits::default()
+        };
+        let found: Vec<(usize, usize, Severity, Option<&str>)> = lint_file(source, &limits)
+            .into_iter()
+            .map(|finding| (finding.line, finding.column, finding.severity, finding.code))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (4, 22, Severity::Error, Some("E0002")),
+                (5, 1, Severity::Error, Some("E0006")),
+                (5, 14, Severity::Error, Some("E0005")),
+                (5, 23, Severity::Warning, Some("W0001")),
+                (6, 22, Severity::Error, Some("E0002")),
+                (7, 10, Severity::Error, Some("P0001")),
+            ]
+        );
+
+        let limits = Limits {
+            max_depth: 2,
+            max_nodes: 4,
+            ..Limits::default()
+        };
+        let findings = lint_file("1 + 2\n1 + 2 + 3", &limits);
+        let messages: Vec<&str> = findings
+            .iter()
+            .map(|finding| finding.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "formula is nested 3 deep, more than the limit of 2",
+                "formula has 5 nodes, more than the limit of 4"
+            ]
+        );
+
+        // Only the first assignment of `x` is overwritten before it's used
+        let findings = lint_file("x = 1\n  x = 2\ny = x\nx = y + 1", &Limits::default());
+        let found: Vec<(usize, usize, Option<&str>)> = findings
+            .iter()
+            .map(|finding| (finding.line, finding.column, finding.code))
+            .collect();
+        assert_eq!(found, [(1, 1, Some("W0005"))]);
+    }
+
+    /// Test that functions can call themselves, and that operators are checked against the kinds of values they get
+    #[test]
+    fn test_definitions_and_types() {
+        let source = "fact(n) = piecewise((n <= 1, 1), n * fact(n - 1))
+due = date(2024, 1, 31) + days(30) * 2
+late = now() - date(2024, 1, 1) > 7
+date(2024, 1, 1) + 1
+-now() + (let d = days(1) in d * d)
+[1, 2] < 3 || !date(2024, 1, 1)
+";
+        let findings = lint_file(source, &Limits::default());
+        let found: Vec<(usize, usize, Option<&str>)> = findings
+            .iter()
+            .map(|finding| (finding.line, finding.column, finding.code))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (3, 8, Some("E0009")),
+                (4, 1, Some("E0009")),
+                (5, 1, Some("E0008")),
+                (5, 30, Some("E0009")),
+                (6, 1, Some("E0009")),
+                (6, 15, Some("E0008")),
+            ]
+        );
+        assert_eq!(
+            findings[1].message,
+            "Can't use '+' with a date and a number"
+        );
+        assert_eq!(
+            findings[3].message,
+            "Can't use '*' with a duration and a duration"
+        );
+    }
+}
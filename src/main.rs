@@ -1,18 +1,136 @@
-use ast::{evaluate, parse_expression};
-use std::io::{self, Write};
+mod completions;
+mod explorer;
+mod generate;
+mod linter;
+mod plot;
+mod reformat;
+mod render;
+#[cfg(feature = "serve")]
+mod serve;
+mod settings;
+#[cfg(feature = "tui")]
+mod tui;
+
+use ast::{
+    Assignee, Diagnostic, EvaluationError, Expr, Instruction, Notation, ParserConfig, Session,
+    SessionError, Span, Value, evaluate_partial, explain, explain_code, lint, lower,
+    parse_expression, parse_traced, parse_with_recovery, parse_with_spans,
+};
+use explorer::{Explorer, Key};
+use plot::Plot;
+use render::{Renderer, Style};
+use settings::{ColorMode, Settings, Verbosity};
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
+
+/// Why the program failed, where the discriminant is its exit code
+///
+/// Anything else that goes wrong, such as not being able to read the
+/// input, exits with 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Input that doesn't parse: an expression, or the command line
+    Syntax = 2,
+    /// An expression that parsed, but couldn't be evaluated
+    Evaluation = 3,
+}
 
 /// Main function - Entry point for the interactive REPL
 ///
-/// The REPL continues until the user types "quit" or "exit".
+/// The REPL continues until the user types "quit" or "exit". Settings come
+/// from the config file and the command line, see [`settings`]. The exit
+/// code is that of the last line that failed, see [`Failure`].
 fn main() {
-    println!("🧮 AST Calculator REPL");
-    println!("Enter mathematical expressions to see the AST and result.");
-    println!("Examples: '3 + 4 * 2', '(5 - 3) * 2.5', '-10 + 5'");
-    println!("Type 'quit' or 'exit' to close.\n");
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "completions").is_some() {
+        match args.next().as_deref().and_then(completions::script) {
+            Some(script) => print!("{}", script),
+            None => {
+                eprintln!("❌ completions: expected bash, zsh or fish");
+                process::exit(Failure::Syntax as i32);
+            }
+        }
+        return;
+    }
+    if args.next_if(|arg| arg == "gen").is_some() {
+        match generate::run(args) {
+            Ok(()) => {}
+            Err(message) if message == generate::USAGE => println!("{}", message),
+            Err(message) => {
+                eprintln!("❌ gen: {}\n\n{}", message, generate::USAGE);
+                process::exit(Failure::Syntax as i32);
+            }
+        }
+        return;
+    }
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        match reformat::run(args) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(message) if message == reformat::USAGE => println!("{}", message),
+            Err(message) => {
+                eprintln!("❌ fmt: {}\n\n{}", message, reformat::USAGE);
+                process::exit(Failure::Syntax as i32);
+            }
+        }
+        return;
+    }
+    if args.next_if(|arg| arg == "lint").is_some() {
+        match linter::run(args) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(message) if message == linter::USAGE => println!("{}", message),
+            Err(message) => {
+                eprintln!("❌ lint: {}\n\n{}", message, linter::USAGE);
+                process::exit(Failure::Syntax as i32);
+            }
+        }
+        return;
+    }
+    if args.next_if(|arg| arg == "serve").is_some() {
+        serve_command(args);
+        return;
+    }
+    let (settings, warnings) = match Settings::load(args) {
+        Ok(loaded) => loaded,
+        Err(message) if message == settings::USAGE => {
+            println!("{}", message);
+            return;
+        }
+        Err(message) => {
+            eprintln!("❌ {}\n\n{}", message, settings::USAGE);
+            process::exit(Failure::Syntax as i32);
+        }
+    };
+    if !settings.eval.is_empty() {
+        if let Err((failure, message)) = evaluate_arguments(&settings) {
+            eprintln!("❌ {}", message);
+            process::exit(failure as i32);
+        }
+        return;
+    }
+
+    let mut repl = Repl::new(&settings);
+    if repl.verbosity != Verbosity::Quiet {
+        print_banner();
+    }
+    for warning in warnings {
+        repl.warn(format!("⚠️ config: {}", warning));
+    }
+    if let Some(path) = &settings.startup {
+        repl.run_startup(path);
+    }
 
     loop {
-        print!(">>> ");
-        io::stdout().flush().unwrap();
+        if repl.verbosity != Verbosity::Quiet {
+            print!("{}", repl.prompt);
+            io::stdout().flush().unwrap();
+        }
 
         let mut input = String::new();
         match io::stdin().read_line(&mut input) {
@@ -27,35 +145,847 @@ fn main() {
                 if input.is_empty() {
                     continue;
                 }
+                repl.remember(input);
                 if input == "quit" || input == "exit" {
-                    println!("👋");
+                    if repl.verbosity != Verbosity::Quiet {
+                        println!("👋");
+                    }
                     break;
                 }
+                repl.run_line(input);
+            }
+            Err(error) => {
+                repl.fail(format!("❌ reading input: {}", error));
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(failure) = repl.failure {
+        process::exit(failure as i32);
+    }
+}
 
-                // Parse and evaluate the expression
-                match parse_expression(input) {
-                    Ok((remaining, ast)) => {
-                        println!("🌳 AST: {:?}", ast);
+/// Run `ast serve`, if the binary was built with the `serve` feature
+fn serve_command(args: impl Iterator<Item = String>) {
+    #[cfg(feature = "serve")]
+    match serve::run(args) {
+        Ok(()) => {}
+        Err(message) if message == serve::USAGE => println!("{}", message),
+        Err(message) => {
+            eprintln!("❌ serve: {}\n\n{}", message, serve::USAGE);
+            process::exit(Failure::Syntax as i32);
+        }
+    }
+    #[cfg(not(feature = "serve"))]
+    {
+        drop(args);
+        eprintln!("❌ serve: this build doesn't include the server; build with --features serve");
+        process::exit(1);
+    }
+}
 
-                        match evaluate(&ast) {
-                            Ok(result) => println!("✅ result: {}", result),
-                            Err(error) => println!("❌ evaluating: {}", error),
+/// Print what the REPL can do
+fn print_banner() {
+    println!("🧮 AST Calculator REPL");
+    println!("Enter mathematical expressions to see the AST and result.");
+    println!("Examples: '3 + 4 * 2', '(5 - 3) * 2.5', '-10 + 5'");
+    println!("'ans' is the last result, and 'ans(2)' the one before it.");
+    println!("Set variables with 'x = 2', define functions with 'f(x) = x * x',");
+    println!("use ':undo' to take one back, and ':save <file>' and ':load <file>' to keep them.");
+    println!("Use ':explain <expression>' to see how an expression is grouped,");
+    println!("':ir <expression>' to see it lowered to three-address code,");
+    println!("':explore <expression>' to walk its tree with the arrow keys,");
+    println!("':trace on' to watch the parser derive each expression,");
+    println!("':plot <expression> over x in [a, b]' to draw it,");
+    println!("':table <expression>, x = a..b step s' to list its values,");
+    println!("or ':profile <expression>' to see which parts of it are slow.");
+    println!("Use ':precision <digits>', ':sigfigs <digits>' and ':notation <auto|eng|si>'");
+    println!("to change how results are shown.");
+    println!("Type 'quit' or 'exit' to close.\n");
+}
+
+/// Print the value of each `-e` expression, one per line, after running the `-D` assignments
+///
+/// Only the values are printed, so that `$(ast -e ...)` gives a number;
+/// verbose output, the trees and timings, goes to stderr.
+fn evaluate_arguments(settings: &Settings) -> Result<(), (Failure, String)> {
+    let mut session = session(settings);
+    let failed = |input: String, error: SessionError| {
+        let failure = match error {
+            SessionError::Syntax { .. } => Failure::Syntax,
+            _ => Failure::Evaluation,
+        };
+        (failure, format!("{}: {}", input, error))
+    };
+    for define in &settings.defines {
+        if session.split_assignment(define).is_none() {
+            let message = format!("-D {}: expected name=value", define);
+            return Err((Failure::Syntax, message));
+        }
+        session
+            .evaluate(define)
+            .map_err(|error| failed(format!("-D {}", define), error))?;
+    }
+    for expression in &settings.eval {
+        let start = Instant::now();
+        let result = session
+            .evaluate(expression)
+            .map_err(|error| failed(expression.clone(), error))?;
+        if settings.verbosity == Verbosity::Verbose
+            && let Ok((_, ast)) = parse_expression(expression)
+        {
+            eprintln!("🌳 AST: {:?}", ast);
+            eprintln!("⏱️ {}", duration(start.elapsed()));
+        }
+        println!("{}", session.format(&result));
+    }
+    Ok(())
+}
+
+/// A duration in the most readable unit, such as `12.3µs`
+fn duration(duration: Duration) -> String {
+    format!("{:.1?}", duration)
+}
+
+/// A session with the functions and formatting the settings ask for
+fn session(settings: &Settings) -> Session {
+    let mut functions = ast::FunctionRegistry::standard();
+    functions.set_angle_unit(settings.angle);
+    let mut session = Session::new().with_functions(functions);
+    session.config_mut().format.precision = settings.precision;
+    session
+}
+
+/// The REPL's state between lines
+struct Repl {
+    /// Variables, functions, results and formatting
+    session: Session,
+    /// The input prompt
+    prompt: String,
+    /// How output is colored, if at all
+    render: Renderer,
+    /// Where input lines are appended, until that fails
+    history: Option<PathBuf>,
+    /// Whether to show the rules the parser goes through for each expression
+    trace: bool,
+    /// How much to print besides results
+    verbosity: Verbosity,
+    /// What went wrong with the last line that failed, for the exit code
+    failure: Option<Failure>,
+}
+
+impl Repl {
+    /// Set up a session according to the settings
+    fn new(settings: &Settings) -> Self {
+        let color = match settings.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        };
+        Repl {
+            session: session(settings),
+            prompt: settings.prompt.clone(),
+            render: Renderer::new(color.then_some(settings.theme)),
+            history: settings.history.clone(),
+            trace: false,
+            verbosity: settings.verbosity,
+            failure: None,
+        }
+    }
+
+    /// Print a warning, unless only results are wanted
+    fn warn(&self, message: String) {
+        if self.verbosity != Verbosity::Quiet {
+            println!("{}", self.render.paint(Style::Warning, message));
+        }
+    }
+
+    /// Print an error, on stderr if only results are wanted on stdout
+    fn fail(&self, message: String) {
+        let message = self.render.paint(Style::Error, message);
+        if self.verbosity == Verbosity::Quiet {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Run the lines of a startup file, showing each one after the prompt
+    fn run_startup(&mut self, path: &PathBuf) {
+        match fs::read_to_string(path) {
+            Ok(script) => {
+                for line in script.lines().map(str::trim) {
+                    if !line.is_empty() && !line.starts_with('#') {
+                        println!("{}{}", self.prompt, line);
+                        self.run_line(line);
+                    }
+                }
+            }
+            Err(error) => println!(
+                "{}",
+                self.render.paint(
+                    Style::Error,
+                    format!("❌ startup file {}: {}", path.display(), error)
+                )
+            ),
+        }
+    }
+
+    /// Append an input line to the history file
+    fn remember(&mut self, input: &str) {
+        let Some(path) = &self.history else {
+            return;
+        };
+        let appended = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::OpenOptions::new().create(true).append(true).open(path))
+            .and_then(|mut file| writeln!(file, "{}", input));
+        if let Err(error) = appended {
+            // Only say so once
+            println!(
+                "{}",
+                self.render.paint(
+                    Style::Error,
+                    format!("❌ history file {}: {}", path.display(), error)
+                )
+            );
+            self.history = None;
+        }
+    }
+
+    /// Run a line of input: a command, an assignment or an expression
+    fn run_line(&mut self, input: &str) {
+        // Handle REPL commands such as ":explain 2 + 3 * 4"
+        if let Some(command) = input.strip_prefix(':') {
+            self.run_command(command);
+            println!();
+            return;
+        }
+
+        // An assignment such as "x = 2 * y" sets a variable to the expression's
+        // value, and one such as "f(x) = x * x" defines a function
+        let (assignee, expression) = match self.session.split_assignment(input) {
+            Some((assignee, expression)) => (Some(assignee), expression),
+            None => (None, input),
+        };
+        // Where the expression starts on the input line, for underlining errors in it
+        let indent = input.len() - expression.len();
+
+        if self.trace {
+            let (_, trace) = parse_traced(expression, &ParserConfig::default());
+            println!("🔎 parser trace:");
+            for line in trace.to_string().lines() {
+                println!("   {}", self.render.paint(Style::Hint, line));
+            }
+        }
+
+        // Parse and evaluate the expression
+        let parsing = Instant::now();
+        match parse_expression(expression) {
+            Ok((remaining, ast)) => {
+                let parsed = parsing.elapsed();
+                if self.verbosity != Verbosity::Quiet {
+                    println!("🌳 AST: {}", self.render.ast(&ast));
+                }
+
+                // An expression's value, which is all that's printed when quiet
+                let mut value = None;
+                let evaluating = Instant::now();
+                let message = match assignee {
+                    Some(Assignee::Function { name, params }) => {
+                        match self.session.define(name, &params, expression) {
+                            Ok(()) => {
+                                let mut message =
+                                    format!("✅ defined {}({})", name, params.join(", "));
+                                let captured = self.session.captured(name).into_iter();
+                                let captured: Vec<String> = captured
+                                    .flat_map(|env| env.iter())
+                                    .map(|(name, value)| format!("{} = {}", name, value))
+                                    .collect();
+                                if !captured.is_empty() {
+                                    message += &format!(", keeping {}", captured.join(", "));
+                                }
+                                Ok(message)
+                            }
+                            Err(error) => Err(format!("❌ defining: {}", error)),
+                        }
+                    }
+                    Some(Assignee::Variable(name)) => match self.session.assign(name, &ast) {
+                        Ok(result) => Ok(format!("✅ {} = {}", name, self.session.format(&result))),
+                        Err(error) => Err(format!(
+                            "❌ evaluating [{}]: {}",
+                            error.code(),
+                            error_message(&error)
+                        )),
+                    },
+                    None => match self.session.evaluate_expr(&ast) {
+                        Ok(result) => {
+                            let formatted = self.session.format(&result);
+                            let message = format!("✅ result: {}", formatted);
+                            value = Some(formatted);
+                            Ok(message)
                         }
+                        Err(error) => Err(format!(
+                            "❌ evaluating [{}]: {}",
+                            error.code(),
+                            error_message(&error)
+                        )),
+                    },
+                };
+                let evaluated = evaluating.elapsed();
+                match message {
+                    Ok(_) if self.verbosity == Verbosity::Quiet => {
+                        if let Some(value) = value {
+                            println!("{}", value);
+                        }
+                    }
+                    Ok(message) => println!("{}", self.render.paint(Style::Success, message)),
+                    Err(message) => {
+                        self.failure = Some(Failure::Evaluation);
+                        self.fail(message);
+                    }
+                }
+                if self.verbosity == Verbosity::Verbose {
+                    let timing = format!(
+                        "⏱️ parsed in {}, evaluated in {}",
+                        duration(parsed),
+                        duration(evaluated)
+                    );
+                    println!("{}", self.render.paint(Style::Hint, timing));
+                }
+
+                if !remaining.trim().is_empty() {
+                    self.failure = Some(Failure::Syntax);
+                    self.warn(format!("⚠️ unparsed input: '{}'", remaining));
+                }
+
+                if self.verbosity != Verbosity::Quiet {
+                    for warning in lint(expression) {
+                        self.underline(expression, indent, warning.span);
+                        let code = warning.code.unwrap_or_default();
+                        let warning =
+                            format!("⚠️ warning [{}]: {}", code, diagnostic_message(&warning));
+                        println!("{}", self.render.paint(Style::Warning, warning));
+                    }
+                }
+            }
+            Err(_) => {
+                self.failure = Some(Failure::Syntax);
+                self.report_syntax_errors(expression, indent);
+            }
+        }
+        if self.verbosity != Verbosity::Quiet {
+            println!();
+        }
+    }
+
+    /// Run a REPL command, given without its leading ':'
+    ///
+    /// `:precision`, `:sigfigs` and `:notation` change the format that results are shown in.
+    fn run_command(&mut self, command: &str) {
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        // Where the argument starts on the input line, for underlining errors in it
+        let indent = command.len() - argument.len() + 1;
 
-                        if !remaining.trim().is_empty() {
-                            println!("⚠️ unparsed input: '{}'", remaining);
+        let format = &mut self.session.config_mut().format;
+        match name {
+            // ":explain P0003" explains a diagnostic code rather than an expression
+            "explain" if explain_code(argument.trim()).is_some() => {
+                let explanation = explain_code(argument.trim()).unwrap_or_default();
+                println!(
+                    "{}",
+                    self.render
+                        .paint(Style::Hint, format!("📖 {}", explanation))
+                );
+            }
+            "explain" => match parse_expression(argument) {
+                Ok((remaining, ast)) if remaining.trim().is_empty() => {
+                    let explanation = explain(&ast);
+                    let mut lines = explanation.lines();
+                    if let Some(grouping) = lines.next() {
+                        println!(
+                            "{}",
+                            self.render
+                                .paint(Style::Hint, format!("🧭 grouping: {}", grouping))
+                        );
+                    }
+                    for note in lines {
+                        println!("   {}", note);
+                    }
+                }
+                _ => self.report_syntax_errors(argument, indent),
+            },
+            "ir" => match parse_expression(argument) {
+                Ok((remaining, ast)) if remaining.trim().is_empty() => {
+                    println!("🛠️ three-address code:");
+                    for instruction in lower(&ast) {
+                        match instruction {
+                            Instruction::Label(_) => println!("   {}", instruction),
+                            _ => println!("     {}", instruction),
                         }
                     }
-                    Err(error) => {
-                        println!("🚫 parsing: {:?}", error);
+                }
+                _ => self.report_syntax_errors(argument, indent),
+            },
+            "explore" => {
+                let (ast, diagnostics, spans) = parse_with_spans(argument);
+                if diagnostics.is_empty() {
+                    self.explore(argument, &ast, &spans);
+                } else {
+                    self.report_syntax_errors(argument, indent);
+                }
+            }
+            "plot" => {
+                if let Err(message) = self.plot(argument) {
+                    println!(
+                        "{}",
+                        self.render.paint(Style::Warning, format!("❓ {}", message))
+                    );
+                }
+            }
+            "profile" => match parse_expression(argument) {
+                Ok((remaining, ast)) if remaining.trim().is_empty() => self.profile(&ast),
+                _ => self.report_syntax_errors(argument, indent),
+            },
+            "table" => {
+                if let Err(message) = self.table(argument) {
+                    println!(
+                        "{}",
+                        self.render.paint(Style::Warning, format!("❓ {}", message))
+                    );
+                }
+            }
+            "trace" => {
+                let message = match argument.trim() {
+                    "on" => "🔎 parser trace: on",
+                    "off" => "🔎 parser trace: off",
+                    _ => {
+                        println!(
+                            "{}",
+                            self.render
+                                .paint(Style::Warning, "❓ trace must be 'on' or 'off'")
+                        );
+                        return;
+                    }
+                };
+                self.trace = argument.trim() == "on";
+                println!("{}", self.render.paint(Style::Success, message));
+            }
+            "precision" | "sigfigs" => match argument.trim() {
+                "" | "off" => {
+                    format.precision = None;
+                    println!(
+                        "{}",
+                        self.render
+                            .paint(Style::Success, "🎯 precision: all digits")
+                    );
+                }
+                digits => match digits.parse::<usize>() {
+                    Ok(digits) if digits > 0 => {
+                        format.precision = Some(digits);
+                        // Significant figures keep their trailing zeros
+                        format.trailing_zeros = name == "sigfigs";
+                        println!(
+                            "{}",
+                            self.render.paint(
+                                Style::Success,
+                                format!("🎯 precision: {} significant digits", digits)
+                            )
+                        );
                     }
+                    _ => println!(
+                        "{}",
+                        self.render.paint(
+                            Style::Warning,
+                            format!("❓ {} must be a positive number of digits, or 'off'", name)
+                        )
+                    ),
+                },
+            },
+            "save" | "load" if argument.trim().is_empty() => {
+                println!(
+                    "{}",
+                    self.render
+                        .paint(Style::Warning, format!("❓ :{} needs a file name", name))
+                )
+            }
+            "save" => match self.session.save(argument.trim()) {
+                Ok(()) => println!(
+                    "{}",
+                    self.render.paint(
+                        Style::Success,
+                        format!("💾 saved variables and functions to {}", argument.trim())
+                    )
+                ),
+                Err(error) => println!(
+                    "{}",
+                    self.render.paint(Style::Error, format!("❌ {}", error))
+                ),
+            },
+            "load" => match self.session.load(argument.trim()) {
+                Ok(()) => println!(
+                    "{}",
+                    self.render.paint(
+                        Style::Success,
+                        format!("📂 loaded variables and functions from {}", argument.trim())
+                    )
+                ),
+                Err(error) => println!(
+                    "{}",
+                    self.render.paint(Style::Error, format!("❌ {}", error))
+                ),
+            },
+            "undo" => {
+                if self.session.rollback() {
+                    println!(
+                        "{}",
+                        self.render.paint(
+                            Style::Success,
+                            "↩️ variables and functions restored to before the last change"
+                        )
+                    );
+                } else {
+                    println!(
+                        "{}",
+                        self.render.paint(Style::Warning, "❓ nothing to undo")
+                    );
                 }
-                println!(); 
             }
+            "notation" => {
+                let notation = match argument.trim() {
+                    "auto" => Notation::Auto,
+                    "eng" | "engineering" => Notation::Engineering,
+                    "si" => Notation::Si,
+                    _ => {
+                        println!(
+                            "{}",
+                            self.render
+                                .paint(Style::Warning, "❓ notation must be 'auto', 'eng' or 'si'")
+                        );
+                        return;
+                    }
+                };
+                format.notation = notation;
+                println!(
+                    "{}",
+                    self.render
+                        .paint(Style::Success, format!("🎯 notation: {:?}", notation))
+                );
+            }
+            _ => println!(
+                "❓ unknown command ':{}' (try ':explain <expression>' or ':ir <expression>')",
+                name
+            ),
+        }
+    }
+
+    /// Walk a tree with the explorer until the user quits or the input ends
+    ///
+    /// With the `tui` feature, in a terminal, the explorer is full-screen.
+    fn explore(&self, input: &str, ast: &Expr, spans: &[Span]) {
+        let mut explorer = Explorer::new(input, ast, spans);
+        // Evaluate on a copy of the session, so exploring doesn't add to the history
+        let evaluate = |expr: &Expr| match self.session.clone().evaluate_expr(expr) {
+            Ok(result) => Ok(self.session.format(&result)),
+            Err(error) => Err(error.to_string()),
+        };
+
+        #[cfg(feature = "tui")]
+        if io::stdin().is_terminal() && io::stdout().is_terminal() {
+            let value = |expr: &Expr| evaluate(expr).unwrap_or_else(|error| error);
+            if let Err(error) = tui::explore(&mut explorer, value) {
+                self.fail(format!("❌ explore: {}", error));
+            }
+            return;
+        }
+
+        let value = |expr: &Expr| match evaluate(expr) {
+            Ok(result) => self.render.paint(Style::Success, result),
+            Err(error) => self.render.paint(Style::Error, error),
+        };
+        let clear = io::stdout().is_terminal();
+        loop {
+            if clear {
+                print!("\x1b[H\x1b[2J");
+            }
+            print!("{}", explorer.render(&self.render, value));
+            print!(
+                "{}",
+                self.render
+                    .paint(Style::Hint, "[↑↓←→ or hjkl, Enter folds, q quits] ")
+            );
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            if !matches!(io::stdin().read_line(&mut line), Ok(n) if n > 0) {
+                println!();
+                return;
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if !Key::parse_line(line)
+                .into_iter()
+                .all(|key| explorer.press(key))
+            {
+                return;
+            }
+        }
+    }
+
+    /// Plot `<expression> over <variable> in [<from>, <to>]`, optionally followed by `to <file.svg>`
+    fn plot(&self, argument: &str) -> Result<(), String> {
+        const FORM: &str = ":plot <expression> over <variable> in [<from>, <to>] [to <file.svg>]";
+        let (expression, range) = argument.rsplit_once(" over ").ok_or(FORM)?;
+        let (variable, range) = range.split_once(" in ").ok_or(FORM)?;
+        let (bounds, file) = range
+            .trim()
+            .strip_prefix('[')
+            .and_then(|range| range.split_once(']'))
+            .ok_or(FORM)?;
+        let (from, to) = split_top_level(bounds, ',').ok_or(FORM)?;
+        let file = match file.trim() {
+            "" => None,
+            file => Some(file.strip_prefix("to ").ok_or(FORM)?.trim()),
+        };
+        let (from, to) = (self.constant(from)?, self.constant(to)?);
+
+        let ast = match parse_expression(expression.trim()) {
+            Ok(("", ast)) => ast,
+            _ => return Err(format!("'{}' isn't an expression", expression.trim())),
+        };
+        let variable = variable.trim();
+        let mut session = self.session.clone();
+        let plot = Plot::sample(from, to, plot::WIDTH * 2, |x| {
+            session.environment_mut().set(variable, x);
+            session
+                .evaluate_expr(&ast)
+                .and_then(|value| value.into_number())
+                .ok()
+        });
+
+        let text = plot
+            .text(plot::WIDTH, plot::HEIGHT)
+            .ok_or("the expression has no value anywhere in the range")?;
+        print!("{}", self.render.paint(Style::Hint, text));
+        if let Some(file) = file {
+            let svg = plot.svg().expect("there are values to plot");
+            match fs::write(file, svg) {
+                Ok(()) => println!(
+                    "{}",
+                    self.render
+                        .paint(Style::Success, format!("🖼️ wrote {}", file))
+                ),
+                Err(error) => println!(
+                    "{}",
+                    self.render
+                        .paint(Style::Error, format!("❌ {}: {}", file, error))
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Show which parts of an expression take the most time, over many evaluations
+    fn profile(&self, ast: &Expr) {
+        /// How many times to evaluate, to even out the timings
+        const ITERATIONS: u32 = 1000;
+        /// How many of the slowest parts to show
+        const SHOWN: usize = 10;
+
+        let profile = match self.session.profile(ast, ITERATIONS as usize) {
+            Ok(profile) => profile,
             Err(error) => {
-                println!("❌ reading input: {}", error);
-                break;
+                return self.fail(format!(
+                    "❌ evaluating [{}]: {}",
+                    error.code(),
+                    error_message(&error)
+                ));
+            }
+        };
+        let summary = format!(
+            "⏱️ {} evaluations in {}, {} each, spent in:",
+            ITERATIONS,
+            duration(profile.total),
+            duration(profile.total / ITERATIONS)
+        );
+        println!("{}", self.render.paint(Style::Success, summary));
+        for entry in profile.hot_spots().into_iter().take(SHOWN) {
+            println!(
+                "   {:>5.1}%  {:>9}  {}",
+                profile.share(entry) * 100.0,
+                duration(entry.own / ITERATIONS),
+                entry.expr
+            );
+        }
+    }
+
+    /// Print `<expression>, <variable> = <from>..<to> [step <step>]` as a table of values
+    fn table(&self, argument: &str) -> Result<(), String> {
+        const FORM: &str = ":table <expression>, <variable> = <from>..<to> [step <step>]";
+        /// More rows than this are probably a typo in the step
+        const MAX_ROWS: usize = 1000;
+
+        let (expression, range) = split_top_level(argument, ',').ok_or(FORM)?;
+        let (variable, range) = range.split_once('=').ok_or(FORM)?;
+        let (range, step) = range.split_once(" step ").unwrap_or((range, "1"));
+        let (from, to) = range.split_once("..").ok_or(FORM)?;
+        let (from, to, step) = (
+            self.constant(from)?,
+            self.constant(to)?,
+            self.constant(step)?,
+        );
+        if step.is_nan() || step <= 0.0 {
+            return Err("the step must be positive".to_string());
+        }
+        if (to - from) / step >= MAX_ROWS as f64 {
+            return Err(format!(
+                "that's more than {} rows, try a bigger step",
+                MAX_ROWS
+            ));
+        }
+
+        let (expression, variable) = (expression.trim(), variable.trim());
+        let ast = match parse_expression(expression) {
+            Ok(("", ast)) => ast,
+            _ => return Err(format!("'{}' isn't an expression", expression)),
+        };
+        let rows: Vec<_> = self
+            .session
+            .tabulate(&ast, variable, from..=to, step)
+            .into_iter()
+            .map(|(x, y)| {
+                let x = self.session.format(&Value::Number(x));
+                match y {
+                    Ok(y) => (x, Ok(self.session.format(&Value::Number(y)))),
+                    Err(error) => (x, Err(error.to_string())),
+                }
+            })
+            .collect();
+
+        let width = |text: &str| text.chars().count();
+        let left = rows
+            .iter()
+            .map(|(x, _)| width(x))
+            .fold(width(variable), usize::max);
+        let right = rows
+            .iter()
+            .map(|(_, y)| y.as_ref().map_or(0, |y| width(y)))
+            .fold(width(expression), usize::max);
+        println!(" {:>left$} │ {}", variable, expression);
+        println!("─{}─┼─{}", "─".repeat(left), "─".repeat(right));
+        for (x, y) in rows {
+            let y = match y {
+                Ok(y) => format!("{:>right$}", y),
+                Err(error) => self.render.paint(Style::Error, error),
+            };
+            println!(" {:>left$} │ {}", x, y);
+        }
+        Ok(())
+    }
+
+    /// The value of an expression such as a plot bound, without adding it to the history
+    fn constant(&self, input: &str) -> Result<f64, String> {
+        match parse_expression(input.trim()) {
+            Ok(("", ast)) => self
+                .session
+                .clone()
+                .evaluate_expr(&ast)
+                .and_then(|value| value.into_number())
+                .map_err(|error| format!("'{}': {}", input.trim(), error)),
+            _ => Err(format!("'{}' isn't an expression", input.trim())),
+        }
+    }
+
+    /// Show every syntax error in the input, then previews of the parts that did parse
+    ///
+    /// `indent` is the number of characters on the input line before `input`.
+    fn report_syntax_errors(&self, input: &str, indent: usize) {
+        let format = &self.session.config().format;
+        let (ast, diagnostics) = parse_with_recovery(input);
+
+        for diagnostic in &diagnostics {
+            if self.verbosity != Verbosity::Quiet {
+                self.underline(input, indent, diagnostic.span);
+            }
+            self.fail(format!(
+                "🚫 parsing [{}]: {}",
+                diagnostic.code.unwrap_or_default(),
+                diagnostic_message(diagnostic)
+            ));
+        }
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+
+        for (part, value) in evaluate_partial(&ast) {
+            if matches!(part, Expr::Float(_)) {
+                continue; // A lone number isn't much of a preview
+            }
+            match value {
+                Ok(result) => println!(
+                    "{}",
+                    self.render.paint(
+                        Style::Hint,
+                        format!(
+                            "🔍 partial: {} = {}",
+                            ast::format(part),
+                            format.format(result)
+                        )
+                    )
+                ),
+                Err(error) => println!(
+                    "{}",
+                    self.render.paint(
+                        Style::Hint,
+                        format!("🔍 partial: {} fails: {}", ast::format(part), error)
+                    )
+                ),
+            }
+        }
+    }
+
+    /// Underline a span of the input, below the line it was typed on
+    ///
+    /// `indent` is the number of characters on the input line before `input`.
+    fn underline(&self, input: &str, indent: usize, span: Span) {
+        // The input follows the prompt
+        let column = self.prompt.chars().count() + indent + input[..span.start].chars().count();
+        let width = input[span.start..span.end].chars().count().max(1);
+        let carets = self.render.paint(Style::Error, "^".repeat(width));
+        println!("{}{}", " ".repeat(column), carets);
+    }
+}
+
+/// A diagnostic's message, in the language the environment asks for if built with `l10n`
+fn diagnostic_message(diagnostic: &Diagnostic) -> String {
+    #[cfg(feature = "l10n")]
+    return diagnostic.localized(ast::Locale::from_env());
+    #[cfg(not(feature = "l10n"))]
+    diagnostic.message.clone()
+}
+
+/// An evaluation error's message, in the language the environment asks for if built with `l10n`
+fn error_message(error: &EvaluationError) -> String {
+    #[cfg(feature = "l10n")]
+    return error.localized(ast::Locale::from_env());
+    #[cfg(not(feature = "l10n"))]
+    error.to_string()
+}
+
+/// Split at the first `separator` that isn't inside parentheses
+fn split_top_level(input: &str, separator: char) -> Option<(&str, &str)> {
+    let mut depth = 0usize;
+    for (index, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c == separator && depth == 0 => {
+                return Some((&input[..index], &input[index + c.len_utf8()..]));
             }
+            _ => {}
         }
     }
+    None
 }
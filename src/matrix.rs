@@ -0,0 +1,348 @@
+//! Matrices and vectors
+//!
+//! `[1, 2, 3]` evaluates to a vector and `[[1, 2], [3, 4]]` to a matrix, one
+//! list per row. A vector is a matrix with one column, so `m * v` multiplies
+//! a matrix by a vector. `+` and `-` work element by element on matrices of
+//! the same size, `*` multiplies matrices (or scales one by a number), and
+//! `/` divides a matrix by a number. The functions `dot`, `cross` and `norm`
+//! work on vectors.
+
+use std::fmt;
+
+use crate::{EvaluationError, FunctionRegistry, NumberFormat, Value};
+
+/// A matrix of numbers, stored row by row
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matrix {
+    rows: usize,
+    columns: usize,
+    elements: Vec<f64>,
+}
+
+impl Matrix {
+    /// A matrix with the given elements, row by row, or `None` if there aren't `rows * columns` of them
+    ///
+    /// # Example
+    /// ```
+    /// use ast::Matrix;
+    ///
+    /// let m = Matrix::new(2, 3, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+    /// assert_eq!(m.get(1, 0), Some(4.0));
+    /// assert_eq!(m.to_string(), "[[1, 2, 3], [4, 5, 6]]");
+    /// assert!(Matrix::new(2, 2, vec![1.0]).is_none());
+    /// ```
+    pub fn new(rows: usize, columns: usize, elements: Vec<f64>) -> Option<Self> {
+        (elements.len() == rows * columns).then_some(Matrix {
+            rows,
+            columns,
+            elements,
+        })
+    }
+
+    /// A column vector
+    pub fn vector(elements: Vec<f64>) -> Self {
+        Matrix {
+            rows: elements.len(),
+            columns: 1,
+            elements,
+        }
+    }
+
+    /// The number of rows
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The number of columns
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Whether this is a vector: a matrix with one column
+    pub fn is_vector(&self) -> bool {
+        self.columns == 1
+    }
+
+    /// The elements, row by row
+    pub fn elements(&self) -> &[f64] {
+        &self.elements
+    }
+
+    /// The element at a row and column, counting from 0
+    pub fn get(&self, row: usize, column: usize) -> Option<f64> {
+        (row < self.rows && column < self.columns)
+            .then(|| self.elements[row * self.columns + column])
+    }
+
+    /// One row, counting from 0, as a vector
+    pub(crate) fn row(&self, row: usize) -> Matrix {
+        let start = row * self.columns;
+        Matrix::vector(self.elements[start..start + self.columns].to_vec())
+    }
+
+    /// Stack vectors of the same length as the rows of a matrix
+    pub(crate) fn from_rows(rows: &[Matrix]) -> Result<Matrix, EvaluationError> {
+        let columns = rows.first().map_or(0, |row| row.rows);
+        let mut elements = Vec::with_capacity(rows.len() * columns);
+        for row in rows {
+            if row.rows != columns {
+                return Err(EvaluationError::RaggedRows {
+                    expected: columns,
+                    found: row.rows,
+                });
+            }
+            elements.extend_from_slice(&row.elements);
+        }
+        Ok(Matrix {
+            rows: rows.len(),
+            columns,
+            elements,
+        })
+    }
+
+    /// Apply a function to every element
+    pub(crate) fn map(&self, f: impl Fn(f64) -> f64) -> Matrix {
+        Matrix {
+            elements: self.elements.iter().map(|&x| f(x)).collect(),
+            ..*self
+        }
+    }
+
+    /// Combine the elements of two matrices of the same size, for `op`
+    pub(crate) fn zip(
+        &self,
+        op: &'static str,
+        other: &Matrix,
+        f: impl Fn(f64, f64) -> f64,
+    ) -> Result<Matrix, EvaluationError> {
+        self.expect_size(
+            op,
+            other,
+            self.rows == other.rows && self.columns == other.columns,
+        )?;
+        let elements = self
+            .elements
+            .iter()
+            .zip(&other.elements)
+            .map(|(&l, &r)| f(l, r))
+            .collect();
+        Ok(Matrix { elements, ..*self })
+    }
+
+    /// The matrix product, which needs as many columns on the left as rows on the right
+    pub(crate) fn product(&self, other: &Matrix) -> Result<Matrix, EvaluationError> {
+        self.expect_size("*", other, self.columns == other.rows)?;
+        let mut elements = Vec::with_capacity(self.rows * other.columns);
+        for row in 0..self.rows {
+            for column in 0..other.columns {
+                let sum = (0..self.columns)
+                    .map(|k| {
+                        self.elements[row * self.columns + k]
+                            * other.elements[k * other.columns + column]
+                    })
+                    .sum();
+                elements.push(sum);
+            }
+        }
+        Ok(Matrix {
+            rows: self.rows,
+            columns: other.columns,
+            elements,
+        })
+    }
+
+    /// A dimension mismatch for `op` unless the sizes fit
+    fn expect_size(
+        &self,
+        op: &'static str,
+        other: &Matrix,
+        fits: bool,
+    ) -> Result<(), EvaluationError> {
+        if fits {
+            Ok(())
+        } else {
+            Err(EvaluationError::DimensionMismatch {
+                op,
+                left: (self.rows, self.columns),
+                right: (other.rows, other.columns),
+            })
+        }
+    }
+
+    /// Write this matrix with numbers in the given format
+    ///
+    /// A vector is written as one list, and any other matrix as a list of rows.
+    pub fn format(&self, format: &NumberFormat) -> String {
+        let list = |elements: &[f64]| {
+            let elements: Vec<String> = elements.iter().map(|&x| format.format(x)).collect();
+            format!("[{}]", elements.join(", "))
+        };
+        if self.is_vector() {
+            return list(&self.elements);
+        }
+        let rows: Vec<String> = self
+            .elements
+            .chunks(self.columns.max(1))
+            .map(list)
+            .collect();
+        format!("[{}]", rows.join(", "))
+    }
+}
+
+/// Add the vector functions to a registry
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register_value("dot", 2, |args| {
+        let (a, b) = (vector(&args[0])?, vector(&args[1])?);
+        same_size("dot", a, b, a.rows == b.rows)?;
+        Ok(Value::Number(
+            a.elements.iter().zip(&b.elements).map(|(x, y)| x * y).sum(),
+        ))
+    });
+    registry.register_value("cross", 2, |args| {
+        let (a, b) = (vector(&args[0])?, vector(&args[1])?);
+        same_size("cross", a, b, a.rows == 3 && b.rows == 3)?;
+        let ([ax, ay, az], [bx, by, bz]) = (element3(a), element3(b));
+        Ok(Value::Matrix(Matrix::vector(vec![
+            ay * bz - az * by,
+            az * bx - ax * bz,
+            ax * by - ay * bx,
+        ])))
+    });
+    // The Frobenius norm of a matrix, which is the Euclidean length of a vector
+    registry.register_value("norm", 1, |args| match &args[0] {
+        Value::Matrix(m) => Ok(Value::Number(
+            m.elements.iter().map(|x| x * x).sum::<f64>().sqrt(),
+        )),
+        other => Err(EvaluationError::TypeMismatch {
+            expected: "a matrix",
+            found: other.type_name(),
+        }),
+    });
+}
+
+/// The vector in a function argument
+fn vector(value: &Value) -> Result<&Matrix, EvaluationError> {
+    match value {
+        Value::Matrix(m) if m.is_vector() => Ok(m),
+        other => Err(EvaluationError::TypeMismatch {
+            expected: "a vector",
+            found: other.type_name(),
+        }),
+    }
+}
+
+/// The elements of a vector known to have three
+fn element3(v: &Matrix) -> [f64; 3] {
+    [v.elements[0], v.elements[1], v.elements[2]]
+}
+
+/// An error for function `name` unless the sizes of its arguments fit
+fn same_size(name: &str, a: &Matrix, b: &Matrix, fits: bool) -> Result<(), EvaluationError> {
+    if fits {
+        Ok(())
+    } else {
+        Err(EvaluationError::ArgumentSizes {
+            name: name.into(),
+            left: (a.rows, a.columns),
+            right: (b.rows, b.columns),
+        })
+    }
+}
+
+/// Numbers are written in the default [`NumberFormat`]
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(&NumberFormat::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Evaluator, parse_expression};
+
+    /// Test matrix products, including with vectors, and mismatched sizes
+    #[test]
+    fn test_product() {
+        let m = Matrix::new(2, 2, vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        let v = Matrix::vector(vec![1.0, 1.0]);
+        assert_eq!(m.product(&v).unwrap(), Matrix::vector(vec![3.0, 7.0]));
+        assert_eq!(
+            m.product(&m).unwrap(),
+            Matrix::new(2, 2, vec![7.0, 10.0, 15.0, 22.0]).unwrap()
+        );
+        assert!(matches!(
+            v.product(&m),
+            Err(EvaluationError::DimensionMismatch {
+                op: "*",
+                left: (2, 1),
+                right: (2, 2)
+            })
+        ));
+    }
+
+    /// Test building matrices from rows, and writing them
+    #[test]
+    fn test_rows() {
+        let rows = [
+            Matrix::vector(vec![1.0, 2.0]),
+            Matrix::vector(vec![3.0, 4.5]),
+        ];
+        let m = Matrix::from_rows(&rows).unwrap();
+        assert_eq!((m.rows(), m.columns()), (2, 2));
+        assert_eq!(m.row(1), rows[1]);
+        assert_eq!(m.to_string(), "[[1, 2], [3, 4.5]]");
+        assert_eq!(rows[0].to_string(), "[1, 2]");
+
+        let ragged = [Matrix::vector(vec![1.0]), Matrix::vector(vec![2.0, 3.0])];
+        assert!(matches!(
+            Matrix::from_rows(&ragged),
+            Err(EvaluationError::RaggedRows {
+                expected: 1,
+                found: 2
+            })
+        ));
+    }
+
+    /// Test `dot`, `cross` and `norm`, and their errors
+    #[test]
+    fn test_vector_functions() {
+        let eval = |expression: &str| {
+            let (_, ast) = parse_expression(expression).unwrap();
+            Evaluator::new(&Environment::new()).evaluate_value(&ast)
+        };
+        assert_eq!(
+            eval("dot([1, 2, 3], [4, 5, 6])").unwrap(),
+            Value::Number(32.0)
+        );
+        assert_eq!(
+            eval("cross([1, 0, 0], [0, 1, 0])").unwrap().to_string(),
+            "[0, 0, 1]"
+        );
+        assert_eq!(eval("norm([3, 4])").unwrap(), Value::Number(5.0));
+        assert_eq!(eval("norm([[1, 1], [1, 1]])").unwrap(), Value::Number(2.0));
+
+        assert!(matches!(
+            eval("dot([1, 2], [1, 2, 3])"),
+            Err(EvaluationError::ArgumentSizes { name, left: (2, 1), right: (3, 1) }) if &*name == "dot"
+        ));
+        assert!(matches!(
+            eval("cross([1, 2], [3, 4])"),
+            Err(EvaluationError::ArgumentSizes { .. })
+        ));
+        assert!(matches!(
+            eval("dot([[1, 2]], [1, 2])"),
+            Err(EvaluationError::TypeMismatch {
+                expected: "a vector",
+                found: "a matrix"
+            })
+        ));
+        assert!(matches!(
+            eval("norm(2)"),
+            Err(EvaluationError::TypeMismatch {
+                found: "a number",
+                ..
+            })
+        ));
+    }
+}
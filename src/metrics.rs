@@ -0,0 +1,208 @@
+//! Counting what an engine does, for monitoring
+//!
+//! An [`Engine`](crate::Engine) built with a
+//! [metrics sink](crate::EngineBuilder::metrics) counts parses, cache hits
+//! and misses, evaluations and failures, and times parses and evaluations.
+//! A [`MetricsSink`] passes those on to whatever metrics system is in use,
+//! keyed by each [`Counter`]'s and [`Histogram`]'s name.
+
+use std::time::Duration;
+
+use crate::{EngineEvent, ParseEvalError, Stage};
+
+/// Something an engine counts
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Counter {
+    /// A formula's text was parsed
+    Parses,
+    /// A formula's text was rejected, with the kind of error
+    ParseFailures(&'static str),
+    /// A formula's tree came from the cache
+    CacheHits,
+    /// A formula's tree wasn't cached, so it had to be parsed
+    CacheMisses,
+    /// A formula was evaluated
+    Evaluations,
+    /// Evaluating a formula failed, with the kind of error
+    EvaluationFailures(&'static str),
+}
+
+impl Counter {
+    /// The counter's name, such as `evaluation_failures`
+    pub fn name(self) -> &'static str {
+        match self {
+            Counter::Parses => "parses",
+            Counter::ParseFailures(_) => "parse_failures",
+            Counter::CacheHits => "cache_hits",
+            Counter::CacheMisses => "cache_misses",
+            Counter::Evaluations => "evaluations",
+            Counter::EvaluationFailures(_) => "evaluation_failures",
+        }
+    }
+
+    /// The kind of error, for the failure counters: an evaluation error's
+    /// [code](crate::EvaluationError::code) such as `E0001`, or `syntax`,
+    /// `too_deep`, `literal_too_long` or `name_too_long` for formulas that
+    /// were rejected
+    pub fn kind(self) -> Option<&'static str> {
+        match self {
+            Counter::ParseFailures(kind) | Counter::EvaluationFailures(kind) => Some(kind),
+            _ => None,
+        }
+    }
+}
+
+/// Something an engine times
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Histogram {
+    /// How long parsing a formula's text took
+    ParseDuration,
+    /// How long evaluating a formula took
+    EvaluationDuration,
+}
+
+impl Histogram {
+    /// The histogram's name, such as `evaluation_duration`
+    pub fn name(self) -> &'static str {
+        match self {
+            Histogram::ParseDuration => "parse_duration",
+            Histogram::EvaluationDuration => "evaluation_duration",
+        }
+    }
+}
+
+/// Receives an engine's counts and timings
+///
+/// Called on the thread doing the work, so implementations should be quick
+/// and usually just update atomics or hand off to a metrics library.
+///
+/// # Example
+/// ```
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::time::Duration;
+/// use ast::{Counter, Engine, Histogram, MetricsSink};
+///
+/// #[derive(Default)]
+/// struct Failures(AtomicU64);
+///
+/// impl MetricsSink for Failures {
+///     fn increment(&self, counter: Counter) {
+///         if let Counter::EvaluationFailures(_) = counter {
+///             self.0.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+///
+///     fn record(&self, _: Histogram, _: Duration) {}
+/// }
+///
+/// let failures = std::sync::Arc::new(Failures::default());
+/// let engine = Engine::builder().metrics(std::sync::Arc::clone(&failures)).build();
+/// let vars = engine.environment();
+/// assert!(engine.eval("1 / 0", &vars).is_err());
+/// assert!(engine.eval("1 / 2", &vars).is_ok());
+/// assert_eq!(failures.0.load(Ordering::Relaxed), 1);
+/// ```
+pub trait MetricsSink: Send + Sync {
+    /// Add one to a counter
+    fn increment(&self, counter: Counter);
+
+    /// Add a timing to a histogram
+    fn record(&self, histogram: Histogram, duration: Duration);
+}
+
+impl<T: MetricsSink + ?Sized> MetricsSink for std::sync::Arc<T> {
+    fn increment(&self, counter: Counter) {
+        (**self).increment(counter)
+    }
+
+    fn record(&self, histogram: Histogram, duration: Duration) {
+        (**self).record(histogram, duration)
+    }
+}
+
+/// Pass what an event says to a sink
+pub(crate) fn update(sink: &dyn MetricsSink, event: &EngineEvent<'_>) {
+    match event.stage {
+        Stage::Parse => {
+            sink.increment(Counter::Parses);
+            if let Some(error) = event.error {
+                sink.increment(Counter::ParseFailures(kind(error)));
+            }
+            sink.record(Histogram::ParseDuration, event.duration);
+        }
+        Stage::Compile if event.cached => sink.increment(Counter::CacheHits),
+        Stage::Compile => sink.increment(Counter::CacheMisses),
+        Stage::Evaluate => {
+            sink.increment(Counter::Evaluations);
+            if let Some(error) = event.error {
+                sink.increment(Counter::EvaluationFailures(kind(error)));
+            }
+            sink.record(Histogram::EvaluationDuration, event.duration);
+        }
+    }
+}
+
+/// A short, stable name for the kind of an error
+fn kind(error: &ParseEvalError) -> &'static str {
+    match error {
+        ParseEvalError::Syntax { .. } => "syntax",
+        ParseEvalError::TooDeep { .. } => "too_deep",
+        ParseEvalError::LiteralTooLong { .. } => "literal_too_long",
+        ParseEvalError::NameTooLong { .. } => "name_too_long",
+        ParseEvalError::Evaluation(error) => error.code(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::*;
+    use crate::Engine;
+
+    /// Counts and timings kept in memory
+    #[derive(Default)]
+    struct Recorded {
+        counters: Mutex<HashMap<Counter, u64>>,
+        timings: Mutex<HashMap<Histogram, usize>>,
+    }
+
+    impl MetricsSink for Recorded {
+        fn increment(&self, counter: Counter) {
+            *self.counters.lock().unwrap().entry(counter).or_default() += 1;
+        }
+
+        fn record(&self, histogram: Histogram, _: Duration) {
+            *self.timings.lock().unwrap().entry(histogram).or_default() += 1;
+        }
+    }
+
+    /// Test what an engine counts
+    #[test]
+    fn test_counts() {
+        let recorded = std::sync::Arc::new(Recorded::default());
+        let engine = Engine::builder()
+            .metrics(std::sync::Arc::clone(&recorded))
+            .max_depth(10)
+            .build();
+        let vars = engine.environment();
+        for formula in ["1 / 0", "1 / 0", "x", "1 +", &vec!["1"; 20].join("+"), "2"] {
+            let _ = engine.eval(formula, &vars);
+        }
+
+        let counters = recorded.counters.lock().unwrap();
+        let count = |counter| counters.get(&counter).copied().unwrap_or(0);
+        assert_eq!(count(Counter::Parses), 5);
+        assert_eq!(count(Counter::ParseFailures("syntax")), 1);
+        assert_eq!(count(Counter::ParseFailures("too_deep")), 1);
+        assert_eq!(count(Counter::CacheHits), 1);
+        assert_eq!(count(Counter::CacheMisses), 5);
+        assert_eq!(count(Counter::Evaluations), 4);
+        assert_eq!(count(Counter::EvaluationFailures("E0001")), 2);
+        assert_eq!(count(Counter::EvaluationFailures("E0002")), 1);
+        let timings = recorded.timings.lock().unwrap();
+        assert_eq!(timings[&Histogram::ParseDuration], 5);
+        assert_eq!(timings[&Histogram::EvaluationDuration], 4);
+        assert_eq!(Counter::EvaluationFailures("E0001").kind(), Some("E0001"));
+    }
+}
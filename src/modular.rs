@@ -0,0 +1,296 @@
+//! Arithmetic modulo a number, for number theory and hashing
+//!
+//! [`evaluate_mod`] computes a formula in the integers modulo `m`: every
+//! value is a residue from 0 to `m - 1`, and `+`, `-`, `*` and powers wrap
+//! around, as in clock arithmetic. Dividing by `b` multiplies by the inverse
+//! of `b`, the residue that gives 1 when multiplied by `b`, which exists
+//! when `b` and `m` have no common factor; otherwise the division is an
+//! [`EvaluationError::NoInverse`].
+
+use crate::{EvaluationError, Expr, Number, evaluate_checked};
+
+/// Evaluate `expr` in the integers modulo `m`, giving a residue from 0 to `m - 1`
+///
+/// Number literals must be whole numbers; negative ones, and negations,
+/// wrap around, so `-1` is `m - 1`. Powers, written `pow(b, e)` (or `b ^ e`
+/// and `b ** e` in the Excel and Python dialects), take an ordinary whole
+/// exponent rather than a residue, since `2^10` and `2^(10 mod m)` differ;
+/// the exponent is evaluated as by [`evaluate_checked`], can't use `let`
+/// names, and may be negative when the base has an inverse. Comparisons
+/// compare residues and, like logic, give 0 or 1; `let` and `piecewise`
+/// work as usual. No other functions are available, and there are no
+/// variables. A modulus of 0 is an [`EvaluationError::OutOfDomain`].
+///
+/// # Example
+/// ```
+/// use ast::{evaluate_mod, parse_expression, EvaluationError};
+///
+/// let (_, ast) = parse_expression("3 * 5 + 4").unwrap();
+/// assert_eq!(evaluate_mod(&ast, 7).unwrap(), 5);
+///
+/// // 3 × 5 = 15 = 1 (mod 7), so dividing by 3 multiplies by 5
+/// let (_, ast) = parse_expression("1 / 3 - pow(2, 100)").unwrap();
+/// assert_eq!(evaluate_mod(&ast, 7).unwrap(), 3);
+///
+/// let (_, ast) = parse_expression("1 / 4").unwrap();
+/// assert!(matches!(
+///     evaluate_mod(&ast, 12),
+///     Err(EvaluationError::NoInverse { value: 4, modulus: 12 })
+/// ));
+/// ```
+pub fn evaluate_mod(expr: &Expr, m: u64) -> Result<u64, EvaluationError> {
+    if m == 0 {
+        return Err(EvaluationError::OutOfDomain {
+            name: "mod".into(),
+            value: 0.0,
+        });
+    }
+    Modular {
+        m,
+        bindings: Vec::new(),
+    }
+    .evaluate(expr)
+}
+
+/// The state of one [`evaluate_mod`]
+struct Modular<'a> {
+    /// The modulus
+    m: u64,
+    /// Names bound by `let`, innermost last
+    bindings: Vec<(&'a str, u64)>,
+}
+
+impl<'a> Modular<'a> {
+    /// The residue of `expr`
+    fn evaluate(&mut self, expr: &'a Expr) -> Result<u64, EvaluationError> {
+        let m = self.m;
+        Ok(match expr {
+            Expr::Float(value) => residue(*value, m).ok_or(EvaluationError::TypeMismatch {
+                expected: "a whole number",
+                found: "a fraction",
+            })?,
+            Expr::Var(name) => self
+                .bindings
+                .iter()
+                .rev()
+                .find(|(bound, _)| bound == name)
+                .map(|(_, value)| *value)
+                .ok_or_else(|| EvaluationError::UndefinedVariable(name.to_string()))?,
+            Expr::Add(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                ((u128::from(left) + u128::from(right)) % u128::from(m)) as u64
+            }
+            Expr::Sub(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                ((u128::from(left) + u128::from(m - right)) % u128::from(m)) as u64
+            }
+            Expr::Mul(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                multiply(left, right, m)
+            }
+            Expr::Div(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                multiply(left, self.inverse(right)?, m)
+            }
+            Expr::Neg(inner) => (m - self.evaluate(inner)?) % m,
+            Expr::Compare(comparison, left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                truth(comparison.holds(left, right), m)
+            }
+            Expr::And(left, right) => {
+                let left = self.evaluate(left)? != 0;
+                truth(left && self.evaluate(right)? != 0, m)
+            }
+            Expr::Or(left, right) => {
+                let left = self.evaluate(left)? != 0;
+                truth(left || self.evaluate(right)? != 0, m)
+            }
+            Expr::Not(inner) => truth(self.evaluate(inner)? == 0, m),
+            Expr::Let(name, value, body) => {
+                let value = self.evaluate(value)?;
+                self.bindings.push((name, value));
+                let result = self.evaluate(body);
+                self.bindings.pop();
+                result?
+            }
+            Expr::Piecewise(cases, default) => {
+                for (condition, value) in cases {
+                    if self.evaluate(condition)? != 0 {
+                        return self.evaluate(value);
+                    }
+                }
+                match default {
+                    Some(default) => self.evaluate(default)?,
+                    None => return Err(EvaluationError::DomainError("piecewise".to_string())),
+                }
+            }
+            Expr::Call(name, args) if name == "pow" => {
+                let [base, exponent] = args.as_slice() else {
+                    return Err(EvaluationError::WrongArgumentCount {
+                        signature: name.as_str().into(),
+                        expected: 2.into(),
+                        found: args.len(),
+                    });
+                };
+                let base = self.evaluate(base)?;
+                let Number::Integer(exponent) = evaluate_checked(exponent, &[])? else {
+                    return Err(EvaluationError::TypeMismatch {
+                        expected: "a whole number",
+                        found: "a fraction",
+                    });
+                };
+                let base = if exponent < 0 {
+                    self.inverse(base)?
+                } else {
+                    base
+                };
+                power(base, exponent.unsigned_abs(), m)
+            }
+            Expr::Call(name, _) => return Err(EvaluationError::UnknownFunction(name.to_string())),
+            Expr::Named(_, value) => self.evaluate(value)?,
+            Expr::CellRef(cell) => return Err(EvaluationError::UnresolvedCell(*cell)),
+            Expr::CellRange(from, to) => return Err(EvaluationError::RangeNotAllowed(*from, *to)),
+            Expr::List(_) | Expr::Index(..) => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a number",
+                    found: "a matrix",
+                });
+            }
+            Expr::Error(_) => return Err(EvaluationError::ContainsErrors),
+        })
+    }
+
+    /// The residue that gives 1 when multiplied by `value`, by the extended Euclidean algorithm
+    fn inverse(&self, value: u64) -> Result<u64, EvaluationError> {
+        if value == 0 {
+            return Err(EvaluationError::DivisionByZero);
+        }
+        let m = i128::from(self.m);
+        let (mut r, mut next_r) = (m, i128::from(value));
+        let (mut t, mut next_t) = (0_i128, 1_i128);
+        while next_r != 0 {
+            let quotient = r / next_r;
+            (r, next_r) = (next_r, r - quotient * next_r);
+            (t, next_t) = (next_t, t - quotient * next_t);
+        }
+        if r != 1 {
+            return Err(EvaluationError::NoInverse {
+                value,
+                modulus: self.m,
+            });
+        }
+        Ok(t.rem_euclid(m) as u64)
+    }
+}
+
+/// The residue of a whole number modulo `m`, or `None` if it isn't whole
+fn residue(value: f64, m: u64) -> Option<u64> {
+    if value.fract() != 0.0 || !value.is_finite() {
+        return None;
+    }
+    let magnitude = value.abs();
+    let reduced = if magnitude < 2f64.powi(127) {
+        (magnitude as u128 % u128::from(m)) as u64
+    } else {
+        // The mantissa times a power of two, both exact
+        let bits = magnitude.to_bits();
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let exponent = (bits >> 52) - 1075;
+        multiply(mantissa % m, power(2 % m, exponent, m), m)
+    };
+    Some(if value < 0.0 {
+        (m - reduced) % m
+    } else {
+        reduced
+    })
+}
+
+/// `a * b` modulo `m`
+fn multiply(a: u64, b: u64, m: u64) -> u64 {
+    (u128::from(a) * u128::from(b) % u128::from(m)) as u64
+}
+
+/// `base` to the power `exponent` modulo `m`, by repeated squaring
+fn power(mut base: u64, mut exponent: u64, m: u64) -> u64 {
+    let mut result = 1 % m;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(result, base, m);
+        }
+        base = multiply(base, base, m);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// 1 for true and 0 for false, which is 0 modulo 1
+fn truth(holds: bool, m: u64) -> u64 {
+    u64::from(holds) % m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// The residue of an expression, which must parse
+    fn eval(input: &str, m: u64) -> Result<u64, EvaluationError> {
+        let (rest, ast) = parse_expression(input).unwrap();
+        assert_eq!(rest, "");
+        evaluate_mod(&ast, m)
+    }
+
+    /// Test the operators, powers and inverses
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval("10 + 5", 12).unwrap(), 3);
+        assert_eq!(eval("2 - 5", 12).unwrap(), 9);
+        assert_eq!(eval("-1", 12).unwrap(), 11);
+        assert_eq!(eval("7 * 8", 12).unwrap(), 8);
+        assert_eq!(eval("1 / 5 * 5", 12).unwrap(), 1);
+        assert_eq!(eval("pow(3, -1) == 1 / 3", 7).unwrap(), 1);
+        assert_eq!(eval("let k = 3 in k * k + pow(k, 0)", 5).unwrap(), 0);
+        // Fermat: a^(p-1) = 1 for a prime p
+        let p = 1_000_000_007;
+        assert_eq!(eval("pow(123456789, 1000000006)", p).unwrap(), 1);
+        assert_eq!(residue(2f64.powi(200), p), Some(power(2, 200, p)));
+        assert_eq!(eval("-1e20 + 1e20", u64::MAX).unwrap(), 0);
+        assert_eq!(eval("5 + 5", 1).unwrap(), 0);
+    }
+
+    /// Test the errors
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            eval("1 / 6", 9),
+            Err(EvaluationError::NoInverse {
+                value: 6,
+                modulus: 9
+            })
+        ));
+        assert!(matches!(
+            eval("1 / (3 - 3)", 9),
+            Err(EvaluationError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval("0.5", 9),
+            Err(EvaluationError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            eval("sqrt(4)", 9),
+            Err(EvaluationError::UnknownFunction(_))
+        ));
+        assert!(matches!(
+            eval("1", 0),
+            Err(EvaluationError::OutOfDomain { .. })
+        ));
+        assert_eq!(
+            EvaluationError::NoInverse {
+                value: 6,
+                modulus: 9
+            }
+            .to_string(),
+            "6 has no inverse modulo 9"
+        );
+    }
+}
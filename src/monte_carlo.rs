@@ -0,0 +1,259 @@
+//! Monte Carlo propagation of uncertain inputs
+//!
+//! When a formula's inputs are estimates rather than known values,
+//! [`monte_carlo`] draws each input from a [`Distribution`], evaluates the
+//! formula once per draw, and summarizes the spread of the results. The
+//! draws come from a fixed seed, so the same call gives the same summary.
+
+use crate::{
+    Environment, EvaluationError, Expr,
+    differential::Random,
+    evaluate_with,
+    stats::{mean, percentile, variance},
+};
+
+/// The seed every simulation starts from
+const SEED: u64 = 0x5eed;
+
+/// How an uncertain input is spread
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Distribution {
+    /// The bell curve around `mean`
+    Normal { mean: f64, std_dev: f64 },
+    /// Any value from `low` to `high`, each equally likely
+    Uniform { low: f64, high: f64 },
+    /// From `low` to `high`, most likely at `mode` and less likely towards either end
+    Triangular { low: f64, mode: f64, high: f64 },
+}
+
+impl Distribution {
+    /// Whether the parameters describe a distribution
+    fn is_valid(&self) -> bool {
+        let finite = |xs: &[f64]| xs.iter().all(|x| x.is_finite());
+        match *self {
+            Distribution::Normal { mean, std_dev } => finite(&[mean, std_dev]) && std_dev >= 0.0,
+            Distribution::Uniform { low, high } => finite(&[low, high]) && low <= high,
+            Distribution::Triangular { low, mode, high } => {
+                finite(&[low, mode, high]) && low <= mode && mode <= high
+            }
+        }
+    }
+
+    /// A value drawn from the distribution
+    fn sample(&self, random: &mut Random) -> f64 {
+        match *self {
+            Distribution::Normal { mean, std_dev } => {
+                // Box-Muller; 1 - u keeps the logarithm away from zero
+                let (u, v) = (1.0 - random.unit(), random.unit());
+                mean + std_dev * (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+            }
+            Distribution::Uniform { low, high } => low + (high - low) * random.unit(),
+            Distribution::Triangular { low, mode, high } => {
+                let (u, width) = (random.unit(), high - low);
+                if width == 0.0 {
+                    return low;
+                }
+                let split = (mode - low) / width;
+                if u < split {
+                    low + (u * width * (mode - low)).sqrt()
+                } else {
+                    high - ((1.0 - u) * width * (high - mode)).sqrt()
+                }
+            }
+        }
+    }
+}
+
+/// The spread of a formula's results over a simulation
+#[derive(Debug, PartialEq, Clone)]
+pub struct MonteCarloSummary {
+    /// The results that could be evaluated, in ascending order
+    pub values: Vec<f64>,
+    /// How many draws couldn't be evaluated, such as by dividing by zero
+    pub failures: usize,
+    /// The mean result
+    pub mean: f64,
+    /// The sample standard deviation of the results, NaN for fewer than two
+    pub std_dev: f64,
+    /// The smallest result
+    pub min: f64,
+    /// The largest result
+    pub max: f64,
+}
+
+impl MonteCarloSummary {
+    /// The result a fraction `p` (from 0 to 1) of the way through the sorted results
+    pub fn percentile(&self, p: f64) -> f64 {
+        percentile(&self.values, p.clamp(0.0, 1.0))
+    }
+
+    /// The median result
+    pub fn median(&self) -> f64 {
+        self.percentile(0.5)
+    }
+}
+
+/// Evaluate `expr` `n` times, drawing each variable from its distribution
+///
+/// Every variable of `expr` needs a distribution; use a zero-width one, such
+/// as `Uniform { low: 2.0, high: 2.0 }`, for an input that's known exactly.
+/// Draws that can't be evaluated are counted in
+/// [`MonteCarloSummary::failures`]; if none can be, the first error is
+/// returned. Invalid parameters, such as a negative standard deviation, or
+/// an `n` of zero are a [`EvaluationError::DomainError`].
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, monte_carlo, Distribution};
+///
+/// let (_, ast) = parse_expression("cost * (1 + overrun)").unwrap();
+/// let inputs = [
+///     ("cost", Distribution::Normal { mean: 1000.0, std_dev: 50.0 }),
+///     ("overrun", Distribution::Triangular { low: 0.0, mode: 0.1, high: 0.5 }),
+/// ];
+/// let summary = monte_carlo(&ast, &inputs, 10_000).unwrap();
+/// assert!((summary.mean - 1200.0).abs() < 10.0);
+/// assert!(summary.percentile(0.05) < summary.median());
+/// assert!(summary.median() < summary.percentile(0.95));
+/// ```
+pub fn monte_carlo(
+    expr: &Expr,
+    distributions: &[(&str, Distribution)],
+    n: usize,
+) -> Result<MonteCarloSummary, EvaluationError> {
+    if n == 0 || !distributions.iter().all(|(_, d)| d.is_valid()) {
+        return Err(EvaluationError::DomainError("monte_carlo".to_string()));
+    }
+    let mut random = Random(SEED);
+    let mut env = Environment::new();
+    let mut values = Vec::with_capacity(n);
+    let mut first_error = None;
+    for _ in 0..n {
+        for (name, distribution) in distributions {
+            env.set(*name, distribution.sample(&mut random));
+        }
+        match evaluate_with(expr, &env) {
+            Ok(value) => values.push(value),
+            Err(error) => {
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    if values.is_empty()
+        && let Some(error) = first_error
+    {
+        return Err(error);
+    }
+    values.sort_by(f64::total_cmp);
+    Ok(MonteCarloSummary {
+        failures: n - values.len(),
+        mean: mean(&values),
+        std_dev: variance(&values).sqrt(),
+        min: values[0],
+        max: values[values.len() - 1],
+        values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// An expression that must parse
+    fn expr(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test that each distribution's draws have the mean and spread they should
+    #[test]
+    fn test_distributions() {
+        let cases = [
+            (
+                Distribution::Normal {
+                    mean: 5.0,
+                    std_dev: 2.0,
+                },
+                5.0,
+                2.0,
+            ),
+            (
+                Distribution::Uniform {
+                    low: 0.0,
+                    high: 12.0,
+                },
+                6.0,
+                12f64.sqrt(),
+            ),
+            // The mean is (low + mode + high) / 3, and the variance 1/18 of
+            // (a² + b² + c² - ab - ac - bc)
+            (
+                Distribution::Triangular {
+                    low: 0.0,
+                    mode: 3.0,
+                    high: 6.0,
+                },
+                3.0,
+                (27.0f64 / 18.0).sqrt(),
+            ),
+        ];
+        for (distribution, expected_mean, expected_std_dev) in cases {
+            let summary = monte_carlo(&expr("x"), &[("x", distribution)], 20_000).unwrap();
+            assert!(
+                (summary.mean - expected_mean).abs() < 0.05,
+                "{:?}",
+                distribution
+            );
+            assert!(
+                (summary.std_dev - expected_std_dev).abs() < 0.05,
+                "{:?}",
+                distribution
+            );
+        }
+        let uniform = monte_carlo(
+            &expr("x"),
+            &[(
+                "x",
+                Distribution::Uniform {
+                    low: 1.0,
+                    high: 2.0,
+                },
+            )],
+            1000,
+        )
+        .unwrap();
+        assert!(uniform.min >= 1.0 && uniform.max < 2.0);
+        assert_eq!(uniform.failures, 0);
+    }
+
+    /// Test failing draws, missing variables and invalid parameters
+    #[test]
+    fn test_errors() {
+        let inputs = [(
+            "x",
+            Distribution::Uniform {
+                low: -1.0,
+                high: 1.0,
+            },
+        )];
+        let summary = monte_carlo(&expr("sqrt(x)"), &inputs, 1000).unwrap();
+        assert!(summary.failures > 400 && summary.failures < 600);
+        assert_eq!(summary.values.len() + summary.failures, 1000);
+        assert!(matches!(
+            monte_carlo(&expr("x + y"), &inputs, 10),
+            Err(EvaluationError::UndefinedVariable(name)) if name == "y"
+        ));
+        let backwards = [(
+            "x",
+            Distribution::Uniform {
+                low: 1.0,
+                high: -1.0,
+            },
+        )];
+        assert!(matches!(
+            monte_carlo(&expr("x"), &backwards, 10),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(monte_carlo(&expr("x"), &inputs, 0).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Watching what an engine does
+//!
+//! A service evaluating many formulas wants to know which are slow or fail
+//! often. An [`Engine`](crate::Engine) built with an
+//! [observer](crate::EngineBuilder::observer) reports every parse, compile and
+//! evaluation to it as an [`EngineEvent`], with how long it took and how big
+//! the formula is. Any closure taking an event is an observer:
+//!
+//! ```
+//! use ast::{Engine, EngineEvent, Stage};
+//!
+//! let engine = Engine::builder()
+//!     .observer(|event: &EngineEvent| {
+//!         if event.stage == Stage::Evaluate {
+//!             eprintln!("{} ({} nodes) took {:?}", event.formula, event.nodes, event.duration);
+//!         }
+//!     })
+//!     .build();
+//! engine.eval("2 * 3", &engine.environment()).unwrap();
+//! ```
+//!
+//! With the `tracing` feature, every engine also reports to the
+//! [`tracing`](https://docs.rs/tracing) subscriber, with no observer needed.
+//! Each stage runs in a span named after it (`parse` inside `compile`), with
+//! a `formula` field, and ends with a `DEBUG` event with the fields of its
+//! [`EngineEvent`]. Failures are `WARN` events. Both have the target
+//! `ast::engine`.
+
+use std::{fmt, time::Duration};
+
+use crate::ParseEvalError;
+
+/// What an engine was doing
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Stage {
+    /// Parsing a formula's text, which only happens when its tree isn't cached
+    Parse,
+    /// Getting a formula's tree, from the cache or by parsing it
+    Compile,
+    /// Evaluating a formula
+    Evaluate,
+}
+
+/// One thing an engine did, for an [`EngineObserver`]
+#[derive(Debug, Clone, Copy)]
+pub struct EngineEvent<'a> {
+    /// What the engine was doing
+    pub stage: Stage,
+    /// The formula's text
+    pub formula: &'a str,
+    /// How many nodes the formula's tree has, or 0 if it didn't parse
+    pub nodes: usize,
+    /// Whether the tree came from the cache, for [`Stage::Compile`]
+    pub cached: bool,
+    /// How long it took
+    pub duration: Duration,
+    /// Why it failed, if it did
+    pub error: Option<&'a ParseEvalError>,
+}
+
+/// Receives an [`EngineEvent`] for everything an engine does
+///
+/// Observers are called on the thread doing the work, so they should be quick.
+pub trait EngineObserver: Send + Sync {
+    /// Called once the engine has finished a stage
+    fn observe(&self, event: &EngineEvent<'_>);
+}
+
+impl<F: Fn(&EngineEvent<'_>) + Send + Sync> EngineObserver for F {
+    fn observe(&self, event: &EngineEvent<'_>) {
+        self(event)
+    }
+}
+
+/// The `tracing` span of a stage, which the stage is in until it's dropped
+///
+/// Without the `tracing` feature it does nothing.
+pub(crate) struct StageSpan {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+}
+
+impl StageSpan {
+    /// Enter the span for `stage` of `formula`
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(stage: Stage, formula: &str) -> Self {
+        let span = match stage {
+            Stage::Parse => tracing::debug_span!(target: "ast::engine", "parse", formula),
+            Stage::Compile => tracing::debug_span!(target: "ast::engine", "compile", formula),
+            Stage::Evaluate => tracing::debug_span!(target: "ast::engine", "evaluate", formula),
+        };
+        StageSpan {
+            _entered: span.entered(),
+        }
+    }
+
+    /// Enter the span for `stage` of `formula`
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn enter(_stage: Stage, _formula: &str) -> Self {
+        StageSpan {}
+    }
+}
+
+/// Whether the `tracing` subscriber wants an event for every stage
+pub(crate) fn traced() -> bool {
+    #[cfg(feature = "tracing")]
+    return tracing::enabled!(target: "ast::engine", tracing::Level::DEBUG);
+    #[cfg(not(feature = "tracing"))]
+    false
+}
+
+/// Pass `event` on to the `tracing` subscriber
+#[cfg(feature = "tracing")]
+pub(crate) fn trace(event: &EngineEvent<'_>) {
+    let duration_us = u64::try_from(event.duration.as_micros()).unwrap_or(u64::MAX);
+    match event.error {
+        Some(error) => tracing::warn!(
+            target: "ast::engine",
+            stage = %event.stage,
+            nodes = event.nodes,
+            cached = event.cached,
+            duration_us,
+            %error,
+            "{} failed",
+            event.stage
+        ),
+        None => tracing::debug!(
+            target: "ast::engine",
+            stage = %event.stage,
+            nodes = event.nodes,
+            cached = event.cached,
+            duration_us,
+            "{} finished",
+            event.stage
+        ),
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Parse => "parse",
+            Stage::Compile => "compile",
+            Stage::Evaluate => "evaluate",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::Engine;
+
+    /// Test which events an engine reports, and what's in them
+    #[test]
+    fn test_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        let engine = Engine::builder()
+            .observer(move |event: &EngineEvent| {
+                let summary = (
+                    event.stage,
+                    event.formula.to_string(),
+                    event.nodes,
+                    event.cached,
+                    event.error.map(ToString::to_string),
+                );
+                recorded.lock().unwrap().push(summary);
+            })
+            .build();
+        let vars = engine.environment();
+        engine.eval("1 / x", &vars).unwrap_err();
+        engine.eval("1 / x", &vars).unwrap_err();
+        engine.eval("1 +", &vars).unwrap_err();
+
+        let undefined = Some("Undefined variable 'x'".to_string());
+        let syntax = Some("Syntax error at character 3".to_string());
+        let expected = [
+            (Stage::Parse, "1 / x", 3, false, None),
+            (Stage::Compile, "1 / x", 3, false, None),
+            (Stage::Evaluate, "1 / x", 3, false, undefined.clone()),
+            (Stage::Compile, "1 / x", 3, true, None),
+            (Stage::Evaluate, "1 / x", 3, false, undefined),
+            (Stage::Parse, "1 +", 0, false, syntax.clone()),
+            (Stage::Compile, "1 +", 0, false, syntax),
+        ]
+        .map(|(stage, formula, nodes, cached, error)| {
+            (stage, formula.to_string(), nodes, cached, error)
+        });
+        assert_eq!(*events.lock().unwrap(), expected);
+    }
+
+    /// Test the spans and events sent to a `tracing` subscriber
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing() {
+        use tracing::{
+            Event, Id, Metadata, Subscriber,
+            field::{Field, Visit},
+            span::{Attributes, Record},
+        };
+
+        /// Writes the fields it's shown as `name=value`, leaving out timings
+        #[derive(Default)]
+        struct Fields(Vec<String>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                if field.name() != "duration_us" {
+                    self.0.push(format!("{}={:?}", field.name(), value));
+                }
+            }
+        }
+
+        /// Logs every span and event, with their fields sorted
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl Recorder {
+            fn log(&self, kind: &str, fields: Fields) {
+                let mut fields = fields.0;
+                fields.sort();
+                let line = format!("{} {}", kind, fields.join(" "));
+                self.0.lock().unwrap().push(line);
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields::default();
+                span.record(&mut fields);
+                self.log(span.metadata().name(), fields);
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                self.log(event.metadata().level().as_str(), fields);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Recorder(Arc::clone(&log)), || {
+            let engine = Engine::new();
+            engine.eval("1 / x", &engine.environment()).unwrap_err();
+        });
+        let expected = [
+            r#"compile formula="1 / x""#,
+            r#"parse formula="1 / x""#,
+            "DEBUG cached=false message=parse finished nodes=3 stage=parse",
+            "DEBUG cached=false message=compile finished nodes=3 stage=compile",
+            r#"evaluate formula="1 / x""#,
+            "WARN cached=false error=Undefined variable 'x' message=evaluate failed nodes=3 \
+             stage=evaluate",
+        ];
+        assert_eq!(*log.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,149 @@
+//! Parsing with reused allocations
+//!
+//! Every tree needs a [`String`] for each name and a [`Vec`] for each
+//! argument list, which adds up for a server parsing thousands of small
+//! formulas a second. A [`Parser`] keeps those from the trees handed back to
+//! [`Parser::recycle`] and builds the next trees from them. The boxes around
+//! each operand are still allocated for every tree.
+
+use std::mem;
+
+use nom::IResult;
+
+use crate::{Context, Expr, ParserConfig, Spare, parse_expression_as};
+
+/// The most spare names, and the most spare lists, a parser keeps
+const SPARE_LIMIT: usize = 1024;
+
+/// Parses expressions, building each tree partly out of earlier ones
+///
+/// [`Parser::parse`] gives the same trees as
+/// [`parse_expression_with`](crate::parse_expression_with). Giving a tree
+/// back with [`Parser::recycle`] once it's no longer needed lets the next
+/// parses reuse its names and lists; dropping it instead is fine too.
+///
+/// # Example
+/// ```
+/// use ast::{Environment, Parser, ParserConfig, evaluate_with};
+///
+/// let mut parser = Parser::new(ParserConfig::default());
+/// let env: Environment = [("x", 4.0)].into_iter().collect();
+/// let mut results = Vec::new();
+/// for formula in ["x * 3", "max(x, 5)", "sqrt(x) + x"] {
+///     let (_, ast) = parser.parse(formula).unwrap();
+///     results.push(evaluate_with(&ast, &env).unwrap());
+///     parser.recycle(ast);
+/// }
+/// assert_eq!(results, [12.0, 5.0, 6.0]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    /// What to accept
+    config: ParserConfig,
+    /// Names and lists from recycled trees
+    spare: Spare<String>,
+    /// The nodes [`Parser::recycle`] has yet to take apart
+    stack: Vec<Expr>,
+}
+
+impl Parser {
+    /// A parser for the syntax `config` describes, with nothing to reuse yet
+    pub fn new(config: ParserConfig) -> Self {
+        Parser {
+            config,
+            ..Parser::default()
+        }
+    }
+
+    /// What the parser accepts
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
+    }
+
+    /// Parse an expression, as [`parse_expression_with`](crate::parse_expression_with) does
+    pub fn parse<'a>(&mut self, input: &'a str) -> IResult<&'a str, Expr> {
+        parse_expression_as(input, &mut Context::new(&self.config, &mut self.spare))
+    }
+
+    /// Take a tree apart, keeping its names and lists for later parses
+    ///
+    /// Like dropping, this works one node at a time, so a tree of any size
+    /// can be recycled.
+    pub fn recycle(&mut self, expr: Expr) {
+        let Parser { spare, stack, .. } = self;
+        stack.push(expr);
+        while let Some(mut node) = stack.pop() {
+            node.each_child_mut(|child| {
+                if !matches!(child, Expr::Float(_)) {
+                    stack.push(mem::replace(child, Expr::Float(0.0)));
+                }
+            });
+            match &mut node {
+                Expr::Var(name) | Expr::Let(name, ..) | Expr::Named(name, _) => {
+                    keep(&mut spare.names, mem::take(name))
+                }
+                Expr::Call(name, args) => {
+                    keep(&mut spare.names, mem::take(name));
+                    args.clear();
+                    keep(&mut spare.lists, mem::take(args));
+                }
+                Expr::List(elements) | Expr::Index(_, elements) => {
+                    elements.clear();
+                    keep(&mut spare.lists, mem::take(elements));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Add an allocation to the spares, unless there are enough already
+fn keep<T>(spares: &mut Vec<T>, spare: T) {
+    if spares.len() < SPARE_LIMIT {
+        spares.push(spare);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dialect, parse_expression_with};
+
+    /// Test that recycled trees don't change what later parses give
+    #[test]
+    fn test_reuse() {
+        let config = ParserConfig {
+            dialect: Dialect::Python,
+            ..ParserConfig::default()
+        };
+        let mut parser = Parser::new(config.clone());
+        let formulas = [
+            "let total = a + b in total * 2",
+            "max(x, [1, 2, m[1, 2]]) - math.sqrt(y) ** 2",
+            "piecewise((x < 0, -x), x)",
+            "f()",
+            "z",
+        ];
+        for _ in 0..3 {
+            for formula in formulas {
+                let parsed = parser.parse(formula).unwrap();
+                let expected = parse_expression_with(formula, &config).unwrap();
+                assert_eq!(parsed, expected, "{}", formula);
+                parser.recycle(parsed.1);
+            }
+        }
+        assert!(!parser.spare.names.is_empty());
+        assert!(!parser.spare.lists.is_empty());
+    }
+
+    /// Test that a long chain can be recycled, and that the spares are limited
+    #[test]
+    fn test_recycle_limit() {
+        let mut parser = Parser::default();
+        let chain = vec!["x"; 100_000].join(" + ");
+        let (_, ast) = parser.parse(&chain).unwrap();
+        parser.recycle(ast);
+        assert_eq!(parser.spare.names.len(), SPARE_LIMIT);
+        assert!(parser.stack.is_empty());
+    }
+}
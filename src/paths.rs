@@ -0,0 +1,298 @@
+//! Finding and replacing subtrees by path
+//!
+//! A path lists the indices of the children to follow from the root, in the
+//! order [`Expr::children`] gives them: for `a + b * c`, `[1]` is `b * c` and
+//! `[1, 0]` is `b`. The empty path is the root itself. These are the paths
+//! that [`diff`](crate::diff) reports changes at.
+
+use std::mem;
+
+use crate::{
+    Expr,
+    explain::{binary, precedence},
+};
+
+/// A step in a path: which of a node's [`Expr::children`] to go to
+pub type ChildIndex = usize;
+
+/// Every node in a tree with its path, parents before their children
+///
+/// Created by [`Expr::subexpressions`].
+pub struct Subexpressions<'a> {
+    /// Nodes still to visit, the next one last, with the length of their
+    /// path and the last step in it
+    pending: Vec<(usize, ChildIndex, &'a Expr)>,
+    /// The path of the node visited last
+    path: Vec<ChildIndex>,
+}
+
+impl<'a> Iterator for Subexpressions<'a> {
+    type Item = (Vec<ChildIndex>, &'a Expr);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, index, expr) = self.pending.pop()?;
+        if depth > 0 {
+            self.path.truncate(depth - 1);
+            self.path.push(index);
+        }
+        for (index, child) in expr.children().into_iter().enumerate().rev() {
+            self.pending.push((depth + 1, index, child));
+        }
+        Some((self.path.clone(), expr))
+    }
+}
+
+impl Expr {
+    /// Every node in the tree with its path, starting with the root and
+    /// visiting each node's children left to right after it
+    ///
+    /// The tree is walked without recursion, but each path is a copy, so a
+    /// chain thousands of operators long has paths thousands of steps long.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, ast) = parse_expression("x * (y + 1)").unwrap();
+    /// let variables: Vec<_> = ast
+    ///     .subexpressions()
+    ///     .filter(|(_, expr)| matches!(expr, Expr::Var(_)))
+    ///     .map(|(path, _)| path)
+    ///     .collect();
+    /// assert_eq!(variables, [vec![0], vec![1, 0]]);
+    /// ```
+    pub fn subexpressions(&self) -> Subexpressions<'_> {
+        Subexpressions {
+            pending: vec![(0, 0, self)],
+            path: Vec::new(),
+        }
+    }
+
+    /// The subtree at `path`, or `None` if there's no node there
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, ast) = parse_expression("max(a, b * 2)").unwrap();
+    /// assert_eq!(ast.get(&[1, 1]), Some(&Expr::Float(2.0)));
+    /// assert_eq!(ast.get(&[]), Some(&ast));
+    /// assert_eq!(ast.get(&[2]), None);
+    /// ```
+    pub fn get(&self, path: &[ChildIndex]) -> Option<&Expr> {
+        path.iter()
+            .try_fold(self, |expr, &index| expr.children().into_iter().nth(index))
+    }
+
+    /// The subtree at `path`, for changing in place, or `None` if there's no node there
+    pub fn get_mut(&mut self, path: &[ChildIndex]) -> Option<&mut Expr> {
+        path.iter()
+            .try_fold(self, |expr, &index| expr.child_mut(index))
+    }
+
+    /// Put `new` in place of the subtree at `path`, giving back the old one
+    ///
+    /// Gives `None`, and leaves the tree as it was, if there's no node at
+    /// `path`; `new` is dropped then.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, mut ast) = parse_expression("x * (y + 1)").unwrap();
+    /// let old = ast.replace_at(&[1, 0], Expr::Float(2.0));
+    /// assert_eq!(old, Some(Expr::Var("y".to_string())));
+    /// assert_eq!(ast, parse_expression("x * (2 + 1)").unwrap().1);
+    ///
+    /// assert_eq!(ast.replace_at(&[0, 0], Expr::Float(3.0)), None);
+    /// ```
+    pub fn replace_at(&mut self, path: &[ChildIndex], new: Expr) -> Option<Expr> {
+        self.get_mut(path).map(|old| mem::replace(old, new))
+    }
+
+    /// How many nodes the tree has, counting without recursion
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// assert_eq!(parse_expression("f(-(a * b), 2)").unwrap().1.size(), 6);
+    /// ```
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            size += 1;
+            pending.extend(expr.children());
+        }
+        size
+    }
+
+    /// How many nodes the longest path from the root down to a leaf has
+    ///
+    /// A single number is 1 deep. Chains count every operator, so
+    /// `1 + 2 + 3` is 3 deep; the tree is walked without recursion, so
+    /// finding the depth of a very deep tree is safe.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// assert_eq!(parse_expression("x").unwrap().1.depth(), 1);
+    /// assert_eq!(parse_expression("f(-(a * b), 2)").unwrap().1.depth(), 4);
+    /// ```
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut pending = vec![(1, self)];
+        while let Some((depth, expr)) = pending.pop() {
+            deepest = deepest.max(depth);
+            pending.extend(expr.children().into_iter().map(|child| (depth + 1, child)));
+        }
+        deepest
+    }
+
+    /// How deeply this tree's text nests, as [`ParserConfig::max_depth`]
+    /// counts it
+    ///
+    /// Operands of `-`, `!`, calls, lists, indices and `let` are a level
+    /// deeper than what holds them, as are operands of binary operators that
+    /// would need parentheses. Chains and operands that bind more tightly
+    /// stay at the same level, so a tree nests as deeply as the parser found
+    /// it to, and recursion through the tree goes only a few times deeper.
+    ///
+    /// [`ParserConfig::max_depth`]: crate::ParserConfig::max_depth
+    pub(crate) fn nesting(&self) -> usize {
+        let mut deepest = 0;
+        let mut pending = vec![(1, self)];
+        while let Some((nesting, expr)) = pending.pop() {
+            deepest = deepest.max(nesting);
+            let Some((op, left, right)) = binary(expr) else {
+                pending.extend(
+                    expr.children()
+                        .into_iter()
+                        .map(|child| (nesting + 1, child)),
+                );
+                continue;
+            };
+            let level = precedence(op);
+            let grouped = |operand: &Expr, right: bool| match binary(operand) {
+                Some((inner, ..)) => {
+                    precedence(inner) < level || (right && precedence(inner) == level)
+                }
+                None => matches!(operand, Expr::Let(..)),
+            };
+            let left_grouped = grouped(left, false);
+            let right_grouped = grouped(right, true);
+            pending.push((nesting + usize::from(left_grouped), left));
+            pending.push((nesting + usize::from(right_grouped), right));
+        }
+        deepest
+    }
+
+    /// The child at `index`, in [`Expr::children`] order, for changing in place
+    pub(crate) fn child_mut(&mut self, index: ChildIndex) -> Option<&mut Expr> {
+        match self {
+            Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => None,
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right)
+            | Expr::Let(_, left, right) => match index {
+                0 => Some(left),
+                1 => Some(right),
+                _ => None,
+            },
+            Expr::Neg(inner) | Expr::Not(inner) | Expr::Named(_, inner) => {
+                (index == 0).then_some(inner)
+            }
+            Expr::Call(_, args) | Expr::List(args) => args.get_mut(index),
+            Expr::Piecewise(cases, default) => {
+                if index == cases.len() * 2 {
+                    return default.as_deref_mut();
+                }
+                let (condition, value) = cases.get_mut(index / 2)?;
+                Some(if index.is_multiple_of(2) {
+                    condition
+                } else {
+                    value
+                })
+            }
+            Expr::Index(target, indices) => match index {
+                0 => Some(target),
+                _ => indices.get_mut(index - 1),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Change, Expr, diff, parse_expression};
+
+    /// Parse an expression that must be valid
+    fn parse(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test that every path from subexpressions leads back to its node, including in long chains
+    #[test]
+    fn test_paths() {
+        let ast = parse("let t = [1, 2][k] in piecewise((t < 0, -t), max(t, 1))");
+        let mut count = 0;
+        for (path, expr) in ast.subexpressions() {
+            assert_eq!(ast.get(&path), Some(expr), "{:?}", path);
+            count += 1;
+        }
+        assert_eq!(count, 15);
+        assert_eq!(ast.get(&[0, 0, 1]), Some(&Expr::Float(2.0)));
+        assert_eq!(ast.get(&[1, 2, 0]), Some(&Expr::Var("t".to_string())));
+        assert_eq!(ast.get(&[1, 9]), None);
+
+        let chain = parse(&vec!["1"; 2_000].join(" + "));
+        let (path, last) = chain.subexpressions().last().unwrap();
+        assert_eq!((path.len(), last), (1, &Expr::Float(1.0)));
+        assert_eq!(chain.subexpressions().count(), 3_999);
+    }
+
+    /// Test replacing subtrees, at the paths diff reports
+    #[test]
+    fn test_replace_at() {
+        let old = parse("a * (b + 4)");
+        let new = parse("a * (b + 5)");
+        let mut edited = old.clone();
+        for change in diff(&old, &new).changes {
+            if let Change::Literal { path, new, .. } = change {
+                edited.replace_at(&path, Expr::Float(new)).unwrap();
+            }
+        }
+        assert_eq!(edited, new);
+
+        let mut ast = parse("f(x, y)");
+        assert_eq!(
+            ast.replace_at(&[], Expr::Float(1.0)),
+            Some(parse("f(x, y)"))
+        );
+        assert_eq!(ast, Expr::Float(1.0));
+        assert_eq!(ast.replace_at(&[0], Expr::Float(2.0)), None);
+        assert_eq!(ast, Expr::Float(1.0));
+    }
+
+    /// Test that chains don't nest, but parentheses and arguments do
+    #[test]
+    fn test_nesting() {
+        assert_eq!(parse("x").nesting(), 1);
+        assert_eq!(parse("1 + 2 + 3").nesting(), 1);
+        assert_eq!(parse("1 + 2 * 3 < 4 || x").nesting(), 1);
+        assert_eq!(parse("1 + (2 + 3)").nesting(), 2);
+        assert_eq!(parse("(1 + 2) * 3").nesting(), 2);
+        assert_eq!(parse("f(-(a * b), 2)").nesting(), 3);
+        assert_eq!(parse(&vec!["1"; 2_000].join(" - ")).nesting(), 1);
+    }
+}
@@ -0,0 +1,243 @@
+//! Plots of single-variable expressions
+//!
+//! `:plot sin(x) over x in [0, 2 * pi]` samples an expression across a range
+//! and draws it in the terminal with Braille characters, which have 2 × 4 dots
+//! each. Adding `to plot.svg` also writes the plot as an SVG image.
+
+use ast::{Notation, NumberFormat};
+
+/// Terminal plot size, in characters
+pub const WIDTH: usize = 60;
+pub const HEIGHT: usize = 15;
+
+/// SVG plot size, in pixels
+const SVG_WIDTH: f64 = 640.0;
+const SVG_HEIGHT: f64 = 400.0;
+const SVG_MARGIN: f64 = 50.0;
+
+/// How axis labels are written
+const LABELS: NumberFormat = NumberFormat {
+    precision: Some(4),
+    trailing_zeros: false,
+    notation: Notation::Auto,
+};
+
+/// An expression's values at evenly spaced points
+#[derive(Debug, PartialEq, Clone)]
+pub struct Plot {
+    /// Each point, with `None` where the expression has no finite value
+    points: Vec<(f64, Option<f64>)>,
+}
+
+impl Plot {
+    /// Sample `f` at `count` points from `from` to `to`, both included
+    pub fn sample(from: f64, to: f64, count: usize, mut f: impl FnMut(f64) -> Option<f64>) -> Self {
+        let step = (to - from) / (count.max(2) - 1) as f64;
+        let points = (0..count)
+            .map(|i| {
+                let x = from + step * i as f64;
+                (x, f(x).filter(|y| y.is_finite()))
+            })
+            .collect();
+        Plot { points }
+    }
+
+    /// The lowest and highest value, widened if they're equal, or `None` if there are no values
+    fn y_range(&self) -> Option<(f64, f64)> {
+        let values = self.points.iter().filter_map(|&(_, y)| y);
+        let (low, high) = values.fold(None, |range, y| match range {
+            None => Some((y, y)),
+            Some((low, high)) => Some((y.min(low), y.max(high))),
+        })?;
+        Some(if low == high {
+            (low - 1.0, high + 1.0)
+        } else {
+            (low, high)
+        })
+    }
+
+    /// The x range sampled
+    fn x_range(&self) -> (f64, f64) {
+        let first = self.points.first().map_or(0.0, |&(x, _)| x);
+        let last = self.points.last().map_or(0.0, |&(x, _)| x);
+        (first, last)
+    }
+
+    /// The plot drawn with Braille characters, `width` × `height` of them, with labelled axes
+    ///
+    /// Returns `None` if the expression has no finite value anywhere.
+    pub fn text(&self, width: usize, height: usize) -> Option<String> {
+        let (low, high) = self.y_range()?;
+        let (columns, rows) = (width * 2, height * 4);
+        let mut dots = vec![vec![false; columns]; rows];
+
+        // Each point lands on a column of dots, joined to the previous point
+        let row = |y: f64| ((high - y) / (high - low) * (rows - 1) as f64).round() as usize;
+        let mut previous = None;
+        for (index, &(_, y)) in self.points.iter().enumerate() {
+            let column = index * columns / self.points.len().max(1);
+            let Some(y) = y else {
+                previous = None;
+                continue;
+            };
+            let current = row(y);
+            // A jump across most of the plot is more likely a pole than a steep climb
+            let from = previous
+                .filter(|&previous: &usize| previous.abs_diff(current) < rows / 2)
+                .unwrap_or(current);
+            for row in &mut dots[from.min(current)..=from.max(current)] {
+                row[column] = true;
+            }
+            previous = Some(current);
+        }
+
+        let label = LABELS;
+        let (top, bottom) = (label.format(high), label.format(low));
+        let margin = top.chars().count().max(bottom.chars().count());
+        let mut out = String::new();
+        for line in 0..height {
+            let tick = match line {
+                0 => &top,
+                _ if line == height - 1 => &bottom,
+                _ => "",
+            };
+            let axis = if tick.is_empty() { '│' } else { '┤' };
+            out.push_str(&format!("{:>margin$} {}", tick, axis));
+            for cell in 0..width {
+                out.push(braille(&dots, line * 4, cell * 2));
+            }
+            out.push('\n');
+        }
+
+        let (first, last) = self.x_range();
+        let (first, last) = (label.format(first), label.format(last));
+        out.push_str(&format!("{} └{}\n", " ".repeat(margin), "─".repeat(width)));
+        let gap = (width + 1)
+            .saturating_sub(first.chars().count() + last.chars().count())
+            .max(1);
+        out.push_str(&format!(
+            "{}  {}{}{}\n",
+            " ".repeat(margin),
+            first,
+            " ".repeat(gap),
+            last
+        ));
+        Some(out)
+    }
+
+    /// The plot as an SVG image, with the axes drawn where they cross the plot
+    ///
+    /// Returns `None` if the expression has no finite value anywhere.
+    pub fn svg(&self) -> Option<String> {
+        let (low, high) = self.y_range()?;
+        let (first, last) = self.x_range();
+        let span = if last == first { 1.0 } else { last - first };
+        let x = |x: f64| SVG_MARGIN + (x - first) / span * (SVG_WIDTH - 2.0 * SVG_MARGIN);
+        let y = |y: f64| SVG_MARGIN + (high - y) / (high - low) * (SVG_HEIGHT - 2.0 * SVG_MARGIN);
+
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+             <rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n",
+            w = SVG_WIDTH,
+            h = SVG_HEIGHT
+        );
+        let line = |x1: f64, y1: f64, x2: f64, y2: f64| {
+            format!(
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"gray\"/>\n",
+                x1, y1, x2, y2
+            )
+        };
+        if low <= 0.0 && 0.0 <= high {
+            out.push_str(&line(x(first), y(0.0), x(last), y(0.0)));
+        }
+        if first <= 0.0 && 0.0 <= last {
+            out.push_str(&line(x(0.0), y(high), x(0.0), y(low)));
+        }
+
+        // One polyline for each run of points with values
+        let runs = self.points.split(|(_, value)| value.is_none());
+        for run in runs.filter(|run| !run.is_empty()) {
+            let points: Vec<_> = run
+                .iter()
+                .filter_map(|&(px, py)| Some(format!("{:.2},{:.2}", x(px), y(py?))))
+                .collect();
+            out.push_str(&format!(
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"steelblue\" stroke-width=\"2\"/>\n",
+                points.join(" ")
+            ));
+        }
+
+        let label = LABELS;
+        let text = |x: f64, y: f64, anchor: &str, value: f64| {
+            format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"{}\" font-family=\"sans-serif\" font-size=\"12\">{}</text>\n",
+                x,
+                y,
+                anchor,
+                label.format(value)
+            )
+        };
+        out.push_str(&text(SVG_MARGIN - 5.0, y(high) + 4.0, "end", high));
+        out.push_str(&text(SVG_MARGIN - 5.0, y(low) + 4.0, "end", low));
+        out.push_str(&text(
+            x(first),
+            SVG_HEIGHT - SVG_MARGIN + 18.0,
+            "start",
+            first,
+        ));
+        out.push_str(&text(x(last), SVG_HEIGHT - SVG_MARGIN + 18.0, "end", last));
+        out.push_str("</svg>\n");
+        Some(out)
+    }
+}
+
+/// The Braille character for the 2 × 4 dots whose top left is at `row`, `column`
+fn braille(dots: &[Vec<bool>], row: usize, column: usize) -> char {
+    // The bit for each dot, by row and then column
+    const BITS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut code = 0x2800;
+    for (dy, bits) in BITS.iter().enumerate() {
+        for (dx, bit) in bits.iter().enumerate() {
+            if dots[row + dy][column + dx] {
+                code |= bit;
+            }
+        }
+    }
+    char::from_u32(code).expect("Braille patterns are characters")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test drawing a line in the terminal, with its axis labels
+    #[test]
+    fn test_text() {
+        let plot = Plot::sample(0.0, 3.0, 4, Some);
+        assert_eq!(plot.text(2, 2).unwrap(), "3 ┤⠀⡼\n0 ┤⣰⠃\n  └──\n   0 3\n");
+
+        let gaps = Plot::sample(-1.0, 1.0, 3, |x| Some(1.0 / x));
+        assert_eq!(gaps.points[1], (0.0, None));
+        assert!(gaps.text(WIDTH, HEIGHT).unwrap().contains("-1 ┤"));
+        assert_eq!(
+            Plot::sample(0.0, 1.0, 5, |_| None).text(WIDTH, HEIGHT),
+            None
+        );
+    }
+
+    /// Test that gaps split the SVG line, and that the axes are drawn
+    #[test]
+    fn test_svg() {
+        let svg = Plot::sample(-2.0, 2.0, 5, |x| (x != 0.0).then_some(x * x))
+            .svg()
+            .unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert_eq!(
+            svg.matches("<line").count(),
+            1,
+            "only the y axis is in range"
+        );
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
@@ -0,0 +1,719 @@
+//! A second parser, written by hand instead of with nom
+//!
+//! [`parse_pratt`] accepts exactly what
+//! [`parse_expression_with`](crate::parse_expression_with) accepts and builds
+//! the same trees, but it is plain recursive descent for operands and a Pratt
+//! loop for binary operators: each operator has a binding power, and an
+//! operand keeps taking operators while they bind at least as tightly as the
+//! one before it. Having two independent implementations of the grammar lets
+//! each be tested against the other.
+
+use std::cell::Cell;
+
+use crate::{
+    CellRef, Comparison, Dialect, Expr, GrammarVersion, ParseEvalError, ParserConfig,
+    argument_name, cells, dialect, si, skip_whitespace, strip_in, strip_let, try_parse_symbol,
+    uncertainty,
+};
+
+/// Parse an expression like [`parse_expression_with`](crate::parse_expression_with), without nom
+///
+/// Returns the input left over after the expression, or where parsing
+/// stopped if it isn't one.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, parse_pratt, ParseEvalError, ParserConfig};
+///
+/// let config = ParserConfig::default();
+/// let (rest, ast) = parse_pratt("2 * (x + 1) >= 8 && f(x)", &config).unwrap();
+/// assert_eq!(rest, "");
+/// assert_eq!(ast, parse_expression("2 * (x + 1) >= 8 && f(x)").unwrap().1);
+///
+/// assert!(matches!(parse_pratt("1 + * 2", &config), Err(ParseEvalError::Syntax { position: 4 })));
+/// ```
+pub fn parse_pratt<'a>(
+    input: &'a str,
+    config: &ParserConfig,
+) -> Result<(&'a str, Expr), ParseEvalError> {
+    Parser {
+        config,
+        depth: Cell::new(0),
+    }
+    .expression(input)
+    .map_err(|rest| ParseEvalError::Syntax {
+        position: input.len() - rest.len(),
+    })
+}
+
+/// What was parsed and the input after it, or the input where parsing failed
+type Parsed<'a, T> = Result<(&'a str, T), &'a str>;
+
+/// A binary operator
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Or,
+    And,
+    Compare(Comparison),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// Python's `//`
+    FloorDivide,
+    /// Excel's `^`
+    Power,
+}
+
+impl Operator {
+    /// How tightly the operator binds its operands; higher binds tighter
+    fn binding_power(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Compare(_) => 3,
+            Operator::Add | Operator::Sub => 4,
+            Operator::Mul | Operator::Div | Operator::FloorDivide => 5,
+            Operator::Power => 6,
+        }
+    }
+
+    /// The first version of the grammar with the operator
+    fn grammar(self) -> GrammarVersion {
+        match self {
+            Operator::Or | Operator::And | Operator::Compare(_) => GrammarVersion::V3,
+            Operator::FloorDivide | Operator::Power => GrammarVersion::V2,
+            Operator::Add | Operator::Sub | Operator::Mul | Operator::Div => GrammarVersion::V1,
+        }
+    }
+
+    /// The tree for `left` and `right` joined by the operator
+    fn apply(self, left: Expr, right: Expr) -> Expr {
+        let join = match self {
+            Operator::FloorDivide => return dialect::floor_division(left, right),
+            Operator::Power => return dialect::power(left, right),
+            Operator::Compare(comparison) => {
+                return Expr::Compare(comparison, Box::new(left), Box::new(right));
+            }
+            Operator::Or => Expr::Or,
+            Operator::And => Expr::And,
+            Operator::Add => Expr::Add,
+            Operator::Sub => Expr::Sub,
+            Operator::Mul => Expr::Mul,
+            Operator::Div => Expr::Div,
+        };
+        join(Box::new(left), Box::new(right))
+    }
+}
+
+/// The parser for one configuration
+struct Parser<'c> {
+    /// What to accept
+    config: &'c ParserConfig,
+    /// How many factors and `let`s are being parsed inside one another
+    depth: Cell<usize>,
+}
+
+impl Parser<'_> {
+    /// Parse with `parse`, which recurses, failing past [`ParserConfig::max_depth`]
+    fn nested<'a, T>(
+        &self,
+        input: &'a str,
+        parse: impl FnOnce() -> Parsed<'a, T>,
+    ) -> Parsed<'a, T> {
+        if self.depth.get() > self.config.max_depth {
+            return Err(input);
+        }
+        self.depth.set(self.depth.get() + 1);
+        let result = parse();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    /// An expression, which may start with `let name = value in`
+    fn expression<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        match strip_let(input) {
+            Some(after) if self.config.grammar >= GrammarVersion::V3 => self.binding(after),
+            _ if self.config.grammar < GrammarVersion::V3 => self.binary(input, 0),
+            _ => self.pipeline(input),
+        }
+    }
+
+    /// Operands joined by operators, then any `|> name` or `|> name(args)` stages
+    fn pipeline<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        let (mut input, mut value) = self.binary(input, 0)?;
+        while let Some(stage) = skip_whitespace(input).strip_prefix("|>") {
+            let stage = skip_whitespace(stage);
+            let (stage, name) = identifier(stage).ok_or(stage)?;
+            let (stage, mut args) = if stage.starts_with('(') {
+                self.separated(stage, '(', ')')?
+            } else {
+                (stage, Vec::new())
+            };
+            args.insert(0, value);
+            value = match self.config.dialect {
+                Dialect::Python => dialect::python_call(name, args),
+                _ => Expr::Call(name.to_string(), args),
+            };
+            input = stage;
+        }
+        Ok((input, value))
+    }
+
+    /// The rest of a local variable after `let`: `name = value in body`
+    fn binding<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        self.nested(input, || {
+            let (input, name) = identifier(skip_whitespace(input)).ok_or(input)?;
+            let input = symbol(skip_whitespace(input), '=')?;
+            if input.starts_with('=') {
+                return Err(input);
+            }
+            let (input, value) = self.expression(input)?;
+            let input = strip_in(input).ok_or(input)?;
+            let (input, body) = self.expression(input)?;
+            Ok((
+                input,
+                Expr::Let(name.to_string(), Box::new(value), Box::new(body)),
+            ))
+        })
+    }
+
+    /// Operands joined by operators that bind at least as tightly as `min_power`
+    ///
+    /// Operators of the same power group to the left, because the right
+    /// operand only takes operators that bind more tightly.
+    fn binary<'a>(&self, input: &'a str, min_power: u8) -> Parsed<'a, Expr> {
+        let (mut input, mut left) = self.factor(input)?;
+        while let Some((operator, after)) = self.operator(skip_whitespace(input))
+            && operator.binding_power() >= min_power
+        {
+            let (after, right) = self.binary(after, operator.binding_power() + 1)?;
+            left = operator.apply(left, right);
+            input = after;
+        }
+        Ok((input, left))
+    }
+
+    /// The binary operator `input` starts with in this dialect, and the input after it
+    fn operator<'a>(&self, input: &'a str) -> Option<(Operator, &'a str)> {
+        let dialect = self.config.dialect;
+        let operator = match input.as_bytes().first()? {
+            b'|' if input.starts_with("||") => Operator::Or,
+            b'&' if input.starts_with("&&") => Operator::And,
+            b'<' | b'>' | b'=' | b'!' => {
+                let (symbol, rest) = try_parse_symbol(input, dialect.comparison_symbols())?;
+                let comparison = dialect.comparison(symbol)?;
+                return (self.config.grammar >= GrammarVersion::V3)
+                    .then_some((Operator::Compare(comparison), rest));
+            }
+            b'+' => Operator::Add,
+            b'-' => Operator::Sub,
+            b'*' => Operator::Mul,
+            b'/' if dialect == Dialect::Python && input.starts_with("//") => Operator::FloorDivide,
+            b'/' => Operator::Div,
+            b'^' if dialect == Dialect::Excel => Operator::Power,
+            _ => return None,
+        };
+        if operator.grammar() > self.config.grammar {
+            return None;
+        }
+        let length = match operator {
+            Operator::Or | Operator::And | Operator::FloorDivide => 2,
+            _ => 1,
+        };
+        Some((operator, &input[length..]))
+    }
+
+    /// A negation, a logical not, or an operand followed by any indices (and Python's `**`)
+    fn factor<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        self.nested(input, || {
+            let input = skip_whitespace(input);
+            let (input, expr) = match input.as_bytes().first() {
+                Some(b'-') => {
+                    let (input, expr) = self.factor(&input[1..])?;
+                    return Ok((input, Expr::Neg(Box::new(expr))));
+                }
+                Some(b'!') if self.config.grammar >= GrammarVersion::V3 => {
+                    let (input, expr) = self.factor(&input[1..])?;
+                    return Ok((input, Expr::Not(Box::new(expr))));
+                }
+                Some(b'(') => self.parenthesized(input).or_else(|_| self.number(input))?,
+                Some(b'[') if self.config.grammar >= GrammarVersion::V3 => {
+                    let (input, elements) = self.separated(input, '[', ']')?;
+                    (input, Expr::List(elements))
+                }
+                Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+                    match self.config.cell_references.then(|| cell_reference(input)) {
+                        Some(Some(parsed)) => parsed,
+                        _ => self.name(input).or_else(|_| self.number(input))?,
+                    }
+                }
+                _ => {
+                    let (input, value) = self.literal(input)?;
+                    match self
+                        .config
+                        .uncertainties
+                        .then(|| uncertainty::plus_minus(input))
+                    {
+                        Some(Some(error)) => {
+                            let error = skip_whitespace(error);
+                            if !error.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                                return Err(error);
+                            }
+                            let (input, error) = self.literal(error)?;
+                            (input, uncertainty::measurement(value, error))
+                        }
+                        _ => (input, value),
+                    }
+                }
+            };
+            let (input, expr) = match self.config.grammar {
+                GrammarVersion::V3 => self.postfix(input, expr)?,
+                _ => (input, expr),
+            };
+
+            if self.config.dialect == Dialect::Python
+                && self.config.grammar >= GrammarVersion::V2
+                && let Some(exponent) = skip_whitespace(input).strip_prefix("**")
+            {
+                let (input, exponent) = self.factor(exponent)?;
+                return Ok((input, dialect::power(expr, exponent)));
+            }
+            Ok((input, expr))
+        })
+    }
+
+    /// A number literal, with an SI prefix if the configuration accepts them
+    fn literal<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        match self.config.si_suffixes.then(|| si_number(input)) {
+            Some(Some(parsed)) => Ok(parsed),
+            _ => self.number(input),
+        }
+    }
+
+    /// A number literal, with the words `nan`, `inf` and `infinity` if the configuration accepts them
+    fn number<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        if !self.config.nonfinite_literals && input.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(input);
+        }
+        number(input)
+    }
+
+    /// An expression in parentheses
+    fn parenthesized<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        let (input, expr) = self.expression(symbol(input, '(')?)?;
+        Ok((symbol(input, ')')?, expr))
+    }
+
+    /// Any element accesses and method calls straight after an operand: `m[2, 1]`, `x.round(2)`
+    fn postfix<'a>(&self, mut input: &'a str, mut expr: Expr) -> Parsed<'a, Expr> {
+        loop {
+            if input.starts_with('[') {
+                let (after, indices) = self.separated(input, '[', ']')?;
+                if !(1..=2).contains(&indices.len()) {
+                    return Err(input);
+                }
+                expr = Expr::Index(Box::new(expr), indices);
+                input = after;
+            } else if self.config.dialect != Dialect::Python
+                && let Some(after_dot) = input.strip_prefix('.')
+                && let Some((after_name, name)) = identifier(after_dot)
+                && after_name.starts_with('(')
+            {
+                let (after, mut args) = self.separated(after_name, '(', ')')?;
+                args.insert(0, expr);
+                expr = Expr::Call(name.to_string(), args);
+                input = after;
+            } else {
+                return Ok((input, expr));
+            }
+        }
+    }
+
+    /// Comma-separated expressions between `open` and `close`, which may be empty
+    ///
+    /// Between parentheses they're arguments, which may end with named ones.
+    fn separated<'a>(&self, input: &'a str, open: char, close: char) -> Parsed<'a, Vec<Expr>> {
+        let mut input = skip_whitespace(symbol(input, open)?);
+        let mut elements = Vec::new();
+        if let Some(input) = input.strip_prefix(close) {
+            return Ok((input, elements));
+        }
+        let mut named = false;
+        loop {
+            let name = argument_name(input, self.config).filter(|_| open == '(');
+            let (rest, element) = match name {
+                Some((value, name)) => {
+                    named = true;
+                    let (rest, value) = self.expression(value)?;
+                    (rest, Expr::Named(name.to_string(), Box::new(value)))
+                }
+                None if named => return Err(input),
+                None => self.expression(input)?,
+            };
+            elements.push(element);
+            let rest = skip_whitespace(rest);
+            match rest.strip_prefix(',') {
+                Some(rest) => input = rest,
+                None => return Ok((symbol(rest, close)?, elements)),
+            }
+        }
+    }
+
+    /// A variable, or a function call if the name is followed by "("
+    fn name<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        let (mut input, mut name) = identifier(input).ok_or(input)?;
+        if self.config.dialect == Dialect::Python
+            && let Some(after_dot) = input.strip_prefix('.')
+        {
+            let (after_name, attribute) = identifier(after_dot).ok_or(after_dot)?;
+            let expr = (name == "math")
+                .then(|| dialect::python_math(attribute))
+                .flatten()
+                .ok_or(input)?;
+            if !matches!(expr, Expr::Var(_)) {
+                return Ok((after_name, expr));
+            }
+            (input, name) = (after_name, attribute);
+        }
+
+        if input.starts_with('(') && self.config.grammar < GrammarVersion::V2 {
+            return Err(input);
+        }
+        if name == "piecewise"
+            && input.starts_with('(')
+            && self.config.grammar >= GrammarVersion::V3
+        {
+            return self.piecewise(input);
+        }
+        if input.starts_with('(') {
+            let (input, args) = self.separated(input, '(', ')')?;
+            let call = match self.config.dialect {
+                Dialect::Python => dialect::python_call(name, args),
+                _ => Expr::Call(name.to_string(), args),
+            };
+            return Ok((input, call));
+        }
+        Ok((input, Expr::Var(name.to_string())))
+    }
+
+    /// The `(condition, value)` cases of a piecewise definition and its default, after `piecewise`
+    fn piecewise<'a>(&self, input: &'a str) -> Parsed<'a, Expr> {
+        let mut input = symbol(input, '(')?;
+        let mut cases = Vec::new();
+        loop {
+            input = skip_whitespace(input);
+            let Ok((rest, case)) = self.case(input) else {
+                break;
+            };
+            cases.push(case);
+            let rest = skip_whitespace(rest);
+            match rest.strip_prefix(',') {
+                Some(rest) => input = rest,
+                None => return Ok((symbol(rest, ')')?, Expr::Piecewise(cases, None))),
+            }
+        }
+        if cases.is_empty() {
+            return Err(input);
+        }
+        let (rest, default) = self.expression(input)?;
+        let rest = symbol(skip_whitespace(rest), ')')?;
+        Ok((rest, Expr::Piecewise(cases, Some(Box::new(default)))))
+    }
+
+    /// One `(condition, value)` case of a piecewise definition
+    fn case<'a>(&self, input: &'a str) -> Parsed<'a, (Expr, Expr)> {
+        let (input, condition) = self.expression(symbol(input, '(')?)?;
+        let (input, value) = self.expression(symbol(skip_whitespace(input), ',')?)?;
+        Ok((symbol(skip_whitespace(input), ')')?, (condition, value)))
+    }
+}
+
+/// The input after `c`, which it must start with
+fn symbol(input: &str, c: char) -> Result<&str, &str> {
+    input.strip_prefix(c).ok_or(input)
+}
+
+/// A variable or function name, which isn't one of the number words `nan`, `inf` and `infinity`
+fn identifier(input: &str) -> Option<(&str, &str)> {
+    if !input.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return None;
+    }
+    let end = input
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(input.len());
+    let (name, rest) = input.split_at(end);
+    let number = ["nan", "inf", "infinity"]
+        .iter()
+        .any(|word| name.eq_ignore_ascii_case(word));
+    (!number).then_some((rest, name))
+}
+
+/// The input after a float literal such as `-1.5e3`, `2.` or `.5`, and the literal
+fn float(input: &str) -> Option<(&str, &str)> {
+    let bytes = input.as_bytes();
+    let digits = |from: usize| {
+        bytes[from.min(bytes.len())..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let whole = digits(end);
+    end += whole;
+    // In `2.abs()` the dot starts a method call rather than ending the number
+    let method = |after: &str| identifier(after).is_some_and(|(rest, _)| rest.starts_with('('));
+    if bytes.get(end) == Some(&b'.')
+        && (whole > 0 || digits(end + 1) > 0)
+        && !method(&input[end + 1..])
+    {
+        end += 1 + digits(end + 1);
+    } else if whole == 0 {
+        return None;
+    }
+    if let Some(b'e' | b'E') = bytes.get(end) {
+        let mut exponent = end + 1;
+        if let Some(b'+' | b'-') = bytes.get(exponent) {
+            exponent += 1;
+        }
+        // Like nom's float parser, an exponent with no digits isn't left unparsed
+        match digits(exponent) {
+            0 => return None,
+            count => end = exponent + count,
+        }
+    }
+    Some((&input[end..], &input[..end]))
+}
+
+/// A number literal, including `nan`, `inf` and `infinity` in any case
+fn number(input: &str) -> Parsed<'_, Expr> {
+    let (rest, text) = float(input)
+        .or_else(|| {
+            ["nan", "infinity", "inf"].iter().find_map(|word| {
+                let text = input.get(..word.len())?;
+                text.eq_ignore_ascii_case(word)
+                    .then(|| (&input[word.len()..], text))
+            })
+        })
+        .ok_or(input)?;
+    let value = text.parse().map_err(|_| input)?;
+    Ok((rest, Expr::Float(value)))
+}
+
+/// A number literal that ends in an SI prefix, such as `4.7k`
+fn si_number(input: &str) -> Option<(&str, Expr)> {
+    let (rest, text) = float(input)?;
+    let (rest, value) = si::scale(text, rest)?;
+    Some((rest, Expr::Float(value)))
+}
+
+/// A cell reference such as `B3`, or a range of cells such as `B2:B10`
+fn cell_reference(input: &str) -> Option<(&str, Expr)> {
+    let (rest, from) = cell(input)?;
+    match rest.strip_prefix(':').and_then(cell) {
+        Some((rest, to)) => Some((rest, Expr::CellRange(from, to))),
+        None => Some((rest, Expr::CellRef(from))),
+    }
+}
+
+/// A single cell name, such as `A1`, that isn't the start of a longer name or a call
+fn cell(input: &str) -> Option<(&str, CellRef)> {
+    let letters = input.bytes().take_while(u8::is_ascii_alphabetic).count();
+    let digits = input[letters..]
+        .bytes()
+        .take_while(u8::is_ascii_digit)
+        .count();
+    let (name, rest) = input.split_at(letters + digits);
+    if !(1..=cells::MAX_COLUMN_LETTERS).contains(&letters)
+        || digits == 0
+        || rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '(')
+    {
+        return None;
+    }
+    let row = name[letters..].parse().ok().filter(|row| *row > 0)?;
+    Some((
+        rest,
+        CellRef::new(cells::column_number(&name[..letters]), row),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression_with;
+
+    /// Every combination of dialect, extensions and grammar version
+    fn configs() -> Vec<ParserConfig> {
+        let mut configs = Vec::new();
+        for dialect in [Dialect::Standard, Dialect::Python, Dialect::Excel] {
+            for (cell_references, si_suffixes) in [(false, false), (true, true)] {
+                for grammar in [GrammarVersion::V1, GrammarVersion::V2, GrammarVersion::V3] {
+                    configs.push(ParserConfig {
+                        cell_references,
+                        dialect,
+                        si_suffixes,
+                        nonfinite_literals: !cell_references,
+                        uncertainties: si_suffixes,
+                        grammar,
+                        ..ParserConfig::default()
+                    });
+                }
+            }
+        }
+        configs
+    }
+
+    /// Assert that both parsers accept `input` with the same tree and leftover input, or both reject it
+    fn assert_same(input: &str, config: &ParserConfig) {
+        let nom = parse_expression_with(input, config).ok();
+        let pratt = parse_pratt(input, config).ok();
+        // NaN literals aren't equal to themselves, so compare the trees as text
+        assert_eq!(
+            format!("{:?}", pratt),
+            format!("{:?}", nom),
+            "'{}' with {:?}",
+            input,
+            config
+        );
+    }
+
+    /// Test inputs that exercise each corner of the grammar
+    #[test]
+    fn test_corpus() {
+        let corpus = [
+            "1 + 2 * 3 - 4 / 5",
+            "10 - 3 - 2",
+            "a < b < c == d",
+            "!a && b || c && !d",
+            "-2 ^ 2 ^ 3",
+            "2 ** -3 ** 2",
+            "7 // 2 * 3",
+            "-x[1][2, 3]",
+            "[1, [2, 3], []][0]",
+            "m[]",
+            "let x = 2 in let y = x * x in y + 1",
+            "let x == 2 in x",
+            "let + 1",
+            "(let t = 3 in t) * 2",
+            "f() + g(1, h(2, 3))",
+            "( 1 + 2 )",
+            "(1 + 2 ) * 3",
+            "piecewise((x < 0, -x), (x < 10, x), 10)",
+            "piecewise(1)",
+            "piecewise((a, b),)",
+            "math.pi * math.sqrt(2) + math.log(8, 2)",
+            "math.nope(1)",
+            "m.sqrt(2)",
+            "value.round(2).clamp(0, 100)",
+            "-(x + 1).abs()[1] * m[2].sum()",
+            "2.5.floor() + x.y",
+            "2.abs() - -3.exp() * 2.e1 + 4.x",
+            "pmt(0.05, nper = 360, pv=x == 1) + x.round(digits=2)",
+            "f(a=1, 2)",
+            "f(a==1, b=)",
+            "[a=1]",
+            "A1 + B2:C3 - xfd1048576 * A0 + ABCD1 + A1B + LOG10(2) + A1:",
+            "4.7k * 2m + 1e3 + 0x10 + 2max + 10u",
+            "5.0 ± 0.1 * 2 +/- .5k + 1 ± -1 + 3 +/-",
+            "nan + Infinity - INF + nanx(1)",
+            "1e + 2",
+            "1.5e-3 + .5 + 5. + +3",
+            "1 = 1 <> 2 >= 3",
+            "1 != 2 <> 3",
+            "a || b | c",
+            "x + 1 |> abs |> round(2) |> max(y, 3) > 1",
+            "let t = x |> sqrt in t |> exp",
+            "x |> 2 + (a |> f) || b |>",
+            "x\n\t*\r\ny",
+            "",
+            "   ",
+            "2 * ",
+        ];
+        for config in configs() {
+            for input in corpus {
+                assert_same(input, &config);
+            }
+        }
+
+        // Both stop at the same depth
+        let default = ParserConfig::default();
+        for depth in [64, 65] {
+            let input = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+            assert_same(&input, &default);
+            assert_same(&format!("{}1", "let a = 1 in ".repeat(depth)), &default);
+        }
+        assert!(parse_pratt(&format!("{}1", "-".repeat(65)), &default).is_err());
+    }
+
+    /// Test random strings of tokens, most of them not expressions
+    #[test]
+    fn test_random_inputs() {
+        let tokens = [
+            "1",
+            "2.5",
+            "1e3",
+            "1e",
+            ".",
+            "4.7k",
+            "±",
+            "+/-",
+            "x",
+            "f",
+            "_y",
+            "nan",
+            "inf",
+            "let",
+            "in",
+            "=",
+            "math",
+            "pi",
+            "sqrt",
+            "piecewise",
+            "A1",
+            "B2",
+            ":",
+            "(",
+            ")",
+            "[",
+            "]",
+            ",",
+            "+",
+            "-",
+            "*",
+            "**",
+            "/",
+            "//",
+            "^",
+            "<",
+            "<=",
+            "==",
+            "<>",
+            "!",
+            "!=",
+            "&&",
+            "||",
+            "|>",
+            " ",
+        ];
+        // SplitMix64, so every run tries the same strings
+        let mut state = 184_u64;
+        let mut random = |n: usize| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            ((z ^ (z >> 31)) % n as u64) as usize
+        };
+        let configs = configs();
+        let mut accepted = 0;
+        for _ in 0..20_000 {
+            let length = 1 + random(12);
+            let input: String = (0..length).map(|_| tokens[random(tokens.len())]).collect();
+            let config = &configs[random(configs.len())];
+            assert_same(&input, config);
+            accepted += usize::from(parse_pratt(&input, config).is_ok());
+        }
+        assert!(accepted > 2_000, "only {} inputs parsed", accepted);
+    }
+}
@@ -0,0 +1,228 @@
+//! Ready-made sets of constants and functions
+//!
+//! What end users can write depends on the variables and functions an
+//! expression is evaluated with. A [`Prelude`] bundles the two, so that an
+//! embedder can pick what to expose, say only arithmetic and money functions
+//! for a pricing rule, and add their own on top.
+
+use std::sync::Arc;
+
+use crate::{Environment, Evaluator, FunctionRegistry};
+
+/// The date and duration functions in [`FunctionRegistry::standard`]
+const DATE_FUNCTIONS: [&str; 9] = [
+    "date", "now", "year", "month", "day", "days", "hours", "minutes", "seconds",
+];
+
+/// The functions [`Prelude::finance`] keeps from the standard ones
+#[cfg(feature = "finance")]
+const FINANCE_FUNCTIONS: [&str; 29] = [
+    "abs",
+    "sign",
+    "sqrt",
+    "pow",
+    "exp",
+    "ln",
+    "log10",
+    "floor",
+    "ceil",
+    "trunc",
+    "round",
+    "min",
+    "max",
+    "coalesce",
+    "if",
+    "mean",
+    "median",
+    "variance",
+    "stddev",
+    "percentile",
+    "pmt",
+    "fv",
+    "pv",
+    "npv",
+    "irr",
+    "round_bankers",
+    "date",
+    "year",
+    "days",
+];
+
+/// Constants and functions for expressions to use
+///
+/// The constants are a shared [`Environment`], so the variables for each
+/// evaluation can be put in a [child](Prelude::environment) of it, where
+/// they can also hide a constant of the same name.
+///
+/// # Example
+/// ```
+/// use ast::{Prelude, parse_expression};
+///
+/// let prelude = Prelude::scientific();
+/// let mut env = prelude.environment();
+/// env.set("r", 2.0);
+///
+/// let (_, ast) = parse_expression("pi * r * r").unwrap();
+/// let area = prelude.evaluator(&env).evaluate(&ast).unwrap();
+/// assert_eq!(area, std::f64::consts::PI * 4.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Prelude {
+    /// The constants, for evaluation environments to be children of
+    constants: Arc<Environment>,
+    /// The functions expressions can call
+    functions: FunctionRegistry,
+}
+
+impl Prelude {
+    /// No constants and no functions, only arithmetic, comparisons and logic
+    pub fn none() -> Self {
+        Prelude {
+            constants: Arc::new(Environment::new()),
+            functions: FunctionRegistry::new(),
+        }
+    }
+
+    /// The standard functions except the date and money ones, and the constants
+    /// `pi`, `e`, `tau` and `phi` (the golden ratio)
+    pub fn scientific() -> Self {
+        let mut functions = FunctionRegistry::standard();
+        for name in DATE_FUNCTIONS {
+            functions.remove(name);
+        }
+        #[cfg(feature = "finance")]
+        for name in ["pmt", "fv", "pv", "npv", "irr", "round_bankers"] {
+            functions.remove(name);
+        }
+        let constants = [
+            ("pi", std::f64::consts::PI),
+            ("e", std::f64::consts::E),
+            ("tau", std::f64::consts::TAU),
+            ("phi", (1.0 + 5f64.sqrt()) / 2.0),
+        ];
+        Prelude {
+            constants: Arc::new(constants.into_iter().collect()),
+            functions,
+        }
+    }
+
+    /// Functions for money: rounding, `min` and `max`, `if` and `coalesce`,
+    /// powers and logarithms, the statistics on lists, `date`, `year` and
+    /// `days`, and `pmt`, `fv`, `pv`, `npv`, `irr` and `round_bankers`
+    ///
+    /// There are no constants and no trigonometry.
+    #[cfg(feature = "finance")]
+    pub fn finance() -> Self {
+        let mut functions = FunctionRegistry::standard();
+        for name in functions
+            .names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+        {
+            if !FINANCE_FUNCTIONS.contains(&name.as_str()) {
+                functions.remove(&name);
+            }
+        }
+        Prelude {
+            constants: Arc::new(Environment::new()),
+            functions,
+        }
+    }
+
+    /// Add a constant, replacing any of the same name
+    pub fn with_constant(mut self, name: &str, value: f64) -> Self {
+        Arc::make_mut(&mut self.constants).set(name, value);
+        self
+    }
+
+    /// The constants
+    pub fn constants(&self) -> &Arc<Environment> {
+        &self.constants
+    }
+
+    /// The functions
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    /// The functions, to register more or take some away
+    pub fn functions_mut(&mut self) -> &mut FunctionRegistry {
+        &mut self.functions
+    }
+
+    /// An empty scope over the constants, for one evaluation's variables
+    pub fn environment(&self) -> Environment {
+        self.constants.child()
+    }
+
+    /// An evaluator with these functions, looking variables up in `env`
+    ///
+    /// `env` should usually come from [`Prelude::environment`]; any other
+    /// environment is used as it is, without the constants.
+    pub fn evaluator<'a>(&'a self, env: &'a Environment) -> Evaluator<'a> {
+        Evaluator::new(env).with_functions(&self.functions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EvaluationError, parse_expression};
+
+    /// Evaluate an expression with a prelude and no variables of its own
+    fn evaluate(prelude: &Prelude, input: &str) -> Result<f64, EvaluationError> {
+        let (_, ast) = parse_expression(input).unwrap();
+        prelude.evaluator(&prelude.environment()).evaluate(&ast)
+    }
+
+    /// Test what each preset lets expressions use
+    #[test]
+    fn test_presets() {
+        let none = Prelude::none();
+        assert_eq!(evaluate(&none, "1 + 2 < 4").unwrap(), 1.0);
+        assert!(matches!(
+            evaluate(&none, "sqrt(4)"),
+            Err(EvaluationError::UnknownFunction(name)) if name == "sqrt"
+        ));
+        assert!(matches!(
+            evaluate(&none, "pi"),
+            Err(EvaluationError::UndefinedVariable(_))
+        ));
+
+        let scientific = Prelude::scientific();
+        assert_eq!(
+            evaluate(&scientific, "cos(tau) + round(phi * 1000)").unwrap(),
+            1619.0
+        );
+        assert_eq!(
+            evaluate(&scientific, "ln(e) + mean([1, 2, 3])").unwrap(),
+            3.0
+        );
+        assert!(evaluate(&scientific, "year(now())").is_err());
+
+        let custom = Prelude::none().with_constant("vat", 0.5);
+        let mut env = custom.environment();
+        env.set("vat", 0.25);
+        let (_, ast) = parse_expression("100 * (1 + vat)").unwrap();
+        assert_eq!(custom.evaluator(&env).evaluate(&ast).unwrap(), 125.0);
+        assert_eq!(evaluate(&custom, "100 * (1 + vat)").unwrap(), 150.0);
+    }
+
+    /// Test the money functions, and that trigonometry is left out
+    #[cfg(feature = "finance")]
+    #[test]
+    fn test_finance() {
+        let finance = Prelude::finance();
+        let mut names = finance.functions().names();
+        names.sort();
+        let mut expected = FINANCE_FUNCTIONS.to_vec();
+        expected.sort();
+        assert_eq!(names, expected);
+        assert_eq!(
+            evaluate(&finance, "round(pmt(0, 10, 1000), 2)").unwrap(),
+            -100.0
+        );
+        assert!(evaluate(&finance, "sin(1)").is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Finding where an evaluation spends its time
+//!
+//! Generated formulas can grow to thousands of nodes, and when one is slow
+//! it's rarely obvious which part is to blame. [`profile`] evaluates a
+//! formula many times, timing every node, and gives a [`Profile`] with the
+//! time spent in each subtree, both in total and in the node itself.
+
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{ChildIndex, Environment, EvaluationError, Evaluator, Expr, format};
+
+/// The time spent in one node of a profiled expression
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProfileEntry {
+    /// Where the node is in the tree, see [`Expr::subexpressions`]
+    pub path: Vec<ChildIndex>,
+    /// The subtree, written as by [`format`]
+    pub expr: String,
+    /// How many times the node was evaluated, over all iterations
+    pub evaluations: u64,
+    /// The time spent evaluating the node, including its children
+    pub total: Duration,
+    /// The time spent in the node itself, without its children
+    pub own: Duration,
+}
+
+/// Where the time went over some evaluations of an expression
+#[derive(Debug, PartialEq, Clone)]
+pub struct Profile {
+    /// How many times the expression was evaluated
+    pub iterations: usize,
+    /// The time all the evaluations took
+    pub total: Duration,
+    /// The nodes that were evaluated, parents before their children
+    pub entries: Vec<ProfileEntry>,
+}
+
+impl Profile {
+    /// The entries, the ones that took the most time themselves first
+    pub fn hot_spots(&self) -> Vec<&ProfileEntry> {
+        let mut entries: Vec<&ProfileEntry> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| Reverse(entry.own));
+        entries
+    }
+
+    /// The share of the total time spent in a node itself, from 0 to 1
+    pub fn share(&self, entry: &ProfileEntry) -> f64 {
+        if self.total.is_zero() {
+            0.0
+        } else {
+            entry.own.as_secs_f64() / self.total.as_secs_f64()
+        }
+    }
+}
+
+/// The time spent in each node so far, while profiling
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    /// What's been counted for each node, by its address
+    nodes: HashMap<*const Expr, Counter>,
+    /// The time spent in the children of each node being evaluated, innermost last
+    children: Vec<Duration>,
+}
+
+/// What's been counted for one node
+#[derive(Debug, Default, Clone, Copy)]
+struct Counter {
+    evaluations: u64,
+    total: Duration,
+    own: Duration,
+}
+
+impl Counters {
+    /// Start evaluating a node
+    pub(crate) fn enter(&mut self) {
+        self.children.push(Duration::ZERO);
+    }
+
+    /// Finish evaluating `expr`, which took `elapsed` including its children
+    pub(crate) fn leave(&mut self, expr: &Expr, elapsed: Duration) {
+        let children = self.children.pop().unwrap_or_default();
+        if let Some(parent) = self.children.last_mut() {
+            *parent += elapsed;
+        }
+        let counter = self.nodes.entry(expr).or_default();
+        counter.evaluations += 1;
+        counter.total += elapsed;
+        counter.own += elapsed.saturating_sub(children);
+    }
+
+    /// The entries for the nodes of `expr` that were evaluated
+    fn entries(&self, expr: &Expr) -> Vec<ProfileEntry> {
+        expr.subexpressions()
+            .filter_map(|(path, node)| {
+                let counter = self.nodes.get(&(node as *const Expr))?;
+                Some(ProfileEntry {
+                    path,
+                    expr: format(node),
+                    evaluations: counter.evaluations,
+                    total: counter.total,
+                    own: counter.own,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Evaluator<'_> {
+    /// Evaluate `expr` `iterations` times, timing each node, see [`profile`]
+    pub fn profile(&self, expr: &Expr, iterations: usize) -> Result<Profile, EvaluationError> {
+        let counters = RefCell::new(Counters::default());
+        let evaluator = self.with_profile(&counters);
+        let start = Instant::now();
+        for _ in 0..iterations {
+            evaluator.evaluate_value(expr)?;
+        }
+        let total = start.elapsed();
+        let entries = counters.borrow().entries(expr);
+        Ok(Profile {
+            iterations,
+            total,
+            entries,
+        })
+    }
+}
+
+/// Evaluate `expr` `iterations` times, giving the time spent in each node
+///
+/// Every node is timed, which makes evaluating several times slower, so
+/// the times are best compared with each other rather than with an
+/// ordinary evaluation. The operators of a chain such as `a + b + c` are
+/// evaluated together, so only the whole chain and its operands have
+/// entries, and a call to a function defined in a
+/// [`Session`](crate::Session) counts as one node. The first error stops
+/// the profiling.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, profile, Environment};
+///
+/// let env: Environment = [("x", 2.0)].into_iter().collect();
+/// let (_, ast) = parse_expression("x + sqrt(x * 3)").unwrap();
+/// let result = profile(&ast, &env, 100).unwrap();
+///
+/// assert_eq!(result.entries[0].expr, "x + sqrt(x * 3)");
+/// assert_eq!(result.entries[0].evaluations, 100);
+/// assert!(result.entries[2].total >= result.entries[3].total);
+/// let hottest = result.hot_spots()[0];
+/// assert!(result.share(hottest) <= 1.0);
+/// ```
+pub fn profile(
+    expr: &Expr,
+    env: &Environment,
+    iterations: usize,
+) -> Result<Profile, EvaluationError> {
+    Evaluator::new(env).profile(expr, iterations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Test which nodes are counted, and that times add up
+    #[test]
+    fn test_profile() {
+        let env: Environment = [("x", 0.5)].into_iter().collect();
+        let (_, ast) = parse_expression("piecewise((x > 1, exp(x)), sin(x) * 2) + 1").unwrap();
+        let result = profile(&ast, &env, 50).unwrap();
+        let counted: Vec<(&str, u64)> = result
+            .entries
+            .iter()
+            .map(|entry| (entry.expr.as_str(), entry.evaluations))
+            .collect();
+        assert_eq!(
+            counted,
+            [
+                ("piecewise((x > 1, exp(x)), sin(x) * 2) + 1", 50),
+                ("piecewise((x > 1, exp(x)), sin(x) * 2)", 50),
+                ("x > 1", 50),
+                ("x", 50),
+                ("1", 50),
+                ("sin(x) * 2", 50),
+                ("sin(x)", 50),
+                ("x", 50),
+                ("2", 50),
+                ("1", 50),
+            ]
+        );
+        assert_eq!(result.entries[1].path, [0]);
+        let own: Duration = result.entries.iter().map(|entry| entry.own).sum();
+        assert!(own <= result.entries[0].total);
+        assert!(result.entries[0].total <= result.total);
+
+        let (_, ast) = parse_expression("1 / (x - 0.5)").unwrap();
+        assert!(matches!(
+            profile(&ast, &env, 10),
+            Err(EvaluationError::DivisionByZero)
+        ));
+    }
+}
@@ -0,0 +1,833 @@
+//! Trees as protobuf messages, for services that speak gRPC
+//!
+//! [`PROTO_SCHEMA`] is `proto/ast.proto`: an `Expr` message that mirrors
+//! [`Expr`] node for node, and a `Calculator` service taking formulas as text
+//! or as trees. [`Expr::to_protobuf`] and [`Expr::from_protobuf`] write and
+//! read `Expr` messages in the protobuf wire format, so a tree built here can
+//! be decoded by code generated from the schema in any language, and the
+//! other way around.
+//!
+//! Both work without recursion, since protobuf messages nest as deeply as
+//! the tree does. Messages are written back to front, so that each nested
+//! message's length is known by the time its header is written.
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::{CellRef, Comparison, Expr, Span};
+
+/// The protobuf schema for trees and evaluation requests, `proto/ast.proto`
+pub const PROTO_SCHEMA: &str = include_str!("../proto/ast.proto");
+
+/// Field numbers of the `Expr` message's `node`, one for each kind of node
+const NUMBER: u32 = 1;
+const VARIABLE: u32 = 2;
+const CELL: u32 = 3;
+const RANGE: u32 = 4;
+const ADD: u32 = 5;
+const SUBTRACT: u32 = 6;
+const MULTIPLY: u32 = 7;
+const DIVIDE: u32 = 8;
+const NEGATE: u32 = 9;
+const COMPARE: u32 = 10;
+const AND: u32 = 11;
+const OR: u32 = 12;
+const NOT: u32 = 13;
+const CALL: u32 = 14;
+const LET: u32 = 15;
+const PIECEWISE: u32 = 16;
+const LIST: u32 = 17;
+const INDEX: u32 = 18;
+const ERROR: u32 = 19;
+const NAMED: u32 = 20;
+
+/// Wire types, the low three bits of a field's tag
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+/// Errors that can occur while decoding a tree from protobuf
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ProtobufError {
+    #[error("Protobuf message ends early")]
+    Truncated,
+
+    #[error("Invalid protobuf message at byte {0}")]
+    Invalid(usize),
+
+    #[error("Protobuf message has no {0}")]
+    Missing(&'static str),
+}
+
+impl Expr {
+    /// This tree as an `Expr` message from [`PROTO_SCHEMA`], in the protobuf wire format
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, ast) = parse_expression("x + 1").unwrap();
+    /// let bytes = ast.to_protobuf();
+    /// // add { left { variable: "x" } right { number: 1 } }
+    /// assert_eq!(bytes[..7], [0x2a, 16, 0x0a, 3, 0x12, 1, b'x']);
+    /// assert_eq!(Expr::from_protobuf(&bytes), Ok(ast));
+    /// ```
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        let mut steps = vec![Step::Expr(self)];
+        while let Some(step) = steps.pop() {
+            writer.step(step, &mut steps);
+        }
+        writer.bytes.reverse();
+        writer.bytes
+    }
+
+    /// Decode an `Expr` message from [`PROTO_SCHEMA`]
+    ///
+    /// Fields the schema doesn't have are skipped, and fields it has that
+    /// are missing take their default values, as protobuf requires, except
+    /// that a node can't be missing its operands. Anything else that isn't
+    /// such a message gives an error rather than a panic.
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Expr, ProtobufError> {
+        // Nodes whose children are still being decoded, innermost last
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut range = 0..bytes.len();
+        loop {
+            let mut expr = match node(bytes, range)? {
+                Node::Leaf(expr) => expr,
+                Node::Branch(shape, mut children) => {
+                    children.reverse();
+                    match children.pop() {
+                        Some(first) => {
+                            frames.push(Frame {
+                                shape,
+                                children,
+                                built: Vec::new(),
+                            });
+                            range = first;
+                            continue;
+                        }
+                        None => shape.build(Vec::new()),
+                    }
+                }
+            };
+            // Hand the node to its parent, finishing each parent that has all its children
+            loop {
+                let Some(frame) = frames.last_mut() else {
+                    return Ok(expr);
+                };
+                frame.built.push(expr);
+                if let Some(next) = frame.children.pop() {
+                    range = next;
+                    break;
+                }
+                let frame = frames.pop().expect("just looked at it");
+                expr = frame.shape.build(frame.built);
+            }
+        }
+    }
+}
+
+/// Something left to write, in the order it's written: back to front
+enum Step<'a> {
+    /// The fields of an `Expr` message
+    Expr(&'a Expr),
+    /// The fields of the message in an `Expr`'s `node`, for nodes with one
+    Body(&'a Expr),
+    /// The fields of a `CellRef` message
+    Cell(&'a CellRef),
+    /// The fields of a `Piecewise.Case` message
+    Case(&'a (Expr, Expr)),
+    /// The end of a nested message's bytes, which are written next
+    Start,
+    /// The header of the nested message written since the matching `Start`
+    End(u32),
+    /// A varint field
+    Varint(u32, u64),
+    /// A string field
+    Text(u32, &'a str),
+    /// A double field
+    Double(u32, f64),
+}
+
+/// A field of a message, to be written
+enum Part<'a> {
+    /// A number or string
+    Scalar(Step<'a>),
+    /// A nested message with this field number, and what writes its fields
+    Message(u32, Step<'a>),
+}
+
+/// Bytes written back to front
+#[derive(Default)]
+struct Writer {
+    /// The message so far, reversed
+    bytes: Vec<u8>,
+    /// Where each nested message being written ends, innermost last
+    ends: Vec<usize>,
+}
+
+impl Writer {
+    /// Write `step`, or put what it stands for on `steps`
+    fn step<'a>(&mut self, step: Step<'a>, steps: &mut Vec<Step<'a>>) {
+        let push = |steps: &mut Vec<Step<'a>>, fields: Vec<Part<'a>>| {
+            // Steps come off the end, so the last field is written first
+            for field in fields {
+                match field {
+                    Part::Scalar(step) => steps.push(step),
+                    Part::Message(number, step) => {
+                        steps.extend([Step::End(number), step, Step::Start])
+                    }
+                }
+            }
+        };
+        match step {
+            Step::Expr(expr) => {
+                let field = match expr {
+                    Expr::Float(value) => Part::Scalar(Step::Double(NUMBER, *value)),
+                    Expr::Var(name) => Part::Scalar(Step::Text(VARIABLE, name)),
+                    Expr::Neg(inner) => Part::Message(NEGATE, Step::Expr(inner)),
+                    Expr::Not(inner) => Part::Message(NOT, Step::Expr(inner)),
+                    _ => Part::Message(kind(expr), Step::Body(expr)),
+                };
+                push(steps, vec![field]);
+            }
+            Step::Body(expr) => {
+                use Part::{Message, Scalar};
+                let operand = |number, expr| Message(number, Step::Expr(expr));
+                let fields = match expr {
+                    Expr::CellRef(cell) => {
+                        vec![
+                            Scalar(Step::Varint(1, cell.column.into())),
+                            Scalar(Step::Varint(2, cell.row.into())),
+                        ]
+                    }
+                    Expr::CellRange(from, to) => {
+                        vec![Message(1, Step::Cell(from)), Message(2, Step::Cell(to))]
+                    }
+                    Expr::Add(left, right)
+                    | Expr::Sub(left, right)
+                    | Expr::Mul(left, right)
+                    | Expr::Div(left, right)
+                    | Expr::And(left, right)
+                    | Expr::Or(left, right) => vec![operand(1, left), operand(2, right)],
+                    Expr::Compare(comparison, left, right) => vec![
+                        Scalar(Step::Varint(1, *comparison as u64)),
+                        operand(2, left),
+                        operand(3, right),
+                    ],
+                    Expr::Call(name, args) => {
+                        let mut fields = vec![Scalar(Step::Text(1, name))];
+                        fields.extend(args.iter().map(|arg| operand(2, arg)));
+                        fields
+                    }
+                    Expr::Named(name, value) => {
+                        vec![Scalar(Step::Text(1, name)), operand(2, value)]
+                    }
+                    Expr::Let(name, value, body) => vec![
+                        Scalar(Step::Text(1, name)),
+                        operand(2, value),
+                        operand(3, body),
+                    ],
+                    Expr::Piecewise(cases, otherwise) => {
+                        let mut fields: Vec<Part> = cases
+                            .iter()
+                            .map(|case| Message(1, Step::Case(case)))
+                            .collect();
+                        fields.extend(otherwise.iter().map(|otherwise| operand(2, otherwise)));
+                        fields
+                    }
+                    Expr::List(elements) => {
+                        elements.iter().map(|element| operand(1, element)).collect()
+                    }
+                    Expr::Index(value, indices) => {
+                        let mut fields = vec![operand(1, value)];
+                        fields.extend(indices.iter().map(|index| operand(2, index)));
+                        fields
+                    }
+                    Expr::Error(span) => vec![
+                        Scalar(Step::Varint(1, span.start as u64)),
+                        Scalar(Step::Varint(2, span.end as u64)),
+                    ],
+                    Expr::Float(_) | Expr::Var(_) | Expr::Neg(_) | Expr::Not(_) => {
+                        unreachable!("written without a body")
+                    }
+                };
+                push(steps, fields);
+            }
+            Step::Cell(cell) => push(
+                steps,
+                vec![
+                    Part::Scalar(Step::Varint(1, cell.column.into())),
+                    Part::Scalar(Step::Varint(2, cell.row.into())),
+                ],
+            ),
+            Step::Case((condition, value)) => push(
+                steps,
+                vec![
+                    Part::Message(1, Step::Expr(condition)),
+                    Part::Message(2, Step::Expr(value)),
+                ],
+            ),
+            Step::Start => self.ends.push(self.bytes.len()),
+            Step::End(number) => {
+                let end = self.ends.pop().expect("every End has a Start");
+                self.varint((self.bytes.len() - end) as u64);
+                self.tag(number, LENGTH_DELIMITED);
+            }
+            Step::Varint(number, value) => {
+                self.varint(value);
+                self.tag(number, VARINT);
+            }
+            Step::Text(number, text) => {
+                self.bytes.extend(text.bytes().rev());
+                self.varint(text.len() as u64);
+                self.tag(number, LENGTH_DELIMITED);
+            }
+            Step::Double(number, value) => {
+                self.bytes.extend(value.to_le_bytes().into_iter().rev());
+                self.tag(number, FIXED64);
+            }
+        }
+    }
+
+    /// Write a field's tag
+    fn tag(&mut self, number: u32, wire_type: u64) {
+        self.varint(u64::from(number) << 3 | wire_type);
+    }
+
+    /// Write a varint, reversed
+    fn varint(&mut self, mut n: u64) {
+        let start = self.bytes.len();
+        while n >= 0x80 {
+            self.bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.bytes.push(n as u8);
+        self.bytes[start..].reverse();
+    }
+}
+
+/// The field number of the message in a node's `node` field
+fn kind(expr: &Expr) -> u32 {
+    match expr {
+        Expr::Float(_) => NUMBER,
+        Expr::Var(_) => VARIABLE,
+        Expr::CellRef(_) => CELL,
+        Expr::CellRange(..) => RANGE,
+        Expr::Add(..) => ADD,
+        Expr::Sub(..) => SUBTRACT,
+        Expr::Mul(..) => MULTIPLY,
+        Expr::Div(..) => DIVIDE,
+        Expr::Neg(_) => NEGATE,
+        Expr::Compare(..) => COMPARE,
+        Expr::And(..) => AND,
+        Expr::Or(..) => OR,
+        Expr::Not(_) => NOT,
+        Expr::Call(..) => CALL,
+        Expr::Named(..) => NAMED,
+        Expr::Let(..) => LET,
+        Expr::Piecewise(..) => PIECEWISE,
+        Expr::List(_) => LIST,
+        Expr::Index(..) => INDEX,
+        Expr::Error(_) => ERROR,
+    }
+}
+
+/// A decoded `Expr` message: either a whole node, or a node still needing its children
+enum Node {
+    Leaf(Expr),
+    Branch(Shape, Vec<Range<usize>>),
+}
+
+/// A node without its children
+enum Shape {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Compare(Comparison),
+    And,
+    Or,
+    Not,
+    Call(String),
+    Named(String),
+    Let(String),
+    /// The number of cases, which may be followed by a default
+    Piecewise(usize),
+    List,
+    Index,
+}
+
+impl Shape {
+    /// The node, given as many children as [`node`] found for it
+    fn build(self, children: Vec<Expr>) -> Expr {
+        let mut children = children.into_iter();
+        let mut child = || Box::new(children.next().expect("checked when decoded"));
+        match self {
+            Shape::Add => Expr::Add(child(), child()),
+            Shape::Sub => Expr::Sub(child(), child()),
+            Shape::Mul => Expr::Mul(child(), child()),
+            Shape::Div => Expr::Div(child(), child()),
+            Shape::Neg => Expr::Neg(child()),
+            Shape::Compare(comparison) => Expr::Compare(comparison, child(), child()),
+            Shape::And => Expr::And(child(), child()),
+            Shape::Or => Expr::Or(child(), child()),
+            Shape::Not => Expr::Not(child()),
+            Shape::Let(name) => Expr::Let(name, child(), child()),
+            Shape::Index => Expr::Index(child(), children.collect()),
+            Shape::Call(name) => Expr::Call(name, children.collect()),
+            Shape::Named(name) => Expr::Named(name, child()),
+            Shape::List => Expr::List(children.collect()),
+            Shape::Piecewise(count) => {
+                let mut cases = Vec::with_capacity(count);
+                for _ in 0..count {
+                    cases.push((*child(), *child()));
+                }
+                Expr::Piecewise(cases, children.next().map(Box::new))
+            }
+        }
+    }
+}
+
+/// A node whose children are being decoded
+struct Frame {
+    /// The node
+    shape: Shape,
+    /// Where the children not yet decoded are, the next one last
+    children: Vec<Range<usize>>,
+    /// The children decoded so far
+    built: Vec<Expr>,
+}
+
+/// A field's value, as its wire type says to read it
+enum Value {
+    Varint(u64),
+    Fixed64(u64),
+    /// Where a string or nested message is
+    Bytes(Range<usize>),
+    /// Four bytes, which no field in the schema has
+    Fixed32,
+}
+
+/// One field of a message
+struct Field {
+    /// The field number
+    number: u32,
+    /// Where its tag starts
+    at: usize,
+    /// What it holds
+    value: Value,
+}
+
+/// The fields of the message in `range`, in order
+fn fields(bytes: &[u8], range: Range<usize>) -> Result<Vec<Field>, ProtobufError> {
+    let mut reader = Reader {
+        bytes: &bytes[..range.end],
+        position: range.start,
+    };
+    let mut fields = Vec::new();
+    while reader.position < range.end {
+        let at = reader.position;
+        let tag = reader.varint()?;
+        let number = u32::try_from(tag >> 3).map_err(|_| ProtobufError::Invalid(at))?;
+        let value = match tag & 7 {
+            VARINT => Value::Varint(reader.varint()?),
+            FIXED64 => Value::Fixed64(u64::from_le_bytes(
+                reader.take(8)?.try_into().expect("took 8 bytes"),
+            )),
+            LENGTH_DELIMITED => {
+                let length = reader.varint()?;
+                let start = reader.position;
+                let length = usize::try_from(length).map_err(|_| ProtobufError::Truncated)?;
+                reader.take(length)?;
+                Value::Bytes(start..reader.position)
+            }
+            FIXED32 => {
+                reader.take(4)?;
+                Value::Fixed32
+            }
+            // Groups, which proto3 doesn't have, and wire types that don't exist
+            _ => return Err(ProtobufError::Invalid(at)),
+        };
+        if number == 0 {
+            return Err(ProtobufError::Invalid(at));
+        }
+        fields.push(Field { number, at, value });
+    }
+    Ok(fields)
+}
+
+/// The `Expr` message in `range`
+fn node(bytes: &[u8], range: Range<usize>) -> Result<Node, ProtobufError> {
+    let fields = fields(bytes, range)?;
+    // Of a oneof's fields, the last one set is the one that counts
+    let Some(field) = fields
+        .iter()
+        .rev()
+        .find(|field| (NUMBER..=NAMED).contains(&field.number))
+    else {
+        return Err(ProtobufError::Missing("Expr.node"));
+    };
+    let invalid = ProtobufError::Invalid(field.at);
+    let range = match &field.value {
+        Value::Fixed64(bits) if field.number == NUMBER => {
+            return Ok(Node::Leaf(Expr::Float(f64::from_bits(*bits))));
+        }
+        Value::Bytes(range) if field.number != NUMBER => range.clone(),
+        _ => return Err(invalid),
+    };
+    match field.number {
+        VARIABLE => return Ok(Node::Leaf(Expr::Var(text(bytes, range, field.at)?))),
+        NEGATE => return Ok(Node::Branch(Shape::Neg, vec![range])),
+        NOT => return Ok(Node::Branch(Shape::Not, vec![range])),
+        _ => {}
+    }
+
+    let body = Message::new(bytes, range)?;
+    let binary = |shape| -> Result<Node, ProtobufError> {
+        let left = body.message(1, "Binary.left")?;
+        let right = body.message(2, "Binary.right")?;
+        Ok(Node::Branch(shape, vec![left, right]))
+    };
+    Ok(match field.number {
+        CELL => Node::Leaf(Expr::CellRef(body.cell()?)),
+        RANGE => {
+            let from = Message::new(bytes, body.message(1, "CellRange.from")?)?;
+            let to = Message::new(bytes, body.message(2, "CellRange.to")?)?;
+            Node::Leaf(Expr::CellRange(from.cell()?, to.cell()?))
+        }
+        ADD => binary(Shape::Add)?,
+        SUBTRACT => binary(Shape::Sub)?,
+        MULTIPLY => binary(Shape::Mul)?,
+        DIVIDE => binary(Shape::Div)?,
+        AND => binary(Shape::And)?,
+        OR => binary(Shape::Or)?,
+        COMPARE => {
+            let comparison = match body.varint(1)? {
+                0 => Comparison::Equal,
+                1 => Comparison::NotEqual,
+                2 => Comparison::Less,
+                3 => Comparison::LessOrEqual,
+                4 => Comparison::Greater,
+                5 => Comparison::GreaterOrEqual,
+                _ => return Err(invalid),
+            };
+            let left = body.message(2, "Comparison.left")?;
+            let right = body.message(3, "Comparison.right")?;
+            Node::Branch(Shape::Compare(comparison), vec![left, right])
+        }
+        CALL => Node::Branch(Shape::Call(body.text(1)?), body.repeated(2)?),
+        NAMED => {
+            let value = body.message(2, "Named.value")?;
+            Node::Branch(Shape::Named(body.text(1)?), vec![value])
+        }
+        LET => {
+            let value = body.message(2, "Let.value")?;
+            let scope = body.message(3, "Let.body")?;
+            Node::Branch(Shape::Let(body.text(1)?), vec![value, scope])
+        }
+        PIECEWISE => {
+            let cases = body.repeated(1)?;
+            let mut children = Vec::with_capacity(cases.len() * 2 + 1);
+            for case in &cases {
+                let case = Message::new(bytes, case.clone())?;
+                children.push(case.message(1, "Piecewise.Case.condition")?);
+                children.push(case.message(2, "Piecewise.Case.value")?);
+            }
+            children.extend(body.optional(2)?);
+            Node::Branch(Shape::Piecewise(cases.len()), children)
+        }
+        LIST => Node::Branch(Shape::List, body.repeated(1)?),
+        INDEX => {
+            let mut children = vec![body.message(1, "Index.value")?];
+            children.extend(body.repeated(2)?);
+            Node::Branch(Shape::Index, children)
+        }
+        _ => {
+            let position =
+                |number| usize::try_from(body.varint(number)?).map_err(|_| invalid.clone());
+            Node::Leaf(Expr::Error(Span {
+                start: position(1)?,
+                end: position(2)?,
+            }))
+        }
+    })
+}
+
+/// A decoded message other than `Expr`
+struct Message<'a> {
+    /// The bytes the message is in
+    bytes: &'a [u8],
+    /// Its fields, in order
+    fields: Vec<Field>,
+}
+
+impl<'a> Message<'a> {
+    /// The message in `range`
+    fn new(bytes: &'a [u8], range: Range<usize>) -> Result<Self, ProtobufError> {
+        Ok(Message {
+            bytes,
+            fields: fields(bytes, range)?,
+        })
+    }
+
+    /// The fields numbered `number`, in order
+    fn all(&self, number: u32) -> impl Iterator<Item = &Field> {
+        self.fields
+            .iter()
+            .filter(move |field| field.number == number)
+    }
+
+    /// Where each nested message numbered `number` is
+    fn repeated(&self, number: u32) -> Result<Vec<Range<usize>>, ProtobufError> {
+        self.all(number)
+            .map(|field| match &field.value {
+                Value::Bytes(range) => Ok(range.clone()),
+                _ => Err(ProtobufError::Invalid(field.at)),
+            })
+            .collect()
+    }
+
+    /// Where the nested message numbered `number` is, the last one if it's repeated
+    fn optional(&self, number: u32) -> Result<Option<Range<usize>>, ProtobufError> {
+        Ok(self.repeated(number)?.pop())
+    }
+
+    /// Where the nested message numbered `number` is, which must be there
+    fn message(&self, number: u32, name: &'static str) -> Result<Range<usize>, ProtobufError> {
+        self.optional(number)?.ok_or(ProtobufError::Missing(name))
+    }
+
+    /// The varint field numbered `number`, 0 if it's missing
+    fn varint(&self, number: u32) -> Result<u64, ProtobufError> {
+        match self.all(number).last() {
+            None => Ok(0),
+            Some(Field {
+                value: Value::Varint(n),
+                ..
+            }) => Ok(*n),
+            Some(field) => Err(ProtobufError::Invalid(field.at)),
+        }
+    }
+
+    /// The string field numbered `number`, empty if it's missing
+    fn text(&self, number: u32) -> Result<String, ProtobufError> {
+        match self.all(number).last() {
+            None => Ok(String::new()),
+            Some(Field {
+                value: Value::Bytes(range),
+                at,
+                ..
+            }) => text(self.bytes, range.clone(), *at),
+            Some(field) => Err(ProtobufError::Invalid(field.at)),
+        }
+    }
+
+    /// The cell a `CellRef` message refers to
+    fn cell(&self) -> Result<CellRef, ProtobufError> {
+        let coordinate = |number| {
+            let n = self.varint(number)?;
+            u32::try_from(n).map_err(|_| {
+                let field = self.all(number).last().expect("0 fits in a u32");
+                ProtobufError::Invalid(field.at)
+            })
+        };
+        Ok(CellRef::new(coordinate(1)?, coordinate(2)?))
+    }
+}
+
+/// The UTF-8 text in `range`, for a field whose tag is at `at`
+fn text(bytes: &[u8], range: Range<usize>, at: usize) -> Result<String, ProtobufError> {
+    String::from_utf8(bytes[range].to_vec()).map_err(|_| ProtobufError::Invalid(at))
+}
+
+/// A position in bytes being decoded
+struct Reader<'a> {
+    /// Everything that may be read
+    bytes: &'a [u8],
+    /// Where the next byte is read from
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// The next `n` bytes
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ProtobufError> {
+        let taken = self
+            .bytes
+            .get(self.position..self.position.saturating_add(n))
+            .ok_or(ProtobufError::Truncated)?;
+        self.position += n;
+        Ok(taken)
+    }
+
+    /// The next varint
+    fn varint(&mut self) -> Result<u64, ProtobufError> {
+        let start = self.position;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return if shift == 63 && byte > 1 {
+                    Err(ProtobufError::Invalid(start))
+                } else {
+                    Ok(n)
+                };
+            }
+        }
+        Err(ProtobufError::Invalid(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParserConfig, parse_expression, parse_expression_with, parse_with_recovery};
+
+    /// Parse an expression that must be valid
+    fn parse(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test that trees of every kind come back as they were
+    #[test]
+    fn test_round_trip() {
+        let mut trees: Vec<Expr> = [
+            "1",
+            "x + y - 2 * z / -w",
+            "x < 1 && !(y >= 2) || z != 3 == (a > b) <= (c == d)",
+            "let t = max(a, b, 0.5) in t == t",
+            "piecewise((x < 0, -x), (x > 9, 9), x) + piecewise((x, 1))",
+            "[[1, 2], [3, 4]][2, 1] + [][1] + f()",
+            "pmt(0.05, nper=360, pv=x * 2)",
+            "0.1 + -0.0 + 0.0 + 1e300 + nan + inf",
+        ]
+        .iter()
+        .map(|input| parse(input))
+        .collect();
+        let cells = ParserConfig {
+            cell_references: true,
+            ..ParserConfig::default()
+        };
+        trees.push(
+            parse_expression_with("SUM(A1:B20) + AA7", &cells)
+                .unwrap()
+                .1,
+        );
+        trees.push(Expr::CellRange(
+            CellRef::new(0, 2),
+            CellRef::new(u32::MAX, 0),
+        ));
+        trees.push(parse_with_recovery("1 + * 2").0);
+        trees.push(Expr::Var("ünïcode".to_string()));
+        for tree in trees {
+            let decoded = Expr::from_protobuf(&tree.to_protobuf()).unwrap();
+            // NaN isn't equal to itself, so compare the encodings
+            assert_eq!(decoded.to_protobuf(), tree.to_protobuf(), "{:?}", tree);
+        }
+        assert_eq!(
+            Expr::from_protobuf(&parse("f(x, 2)").to_protobuf()),
+            Ok(parse("f(x, 2)"))
+        );
+
+        let chain = parse(&vec!["x"; 100_000].join(" - "));
+        let decoded = Expr::from_protobuf(&chain.to_protobuf()).unwrap();
+        assert_eq!(decoded.to_protobuf(), chain.to_protobuf());
+    }
+
+    /// Test the bytes against the wire format, and the field numbers against the schema
+    #[test]
+    fn test_wire_format() {
+        // call { name: "f" arguments { number: 0.5 } arguments { negate { variable: "x" } } }
+        let mut expected = vec![0x72, 21, 0x0a, 1, b'f', 0x12, 9, 0x09];
+        expected.extend(0.5f64.to_le_bytes());
+        expected.extend([0x12, 5, 0x4a, 3, 0x12, 1, b'x']);
+        assert_eq!(parse("f(0.5, -x)").to_protobuf(), expected);
+
+        // Unknown fields are skipped, and proto3 defaults fill in missing ones
+        let mut extended = vec![0x18, 7, 0x7a, 4, 0x0a, 0, 0x12, 0];
+        extended.extend([0x72, 0, 0xad, 0x01, 1, 2, 3, 4]);
+        assert_eq!(
+            Expr::from_protobuf(&extended),
+            Ok(Expr::Call(String::new(), vec![]))
+        );
+
+        let node = PROTO_SCHEMA
+            .split("message Expr {")
+            .nth(1)
+            .and_then(|rest| rest.split("\n}").next())
+            .unwrap();
+        let mut numbers = Vec::new();
+        for line in node.lines().filter(|line| line.trim_end().ends_with(';')) {
+            let (field, number) = line.trim().trim_end_matches(';').split_once(" = ").unwrap();
+            let name = field.split_whitespace().last().unwrap();
+            numbers.push((name.to_string(), number.parse::<u32>().unwrap()));
+        }
+        let expected = [
+            ("number", NUMBER),
+            ("variable", VARIABLE),
+            ("cell", CELL),
+            ("range", RANGE),
+            ("add", ADD),
+            ("subtract", SUBTRACT),
+            ("multiply", MULTIPLY),
+            ("divide", DIVIDE),
+            ("negate", NEGATE),
+            ("compare", COMPARE),
+            ("and", AND),
+            ("or", OR),
+            ("not", NOT),
+            ("call", CALL),
+            ("let", LET),
+            ("piecewise", PIECEWISE),
+            ("list", LIST),
+            ("index", INDEX),
+            ("error", ERROR),
+            ("named", NAMED),
+        ]
+        .map(|(name, number)| (name.to_string(), number));
+        assert_eq!(numbers, expected);
+    }
+
+    /// Test that broken input is rejected
+    #[test]
+    fn test_invalid() {
+        let bytes = parse("f(x, 2) + [x][1]").to_protobuf();
+        for end in 0..bytes.len() {
+            assert!(Expr::from_protobuf(&bytes[..end]).is_err(), "{}", end);
+        }
+        assert_eq!(
+            Expr::from_protobuf(&[]),
+            Err(ProtobufError::Missing("Expr.node"))
+        );
+        // add { left { variable: "x" } }
+        assert_eq!(
+            Expr::from_protobuf(&[0x2a, 5, 0x0a, 3, 0x12, 1, b'x']),
+            Err(ProtobufError::Missing("Binary.right"))
+        );
+        // number as a varint rather than a double
+        assert_eq!(
+            Expr::from_protobuf(&[0x08, 1]),
+            Err(ProtobufError::Invalid(0))
+        );
+        // A nested message longer than the one it's in
+        assert_eq!(
+            Expr::from_protobuf(&[0x4a, 2, 0x4a, 9]),
+            Err(ProtobufError::Truncated)
+        );
+        // variable: invalid UTF-8
+        assert_eq!(
+            Expr::from_protobuf(&[0x12, 1, 0xff]),
+            Err(ProtobufError::Invalid(0))
+        );
+        // compare { operator: 6 }
+        assert_eq!(
+            Expr::from_protobuf(&[0x52, 2, 0x08, 6]),
+            Err(ProtobufError::Invalid(0))
+        );
+    }
+}
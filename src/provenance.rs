@@ -0,0 +1,269 @@
+//! Where each part of a tree came from
+//!
+//! Pipelines often put one formula together from several: a rate from one
+//! file, a rule from another, a default someone typed in. A [`Provenance`]
+//! is a side table of metadata, such as a file name or an author, for nodes
+//! of a tree, kept by [path](crate::Expr::get). Parts without their own entry
+//! come from wherever their nearest annotated ancestor did. The table is
+//! moved along when trees are [combined](Provenance::graft) and
+//! [rewritten](Provenance::follow), so the trees themselves stay plain
+//! [`Expr`]s.
+
+use std::collections::BTreeMap;
+
+use crate::{Change, ChildIndex, Expr, diff};
+
+/// Metadata for the nodes of one tree, by path
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, Expr, Provenance};
+///
+/// let (_, rate) = parse_expression("base + margin").unwrap();
+/// let (_, amount) = parse_expression("quantity * 2").unwrap();
+///
+/// // rate * amount, keeping track of which file each side is from
+/// let total = Expr::Mul(Box::new(rate), Box::new(amount));
+/// let mut provenance = Provenance::new();
+/// provenance.graft(&[0], Provenance::whole("rates.txt"));
+/// provenance.graft(&[1], Provenance::whole("orders.txt"));
+///
+/// assert_eq!(total.get(&[1, 0]), Some(&Expr::Var("quantity".to_string())));
+/// assert_eq!(provenance.origin(&[1, 0]), Some(&"orders.txt"));
+/// assert_eq!(provenance.origin(&[]), None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance<M> {
+    /// The metadata, by the path of the node it's for
+    entries: BTreeMap<Vec<ChildIndex>, M>,
+}
+
+impl<M> Provenance<M> {
+    /// A table with no metadata
+    pub fn new() -> Self {
+        Provenance {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// A table giving a whole tree the same metadata
+    pub fn whole(metadata: M) -> Self {
+        let mut provenance = Provenance::new();
+        provenance.annotate(&[], metadata);
+        provenance
+    }
+
+    /// Give the node at `path` its own metadata, returning what it had before
+    pub fn annotate(&mut self, path: &[ChildIndex], metadata: M) -> Option<M> {
+        self.entries.insert(path.to_vec(), metadata)
+    }
+
+    /// Take away the metadata of the node at `path`, leaving its children's
+    pub fn remove(&mut self, path: &[ChildIndex]) -> Option<M> {
+        self.entries.remove(path)
+    }
+
+    /// The metadata given to the node at `path` itself
+    pub fn get(&self, path: &[ChildIndex]) -> Option<&M> {
+        self.entries.get(path)
+    }
+
+    /// Where the node at `path` came from: its own metadata, or else its
+    /// nearest ancestor's
+    pub fn origin(&self, path: &[ChildIndex]) -> Option<&M> {
+        (0..=path.len())
+            .rev()
+            .find_map(|length| self.entries.get(&path[..length]))
+    }
+
+    /// Every node with metadata of its own, parents before their children
+    pub fn iter(&self) -> impl Iterator<Item = (&[ChildIndex], &M)> {
+        self.entries
+            .iter()
+            .map(|(path, metadata)| (path.as_slice(), metadata))
+    }
+
+    /// How many nodes have metadata of their own
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no node has metadata
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Use the table of a tree that was put at `path`, such as with
+    /// [`Expr::replace_at`] or by building a node around it, replacing the
+    /// metadata of whatever was there before
+    pub fn graft(&mut self, path: &[ChildIndex], other: Provenance<M>) {
+        drop(self.take_under(path));
+        self.insert_under(path, other.entries);
+    }
+
+    /// Move the metadata from `old` to where its nodes are in `new`, a
+    /// rewritten version of it
+    ///
+    /// The two trees are compared with [`diff`]. Nodes that are still there,
+    /// including operands that had an operation added around them or taken
+    /// away, keep their metadata. A subtree that was replaced keeps the
+    /// metadata of its root, since that's where the replacement came from,
+    /// and loses the metadata inside it.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Provenance};
+    ///
+    /// let (_, old) = parse_expression("price * 2").unwrap();
+    /// let (_, new) = parse_expression("(price + fee) * 2").unwrap();
+    /// let mut provenance = Provenance::new();
+    /// provenance.annotate(&[0], "catalog");
+    ///
+    /// provenance.follow(&old, &new);
+    /// assert_eq!(provenance.get(&[0, 0]), Some(&"catalog"));
+    /// assert_eq!(provenance.get(&[0]), None);
+    /// ```
+    pub fn follow(&mut self, old: &Expr, new: &Expr) {
+        for change in diff(old, new).changes {
+            match change {
+                Change::Literal { .. } => {}
+                Change::Added { mut path, .. } => {
+                    let Some(added) = path.pop() else { continue };
+                    let moved = self.take_under(&path);
+                    path.push(1 - added);
+                    self.insert_under(&path, moved);
+                }
+                Change::Removed { mut path, .. } => {
+                    let Some(removed) = path.pop() else { continue };
+                    let mut taken = self.take_under(&path);
+                    let operation = taken.remove(&[][..]);
+                    let kept = 1 - removed;
+                    let mut moved: BTreeMap<_, _> = taken
+                        .into_iter()
+                        .filter(|(relative, _)| relative.first() == Some(&kept))
+                        .map(|(relative, metadata)| (relative[1..].to_vec(), metadata))
+                        .collect();
+                    // The operand left behind came from where the operation did
+                    if let Some(operation) = operation {
+                        moved.entry(Vec::new()).or_insert(operation);
+                    }
+                    self.insert_under(&path, moved);
+                }
+                Change::Replaced { path, .. } => {
+                    let mut taken = self.take_under(&path);
+                    if let Some(root) = taken.remove(&[][..]) {
+                        self.entries.insert(path, root);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take out the metadata of the node at `path` and everything under it,
+    /// by path relative to it
+    fn take_under(&mut self, path: &[ChildIndex]) -> BTreeMap<Vec<ChildIndex>, M> {
+        // Descendants sort right after their ancestor, so they're one range
+        let mut rest = self.entries.split_off(path);
+        let after = match rest.keys().find(|key| !key.starts_with(path)) {
+            Some(key) => key.clone(),
+            None => return strip(rest, path.len()),
+        };
+        let mut after = rest.split_off(&after);
+        self.entries.append(&mut after);
+        strip(rest, path.len())
+    }
+
+    /// Put metadata with paths relative to `path` under it
+    fn insert_under(&mut self, path: &[ChildIndex], taken: BTreeMap<Vec<ChildIndex>, M>) {
+        for (relative, metadata) in taken {
+            let mut absolute = path.to_vec();
+            absolute.extend(relative);
+            self.entries.insert(absolute, metadata);
+        }
+    }
+}
+
+/// Drop the first `length` steps of every path
+fn strip<M>(entries: BTreeMap<Vec<ChildIndex>, M>, length: usize) -> BTreeMap<Vec<ChildIndex>, M> {
+    entries
+        .into_iter()
+        .map(|(path, metadata)| (path[length..].to_vec(), metadata))
+        .collect()
+}
+
+impl<M> Default for Provenance<M> {
+    fn default() -> Self {
+        Provenance::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Parse an expression that must be valid
+    fn parse(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test grafting tables and looking nodes up
+    #[test]
+    fn test_graft() {
+        let mut provenance = Provenance::whole("main");
+        provenance.annotate(&[1], "sub");
+        provenance.annotate(&[1, 0], "leaf");
+        provenance.annotate(&[2], "next");
+        assert_eq!(provenance.origin(&[1, 1, 3]), Some(&"sub"));
+        assert_eq!(provenance.origin(&[0]), Some(&"main"));
+
+        let mut inner = Provenance::whole("other");
+        inner.annotate(&[0], "other leaf");
+        provenance.graft(&[1], inner);
+        let entries: Vec<_> = provenance.iter().collect();
+        assert_eq!(
+            entries,
+            [
+                (&[][..], &"main"),
+                (&[1][..], &"other"),
+                (&[1, 0][..], &"other leaf"),
+                (&[2][..], &"next"),
+            ]
+        );
+
+        provenance.graft(&[], Provenance::new());
+        assert!(provenance.is_empty());
+    }
+
+    /// Test following a tree through operands being added, removed and replaced
+    #[test]
+    fn test_follow() {
+        let mut provenance = Provenance::new();
+        provenance.annotate(&[0], "a");
+        provenance.annotate(&[1], "b");
+        provenance.annotate(&[1, 1], "c");
+
+        let old = parse("x * (2 + y)");
+        let new = parse("x * ((2 + y) - 1)");
+        provenance.follow(&old, &new);
+        assert_eq!(provenance.get(&[1, 0]), Some(&"b"));
+        assert_eq!(provenance.get(&[1, 0, 1]), Some(&"c"));
+        assert_eq!(provenance.origin(&[1, 1]), None);
+
+        let (old, new) = (new, parse("x * (2 + y)"));
+        provenance.follow(&old, &new);
+        assert_eq!(provenance.len(), 3);
+        assert_eq!(provenance.get(&[1]), Some(&"b"));
+        assert_eq!(provenance.get(&[1, 1]), Some(&"c"));
+
+        let (old, new) = (new, parse("x * 5"));
+        provenance.follow(&old, &new);
+        assert_eq!(provenance.len(), 2);
+        assert_eq!(provenance.get(&[1]), Some(&"b"));
+
+        // The operation's metadata goes to the operand left behind
+        let mut provenance = Provenance::whole("sum");
+        provenance.follow(&parse("1 + z"), &parse("z"));
+        assert_eq!(provenance.get(&[]), Some(&"sum"));
+    }
+}
@@ -0,0 +1,900 @@
+//! Error-recovering parser
+//!
+//! [`parse_expression`](crate::parse_expression) stops at the first problem it
+//! finds. Editors and language servers want more than that: they want *every*
+//! syntax error in the input, plus a tree for the parts that did make sense.
+//!
+//! This parser follows the same grammar, but whenever something is missing or
+//! unexpected it records a [`Diagnostic`], puts an [`Expr::Error`] placeholder
+//! into the tree and skips ahead to the next operator boundary (such as `+` or `)`)
+//! before carrying on.
+
+use crate::{
+    Comparison, Diagnostic, Expr, ParserConfig, Span, argument_name, codes, parse_number,
+    parse_variable, strip_in, strip_let,
+};
+
+/// Characters where the parser can safely pick up again after an error
+const BOUNDARIES: &[char] = &['+', '-', '*', '/', '(', ')', '[', ']', ',', '<', '>'];
+
+/// Operators that also mark a boundary, although their first character alone doesn't
+const DOUBLE_BOUNDARIES: &[&str] = &["==", "!=", "&&", "||", "|>"];
+
+/// Whether the input at `rest` starts with an operator boundary
+fn at_boundary(rest: &str) -> bool {
+    rest.starts_with(BOUNDARIES) || DOUBLE_BOUNDARIES.iter().any(|op| rest.starts_with(op))
+}
+
+/// How much of `rest` the factor at its start takes up, without parsing it
+///
+/// With `expression`, it's the rest of the expression instead, as for the body of a `let`.
+fn skip_nested(rest: &str, expression: bool) -> usize {
+    let mut depth = 0;
+    let mut operand = false;
+    for (index, c) in rest.char_indices() {
+        match c {
+            ')' | ']' | ',' if depth == 0 => return index,
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ if depth == 0 && operand && !expression && at_boundary(&rest[index..]) => {
+                return index;
+            }
+            _ => {}
+        }
+        operand |= !matches!(c, '-' | '!') && !c.is_whitespace();
+    }
+    rest.len()
+}
+
+/// The binary operators, loosest binding first
+///
+/// Within a level, operators come before any shorter operators they start with.
+const LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<=", ">=", "<", ">"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+/// Build the node for a binary operator from `LEVELS`
+fn binary(op: &str, left: Expr, right: Expr) -> Expr {
+    let (left, right) = (Box::new(left), Box::new(right));
+    match op {
+        "||" => Expr::Or(left, right),
+        "&&" => Expr::And(left, right),
+        "+" => Expr::Add(left, right),
+        "-" => Expr::Sub(left, right),
+        "*" => Expr::Mul(left, right),
+        "/" => Expr::Div(left, right),
+        comparison => Expr::Compare(
+            Comparison::from_symbol(comparison).expect("the other operators are comparisons"),
+            left,
+            right,
+        ),
+    }
+}
+
+/// Parse an expression, recovering from syntax errors
+///
+/// Always returns a tree. Any part of the input that could not be parsed is
+/// represented by an [`Expr::Error`] node, and every problem found is listed
+/// in the returned diagnostics (in source order). An empty diagnostics list
+/// means the input was valid and the tree equals what
+/// [`parse_expression`](crate::parse_expression) produces.
+///
+/// Like `parse_expression`, nesting deeper than [`ParserConfig::max_depth`]
+/// is an error; the part that goes too deep is skipped.
+///
+/// # Example
+/// ```
+/// use ast::{parse_with_recovery, Expr};
+///
+/// let (ast, diagnostics) = parse_with_recovery("1 + * 2 - (3");
+/// assert_eq!(diagnostics.len(), 2);
+/// assert!(ast.has_errors());
+///
+/// let (ast, diagnostics) = parse_with_recovery("1 + 2");
+/// assert!(diagnostics.is_empty());
+/// assert_eq!(ast, Expr::Add(Box::new(Expr::Float(1.0)), Box::new(Expr::Float(2.0))));
+/// ```
+pub fn parse_with_recovery(input: &str) -> (Expr, Vec<Diagnostic>) {
+    let (expr, diagnostics, _) = parse_with_spans(input);
+    (expr, diagnostics)
+}
+
+/// Parse with recovery, also returning the source span of every node
+///
+/// The spans are listed in post-order (children before their parent, left
+/// before right), which is the order the nodes are built in. Walking the tree
+/// in the same order lines each node up with its span. The span of a
+/// parenthesized expression doesn't include the parentheses themselves.
+///
+/// # Example
+/// ```
+/// use ast::{parse_with_spans, Span};
+///
+/// let (_, diagnostics, spans) = parse_with_spans("1 + (x * 2)");
+/// assert!(diagnostics.is_empty());
+/// // 1, x, 2, x * 2, then the whole sum
+/// assert_eq!(spans[3], Span::new(5, 10));
+/// assert_eq!(spans[4], Span::new(0, 11));
+/// ```
+pub fn parse_with_spans(input: &str) -> (Expr, Vec<Diagnostic>, Vec<Span>) {
+    let mut parser = RecoveringParser {
+        source: input,
+        depth: 0,
+        nesting: 0,
+        max_depth: ParserConfig::default().max_depth,
+        lets: 0,
+        diagnostics: Vec::new(),
+        spans: Vec::new(),
+    };
+    let (_, expr) = parser.expression(input);
+    (expr, parser.diagnostics, parser.spans)
+}
+
+/// Whether the input after a '(' has a comma before its matching ')', making it a piecewise case
+fn case_ahead(inner: &str) -> bool {
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return false,
+            ')' => depth -= 1,
+            ',' if depth == 0 => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// State shared by the recursive parse functions
+struct RecoveringParser<'a> {
+    /// The complete input, used to turn remaining slices into offsets
+    source: &'a str,
+    /// How many parentheses are currently open
+    depth: usize,
+    /// How many factors and `let`s are being parsed, one inside the other
+    nesting: usize,
+    /// How deep `nesting` may go, from [`ParserConfig::max_depth`]
+    max_depth: usize,
+    /// How many `let` values are being parsed, which end at the keyword `in`
+    lets: usize,
+    /// Problems found so far
+    diagnostics: Vec<Diagnostic>,
+    /// Spans of the nodes built so far, in post-order
+    spans: Vec<Span>,
+}
+
+impl<'a> RecoveringParser<'a> {
+    /// Byte offset of the start of `rest` within the source
+    fn offset(&self, rest: &str) -> usize {
+        self.source.len() - rest.len()
+    }
+
+    /// Record the span of a node that ends where `rest` begins
+    fn node(&mut self, start: usize, rest: &str, expr: Expr) -> Expr {
+        self.spans.push(Span::new(start, self.offset(rest)));
+        expr
+    }
+
+    /// Record a problem and return a placeholder node for it
+    fn error(&mut self, span: Span, code: &'static str, message: String) -> Expr {
+        self.diagnostics
+            .push(Diagnostic::new(span, message).with_code(code));
+        self.spans.push(span);
+        Expr::Error(span)
+    }
+
+    /// Parse a factor or `let` at `rest` one level deeper, or skip it past the nesting limit
+    fn nested(
+        &mut self,
+        rest: &'a str,
+        expression: bool,
+        parse: impl FnOnce(&mut Self) -> (&'a str, Expr),
+    ) -> (&'a str, Expr) {
+        if self.nesting > self.max_depth {
+            let start = self.offset(rest);
+            let skipped = skip_nested(rest, expression);
+            let span = Span::new(start, start + rest[..skipped].trim_end().len());
+            let message = format!("nested more than {} levels deep", self.max_depth);
+            return (&rest[skipped..], self.error(span, codes::TOO_DEEP, message));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    /// expression := pipeline, with operators in the usual precedence
+    ///
+    /// This is the level where leftover input is dealt with: unmatched ')',
+    /// stray commas and operands without an operator between them.
+    fn expression(&mut self, input: &'a str) -> (&'a str, Expr) {
+        let first = self.offset(input.trim_start());
+        if let Some(after) = strip_let(input) {
+            let rest = input.trim_start();
+            return self.nested(rest, true, |parser| parser.binding(first, after));
+        }
+        let (mut remaining, mut left) = self.level(0, input);
+
+        loop {
+            let piped;
+            (remaining, left, piped) = self.pipeline(first, remaining, left);
+            let rest = remaining.trim_start();
+            let start = self.offset(rest);
+            match rest.chars().next() {
+                Some(c @ (')' | ']')) if self.depth == 0 => {
+                    self.diagnostics.push(
+                        Diagnostic::new(Span::new(start, start + 1), format!("unmatched '{}'", c))
+                            .with_code(codes::UNBALANCED_PARENTHESIS),
+                    );
+                    remaining = &rest[1..];
+                }
+                Some(',') if self.depth == 0 => {
+                    self.diagnostics.push(
+                        Diagnostic::new(
+                            Span::new(start, start + 1),
+                            "unexpected ',' outside a function call",
+                        )
+                        .with_code(codes::STRAY_COMMA),
+                    );
+                    remaining = &rest[1..];
+                }
+                None | Some(')' | ']' | ',') => return (rest, left),
+                // The operators have all been taken, so this one follows a pipeline
+                Some(_)
+                    if piped
+                        && LEVELS
+                            .iter()
+                            .flat_map(|ops| ops.iter())
+                            .any(|op| rest.starts_with(op)) =>
+                {
+                    self.diagnostics.push(
+                        Diagnostic::new(
+                            Span::new(start, start),
+                            "a pipeline needs parentheses to be an operand",
+                        )
+                        .with_code(codes::UNEXPECTED_INPUT),
+                    );
+                }
+                Some(_) if self.lets > 0 && strip_in(rest).is_some() => return (rest, left),
+                Some(_) => {
+                    // Two operands in a row, e.g. "1 2". Report the gap, then
+                    // parse (and drop) the stray operand so its own problems
+                    // are still reported.
+                    self.diagnostics.push(
+                        Diagnostic::new(Span::new(start, start), "expected an operator")
+                            .with_code(codes::MISSING_OPERATOR),
+                    );
+                    let built = self.spans.len();
+                    let (rest, _) = self.level(LEVELS.len() - 1, rest);
+                    self.spans.truncate(built);
+                    remaining = rest;
+                }
+            }
+
+            // Carry on with any operators after the problem, tightest first
+            for level in (0..LEVELS.len()).rev() {
+                (remaining, left) = self.operators(level, first, remaining, left);
+            }
+        }
+    }
+
+    /// pipeline := operand ('|>' name arguments?)*
+    ///
+    /// `left` is the operand, which started at `first`. Also gives whether
+    /// there were any stages.
+    fn pipeline(&mut self, first: usize, input: &'a str, mut left: Expr) -> (&'a str, Expr, bool) {
+        let mut remaining = input;
+        let mut piped = false;
+        while let Some(after) = remaining.trim_start().strip_prefix("|>") {
+            piped = true;
+            let rest = after.trim_start();
+            let Ok((after, Expr::Var(ref mut name))) = parse_variable(rest) else {
+                let at = self.offset(rest);
+                self.diagnostics.push(
+                    Diagnostic::new(Span::new(at, at), "expected a function name after '|>'")
+                        .with_code(codes::MISSING_OPERAND),
+                );
+                remaining = rest;
+                if !rest.is_empty() && !at_boundary(rest) {
+                    // Skip the stray operand, reporting its own problems
+                    let built = self.spans.len();
+                    (remaining, _) = self.level(LEVELS.len() - 1, rest);
+                    self.spans.truncate(built);
+                }
+                continue;
+            };
+            let (after, mut args) = match after.strip_prefix('(') {
+                Some(inner) => self.arguments(self.offset(after), inner, ')'),
+                None => (after, Vec::new()),
+            };
+            args.insert(0, left);
+            left = self.node(first, after, Expr::Call(std::mem::take(name), args));
+            remaining = after;
+        }
+        (remaining, left, piped)
+    }
+
+    /// binding := 'let' name '=' expression 'in' expression
+    ///
+    /// `input` starts just after the `let` at `start`.
+    fn binding(&mut self, start: usize, input: &'a str) -> (&'a str, Expr) {
+        let rest = input.trim_start();
+        let (mut rest, name) = match parse_variable(rest) {
+            Ok((rest, Expr::Var(ref mut name))) => (rest, std::mem::take(name)),
+            _ => unreachable!("a name follows every 'let' keyword"),
+        };
+
+        let after_name = rest.trim_start();
+        match after_name
+            .strip_prefix('=')
+            .filter(|after| !after.starts_with('='))
+        {
+            Some(after) => rest = after,
+            None => {
+                let at = self.offset(after_name);
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Span::new(at, at),
+                        format!("expected '=' after 'let {}'", name),
+                    )
+                    .with_code(codes::INCOMPLETE_LET),
+                );
+            }
+        }
+
+        self.lets += 1;
+        let (rest, value) = self.expression(rest);
+        self.lets -= 1;
+
+        let (rest, body) = match strip_in(rest) {
+            Some(after) => self.expression(after),
+            None => {
+                let at = self.offset(rest);
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Span::new(at, at),
+                        format!("expected 'in' after the value of '{}'", name),
+                    )
+                    .with_code(codes::INCOMPLETE_LET),
+                );
+                self.spans.push(Span::new(at, at));
+                (rest, Expr::Error(Span::new(at, at)))
+            }
+        };
+        let binding = Expr::Let(name, Box::new(value), Box::new(body));
+        (rest, self.node(start, rest, binding))
+    }
+
+    /// level := next_level (operator next_level)*, for the operators in `LEVELS[level]`
+    ///
+    /// Past the last level come the factors.
+    fn level(&mut self, level: usize, input: &'a str) -> (&'a str, Expr) {
+        if level == LEVELS.len() {
+            return self.factor(input);
+        }
+        let first = self.offset(input.trim_start());
+        let (rest, left) = self.level(level + 1, input);
+        self.operators(level, first, rest, left)
+    }
+
+    /// Parse the operators of one level that follow `left`, which started at `first`
+    fn operators(
+        &mut self,
+        level: usize,
+        first: usize,
+        input: &'a str,
+        mut left: Expr,
+    ) -> (&'a str, Expr) {
+        let mut remaining = input;
+        loop {
+            let rest = remaining.trim_start();
+            let Some((op, len)) = self.operator(LEVELS[level], rest) else {
+                return (remaining, left);
+            };
+            let (rest, right) = self.level(level + 1, &rest[len..]);
+            left = self.node(first, rest, binary(op, left, right));
+            remaining = rest;
+        }
+    }
+
+    /// Match one of `operators` at the start of `rest`, returning it and its length in the input
+    ///
+    /// A lone '=', '&' or '|' is reported as a typo for '==', '&&' or '||',
+    /// and parsed as if it were one.
+    fn operator(
+        &mut self,
+        operators: &[&'static str],
+        rest: &str,
+    ) -> Option<(&'static str, usize)> {
+        if let Some(op) = operators.iter().find(|op| rest.starts_with(**op)) {
+            return Some((op, op.len()));
+        }
+
+        if rest.starts_with("|>") {
+            return None;
+        }
+        let op = operators
+            .iter()
+            .find(|op| matches!(**op, "==" | "&&" | "||") && rest.starts_with(&op[..1]))?;
+        let start = self.offset(rest);
+        self.diagnostics.push(
+            Diagnostic::new(
+                Span::new(start, start + 1),
+                format!("'{}' is not an operator, did you mean '{}'?", &op[..1], op),
+            )
+            .with_code(codes::HALF_OPERATOR),
+        );
+        Some((op, 1))
+    }
+
+    /// Expect the ')' (or ']') closing the '(' (or '[') at `open`
+    fn close(&mut self, open: usize, rest: &'a str, closing: char) -> &'a str {
+        match rest.strip_prefix(closing) {
+            Some(rest) => rest,
+            None => {
+                let end = self.offset(rest);
+                let opening = if closing == ']' { '[' } else { '(' };
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Span::new(open, end),
+                        format!("unclosed '{}' (expected '{}')", opening, closing),
+                    )
+                    .with_code(codes::UNBALANCED_PARENTHESIS),
+                );
+                rest
+            }
+        }
+    }
+
+    /// arguments := '(' (expression (',' expression)*)? ')', or the same in '[' and ']'
+    ///
+    /// `input` starts just after the '(' (or '[') at `open`.
+    fn arguments(&mut self, open: usize, input: &'a str, closing: char) -> (&'a str, Vec<Expr>) {
+        let mut args = Vec::new();
+        self.depth += 1;
+        let mut rest = input.trim_start();
+        let config = ParserConfig::default();
+        let mut named = false;
+        if !rest.starts_with(closing) {
+            loop {
+                let start = self.offset(rest.trim_start());
+                let (after, arg) = match argument_name(rest, &config).filter(|_| closing == ')') {
+                    Some((value, name)) => {
+                        named = true;
+                        let (after, value) = self.expression(value);
+                        let arg = Expr::Named(name.to_string(), Box::new(value));
+                        (after, self.node(start, after, arg))
+                    }
+                    None => {
+                        let (after, arg) = self.expression(rest);
+                        if named {
+                            self.diagnostics.push(
+                                Diagnostic::new(
+                                    Span::new(start, self.offset(after)),
+                                    "positional arguments must come before named ones",
+                                )
+                                .with_code(codes::ARGUMENT_ORDER),
+                            );
+                        }
+                        (after, arg)
+                    }
+                };
+                args.push(arg);
+                match after.strip_prefix(',') {
+                    Some(after) => rest = after,
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+        self.depth -= 1;
+        (self.close(open, rest, closing), args)
+    }
+
+    /// piecewise := '(' item (',' item)* ')', where item := '(' expression ',' expression ')' | expression
+    ///
+    /// `input` starts just after the '(' at `open`. An item whose parentheses
+    /// hold a top-level comma is a case; any other is the default. A missing
+    /// case, or a default that isn't last, is reported.
+    fn piecewise(&mut self, open: usize, input: &'a str) -> (&'a str, Expr) {
+        let (mut cases, mut default) = (Vec::new(), None);
+        self.depth += 1;
+        let mut rest = input.trim_start();
+        if !rest.starts_with(')') {
+            loop {
+                let item = self.offset(rest);
+                if default.is_some() {
+                    self.diagnostics.push(
+                        Diagnostic::new(
+                            Span::new(item, item),
+                            "the default of 'piecewise' must come last",
+                        )
+                        .with_code(codes::MALFORMED_PIECEWISE),
+                    );
+                }
+                if let Some(inner) = rest.strip_prefix('(')
+                    && case_ahead(inner)
+                {
+                    self.depth += 1;
+                    let (after, condition) = self.expression(inner);
+                    let after = after.strip_prefix(',').unwrap_or(after);
+                    let (after, value) = self.expression(after);
+                    self.depth -= 1;
+                    rest = self.close(item, after, ')');
+                    cases.push((condition, value));
+                } else {
+                    let (after, expr) = self.expression(rest);
+                    rest = after;
+                    default = Some(Box::new(expr));
+                }
+                match rest.trim_start().strip_prefix(',') {
+                    Some(after) => rest = after.trim_start(),
+                    None => break,
+                }
+            }
+        }
+        rest = rest.trim_start();
+        if cases.is_empty() {
+            let end = self.offset(rest);
+            self.diagnostics.push(
+                Diagnostic::new(
+                    Span::new(open, end),
+                    "expected a (condition, value) case in 'piecewise'",
+                )
+                .with_code(codes::MALFORMED_PIECEWISE),
+            );
+        }
+        self.depth -= 1;
+        (self.close(open, rest, ')'), Expr::Piecewise(cases, default))
+    }
+
+    /// factor := '-' factor | '!' factor | primary ('[' expression (',' expression)? ']' | '.' name arguments)*
+    fn factor(&mut self, input: &'a str) -> (&'a str, Expr) {
+        let rest = input.trim_start();
+        self.nested(rest, false, |parser| parser.unary(rest))
+    }
+
+    /// The body of [`RecoveringParser::factor`], for `rest` without leading whitespace
+    fn unary(&mut self, rest: &'a str) -> (&'a str, Expr) {
+        let start = self.offset(rest);
+
+        if let Some(rest) = rest.strip_prefix('-') {
+            let (rest, expr) = self.factor(rest);
+            return (rest, self.node(start, rest, Expr::Neg(Box::new(expr))));
+        }
+
+        if let Some(rest) = rest.strip_prefix('!') {
+            let (rest, expr) = self.factor(rest);
+            return (rest, self.node(start, rest, Expr::Not(Box::new(expr))));
+        }
+
+        let (mut rest, mut expr) = self.primary(rest);
+        loop {
+            if rest.starts_with('[') {
+                let open = self.offset(rest);
+                let (after, indices) = self.arguments(open, &rest[1..], ']');
+                if !(1..=2).contains(&indices.len()) {
+                    let end = self.offset(after);
+                    self.diagnostics.push(
+                        Diagnostic::new(Span::new(open, end), "expected one or two indices")
+                            .with_code(codes::UNEXPECTED_INPUT),
+                    );
+                }
+                rest = after;
+                expr = self.node(start, rest, Expr::Index(Box::new(expr), indices));
+            } else if let Some(after_dot) = rest.strip_prefix('.')
+                && let Ok((after, Expr::Var(ref mut name))) = parse_variable(after_dot)
+                && after.starts_with('(')
+            {
+                // A method call: the value before the dot is the first argument
+                let open = self.offset(after);
+                let (after, mut args) = self.arguments(open, &after[1..], ')');
+                args.insert(0, expr);
+                rest = after;
+                let call = Expr::Call(std::mem::take(name), args);
+                expr = self.node(start, rest, call);
+            } else {
+                break;
+            }
+        }
+        (rest, expr)
+    }
+
+    /// primary := '(' expression ')' | '[' list ']' | piecewise | name arguments | variable | number
+    fn primary(&mut self, rest: &'a str) -> (&'a str, Expr) {
+        let start = self.offset(rest);
+
+        if let Some(inner) = rest.strip_prefix('[') {
+            let (rest, elements) = self.arguments(start, inner, ']');
+            return (rest, self.node(start, rest, Expr::List(elements)));
+        }
+
+        if let Some(inner) = rest.strip_prefix('(') {
+            self.depth += 1;
+            let (mut rest, expr) = self.expression(inner);
+            // A list like "(1, 2)" outside a call: report and skip the extras
+            while let Some(after) = rest.strip_prefix(',') {
+                let comma = self.offset(rest);
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        Span::new(comma, comma + 1),
+                        "unexpected ',' outside a function call",
+                    )
+                    .with_code(codes::STRAY_COMMA),
+                );
+                let built = self.spans.len();
+                (rest, _) = self.expression(after);
+                self.spans.truncate(built);
+            }
+            self.depth -= 1;
+            return (self.close(start, rest, ')'), expr);
+        }
+
+        if let Ok((after, Expr::Var(ref name))) = parse_variable(rest)
+            && name == "piecewise"
+            && after.starts_with('(')
+        {
+            let open = self.offset(after);
+            let (rest, piecewise) = self.piecewise(open, &after[1..]);
+            return (rest, self.node(start, rest, piecewise));
+        }
+
+        if let Ok((after, Expr::Var(ref mut name))) = parse_variable(rest)
+            && after.starts_with('(')
+        {
+            let open = self.offset(after);
+            let (rest, args) = self.arguments(open, &after[1..], ')');
+            let call = Expr::Call(std::mem::take(name), args);
+            return (rest, self.node(start, rest, call));
+        }
+
+        if let Ok((rest, expr)) = parse_variable(rest).or_else(|_| parse_number(rest)) {
+            return (rest, self.node(start, rest, expr));
+        }
+
+        match rest.chars().next() {
+            None => (
+                rest,
+                self.error(
+                    Span::new(start, start),
+                    codes::MISSING_OPERAND,
+                    "expected an expression, found end of input".to_string(),
+                ),
+            ),
+            // Leave operators and ')' for the caller; the operand is simply missing
+            Some(c) if at_boundary(rest) => (
+                rest,
+                self.error(
+                    Span::new(start, start),
+                    codes::MISSING_OPERAND,
+                    format!("expected an expression before '{}'", c),
+                ),
+            ),
+            Some(_) => {
+                let skipped = rest
+                    .char_indices()
+                    .find(|(index, _)| at_boundary(&rest[*index..]))
+                    .map_or(rest.len(), |(index, _)| index);
+                let junk = rest[..skipped].trim_end();
+                let span = Span::new(start, start + junk.len());
+                (
+                    &rest[skipped..],
+                    self.error(
+                        span,
+                        codes::UNEXPECTED_INPUT,
+                        format!("expected an expression, found '{}'", junk),
+                    ),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Test that valid input produces no diagnostics and the regular tree
+    #[test]
+    fn test_valid_input_matches_parse_expression() {
+        for expression in [
+            "42",
+            "1 + 2 * (3 - 4) / 5",
+            "-(3 + 4) * 2",
+            "-(-5)",
+            "x * y_1",
+            "max(1, sqrt(x) * 2, pi()) - f()",
+            "x != 0 && 1 / x > 2 || !y",
+            "a <= b == (c >= d) && e < f - 1",
+            "let t = x + 1 in (let u = t in u * t) - 2",
+            "piecewise((x < 0, -x), ((x), max(x, 1)), (x + 1) * 2) + 1",
+            "piecewise((x, 1))",
+            "[[1, x], [-y, 2]][1][2] * [a][1] - []",
+            "x.round(2).clamp(0, [1][1]) + (y - 1).abs()",
+            "a + 1 |> abs |> round(2, (b |> f)) |> g",
+            "pmt(0.05, nper = 360, pv=max(a, b)) |> round(digits=2)",
+        ] {
+            let (ast, diagnostics) = parse_with_recovery(expression);
+            assert!(
+                diagnostics.is_empty(),
+                "'{}': {:?}",
+                expression,
+                diagnostics
+            );
+            assert_eq!(ast, parse_expression(expression).unwrap().1);
+        }
+    }
+
+    /// Test that every error in the input is reported, in source order
+    #[test]
+    fn test_reports_all_errors() {
+        let (ast, diagnostics) = parse_with_recovery("5 + $%& * (2 - ) + )");
+        let spans: Vec<Span> = diagnostics.iter().map(|d| d.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(4, 7),
+                Span::new(15, 15),
+                Span::new(19, 19),
+                Span::new(19, 20)
+            ],
+            "{:?}",
+            diagnostics
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "expected an expression, found '$%&'"
+        );
+        assert!(ast.has_errors());
+    }
+
+    /// Test that the partial tree keeps the valid parts around the error nodes
+    #[test]
+    fn test_partial_tree() {
+        let (ast, diagnostics) = parse_with_recovery("2 * ? + 3");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            ast,
+            Expr::Add(
+                Box::new(Expr::Mul(
+                    Box::new(Expr::Float(2.0)),
+                    Box::new(Expr::Error(Span::new(4, 5)))
+                )),
+                Box::new(Expr::Float(3.0))
+            )
+        );
+    }
+
+    /// Test that node spans are recorded in post-order
+    #[test]
+    fn test_spans_in_post_order() {
+        let (_, diagnostics, spans) = parse_with_spans("1 + (2 * 3)");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 1),  // 1
+                Span::new(5, 6),  // 2
+                Span::new(9, 10), // 3
+                Span::new(5, 10), // 2 * 3
+                Span::new(0, 11), // 1 + (2 * 3)
+            ]
+        );
+    }
+
+    /// Test that a call's span covers its name and parentheses, after its arguments
+    #[test]
+    fn test_call_spans() {
+        let (ast, diagnostics, spans) = parse_with_spans("2 * max(a, (b))");
+        assert!(diagnostics.is_empty());
+        assert!(matches!(&ast, Expr::Mul(_, call) if matches!(**call, Expr::Call(..))));
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 1),   // 2
+                Span::new(8, 9),   // a
+                Span::new(12, 13), // b
+                Span::new(4, 15),  // max(a, (b))
+                Span::new(0, 15),  // 2 * max(a, (b))
+            ]
+        );
+    }
+
+    /// Test that a single '=', '&' or '|' is reported but parsed as the intended operator
+    #[test]
+    fn test_operator_typos() {
+        let (ast, diagnostics) = parse_with_recovery("a = 1 | b & c");
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "'=' is not an operator, did you mean '=='?",
+                "'|' is not an operator, did you mean '||'?",
+                "'&' is not an operator, did you mean '&&'?",
+            ]
+        );
+        assert_eq!(ast, parse_expression("a == 1 || b && c").unwrap().1);
+    }
+
+    /// Test the errors that the strict parser rejects are all reported here
+    #[test]
+    fn test_invalid_expressions_have_diagnostics() {
+        for expression in [
+            "* 40 - 10",
+            "5 + + 3",
+            "(5 + 3",
+            "5 + ",
+            "",
+            "5 1",
+            ")))",
+            "5 + ()",
+            "1, 2",
+            "(1, 2)",
+            "sqrt(4",
+            "max(1,)",
+            "a < < b",
+            "a & b",
+            "1 !",
+            "piecewise()",
+            "piecewise(1, (x, 2))",
+            "piecewise((x, 1), 2, 3)",
+            "piecewise((x 1))",
+            "[1, 2",
+            "[1]]",
+            "x[]",
+            "x[1, 2, 3]",
+            "[1 2]",
+            "x |>",
+            "x |> 2",
+            "x |> f + 1",
+            "round(digits=2, x)",
+            "[a=1]",
+            "x |> f(",
+        ] {
+            let (_, diagnostics) = parse_with_recovery(expression);
+            assert!(
+                !diagnostics.is_empty(),
+                "'{}' should have diagnostics",
+                expression
+            );
+        }
+    }
+
+    /// Test that nesting is limited like `parse_expression` limits it, and skipped past the limit
+    #[test]
+    fn test_nesting_limit() {
+        let depth = ParserConfig::default().max_depth;
+        for levels in [depth - 1, depth, depth + 1, depth + 2] {
+            for (open, close) in [("(", ")"), ("-", ""), ("abs(", ")"), ("let t = 1 in ", "")] {
+                let input = format!("{}1{}", open.repeat(levels), close.repeat(levels));
+                let (_, diagnostics) = parse_with_recovery(&input);
+                assert_eq!(
+                    diagnostics.is_empty(),
+                    parse_expression(&input).is_ok(),
+                    "{} levels of '{}'",
+                    levels,
+                    open
+                );
+            }
+        }
+
+        let input = format!("{}1{} + 2", "(".repeat(100_000), ")".repeat(100_000));
+        let (ast, diagnostics) = parse_with_recovery(&input);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some(codes::TOO_DEEP));
+        assert!(matches!(&ast, Expr::Add(left, _) if left.has_errors()));
+
+        for input in ["(".repeat(100_000), format!("{}1", "-".repeat(100_000))] {
+            let (ast, diagnostics) = parse_with_recovery(&input);
+            assert_eq!(diagnostics[0].code, Some(codes::TOO_DEEP));
+            assert!(ast.has_errors());
+        }
+    }
+}
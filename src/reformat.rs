@@ -0,0 +1,164 @@
+//! Formatting formula files from the command line
+//!
+//! `ast fmt rates.calc` rewrites a file of formulas, one per line like a
+//! startup file, in the canonical style of [`ast::format`]. Assignments and
+//! function definitions keep their left side, `#` comments are kept (after
+//! two spaces when they follow a formula), and runs of blank lines become
+//! one. With `--check`, files are only compared with their formatted text,
+//! for continuous integration.
+
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use ast::{Assignee, Session, format, parse_expression};
+
+/// The command line help for `ast fmt`
+pub const USAGE: &str = "Usage: ast fmt [--check] [<file>...]
+
+Rewrite files of formulas, one per line, in the canonical style: one space
+around binary operators, parentheses only where the grouping needs them,
+and numbers in their shortest form. Comments and assignments are kept.
+Without files, or with '-', standard input is formatted to standard output.
+With --check, files are left alone and the ones that would change are
+listed, failing if there are any.";
+
+/// Parse the arguments after `fmt` and format the files, giving whether they were all fine
+pub fn run(args: impl Iterator<Item = String>) -> Result<bool, String> {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            option if option.starts_with("--") => {
+                return Err(format!("{}: unknown option", option));
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut fine = true;
+    for file in files {
+        let source = if file == "-" {
+            let mut source = String::new();
+            io::stdin()
+                .read_to_string(&mut source)
+                .map(|_| source)
+                .map_err(|error| error.to_string())
+        } else {
+            fs::read_to_string(&file).map_err(|error| error.to_string())
+        };
+        let formatted = source.and_then(|source| {
+            format_file(&source)
+                .map(|formatted| (formatted != source, formatted))
+                .map_err(|(line, message)| format!("line {}: {}", line, message))
+        });
+        let result = match formatted {
+            Ok((changed, _)) if check => {
+                if changed {
+                    println!("{}", file);
+                }
+                Ok(!changed)
+            }
+            Ok((_, formatted)) if file == "-" => {
+                print!("{}", formatted);
+                Ok(true)
+            }
+            Ok((false, _)) => Ok(true),
+            Ok((true, formatted)) => fs::write(&file, formatted)
+                .map(|_| true)
+                .map_err(|error| error.to_string()),
+            Err(message) => Err(message),
+        };
+        match result {
+            Ok(ok) => fine &= ok,
+            Err(message) => {
+                eprintln!("❌ {}: {}", file, message);
+                fine = false;
+            }
+        }
+    }
+    Ok(fine)
+}
+
+/// A file of formulas in the canonical style, or the number of the first line that doesn't parse and why
+fn format_file(source: &str) -> Result<String, (usize, String)> {
+    let session = Session::new();
+    let mut formatted = String::new();
+    let mut blank = false;
+    for (number, line) in source.lines().enumerate() {
+        let (code, comment) = match line.find('#') {
+            Some(at) => (&line[..at], Some(line[at..].trim_end())),
+            None => (line, None),
+        };
+        let code = code.trim();
+        if code.is_empty() && comment.is_none() {
+            blank = !formatted.is_empty();
+            continue;
+        }
+        if blank {
+            formatted.push('\n');
+            blank = false;
+        }
+
+        if !code.is_empty() {
+            let (left, expression) = match session.split_assignment(code) {
+                Some((Assignee::Variable(name), expression)) => {
+                    (format!("{} = ", name), expression)
+                }
+                Some((Assignee::Function { name, params }, expression)) => {
+                    (format!("{}({}) = ", name, params.join(", ")), expression)
+                }
+                None => (String::new(), code),
+            };
+            let ast = match parse_expression(expression) {
+                Ok((rest, ast)) if rest.trim().is_empty() => Ok(ast),
+                Ok((rest, _)) => Err(rest),
+                Err(nom::Err::Error(error) | nom::Err::Failure(error)) => Err(error.input),
+                Err(nom::Err::Incomplete(_)) => Err(""),
+            };
+            let ast = ast.map_err(|rest| {
+                let start = line.len() - line.trim_start().len();
+                let column = start + code.len() - rest.trim_start().len() + 1;
+                (number + 1, format!("syntax error at column {}", column))
+            })?;
+            formatted.push_str(&left);
+            formatted.push_str(&format(&ast));
+        }
+        if let Some(comment) = comment {
+            if !code.is_empty() {
+                formatted.push_str("  ");
+            }
+            formatted.push_str(comment);
+        }
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test formatting a file with assignments, comments and blank lines
+    #[test]
+    fn test_format_file() {
+        let source = "\n# Rates\nrate=(base+margin)   # yearly\n\n\n\nmonthly( r ,n )=r/12*n\n  total*(1)\n\n";
+        let formatted = format_file(source).unwrap();
+        assert_eq!(
+            formatted,
+            "# Rates\nrate = base + margin  # yearly\n\nmonthly(r, n) = r / 12 * n\ntotal * 1\n"
+        );
+        assert_eq!(format_file(&formatted).unwrap(), formatted);
+
+        assert_eq!(
+            format_file("1 + 2\nx = 3 +* 4\n"),
+            Err((2, "syntax error at column 8".to_string()))
+        );
+    }
+}
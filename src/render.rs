@@ -0,0 +1,317 @@
+//! Colored output for the REPL
+//!
+//! The library only produces plain values and trees; this module decides how
+//! they look on a terminal. A [`Renderer`] either colors text with the ANSI
+//! codes of a [`Theme`], or leaves it exactly as it is.
+
+use ast::Expr;
+
+/// What a piece of output is, which decides its color
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Style {
+    /// A result, or a confirmation that something worked
+    Success,
+    /// Something that failed
+    Error,
+    /// A warning about something that worked
+    Warning,
+    /// Extra information, such as previews and explanations
+    Hint,
+    /// A number in a tree
+    Number,
+    /// A variable name in a tree
+    Variable,
+    /// A function name in a tree
+    Function,
+    /// The kind of a node in a tree, such as `Add`
+    Node,
+}
+
+/// ANSI SGR codes for each [`Style`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Theme {
+    success: &'static str,
+    error: &'static str,
+    warning: &'static str,
+    hint: &'static str,
+    number: &'static str,
+    variable: &'static str,
+    function: &'static str,
+    node: &'static str,
+}
+
+impl Theme {
+    /// Bright colors, for dark terminal backgrounds
+    pub const DARK: Theme = Theme {
+        success: "92",
+        error: "91",
+        warning: "93",
+        hint: "96",
+        number: "95",
+        variable: "94",
+        function: "92",
+        node: "37",
+    };
+
+    /// Deeper colors, for light terminal backgrounds
+    pub const LIGHT: Theme = Theme {
+        success: "32",
+        error: "31",
+        warning: "33",
+        hint: "36",
+        number: "35",
+        variable: "34",
+        function: "32",
+        node: "90",
+    };
+
+    /// The theme with this name, as given in the settings
+    pub fn named(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::DARK),
+            "light" => Some(Theme::LIGHT),
+            _ => None,
+        }
+    }
+
+    /// The code for a style
+    fn code(&self, style: Style) -> &'static str {
+        match style {
+            Style::Success => self.success,
+            Style::Error => self.error,
+            Style::Warning => self.warning,
+            Style::Hint => self.hint,
+            Style::Number => self.number,
+            Style::Variable => self.variable,
+            Style::Function => self.function,
+            Style::Node => self.node,
+        }
+    }
+}
+
+/// Writes REPL output, in color or not
+#[derive(Debug, Clone, Copy)]
+pub struct Renderer {
+    /// The colors to use, or `None` for plain text
+    theme: Option<Theme>,
+}
+
+impl Renderer {
+    /// A renderer that colors with `theme`, or writes plain text if it's `None`
+    pub fn new(theme: Option<Theme>) -> Self {
+        Renderer { theme }
+    }
+
+    /// Text in the color of a style
+    pub fn paint(&self, style: Style, text: impl AsRef<str>) -> String {
+        match self.theme {
+            Some(theme) => format!("\x1b[{}m{}\x1b[0m", theme.code(style), text.as_ref()),
+            None => text.as_ref().to_string(),
+        }
+    }
+
+    /// A tree in the same layout as its `Debug` output, with its parts colored
+    pub fn ast(&self, expr: &Expr) -> String {
+        let mut out = String::new();
+        self.write_ast(expr, &mut out);
+        out
+    }
+
+    /// Write trees in square brackets, separated by commas
+    fn write_list(&self, exprs: &[Expr], out: &mut String) {
+        out.push('[');
+        for (index, expr) in exprs.iter().enumerate() {
+            if index > 0 {
+                out.push_str(", ");
+            }
+            self.write_ast(expr, out);
+        }
+        out.push(']');
+    }
+
+    /// Helper for [`Renderer::ast`]
+    fn write_ast(&self, expr: &Expr, out: &mut String) {
+        let node = |name: &str, out: &mut String| {
+            out.push_str(&self.paint(Style::Node, name));
+            out.push('(');
+        };
+        match expr {
+            Expr::Float(value) => {
+                node("Float", out);
+                out.push_str(&self.paint(Style::Number, format!("{:?}", value)));
+            }
+            Expr::Var(name) => {
+                node("Var", out);
+                out.push_str(&self.paint(Style::Variable, format!("{:?}", name)));
+            }
+            Expr::Call(name, args) => {
+                node("Call", out);
+                out.push_str(&self.paint(Style::Function, format!("{:?}", name)));
+                out.push_str(", ");
+                self.write_list(args, out);
+            }
+            Expr::Add(..)
+            | Expr::Sub(..)
+            | Expr::Mul(..)
+            | Expr::Div(..)
+            | Expr::And(..)
+            | Expr::Or(..)
+            | Expr::Compare(..) => {
+                // Left operands are followed in a loop, so that a long chain
+                // like `1 + 1 + … + 1` doesn't recurse
+                let mut rights = Vec::new();
+                let mut first = expr;
+                while let Some((left, right)) = operands(first) {
+                    match first {
+                        Expr::Compare(comparison, ..) => {
+                            node("Compare", out);
+                            out.push_str(&format!("{:?}, ", comparison));
+                        }
+                        _ => node(variant_name(first), out),
+                    }
+                    rights.push(right);
+                    first = left;
+                }
+                self.write_ast(first, out);
+                for right in rights.into_iter().rev() {
+                    out.push_str(", ");
+                    self.write_ast(right, out);
+                    out.push(')');
+                }
+                return;
+            }
+            Expr::Neg(inner) | Expr::Not(inner) => {
+                node(variant_name(expr), out);
+                self.write_ast(inner, out);
+            }
+            Expr::Named(name, value) => {
+                node("Named", out);
+                out.push_str(&self.paint(Style::Variable, format!("{:?}", name)));
+                out.push_str(", ");
+                self.write_ast(value, out);
+            }
+            Expr::Let(name, value, body) => {
+                node("Let", out);
+                out.push_str(&self.paint(Style::Variable, format!("{:?}", name)));
+                out.push_str(", ");
+                self.write_ast(value, out);
+                out.push_str(", ");
+                self.write_ast(body, out);
+            }
+            Expr::List(elements) => {
+                node("List", out);
+                self.write_list(elements, out);
+            }
+            Expr::Index(target, indices) => {
+                node("Index", out);
+                self.write_ast(target, out);
+                out.push_str(", ");
+                self.write_list(indices, out);
+            }
+            Expr::Piecewise(cases, default) => {
+                node("Piecewise", out);
+                out.push('[');
+                for (index, (condition, value)) in cases.iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push('(');
+                    self.write_ast(condition, out);
+                    out.push_str(", ");
+                    self.write_ast(value, out);
+                    out.push(')');
+                }
+                out.push_str("], ");
+                match default {
+                    Some(default) => {
+                        out.push_str("Some(");
+                        self.write_ast(default, out);
+                        out.push(')');
+                    }
+                    None => out.push_str("None"),
+                }
+            }
+            // Leaves without anything worth coloring inside
+            Expr::CellRef(_) | Expr::CellRange(_, _) | Expr::Error(_) => {
+                let debug = format!("{:?}", expr);
+                let (name, rest) = debug.split_once('(').expect("a tuple variant");
+                node(name, out);
+                out.push_str(&rest[..rest.len() - 1]);
+            }
+        }
+        out.push(')');
+    }
+}
+
+/// The operands of a binary operator node
+fn operands(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match expr {
+        Expr::Add(left, right)
+        | Expr::Sub(left, right)
+        | Expr::Mul(left, right)
+        | Expr::Div(left, right)
+        | Expr::Compare(_, left, right)
+        | Expr::And(left, right)
+        | Expr::Or(left, right) => Some((left, right)),
+        _ => None,
+    }
+}
+
+/// The name of an operator node's variant
+fn variant_name(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::Add(..) => "Add",
+        Expr::Sub(..) => "Sub",
+        Expr::Mul(..) => "Mul",
+        Expr::Div(..) => "Div",
+        Expr::And(..) => "And",
+        Expr::Or(..) => "Or",
+        Expr::Neg(..) => "Neg",
+        Expr::Not(..) => "Not",
+        _ => unreachable!("only called for operators"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::{ParserConfig, parse_expression_with};
+
+    /// Test that plain trees look exactly like their Debug output
+    #[test]
+    fn test_plain_ast() {
+        let plain = Renderer::new(None);
+        for input in [
+            "-(1.5 + x) * 2 / y - !z",
+            "max(a, 2) <= 3 && b || c != -1",
+            "now()",
+            "SUM(A1:B2) + C3",
+        ] {
+            let (_, ast) = parse_expression_with(input, &ParserConfig::spreadsheet()).unwrap();
+            assert_eq!(plain.ast(&ast), format!("{:?}", ast), "'{}'", input);
+        }
+        assert_eq!(plain.paint(Style::Error, "oops"), "oops");
+    }
+
+    /// Test that colored trees have the same text between the color codes
+    #[test]
+    fn test_colored_ast() {
+        let colored = Renderer::new(Theme::named("light"));
+        let (_, ast) = parse_expression_with("sqrt(x) + 1", &ParserConfig::default()).unwrap();
+        let rendered = colored.ast(&ast);
+        assert!(rendered.starts_with("\x1b[90mAdd\x1b[0m(\x1b[90mCall\x1b[0m(\x1b[32m\"sqrt\""));
+
+        let mut stripped = String::new();
+        let mut in_code = false;
+        for c in rendered.chars() {
+            match c {
+                '\x1b' => in_code = true,
+                'm' if in_code => in_code = false,
+                _ if in_code => {}
+                c => stripped.push(c),
+            }
+        }
+        assert_eq!(stripped, format!("{:?}", ast));
+        assert!(Theme::named("neon").is_none());
+    }
+}
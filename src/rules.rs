@@ -0,0 +1,294 @@
+//! Named validation rules, checked against the values in an environment
+//!
+//! The parser isn't only for arithmetic: a condition such as `qty > 0 &&
+//! qty <= stock` is an expression too. A [`RuleSet`] keeps named conditions
+//! like that, written one per line as `must_be_positive: x > 0`, and
+//! [`RuleSet::check`] gives a [`RuleReport`] saying which of them hold for
+//! a record's values.
+
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::{Environment, EvaluationError, Evaluator, Expr, parse_expression};
+
+/// Errors from adding a rule to a [`RuleSet`]
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum RuleError {
+    /// A rule's condition isn't a valid expression
+    #[error("Syntax error in rule '{name}' at character {position}")]
+    Syntax {
+        /// The rule's name
+        name: String,
+        /// Where in the condition (in bytes) the parser stopped
+        position: usize,
+    },
+
+    /// Another rule already has this name
+    #[error("Duplicate rule '{0}'")]
+    DuplicateName(String),
+
+    /// A rule's name isn't a valid identifier
+    #[error("Invalid rule name '{0}'")]
+    InvalidName(String),
+
+    /// A line of rules text isn't of the form `name: condition`
+    #[error("Line {0} is not of the form 'name: condition'")]
+    MissingName(usize),
+}
+
+/// A named condition
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rule {
+    /// The rule's name
+    pub name: String,
+    /// The condition as written
+    pub source: String,
+    /// The parsed condition
+    pub condition: Expr,
+}
+
+/// How checking one rule went
+#[derive(Debug)]
+pub enum RuleOutcome {
+    /// The condition is true
+    Passed,
+    /// The condition is false
+    Failed,
+    /// The condition couldn't be evaluated, say because a variable is missing
+    Error(EvaluationError),
+}
+
+/// The outcome of one rule in a [`RuleReport`]
+#[derive(Debug)]
+pub struct RuleResult {
+    /// The rule's name
+    pub name: String,
+    /// Whether it passed
+    pub outcome: RuleOutcome,
+}
+
+/// The outcome of every rule in a [`RuleSet`], in the order they were added
+#[derive(Debug)]
+pub struct RuleReport {
+    /// One result per rule
+    pub results: Vec<RuleResult>,
+}
+
+impl RuleReport {
+    /// Whether every rule passed
+    pub fn all_passed(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| matches!(result.outcome, RuleOutcome::Passed))
+    }
+
+    /// The rules that failed or couldn't be evaluated
+    pub fn failures(&self) -> impl Iterator<Item = &RuleResult> {
+        self.results
+            .iter()
+            .filter(|result| !matches!(result.outcome, RuleOutcome::Passed))
+    }
+}
+
+impl fmt::Display for RuleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                RuleOutcome::Passed => writeln!(f, "pass  {}", result.name)?,
+                RuleOutcome::Failed => writeln!(f, "FAIL  {}", result.name)?,
+                RuleOutcome::Error(error) => writeln!(f, "ERROR {}: {}", result.name, error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Named conditions to check a record's values against
+///
+/// A rule passes when its condition evaluates to anything but 0, so
+/// comparisons and logic, which give 1 or 0, read naturally. NaN fails, and
+/// a condition that can't be evaluated is reported as an error rather than
+/// a failure, so a missing field isn't mistaken for an invalid one.
+///
+/// # Example
+/// ```
+/// use ast::{Environment, RuleOutcome, RuleSet};
+///
+/// let rules = RuleSet::parse(
+///     "# Order lines
+///      must_be_positive: qty > 0
+///      in_stock: qty <= stock",
+/// )
+/// .unwrap();
+///
+/// let env: Environment = [("qty", 12.0), ("stock", 10.0)].into_iter().collect();
+/// let report = rules.check(&env);
+/// assert!(!report.all_passed());
+/// let failed: Vec<&str> = report.failures().map(|result| result.name.as_str()).collect();
+/// assert_eq!(failed, ["in_stock"]);
+/// assert!(matches!(report.results[0].outcome, RuleOutcome::Passed));
+/// ```
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Create an empty rule set
+    pub fn new() -> Self {
+        RuleSet::default()
+    }
+
+    /// Parse rules written one per line as `name: condition`
+    ///
+    /// Blank lines, and lines starting with `#`, are skipped. Errors in a
+    /// condition give the position within that condition.
+    pub fn parse(text: &str) -> Result<Self, RuleError> {
+        let mut rules = RuleSet::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, condition) = line
+                .split_once(':')
+                .ok_or(RuleError::MissingName(number + 1))?;
+            rules.add(name.trim(), condition.trim())?;
+        }
+        Ok(rules)
+    }
+
+    /// Add a rule, which is checked after the ones already added
+    pub fn add(&mut self, name: &str, condition: &str) -> Result<(), RuleError> {
+        let valid_name = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(RuleError::InvalidName(name.to_string()));
+        }
+        if self.rules.iter().any(|rule| rule.name == name) {
+            return Err(RuleError::DuplicateName(name.to_string()));
+        }
+        let rest = match parse_expression(condition) {
+            Ok((rest, ast)) if rest.trim().is_empty() => {
+                self.rules.push(Rule {
+                    name: name.to_string(),
+                    source: condition.to_string(),
+                    condition: ast,
+                });
+                return Ok(());
+            }
+            Ok((rest, _)) => rest.trim_start(),
+            Err(nom::Err::Error(error) | nom::Err::Failure(error)) => error.input.trim_start(),
+            Err(nom::Err::Incomplete(_)) => "",
+        };
+        Err(RuleError::Syntax {
+            name: name.to_string(),
+            position: condition.len() - rest.len(),
+        })
+    }
+
+    /// The rules, in the order they're checked
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// How many rules there are
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Check every rule against the variables in `env`, with the standard functions
+    pub fn check(&self, env: &Environment) -> RuleReport {
+        self.check_with(&Evaluator::new(env))
+    }
+
+    /// Check every rule with an evaluator, for other functions or settings
+    pub fn check_with(&self, evaluator: &Evaluator) -> RuleReport {
+        let results = self
+            .rules
+            .iter()
+            .map(|rule| RuleResult {
+                name: rule.name.clone(),
+                outcome: match evaluator.evaluate(&rule.condition) {
+                    Ok(value) if value != 0.0 && !value.is_nan() => RuleOutcome::Passed,
+                    Ok(_) => RuleOutcome::Failed,
+                    Err(error) => RuleOutcome::Error(error),
+                },
+            })
+            .collect();
+        RuleReport { results }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test passing, failing and erroring rules, and the report
+    #[test]
+    fn test_check() {
+        let mut rules = RuleSet::new();
+        rules.add("positive", "x > 0").unwrap();
+        rules.add("small", "x < 10 && x == floor(x)").unwrap();
+        rules.add("ratio", "y / x").unwrap();
+        rules.add("has_z", "z >= 0").unwrap();
+        assert_eq!(rules.len(), 4);
+
+        let env: Environment = [("x", 2.5), ("y", 0.0)].into_iter().collect();
+        let report = rules.check(&env);
+        assert!(matches!(report.results[0].outcome, RuleOutcome::Passed));
+        assert!(matches!(report.results[1].outcome, RuleOutcome::Failed));
+        assert!(matches!(report.results[2].outcome, RuleOutcome::Failed));
+        assert!(matches!(
+            &report.results[3].outcome,
+            RuleOutcome::Error(EvaluationError::UndefinedVariable(name)) if name == "z"
+        ));
+        assert_eq!(report.failures().count(), 3);
+        assert_eq!(
+            report.to_string(),
+            "pass  positive\nFAIL  small\nFAIL  ratio\nERROR has_z: Undefined variable 'z'\n"
+        );
+
+        let env: Environment = [("x", 2.0), ("y", 1.0), ("z", 0.0)].into_iter().collect();
+        assert!(rules.check(&env).all_passed());
+    }
+
+    /// Test parsing rules text and its errors
+    #[test]
+    fn test_parse() {
+        let rules = RuleSet::parse("\n# comment\n a: x > 1 \n\nb:y").unwrap();
+        let names: Vec<&str> = rules
+            .rules()
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(rules.rules()[0].source, "x > 1");
+
+        assert_eq!(
+            RuleSet::parse("a: x > 1\nb: x >"),
+            Err(RuleError::Syntax {
+                name: "b".to_string(),
+                position: 3
+            })
+        );
+        assert_eq!(
+            RuleSet::parse("a: x\na: y"),
+            Err(RuleError::DuplicateName("a".to_string()))
+        );
+        assert_eq!(
+            RuleSet::parse("a: x\nx > 1"),
+            Err(RuleError::MissingName(2))
+        );
+        assert_eq!(
+            RuleSet::parse("two words: x"),
+            Err(RuleError::InvalidName("two words".to_string()))
+        );
+    }
+}
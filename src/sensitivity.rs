@@ -0,0 +1,167 @@
+//! Sensitivity analysis: which inputs a formula's result depends on most
+//!
+//! [`sensitivity`] estimates the partial derivative of an expression with
+//! respect to one variable by evaluating it a small step either side of the
+//! variable's value. [`sensitivities`] does so for every variable, along
+//! with each one's elasticity (the percentage change in the result per
+//! percent change in the input), which compares inputs measured in
+//! different units.
+
+use crate::{Environment, EvaluationError, Expr, evaluate_with};
+
+/// How a formula's result responds to one of its inputs
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sensitivity {
+    /// The input's name
+    pub variable: String,
+    /// The input's value
+    pub value: f64,
+    /// How much the result changes per unit change of the input
+    pub partial: f64,
+    /// The relative change in the result per relative change of the input,
+    /// or NaN where the result is zero
+    pub elasticity: f64,
+}
+
+/// How much `expr` changes per unit change of `var`, around its value in `env`
+///
+/// The partial derivative is estimated with a central difference, evaluating
+/// `expr` at `var - delta` and `var + delta`. Where only one side can be
+/// evaluated, as at the edge of `sqrt`'s domain, the difference from the
+/// value at `var` is used instead.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, sensitivity, Environment};
+///
+/// let (_, ast) = parse_expression("x * x * y").unwrap();
+/// let env: Environment = [("x", 3.0), ("y", 2.0)].into_iter().collect();
+/// let partial = sensitivity(&ast, &env, "x", 1e-6).unwrap();
+/// assert!((partial - 12.0).abs() < 1e-6);
+/// ```
+pub fn sensitivity(
+    expr: &Expr,
+    env: &Environment,
+    var: &str,
+    delta: f64,
+) -> Result<f64, EvaluationError> {
+    if !(delta.is_finite() && delta > 0.0) {
+        return Err(EvaluationError::DomainError("sensitivity".to_string()));
+    }
+    let value = env
+        .get(var)
+        .ok_or_else(|| EvaluationError::UndefinedVariable(var.to_string()))?;
+    let mut env = env.clone();
+    let mut at = |x: f64| {
+        env.set(var, x);
+        evaluate_with(expr, &env)
+    };
+    match (at(value - delta), at(value + delta)) {
+        (Ok(below), Ok(above)) => Ok((above - below) / (2.0 * delta)),
+        (Ok(below), Err(_)) => Ok((at(value)? - below) / delta),
+        (Err(_), Ok(above)) => Ok((above - at(value)?) / delta),
+        (Err(error), Err(_)) => Err(error),
+    }
+}
+
+/// The [`sensitivity`] of `expr` to each of its variables, the most influential first
+///
+/// Inputs are ranked by the size of their elasticity, so a rate of 0.05 and
+/// a principal of 200000 can be compared even though a unit change means
+/// very different things for each.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, sensitivities, Environment};
+///
+/// let (_, ast) = parse_expression("principal * rate * rate + fee").unwrap();
+/// let env: Environment = [("principal", 1000.0), ("rate", 0.05), ("fee", 1.0)]
+///     .into_iter()
+///     .collect();
+/// let ranked = sensitivities(&ast, &env, 1e-6).unwrap();
+/// let names: Vec<&str> = ranked.iter().map(|s| s.variable.as_str()).collect();
+/// assert_eq!(names, ["rate", "principal", "fee"]);
+/// ```
+pub fn sensitivities(
+    expr: &Expr,
+    env: &Environment,
+    delta: f64,
+) -> Result<Vec<Sensitivity>, EvaluationError> {
+    let result = evaluate_with(expr, env)?;
+    let mut ranked = expr
+        .variables()
+        .into_iter()
+        .map(|variable| {
+            let partial = sensitivity(expr, env, variable, delta)?;
+            let value = env.get(variable).unwrap_or_default();
+            let elasticity = if result == 0.0 {
+                f64::NAN
+            } else {
+                partial * value / result
+            };
+            Ok(Sensitivity {
+                variable: variable.to_string(),
+                value,
+                partial,
+                elasticity,
+            })
+        })
+        .collect::<Result<Vec<_>, EvaluationError>>()?;
+    ranked.sort_by(|a, b| {
+        let size = |s: &Sensitivity| match s.elasticity {
+            elasticity if elasticity.is_nan() => s.partial.abs(),
+            elasticity => elasticity.abs(),
+        };
+        size(b).total_cmp(&size(a))
+    });
+    Ok(ranked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// An expression that must parse
+    fn expr(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Test partials, including at the edge of a function's domain and errors
+    #[test]
+    fn test_sensitivity() {
+        let env: Environment = [("x", 2.0), ("y", 0.0)].into_iter().collect();
+        let partial = sensitivity(&expr("sin(x) + y"), &env, "x", 1e-5).unwrap();
+        assert!((partial - 2f64.cos()).abs() < 1e-8);
+        // sqrt can't be evaluated below 0, so only the step above is used
+        let partial = sensitivity(&expr("sqrt(y) + 3 * y"), &env, "y", 1e-8).unwrap();
+        assert!(partial > 1000.0);
+        assert!(matches!(
+            sensitivity(&expr("x + z"), &env, "z", 1e-6),
+            Err(EvaluationError::UndefinedVariable(name)) if name == "z"
+        ));
+        assert!(matches!(
+            sensitivity(&expr("x"), &env, "x", 0.0),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(matches!(
+            sensitivity(&expr("1 / y"), &env, "x", 1e-6),
+            Err(EvaluationError::DivisionByZero)
+        ));
+    }
+
+    /// Test ranking every input, and elasticity where the result is zero
+    #[test]
+    fn test_sensitivities() {
+        let env: Environment = [("a", 10.0), ("b", 2.0)].into_iter().collect();
+        let ranked = sensitivities(&expr("a * b * b"), &env, 1e-6).unwrap();
+        assert_eq!(ranked[0].variable, "b");
+        assert!((ranked[0].partial - 40.0).abs() < 1e-6);
+        assert!((ranked[0].elasticity - 2.0).abs() < 1e-6);
+        assert!((ranked[1].elasticity - 1.0).abs() < 1e-6);
+
+        let ranked = sensitivities(&expr("a - 5 * b"), &env, 1e-6).unwrap();
+        assert!(ranked.iter().all(|s| s.elasticity.is_nan()));
+        assert_eq!(ranked[0].variable, "b");
+    }
+}
@@ -0,0 +1,369 @@
+//! An HTTP server that evaluates formulas, for running the calculator as a sidecar
+//!
+//! `ast serve --port 8080` answers two JSON requests:
+//!
+//! - `POST /eval` with `{"expr": "a * b", "vars": {"a": 2, "b": 3}}` gives
+//!   `{"result": 6}`. Results that JSON can't hold are strings: `"NaN"`,
+//!   `"inf"` and `"-inf"`.
+//! - `POST /parse` with `{"expr": "a * b"}` gives `{"ast": ...}`, the tree as
+//!   nested `{"kind", "label", "children"}` nodes.
+//!
+//! Failures give `{"error": {"kind", "message", ...}}`: status 400 with the
+//! kind `request` for a malformed request, or a kind from parsing such as
+//! `syntax` (with a `position`, also for brackets nested more than 256 deep)
+//! or `literal_too_long`, and status 422 with the kind
+//! `evaluation` and the error's `code` when the formula parsed but couldn't be
+//! evaluated. Each connection gets a thread and answers one request.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use ast::{Engine, Expr, ParseEvalError, ParserConfig};
+use serde_json::{Value as Json, json};
+
+/// The largest request body accepted, in bytes
+const MAX_BODY: usize = 64 * 1024;
+
+/// The stack each connection's thread gets, enough for the parser to nest
+/// as deeply as [`engine`] lets it
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// How long a client may take to send its request
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The command line help for `ast serve`
+pub const USAGE: &str = "Usage: ast serve [--host <address>] [--port <port>]
+
+Answer POST /eval and POST /parse with JSON, on 127.0.0.1:8080 by default.";
+
+/// Parse the arguments after `serve` and answer requests until the process is stopped
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (mut host, mut port) = ("127.0.0.1".to_string(), 8080u16);
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{}: expected a value", name))
+        };
+        match arg.as_str() {
+            "--host" => host = value("--host")?,
+            "--port" => {
+                let text = value("--port")?;
+                port = text
+                    .parse()
+                    .map_err(|_| format!("--port {}: expected a port number", text))?;
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("{}: unknown option", arg)),
+        }
+    }
+    let listener = TcpListener::bind((host.as_str(), port))
+        .map_err(|error| format!("listening on {}:{}: {}", host, port, error))?;
+    eprintln!(
+        "🧮 serving on http://{}",
+        listener.local_addr().map_err(|e| e.to_string())?
+    );
+    serve(listener, engine());
+    Ok(())
+}
+
+/// The engine formulas are evaluated with: the standard functions, with limits on size
+pub fn engine() -> Arc<Engine> {
+    Arc::new(
+        Engine::builder()
+            .parser_config(ParserConfig {
+                max_depth: 256,
+                ..ParserConfig::default()
+            })
+            .max_literal_length(64)
+            .max_name_length(64)
+            .build(),
+    )
+}
+
+/// Answer each connection to `listener` on its own thread
+pub fn serve(listener: TcpListener, engine: Arc<Engine>) {
+    for stream in listener.incoming().flatten() {
+        let engine = Arc::clone(&engine);
+        // A client that hangs up early gets no answer
+        let _ = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || answer(stream, &engine));
+    }
+}
+
+/// Read one request from `stream` and write its response
+fn answer(stream: TcpStream, engine: &Engine) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let (status, body) = match read_request(&mut reader)? {
+        Ok((method, path, body)) => handle(engine, &method, &path, &body),
+        Err(response) => response,
+    };
+    let body = body.to_string();
+    write!(
+        &stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )?;
+    (&stream).flush()
+}
+
+/// The method, path and body of a request, or the response to a malformed one
+type Request = Result<(String, String, Vec<u8>), (u16, Json)>;
+
+/// Read a request line, headers and body
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(path)) = (words.next(), words.next()) else {
+        return Ok(Err(error(400, "request", "expected a request line")));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            match value.trim().parse() {
+                Ok(value) => length = value,
+                Err(_) => return Ok(Err(error(400, "request", "invalid Content-Length"))),
+            }
+        }
+    }
+    if length > MAX_BODY {
+        let message = format!("bodies are limited to {} bytes", MAX_BODY);
+        return Ok(Err(error(413, "request", &message)));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok((method, path, body)))
+}
+
+/// The status and JSON response to a request
+fn handle(engine: &Engine, method: &str, path: &str, body: &[u8]) -> (u16, Json) {
+    if path != "/eval" && path != "/parse" {
+        return error(404, "request", &format!("no such endpoint: {}", path));
+    }
+    if method != "POST" {
+        return error(405, "request", &format!("{} takes POST requests", path));
+    }
+    let request: Json = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(400, "request", &format!("invalid JSON: {}", e)),
+    };
+    let Some(expr) = request.get("expr").and_then(Json::as_str) else {
+        return error(400, "request", "expected \"expr\" to be a string");
+    };
+    let formula = match engine.compile(expr) {
+        Ok(formula) => formula,
+        Err(e) => return failure(&e),
+    };
+    if path == "/parse" {
+        return (200, json!({ "ast": tree(formula.expr()) }));
+    }
+
+    let mut vars = engine.environment();
+    if let Some(given) = request.get("vars") {
+        let Some(given) = given.as_object() else {
+            return error(400, "request", "expected \"vars\" to be an object");
+        };
+        for (name, value) in given {
+            let Some(value) = value.as_f64() else {
+                return error(400, "request", &format!("vars.{}: expected a number", name));
+            };
+            vars.set(name.as_str(), value);
+        }
+    }
+    match engine.run(&formula, &vars) {
+        Ok(result) => (200, json!({ "result": number(result) })),
+        Err(e) => failure(&e),
+    }
+}
+
+/// A number as JSON, which has no NaN or infinities
+fn number(value: f64) -> Json {
+    match value {
+        _ if value.is_nan() => json!("NaN"),
+        f64::INFINITY => json!("inf"),
+        f64::NEG_INFINITY => json!("-inf"),
+        _ => json!(value),
+    }
+}
+
+/// A tree as nested JSON objects, built from its graph so deep trees don't recurse twice
+fn tree(expr: &Expr) -> Json {
+    let graph = expr.to_graph();
+    let mut built: Vec<Json> = Vec::with_capacity(graph.nodes.len());
+    // Children come before their parents
+    for node in &graph.nodes {
+        let children: Vec<Json> = node.children.iter().map(|&id| built[id].clone()).collect();
+        built.push(json!({ "kind": node.kind, "label": node.label, "children": children }));
+    }
+    built.swap_remove(graph.root)
+}
+
+/// The response to a formula that couldn't be parsed or evaluated
+fn failure(e: &ParseEvalError) -> (u16, Json) {
+    let message = e.to_string();
+    let (status, detail) = match e {
+        ParseEvalError::Syntax { position } => (
+            400,
+            json!({ "kind": "syntax", "message": message, "position": position }),
+        ),
+        ParseEvalError::TooDeep { .. } => (400, json!({ "kind": "too_deep", "message": message })),
+        ParseEvalError::LiteralTooLong { position, .. } => (
+            400,
+            json!({ "kind": "literal_too_long", "message": message, "position": position }),
+        ),
+        ParseEvalError::NameTooLong { position, .. } => (
+            400,
+            json!({ "kind": "name_too_long", "message": message, "position": position }),
+        ),
+        ParseEvalError::Evaluation(error) => (
+            422,
+            json!({ "kind": "evaluation", "message": message, "code": error.code() }),
+        ),
+    };
+    (status, json!({ "error": detail }))
+}
+
+/// An error response of some kind
+fn error(status: u16, kind: &str, message: &str) -> (u16, Json) {
+    (
+        status,
+        json!({ "error": { "kind": kind, "message": message } }),
+    )
+}
+
+/// The reason phrase for a status code
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        422 => "Unprocessable Content",
+        _ => "Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    /// The response to a POST of `body` to `path`, on a thread like a connection's
+    fn post(path: &str, body: &str) -> (u16, Json) {
+        let (path, body) = (path.to_string(), body.to_string());
+        let thread = thread::Builder::new().stack_size(STACK_SIZE);
+        let handler = move || handle(&engine(), "POST", &path, body.as_bytes());
+        thread.spawn(handler).unwrap().join().unwrap()
+    }
+
+    /// Test evaluating, with variables and with each kind of error
+    #[test]
+    fn test_eval() {
+        let ok = |result| (200, json!({ "result": result }));
+        assert_eq!(
+            post(
+                "/eval",
+                r#"{"expr": "a * b + c", "vars": {"a": 2, "b": 3, "c": 4}}"#
+            ),
+            ok(json!(10.0))
+        );
+        assert_eq!(post("/eval", r#"{"expr": "sqrt(16)"}"#), ok(json!(4.0)));
+        assert_eq!(post("/eval", r#"{"expr": "inf - inf"}"#), ok(json!("NaN")));
+
+        let (status, body) = post("/eval", r#"{"expr": "1 / x", "vars": {"x": 0}}"#);
+        assert_eq!(status, 422);
+        assert_eq!(body["error"]["kind"], "evaluation");
+        assert_eq!(body["error"]["code"], "E0001");
+
+        let (status, body) = post("/eval", r#"{"expr": "2 +"}"#);
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["kind"], "syntax");
+        assert!(body["error"]["position"].is_u64());
+
+        // The parser stops at the level that goes too deep
+        let deep = format!(r#"{{"expr": "{}1{}"}}"#, "(".repeat(300), ")".repeat(300));
+        let negated = format!(r#"{{"expr": "{}1"}}"#, "- ".repeat(300));
+        for body in [deep, negated] {
+            let (status, response) = post("/eval", &body);
+            assert_eq!(status, 400);
+            assert_eq!(response["error"]["kind"], "syntax");
+        }
+        let nested = format!(r#"{{"expr": "{}1{}"}}"#, "(".repeat(200), ")".repeat(200));
+        assert_eq!(post("/eval", &nested), ok(json!(1.0)));
+        let chain = format!(r#"{{"expr": "{}"}}"#, vec!["1"; 10_000].join("+"));
+        assert_eq!(post("/eval", &chain), ok(json!(10_000.0)));
+        for body in [
+            "{",
+            r#"{"expr": 1}"#,
+            r#"{"expr": "x", "vars": {"x": "1"}}"#,
+        ] {
+            let (status, response) = post("/eval", body);
+            assert_eq!(
+                (status, &response["error"]["kind"]),
+                (400, &json!("request"))
+            );
+        }
+        assert_eq!(post("/nothing", "{}").0, 404);
+        assert_eq!(handle(&engine(), "GET", "/eval", b"").0, 405);
+    }
+
+    /// Test that parsing gives the tree as nested nodes
+    #[test]
+    fn test_parse() {
+        let (status, body) = post("/parse", r#"{"expr": "x * x + 1"}"#);
+        assert_eq!(status, 200);
+        let x = json!({ "kind": "Var", "label": "x", "children": [] });
+        let one = json!({ "kind": "Float", "label": "1", "children": [] });
+        let product = json!({ "kind": "Mul", "label": "*", "children": [x, x] });
+        assert_eq!(
+            body,
+            json!({ "ast": { "kind": "Add", "label": "+", "children": [product, one] } })
+        );
+    }
+
+    /// Test a request over a real connection
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, engine()));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let body = r#"{"expr": "2 * x", "vars": {"x": 21}}"#;
+        write!(
+            stream,
+            "POST /eval HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(
+            response.ends_with("\r\n\r\n{\"result\":42.0}"),
+            "{}",
+            response
+        );
+    }
+}
@@ -0,0 +1,1161 @@
+//! Calculator sessions
+//!
+//! A [`Session`] evaluates one input after another, the way a calculator
+//! does, remembering the variables and settings in between. Every result is
+//! kept in a history: expressions can use the last one as `ans`, and older
+//! ones as `ans(2)`, `ans(3)` and so on, while programs can inspect it
+//! through [`Session::history`].
+//!
+//! Inputs of the form `name = expression` set a variable, and ones like
+//! `area(w, h) = w * h` define a function. A function keeps the values that
+//! the variables it uses had when it was defined, so `scale(x) = k * x` goes
+//! on using the same `k` after `k` is set to something else. The variables and functions as
+//! they were before are saved first, so that [`Session::rollback`] (`:undo`
+//! in the REPL) can bring back a value that was overwritten by accident.
+//!
+//! [`Session::save`] and [`Session::load`] keep the variables and functions
+//! in a JSON file, so that work survives restarting the calculator.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs, io,
+    ops::RangeInclusive,
+    path::Path,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    Capabilities, Dialect, Environment, EvalConfig, EvaluationError, Evaluator, Expr, Function,
+    FunctionRegistry, Parser, ParserConfig, Profile, Value, parse_variable, table::steps,
+};
+
+/// The version of the JSON file layout written by [`Session::save`]
+const FORMAT_VERSION: u32 = 1;
+
+/// Errors from evaluating an input in a [`Session`]
+#[derive(Error, Debug)]
+pub enum SessionError {
+    /// The input isn't a valid expression
+    #[error("Syntax error at character {position}")]
+    Syntax {
+        /// Where in the input (in bytes) the parser stopped
+        position: usize,
+    },
+
+    /// The expression parsed, but evaluating it failed
+    #[error(transparent)]
+    Evaluation(#[from] EvaluationError),
+
+    #[error("Accessing the session file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid session file: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Unsupported session file format version {0}")]
+    UnsupportedFormat(u32),
+
+    /// A session file has a variable or function that can't be read back
+    #[error("Invalid session file: {0}")]
+    InvalidFile(String),
+
+    /// A function would never return, since every call leads to another call of it
+    #[error("Function '{name}' would never return: every call leads to another ({})", .chain.join(" → "))]
+    Divergent {
+        /// The function being defined
+        name: String,
+        /// The calls that lead back to it, starting and ending with its name
+        chain: Vec<String>,
+    },
+}
+
+/// What the left side of an assignment sets
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Assignee<'a> {
+    /// A variable, as in `x = 2`
+    Variable(&'a str),
+
+    /// A function, as in `f(x, y) = x * y`
+    Function {
+        /// The function's name
+        name: &'a str,
+        /// The names of its parameters
+        params: Vec<&'a str>,
+    },
+}
+
+/// A function defined in a session, such as `area(w, h) = w * h`
+#[derive(Debug, PartialEq, Clone)]
+struct Definition {
+    /// The function's name
+    name: String,
+    /// The names of its parameters
+    params: Vec<String>,
+    /// The body as it was written, for saving
+    source: String,
+    /// The parsed body
+    body: Expr,
+    /// The variables the body uses, with their values when the function was defined
+    captured: Environment,
+}
+
+/// The layout of a session file on disk
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    format: u32,
+    /// Values are written as text, so that infinities and NaN survive
+    variables: BTreeMap<String, String>,
+    /// In the order they were defined
+    functions: Vec<SavedFunction>,
+}
+
+/// A function in a session file
+#[derive(Serialize, Deserialize)]
+struct SavedFunction {
+    name: String,
+    params: Vec<String>,
+    body: String,
+    /// Written as text, like variables; missing from files saved before functions captured values
+    #[serde(default)]
+    captured: BTreeMap<String, String>,
+}
+
+/// A sequence of evaluations that share variables, settings and a history of results
+///
+/// # Example
+/// ```
+/// use ast::{Session, Value};
+///
+/// let mut session = Session::new();
+/// session.evaluate("6 * 7").unwrap();
+/// session.evaluate("ans / 2").unwrap();
+/// assert_eq!(session.evaluate("ans(2) - ans").unwrap(), Value::Number(21.0));
+/// assert_eq!(session.history(), [Value::Number(42.0), Value::Number(21.0), Value::Number(21.0)]);
+///
+/// session.evaluate("rate = 0.25").unwrap();
+/// session.evaluate("rate = 0").unwrap();
+/// assert!(session.rollback());
+/// assert_eq!(session.environment().get("rate"), Some(0.25));
+///
+/// session.evaluate("interest(amount) = amount * rate").unwrap();
+/// assert_eq!(session.evaluate("interest(200)").unwrap(), Value::Number(50.0));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// The variables that expressions can use
+    env: Environment,
+    /// The functions that expressions can call, besides `ans`
+    functions: FunctionRegistry,
+    /// How expressions are evaluated and results formatted
+    config: EvalConfig,
+    /// How inputs are parsed, reusing allocations from earlier inputs
+    parser: Parser,
+    /// Every result so far, oldest first
+    history: Vec<Value>,
+    /// The functions defined in the session, in the order they were defined
+    definitions: Vec<Definition>,
+    /// Saved variables and definitions for [`Session::rollback`], most recent last
+    snapshots: Vec<(Environment, Vec<Definition>)>,
+}
+
+impl Session {
+    /// Start a session with no variables, the standard functions and the default configuration
+    pub fn new() -> Self {
+        Session {
+            functions: FunctionRegistry::standard(),
+            ..Session::default()
+        }
+    }
+
+    /// Start with the variables in `env`
+    pub fn with_environment(mut self, env: Environment) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Call functions from `functions` instead of the standard ones
+    pub fn with_functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Evaluate according to `config`
+    pub fn with_config(mut self, config: EvalConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Parse inputs according to `parser`
+    pub fn with_parser_config(mut self, parser: ParserConfig) -> Self {
+        self.parser = Parser::new(parser);
+        self
+    }
+
+    /// The variables that expressions can use
+    pub fn environment(&self) -> &Environment {
+        &self.env
+    }
+
+    /// The variables, for changing them between evaluations
+    pub fn environment_mut(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    /// The evaluation configuration
+    pub fn config(&self) -> &EvalConfig {
+        &self.config
+    }
+
+    /// The evaluation configuration, for changing it between evaluations
+    pub fn config_mut(&mut self) -> &mut EvalConfig {
+        &mut self.config
+    }
+
+    /// Every result so far, oldest first
+    pub fn history(&self) -> &[Value] {
+        &self.history
+    }
+
+    /// The `n`th most recent result, counting from 1, as `ans(n)` gives it
+    pub fn ans(&self, n: usize) -> Option<&Value> {
+        n.checked_sub(1)
+            .and_then(|back| self.history.iter().rev().nth(back))
+    }
+
+    /// Forget all results
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Save the variables and functions as they are now, for [`Session::rollback`]
+    ///
+    /// Assignments, definitions and [`Session::load`] take a snapshot by
+    /// themselves; changes through [`Session::environment_mut`] don't.
+    pub fn snapshot(&mut self) {
+        self.snapshots
+            .push((self.env.clone(), self.definitions.clone()));
+    }
+
+    /// Bring back the variables and functions saved by the most recent snapshot, and forget it
+    ///
+    /// Returns `false`, changing nothing, if there are no snapshots. The
+    /// history of results is kept as it is.
+    pub fn rollback(&mut self) -> bool {
+        match self.snapshots.pop() {
+            Some((env, definitions)) => {
+                self.env = env;
+                self.definitions = definitions;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Parse and evaluate an input, adding the result to the history
+    ///
+    /// The input is either one expression, an assignment of one to a
+    /// variable (`name = expression`), or a function definition
+    /// (`name(params) = expression`). Definitions give [`Value::Null`], and
+    /// aren't added to the history.
+    pub fn evaluate(&mut self, input: &str) -> Result<Value, SessionError> {
+        let (assignee, expression) = match self.split_assignment(input) {
+            Some((assignee, expression)) => (Some(assignee), expression),
+            None => (None, input),
+        };
+        let ast = self
+            .parse(expression)
+            .map_err(|rest| SessionError::Syntax {
+                position: input.len() - rest.len(),
+            })?;
+        let result = match assignee {
+            Some(Assignee::Variable(name)) => self.assign(name, &ast).map_err(SessionError::from),
+            Some(Assignee::Function { name, params }) => {
+                self.define(name, &params, expression).map(|()| Value::Null)
+            }
+            None => self.evaluate_expr(&ast).map_err(SessionError::from),
+        };
+        self.parser.recycle(ast);
+        result
+    }
+
+    /// Parse a whole expression, or give the input from where parsing stopped
+    fn parse<'a>(&mut self, input: &'a str) -> Result<Expr, &'a str> {
+        match self.parser.parse(input) {
+            Ok((remaining, ast)) if remaining.trim().is_empty() => Ok(ast),
+            Ok((remaining, _)) => Err(remaining.trim_start()),
+            Err(nom::Err::Error(error) | nom::Err::Failure(error)) => Err(error.input.trim_start()),
+            Err(nom::Err::Incomplete(_)) => Err(""),
+        }
+    }
+
+    /// Split an assignment such as `x = 2 * y` or `f(x) = x * x` into what it
+    /// sets and the expression
+    ///
+    /// Returns `None` if the input isn't an assignment. In the Excel dialect,
+    /// `=` compares, so there are no assignments.
+    pub fn split_assignment<'a>(&self, input: &'a str) -> Option<(Assignee<'a>, &'a str)> {
+        if self.parser.config().dialect == Dialect::Excel {
+            return None;
+        }
+        let input = input.trim_start();
+        let (mut rest, _) = parse_variable(input).ok()?;
+        let name = &input[..input.len() - rest.len()];
+
+        let mut params = None;
+        if let Some(list) = rest.strip_prefix('(') {
+            let (list, after) = list.split_once(')')?;
+            let names = match list.trim() {
+                "" => Vec::new(),
+                _ => list
+                    .split(',')
+                    .map(|param| {
+                        let param = param.trim();
+                        matches!(parse_variable(param), Ok(("", _))).then_some(param)
+                    })
+                    .collect::<Option<_>>()?,
+            };
+            params = Some(names);
+            rest = after;
+        }
+
+        let expression = rest.trim_start().strip_prefix('=')?;
+        if expression.starts_with('=') {
+            return None; // A comparison, `x == 2`
+        }
+        let assignee = match params {
+            Some(params) => Assignee::Function { name, params },
+            None => Assignee::Variable(name),
+        };
+        Some((assignee, expression))
+    }
+
+    /// Define a function, replacing any function of the same name, after taking a [`Session::snapshot`]
+    ///
+    /// The body can use the parameters, the session's variables and any
+    /// function, including the session's others and itself. Recursion needs
+    /// `if(condition, then, otherwise)` to stop, and may go
+    /// [`EvalConfig::max_call_depth`] calls deep. A name in the body is looked up:
+    ///
+    /// 1. among the parameters, which hide variables of the same name;
+    /// 2. among the variables that existed when the function was defined,
+    ///    whose values then are captured and used from then on;
+    /// 3. among the session's variables when the function is called, for
+    ///    names that weren't variables when the function was defined.
+    ///
+    /// A function that calls itself whatever its arguments, directly or
+    /// through other functions, would never return, and is a
+    /// [`SessionError::Divergent`]. Calls in a branch of `if` or
+    /// `piecewise`, or on the right of `&&` and `||`, may be skipped, so
+    /// they don't count.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{Session, Value};
+    ///
+    /// let mut session = Session::new();
+    /// session.evaluate("k = 2").unwrap();
+    /// session.evaluate("scale(x) = k * x + offset").unwrap();
+    /// session.evaluate("k = 10").unwrap();
+    /// session.evaluate("offset = 1").unwrap();
+    /// // k was captured as 2, offset didn't exist yet so it's looked up on each call
+    /// assert_eq!(session.evaluate("scale(3)").unwrap(), Value::Number(7.0));
+    /// assert_eq!(session.captured("scale").unwrap().get("k"), Some(2.0));
+    /// ```
+    pub fn define(&mut self, name: &str, params: &[&str], body: &str) -> Result<(), SessionError> {
+        let ast = self.parse(body).map_err(|rest| SessionError::Syntax {
+            position: body.len() - rest.len(),
+        })?;
+        if let Some(chain) = self.divergence(name, &ast) {
+            return Err(SessionError::Divergent {
+                name: name.to_string(),
+                chain,
+            });
+        }
+        let mut captured = Environment::new();
+        for variable in ast.variables() {
+            if let Some(value) = self.env.get(variable)
+                && !params.contains(&variable)
+            {
+                captured.set(variable, value);
+            }
+        }
+        self.snapshot();
+        self.definitions
+            .retain(|definition| definition.name != name);
+        self.definitions.push(Definition {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            source: body.trim().to_string(),
+            body: ast,
+            captured,
+        });
+        Ok(())
+    }
+
+    /// The shortest chain of calls that every call of `name`, defined as `body`, makes back to itself
+    fn divergence(&self, name: &str, body: &Expr) -> Option<Vec<String>> {
+        let mut calls = BTreeMap::new();
+        for definition in &self.definitions {
+            let mut called = BTreeSet::new();
+            self.always_called(&definition.body, &mut called);
+            calls.insert(definition.name.as_str(), called);
+        }
+        let mut called = BTreeSet::new();
+        self.always_called(body, &mut called);
+        calls.insert(name, called);
+
+        // Breadth first, so that the chain is as short as it can be
+        let mut callers = BTreeMap::new();
+        let mut pending = VecDeque::from([name]);
+        while let Some(caller) = pending.pop_front() {
+            for &callee in &calls[caller] {
+                if callee == name {
+                    let mut chain = vec![name.to_string()];
+                    let mut at = caller;
+                    while at != name {
+                        chain.push(at.to_string());
+                        at = callers[at];
+                    }
+                    chain.push(name.to_string());
+                    chain.reverse();
+                    return Some(chain);
+                }
+                if calls.contains_key(callee) && !callers.contains_key(callee) {
+                    callers.insert(callee, caller);
+                    pending.push_back(callee);
+                }
+            }
+        }
+        None
+    }
+
+    /// Add the names of the functions that every evaluation of `expr` calls to `called`
+    fn always_called<'e>(&self, expr: &'e Expr, called: &mut BTreeSet<&'e str>) {
+        let mut pending = vec![expr];
+        while let Some(expr) = pending.pop() {
+            match expr {
+                Expr::Call(name, args) => {
+                    called.insert(name);
+                    let defined = self.definitions.iter().any(|d| d.name == *name);
+                    let lazy = !defined && self.functions.get(name).is_some_and(Function::is_lazy);
+                    let evaluated = if lazy {
+                        &args[..args.len().min(1)]
+                    } else {
+                        args
+                    };
+                    pending.extend(evaluated);
+                }
+                Expr::And(left, _) | Expr::Or(left, _) if !self.config.strict_evaluation => {
+                    pending.push(left);
+                }
+                Expr::Piecewise(cases, default) => match cases.first() {
+                    Some((condition, _)) => pending.push(condition),
+                    None => pending.extend(default.as_deref()),
+                },
+                _ => pending.extend(expr.children()),
+            }
+        }
+    }
+
+    /// The values a defined function captured when it was defined, see [`Session::define`]
+    pub fn captured(&self, name: &str) -> Option<&Environment> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name == name)
+            .map(|definition| &definition.captured)
+    }
+
+    /// The names of the functions defined in the session, in the order they were defined
+    pub fn defined_functions(&self) -> impl Iterator<Item = &str> {
+        self.definitions
+            .iter()
+            .map(|definition| definition.name.as_str())
+    }
+
+    /// Evaluate an expression and set a variable to the result, after taking a [`Session::snapshot`]
+    ///
+    /// The result is added to the history. Variables can only hold numbers,
+    /// so other values are an [`EvaluationError::TypeMismatch`].
+    pub fn assign(&mut self, name: &str, expr: &Expr) -> Result<Value, EvaluationError> {
+        let result = self.evaluate_expr(expr)?;
+        let number = result.clone().into_number()?;
+        self.snapshot();
+        self.env.set(name, number);
+        Ok(result)
+    }
+
+    /// Evaluate an expression that has already been parsed, adding the result to the history
+    ///
+    /// `ans` is the most recent result, unless there's a variable of that
+    /// name, and `ans(n)` is the `n`th most recent. Asking for a result that
+    /// doesn't exist is an [`EvaluationError::DomainError`].
+    pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, EvaluationError> {
+        let result = self.with_evaluator(expr, |evaluator, expr| evaluator.evaluate_value(expr))?;
+        self.history.push(result.clone());
+        Ok(result)
+    }
+
+    /// Evaluate an expression many times, timing each node, like [`profile`](crate::profile)
+    /// but with the session's variables and functions
+    ///
+    /// The results aren't added to the history.
+    pub fn profile(&self, expr: &Expr, iterations: usize) -> Result<Profile, EvaluationError> {
+        self.with_evaluator(expr, |evaluator, expr| evaluator.profile(expr, iterations))
+    }
+
+    /// Run `f` with an evaluator that has the session's variables and functions,
+    /// on the expression with `ans` turned into calls
+    fn with_evaluator<T>(&self, expr: &Expr, f: impl FnOnce(Evaluator, &Expr) -> T) -> T {
+        let mut expr = expr.clone();
+        if !self.env.contains("ans") {
+            recall(&mut expr);
+        }
+
+        // Session functions can call each other and themselves, so each one
+        // reaches the finished registry through a weak reference, which
+        // doesn't keep it alive once the evaluation is over
+        let registry = Arc::new(OnceLock::new());
+        let depth = Arc::new(AtomicUsize::new(0));
+        let mut functions = self.functions.clone();
+        let pure = self.pure_definitions();
+        // Each call's arguments are a scope over what the function captured,
+        // which is over the session's variables, so nothing is copied per call
+        let globals = Arc::new(self.env.clone());
+        for definition in &self.definitions {
+            let Definition {
+                params,
+                body,
+                captured,
+                ..
+            } = definition.clone();
+            let mut scope = globals.child();
+            for (name, value) in captured.iter() {
+                scope.set(name, value);
+            }
+            let (scope, config) = (Arc::new(scope), self.config);
+            let (registry, depth) = (Arc::downgrade(&registry), Arc::clone(&depth));
+            // Results by the bits of the arguments, for this evaluation only
+            let memo = (config.memoize_calls && pure.contains(definition.name.as_str()))
+                .then(|| Arc::new(Mutex::new(HashMap::<Vec<u64>, Value>::new())));
+            functions.register_value(&definition.name, params.len(), move |args| {
+                let mut env = scope.child();
+                let mut key = Vec::with_capacity(args.len());
+                for (param, arg) in params.iter().zip(args) {
+                    let arg = arg.clone().into_number()?;
+                    key.push(arg.to_bits());
+                    env.set(param, arg);
+                }
+                if let Some(memo) = &memo
+                    && let Some(result) = memo.lock().unwrap().get(&key)
+                {
+                    return Ok(result.clone());
+                }
+                if depth.fetch_add(1, Ordering::Relaxed) >= config.max_call_depth {
+                    depth.fetch_sub(1, Ordering::Relaxed);
+                    return Err(EvaluationError::RecursionLimit(config.max_call_depth));
+                }
+                let registry = registry.upgrade();
+                let functions = registry
+                    .as_deref()
+                    .and_then(OnceLock::get)
+                    .expect("the registry outlives the evaluation");
+                let result = Evaluator::new(&env)
+                    .with_functions(functions)
+                    .with_config(config)
+                    .evaluate_value(&body);
+                depth.fetch_sub(1, Ordering::Relaxed);
+                if let (Some(memo), Ok(result)) = (&memo, &result) {
+                    memo.lock().unwrap().insert(key, result.clone());
+                }
+                result
+            });
+            let names: Vec<&str> = definition.params.iter().map(String::as_str).collect();
+            functions.set_parameters(&definition.name, &names);
+        }
+
+        let history = self.history.clone();
+        functions.register_value("ans", 0..=1, move |args| {
+            let back = match args.first() {
+                None => 1.0,
+                Some(n) => n.clone().into_number()?,
+            };
+            let found = (back >= 1.0 && back.fract() == 0.0)
+                .then(|| history.iter().rev().nth(back as usize - 1))
+                .flatten();
+            found
+                .cloned()
+                .ok_or_else(|| EvaluationError::DomainError("ans".to_string()))
+        });
+
+        let functions = registry.get_or_init(|| functions);
+        let evaluator = Evaluator::new(&self.env)
+            .with_functions(functions)
+            .with_config(self.config);
+        f(evaluator, &expr)
+    }
+
+    /// Evaluate an expression for each value of a variable, like [`tabulate`](crate::tabulate),
+    /// but with the session's variables and functions
+    ///
+    /// The variable hides any session variable of the same name, and the
+    /// results aren't added to the history.
+    pub fn tabulate(
+        &self,
+        expr: &Expr,
+        var: &str,
+        range: RangeInclusive<f64>,
+        step: f64,
+    ) -> Vec<(f64, Result<f64, EvaluationError>)> {
+        let mut session = self.clone();
+        steps(range, step)
+            .map(|x| {
+                session.env.set(var, x);
+                let result = session.evaluate_expr(expr).and_then(Value::into_number);
+                session.history.truncate(self.history.len());
+                (x, result)
+            })
+            .collect()
+    }
+
+    /// The names of the defined functions whose results follow from their arguments alone
+    ///
+    /// A function isn't pure if its body calls one that uses the clock,
+    /// random numbers or the outside world, or that isn't known, or another
+    /// defined function that isn't pure.
+    fn pure_definitions(&self) -> BTreeSet<&str> {
+        let defined: BTreeSet<&str> = self.definitions.iter().map(|d| d.name.as_str()).collect();
+        let mut pure = defined.clone();
+        loop {
+            let impure: Vec<&str> = self
+                .definitions
+                .iter()
+                .filter(|definition| pure.contains(definition.name.as_str()))
+                .filter(|definition| {
+                    !called(&definition.body).into_iter().all(|name| {
+                        if defined.contains(name) {
+                            pure.contains(name)
+                        } else {
+                            name == "ans"
+                                || self.functions.get(name).is_some_and(|function| {
+                                    !function
+                                        .capabilities()
+                                        .intersects(Capabilities::NONDETERMINISTIC)
+                                })
+                        }
+                    })
+                })
+                .map(|definition| definition.name.as_str())
+                .collect();
+            if impure.is_empty() {
+                return pure;
+            }
+            for name in impure {
+                pure.remove(name);
+            }
+        }
+    }
+
+    /// Write a result in the configured [`NumberFormat`](crate::NumberFormat)
+    pub fn format(&self, value: &Value) -> String {
+        value.format(&self.config.format)
+    }
+
+    /// Save the variables and functions to a JSON file, replacing it if it exists
+    ///
+    /// The history and the configuration aren't saved.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{Session, Value};
+    ///
+    /// let path = std::env::temp_dir().join("ast-session-example.json");
+    /// let mut session = Session::new();
+    /// session.evaluate("g = 9.81").unwrap();
+    /// session.evaluate("fall(t) = g * t * t / 2").unwrap();
+    /// session.save(&path).unwrap();
+    ///
+    /// let mut restored = Session::new();
+    /// restored.load(&path).unwrap();
+    /// assert_eq!(restored.evaluate("fall(2)").unwrap(), Value::Number(19.62));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SessionError> {
+        let file = SessionFile {
+            format: FORMAT_VERSION,
+            variables: text(&self.env),
+            functions: self
+                .definitions
+                .iter()
+                .map(|definition| SavedFunction {
+                    name: definition.name.clone(),
+                    params: definition.params.clone(),
+                    body: definition.source.clone(),
+                    captured: text(&definition.captured),
+                })
+                .collect(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// Replace the variables and functions with those in a file written by
+    /// [`Session::save`], after taking a [`Session::snapshot`]
+    ///
+    /// Nothing changes if the file can't be read.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), SessionError> {
+        let file: SessionFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if file.format != FORMAT_VERSION {
+            return Err(SessionError::UnsupportedFormat(file.format));
+        }
+
+        let env = numbers(file.variables)?;
+        let mut definitions = Vec::new();
+        for function in file.functions {
+            let body = self.parse(&function.body).map_err(|_| {
+                SessionError::InvalidFile(format!("the body of '{}' doesn't parse", function.name))
+            })?;
+            definitions.push(Definition {
+                name: function.name,
+                params: function.params,
+                source: function.body,
+                body,
+                captured: numbers(function.captured)?,
+            });
+        }
+
+        self.snapshot();
+        self.env = env;
+        self.definitions = definitions;
+        Ok(())
+    }
+}
+
+/// Variables written as text, for a session file
+fn text(env: &Environment) -> BTreeMap<String, String> {
+    env.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Variables read back from the text in a session file
+fn numbers(variables: BTreeMap<String, String>) -> Result<Environment, SessionError> {
+    let mut env = Environment::new();
+    for (name, value) in variables {
+        let value = value.parse().map_err(|_| {
+            SessionError::InvalidFile(format!("'{}' isn't a number for '{}'", value, name))
+        })?;
+        env.set(name, value);
+    }
+    Ok(env)
+}
+
+/// The names of the functions called anywhere in `expr`
+fn called(expr: &Expr) -> BTreeSet<&str> {
+    let mut names = BTreeSet::new();
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        if let Expr::Call(name, _) = expr {
+            names.insert(name.as_str());
+        }
+        pending.extend(expr.children());
+    }
+    names
+}
+
+/// Turn the variable `ans` into the call `ans()`, so that it can be any kind of value
+///
+/// The tree is walked with a stack of nodes still to visit rather than by
+/// recursion, so that long chains are safe.
+fn recall(expr: &mut Expr) {
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        match expr {
+            Expr::Var(name) if name == "ans" => *expr = Expr::Call("ans".to_string(), Vec::new()),
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right) => pending.extend([left.as_mut(), right.as_mut()]),
+            Expr::Neg(inner) | Expr::Not(inner) | Expr::Named(_, inner) => pending.push(inner),
+            Expr::Call(_, args) | Expr::List(args) => pending.extend(args),
+            Expr::Index(target, indices) => {
+                pending.push(target);
+                pending.extend(indices);
+            }
+            Expr::Let(name, value, body) => {
+                pending.push(value);
+                // A local `ans` hides the history inside the body
+                if name != "ans" {
+                    pending.push(body);
+                }
+            }
+            Expr::Piecewise(cases, default) => {
+                for (condition, value) in cases {
+                    pending.extend([condition, value]);
+                }
+                pending.extend(default.as_deref_mut());
+            }
+            Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test recalling results, including ones that aren't numbers
+    #[test]
+    fn test_history() {
+        let mut session = Session::new();
+        assert!(matches!(
+            session.evaluate("ans + 1"),
+            Err(SessionError::Evaluation(EvaluationError::DomainError(_)))
+        ));
+
+        session.evaluate("1").unwrap();
+        session.evaluate("date(2024, 1, 1)").unwrap();
+        session.evaluate("10").unwrap();
+        assert_eq!(
+            session.evaluate("ans(3) + ans").unwrap(),
+            Value::Number(11.0)
+        );
+        assert_eq!(
+            session.evaluate("year(ans(3))").unwrap(),
+            Value::Number(2024.0)
+        );
+        assert_eq!(session.ans(1), Some(&Value::Number(2024.0)));
+        assert_eq!(session.ans(0), None);
+
+        for input in ["ans(6)", "ans(0)", "ans(1.5)", "ans(1, 2)"] {
+            assert!(session.evaluate(input).is_err(), "'{}'", input);
+        }
+
+        // Tabulating doesn't add to the history, so every row sees the same ans
+        let (_, ast) = crate::parse_expression("ans + x").unwrap();
+        let table = session.tabulate(&ast, "x", 1.0..=3.0, 1.0);
+        let values: Vec<f64> = table.into_iter().map(|(_, y)| y.unwrap()).collect();
+        assert_eq!(values, [2025.0, 2026.0, 2027.0]);
+        assert_eq!(session.ans(1), Some(&Value::Number(2024.0)));
+        assert_eq!(session.history().len(), 5);
+
+        session.clear_history();
+        assert!(session.evaluate("ans").is_err());
+    }
+
+    /// Test that variables and syntax errors are handled like the rest of the crate does
+    #[test]
+    fn test_inputs() {
+        let mut session = Session::new();
+        session.environment_mut().set("x", 4.0);
+        assert_eq!(session.evaluate("x * 2").unwrap(), Value::Number(8.0));
+
+        session.environment_mut().set("ans", 100.0);
+        assert_eq!(session.evaluate("ans").unwrap(), Value::Number(100.0));
+        assert_eq!(session.evaluate("ans(2)").unwrap(), Value::Number(8.0));
+
+        assert!(matches!(
+            session.evaluate("2 + 3 )"),
+            Err(SessionError::Syntax { position: 6 })
+        ));
+        assert!(matches!(
+            session.evaluate("* 2"),
+            Err(SessionError::Syntax { .. })
+        ));
+        assert_eq!(session.history().len(), 3);
+    }
+
+    /// Test that assignments can be undone, one at a time
+    #[test]
+    fn test_rollback() {
+        let mut session = Session::new();
+        assert!(!session.rollback());
+
+        session.evaluate("x = 2").unwrap();
+        session.evaluate(" y=x * 3").unwrap();
+        session.evaluate("x = 0").unwrap();
+        assert_eq!(session.evaluate("x == 0").unwrap(), Value::Number(1.0));
+        assert!(session.evaluate("x = date(2024, 1, 1)").is_err());
+        assert!(matches!(
+            session.evaluate("x = 1 +"),
+            Err(SessionError::Syntax { position: 7 })
+        ));
+
+        assert!(session.rollback());
+        assert_eq!(session.environment().get("x"), Some(2.0));
+        assert_eq!(session.environment().get("y"), Some(6.0));
+        assert!(session.rollback());
+        assert!(!session.environment().contains("y"));
+        assert!(session.rollback());
+        assert!(!session.rollback());
+        assert_eq!(session.environment(), &Environment::new());
+
+        let excel = Session::new().with_parser_config(ParserConfig {
+            dialect: Dialect::Excel,
+            ..ParserConfig::default()
+        });
+        assert_eq!(excel.split_assignment("x = 1"), None);
+    }
+
+    /// Test which variables a function captures, and that parameters hide them
+    #[test]
+    fn test_closures() {
+        let mut session = Session::new();
+        session.evaluate("k = 2").unwrap();
+        session.evaluate("x = 100").unwrap();
+        session.evaluate("scale(x) = k * x").unwrap();
+        session.evaluate("k = 3").unwrap();
+        assert_eq!(session.evaluate("scale(5)").unwrap(), Value::Number(10.0));
+        assert_eq!(session.evaluate("k * 5").unwrap(), Value::Number(15.0));
+        let captured: Vec<_> = session.captured("scale").unwrap().iter().collect();
+        assert_eq!(captured, [("k", 2.0)]);
+
+        // Redefining the function captures the new value
+        session.evaluate("scale(x) = k * x").unwrap();
+        assert_eq!(session.evaluate("scale(5)").unwrap(), Value::Number(15.0));
+
+        // Functions calling functions each keep their own captures
+        session.evaluate("twice(x) = scale(x) * 2 + k").unwrap();
+        session.evaluate("k = 0").unwrap();
+        assert_eq!(session.evaluate("twice(1)").unwrap(), Value::Number(9.0));
+        assert_eq!(session.evaluate("twice(x=1)").unwrap(), Value::Number(9.0));
+
+        // Undoing the definition brings back the old captures
+        session.rollback();
+        session.rollback();
+        session.rollback();
+        assert_eq!(session.evaluate("scale(5)").unwrap(), Value::Number(10.0));
+        assert_eq!(session.captured("missing"), None);
+
+        let path = std::env::temp_dir().join(format!("ast-closures-{}.json", std::process::id()));
+        session.save(&path).unwrap();
+        let mut restored = Session::new();
+        restored.load(&path).unwrap();
+        restored.evaluate("k = 50").unwrap();
+        assert_eq!(restored.evaluate("scale(5)").unwrap(), Value::Number(10.0));
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Test recursive functions, and that runaway recursion stops at the limit
+    #[test]
+    fn test_recursion() {
+        let mut session = Session::new();
+        session
+            .evaluate("fact(n) = if(n <= 1, 1, n * fact(n - 1))")
+            .unwrap();
+        assert_eq!(
+            session.evaluate("fact(10)").unwrap(),
+            Value::Number(3628800.0)
+        );
+
+        // Functions can call ones defined after them
+        session
+            .evaluate("even(n) = if(n == 0, 1, odd(n - 1))")
+            .unwrap();
+        session
+            .evaluate("odd(n) = if(n == 0, 0, even(n - 1))")
+            .unwrap();
+        assert_eq!(session.evaluate("even(10)").unwrap(), Value::Number(1.0));
+
+        session
+            .evaluate("forever(n) = if(n < 0, 0, forever(n + 1))")
+            .unwrap();
+        assert!(matches!(
+            session.evaluate("forever(0)"),
+            Err(SessionError::Evaluation(EvaluationError::RecursionLimit(
+                100
+            )))
+        ));
+        // The depth starts over with each evaluation, and counts every function
+        assert_eq!(
+            session.evaluate("fact(100) > 0").unwrap(),
+            Value::Number(1.0)
+        );
+        assert!(session.evaluate("fact(101)").is_err());
+        session.config_mut().max_call_depth = 5;
+        assert!(session.evaluate("even(5)").is_err());
+        assert_eq!(session.evaluate("even(4)").unwrap(), Value::Number(1.0));
+    }
+
+    /// Test that definitions which could never return are refused
+    #[test]
+    fn test_divergent_definitions() {
+        let mut session = Session::new();
+        let chain = |session: &mut Session, input: &str| match session.evaluate(input) {
+            Err(SessionError::Divergent { chain, .. }) => chain.join(" "),
+            result => panic!("'{}' gave {:?}", input, result),
+        };
+        assert_eq!(chain(&mut session, "f(n) = 1 + f(n - 1)"), "f f");
+        assert_eq!(chain(&mut session, "f(n) = if(f(n) > 0, 1, 2)"), "f f");
+        assert_eq!(chain(&mut session, "f(n) = piecewise((f(n), 1), 2)"), "f f");
+
+        // Mutual recursion is found when the cycle closes
+        session.evaluate("g(n) = h(n) * 2").unwrap();
+        session.evaluate("h(n) = abs(k(n))").unwrap();
+        assert_eq!(chain(&mut session, "k(n) = g(n - 1)"), "k g h k");
+        assert_eq!(
+            session.evaluate("k(n) = g(n)").unwrap_err().to_string(),
+            "Function 'k' would never return: every call leads to another (k → g → h → k)"
+        );
+        assert!(session.defined_functions().all(|name| name != "k"));
+
+        // Calls that can be skipped may be the way out
+        for input in [
+            "k(n) = if(n <= 0, 0, g(n - 1))",
+            "k(n) = piecewise((n <= 0, 0), g(n - 1))",
+            "k(n) = n > 0 && g(n - 1) > 0",
+        ] {
+            session.evaluate(input).unwrap();
+        }
+        session.config_mut().strict_evaluation = true;
+        assert_eq!(
+            chain(&mut session, "k(n) = n > 0 && g(n - 1) > 0"),
+            "k g h k"
+        );
+    }
+
+    /// Test that calls with the same arguments are evaluated once per evaluation, when that's safe
+    #[test]
+    fn test_memoized_calls() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut functions = FunctionRegistry::standard();
+        for name in ["count", "noisy"] {
+            let calls = Arc::clone(&calls);
+            functions.register(name, 1, move |args| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok(args[0])
+            });
+        }
+        functions.set_capabilities("noisy", Capabilities::RANDOM);
+        let mut session = Session::new().with_functions(functions);
+        session.evaluate("square(x) = count(x) * x").unwrap();
+        session.evaluate("loud(x) = noisy(x)").unwrap();
+        session.evaluate("louder(x) = loud(x) * 2").unwrap();
+        let calls_for = |session: &mut Session, input: &str| {
+            let before = calls.load(Ordering::Relaxed);
+            session.evaluate(input).unwrap();
+            calls.load(Ordering::Relaxed) - before
+        };
+
+        assert_eq!(
+            calls_for(&mut session, "square(3) + square(3) + square(4)"),
+            2
+        );
+        assert_eq!(calls_for(&mut session, "square(3)"), 1);
+        assert_eq!(calls_for(&mut session, "louder(1) + louder(1)"), 2);
+        session.config_mut().memoize_calls = false;
+        assert_eq!(calls_for(&mut session, "square(3) + square(3)"), 2);
+        session.config_mut().memoize_calls = true;
+
+        session
+            .evaluate("fib(n) = if(n < 2, n, fib(n - 1) + fib(n - 2))")
+            .unwrap();
+        assert_eq!(
+            session.evaluate("fib(80)").unwrap(),
+            Value::Number(23416728348467685.0)
+        );
+    }
+
+    /// Test defining functions, and saving and loading them with the variables
+    #[test]
+    fn test_definitions_and_files() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.split_assignment(" f( a ,b ) = a"),
+            Some((
+                Assignee::Function {
+                    name: "f",
+                    params: vec!["a", "b"]
+                },
+                " a"
+            ))
+        );
+        assert_eq!(session.split_assignment("f(2) = 4"), None);
+        assert_eq!(session.split_assignment("f(x) == 4"), None);
+
+        session.evaluate("x = 10").unwrap();
+        session.evaluate("k = inf").unwrap();
+        session.evaluate("square(x) = x * x").unwrap();
+        session.evaluate("answer() = 42").unwrap();
+        session.evaluate("area(w, h) = square(w) * h / x").unwrap();
+        assert_eq!(session.evaluate("area(3, 10)").unwrap(), Value::Number(9.0));
+        assert!(session.evaluate("square(1, 2)").is_err());
+        assert_eq!(session.history().len(), 3);
+
+        session.evaluate("square(x) = x * x * x").unwrap();
+        assert_eq!(
+            session.defined_functions().collect::<Vec<_>>(),
+            ["answer", "area", "square"]
+        );
+        assert!(session.rollback());
+        assert_eq!(session.evaluate("square(3)").unwrap(), Value::Number(9.0));
+
+        let path = std::env::temp_dir().join(format!("ast-session-{}.json", std::process::id()));
+        session.save(&path).unwrap();
+        let mut restored = Session::new();
+        restored.evaluate("y = 1").unwrap();
+        restored.load(&path).unwrap();
+        assert_eq!(restored.environment().get("k"), Some(f64::INFINITY));
+        assert!(!restored.environment().contains("y"));
+        assert_eq!(
+            restored.evaluate("area(3, 10) + answer()").unwrap(),
+            Value::Number(51.0)
+        );
+        assert!(restored.rollback());
+        assert_eq!(restored.environment().get("y"), Some(1.0));
+
+        // Files from before functions captured values still load
+        fs::write(
+            &path,
+            r#"{"format": 1, "variables": {"x": "2"}, "functions": [{"name": "f", "params": [], "body": "x"}]}"#,
+        )
+        .unwrap();
+        restored.load(&path).unwrap();
+        assert_eq!(restored.evaluate("f()").unwrap(), Value::Number(2.0));
+
+        fs::write(&path, r#"{"format": 2, "variables": {}, "functions": []}"#).unwrap();
+        assert!(matches!(
+            restored.load(&path),
+            Err(SessionError::UnsupportedFormat(2))
+        ));
+        fs::write(
+            &path,
+            r#"{"format": 1, "variables": {"a": "x"}, "functions": []}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            restored.load(&path),
+            Err(SessionError::InvalidFile(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Test that long chains can be assigned, defined and recalled
+    #[test]
+    fn test_long_chains() {
+        let mut session = Session::new();
+        let chain = vec!["x"; 100_000].join(" + ");
+        session.evaluate("x = 1").unwrap();
+        session.evaluate(&format!("y = {}", chain)).unwrap();
+        session.evaluate(&format!("f(x) = {}", chain)).unwrap();
+        assert_eq!(session.evaluate("f(2)").unwrap(), Value::Number(200_000.0));
+        let average = format!("({}) / 100000", vec!["ans"; 100_000].join(" + "));
+        assert_eq!(
+            session.evaluate(&average).unwrap(),
+            Value::Number(200_000.0)
+        );
+        assert_eq!(session.environment().get("y"), Some(100_000.0));
+    }
+}
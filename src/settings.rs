@@ -0,0 +1,605 @@
+//! REPL settings, from the config file and the command line
+//!
+//! The config file is `$XDG_CONFIG_HOME/ast/config.toml`, which is usually
+//! `~/.config/ast/config.toml`. It's a TOML file of top-level keys with
+//! string, integer and boolean values:
+//!
+//! ```toml
+//! precision = 10                        # significant digits, or "off"
+//! angle = "degrees"                     # or "radians"
+//! prompt = "calc> "
+//! color = "auto"                        # or "always", "never"
+//! theme = "dark"                        # or "light"
+//! startup = "~/.config/ast/startup.txt" # lines to run at startup
+//! history = "~/.local/share/ast/history"
+//! verbosity = "normal"                  # or "quiet", "verbose"
+//! ```
+//!
+//! Command-line flags with the same names (`--precision 10`) override it.
+//! `-e` and `-D` only exist on the command line: they evaluate expressions
+//! without starting the REPL, for shell scripts.
+
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use ast::AngleUnit;
+use toml::Spanned;
+
+use crate::render::Theme;
+
+/// The command-line help
+pub const USAGE: &str = "\
+Usage: ast [options]
+       ast [options] -e <expression> [-D <name=value>]...
+       ast serve [options]
+       ast gen [--count <n>] [--max-depth <n>] [--seed <n>] [--values]
+       ast fmt [--check] [<file>...]
+       ast lint [--json] [--var <name>]... [--deny-warnings] [<file>...]
+       ast completions <bash|zsh|fish>
+
+Options:
+  -e, --eval <expression>     Print the expression's value and exit; may be repeated
+  -D, --define <name=value>   Set a variable (or define a function) for -e first
+  -q, --quiet                 Print only results, and errors on stderr
+  -v, --verbose               Also print trees, and how long parsing and evaluating took
+  --precision <digits|off>    Round results to this many significant digits
+  --angle <radians|degrees>   The unit of angles for trigonometric functions
+  --prompt <text>             The input prompt
+  --color <auto|always|never> Whether to color the output
+  --theme <dark|light>        The colors to use, for dark or light backgrounds
+  --startup <file>            Run the lines of a file before reading input
+  --history <file>            Append every input line to a file
+  --config <file>             Read settings from this file instead of the default
+  --no-config                 Don't read a config file
+  --verbosity <quiet|normal|verbose>
+                              How much to print, as -q and -v set
+  --help                      Show this help
+
+Exit codes: 0 on success, 2 if an expression (or the command line) doesn't
+parse, 3 if one can't be evaluated, and 1 for anything else.";
+
+/// A command-line option, as listed in [`USAGE`], for shell completion
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Flag {
+    /// The one-letter form, such as `q` for `-q`
+    pub short: Option<char>,
+    /// The long form without its dashes, such as `quiet`
+    pub long: &'static str,
+    /// What follows the option
+    pub value: FlagValue,
+    /// What it does
+    pub help: &'static str,
+}
+
+/// What follows a command-line option
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FlagValue {
+    /// Nothing; the option is a switch
+    None,
+    /// One of these words
+    Choice(&'static [&'static str]),
+    /// A path to a file
+    File,
+    /// Any text, such as an expression
+    Text,
+}
+
+/// Every command-line option
+pub const FLAGS: &[Flag] = &[
+    Flag {
+        short: Some('e'),
+        long: "eval",
+        value: FlagValue::Text,
+        help: "Print an expression's value and exit",
+    },
+    Flag {
+        short: Some('D'),
+        long: "define",
+        value: FlagValue::Text,
+        help: "Set a variable for -e",
+    },
+    Flag {
+        short: Some('q'),
+        long: "quiet",
+        value: FlagValue::None,
+        help: "Print only results",
+    },
+    Flag {
+        short: Some('v'),
+        long: "verbose",
+        value: FlagValue::None,
+        help: "Also print trees and timings",
+    },
+    Flag {
+        short: None,
+        long: "precision",
+        value: FlagValue::Text,
+        help: "Round results to this many significant digits",
+    },
+    Flag {
+        short: None,
+        long: "angle",
+        value: FlagValue::Choice(&["radians", "degrees"]),
+        help: "The unit of angles",
+    },
+    Flag {
+        short: None,
+        long: "prompt",
+        value: FlagValue::Text,
+        help: "The input prompt",
+    },
+    Flag {
+        short: None,
+        long: "color",
+        value: FlagValue::Choice(&["auto", "always", "never"]),
+        help: "Whether to color the output",
+    },
+    Flag {
+        short: None,
+        long: "theme",
+        value: FlagValue::Choice(&["dark", "light"]),
+        help: "The colors to use",
+    },
+    Flag {
+        short: None,
+        long: "startup",
+        value: FlagValue::File,
+        help: "Run the lines of a file first",
+    },
+    Flag {
+        short: None,
+        long: "history",
+        value: FlagValue::File,
+        help: "Append every input line to a file",
+    },
+    Flag {
+        short: None,
+        long: "config",
+        value: FlagValue::File,
+        help: "Read settings from this file",
+    },
+    Flag {
+        short: None,
+        long: "no-config",
+        value: FlagValue::None,
+        help: "Don't read a config file",
+    },
+    Flag {
+        short: None,
+        long: "verbosity",
+        value: FlagValue::Choice(&["quiet", "normal", "verbose"]),
+        help: "How much to print",
+    },
+    Flag {
+        short: None,
+        long: "help",
+        value: FlagValue::None,
+        help: "Show the help",
+    },
+];
+
+/// When to color the output
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ColorMode {
+    /// When writing to a terminal, and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How much the program prints besides results
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Verbosity {
+    /// Only results, with errors on stderr, for scripts and Makefiles
+    Quiet,
+    /// Results with their trees, warnings and errors
+    #[default]
+    Normal,
+    /// As normal, and how long parsing and evaluating took
+    Verbose,
+}
+
+/// Everything the config file and the command line can set
+#[derive(Debug, PartialEq, Clone)]
+pub struct Settings {
+    /// Significant digits to show in results, or `None` for all
+    pub precision: Option<usize>,
+    /// The unit of angles for trigonometric functions
+    pub angle: AngleUnit,
+    /// The input prompt
+    pub prompt: String,
+    /// When to color the output
+    pub color: ColorMode,
+    /// The colors to use
+    pub theme: Theme,
+    /// A file whose lines are run before reading input
+    pub startup: Option<PathBuf>,
+    /// A file that every input line is appended to
+    pub history: Option<PathBuf>,
+    /// How much to print besides results
+    pub verbosity: Verbosity,
+    /// Expressions to print the values of instead of starting the REPL
+    pub eval: Vec<String>,
+    /// Assignments such as `a=2` to run before evaluating [`Settings::eval`]
+    pub defines: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            precision: None,
+            angle: AngleUnit::Radians,
+            prompt: ">>> ".to_string(),
+            color: ColorMode::Auto,
+            theme: Theme::DARK,
+            startup: None,
+            history: None,
+            verbosity: Verbosity::Normal,
+            eval: Vec::new(),
+            defines: Vec::new(),
+        }
+    }
+}
+
+/// A setting's value, as written in the config file or on the command line
+#[derive(Debug, PartialEq, Clone)]
+enum SettingValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Settings {
+    /// Read the config file, then apply the command-line arguments (without the program name)
+    ///
+    /// Returns the settings with warnings about the config file, which don't
+    /// stop the REPL, or an error about the arguments, which does. `--help`
+    /// is an error whose message is [`USAGE`].
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<(Settings, Vec<String>), String> {
+        let mut flags = Vec::new();
+        let mut config = default_config_path();
+        let (mut eval, mut defines) = (Vec::new(), Vec::new());
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-e" | "--eval" => {
+                    eval.push(args.next().ok_or("-e needs an expression")?);
+                    continue;
+                }
+                "-D" | "--define" => {
+                    defines.push(args.next().ok_or("-D needs a name=value")?);
+                    continue;
+                }
+                "-q" | "--quiet" => {
+                    flags.push(("verbosity".to_string(), "quiet".to_string()));
+                    continue;
+                }
+                "-v" | "--verbose" => {
+                    flags.push(("verbosity".to_string(), "verbose".to_string()));
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(define) = arg.strip_prefix("-D") {
+                defines.push(define.to_string());
+                continue;
+            }
+            let key = match arg.strip_prefix("--") {
+                Some("help") => return Err(USAGE.to_string()),
+                Some("no-config") => {
+                    config = None;
+                    continue;
+                }
+                Some(key) => key.to_string(),
+                None => return Err(format!("unexpected argument '{}'", arg)),
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("--{} needs a value", key))?;
+            if key == "config" {
+                config = Some(expand_home(&value));
+            } else {
+                flags.push((key, value));
+            }
+        }
+
+        if let Some(define) = defines.iter().find(|define| !define.contains('=')) {
+            return Err(format!("-D {}: expected name=value", define));
+        }
+
+        let mut settings = Settings {
+            eval,
+            defines,
+            ..Settings::default()
+        };
+        let mut warnings = Vec::new();
+        if let Some(path) = config {
+            match fs::read_to_string(&path) {
+                Ok(text) => warnings = settings.apply_file(&path, &text),
+                // Having no config file is fine
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => warnings.push(format!("{}: {}", path.display(), error)),
+            }
+        }
+        for (key, value) in flags {
+            settings
+                .set(&key, SettingValue::String(value))
+                .map_err(|error| format!("--{}: {}", key, error))?;
+        }
+        Ok((settings, warnings))
+    }
+
+    /// Apply the settings in a config file, returning warnings about the lines that are wrong
+    ///
+    /// A file that isn't valid TOML is left out altogether, with a warning
+    /// about the first problem in it.
+    fn apply_file(&mut self, path: &Path, text: &str) -> Vec<String> {
+        let line = |offset: usize| text[..offset].matches('\n').count() + 1;
+        let table: BTreeMap<String, Spanned<toml::Value>> = match toml::from_str(text) {
+            Ok(table) => table,
+            Err(error) => {
+                let offset = error.span().map_or(0, |span| span.start);
+                let message = error.message().trim_end();
+                return vec![format!(
+                    "{} line {}: {}",
+                    path.display(),
+                    line(offset),
+                    message
+                )];
+            }
+        };
+
+        let mut entries: Vec<_> = table.into_iter().collect();
+        entries.sort_by_key(|(_, value)| value.span().start);
+        let mut warnings = Vec::new();
+        for (key, value) in entries {
+            let number = line(value.span().start);
+            let applied = setting_value(value.into_inner()).and_then(|value| self.set(&key, value));
+            if let Err(error) = applied {
+                warnings.push(format!(
+                    "{} line {}: {}: {}",
+                    path.display(),
+                    number,
+                    key,
+                    error
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Set one setting
+    fn set(&mut self, key: &str, value: SettingValue) -> Result<(), String> {
+        use SettingValue::*;
+
+        match (key, value) {
+            ("precision", String(text)) if text == "off" => self.precision = None,
+            ("precision", Integer(digits)) if digits > 0 => self.precision = Some(digits as usize),
+            ("precision", String(text)) => match text.parse::<usize>() {
+                Ok(digits) if digits > 0 => self.precision = Some(digits),
+                _ => return Err("expected a positive number of digits, or \"off\"".to_string()),
+            },
+            ("precision", _) => return Err("expected a positive number of digits".to_string()),
+            ("angle", String(unit)) => {
+                self.angle = match unit.as_str() {
+                    "radians" | "rad" => AngleUnit::Radians,
+                    "degrees" | "deg" => AngleUnit::Degrees,
+                    _ => return Err("expected \"radians\" or \"degrees\"".to_string()),
+                }
+            }
+            ("prompt", String(prompt)) => self.prompt = prompt,
+            ("color", Boolean(color)) => {
+                self.color = if color {
+                    ColorMode::Always
+                } else {
+                    ColorMode::Never
+                }
+            }
+            ("color", String(mode)) => {
+                self.color = match mode.as_str() {
+                    "auto" => ColorMode::Auto,
+                    "always" => ColorMode::Always,
+                    "never" => ColorMode::Never,
+                    _ => return Err("expected \"auto\", \"always\" or \"never\"".to_string()),
+                }
+            }
+            ("theme", String(name)) => {
+                self.theme = Theme::named(&name)
+                    .ok_or_else(|| "expected \"dark\" or \"light\"".to_string())?
+            }
+            ("startup", String(path)) => self.startup = Some(expand_home(&path)),
+            ("history", String(path)) => self.history = Some(expand_home(&path)),
+            ("verbosity", String(level)) => {
+                self.verbosity = match level.as_str() {
+                    "quiet" => Verbosity::Quiet,
+                    "normal" => Verbosity::Normal,
+                    "verbose" => Verbosity::Verbose,
+                    _ => return Err("expected \"quiet\", \"normal\" or \"verbose\"".to_string()),
+                }
+            }
+            ("angle" | "prompt" | "color" | "theme" | "startup" | "history" | "verbosity", _) => {
+                return Err("expected a string".to_string());
+            }
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// Where the config file is unless `--config` says otherwise
+fn default_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("ast").join("config.toml"))
+}
+
+/// Replace a leading `~/` with the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// A TOML value as a setting's value, if it's a kind that settings take
+fn setting_value(value: toml::Value) -> Result<SettingValue, String> {
+    match value {
+        toml::Value::String(text) => Ok(SettingValue::String(text)),
+        toml::Value::Integer(number) => Ok(SettingValue::Integer(number)),
+        toml::Value::Boolean(flag) => Ok(SettingValue::Boolean(flag)),
+        other => Err(format!(
+            "expected a string, integer or boolean, found {}",
+            other.type_str()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test reading a config file, with comments, bad settings and every kind of value
+    #[test]
+    fn test_config_file() {
+        let text = r#"
+            # Settings for the lab
+            precision = 6
+            angle = "degrees"    # for the surveyors
+            prompt = 'calc # "lab"> '
+            color = false
+            theme = "light"
+            history = "/tmp/history"
+            speed = 11
+            verbosity = 90
+            startup = 1.5
+        "#;
+        let mut settings = Settings::default();
+        let warnings = settings.apply_file(Path::new("config.toml"), text);
+
+        assert_eq!(settings.precision, Some(6));
+        assert_eq!(settings.angle, AngleUnit::Degrees);
+        assert_eq!(settings.prompt, "calc # \"lab\"> ");
+        assert_eq!(settings.color, ColorMode::Never);
+        assert_eq!(settings.theme, Theme::LIGHT);
+        assert_eq!(settings.history, Some(PathBuf::from("/tmp/history")));
+        assert_eq!(
+            warnings,
+            [
+                "config.toml line 9: speed: unknown setting",
+                "config.toml line 10: verbosity: expected a string",
+                "config.toml line 11: startup: expected a string, integer or boolean, found float",
+            ]
+        );
+
+        // A file that isn't TOML is left out, and where it goes wrong is reported
+        let mut settings = Settings::default();
+        let warnings = settings.apply_file(Path::new("config.toml"), "precision = 6\nstartup\n");
+        assert_eq!(settings, Settings::default());
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with("config.toml line 2: "),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    /// Test that flags override the config file, and that bad flags are errors
+    #[test]
+    fn test_flags() {
+        let path = env::temp_dir().join(format!("ast-config-{}.toml", std::process::id()));
+        fs::write(&path, "precision = 4\nprompt = \"> \"\n").unwrap();
+        let args = |extra: &[&str]| {
+            let config = ["--config", path.to_str().unwrap()];
+            config
+                .iter()
+                .chain(extra)
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let (settings, warnings) =
+            Settings::load(args(&["--precision", "off", "--angle", "deg"])).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(settings.precision, None);
+        assert_eq!(settings.angle, AngleUnit::Degrees);
+        assert_eq!(settings.prompt, "> ");
+
+        assert_eq!(
+            Settings::load(args(&["--precision"])),
+            Err("--precision needs a value".to_string())
+        );
+        assert!(Settings::load(args(&["--color", "sometimes"])).is_err());
+        assert!(Settings::load(args(&["extra"])).is_err());
+        assert_eq!(Settings::load(args(&["--help"])), Err(USAGE.to_string()));
+
+        let (settings, _) = Settings::load(args(&["--no-config"])).unwrap();
+        assert_eq!(settings, Settings::default());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Test that the flags for shell completion are the ones in the help
+    #[test]
+    fn test_flag_list() {
+        let mut listed: Vec<&str> = USAGE
+            .split_whitespace()
+            .filter_map(|word| word.trim_end_matches(',').strip_prefix("--"))
+            .collect();
+        let mut flags: Vec<&str> = FLAGS.iter().map(|flag| flag.long).collect();
+        listed.sort_unstable();
+        flags.sort_unstable();
+        assert_eq!(listed, flags);
+        for flag in FLAGS.iter().filter(|flag| flag.short.is_some()) {
+            let short = format!("-{}, --{}", flag.short.unwrap(), flag.long);
+            assert!(USAGE.contains(&short), "{}", short);
+        }
+    }
+
+    /// Test the flags for evaluating expressions from the command line
+    #[test]
+    fn test_eval_flags() {
+        let args = [
+            "--no-config",
+            "-e",
+            "a*b + c",
+            "-D",
+            "a=2",
+            "-Db=3",
+            "--define",
+            "c = 4",
+        ];
+        let (settings, _) = Settings::load(args.map(String::from)).unwrap();
+        assert_eq!(settings.eval, ["a*b + c"]);
+        assert_eq!(settings.defines, ["a=2", "b=3", "c = 4"]);
+
+        assert_eq!(
+            Settings::load(["--no-config", "-D", "a"].map(String::from)),
+            Err("-D a: expected name=value".to_string())
+        );
+        assert_eq!(
+            Settings::load(["-e"].map(String::from)),
+            Err("-e needs an expression".to_string())
+        );
+
+        // The last of -q, -v and --verbosity wins
+        let verbosity = |args: &[&str]| {
+            let args = ["--no-config"]
+                .iter()
+                .chain(args)
+                .map(|arg| arg.to_string());
+            Settings::load(args).map(|(settings, _)| settings.verbosity)
+        };
+        assert_eq!(verbosity(&[]), Ok(Verbosity::Normal));
+        assert_eq!(verbosity(&["-q"]), Ok(Verbosity::Quiet));
+        assert_eq!(verbosity(&["-q", "--verbose"]), Ok(Verbosity::Verbose));
+        assert_eq!(
+            verbosity(&["-v", "--verbosity", "normal"]),
+            Ok(Verbosity::Normal)
+        );
+        assert!(verbosity(&["--verbosity", "loud"]).is_err());
+    }
+}
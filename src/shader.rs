@@ -0,0 +1,314 @@
+//! Writing expressions as shader code
+//!
+//! [`Expr::to_glsl`] and [`Expr::to_wgsl`] write an expression in GLSL or
+//! WGSL, so a user-authored formula can be baked into a GPU shader, e.g. by a
+//! procedural texture tool. Variables are written as they are, for the shader
+//! to declare as uniforms or locals.
+//!
+//! Shaders work with 32-bit floats, so results are less precise than the
+//! evaluator's, and numbers that don't fit in an `f32` can't be written.
+
+use crate::{
+    CodegenError, Expr,
+    codegen::{ATOM, LOOSEST, PRODUCT, Target, UNARY, identifier, pairwise, write},
+};
+
+/// GLSL keywords and reserved words, which can't be used as names
+const GLSL_KEYWORDS: &str = "\
+    active asm atomic_uint attribute bool break buffer bvec2 bvec3 bvec4 case cast centroid \
+    class coherent common const continue default discard dmat2 dmat3 dmat4 do double dvec2 dvec3 \
+    dvec4 else enum extern external false filter fixed flat float for goto half highp if in \
+    inline inout input int interface invariant isampler2D ivec2 ivec3 ivec4 layout long lowp \
+    mat2 mat3 mat4 mediump namespace noinline noperspective out output partition patch precise \
+    precision public readonly resource restrict return sample sampler2D sampler3D samplerCube \
+    shared short sizeof smooth static struct subroutine superp switch template this true typedef \
+    uint uniform union unsigned using uvec2 uvec3 uvec4 varying vec2 vec3 vec4 void volatile \
+    while writeonly";
+
+/// WGSL keywords and reserved words, which can't be used as names
+const WGSL_KEYWORDS: &str = "\
+    NULL Self abstract active alias alignas alignof as asm asm_fragment async attribute auto \
+    await become break case cast catch class co_await co_return co_yield coherent column_major \
+    common compile compile_fragment concept const const_assert const_cast consteval constexpr \
+    constinit continue continuing crate debugger decltype default delete demote demote_to_helper \
+    diagnostic discard do dynamic_cast else enable enum explicit export extends extern external \
+    fallthrough false filter final finally fn for friend from fxgroup get goto groupshared highp \
+    if impl implements import inline instanceof interface layout let loop lowp macro macro_rules \
+    match mediump meta mod module move mut mutable namespace new nil noexcept noinline \
+    nointerpolation non_coherent noncoherent noperspective null nullptr of operator override \
+    package packoffset partition pass patch pixelfragment precise precision premerge priv \
+    protected pub public readonly ref regardless register reinterpret_cast require requires \
+    resource restrict return self set shared sizeof smooth snorm static static_assert \
+    static_cast std struct subroutine super switch target template this thread_local throw trait \
+    true try type typedef typeid typename typeof union unless unorm unsafe unsized use using var \
+    varying virtual volatile wgsl where while with writeonly yield";
+
+/// Functions with the same name and meaning in GLSL and WGSL
+const SHADER_BUILTINS: &[&str] = &[
+    "abs", "sqrt", "exp", "log2", "sin", "cos", "tan", "asin", "acos", "atan", "sinh", "cosh",
+    "tanh", "floor", "ceil", "trunc", "pow", "sign",
+];
+
+impl Expr {
+    /// Write this expression in GLSL, as a `float` expression
+    ///
+    /// Comparisons and logical operations become `float(...)` of a `bool`,
+    /// so they still give 1.0 or 0.0. Cell references, numbers that don't fit
+    /// in a `float` and functions other than the
+    /// [standard math functions](crate::FunctionRegistry::standard) can't be
+    /// translated.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("mix * atan2(uv_y, uv_x) + (t > 0.5)").unwrap();
+    /// assert_eq!(
+    ///     ast.to_glsl().unwrap(),
+    ///     "mix * atan(uv_y, uv_x) + float(t > 0.5)"
+    /// );
+    /// ```
+    pub fn to_glsl(&self) -> Result<String, CodegenError> {
+        write(&Glsl, self, None)
+    }
+
+    /// Write this expression in WGSL, as an `f32` expression
+    ///
+    /// Comparisons and logical operations become `f32(...)` of a `bool`, so
+    /// they still give 1.0 or 0.0. The same things can't be translated as
+    /// with [`Expr::to_glsl`].
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("a && b || hypot(x, y) < 1").unwrap();
+    /// assert_eq!(
+    ///     ast.to_wgsl().unwrap(),
+    ///     "f32((a != 0.0 && b != 0.0) || length(vec2<f32>(x, y)) < 1.0)"
+    /// );
+    /// ```
+    pub fn to_wgsl(&self) -> Result<String, CodegenError> {
+        write(&Wgsl, self, None)
+    }
+}
+
+/// GLSL, with `float` for numbers
+struct Glsl;
+
+impl Target for Glsl {
+    fn language(&self) -> &'static str {
+        "GLSL"
+    }
+
+    /// Names starting with `gl_` or containing `__` are reserved
+    fn name(&self, name: &str) -> Result<String, CodegenError> {
+        let name = identifier(name)?;
+        if is_keyword(GLSL_KEYWORDS, name) || name.starts_with("gl_") || name.contains("__") {
+            Err(CodegenError::InvalidName(name.to_string()))
+        } else {
+            Ok(name.to_string())
+        }
+    }
+
+    fn literal(&self, value: f64) -> Result<(String, u8), CodegenError> {
+        literal(value, self.language())
+    }
+
+    fn number(&self, boolean: &str) -> String {
+        format!("float({})", boolean)
+    }
+
+    fn call(&self, name: &str, args: Vec<String>) -> Option<(String, u8)> {
+        match name {
+            "atan2" => Some((format!("atan({}, {})", args[0], args[1]), ATOM)),
+            "hypot" => Some((format!("length(vec2({}, {}))", args[0], args[1]), ATOM)),
+            _ => call(name, args),
+        }
+    }
+
+    /// A chain of `?:`, which groups to the right
+    fn piecewise(&self, cases: Vec<(String, String)>, default: String) -> (String, u8) {
+        let mut code = String::new();
+        for (condition, value) in cases {
+            code.push_str(&format!("{} ? {} : ", condition, value));
+        }
+        (code + &default, LOOSEST)
+    }
+}
+
+/// WGSL, with `f32` for numbers
+struct Wgsl;
+
+impl Target for Wgsl {
+    fn language(&self) -> &'static str {
+        "WGSL"
+    }
+
+    /// WGSL doesn't allow `&&` and `||` to be mixed without parentheses
+    const PARENTHESIZE_AND_IN_OR: bool = true;
+
+    /// `_` on its own and names starting with `__` are reserved
+    fn name(&self, name: &str) -> Result<String, CodegenError> {
+        let name = identifier(name)?;
+        if is_keyword(WGSL_KEYWORDS, name) || name == "_" || name.starts_with("__") {
+            Err(CodegenError::InvalidName(name.to_string()))
+        } else {
+            Ok(name.to_string())
+        }
+    }
+
+    fn literal(&self, value: f64) -> Result<(String, u8), CodegenError> {
+        literal(value, self.language())
+    }
+
+    fn number(&self, boolean: &str) -> String {
+        format!("f32({})", boolean)
+    }
+
+    fn call(&self, name: &str, args: Vec<String>) -> Option<(String, u8)> {
+        match name {
+            "atan2" => Some((format!("atan2({}, {})", args[0], args[1]), ATOM)),
+            "hypot" => Some((format!("length(vec2<f32>({}, {}))", args[0], args[1]), ATOM)),
+            _ => call(name, args),
+        }
+    }
+
+    /// Nested `select(otherwise, then, condition)`, since WGSL has no `?:`
+    ///
+    /// Every value is computed, which only matters for speed.
+    fn piecewise(&self, cases: Vec<(String, String)>, default: String) -> (String, u8) {
+        let code = cases
+            .into_iter()
+            .rev()
+            .fold(default, |otherwise, (condition, value)| {
+                format!("select({}, {}, {})", otherwise, value, condition)
+            });
+        (code, ATOM)
+    }
+}
+
+/// Whether a name is one of a space-separated list of keywords
+fn is_keyword(keywords: &str, name: &str) -> bool {
+    keywords.split_whitespace().any(|keyword| keyword == name)
+}
+
+/// A number as a 32-bit float literal
+fn literal(value: f64, target: &'static str) -> Result<(String, u8), CodegenError> {
+    let single = value as f32;
+    if !single.is_finite() {
+        return Err(CodegenError::UnrepresentableNumber { value, target });
+    }
+    // Debug formatting always includes a '.' or an exponent, so it's never read as an integer
+    let precedence = if single < 0.0 { UNARY } else { ATOM };
+    Ok((format!("{:?}", single), precedence))
+}
+
+/// Write a call to a function that's the same in GLSL and WGSL
+fn call(name: &str, args: Vec<String>) -> Option<(String, u8)> {
+    Some(match name {
+        _ if SHADER_BUILTINS.contains(&name) => (format!("{}({})", name, args.join(", ")), ATOM),
+        "ln" => (format!("log({})", args[0]), ATOM),
+        "log10" => (format!("log({}) / log(10.0)", args[0]), PRODUCT),
+        "cbrt" => (
+            format!("sign({0}) * pow(abs({0}), 1.0 / 3.0)", args[0]),
+            PRODUCT,
+        ),
+        // The built-in round() may round halves either way (GLSL) or to even (WGSL)
+        "round" if args.len() == 1 => (
+            format!("sign({0}) * floor(abs({0}) + 0.5)", args[0]),
+            PRODUCT,
+        ),
+        "min" | "max" => pairwise(args, |so_far, arg| format!("{}({}, {})", name, so_far, arg)),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Parse an expression, which must parse completely
+    fn parse(expression: &str) -> Expr {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        ast
+    }
+
+    /// Test the generated GLSL for a variety of expressions
+    #[test]
+    fn test_glsl() {
+        let cases = [
+            ("1 + 2 * x", "1.0 + 2.0 * x"),
+            ("x - -1 - (y - 0.1)", "x - -1.0 - (y - 0.1)"),
+            ("2 / log10(x)", "2.0 / (log(x) / log(10.0))"),
+            (
+                "round(x + 1) + 1",
+                "sign(x + 1.0) * floor(abs(x + 1.0) + 0.5) + 1.0",
+            ),
+            (
+                "max(a, b, c) - min(ln(a), 1)",
+                "max(max(a, b), c) - min(log(a), 1.0)",
+            ),
+            ("-cbrt(x)", "-(sign(x) * pow(abs(x), 1.0 / 3.0))"),
+            ("!x || y && z", "float(x == 0.0 || y != 0.0 && z != 0.0)"),
+            ("1e30 * x", "1e30 * x"),
+            (
+                "2 * piecewise((x < 0, -x), (x < 1, x * x), 1)",
+                "2.0 * (x < 0.0 ? -x : x < 1.0 ? x * x : 1.0)",
+            ),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(
+                parse(expression).to_glsl().unwrap(),
+                expected,
+                "'{}'",
+                expression
+            );
+        }
+    }
+
+    /// Test WGSL, and the expressions that no shader language can take
+    #[test]
+    fn test_wgsl() {
+        assert_eq!(
+            parse("!x || y && z || sign(-x)").to_wgsl().unwrap(),
+            "f32(x == 0.0 || (y != 0.0 && z != 0.0) || sign(-x) != 0.0)"
+        );
+        assert_eq!(
+            parse("atan2(y, x) * (x >= 0)").to_wgsl().unwrap(),
+            "atan2(y, x) * f32(x >= 0.0)"
+        );
+        assert_eq!(
+            parse("piecewise((x < 0, -x), (x < 1, x * x), 1)")
+                .to_wgsl()
+                .unwrap(),
+            "select(select(1.0, x * x, x < 1.0), -x, x < 0.0)"
+        );
+
+        assert_eq!(
+            parse("1e300 * x").to_wgsl(),
+            Err(CodegenError::UnrepresentableNumber {
+                value: 1e300,
+                target: "WGSL"
+            })
+        );
+        assert_eq!(
+            parse("uniform + 1").to_glsl(),
+            Err(CodegenError::InvalidName("uniform".to_string()))
+        );
+        assert_eq!(
+            parse("var + 1").to_wgsl(),
+            Err(CodegenError::InvalidName("var".to_string()))
+        );
+        assert_eq!(
+            parse("round(x, 2)").to_glsl(),
+            Err(CodegenError::UnsupportedFunction {
+                name: "round".to_string(),
+                target: "GLSL"
+            })
+        );
+    }
+}
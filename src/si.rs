@@ -0,0 +1,116 @@
+//! SI prefixes
+//!
+//! Component values and measurements are usually written with an SI prefix
+//! instead of an exponent: `4.7k` for 4700, `100n` for 100 × 10^-9. With
+//! [`ParserConfig::si_suffixes`](crate::ParserConfig::si_suffixes), number
+//! literals may end in one, and [`Notation::Si`](crate::Notation::Si) writes
+//! results that way.
+
+use nom::{IResult, number::complete::recognize_float};
+
+use crate::Expr;
+
+/// The SI prefixes from 10^-24 to 10^24, in steps of 10^3
+pub(crate) const PREFIXES: [&str; 17] = [
+    "y", "z", "a", "f", "p", "n", "µ", "m", "", "k", "M", "G", "T", "P", "E", "Z", "Y",
+];
+
+/// The power of 10 that a prefix after a number literal stands for
+///
+/// `u` and the Greek letter mu are accepted for micro, since the micro sign
+/// is hard to type. Exa (`E`) isn't accepted, because `1E3` is an exponent.
+fn power(prefix: char) -> Option<i32> {
+    let prefix = match prefix {
+        'u' | 'μ' => 'µ',
+        'E' => return None,
+        prefix => prefix,
+    };
+    let index = PREFIXES
+        .iter()
+        .position(|symbol| symbol.chars().eq([prefix]))?;
+    Some((index as i32 - 8) * 3)
+}
+
+/// Parse a number literal that ends in an SI prefix, such as `4.7k` or `100n`
+///
+/// The prefix must come straight after the number and must not be followed by
+/// a letter, digit or underscore, so `2m` is 0.002 but `2max` and `0x10` are
+/// not literals with a prefix. The result is correctly rounded: `0.1n` is
+/// exactly the number `1e-10`.
+pub(crate) fn parse_si_number<S>(input: &str) -> IResult<&str, Expr<S>> {
+    let (remaining, text) = recognize_float(input)?;
+    let (after, value) = scale(text, remaining).ok_or_else(|| {
+        nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify))
+    })?;
+    Ok((after, Expr::Float(value)))
+}
+
+/// The number literal `text` scaled by the prefix that `remaining` starts with, and the input after the prefix
+pub(crate) fn scale<'a>(text: &str, remaining: &'a str) -> Option<(&'a str, f64)> {
+    let mut chars = remaining.chars();
+    let power = chars.next().and_then(power)?;
+    let after = chars.as_str();
+    if after.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    // Move the prefix into the exponent, and let the float parser round
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (text, 0),
+    };
+    let value = format!("{}e{}", mantissa, exponent + power).parse().ok()?;
+    Some((after, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParserConfig, parse_expression_with};
+
+    /// Test the prefixes, and that the scaling is correctly rounded
+    #[test]
+    fn test_prefixed_numbers() {
+        let cases = [
+            ("4.7k", 4700.0),
+            ("100n", 1e-7),
+            ("2.2M", 2.2e6),
+            ("10u", 1e-5),
+            ("10µ", 1e-5),
+            ("10μ", 1e-5),
+            ("0.1n", 1e-10),
+            ("1.5e3m", 1.5),
+            ("-33p", -3.3e-11),
+            ("3G", 3e9),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_si_number::<String>(input),
+                Ok(("", Expr::Float(expected))),
+                "'{}'",
+                input
+            );
+        }
+    }
+
+    /// Test that only a prefix on its own, with the flag set, scales a literal
+    #[test]
+    fn test_prefix_conflicts() {
+        for input in ["2max", "0x10", "1E", "5k2", "7", "2 k"] {
+            assert!(parse_si_number::<String>(input).is_err(), "'{}'", input);
+        }
+
+        let config = ParserConfig {
+            si_suffixes: true,
+            ..ParserConfig::default()
+        };
+        let (remaining, ast) = parse_expression_with("4.7k * 2m + 1e3", &config).unwrap();
+        assert_eq!(remaining, "");
+        assert_eq!(crate::evaluate(&ast).unwrap(), 4700.0 * 0.002 + 1000.0);
+
+        let (remaining, _) = parse_expression_with("0x10", &config).unwrap();
+        assert_eq!(remaining, "x10");
+        let (remaining, _) = parse_expression_with("4.7k", &ParserConfig::default()).unwrap();
+        assert_eq!(remaining, "k");
+    }
+}
@@ -0,0 +1,209 @@
+//! Stable text forms of trees, for golden-file tests
+//!
+//! A program that stores user formulas wants to know when a new version of
+//! this crate parses one of them differently. [`Expr::to_snapshot`] writes a
+//! tree as JSON with sorted keys and a format version, so the text only
+//! changes when the tree does. It's a plain string, ready for
+//! `insta::assert_snapshot!`; [`snapshot!`](crate::snapshot!) parses and
+//! writes in one step, and [`assert_golden!`](crate::assert_golden!) keeps
+//! snapshots in files without another testing crate.
+
+use std::{env, fs, path::Path};
+
+use serde_json::{Value as Json, json};
+
+use crate::{Expr, graph::describe};
+
+/// The version of the layout written by [`Expr::to_snapshot`], part of every snapshot
+///
+/// It only changes when the layout does, not with the crate's version.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The environment variable that makes [`check_golden`] write snapshots instead of comparing them
+pub const UPDATE_GOLDEN_VAR: &str = "AST_UPDATE_GOLDEN";
+
+impl Expr {
+    /// This tree as pretty-printed JSON that stays the same across crate versions
+    ///
+    /// Each node is an object with its `kind` (such as `Add`), a `label`
+    /// with what it holds or does (such as `+` or a name) and its
+    /// `children`, if it has any. Keys are sorted, numbers are written the
+    /// shortest way that reads back exactly, and the whole is wrapped with
+    /// [`SNAPSHOT_VERSION`]. The text ends with a line break.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::parse_expression;
+    ///
+    /// let (_, ast) = parse_expression("-x").unwrap();
+    /// assert_eq!(
+    ///     ast.to_snapshot(),
+    ///     r#"{
+    ///   "snapshot_version": 1,
+    ///   "tree": {
+    ///     "children": [
+    ///       {
+    ///         "kind": "Var",
+    ///         "label": "x"
+    ///       }
+    ///     ],
+    ///     "kind": "Neg",
+    ///     "label": "-"
+    ///   }
+    /// }
+    /// "#
+    /// );
+    /// ```
+    pub fn to_snapshot(&self) -> String {
+        let snapshot = json!({
+            "snapshot_version": SNAPSHOT_VERSION,
+            "tree": node(self),
+        });
+        let mut text = serde_json::to_string_pretty(&snapshot).expect("snapshots are valid JSON");
+        text.push('\n');
+        text
+    }
+}
+
+/// One node of a snapshot, with its children
+fn node(expr: &Expr) -> Json {
+    let (kind, label) = describe(expr);
+    let mut object = json!({ "kind": kind, "label": label });
+    let children = expr.children();
+    if !children.is_empty() {
+        object["children"] = children.into_iter().map(node).collect();
+    }
+    object
+}
+
+/// Compare a snapshot with the one stored at `path`, panicking if they differ
+///
+/// When there's no file yet, or [`UPDATE_GOLDEN_VAR`] is set, the snapshot
+/// is written there instead (creating directories as needed), so the first
+/// run records it and later runs check against it. This is what
+/// [`assert_golden!`](crate::assert_golden!) calls.
+#[track_caller]
+pub fn check_golden(path: impl AsRef<Path>, snapshot: &str) {
+    let path = path.as_ref();
+    let stored = fs::read_to_string(path).ok();
+    if stored.is_none() || env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        if stored.as_deref() != Some(snapshot) {
+            if let Some(directory) = path.parent() {
+                fs::create_dir_all(directory)
+                    .unwrap_or_else(|error| panic!("creating {}: {}", directory.display(), error));
+            }
+            fs::write(path, snapshot)
+                .unwrap_or_else(|error| panic!("writing {}: {}", path.display(), error));
+        }
+        return;
+    }
+    let stored = stored.expect("checked above");
+    if stored != snapshot {
+        let first = stored
+            .lines()
+            .zip(snapshot.lines())
+            .position(|(old, new)| old != new)
+            .unwrap_or_else(|| stored.lines().count().min(snapshot.lines().count()));
+        panic!(
+            "snapshot {} has changed from line {}; rerun with {}=1 to accept it\n--- stored\n{}\n--- now\n{}",
+            path.display(),
+            first + 1,
+            UPDATE_GOLDEN_VAR,
+            stored,
+            snapshot
+        );
+    }
+}
+
+/// Parse a formula and write its [snapshot](Expr::to_snapshot), panicking if it doesn't parse
+///
+/// The formula is parsed with [`parse_expression`](crate::parse_expression)
+/// and must be parsed completely. The result suits `insta::assert_snapshot!`.
+///
+/// # Example
+/// ```
+/// let snapshot = ast::snapshot!("a * (b + 1)");
+/// assert!(snapshot.starts_with("{\n  \"snapshot_version\": 1,"));
+/// assert_eq!(snapshot, ast::snapshot!("a*(b+1)"));
+/// ```
+#[macro_export]
+macro_rules! snapshot {
+    ($input:expr) => {
+        $crate::snapshot_of($input)
+    };
+}
+
+/// Check a formula's snapshot against the golden file `tests/snapshots/<name>.snap`
+///
+/// The directory is relative to the calling crate's manifest. The first run
+/// writes the file; after that, a tree that parses differently fails the
+/// test with both snapshots, until it's accepted by running with
+/// [`UPDATE_GOLDEN_VAR`] set. See [`check_golden`].
+///
+/// # Example
+/// ```no_run
+/// #[test]
+/// fn pricing_formulas_parse_the_same() {
+///     ast::assert_golden!("discount", "price * (1 - rate) - coupon");
+/// }
+/// ```
+#[macro_export]
+macro_rules! assert_golden {
+    ($name:expr, $input:expr) => {
+        $crate::check_golden(
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("snapshots")
+                .join(format!("{}.snap", $name)),
+            &$crate::snapshot!($input),
+        )
+    };
+}
+
+/// The snapshot of a formula, for [`snapshot!`](crate::snapshot!)
+#[doc(hidden)]
+#[track_caller]
+pub fn snapshot_of(input: &str) -> String {
+    match crate::parse_expression(input) {
+        Ok((rest, ast)) if rest.trim().is_empty() => ast.to_snapshot(),
+        Ok((rest, _)) => panic!("'{}' has unparsed input: '{}'", input, rest),
+        Err(error) => panic!("'{}' doesn't parse: {}", input, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that snapshots are the same for the same tree, and differ for different ones
+    #[test]
+    fn test_snapshots() {
+        let snapshot = snapshot!("f(x, 2.5) + -x < nan");
+        assert_eq!(snapshot, snapshot!("f( x,2.50 )+-x<NaN"));
+        assert_ne!(snapshot, snapshot!("f(x, 2.5) + (-x < nan)"));
+        let json: Json = serde_json::from_str(&snapshot).unwrap();
+        assert_eq!(json["snapshot_version"], SNAPSHOT_VERSION);
+        assert_eq!(json["tree"]["label"], "<");
+        assert_eq!(json["tree"]["children"][1]["label"], "NaN");
+
+        // Repeated subtrees are written out each time
+        let repeated = snapshot!("(a + 1) * (a + 1)");
+        assert_eq!(repeated.matches("\"Add\"").count(), 2);
+    }
+
+    /// Test recording a golden file, checking against it, and failing on a change
+    #[test]
+    fn test_golden_files() {
+        let directory = env::temp_dir().join(format!("ast-golden-{}", std::process::id()));
+        let path = directory.join("nested").join("f.snap");
+        check_golden(&path, &snapshot!("a + b"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), snapshot!("a + b"));
+        check_golden(&path, &snapshot!("a+b"));
+
+        let changed = std::panic::catch_unwind(|| check_golden(&path, &snapshot!("a - b")));
+        fs::remove_dir_all(&directory).unwrap();
+        let message = changed.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("has changed from line 14"), "{}", message);
+    }
+}
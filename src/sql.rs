@@ -0,0 +1,251 @@
+//! Writing expressions as SQL
+//!
+//! [`Expr::to_sql`] writes an expression as a SQL scalar expression, so a
+//! formula can be pushed down into a database query instead of being
+//! evaluated row by row in the application. Variables become column names.
+//!
+//! Databases differ in their function names, so the SQL is written for a
+//! particular [`SqlDialect`].
+
+use crate::{
+    CodegenError, Expr,
+    codegen::{ATOM, LOOSEST, PRODUCT, Target, UNARY, identifier, write},
+};
+
+/// A database whose SQL can be generated
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SqlDialect {
+    /// PostgreSQL
+    PostgreSql,
+    /// MySQL and MariaDB
+    MySql,
+    /// SQLite, with its math functions enabled (the default since 3.35)
+    Sqlite,
+    /// Microsoft SQL Server
+    SqlServer,
+}
+
+impl Expr {
+    /// Write this expression as a SQL scalar expression
+    ///
+    /// Variables are written as quoted column names, so they keep their case.
+    /// Comparisons and logical operations become `CASE WHEN ... THEN 1 ELSE 0
+    /// END`, so they still give 1 or 0. Cell references, infinities and NaN,
+    /// and functions the dialect has no equivalent for can't be translated.
+    ///
+    /// The columns are assumed to hold floating-point numbers: with integer
+    /// columns, `/` divides integers in most databases. `round` may round
+    /// halves differently from the evaluator, depending on the database.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, SqlDialect};
+    ///
+    /// let (_, ast) = parse_expression("max(price - discount, 0) * (qty > 10 || vip)").unwrap();
+    /// assert_eq!(
+    ///     ast.to_sql(SqlDialect::PostgreSql).unwrap(),
+    ///     r#"GREATEST("price" - "discount", 0.0) * CASE WHEN "qty" > 10.0 OR "vip" <> 0.0 THEN 1 ELSE 0 END"#
+    /// );
+    /// ```
+    pub fn to_sql(&self, dialect: SqlDialect) -> Result<String, CodegenError> {
+        write(&dialect, self, None)
+    }
+}
+
+impl Target for SqlDialect {
+    fn language(&self) -> &'static str {
+        match self {
+            SqlDialect::PostgreSql => "PostgreSQL",
+            SqlDialect::MySql => "MySQL",
+            SqlDialect::Sqlite => "SQLite",
+            SqlDialect::SqlServer => "SQL Server",
+        }
+    }
+
+    fn operator(&self, op: &'static str) -> &'static str {
+        match op {
+            "==" => "=",
+            "!=" => "<>",
+            "&&" => "AND",
+            "||" => "OR",
+            "!" => "NOT ",
+            _ => op,
+        }
+    }
+
+    /// Quoted, so that keywords can be column names and the case is kept
+    fn name(&self, name: &str) -> Result<String, CodegenError> {
+        let name = identifier(name)?;
+        Ok(match self {
+            SqlDialect::PostgreSql | SqlDialect::Sqlite => format!("\"{}\"", name),
+            SqlDialect::MySql => format!("`{}`", name),
+            SqlDialect::SqlServer => format!("[{}]", name),
+        })
+    }
+
+    fn literal(&self, value: f64) -> Result<(String, u8), CodegenError> {
+        if !value.is_finite() {
+            return Err(CodegenError::UnrepresentableNumber {
+                value,
+                target: self.language(),
+            });
+        }
+        // With a '.' or an exponent, so it isn't an integer (which would make `/` divide integers)
+        let precedence = if value < 0.0 { UNARY } else { ATOM };
+        Ok((format!("{:?}", value), precedence))
+    }
+
+    fn number(&self, boolean: &str) -> String {
+        format!("CASE WHEN {} THEN 1 ELSE 0 END", boolean)
+    }
+
+    fn call(&self, name: &str, args: Vec<String>) -> Option<(String, u8)> {
+        use SqlDialect::*;
+
+        let function = match (name, self) {
+            ("abs" | "sqrt" | "exp" | "sign" | "floor", _) => name.to_ascii_uppercase(),
+            ("sin" | "cos" | "tan" | "asin" | "acos" | "atan", _) => name.to_ascii_uppercase(),
+            ("pow", _) => "POWER".to_string(),
+            ("ln", SqlServer) => "LOG".to_string(),
+            ("ln", _) => "LN".to_string(),
+            ("log10", PostgreSql) => "LOG".to_string(),
+            ("log10", _) => "LOG10".to_string(),
+            // PostgreSQL's LOG(base, x) only takes NUMERIC
+            ("log2", PostgreSql) => return Some((format!("LN({}) / LN(2.0)", args[0]), PRODUCT)),
+            ("log2", SqlServer) => return Some((format!("LOG({}, 2)", args[0]), ATOM)),
+            ("log2", _) => "LOG2".to_string(),
+            ("atan2", SqlServer) => "ATN2".to_string(),
+            ("atan2", _) => "ATAN2".to_string(),
+            ("ceil", SqlServer) => "CEILING".to_string(),
+            ("ceil", _) => "CEIL".to_string(),
+            ("trunc", MySql) => return Some((format!("TRUNCATE({}, 0)", args[0]), ATOM)),
+            ("trunc", SqlServer) => return Some((format!("ROUND({}, 0, 1)", args[0]), ATOM)),
+            ("trunc", _) => "TRUNC".to_string(),
+            ("cbrt", PostgreSql) => "CBRT".to_string(),
+            ("sinh" | "cosh" | "tanh", PostgreSql | Sqlite) => name.to_ascii_uppercase(),
+            // PostgreSQL only rounds to a number of digits for NUMERIC, with INTEGER digits
+            ("round", PostgreSql) if args.len() == 2 => {
+                let code = format!(
+                    "ROUND(CAST({} AS NUMERIC), CAST({} AS INTEGER))",
+                    args[0], args[1]
+                );
+                return Some((code, ATOM));
+            }
+            ("round", PostgreSql) => "ROUND".to_string(),
+            ("round", _) => {
+                let digits = args.get(1).map_or("0", String::as_str);
+                return Some((format!("ROUND({}, {})", args[0], digits), ATOM));
+            }
+            ("hypot", _) => {
+                let code = format!("SQRT(POWER({}, 2) + POWER({}, 2))", args[0], args[1]);
+                return Some((code, ATOM));
+            }
+            ("min" | "max", _) if args.len() == 1 => return Some((args[0].clone(), LOOSEST)),
+            // SQLite's MIN and MAX take any number of arguments
+            ("min", Sqlite) => "MIN".to_string(),
+            ("max", Sqlite) => "MAX".to_string(),
+            // SQL Server has LEAST and GREATEST since 2022
+            ("min", _) => "LEAST".to_string(),
+            ("max", _) => "GREATEST".to_string(),
+            _ => return None,
+        };
+        Some((format!("{}({})", function, args.join(", ")), ATOM))
+    }
+
+    fn piecewise(&self, cases: Vec<(String, String)>, default: String) -> (String, u8) {
+        let mut code = "CASE".to_string();
+        for (condition, value) in cases {
+            code.push_str(&format!(" WHEN {} THEN {}", condition, value));
+        }
+        (format!("{} ELSE {} END", code, default), ATOM)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Write an expression, which must parse completely, in a dialect
+    fn sql(expression: &str, dialect: SqlDialect) -> Result<String, CodegenError> {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        ast.to_sql(dialect)
+    }
+
+    /// Test the operators and parenthesization, which are the same in every dialect
+    #[test]
+    fn test_operators() {
+        let cases = [
+            ("a - (b - 1) / -c", r#""a" - ("b" - 1.0) / -"c""#),
+            ("a - -1 - --b", r#""a" - -1.0 - -(-"b")"#),
+            (
+                "a == 1 && !(b != 2) || !c",
+                r#"CASE WHEN "a" = 1.0 AND NOT ("b" <> 2.0) OR "c" = 0.0 THEN 1 ELSE 0 END"#,
+            ),
+            (
+                "!!(a < b) * 2",
+                r#"CASE WHEN NOT NOT ("a" < "b") THEN 1 ELSE 0 END * 2.0"#,
+            ),
+            (
+                "piecewise((a < 0, -a), b) * 2",
+                r#"CASE WHEN "a" < 0.0 THEN -"a" ELSE "b" END * 2.0"#,
+            ),
+            (
+                "a && (b || c)",
+                r#"CASE WHEN "a" <> 0.0 AND ("b" <> 0.0 OR "c" <> 0.0) THEN 1 ELSE 0 END"#,
+            ),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                sql(expression, SqlDialect::Sqlite).unwrap(),
+                expected,
+                "'{}'",
+                expression
+            );
+        }
+    }
+
+    /// Test each dialect's names, quoting and functions
+    #[test]
+    fn test_dialects() {
+        let expression = "ln(x) + log2(x) + max(x, y, 1) + round(x, 2) + ceil(x)";
+        let cases = [
+            (
+                SqlDialect::PostgreSql,
+                r#"LN("x") + LN("x") / LN(2.0) + GREATEST("x", "y", 1.0) + ROUND(CAST("x" AS NUMERIC), CAST(2.0 AS INTEGER)) + CEIL("x")"#,
+            ),
+            (
+                SqlDialect::MySql,
+                "LN(`x`) + LOG2(`x`) + GREATEST(`x`, `y`, 1.0) + ROUND(`x`, 2.0) + CEIL(`x`)",
+            ),
+            (
+                SqlDialect::Sqlite,
+                r#"LN("x") + LOG2("x") + MAX("x", "y", 1.0) + ROUND("x", 2.0) + CEIL("x")"#,
+            ),
+            (
+                SqlDialect::SqlServer,
+                "LOG([x]) + LOG([x], 2) + GREATEST([x], [y], 1.0) + ROUND([x], 2.0) + CEILING([x])",
+            ),
+        ];
+        for (dialect, expected) in cases {
+            assert_eq!(sql(expression, dialect).unwrap(), expected, "{:?}", dialect);
+        }
+
+        assert_eq!(
+            sql("max(x + 1) * 2", SqlDialect::MySql).unwrap(),
+            "(`x` + 1.0) * 2.0"
+        );
+        assert_eq!(
+            sql("cbrt(x)", SqlDialect::MySql),
+            Err(CodegenError::UnsupportedFunction {
+                name: "cbrt".to_string(),
+                target: "MySQL"
+            })
+        );
+        assert!(matches!(
+            sql("x / inf", SqlDialect::PostgreSql),
+            Err(CodegenError::UnrepresentableNumber { .. })
+        ));
+    }
+}
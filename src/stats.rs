@@ -0,0 +1,197 @@
+//! Descriptive statistics
+//!
+//! `mean`, `median`, `variance` and `stddev` take any mix of numbers and
+//! lists, so `mean([1, 2, 3])` and `mean(1, 2, 3)` are the same, and every
+//! element of a matrix counts. `variance` and `stddev` are for a sample,
+//! dividing by one less than the count, as spreadsheets' `VAR` and `STDEV`
+//! do. `percentile(list, p)` interpolates between the sorted values, with `p`
+//! from 0 to 1, and `correl(xs, ys)` is the Pearson correlation of two lists
+//! of the same length.
+
+use crate::{EvaluationError, FunctionRegistry, Matrix, Value, functions::domain};
+
+/// Add the statistics functions to a registry
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register_value("mean", 1.., |args| {
+        let xs = numbers(args)?;
+        domain("mean", !xs.is_empty(), mean(&xs)).map(Value::Number)
+    });
+    registry.register_value("median", 1.., |args| {
+        let xs = sorted(numbers(args)?);
+        domain("median", !xs.is_empty(), percentile(&xs, 0.5)).map(Value::Number)
+    });
+    registry.register_value("variance", 1.., |args| {
+        let xs = numbers(args)?;
+        domain("variance", xs.len() > 1, variance(&xs)).map(Value::Number)
+    });
+    registry.register_value("stddev", 1.., |args| {
+        let xs = numbers(args)?;
+        domain("stddev", xs.len() > 1, variance(&xs).sqrt()).map(Value::Number)
+    });
+    registry.register_value("percentile", 2, |args| {
+        let xs = sorted(numbers(&args[..1])?);
+        let p = args[1].clone().into_number()?;
+        let fits = !xs.is_empty() && (0.0..=1.0).contains(&p);
+        domain("percentile", fits, percentile(&xs, p.clamp(0.0, 1.0))).map(Value::Number)
+    });
+    registry.set_parameters("percentile", &["list", "p"]);
+    registry.register_value("correl", 2, |args| {
+        let (xs, ys) = (list(&args[0])?, list(&args[1])?);
+        if xs.elements().len() != ys.elements().len() {
+            return Err(EvaluationError::ArgumentSizes {
+                name: "correl".into(),
+                left: (xs.rows(), xs.columns()),
+                right: (ys.rows(), ys.columns()),
+            });
+        }
+        let r = correlation(xs.elements(), ys.elements());
+        domain("correl", r.is_finite(), r).map(Value::Number)
+    });
+}
+
+/// The numbers in the arguments, with matrices flattened row by row
+pub(crate) fn numbers(args: &[Value]) -> Result<Vec<f64>, EvaluationError> {
+    let mut numbers = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Number(x) => numbers.push(*x),
+            Value::Matrix(m) => numbers.extend_from_slice(m.elements()),
+            other => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a number or a matrix",
+                    found: other.type_name(),
+                });
+            }
+        }
+    }
+    Ok(numbers)
+}
+
+/// The matrix in an argument that must be a list
+fn list(value: &Value) -> Result<&Matrix, EvaluationError> {
+    match value {
+        Value::Matrix(m) => Ok(m),
+        other => Err(EvaluationError::TypeMismatch {
+            expected: "a matrix",
+            found: other.type_name(),
+        }),
+    }
+}
+
+/// Numbers in ascending order, with NaN last
+fn sorted(mut xs: Vec<f64>) -> Vec<f64> {
+    xs.sort_by(f64::total_cmp);
+    xs
+}
+
+/// The arithmetic mean
+pub(crate) fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// The sample variance, NaN for fewer than two numbers
+pub(crate) fn variance(xs: &[f64]) -> f64 {
+    let mean = mean(xs);
+    xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (xs.len() as f64 - 1.0)
+}
+
+/// The value a fraction `p` (from 0 to 1) of the way through sorted numbers,
+/// interpolating between neighbours
+pub(crate) fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let Some(last) = sorted.len().checked_sub(1) else {
+        return f64::NAN;
+    };
+    let position = p * last as f64;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+    sorted[below] + (position - below as f64) * (sorted[above] - sorted[below])
+}
+
+/// The Pearson correlation coefficient, NaN when either list doesn't vary
+fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let (mx, my) = (mean(xs), mean(ys));
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys) {
+        sxy += (x - mx) * (y - my);
+        sxx += (x - mx).powi(2);
+        syy += (y - my).powi(2);
+    }
+    sxy / (sxx * syy).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Evaluator, parse_expression};
+
+    /// Evaluate an expression with the standard functions
+    fn eval(expression: &str) -> Result<Value, EvaluationError> {
+        let (remaining, ast) = parse_expression(expression).unwrap();
+        assert!(remaining.is_empty(), "unparsed: '{}'", remaining);
+        Evaluator::new(&Environment::new()).evaluate_value(&ast)
+    }
+
+    /// Test each function against known results
+    #[test]
+    fn test_statistics() {
+        let cases = [
+            ("mean([1, 2, 3, 4])", 2.5),
+            ("mean(1, [2, 3], 6)", 3.0),
+            ("median([5, 1, 3])", 3.0),
+            ("median([[4, 1], [3, 2]])", 2.5),
+            ("variance([2, 4, 4, 4, 5, 5, 7, 9])", 32.0 / 7.0),
+            ("stddev([1, 3])", std::f64::consts::SQRT_2),
+            ("percentile([1, 2, 3, 4], 0.25)", 1.75),
+            ("percentile([10, 30, 20], 1)", 30.0),
+            ("correl([1, 2, 3], [2, 4, 6])", 1.0),
+            ("correl([1, 2, 3], [3, 1, 2])", -0.5),
+        ];
+        for (expression, expected) in cases {
+            let Value::Number(result) = eval(expression).unwrap() else {
+                panic!("'{}' isn't a number", expression);
+            };
+            assert!(
+                (result - expected).abs() < 1e-12,
+                "'{}': expected {}, got {}",
+                expression,
+                expected,
+                result
+            );
+        }
+    }
+
+    /// Test empty lists, bad fractions, mismatched lengths and other types
+    #[test]
+    fn test_statistics_errors() {
+        assert!(
+            matches!(eval("mean([])"), Err(EvaluationError::DomainError(name)) if name == "mean")
+        );
+        assert!(matches!(
+            eval("stddev([1])"),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(matches!(
+            eval("percentile([1, 2], 50)"),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(matches!(
+            eval("correl([1, 1], [1, 2])"),
+            Err(EvaluationError::DomainError(_))
+        ));
+        assert!(matches!(
+            eval("correl([1, 2], [1, 2, 3])"),
+            Err(EvaluationError::ArgumentSizes {
+                left: (2, 1),
+                right: (3, 1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval("mean(days(1))"),
+            Err(EvaluationError::TypeMismatch {
+                expected: "a number or a matrix",
+                ..
+            })
+        ));
+    }
+}
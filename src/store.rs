@@ -0,0 +1,320 @@
+//! Versioned storage for named formulas
+//!
+//! A [`FormulaStore`] keeps every version of each named formula as source
+//! text, and can be saved to and loaded from a JSON file. Keeping the source
+//! (rather than a parsed tree) means stored formulas can be re-checked with
+//! [`FormulaStore::check`] whenever the grammar or the set of available
+//! variables changes, to find the formulas that need migrating.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Diagnostic, Environment, parse_with_recovery};
+
+/// The version of the JSON file layout written by [`FormulaStore::save`]
+const FORMAT_VERSION: u32 = 1;
+
+/// Errors that can occur while loading or saving a store
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Accessing the store file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid store file: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Unsupported store file format version {0}")]
+    UnsupportedFormat(u32),
+}
+
+/// One saved version of a formula
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FormulaVersion {
+    /// Version number, starting at 1 and increasing with each change
+    pub version: u32,
+    /// The formula's source text
+    pub source: String,
+}
+
+/// A stored formula that no longer works with the current grammar or environment
+#[derive(Debug, PartialEq, Clone)]
+pub enum FormulaProblem {
+    /// The latest version doesn't parse
+    Syntax {
+        /// The formula's name
+        name: String,
+        /// The version that was checked
+        version: u32,
+        /// Every syntax error found
+        diagnostics: Vec<Diagnostic>,
+    },
+
+    /// The latest version uses variables the environment doesn't define
+    UndefinedVariables {
+        /// The formula's name
+        name: String,
+        /// The version that was checked
+        version: u32,
+        /// The undefined variables, sorted
+        variables: Vec<String>,
+    },
+}
+
+/// Named formulas with their full version history
+///
+/// # Example
+/// ```
+/// use ast::{Environment, FormulaProblem, FormulaStore};
+///
+/// let mut store = FormulaStore::new();
+/// store.insert("area", "width * height");
+/// store.insert("area", "width * height / 2");
+/// assert_eq!(store.get("area"), Some("width * height / 2"));
+/// assert_eq!(store.history("area").len(), 2);
+///
+/// // After "height" is removed from the environment, "area" needs attention
+/// let env: Environment = [("width", 2.0)].into_iter().collect();
+/// let problems = store.check(&env);
+/// assert!(matches!(
+///     &problems[..],
+///     [FormulaProblem::UndefinedVariables { name, version: 2, .. }] if name == "area"
+/// ));
+/// ```
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FormulaStore {
+    formulas: BTreeMap<String, Vec<FormulaVersion>>,
+}
+
+/// The layout of a store file on disk
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    format: u32,
+    formulas: BTreeMap<String, Vec<FormulaVersion>>,
+}
+
+impl FormulaStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        FormulaStore::default()
+    }
+
+    /// Load a store from a JSON file written by [`FormulaStore::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let file: StoreFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if file.format != FORMAT_VERSION {
+            return Err(StoreError::UnsupportedFormat(file.format));
+        }
+        Ok(FormulaStore {
+            formulas: file.formulas,
+        })
+    }
+
+    /// Save the store to a JSON file, replacing it if it exists
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StoreError> {
+        let file = StoreFile {
+            format: FORMAT_VERSION,
+            formulas: self.formulas.clone(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// Store a new version of a formula, returning its version number
+    ///
+    /// Storing the same source as the latest version doesn't create a new one.
+    pub fn insert(&mut self, name: impl Into<String>, source: impl Into<String>) -> u32 {
+        let source = source.into();
+        let versions = self.formulas.entry(name.into()).or_default();
+        match versions.last() {
+            Some(latest) if latest.source == source => latest.version,
+            latest => {
+                let version = latest.map_or(1, |latest| latest.version + 1);
+                versions.push(FormulaVersion { version, source });
+                version
+            }
+        }
+    }
+
+    /// The source of the latest version of a formula
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.formulas
+            .get(name)
+            .and_then(|versions| versions.last())
+            .map(|latest| latest.source.as_str())
+    }
+
+    /// The source of a specific version of a formula
+    pub fn version(&self, name: &str, version: u32) -> Option<&str> {
+        self.history(name)
+            .iter()
+            .find(|saved| saved.version == version)
+            .map(|saved| saved.source.as_str())
+    }
+
+    /// Every version of a formula, oldest first (empty if there's no such formula)
+    pub fn history(&self, name: &str) -> &[FormulaVersion] {
+        self.formulas.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Remove a formula and its whole history, returning whether it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.formulas.remove(name).is_some()
+    }
+
+    /// The names of all stored formulas, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.formulas.keys().map(String::as_str)
+    }
+
+    /// Check the latest version of every formula against the current grammar and environment
+    ///
+    /// Problems are listed by formula name. A formula with syntax errors isn't
+    /// checked for undefined variables.
+    pub fn check(&self, env: &Environment) -> Vec<FormulaProblem> {
+        let mut problems = Vec::new();
+        for (name, versions) in &self.formulas {
+            let Some(latest) = versions.last() else {
+                continue;
+            };
+
+            let (ast, diagnostics) = parse_with_recovery(&latest.source);
+            if !diagnostics.is_empty() {
+                problems.push(FormulaProblem::Syntax {
+                    name: name.clone(),
+                    version: latest.version,
+                    diagnostics,
+                });
+                continue;
+            }
+
+            let variables: Vec<String> = ast
+                .variables()
+                .into_iter()
+                .filter(|variable| !env.contains(variable))
+                .map(str::to_string)
+                .collect();
+            if !variables.is_empty() {
+                problems.push(FormulaProblem::UndefinedVariables {
+                    name: name.clone(),
+                    version: latest.version,
+                    variables,
+                });
+            }
+        }
+        problems
+    }
+
+    /// Rewrite formulas, storing the results as new versions
+    ///
+    /// `rewrite` is called with each formula's name and latest source, and
+    /// returns the migrated source (or `None` to leave the formula alone).
+    /// Returns the names of the formulas that got a new version.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::FormulaStore;
+    ///
+    /// let mut store = FormulaStore::new();
+    /// store.insert("total", "price * qty");
+    /// store.insert("unit", "price");
+    ///
+    /// // "qty" was renamed to "quantity"
+    /// let migrated = store.migrate(|_, source| {
+    ///     source.contains("qty").then(|| source.replace("qty", "quantity"))
+    /// });
+    /// assert_eq!(migrated, vec!["total"]);
+    /// assert_eq!(store.get("total"), Some("price * quantity"));
+    /// assert_eq!(store.version("total", 1), Some("price * qty"));
+    /// ```
+    pub fn migrate(
+        &mut self,
+        mut rewrite: impl FnMut(&str, &str) -> Option<String>,
+    ) -> Vec<String> {
+        let mut migrated = Vec::new();
+        let names: Vec<String> = self.formulas.keys().cloned().collect();
+        for name in names {
+            let Some(source) = self.get(&name) else {
+                continue;
+            };
+            if let Some(new_source) = rewrite(&name, source)
+                && new_source != source
+            {
+                self.insert(name.clone(), new_source);
+                migrated.push(name);
+            }
+        }
+        migrated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test version numbering and history lookups
+    #[test]
+    fn test_versions() {
+        let mut store = FormulaStore::new();
+        assert_eq!(store.insert("f", "1 + 1"), 1);
+        assert_eq!(
+            store.insert("f", "1 + 1"),
+            1,
+            "unchanged source isn't a new version"
+        );
+        assert_eq!(store.insert("f", "2 + 2"), 2);
+        assert_eq!(store.insert("g", "x"), 1);
+
+        assert_eq!(store.get("f"), Some("2 + 2"));
+        assert_eq!(store.version("f", 1), Some("1 + 1"));
+        assert_eq!(store.version("f", 3), None);
+        assert!(store.history("missing").is_empty());
+        assert_eq!(store.names().collect::<Vec<_>>(), vec!["f", "g"]);
+
+        assert!(store.remove("g"));
+        assert_eq!(store.get("g"), None);
+    }
+
+    /// Test that syntax errors and undefined variables are both reported
+    #[test]
+    fn test_check() {
+        let mut store = FormulaStore::new();
+        store.insert("bad", "1 +");
+        store.insert("good", "x * 2");
+        store.insert("missing", "x + y + z");
+
+        let env: Environment = [("x", 1.0)].into_iter().collect();
+        let problems = store.check(&env);
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(&problems[0], FormulaProblem::Syntax { name, .. } if name == "bad"));
+        assert_eq!(
+            problems[1],
+            FormulaProblem::UndefinedVariables {
+                name: "missing".to_string(),
+                version: 1,
+                variables: vec!["y".to_string(), "z".to_string()],
+            }
+        );
+    }
+
+    /// Test that saving and loading round-trips the whole history
+    #[test]
+    fn test_save_and_load() {
+        let mut store = FormulaStore::new();
+        store.insert("f", "1 + 1");
+        store.insert("f", "a * b");
+        store.insert("g", "-(c)");
+
+        let path = std::env::temp_dir().join(format!("ast-store-{}.json", std::process::id()));
+        store.save(&path).unwrap();
+        let loaded = FormulaStore::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), store);
+
+        fs::write(&path, r#"{"format": 99, "formulas": {}}"#).unwrap();
+        let loaded = FormulaStore::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(StoreError::UnsupportedFormat(99))));
+    }
+}
@@ -0,0 +1,505 @@
+//! Evaluating while parsing
+//!
+//! [`parse_and_eval`] computes an expression's value as it goes, without
+//! building a tree, for callers that only want the number. It follows the
+//! grammar of [`parse_expression`] and the rules of [`evaluate`] exactly,
+//! down to which error is reported when several things are wrong, and hands
+//! anything it doesn't handle itself to them: syntax errors, lists and
+//! element access, piecewise definitions, functions such as `if` that
+//! evaluate their own arguments, and results other than numbers.
+
+use nom::{IResult, number::complete::double};
+use thiserror::Error;
+
+use crate::{
+    Comparison, EvaluationError, ParserConfig, Value, evaluate, functions::STANDARD, identifier,
+    parse_expression, skip_whitespace, strip_in, strip_let, try_parse_symbol,
+};
+
+/// Errors from [`parse_and_eval`]
+#[derive(Error, Debug)]
+pub enum ParseEvalError {
+    /// The input isn't a valid expression
+    #[error("Syntax error at character {position}")]
+    Syntax {
+        /// Where in the input (in bytes) the parser stopped
+        position: usize,
+    },
+
+    /// The expression is nested more deeply than allowed, see [`EngineBuilder::max_depth`](crate::EngineBuilder::max_depth)
+    #[error("Expression is {depth} levels deep, more than the limit of {limit}")]
+    TooDeep {
+        /// How deep the expression's tree is, see [`Expr::depth`](crate::Expr::depth)
+        depth: usize,
+        /// The deepest a tree may be
+        limit: usize,
+    },
+
+    /// A number literal is longer than allowed, see [`EngineBuilder::max_literal_length`](crate::EngineBuilder::max_literal_length)
+    #[error(
+        "Number at character {position} is {length} characters long, more than the limit of {limit}"
+    )]
+    LiteralTooLong {
+        /// Where in the input (in bytes) the literal starts
+        position: usize,
+        /// How long the literal is
+        length: usize,
+        /// The longest a literal may be
+        limit: usize,
+    },
+
+    /// A variable or function name is longer than allowed, see [`EngineBuilder::max_name_length`](crate::EngineBuilder::max_name_length)
+    #[error(
+        "Name at character {position} is {length} characters long, more than the limit of {limit}"
+    )]
+    NameTooLong {
+        /// Where in the input (in bytes) the name starts
+        position: usize,
+        /// How long the name is
+        length: usize,
+        /// The longest a name may be
+        limit: usize,
+    },
+
+    /// The expression parsed, but evaluating it failed
+    #[error(transparent)]
+    Evaluation(#[from] EvaluationError),
+}
+
+/// Parse and evaluate an expression in one pass, without building a tree
+///
+/// The result is what [`evaluate`] gives for the tree from
+/// [`parse_expression`]: no variables besides those from `let`, the
+/// standard functions and the default [`EvalConfig`](crate::EvalConfig).
+/// The whole input must be one expression, nested no deeper than
+/// [`ParserConfig::max_depth`] allows; deeper input is a syntax error.
+///
+/// # Example
+/// ```
+/// use ast::{EvaluationError, ParseEvalError, parse_and_eval};
+///
+/// assert_eq!(parse_and_eval("2 * (3 + 4) - sqrt(16)").unwrap(), 10.0);
+/// assert_eq!(parse_and_eval("let r = 2 in 3 * r * r").unwrap(), 12.0);
+/// assert!(matches!(
+///     parse_and_eval("1 / (2 - 2)"),
+///     Err(ParseEvalError::Evaluation(EvaluationError::DivisionByZero))
+/// ));
+/// assert!(matches!(
+///     parse_and_eval("1 + * 2"),
+///     Err(ParseEvalError::Syntax { position: 4 })
+/// ));
+/// ```
+pub fn parse_and_eval(input: &str) -> Result<f64, ParseEvalError> {
+    let mut stream = Stream::default();
+    match stream.expression(input) {
+        Some((rest, outcome)) if rest.trim().is_empty() => Ok(outcome?),
+        _ => parse_then_evaluate(input),
+    }
+}
+
+/// Parse a tree and evaluate it, for the inputs [`Stream`] leaves alone
+fn parse_then_evaluate(input: &str) -> Result<f64, ParseEvalError> {
+    Ok(evaluate(&whole(input, parse_expression(input))?)?)
+}
+
+/// What a parser made of `input`, if it used all of it
+pub(crate) fn whole<T>(input: &str, parsed: IResult<&str, T>) -> Result<T, ParseEvalError> {
+    let syntax = |rest: &str| ParseEvalError::Syntax {
+        position: input.len() - rest.trim_start().len(),
+    };
+    match parsed {
+        Ok((rest, parsed)) if rest.trim().is_empty() => Ok(parsed),
+        Ok((rest, _)) => Err(syntax(rest)),
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => Err(syntax(error.input)),
+        Err(nom::Err::Incomplete(_)) => Err(syntax("")),
+    }
+}
+
+/// A value, or the error evaluating it gives
+///
+/// Errors are carried along rather than returned straight away, because
+/// the rest of the input still has to be parsed.
+type Outcome = Result<f64, EvaluationError>;
+
+/// The rest of the input and the outcome of what was parsed, or `None` to
+/// leave the whole input to the parser and evaluator
+type Step<'a> = Option<(&'a str, Outcome)>;
+
+/// A level of precedence, continuing a chain of operations
+type Level<'a> = fn(&mut Stream<'a>, &'a str, &mut Option<EvaluationError>) -> Step<'a>;
+
+/// The state of a parse that evaluates as it goes
+#[derive(Default)]
+struct Stream<'a> {
+    /// The variables set by the `let`s being parsed, innermost last
+    scope: Vec<(&'a str, f64)>,
+    /// The arguments of the calls being parsed, innermost last
+    args: Vec<f64>,
+    /// How many factors and `let`s are being parsed, one inside the other
+    depth: usize,
+}
+
+impl<'a> Stream<'a> {
+    /// An expression, which may start with `let`
+    fn expression(&mut self, input: &'a str) -> Step<'a> {
+        match strip_let(input) {
+            Some(after) => self.nested(|stream| stream.binding(after)),
+            None => self.operand(input, Stream::or),
+        }
+    }
+
+    /// A factor or `let` one level deeper, leaving input nested past
+    /// [`ParserConfig::max_depth`] to the parser, which rejects it
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Step<'a>) -> Step<'a> {
+        if self.depth > ParserConfig::default().max_depth {
+            return None;
+        }
+        self.depth += 1;
+        let step = parse(self);
+        self.depth -= 1;
+        step
+    }
+
+    /// The rest of `let name = value in body`, after `let`
+    fn binding(&mut self, input: &'a str) -> Step<'a> {
+        let (input, name) = identifier(skip_whitespace(input)).ok()?;
+        let input = skip_whitespace(input).strip_prefix('=')?;
+        if input.starts_with('=') {
+            return None;
+        }
+        let (input, value) = self.expression(input)?;
+        let input = strip_in(input)?;
+
+        // The body doesn't matter if the value failed, but it still has to parse
+        let bound = value.as_ref().map_or(f64::NAN, |value| *value);
+        self.scope.push((name, bound));
+        let body = self.expression(input);
+        self.scope.pop();
+        let (input, body) = body?;
+        Some((input, value.and(body)))
+    }
+
+    /// An operand parsed at `level`, which is a chain of operations of its own
+    ///
+    /// The evaluator takes every divisor on a chain before anything else,
+    /// outermost first, so an error in one of those beats any other error in
+    /// the chain. The levels record the latest one, which is the outermost.
+    fn operand(&mut self, input: &'a str, level: Level<'a>) -> Step<'a> {
+        let mut divisor_error = None;
+        let (input, total) = level(self, input, &mut divisor_error)?;
+        Some((input, divisor_error.map_or(total, Err)))
+    }
+
+    /// Logical or: `a || b`
+    fn or(&mut self, input: &'a str, divisor_error: &mut Option<EvaluationError>) -> Step<'a> {
+        let (mut input, mut total) = self.and(input, divisor_error)?;
+        while let Some(after) = skip_whitespace(input).strip_prefix("||") {
+            let (rest, right) = self.operand(after, Stream::and)?;
+            total = logical(total, right, true);
+            input = rest;
+        }
+        Some((input, total))
+    }
+
+    /// Logical and: `a && b`
+    fn and(&mut self, input: &'a str, divisor_error: &mut Option<EvaluationError>) -> Step<'a> {
+        let (mut input, mut total) = self.comparison(input, divisor_error)?;
+        while let Some(after) = skip_whitespace(input).strip_prefix("&&") {
+            let (rest, right) = self.operand(after, Stream::comparison)?;
+            total = logical(total, right, false);
+            input = rest;
+        }
+        Some((input, total))
+    }
+
+    /// Comparisons: `a < b`
+    fn comparison(
+        &mut self,
+        input: &'a str,
+        divisor_error: &mut Option<EvaluationError>,
+    ) -> Step<'a> {
+        let (mut input, mut total) = self.sum(input, divisor_error)?;
+        while let Some((symbol, after)) =
+            try_parse_symbol(skip_whitespace(input), Comparison::SYMBOLS)
+        {
+            let (rest, right) = self.operand(after, Stream::sum)?;
+            let comparison = Comparison::from_symbol(symbol)?;
+            total = total.and_then(|left| Ok(f64::from(comparison.holds(left, right?))));
+            input = rest;
+        }
+        Some((input, total))
+    }
+
+    /// Addition and subtraction
+    fn sum(&mut self, input: &'a str, divisor_error: &mut Option<EvaluationError>) -> Step<'a> {
+        let (mut input, mut total) = self.term(input, divisor_error)?;
+        loop {
+            let after = skip_whitespace(input);
+            let add = match after.as_bytes().first() {
+                Some(b'+') => true,
+                Some(b'-') => false,
+                _ => break,
+            };
+            let (rest, right) = self.operand(&after[1..], Stream::term)?;
+            total = total
+                .and_then(|left| right.map(|right| if add { left + right } else { left - right }));
+            input = rest;
+        }
+        Some((input, total))
+    }
+
+    /// Multiplication and division
+    fn term(&mut self, input: &'a str, divisor_error: &mut Option<EvaluationError>) -> Step<'a> {
+        let (mut input, mut total) = self.factor(input)?;
+        loop {
+            let after = skip_whitespace(input);
+            let multiply = match after.as_bytes().first() {
+                Some(b'*') => true,
+                Some(b'/') => false,
+                _ => break,
+            };
+            let (rest, right) = self.factor(&after[1..])?;
+            total = match right {
+                Ok(right) if multiply => total.map(|left| left * right),
+                Ok(0.0) => total.and(Err(EvaluationError::DivisionByZero)),
+                Ok(right) => total.map(|left| left / right),
+                Err(error) if multiply => total.and(Err(error)),
+                Err(error) => {
+                    *divisor_error = Some(error);
+                    total
+                }
+            };
+            input = rest;
+        }
+        Some((input, total))
+    }
+
+    /// A number, variable, call, negation or parenthesized expression
+    fn factor(&mut self, input: &'a str) -> Step<'a> {
+        self.nested(|stream| stream.unary(input))
+    }
+
+    /// The body of [`Stream::factor`]
+    fn unary(&mut self, input: &'a str) -> Step<'a> {
+        let input = skip_whitespace(input);
+        let (input, outcome) = match input.as_bytes().first() {
+            Some(b'-') => {
+                let (input, operand) = self.factor(&input[1..])?;
+                return Some((input, operand.map(|x| -x)));
+            }
+            Some(b'!') => {
+                let (input, operand) = self.factor(&input[1..])?;
+                return Some((input, operand.map(|x| f64::from(x == 0.0))));
+            }
+            Some(b'(') => {
+                let (input, inner) = self.expression(&input[1..])?;
+                (input.strip_prefix(')')?, inner)
+            }
+            Some(b'[') => return None,
+            Some(c) if c.is_ascii_alphabetic() || *c == b'_' => match identifier(input) {
+                Ok((input, name)) => self.name(input, name)?,
+                Err(_) => number(input)?,
+            },
+            _ => number(input)?,
+        };
+        // Element access needs a list to index
+        (!input.starts_with('[')).then_some((input, outcome))
+    }
+
+    /// A variable, or a call if the name is followed by `(`
+    fn name(&mut self, input: &'a str, name: &'a str) -> Step<'a> {
+        if !input.starts_with('(') {
+            let value = self
+                .scope
+                .iter()
+                .rev()
+                .find(|(bound, _)| *bound == name)
+                .map(|&(_, value)| value)
+                .ok_or_else(|| EvaluationError::UndefinedVariable(name.to_string()));
+            return Some((input, value));
+        }
+        if name == "piecewise" {
+            return None;
+        }
+
+        let start = self.args.len();
+        let (input, args) = self.arguments(input)?;
+        let outcome = self.call(name, start, args);
+        self.args.truncate(start);
+        Some((input, outcome?))
+    }
+
+    /// Parse the arguments of a call onto `self.args`, giving the first one that failed
+    fn arguments(&mut self, input: &'a str) -> Option<(&'a str, Result<(), EvaluationError>)> {
+        let mut input = skip_whitespace(input.strip_prefix('(')?);
+        if let Some(input) = input.strip_prefix(')') {
+            return Some((input, Ok(())));
+        }
+        let mut outcome = Ok(());
+        loop {
+            let (rest, arg) = self.expression(input)?;
+            match arg {
+                Ok(arg) => self.args.push(arg),
+                Err(error) => {
+                    // Keep the count right; the value won't be used
+                    self.args.push(f64::NAN);
+                    outcome = outcome.and(Err(error));
+                }
+            }
+            let rest = skip_whitespace(rest);
+            match rest.strip_prefix(',') {
+                Some(rest) => input = rest,
+                None => return Some((rest.strip_prefix(')')?, outcome)),
+            }
+        }
+    }
+
+    /// Call a standard function with the arguments from `start` on
+    ///
+    /// `None` if the function needs unevaluated arguments or gives something other than a number.
+    fn call(&self, name: &str, start: usize, args: Result<(), EvaluationError>) -> Option<Outcome> {
+        let args_given = &self.args[start..];
+        let Some(function) = STANDARD.get(name) else {
+            return Some(Err(EvaluationError::UnknownFunction(name.to_string())));
+        };
+        if !function.arity().accepts(args_given.len()) {
+            return Some(Err(function.wrong_count(name, args_given.len())));
+        }
+        if function.is_lazy() {
+            return None;
+        }
+        if let Err(error) = args {
+            return Some(Err(error));
+        }
+        match function.call_numbers(args_given)? {
+            Ok(Value::Number(value)) => Some(Ok(value)),
+            Ok(_) => None,
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+/// A number literal, as the parser reads it
+fn number(input: &str) -> Step<'_> {
+    let (input, value) = double::<_, nom::error::Error<_>>(input).ok()?;
+    Some((input, Ok(value)))
+}
+
+/// Finish `&&` (when `decided_by` is false) or `||` (when it's true)
+///
+/// Once the left operand is `decided_by`, that's the result, and whatever
+/// happened to the right operand doesn't matter.
+fn logical(left: Outcome, right: Outcome, decided_by: bool) -> Outcome {
+    if (left? != 0.0) == decided_by {
+        return Ok(f64::from(decided_by));
+    }
+    Ok(f64::from(right? != 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The result of parsing a tree and evaluating it, for comparison
+    fn expected(input: &str) -> String {
+        format!("{:?}", parse_then_evaluate(input))
+    }
+
+    /// Test that results and errors match parsing and then evaluating
+    #[test]
+    fn test_matches_evaluate() {
+        let inputs = [
+            "1 + 2 * 3 - 4 / 8",
+            "\t2 *\n(3 + -4) / -0.5 ",
+            "10 - 3 - 2 == 5 && !(1 > 2) || 0",
+            "let x = 3 in let y = x * 2 in x + y",
+            "let x = 1 in (let x = 2 in x) + x",
+            "max(1, min(4, 2), 3) + sqrt(16) + pi()",
+            "mean(1, 2, 3) + abs(-2)",
+            "x + 1",
+            "nope(1)",
+            "sqrt(1, 2)",
+            "sqrt(-1)",
+            "0 && 1 / 0",
+            "1 || y",
+            "1 && y",
+            "y / 0",
+            "(1 / 0) / y",
+            "y / (1 / 0) / (2 / 0) + z",
+            "a * b / c < 1 / 0",
+            "max(x, 1 / 0)",
+            "nope(1 / 0)",
+            "inf - nan + infinity",
+            "1e308 * 10",
+        ];
+        for input in inputs {
+            let streamed = Stream::default().expression(input);
+            assert!(
+                matches!(&streamed, Some((rest, _)) if rest.trim().is_empty()),
+                "'{}' wasn't evaluated in one pass",
+                input
+            );
+            assert_eq!(
+                format!("{:?}", parse_and_eval(input)),
+                expected(input),
+                "{}",
+                input
+            );
+        }
+    }
+
+    /// Test the inputs handed to the parser and evaluator
+    #[test]
+    fn test_fallback() {
+        let inputs = [
+            "[1, 2, 3][2] * 2",
+            "piecewise((1 < 2, 5), 6)",
+            "if(1, 2, 1 / 0)",
+            "days(1)",
+            "1 + * 2",
+            "(1 + 2",
+            "2 3",
+            "",
+            "let x = 1 x",
+        ];
+        for input in inputs {
+            assert!(
+                !matches!(Stream::default().expression(input), Some((rest, _)) if rest.trim().is_empty()),
+                "'{}' was evaluated in one pass",
+                input
+            );
+            assert_eq!(
+                format!("{:?}", parse_and_eval(input)),
+                expected(input),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(parse_and_eval("[1, 2, 3][2] * 2").unwrap(), 4.0);
+        assert!(matches!(
+            parse_and_eval("2 3"),
+            Err(ParseEvalError::Syntax { position: 2 })
+        ));
+    }
+
+    /// Test that nesting is limited like the parser limits it
+    #[test]
+    fn test_nesting_limit() {
+        let depth = ParserConfig::default().max_depth;
+        for (open, close) in [("(", ")"), ("-", ""), ("abs(", ")"), ("let t = 1 in ", "")] {
+            let nested =
+                |levels: usize| format!("{}1{}", open.repeat(levels), close.repeat(levels));
+            assert!(parse_and_eval(&nested(depth)).is_ok(), "'{}'", open);
+            for levels in [depth + 1, 100_000] {
+                let input = nested(levels);
+                assert!(
+                    matches!(parse_and_eval(&input), Err(ParseEvalError::Syntax { .. })),
+                    "{} levels of '{}'",
+                    levels,
+                    open
+                );
+                assert_eq!(format!("{:?}", parse_and_eval(&input)), expected(&input));
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Tables of an expression's values
+//!
+//! [`tabulate`] evaluates an expression for each value of one variable across
+//! a range, the way a table of values in a textbook does. `:table` in the REPL
+//! prints one, and [`Session::tabulate`](crate::Session::tabulate) makes one
+//! with a session's variables and functions.
+//!
+//! [`enumerate`] evaluates an expression for every combination of values of
+//! several variables, each from a small set, like a truth table. Formulas
+//! over flags and categories can be checked exhaustively that way.
+
+use std::ops::RangeInclusive;
+
+use thiserror::Error;
+
+use crate::{Environment, EvaluationError, Expr, evaluate_with};
+
+/// Evaluate an expression for a variable going from the start of a range to its end in steps
+///
+/// Each value of the variable is `start + i * step`, so rounding errors don't
+/// build up, and the end is included when a step lands on it (give or take
+/// rounding). A step that isn't positive and finite gives an empty table.
+///
+/// # Example
+/// ```
+/// use ast::{parse_expression, tabulate};
+///
+/// let (_, ast) = parse_expression("1 / (x - 1)").unwrap();
+/// let table = tabulate(&ast, "x", 0.0..=2.0, 0.5);
+/// assert_eq!(table.len(), 5);
+/// assert_eq!(table[0].0, 0.0);
+/// assert_eq!(*table[0].1.as_ref().unwrap(), -1.0);
+/// assert!(table[2].1.is_err()); // division by zero at x = 1
+/// assert_eq!(*table[4].1.as_ref().unwrap(), 1.0);
+/// ```
+pub fn tabulate(
+    expr: &Expr,
+    var: &str,
+    range: RangeInclusive<f64>,
+    step: f64,
+) -> Vec<(f64, Result<f64, EvaluationError>)> {
+    let mut env = Environment::new();
+    steps(range, step)
+        .map(|x| {
+            env.set(var, x);
+            (x, evaluate_with(expr, &env))
+        })
+        .collect()
+}
+
+/// The values from the start of a range to its end, `step` apart
+pub(crate) fn steps(range: RangeInclusive<f64>, step: f64) -> impl Iterator<Item = f64> {
+    let (start, end) = range.into_inner();
+    let count = if step > 0.0 && step.is_finite() && start <= end {
+        // Allow for the end being a little short of a whole number of steps
+        ((end - start) / step * (1.0 + 1e-12)).floor() as usize + 1
+    } else {
+        0
+    };
+    (0..count).map(move |i| start + step * i as f64)
+}
+
+/// How many rows [`enumerate`] makes unless told otherwise
+pub const DEFAULT_MAX_ROWS: usize = 100_000;
+
+/// The values each variable takes in [`enumerate`], and how many rows it may make
+///
+/// # Example
+/// ```
+/// use ast::Domains;
+///
+/// let domains = Domains::new()
+///     .with_boolean("member")
+///     .with_integers("items", 0..=3)
+///     .with_values("rate", [0.0, 0.5]);
+/// assert_eq!(domains.rows(), 16);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Domains {
+    /// Each variable's values, in the order of the table's columns
+    vars: Vec<(String, Vec<f64>)>,
+    /// The most rows allowed
+    max_rows: usize,
+}
+
+impl Default for Domains {
+    fn default() -> Self {
+        Domains {
+            vars: Vec::new(),
+            max_rows: DEFAULT_MAX_ROWS,
+        }
+    }
+}
+
+impl Domains {
+    /// No variables, and at most [`DEFAULT_MAX_ROWS`] rows
+    pub fn new() -> Self {
+        Domains::default()
+    }
+
+    /// Add a variable that takes each of `values`
+    pub fn with_values(mut self, name: &str, values: impl IntoIterator<Item = f64>) -> Self {
+        self.vars
+            .push((name.to_string(), values.into_iter().collect()));
+        self
+    }
+
+    /// Add a variable that's false (0) or true (1)
+    pub fn with_boolean(self, name: &str) -> Self {
+        self.with_values(name, [0.0, 1.0])
+    }
+
+    /// Add a variable that takes each whole number in `range`
+    pub fn with_integers(self, name: &str, range: RangeInclusive<i64>) -> Self {
+        self.with_values(name, range.map(|value| value as f64))
+    }
+
+    /// Allow at most `max_rows` rows
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    /// The variables' names, in the order of the table's columns
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.vars.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// How many combinations of values there are, or `usize::MAX` if that's more than fits
+    pub fn rows(&self) -> usize {
+        self.vars
+            .iter()
+            .try_fold(1_usize, |rows, (_, values)| rows.checked_mul(values.len()))
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// A row of [`enumerate`]'s table: the variables' values, and the result for them
+pub type TableRow = (Vec<f64>, Result<f64, EvaluationError>);
+
+/// Why [`enumerate`] couldn't make a table
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum EnumerationError {
+    /// There are more combinations than [`Domains::with_max_rows`] allows
+    #[error("The domains have {rows} combinations, more than the limit of {limit}")]
+    TooManyRows {
+        /// How many combinations there are, see [`Domains::rows`]
+        rows: usize,
+        /// The most rows allowed
+        limit: usize,
+    },
+}
+
+/// Evaluate an expression for every combination of its variables' values
+///
+/// Each row has the variables' values, in the order they were added to
+/// `domains`, and the result for them. The last variable changes fastest,
+/// as in a truth table. A variable the expression uses but `domains`
+/// doesn't have makes every row an [`EvaluationError::UndefinedVariable`].
+///
+/// # Example
+/// ```
+/// use ast::{enumerate, parse_expression, Domains};
+///
+/// let (_, ast) = parse_expression("a && !b || c").unwrap();
+/// let domains = Domains::new().with_boolean("a").with_boolean("b").with_boolean("c");
+/// let table = enumerate(&ast, &domains).unwrap();
+/// assert_eq!(table.len(), 8);
+/// assert_eq!(table[4].0, [1.0, 0.0, 0.0]);
+/// assert_eq!(*table[4].1.as_ref().unwrap(), 1.0);
+///
+/// // Exhaustive checks of larger domains need a higher limit
+/// let domains = Domains::new().with_integers("x", 1..=1000).with_integers("y", 1..=1000);
+/// assert!(enumerate(&ast, &domains).is_err());
+/// ```
+pub fn enumerate(expr: &Expr, domains: &Domains) -> Result<Vec<TableRow>, EnumerationError> {
+    let rows = domains.rows();
+    if rows > domains.max_rows {
+        return Err(EnumerationError::TooManyRows {
+            rows,
+            limit: domains.max_rows,
+        });
+    }
+
+    let mut env = Environment::new();
+    let mut table = Vec::with_capacity(rows);
+    // Which value each variable has, counting up like an odometer
+    let mut positions = vec![0; domains.vars.len()];
+    for _ in 0..rows {
+        let inputs: Vec<f64> = domains
+            .vars
+            .iter()
+            .zip(&positions)
+            .map(|((_, values), &position)| values[position])
+            .collect();
+        for ((name, _), &value) in domains.vars.iter().zip(&inputs) {
+            env.set(name, value);
+        }
+        table.push((inputs, evaluate_with(expr, &env)));
+
+        for (position, (_, values)) in positions.iter_mut().zip(&domains.vars).rev() {
+            *position += 1;
+            if *position < values.len() {
+                break;
+            }
+            *position = 0;
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    /// Test that the steps don't drift, and that the end is only included when it's reached
+    #[test]
+    fn test_steps() {
+        let values: Vec<f64> = steps(0.0..=1.0, 0.1).collect();
+        assert_eq!(values.len(), 11);
+        assert_eq!(values[10], 1.0); // adding up ten 0.1s would give 0.9999999999999999
+
+        assert_eq!(steps(0.0..=1.0, 0.3).count(), 4);
+        assert_eq!(steps(2.0..=2.0, 1.0).collect::<Vec<_>>(), [2.0]);
+        assert_eq!(steps(0.0..=1.0, 0.0).count(), 0);
+        assert_eq!(steps(0.0..=1.0, f64::NAN).count(), 0);
+        assert_eq!(steps(1.0..=0.0, 0.5).count(), 0);
+    }
+
+    /// Test tabulating an expression, including rows that fail
+    #[test]
+    fn test_tabulate() {
+        let (_, ast) = parse_expression("sqrt(x) + y").unwrap();
+        let table = tabulate(&ast, "x", 0.0..=4.0, 4.0);
+        assert_eq!(table.len(), 2);
+        assert!(matches!(
+            &table[0],
+            (0.0, Err(EvaluationError::UndefinedVariable(name))) if name == "y"
+        ));
+    }
+
+    /// Test enumerating every combination, and the row limit
+    #[test]
+    fn test_enumerate() {
+        let (_, ast) = parse_expression("piecewise((n < 0, 0), (member, n * 0.9), n)").unwrap();
+        let domains = Domains::new()
+            .with_integers("n", -1..=1)
+            .with_boolean("member");
+        let table = enumerate(&ast, &domains).unwrap();
+        let inputs: Vec<_> = table.iter().map(|(inputs, _)| inputs.clone()).collect();
+        assert_eq!(
+            inputs,
+            [
+                [-1.0, 0.0],
+                [-1.0, 1.0],
+                [0.0, 0.0],
+                [0.0, 1.0],
+                [1.0, 0.0],
+                [1.0, 1.0]
+            ]
+        );
+        let outputs: Vec<_> = table
+            .into_iter()
+            .map(|(_, output)| output.unwrap())
+            .collect();
+        assert_eq!(outputs, [0.0, 0.0, 0.0, 0.0, 1.0, 0.9]);
+
+        // No variables is one row, and an empty domain none
+        assert_eq!(enumerate(&ast, &Domains::new()).unwrap().len(), 1);
+        let empty = domains.clone().with_values("unused", []);
+        assert_eq!(enumerate(&ast, &empty).unwrap().len(), 0);
+
+        let limited = domains.with_max_rows(5);
+        assert!(matches!(
+            enumerate(&ast, &limited),
+            Err(EnumerationError::TooManyRows { rows: 6, limit: 5 })
+        ));
+    }
+}
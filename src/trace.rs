@@ -0,0 +1,195 @@
+//! Evaluation errors with their place in the tree
+//!
+//! An [`EvaluationError`] says what went wrong, but not where: "Division by
+//! zero" is little help in a long formula with several divisions. A
+//! [`TracedError`], from [`Evaluator::evaluate_traced`], adds the subtree
+//! that failed and the operations around it, so that embedders can point at
+//! the exact part of the input.
+
+use std::{error::Error, fmt};
+
+use crate::{EvaluationError, Evaluator, Expr, Span, Value, explain::grouped, parse_with_spans};
+
+/// An [`EvaluationError`] with the subtree it came from and the operations around it
+///
+/// The error itself is the [`source`](Error::source).
+#[derive(Debug)]
+pub struct TracedError {
+    /// What went wrong
+    pub kind: EvaluationError,
+    /// The smallest subtree that fails with this error
+    pub expr: Box<Expr>,
+    /// The child indices (as in [`Expr::children`]) leading from the whole expression to `expr`
+    pub path: Vec<usize>,
+    /// The operations that `expr` is part of, innermost first; the last is the whole expression
+    pub context: Vec<Expr>,
+}
+
+impl TracedError {
+    /// The whole expression that was evaluated
+    pub fn root(&self) -> &Expr {
+        self.context.last().unwrap_or(&self.expr)
+    }
+
+    /// Where the failing subtree is in the input the expression was parsed from
+    ///
+    /// Returns `None` if `input` doesn't parse to the expression that was evaluated.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Environment, Evaluator, Span};
+    ///
+    /// let input = "1 / 2 + 3 / (x - x)";
+    /// let (_, ast) = parse_expression(input).unwrap();
+    /// let env: Environment = [("x", 1.0)].into_iter().collect();
+    /// let error = Evaluator::new(&env).evaluate_traced(&ast).unwrap_err();
+    /// assert_eq!(error.to_string(), "Division by zero in '3 / (x - x)'");
+    /// assert_eq!(error.span(input), Some(Span::new(8, 19)));
+    /// ```
+    pub fn span(&self, input: &str) -> Option<Span> {
+        let (tree, diagnostics, spans) = parse_with_spans(input);
+        if !diagnostics.is_empty() || &tree != self.root() {
+            return None;
+        }
+
+        // Spans are in post-order, where a node comes after everything in
+        // its subtree and after the subtrees of its earlier siblings
+        let mut before = 0;
+        let mut node = self.root();
+        for &index in &self.path {
+            let children = node.children();
+            before += children[..index]
+                .iter()
+                .map(|child| child.size())
+                .sum::<usize>();
+            node = children[index];
+        }
+        spans.get(before + node.size() - 1).copied()
+    }
+}
+
+impl fmt::Display for TracedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in '{}'", self.kind, grouped(&self.expr))
+    }
+}
+
+impl Error for TracedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+impl Evaluator<'_> {
+    /// Evaluate an expression like [`Evaluator::evaluate_value`], but say where it failed
+    ///
+    /// Finding the failing subtree evaluates parts of the expression again,
+    /// so it only costs anything when there's an error.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Environment, EvaluationError, Evaluator, Expr};
+    ///
+    /// let (_, ast) = parse_expression("2 * (1 + sqrt(y - 5))").unwrap();
+    /// let env: Environment = [("y", 1.0)].into_iter().collect();
+    /// let error = Evaluator::new(&env).evaluate_traced(&ast).unwrap_err();
+    ///
+    /// assert!(matches!(error.kind, EvaluationError::OutOfDomain { value: -4.0, .. }));
+    /// assert!(matches!(*error.expr, Expr::Call(ref name, _) if name == "sqrt"));
+    /// assert_eq!(error.path, [1, 1]);
+    /// assert_eq!(error.context.len(), 2); // 1 + sqrt(..), then the product
+    /// ```
+    pub fn evaluate_traced(&self, expr: &Expr) -> Result<Value, TracedError> {
+        let kind = match self.evaluate_value(expr) {
+            Ok(value) => return Ok(value),
+            Err(kind) => kind,
+        };
+
+        // Go down into whichever operand fails the same way, as long as there is one
+        let message = kind.to_string();
+        let (mut node, mut path, mut context) = (expr, Vec::new(), Vec::new());
+        loop {
+            let mut children: Vec<(usize, &Expr)> =
+                node.children().into_iter().enumerate().collect();
+            if matches!(node, Expr::Div(..)) {
+                children.reverse(); // the divisor is evaluated first
+            }
+            let failing = children.into_iter().find(|(_, child)| {
+                matches!(self.evaluate_value(child), Err(error) if error.to_string() == message)
+            });
+            let Some((index, child)) = failing else {
+                break;
+            };
+            context.push(node.clone());
+            path.push(index);
+            node = child;
+        }
+
+        context.reverse();
+        Err(TracedError {
+            kind,
+            expr: Box::new(node.clone()),
+            path,
+            context,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, parse_expression};
+
+    /// Test finding the failing subtree in various places
+    #[test]
+    fn test_trace() {
+        let env: Environment = [("x", 0.0)].into_iter().collect();
+        let trace = |input: &str| {
+            let (_, ast) = parse_expression(input).unwrap();
+            let error = Evaluator::new(&env).evaluate_traced(&ast).unwrap_err();
+            (grouped(&error.expr), error.span(input))
+        };
+
+        assert_eq!(trace("y"), ("y".to_string(), Some(Span::new(0, 1))));
+        assert_eq!(
+            trace("max(1, -(2 / x), 3)"),
+            ("2 / x".to_string(), Some(Span::new(9, 14)))
+        );
+        assert_eq!(
+            trace("(1 / x) / x"),
+            ("1 / x".to_string(), Some(Span::new(1, 6)))
+        );
+        // The divisor is evaluated first, so its error is the one reported, not y's
+        assert_eq!(
+            trace("y / (1 / x)"),
+            ("1 / x".to_string(), Some(Span::new(5, 10)))
+        );
+        // Short-circuiting skips the failing operand; only the second one fails
+        assert_eq!(
+            trace("x && y || z"),
+            ("z".to_string(), Some(Span::new(10, 11)))
+        );
+
+        let (_, ast) = parse_expression("1 + x").unwrap();
+        assert_eq!(
+            Evaluator::new(&env).evaluate_traced(&ast).unwrap(),
+            Value::Number(1.0)
+        );
+    }
+
+    /// Test the context chain, the source and spans for a tree from other input
+    #[test]
+    fn test_context() {
+        let env = Environment::new();
+        let (_, ast) = parse_expression("-(2 * ln(0))").unwrap();
+        let error = Evaluator::new(&env).evaluate_traced(&ast).unwrap_err();
+        let context: Vec<String> = error.context.iter().map(grouped).collect();
+        assert_eq!(context, ["2 * ln(0)", "-(2 * ln(0))"]);
+        assert_eq!(error.root(), &ast);
+        assert!(matches!(
+            error.source().unwrap().downcast_ref(),
+            Some(EvaluationError::OutOfDomain { name, .. }) if &**name == "ln"
+        ));
+        assert_eq!(error.span("-(2 * ln(1))"), None);
+    }
+}
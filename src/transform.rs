@@ -0,0 +1,202 @@
+//! Rewriting trees in place
+//!
+//! Passes like constant folding usually change a few nodes of a large tree.
+//! Rebuilding the tree for that copies every node; these helpers instead hand
+//! each node to a closure as `&mut Expr`, so whatever the closure leaves
+//! alone, boxes included, stays where it is. Neither recurses, so they work
+//! on chains of any length.
+
+use std::mem;
+
+use crate::{ChildIndex, Expr};
+
+impl Expr {
+    /// Call `f` on every node, each before its children
+    ///
+    /// The children visited are the ones `f` leaves in place, so replacing a
+    /// node with a new subtree visits the new subtree too. A closure that
+    /// always grows what it's given never finishes.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, mut ast) = parse_expression("x * (x + y)").unwrap();
+    /// ast.map_mut(|node| {
+    ///     if let Expr::Var(name) = node {
+    ///         name.make_ascii_uppercase();
+    ///     }
+    /// });
+    /// assert_eq!(ast, parse_expression("X * (X + Y)").unwrap().1);
+    /// ```
+    pub fn map_mut(&mut self, mut f: impl FnMut(&mut Expr)) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            f(node);
+            stack.extend(node.children_mut().into_iter().rev());
+        }
+    }
+
+    /// Call `f` on every node, each after its children
+    ///
+    /// When `f` sees a node, its children are the ones `f` already gave back,
+    /// which is the order folding constants needs. Whatever `f` replaces a
+    /// node with isn't visited again. If `f` panics, the tree is left partly
+    /// taken apart.
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{parse_expression, Expr};
+    ///
+    /// let (_, mut ast) = parse_expression("x * (1 + 2 + 3)").unwrap();
+    /// ast.transform_bottom_up(|node| {
+    ///     if let Expr::Add(left, right) = node
+    ///         && let (Expr::Float(left), Expr::Float(right)) = (&**left, &**right)
+    ///     {
+    ///         *node = Expr::Float(left + right);
+    ///     }
+    /// });
+    /// assert_eq!(ast, parse_expression("x * 6").unwrap().1);
+    /// ```
+    pub fn transform_bottom_up(&mut self, mut f: impl FnMut(&mut Expr)) {
+        // Nodes taken out of the tree, innermost last, each with how many of
+        // its children have been done
+        let mut taken: Vec<(Expr, ChildIndex)> = vec![(mem::replace(self, Expr::Float(0.0)), 0)];
+        while let Some((node, done)) = taken.last_mut() {
+            if let Some(child) = node.child_mut(*done) {
+                *done += 1;
+                if is_leaf(child) {
+                    f(child);
+                } else {
+                    let child = mem::replace(child, Expr::Float(0.0));
+                    taken.push((child, 0));
+                }
+                continue;
+            }
+            let Some((mut node, _)) = taken.pop() else {
+                break;
+            };
+            f(&mut node);
+            match taken.last_mut() {
+                Some((parent, done)) => {
+                    if let Some(slot) = parent.child_mut(*done - 1) {
+                        *slot = node;
+                    }
+                }
+                None => *self = node,
+            }
+        }
+    }
+
+    /// The direct children of this node, left to right, for changing in place
+    fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Float(_)
+            | Expr::Var(_)
+            | Expr::CellRef(_)
+            | Expr::CellRange(_, _)
+            | Expr::Error(_) => vec![],
+            Expr::Add(left, right)
+            | Expr::Sub(left, right)
+            | Expr::Mul(left, right)
+            | Expr::Div(left, right)
+            | Expr::Compare(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right)
+            | Expr::Let(_, left, right) => vec![left, right],
+            Expr::Neg(inner) | Expr::Not(inner) | Expr::Named(_, inner) => vec![inner],
+            Expr::Call(_, args) | Expr::List(args) => args.iter_mut().collect(),
+            Expr::Piecewise(cases, default) => cases
+                .iter_mut()
+                .flat_map(|(condition, value)| [condition, value])
+                .chain(default.as_deref_mut())
+                .collect(),
+            Expr::Index(target, indices) => std::iter::once(target.as_mut())
+                .chain(indices.iter_mut())
+                .collect(),
+        }
+    }
+}
+
+/// Whether a node has no children, so it can be rewritten where it is
+fn is_leaf(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Float(_) | Expr::Var(_) | Expr::CellRef(_) | Expr::CellRange(..) | Expr::Error(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Expr, parse_expression};
+
+    /// Parse an expression that must be valid
+    fn parse(input: &str) -> Expr {
+        parse_expression(input).unwrap().1
+    }
+
+    /// Fold additions and multiplications of two numbers into one number
+    fn fold(node: &mut Expr) {
+        let folded = match node {
+            Expr::Add(left, right) => match (&**left, &**right) {
+                (Expr::Float(a), Expr::Float(b)) => a + b,
+                _ => return,
+            },
+            Expr::Mul(left, right) => match (&**left, &**right) {
+                (Expr::Float(a), Expr::Float(b)) => a * b,
+                _ => return,
+            },
+            _ => return,
+        };
+        *node = Expr::Float(folded);
+    }
+
+    /// A short name for a node, for checking the order nodes are seen in
+    fn label(node: &Expr) -> String {
+        match node {
+            Expr::Var(name) | Expr::Call(name, _) => name.clone(),
+            Expr::Add(..) => "+".to_string(),
+            Expr::Neg(_) => "-".to_string(),
+            Expr::Piecewise(..) => "piecewise".to_string(),
+            _ => "?".to_string(),
+        }
+    }
+
+    /// Test the order nodes are visited in, and that map_mut visits what it puts in
+    #[test]
+    fn test_order() {
+        let mut ast = parse("f(a, -b) + piecewise((c, d), e)");
+        let mut before = Vec::new();
+        ast.map_mut(|node| before.push(label(node)));
+        let mut after = Vec::new();
+        ast.transform_bottom_up(|node| after.push(label(node)));
+        let words = |text: &str| text.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(before, words("+ f a - b piecewise c d e"));
+        assert_eq!(after, words("a b - f c d e piecewise +"));
+        assert_eq!(ast, parse("f(a, -b) + piecewise((c, d), e)"));
+
+        let mut ast = parse("x + 1");
+        ast.map_mut(|node| match node {
+            Expr::Var(name) if name == "x" => *node = parse("2 * y"),
+            Expr::Var(name) => name.make_ascii_uppercase(),
+            _ => {}
+        });
+        assert_eq!(ast, parse("2 * Y + 1"));
+    }
+
+    /// Test folding constants, including through a chain too long to recurse down
+    #[test]
+    fn test_bottom_up() {
+        let mut ast = parse("x * (2 + 3 * 4) + [1 + 1, y][1 * 1]");
+        ast.transform_bottom_up(fold);
+        assert_eq!(ast, parse("x * 14 + [2, y][1]"));
+
+        let mut chain = parse(&vec!["1"; 100_000].join(" + "));
+        chain.transform_bottom_up(fold);
+        assert_eq!(chain, Expr::Float(100_000.0));
+
+        let mut leaf = parse("7");
+        leaf.transform_bottom_up(|node| *node = Expr::Var("seven".to_string()));
+        assert_eq!(leaf, Expr::Var("seven".to_string()));
+    }
+}
@@ -0,0 +1,164 @@
+//! Full-screen tree explorer
+//!
+//! With the `tui` feature, `:explore` in a terminal takes over the screen
+//! rather than reading lines: each key acts as soon as it's pressed, the
+//! selected node is highlighted, and the outline scrolls to keep it in view.
+//! The selected subtree's value and span are shown below the outline, with
+//! the span highlighted in the source.
+
+use std::io;
+
+use ast::Expr;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListState, Paragraph},
+};
+
+use crate::{
+    explorer::{Explorer, Key},
+    render::Renderer,
+};
+
+/// Walk a tree full-screen until the user quits
+///
+/// `value` describes the value of a subtree, or why it has none. The
+/// terminal is put back the way it was, even if reading a key fails.
+pub fn explore(explorer: &mut Explorer, value: impl Fn(&Expr) -> String) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run(&mut terminal, explorer, value);
+    ratatui::restore();
+    result
+}
+
+/// Draw the explorer and handle keys until the user quits
+fn run(
+    terminal: &mut DefaultTerminal,
+    explorer: &mut Explorer,
+    value: impl Fn(&Expr) -> String,
+) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| draw(frame, explorer, &value))?;
+        if let Event::Key(event) = event::read()?
+            && let Some(key) = key(event)
+            && !explorer.press(key)
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// The explorer's key for a key press, if it has one
+fn key(event: KeyEvent) -> Option<Key> {
+    // Some terminals also report keys being released
+    if event.kind != KeyEventKind::Press {
+        return None;
+    }
+    match event.code {
+        KeyCode::Up | KeyCode::Char('k') => Some(Key::Up),
+        KeyCode::Down | KeyCode::Char('j') => Some(Key::Down),
+        KeyCode::Left | KeyCode::Char('h') => Some(Key::Left),
+        KeyCode::Right | KeyCode::Char('l') => Some(Key::Right),
+        KeyCode::Enter | KeyCode::Char(' ') => Some(Key::Toggle),
+        KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Quit),
+        KeyCode::Esc | KeyCode::Char('q') => Some(Key::Quit),
+        _ => None,
+    }
+}
+
+/// Draw the outline, the selection's value and span, and a line of help
+fn draw(frame: &mut Frame, explorer: &Explorer, value: impl Fn(&Expr) -> String) {
+    let [tree, selection, help] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+    let (lines, selected) = explorer.outline(&Renderer::new(None));
+    let outline = List::new(lines)
+        .block(Block::bordered().title(" tree "))
+        .highlight_style(highlight)
+        .highlight_symbol("> ");
+    let mut state = ListState::default().with_selected(Some(selected));
+    frame.render_stateful_widget(outline, tree, &mut state);
+
+    let source = explorer.source();
+    let span = explorer.selected_span();
+    let details = vec![
+        Line::from(format!("value: {}", value(explorer.selected()))),
+        Line::from(format!("span:  {}", span)),
+        Line::from(vec![
+            Span::raw(&source[..span.start]),
+            Span::styled(&source[span.start..span.end], highlight),
+            Span::raw(&source[span.end..]),
+        ]),
+    ];
+    let details = Paragraph::new(details).block(Block::bordered().title(" selection "));
+    frame.render_widget(details, selection);
+
+    let keys = "↑↓←→ or hjkl move, Enter or space folds, q quits";
+    let keys = Paragraph::new(keys).style(Style::new().add_modifier(Modifier::DIM));
+    frame.render_widget(keys, help);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::parse_with_spans;
+    use ratatui::{Terminal, backend::TestBackend};
+
+    /// Test which key presses the explorer hears about
+    #[test]
+    fn test_keys() {
+        let press = |code| key(KeyEvent::from(code));
+        assert_eq!(press(KeyCode::Up), Some(Key::Up));
+        assert_eq!(press(KeyCode::Char('l')), Some(Key::Right));
+        assert_eq!(press(KeyCode::Enter), Some(Key::Toggle));
+        assert_eq!(press(KeyCode::Esc), Some(Key::Quit));
+        assert_eq!(press(KeyCode::Char('x')), None);
+
+        let control_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(key(control_c), Some(Key::Quit));
+        let mut released = KeyEvent::from(KeyCode::Down);
+        released.kind = KeyEventKind::Release;
+        assert_eq!(key(released), None);
+    }
+
+    /// Test what's drawn for a selection
+    #[test]
+    fn test_draw() {
+        let source = "1 + (x * 2)";
+        let (ast, _, spans) = parse_with_spans(source);
+        let mut explorer = Explorer::new(source, &ast, &spans);
+        explorer.press(Key::Down);
+        explorer.press(Key::Down);
+        explorer.press(Key::Left);
+
+        let mut terminal = Terminal::new(TestBackend::new(30, 12)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, &explorer, |_| "1".to_string()))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        let rows: Vec<String> = buffer
+            .content
+            .chunks(30)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect())
+            .collect();
+        assert_eq!(rows[1].trim_end(), "│  ▾ Add +                   │");
+        assert_eq!(rows[3].trim_end(), "│>   ▸ Mul *  x * 2          │");
+        assert_eq!(rows[7].trim_end(), "│value: 1                    │");
+        assert_eq!(rows[8].trim_end(), "│span:  5..10                │");
+        assert_eq!(rows[9].trim_end(), "│1 + (x * 2)                 │");
+        // Only the selected span of the source is highlighted
+        let highlighted: String = (0..11)
+            .filter(|&x| buffer[(x + 1, 9)].modifier.contains(Modifier::REVERSED))
+            .map(|x| &source[x as usize..x as usize + 1])
+            .collect();
+        assert_eq!(highlighted, "x * 2");
+    }
+}
@@ -0,0 +1,391 @@
+//! Measurements with uncertainties, and how the uncertainties propagate
+//!
+//! Lab results are written as a value and its standard uncertainty, such as
+//! `5.0 ± 0.1`. With [`ParserConfig::uncertainties`](crate::ParserConfig::uncertainties)
+//! the parser accepts them (as `±` or `+/-` between two number literals),
+//! and [`evaluate_uncertain`] computes a formula's value along with its
+//! uncertainty.
+//!
+//! Uncertainties propagate to first order: the result's uncertainty is the
+//! root sum of squares of each source's uncertainty times the partial
+//! derivative of the result with respect to it. Each measurement and each
+//! variable is an independent source, but uses of the same source are
+//! correlated, so `x - x` is exactly 0 however uncertain `x` is. Operators
+//! use their derivatives; functions are differentiated numerically.
+
+use std::{borrow::Cow, collections::BTreeMap, fmt};
+
+use crate::{EvaluationError, Expr, FunctionRegistry, Name, Value, skip_whitespace};
+
+/// The function a measurement literal stands for, as in `uncertain(5, 0.1)`
+const UNCERTAIN: &str = "uncertain";
+
+/// A value with a standard uncertainty
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Uncertain {
+    /// The best estimate
+    pub value: f64,
+    /// The standard uncertainty, never negative
+    pub error: f64,
+}
+
+impl Uncertain {
+    /// A value with the uncertainty `error`
+    pub fn new(value: f64, error: f64) -> Self {
+        Uncertain {
+            value,
+            error: error.abs(),
+        }
+    }
+
+    /// A value known exactly
+    pub fn exact(value: f64) -> Self {
+        Uncertain { value, error: 0.0 }
+    }
+
+    /// The uncertainty relative to the value's size
+    pub fn relative_error(&self) -> f64 {
+        self.error / self.value.abs()
+    }
+}
+
+impl fmt::Display for Uncertain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ± {}", self.value, self.error)
+    }
+}
+
+/// A value as a linear function of the independent sources of uncertainty
+#[derive(Debug, Clone)]
+struct Linear {
+    value: f64,
+    /// Each source's contribution: its uncertainty times the value's partial derivative
+    terms: BTreeMap<usize, f64>,
+}
+
+impl Linear {
+    /// A value with no uncertainty
+    fn exact(value: f64) -> Self {
+        Linear {
+            value,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    /// `value`, whose partial derivatives with respect to each of `inputs` are `partials`
+    fn chain(value: f64, inputs: &[&Linear], partials: &[f64]) -> Self {
+        let mut terms = BTreeMap::new();
+        for (input, partial) in inputs.iter().zip(partials) {
+            for (source, term) in &input.terms {
+                *terms.entry(*source).or_insert(0.0) += partial * term;
+            }
+        }
+        Linear { value, terms }
+    }
+
+    /// This value, also depending on a new source with uncertainty `error`
+    fn with_term(mut self, source: usize, error: f64) -> Self {
+        if error != 0.0 {
+            self.terms.insert(source, error);
+        }
+        self
+    }
+
+    /// The value and its uncertainty
+    fn uncertain(&self) -> Uncertain {
+        let error = self
+            .terms
+            .values()
+            .map(|term| term * term)
+            .sum::<f64>()
+            .sqrt();
+        Uncertain::new(self.value, error)
+    }
+}
+
+/// Evaluate `expr`, propagating the uncertainties of its measurements and of `vars`
+///
+/// Functions come from [`FunctionRegistry::standard`]; ones that evaluate
+/// their own arguments, like `if`, can't be used, but `piecewise` can, and
+/// picks its case by the values alone. Comparisons and logic give exact
+/// results. Lists aren't supported.
+///
+/// # Example
+/// ```
+/// use ast::{evaluate_uncertain, parse_expression_with, ParserConfig, Uncertain};
+///
+/// let config = ParserConfig { uncertainties: true, ..ParserConfig::default() };
+/// let (_, ast) = parse_expression_with("(2.0 ± 0.1) * length", &config).unwrap();
+/// let result = evaluate_uncertain(&ast, &[("length", Uncertain::new(3.0, 0.2))]).unwrap();
+/// assert_eq!(result.value, 6.0);
+/// // √((3 × 0.1)² + (2 × 0.2)²) = 0.5
+/// assert!((result.error - 0.5).abs() < 1e-12);
+/// ```
+pub fn evaluate_uncertain(
+    expr: &Expr,
+    vars: &[(&str, Uncertain)],
+) -> Result<Uncertain, EvaluationError> {
+    let mut propagation = Propagation {
+        functions: FunctionRegistry::standard(),
+        bindings: Vec::new(),
+        // Variables are the first sources, measurements the ones after
+        sources: vars.len(),
+        vars,
+    };
+    propagation.evaluate(expr).map(|linear| linear.uncertain())
+}
+
+/// The state of one [`evaluate_uncertain`]
+struct Propagation<'a> {
+    functions: FunctionRegistry,
+    /// Names bound by `let`, innermost last
+    bindings: Vec<(&'a str, Linear)>,
+    vars: &'a [(&'a str, Uncertain)],
+    /// How many sources of uncertainty have been used
+    sources: usize,
+}
+
+impl<'a> Propagation<'a> {
+    /// The value of `expr` as a linear function of the sources
+    fn evaluate(&mut self, expr: &'a Expr) -> Result<Linear, EvaluationError> {
+        Ok(match expr {
+            Expr::Float(value) => Linear::exact(*value),
+            Expr::Var(name) => self.variable(name)?,
+            Expr::Add(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                Linear::chain(left.value + right.value, &[&left, &right], &[1.0, 1.0])
+            }
+            Expr::Sub(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                Linear::chain(left.value - right.value, &[&left, &right], &[1.0, -1.0])
+            }
+            Expr::Mul(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                let partials = [right.value, left.value];
+                Linear::chain(left.value * right.value, &[&left, &right], &partials)
+            }
+            Expr::Div(left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                if right.value == 0.0 {
+                    return Err(EvaluationError::DivisionByZero);
+                }
+                let quotient = left.value / right.value;
+                let partials = [1.0 / right.value, -quotient / right.value];
+                Linear::chain(quotient, &[&left, &right], &partials)
+            }
+            Expr::Neg(inner) => {
+                let inner = self.evaluate(inner)?;
+                Linear::chain(-inner.value, &[&inner], &[-1.0])
+            }
+            Expr::Compare(comparison, left, right) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                Linear::exact(truth(comparison.holds(left.value, right.value)))
+            }
+            Expr::And(left, right) => {
+                let left = self.evaluate(left)?.value != 0.0;
+                Linear::exact(truth(left && self.evaluate(right)?.value != 0.0))
+            }
+            Expr::Or(left, right) => {
+                let left = self.evaluate(left)?.value != 0.0;
+                Linear::exact(truth(left || self.evaluate(right)?.value != 0.0))
+            }
+            Expr::Not(inner) => Linear::exact(truth(self.evaluate(inner)?.value == 0.0)),
+            Expr::Let(name, value, body) => {
+                let value = self.evaluate(value)?;
+                self.bindings.push((name, value));
+                let result = self.evaluate(body);
+                self.bindings.pop();
+                result?
+            }
+            Expr::Piecewise(cases, default) => {
+                for (condition, value) in cases {
+                    if self.evaluate(condition)?.value != 0.0 {
+                        return self.evaluate(value);
+                    }
+                }
+                match default {
+                    Some(default) => self.evaluate(default)?,
+                    None => return Err(EvaluationError::DomainError("piecewise".to_string())),
+                }
+            }
+            Expr::Call(name, args) if name == UNCERTAIN && args.len() == 2 => {
+                let (value, error) = (self.evaluate(&args[0])?, self.evaluate(&args[1])?);
+                let source = self.sources;
+                self.sources += 1;
+                value.with_term(source, error.value.abs())
+            }
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Named(_, value) => self.evaluate(value)?,
+            Expr::CellRef(cell) => return Err(EvaluationError::UnresolvedCell(*cell)),
+            Expr::CellRange(from, to) => return Err(EvaluationError::RangeNotAllowed(*from, *to)),
+            Expr::List(_) | Expr::Index(..) => {
+                return Err(EvaluationError::TypeMismatch {
+                    expected: "a number",
+                    found: "a matrix",
+                });
+            }
+            Expr::Error(_) => return Err(EvaluationError::ContainsErrors),
+        })
+    }
+
+    /// A variable, from the innermost `let` that binds it or else the inputs
+    fn variable(&self, name: &str) -> Result<Linear, EvaluationError> {
+        if let Some((_, value)) = self.bindings.iter().rev().find(|(bound, _)| *bound == name) {
+            return Ok(value.clone());
+        }
+        let (source, (_, input)) = self
+            .vars
+            .iter()
+            .enumerate()
+            .find(|(_, (var, _))| *var == name)
+            .ok_or_else(|| EvaluationError::UndefinedVariable(name.to_string()))?;
+        Ok(Linear::exact(input.value).with_term(source, input.error))
+    }
+
+    /// A function call, with the partial derivatives estimated by central differences
+    fn call(&mut self, name: &str, args: &'a [Expr]) -> Result<Linear, EvaluationError> {
+        let unknown = || EvaluationError::UnknownFunction(name.to_string());
+        let args = self
+            .functions
+            .get(name)
+            .ok_or_else(unknown)?
+            .arrange(name, args)?;
+        let args = args
+            .into_iter()
+            .map(|arg| match arg {
+                Cow::Borrowed(arg) => self.evaluate(arg),
+                // A default is a constant, so it has no uncertainty
+                Cow::Owned(default) => Ok(Linear::exact(evaluate_uncertain(&default, &[])?.value)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let function = self.functions.get(name).ok_or_else(unknown)?;
+        if !function.arity().accepts(args.len()) {
+            return Err(function.wrong_count(name, args.len()));
+        }
+        let at = |values: &[f64]| -> Result<f64, EvaluationError> {
+            function
+                .call_numbers(values)
+                .ok_or_else(|| EvaluationError::NotPermitted(name.to_string()))?
+                .and_then(Value::into_number)
+        };
+        let values: Vec<f64> = args.iter().map(|arg| arg.value).collect();
+        let value = at(&values)?;
+        let mut partials = Vec::with_capacity(args.len());
+        for (index, arg) in args.iter().enumerate() {
+            if arg.terms.is_empty() {
+                partials.push(0.0);
+                continue;
+            }
+            let step = 1e-6 * arg.value.abs().max(1.0);
+            let shifted = |by: f64| {
+                let mut values = values.clone();
+                values[index] += by;
+                at(&values)
+            };
+            // One-sided at the edge of the function's domain
+            let partial = match (shifted(-step), shifted(step)) {
+                (Ok(below), Ok(above)) => (above - below) / (2.0 * step),
+                (Ok(below), Err(_)) => (value - below) / step,
+                (Err(_), Ok(above)) => (above - value) / step,
+                (Err(error), Err(_)) => return Err(error),
+            };
+            partials.push(partial);
+        }
+        let inputs: Vec<&Linear> = args.iter().collect();
+        Ok(Linear::chain(value, &inputs, &partials))
+    }
+}
+
+/// 1 for true and 0 for false
+fn truth(holds: bool) -> f64 {
+    if holds { 1.0 } else { 0.0 }
+}
+
+/// The input after a `±` or `+/-`, if it starts with one
+pub(crate) fn plus_minus(input: &str) -> Option<&str> {
+    let input = skip_whitespace(input);
+    input
+        .strip_prefix('±')
+        .or_else(|| input.strip_prefix("+/-"))
+}
+
+/// The tree for the measurement `value ± error`
+pub(crate) fn measurement<'a, S: Name<'a>>(value: Expr<S>, error: Expr<S>) -> Expr<S> {
+    Expr::Call(UNCERTAIN.into(), vec![value, error])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParserConfig, parse_expression_with};
+
+    /// The uncertain value of an expression with measurements, which must parse
+    fn eval(input: &str, vars: &[(&str, Uncertain)]) -> Uncertain {
+        let config = ParserConfig {
+            uncertainties: true,
+            ..ParserConfig::default()
+        };
+        let (rest, ast) = parse_expression_with(input, &config).unwrap();
+        assert_eq!(rest, "");
+        evaluate_uncertain(&ast, vars).unwrap()
+    }
+
+    /// Whether two numbers agree to within `1e-9`
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    /// Test propagation through operators, and that reused sources are correlated
+    #[test]
+    fn test_operators() {
+        let sum = eval("(5.0 ± 0.3) + 2 +/- 0.4", &[]);
+        assert_eq!(sum.value, 7.0);
+        assert!(close(sum.error, 0.5));
+        let quotient = eval("(10 ± 0.1) / (4 ± 0.2)", &[]);
+        assert!(close(quotient.value, 2.5));
+        // Relative errors add in quadrature: √(0.01² + 0.05²)
+        assert!(close(
+            quotient.relative_error(),
+            (0.01f64.powi(2) + 0.05f64.powi(2)).sqrt()
+        ));
+
+        let x = [("x", Uncertain::new(3.0, 0.1))];
+        assert_eq!(eval("x - x", &x), Uncertain::exact(0.0));
+        assert!(close(eval("x * x", &x).error, 0.6));
+        assert!(close(eval("let y = x in y + x", &x).error, 0.2));
+        assert!(close(eval("-(1 ± 0.5)", &x).error, 0.5));
+        assert_eq!(eval("x > 2 && x < 4", &x), Uncertain::exact(1.0));
+    }
+
+    /// Test functions, piecewise, errors and display
+    #[test]
+    fn test_functions() {
+        let root = eval("sqrt(16 ± 0.8)", &[]);
+        assert!(close(root.value, 4.0));
+        assert!((root.error - 0.1).abs() < 1e-6);
+        let sine = eval("sin(0 ± 0.01) + max(1, 2 ± 0.1)", &[]);
+        assert!((sine.error - (0.01f64.powi(2) + 0.1f64.powi(2)).sqrt()).abs() < 1e-6);
+        let branch = eval("piecewise((1 > 2, 0), 3 ± 0.2)", &[]);
+        assert_eq!(branch, Uncertain::new(3.0, 0.2));
+        assert_eq!(Uncertain::new(5.0, -0.1).to_string(), "5 ± 0.1");
+
+        let config = ParserConfig {
+            uncertainties: true,
+            ..ParserConfig::default()
+        };
+        let (_, ast) = parse_expression_with("1 / (x - 2)", &config).unwrap();
+        let x = [("x", Uncertain::new(2.0, 0.1))];
+        assert!(matches!(
+            evaluate_uncertain(&ast, &x),
+            Err(EvaluationError::DivisionByZero)
+        ));
+        assert!(matches!(
+            evaluate_uncertain(&ast, &[]),
+            Err(EvaluationError::UndefinedVariable(_))
+        ));
+        // Without the option, ± isn't part of the grammar
+        let (rest, _) = crate::parse_expression("5 ± 0.1").unwrap();
+        assert_eq!(rest, " ± 0.1");
+        assert!(parse_expression_with("5 ± -0.1", &config).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Values produced by evaluation
+//!
+//! Most expressions evaluate to plain numbers, which is what
+//! [`Evaluator::evaluate`](crate::Evaluator::evaluate) returns. Some functions
+//! produce other kinds of values, such as dates; those are only available
+//! through [`Evaluator::evaluate_value`](crate::Evaluator::evaluate_value).
+//! Lists evaluate to [`Matrix`] values.
+
+use std::{cmp::Ordering, fmt, sync::Arc};
+
+use crate::{Comparison, CustomValue, DateTime, Duration, EvaluationError, Matrix, NumberFormat};
+
+/// The result of evaluating an expression
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// A plain number
+    Number(f64),
+    /// A point in time, e.g. from `date(2024, 1, 31)`
+    Date(DateTime),
+    /// A length of time, e.g. from `days(3)` or subtracting two dates
+    Duration(Duration),
+    /// A matrix or vector, e.g. from `[[1, 2], [3, 4]]`
+    Matrix(Matrix),
+    /// A value of a kind the application defines, see [`CustomValue`]
+    Custom(Arc<dyn CustomValue>),
+    /// A missing value, e.g. an undefined variable or an empty cell
+    ///
+    /// Only produced when [`EvalConfig::nulls`](crate::EvalConfig::nulls)
+    /// isn't [`NullPolicy::Error`](crate::NullPolicy::Error).
+    Null,
+}
+
+impl Value {
+    /// A description of the kind of value, for error messages: "a number", "a date", ...
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "a number",
+            Value::Date(_) => "a date",
+            Value::Duration(_) => "a duration",
+            Value::Matrix(_) => "a matrix",
+            Value::Custom(value) => value.type_name(),
+            Value::Null => "null",
+        }
+    }
+
+    /// Wrap a value of a kind the application defines
+    pub fn custom(value: impl CustomValue) -> Self {
+        Value::Custom(Arc::new(value))
+    }
+
+    /// The custom value this holds, if it's a `T`
+    pub fn downcast_ref<T: CustomValue>(&self) -> Option<&T> {
+        match self {
+            Value::Custom(value) => {
+                let value: &dyn std::any::Any = value.as_ref();
+                value.downcast_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this is [`Value::Null`]
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The number this value holds, or a type error if it isn't a number
+    pub fn into_number(self) -> Result<f64, EvaluationError> {
+        match self {
+            Value::Number(value) => Ok(value),
+            other => Err(EvaluationError::TypeMismatch {
+                expected: "a number",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// The date this value holds, or a type error if it isn't a date
+    pub fn into_date(self) -> Result<DateTime, EvaluationError> {
+        match self {
+            Value::Date(date) => Ok(date),
+            other => Err(EvaluationError::TypeMismatch {
+                expected: "a date",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// Whether the value counts as true: any number other than 0
+    ///
+    /// Only numbers have a truth value; anything else is a type error.
+    pub fn truth(&self) -> Result<bool, EvaluationError> {
+        match self {
+            Value::Number(value) => Ok(*value != 0.0),
+            other => Err(EvaluationError::TypeMismatch {
+                expected: "a number",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /// Compare two values of the same type, giving 1 if the comparison holds and 0 otherwise
+    pub(crate) fn compare(
+        comparison: Comparison,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, EvaluationError> {
+        let holds = match (left, right) {
+            (Value::Number(l), Value::Number(r)) => comparison.holds(l, r),
+            (Value::Date(l), Value::Date(r)) => comparison.holds(l, r),
+            (Value::Duration(l), Value::Duration(r)) => comparison.holds(l, r),
+            (Value::Custom(l), right) if let Some(order) = l.compare(&right) => {
+                comparison.holds(order, Ordering::Equal)
+            }
+            (left, Value::Custom(r)) if let Some(order) = r.compare(&left) => {
+                comparison.holds(order.reverse(), Ordering::Equal)
+            }
+            (left, right) => {
+                return Err(EvaluationError::UnsupportedOperands {
+                    op: comparison.symbol(),
+                    left: left.type_name(),
+                    right: right.type_name(),
+                });
+            }
+        };
+        Ok(Value::from(holds))
+    }
+
+    /// Apply a binary operator, following the rules for mixing numbers, dates and durations
+    ///
+    /// | Operation | Result |
+    /// | --- | --- |
+    /// | date - date | duration |
+    /// | date ± duration, duration + date | date |
+    /// | duration ± duration | duration |
+    /// | duration * number, number * duration, duration / number | duration |
+    /// | duration / duration | number |
+    /// | matrix ± matrix, of the same size | matrix |
+    /// | matrix * matrix, number * matrix, matrix * number, matrix / number | matrix |
+    pub(crate) fn binary(
+        op: &'static str,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, EvaluationError> {
+        use Value::{Date, Duration as Span, Number};
+
+        if let Value::Custom(custom) = &left
+            && let Some(result) = custom.binary(op, &right, false)
+        {
+            return result;
+        }
+        if let Value::Custom(custom) = &right
+            && let Some(result) = custom.binary(op, &left, true)
+        {
+            return result;
+        }
+
+        let divisor = |divisor: f64| {
+            if divisor == 0.0 {
+                Err(EvaluationError::DivisionByZero)
+            } else {
+                Ok(divisor)
+            }
+        };
+
+        Ok(match (op, left, right) {
+            ("+", Number(l), Number(r)) => Number(l + r),
+            ("-", Number(l), Number(r)) => Number(l - r),
+            ("*", Number(l), Number(r)) => Number(l * r),
+            ("/", Number(l), Number(r)) => Number(l / divisor(r)?),
+
+            ("-", Date(l), Date(r)) => Span(l - r),
+            ("+", Date(date), Span(span)) | ("+", Span(span), Date(date)) => Date(date + span),
+            ("-", Date(date), Span(span)) => Date(date + -span),
+
+            ("+", Span(l), Span(r)) => Span(l + r),
+            ("-", Span(l), Span(r)) => Span(l + -r),
+            ("*", Span(span), Number(factor)) | ("*", Number(factor), Span(span)) => {
+                Span(span * factor)
+            }
+            ("/", Span(span), Number(r)) => Span(span * (1.0 / divisor(r)?)),
+            ("/", Span(l), Span(r)) => Number(l.seconds() / divisor(r.seconds())?),
+
+            ("+", Value::Matrix(l), Value::Matrix(r)) => {
+                Value::Matrix(l.zip("+", &r, |l, r| l + r)?)
+            }
+            ("-", Value::Matrix(l), Value::Matrix(r)) => {
+                Value::Matrix(l.zip("-", &r, |l, r| l - r)?)
+            }
+            ("*", Value::Matrix(l), Value::Matrix(r)) => Value::Matrix(l.product(&r)?),
+            ("*", Value::Matrix(m), Number(factor)) | ("*", Number(factor), Value::Matrix(m)) => {
+                Value::Matrix(m.map(|x| x * factor))
+            }
+            ("/", Value::Matrix(m), Number(r)) => {
+                let r = divisor(r)?;
+                Value::Matrix(m.map(|x| x / r))
+            }
+
+            (op, left, right) => {
+                return Err(EvaluationError::UnsupportedOperands {
+                    op,
+                    left: left.type_name(),
+                    right: right.type_name(),
+                });
+            }
+        })
+    }
+
+    /// Negate a number, duration or matrix (null stays null)
+    pub(crate) fn negate(self) -> Result<Value, EvaluationError> {
+        match self {
+            Value::Null => Ok(Value::Null),
+            Value::Number(value) => Ok(Value::Number(-value)),
+            Value::Duration(span) => Ok(Value::Duration(-span)),
+            Value::Matrix(m) => Ok(Value::Matrix(m.map(|x| -x))),
+            Value::Custom(ref custom) if let Some(result) = custom.negate() => result,
+            Value::Date(_) | Value::Custom(_) => Err(EvaluationError::TypeMismatch {
+                expected: "a number, a duration or a matrix",
+                found: self.type_name(),
+            }),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    /// Custom values are equal to what [`CustomValue::compare`] says they're equal to
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::Date(l), Value::Date(r)) => l == r,
+            (Value::Duration(l), Value::Duration(r)) => l == r,
+            (Value::Matrix(l), Value::Matrix(r)) => l == r,
+            (Value::Null, Value::Null) => true,
+            (Value::Custom(l), other) => l.compare(other) == Some(Ordering::Equal),
+            (this, Value::Custom(r)) => r.compare(this) == Some(Ordering::Equal),
+            _ => false,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    /// 1 for true and 0 for false
+    fn from(value: bool) -> Self {
+        Value::Number(if value { 1.0 } else { 0.0 })
+    }
+}
+
+impl Value {
+    /// Write this value with numbers in the given format
+    ///
+    /// # Example
+    /// ```
+    /// use ast::{NumberFormat, Value};
+    ///
+    /// let third = Value::Number(1.0 / 3.0);
+    /// assert_eq!(third.to_string(), "0.3333333333333333");
+    /// assert_eq!(third.format(&NumberFormat::significant_figures(4)), "0.3333");
+    /// ```
+    pub fn format(&self, format: &NumberFormat) -> String {
+        match self {
+            Value::Number(value) => format.format(*value),
+            Value::Matrix(m) => m.format(format),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// Numbers are written in the default [`NumberFormat`]
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", NumberFormat::default().format(*value)),
+            Value::Date(date) => write!(f, "{}", date),
+            Value::Duration(span) => write!(f, "{}", span),
+            Value::Matrix(m) => write!(f, "{}", m),
+            Value::Custom(value) => write!(f, "{}", value),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}